@@ -0,0 +1,18 @@
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
+use classfile::bulk::parse_dir_parallel;
+use rayon::ThreadPoolBuilder;
+
+fn parse_dir_bench(c: &mut Criterion) {
+	let mut group = c.benchmark_group("parse_dir");
+	group.sample_size(10);
+	
+	for threads in [1, 2, 4, 8].iter() {
+		let pool = ThreadPoolBuilder::new().num_threads(*threads).build().unwrap();
+		group.bench_with_input(BenchmarkId::from_parameter(threads), threads, |b, _| {
+			b.iter(|| pool.install(|| parse_dir_parallel("classes/benchmarking")));
+		});
+	}
+}
+
+criterion_group!(benches, parse_dir_bench);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
+use classfile::classfile::{ClassFile, ParseOptions};
+use classfile::constantpool::ConstantPoolWriter;
+use classfile::attributes::{Attribute, AttributeKind};
+use std::io::Cursor;
+use std::fs;
+
+/// Compares replacing the code of one method through `write_patched` with a full parse and write
+fn patch_method_bench(c: &mut Criterion) {
+	let mut group = c.benchmark_group("patch_method");
+	
+	let mut paths: Vec<_> = fs::read_dir("classes/benchmarking").unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.extension() == Some("class".as_ref()))
+		.collect();
+	paths.sort();
+	for path in paths {
+		let bytes: Vec<u8> = fs::read(&path).unwrap();
+		let class = match ClassFile::parse(&mut Cursor::new(&bytes)) {
+			Ok(class) => class,
+			Err(_) => continue
+		};
+		// the method with the largest code
+		let method = class.methods().iter()
+			.filter_map(|method| method.attributes.iter().find_map(|attribute| match attribute {
+				Attribute::Code(code) => Some((method, code)),
+				_ => None
+			}))
+			.max_by_key(|(_, code)| code.insns.len());
+		let (method, code) = match method {
+			Some(x) => x,
+			None => continue
+		};
+		let name = path.file_name().unwrap().to_string_lossy().to_string();
+		// the patch is encoded again, which would leave its stack map frames stale
+		let mut code = code.clone();
+		code.attributes.retain(|attr| attr.kind() != AttributeKind::StackMapTable);
+		// the other methods are copied as they were, like write_patched does
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		
+		group.bench_with_input(BenchmarkId::new("round_trip", &name), &bytes, |b, bytes| {
+			b.iter(|| {
+				let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(bytes), &options).unwrap();
+				class.method_mut(&method.name, &method.descriptor).unwrap().set_code(Some(code.clone()));
+				let mut out = Vec::with_capacity(bytes.len());
+				class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool)).unwrap();
+				out
+			});
+		});
+		let patches = [(method.name.as_str(), method.descriptor.as_str(), code.clone())];
+		group.bench_with_input(BenchmarkId::new("patched", &name), &bytes, |b, bytes| {
+			b.iter(|| ClassFile::write_patched(bytes, &patches).unwrap());
+		});
+	}
+}
+
+criterion_group!(benches, patch_method_bench);
+criterion_main!(benches);
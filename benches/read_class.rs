@@ -1,8 +1,37 @@
 use criterion::{criterion_group, criterion_main, Criterion, Throughput, BatchSize, BenchmarkId};
 use classfile::classfile::ClassFile;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::io::{Cursor};
 use std::fs;
 
+/// Counts allocations, to compare parsing from a reader with parsing from bytes
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+	
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<T>(op: impl FnOnce() -> T) -> usize {
+	let before = ALLOCATIONS.load(Ordering::Relaxed);
+	let result = op();
+	let after = ALLOCATIONS.load(Ordering::Relaxed);
+	drop(result);
+	after - before
+}
+
 fn read_class_bench(c: &mut Criterion) {
 	let mut group = c.benchmark_group("read_class");
 	
@@ -15,12 +44,20 @@ fn read_class_bench(c: &mut Criterion) {
 					let ex = ex.to_string();
 					if ex == "class" {
 						let bytes: Vec<u8> = fs::read(path).unwrap();
+						let name = entry.file_name().into_string().unwrap();
+						println!("{}: {} allocations from a reader, {} from bytes", name,
+							allocations(|| ClassFile::parse(&mut Cursor::new(&bytes))),
+							allocations(|| ClassFile::parse_bytes(&bytes)));
+						
 						group.throughput(Throughput::Bytes(bytes.len() as u64));
-						group.bench_with_input(BenchmarkId::from_parameter(entry.file_name().into_string().unwrap()), &bytes, |b, bytes| {
+						group.bench_with_input(BenchmarkId::new("reader", &name), &bytes, |b, bytes| {
 							b.iter_batched(|| Cursor::new(bytes), | mut slice |{
 								ClassFile::parse(&mut slice)
 							}, BatchSize::SmallInput);
 						});
+						group.bench_with_input(BenchmarkId::new("bytes", &name), &bytes, |b, bytes| {
+							b.iter(|| ClassFile::parse_bytes(bytes));
+						});
 					}
 				}
 			}
@@ -0,0 +1,47 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput, BatchSize, BenchmarkId};
+use classfile::classfile::{ClassFile, ParseOptions};
+use classfile::constantpool::ConstantPoolWriter;
+use classfile::attributes::AttributeKind;
+use std::io::Cursor;
+use std::fs;
+
+/// Writes each class with the pool it was parsed with, parsing outside of the measurement
+fn write_class_bench(c: &mut Criterion) {
+	let mut group = c.benchmark_group("write_class");
+	
+	let mut paths: Vec<_> = fs::read_dir("classes/benchmarking").unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.extension() == Some("class".as_ref()))
+		.collect();
+	paths.sort();
+	for path in paths {
+		let bytes: Vec<u8> = fs::read(&path).unwrap();
+		let (mut class, constant_pool) = match ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default()) {
+			Ok(x) => x,
+			Err(_) => continue
+		};
+		// the code is encoded again, which would leave its stack map frames stale
+		for method in class.methods_mut().iter_mut() {
+			if let Some(code) = method.code() {
+				code.attributes.retain(|attr| attr.kind() != AttributeKind::StackMapTable);
+			}
+		}
+		// skip classes that cannot be written
+		if class.write_with_pool(&mut Vec::new(), ConstantPoolWriter::from_pool(&constant_pool)).is_err() {
+			continue;
+		}
+		let name = path.file_name().unwrap().to_string_lossy().to_string();
+		
+		group.throughput(Throughput::Bytes(bytes.len() as u64));
+		group.bench_with_input(BenchmarkId::from_parameter(name), &class, |b, class| {
+			b.iter_batched(|| ConstantPoolWriter::from_pool(&constant_pool), |constant_pool| {
+				let mut out = Vec::with_capacity(bytes.len());
+				class.write_with_pool(&mut out, constant_pool).unwrap();
+				out
+			}, BatchSize::SmallInput);
+		});
+	}
+}
+
+criterion_group!(benches, write_class_bench);
+criterion_main!(benches);
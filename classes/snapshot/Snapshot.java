@@ -0,0 +1,23 @@
+public class Snapshot {
+	private static int count;
+	
+	public static void main(String[] args) {
+		System.out.println("Hello");
+	}
+	
+	int sum(int[] values) {
+		int total = 0;
+		for (int value : values) {
+			total += value;
+		}
+		return total;
+	}
+	
+	static synchronized int parse(String s) {
+		try {
+			return Integer.parseInt(s);
+		} catch (NumberFormatException e) {
+			return -1;
+		}
+	}
+}
@@ -0,0 +1,11 @@
+public class Monitors {
+	static final Object lock = new Object();
+
+	public static void main(String[] args) {
+		int[][] grid = new int[2][3];
+		synchronized (lock) {
+			grid[1][2] = grid.length;
+		}
+		System.out.println(grid[1][2]);
+	}
+}
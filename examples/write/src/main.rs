@@ -0,0 +1,28 @@
+use classfile::classfile::ClassFile;
+use classfile::version::{ClassVersion, MajorVersion};
+use classfile::access::{ClassAccessFlags, MethodAccessFlags};
+use classfile::method::Method;
+use classfile::code::CodeAttribute;
+use classfile::types::{Type, ClassName, method_desc};
+use classfile::error::Result;
+
+use std::fs::File;
+use std::io::BufWriter;
+
+
+/// This example will write a class printing "Hello, World!" to disc, run it with `java HelloWorld`
+fn main() -> Result<()> {
+	let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("HelloWorld"), Some(ClassName::from("java/lang/Object")));
+	
+	let mut code = CodeAttribute { max_stack: 2, max_locals: 1, ..CodeAttribute::default() };
+	code.insns.get_static("java/lang/System", "out", "Ljava/io/PrintStream;")
+		.ldc_string("Hello, World!")
+		.invoke_virtual("java/io/PrintStream", "println", &method_desc(&[Type::object("java/lang/String")], &Type::Void))
+		.return_void();
+	let mut main = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from("main"), String::from("([Ljava/lang/String;)V"));
+	main.set_code(Some(code));
+	class.add_method(main)?;
+	
+	let mut writer = BufWriter::new(File::create("HelloWorld.class")?);
+	class.write(&mut writer)
+}
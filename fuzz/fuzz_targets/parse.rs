@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use classfile::classfile::ClassFile;
+use std::io::Cursor;
+
+// Run with `cargo fuzz run parse`. Seeding the corpus with real class files, for example those in
+// classes/, finds deeper bugs much sooner than starting from nothing.
+fuzz_target!(|data: &[u8]| {
+	let _ = ClassFile::parse(&mut Cursor::new(data));
+});
@@ -3,9 +3,73 @@
 use crate::Serializable;
 use std::io::{Read, Write};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
-use crate::error::Result;
+use crate::error::{Result, ParserError};
 use bitflags::bitflags;
 
+/// Generates `is_*` predicates for access flags
+macro_rules! predicates {
+	($typ:ident { $($name:ident => $flag:ident),* }) => {
+		impl $typ {
+			$(
+				pub fn $name(&self) -> bool {
+					self.contains($typ::$flag)
+				}
+			)*
+		}
+	};
+}
+
+/// Generates the conversions between access flags and Java source modifiers, which are listed in
+/// the order the JLS recommends
+macro_rules! java_modifiers {
+	($typ:ident { $($keyword:literal => $flag:ident),* }) => {
+		impl $typ {
+			const MODIFIERS: &'static [(&'static str, $typ)] = &[$(($keyword, $typ::$flag)),*];
+			
+			/// Parses space separated Java modifiers such as `public static final`
+			pub fn from_java_modifiers(modifiers: &str) -> Result<Self> {
+				let mut flags = $typ::empty();
+				for word in modifiers.split_whitespace() {
+					let (_, flag) = $typ::MODIFIERS.iter()
+						.find(|(keyword, _)| *keyword == word)
+						.ok_or_else(|| ParserError::unrecognised("modifier", word.to_string()))?;
+					if flags.contains(*flag) {
+						return Err(ParserError::unrecognised("repeated modifier", word.to_string()));
+					}
+					flags.insert(*flag);
+				}
+				Ok(flags)
+			}
+		}
+	};
+}
+
+/// Serializes access flags as their bits, the same as in the class file
+macro_rules! serde_bits {
+	($($typ:ident),*) => {
+		$(
+			#[cfg(feature = "serde")]
+			impl serde::Serialize for $typ {
+				fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+					serializer.serialize_u16(self.bits)
+				}
+			}
+			
+			#[cfg(feature = "serde")]
+			impl <'de> serde::Deserialize<'de> for $typ {
+				fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+					<u16 as serde::Deserialize>::deserialize(deserializer).map($typ::from_bits_truncate)
+				}
+			}
+		)*
+	};
+}
+
+/// Whether more than one of public, private and protected is set
+fn conflicting_visibility(bits: u16) -> bool {
+	(bits & 0x0007).count_ones() > 1
+}
+
 bitflags! {
 	pub struct ClassAccessFlags: u16 {
 		const PUBLIC = 0x0001;
@@ -25,8 +89,58 @@ impl ClassAccessFlags {
 	pub fn clear(&mut self) {
 		self.bits = 0;
 	}
+	
+	/// The Java source modifiers, e.g. `public abstract`. Interfaces are implicitly abstract, so
+	/// abstract is left out for them.
+	pub fn to_java_modifiers(&self) -> String {
+		let mut flags = *self;
+		if flags.is_interface() {
+			flags.remove(ClassAccessFlags::ABSTRACT);
+		}
+		modifier_string(ClassAccessFlags::MODIFIERS.iter().filter(|(_, flag)| flags.contains(*flag)).map(|(x, _)| *x))
+	}
+	
+	/// Checks for combinations the JVM refuses to load
+	pub fn validate(&self) -> Result<()> {
+		let reason = if conflicting_visibility(self.bits) {
+			"at most one of public, private and protected may be set"
+		} else if self.is_abstract() && self.is_final() {
+			"a class cannot be both abstract and final"
+		} else if self.is_interface() && !self.is_abstract() {
+			"an interface must be abstract"
+		} else if self.is_interface() && (self.is_final() || self.is_enum()) {
+			"an interface cannot be final or an enum"
+		} else if self.is_annotation() && !self.is_interface() {
+			"an annotation must be an interface"
+		} else {
+			return Ok(());
+		};
+		Err(ParserError::illegal_access_flags("class", reason))
+	}
 }
 
+predicates!(ClassAccessFlags {
+	is_public => PUBLIC,
+	is_private => PRIVATE,
+	is_protected => PROTECTED,
+	is_static => STATIC,
+	is_final => FINAL,
+	is_interface => INTERFACE,
+	is_abstract => ABSTRACT,
+	is_synthetic => SYNTHETIC,
+	is_annotation => ANNOTATION,
+	is_enum => ENUM
+});
+
+java_modifiers!(ClassAccessFlags {
+	"public" => PUBLIC,
+	"protected" => PROTECTED,
+	"private" => PRIVATE,
+	"abstract" => ABSTRACT,
+	"static" => STATIC,
+	"final" => FINAL
+});
+
 impl Serializable for ClassAccessFlags {
 	fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
 		let bits = rdr.read_u16::<BigEndian>()?;
@@ -57,8 +171,47 @@ impl FieldAccessFlags {
 	pub fn clear(&mut self) {
 		self.bits = 0;
 	}
+	
+	/// The Java source modifiers, e.g. `private static final`
+	pub fn to_java_modifiers(&self) -> String {
+		modifier_string(FieldAccessFlags::MODIFIERS.iter().filter(|(_, flag)| self.contains(*flag)).map(|(x, _)| *x))
+	}
+	
+	/// Checks for combinations the JVM refuses to load
+	pub fn validate(&self) -> Result<()> {
+		let reason = if conflicting_visibility(self.bits) {
+			"at most one of public, private and protected may be set"
+		} else if self.is_volatile() && self.is_final() {
+			"a field cannot be both volatile and final"
+		} else {
+			return Ok(());
+		};
+		Err(ParserError::illegal_access_flags("field", reason))
+	}
 }
 
+predicates!(FieldAccessFlags {
+	is_public => PUBLIC,
+	is_private => PRIVATE,
+	is_protected => PROTECTED,
+	is_static => STATIC,
+	is_final => FINAL,
+	is_volatile => VOLATILE,
+	is_transient => TRANSIENT,
+	is_synthetic => SYNTHETIC,
+	is_enum => ENUM
+});
+
+java_modifiers!(FieldAccessFlags {
+	"public" => PUBLIC,
+	"protected" => PROTECTED,
+	"private" => PRIVATE,
+	"static" => STATIC,
+	"final" => FINAL,
+	"transient" => TRANSIENT,
+	"volatile" => VOLATILE
+});
+
 impl Serializable for FieldAccessFlags {
 	fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
 		let bits = rdr.read_u16::<BigEndian>()?;
@@ -92,8 +245,54 @@ impl MethodAccessFlags {
 	pub fn clear(&mut self) {
 		self.bits = 0;
 	}
+	
+	/// The Java source modifiers, e.g. `public synchronized`
+	pub fn to_java_modifiers(&self) -> String {
+		modifier_string(MethodAccessFlags::MODIFIERS.iter().filter(|(_, flag)| self.contains(*flag)).map(|(x, _)| *x))
+	}
+	
+	/// Checks for combinations the JVM refuses to load
+	pub fn validate(&self) -> Result<()> {
+		let reason = if conflicting_visibility(self.bits) {
+			"at most one of public, private and protected may be set"
+		} else if self.is_abstract() && (self.is_private() || self.is_static() || self.is_final()) {
+			"an abstract method cannot be private, static or final"
+		} else if self.is_abstract() && (self.is_synchronized() || self.is_native()) {
+			"an abstract method cannot be synchronized or native"
+		} else {
+			return Ok(());
+		};
+		Err(ParserError::illegal_access_flags("method", reason))
+	}
 }
 
+predicates!(MethodAccessFlags {
+	is_public => PUBLIC,
+	is_private => PRIVATE,
+	is_protected => PROTECTED,
+	is_static => STATIC,
+	is_final => FINAL,
+	is_synchronized => SYNCHRONIZED,
+	is_bridge => BRIDGE,
+	is_varargs => VARARGS,
+	is_native => NATIVE,
+	is_abstract => ABSTRACT,
+	is_strict => STRICT,
+	is_synthetic => SYNTHETIC
+});
+
+java_modifiers!(MethodAccessFlags {
+	"public" => PUBLIC,
+	"protected" => PROTECTED,
+	"private" => PRIVATE,
+	"abstract" => ABSTRACT,
+	"static" => STATIC,
+	"final" => FINAL,
+	"synchronized" => SYNCHRONIZED,
+	"native" => NATIVE,
+	"strictfp" => STRICT
+});
+
 impl Serializable for MethodAccessFlags {
 	fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
 		let bits = rdr.read_u16::<BigEndian>()?;
@@ -106,6 +305,10 @@ impl Serializable for MethodAccessFlags {
 	}
 }
 
+fn modifier_string<'a, I: Iterator<Item=&'a str>>(modifiers: I) -> String {
+	modifiers.collect::<Vec<&str>>().join(" ")
+}
+
 bitflags! {
 	pub struct InnerClassAccessFlags: u16 {
 		const PUBLIC = 0x0001;
@@ -138,3 +341,64 @@ impl Serializable for InnerClassAccessFlags {
 		Ok(())
 	}
 }
+
+serde_bits!(ClassAccessFlags, FieldAccessFlags, MethodAccessFlags, InnerClassAccessFlags);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn java_modifiers_in_jls_order() {
+		let flags = MethodAccessFlags::FINAL | MethodAccessFlags::STATIC | MethodAccessFlags::PUBLIC | MethodAccessFlags::SYNCHRONIZED | MethodAccessFlags::BRIDGE;
+		assert_eq!(flags.to_java_modifiers(), "public static final synchronized");
+		let flags = FieldAccessFlags::VOLATILE | FieldAccessFlags::PRIVATE | FieldAccessFlags::TRANSIENT;
+		assert_eq!(flags.to_java_modifiers(), "private transient volatile");
+		let flags = ClassAccessFlags::PUBLIC | ClassAccessFlags::ABSTRACT | ClassAccessFlags::SYNTHETIC;
+		assert_eq!(flags.to_java_modifiers(), "public abstract");
+		let flags = ClassAccessFlags::PUBLIC | ClassAccessFlags::ABSTRACT | ClassAccessFlags::INTERFACE;
+		assert_eq!(flags.to_java_modifiers(), "public");
+		assert_eq!(MethodAccessFlags::empty().to_java_modifiers(), "");
+	}
+	
+	#[test]
+	fn parse_java_modifiers() {
+		let flags = MethodAccessFlags::from_java_modifiers(" static  public\tnative ").unwrap();
+		assert_eq!(flags, MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC | MethodAccessFlags::NATIVE);
+		assert_eq!(MethodAccessFlags::from_java_modifiers(&flags.to_java_modifiers()).unwrap(), flags);
+		assert_eq!(FieldAccessFlags::from_java_modifiers("").unwrap(), FieldAccessFlags::empty());
+		assert!(FieldAccessFlags::from_java_modifiers("native").is_err());
+		assert!(ClassAccessFlags::from_java_modifiers("public public").is_err());
+	}
+	
+	#[test]
+	fn predicates() {
+		let flags = FieldAccessFlags::PUBLIC | FieldAccessFlags::STATIC;
+		assert!(flags.is_public() && flags.is_static());
+		assert!(!flags.is_final() && !flags.is_private());
+		assert!(ClassAccessFlags::ANNOTATION.is_annotation());
+		assert!(MethodAccessFlags::VARARGS.is_varargs());
+	}
+	
+	#[test]
+	fn validate_combinations() {
+		assert!((ClassAccessFlags::PUBLIC | ClassAccessFlags::FINAL).validate().is_ok());
+		assert!((ClassAccessFlags::INTERFACE | ClassAccessFlags::ABSTRACT | ClassAccessFlags::ANNOTATION).validate().is_ok());
+		let err = (ClassAccessFlags::ABSTRACT | ClassAccessFlags::FINAL).validate().unwrap_err();
+		assert_eq!(err.to_string(), "Illegal class access flags: a class cannot be both abstract and final");
+		assert!(ClassAccessFlags::INTERFACE.validate().is_err());
+		assert!(ClassAccessFlags::ANNOTATION.validate().is_err());
+		assert!((ClassAccessFlags::PUBLIC | ClassAccessFlags::PRIVATE).validate().is_err());
+		
+		assert!((FieldAccessFlags::PRIVATE | FieldAccessFlags::VOLATILE).validate().is_ok());
+		let err = (FieldAccessFlags::VOLATILE | FieldAccessFlags::FINAL).validate().unwrap_err();
+		assert_eq!(err.to_string(), "Illegal field access flags: a field cannot be both volatile and final");
+		assert!((FieldAccessFlags::PROTECTED | FieldAccessFlags::PRIVATE).validate().is_err());
+		
+		assert!((MethodAccessFlags::PUBLIC | MethodAccessFlags::ABSTRACT).validate().is_ok());
+		for flag in [MethodAccessFlags::PRIVATE, MethodAccessFlags::STATIC, MethodAccessFlags::FINAL, MethodAccessFlags::NATIVE, MethodAccessFlags::SYNCHRONIZED].iter() {
+			assert!((MethodAccessFlags::ABSTRACT | *flag).validate().is_err(), "{:?}", flag);
+		}
+	}
+}
+
@@ -0,0 +1,307 @@
+//! Flags calls to reflection, dynamic class loading, process and native library APIs
+
+use crate::ast::{Insn, LdcInsn, LdcType, MemberAccess};
+use crate::attributes::Attribute;
+use crate::classfile::ClassFile;
+use crate::code::CodeAttribute;
+use crate::types::ClassName;
+
+/// A method looked for by a scan, matched by the class named in the instruction and the method
+/// name. Calls through a subclass, such as `URLClassLoader.loadClass`, need their own entry.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ApiMethod {
+	pub class: ClassName,
+	pub name: String
+}
+
+impl ApiMethod {
+	pub fn new(class: &str, name: &str) -> Self {
+		ApiMethod {
+			class: ClassName::from(class),
+			name: String::from(name)
+		}
+	}
+}
+
+/// The methods a scan looks for
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanConfig {
+	pub apis: Vec<ApiMethod>
+}
+
+impl Default for ScanConfig {
+	/// Reflection, method handle lookups, class loading, process creation and native library loading
+	fn default() -> Self {
+		let apis = [
+			("java/lang/Class", "forName"),
+			("java/lang/Class", "newInstance"),
+			("java/lang/Class", "getMethod"),
+			("java/lang/Class", "getDeclaredMethod"),
+			("java/lang/Class", "getField"),
+			("java/lang/Class", "getDeclaredField"),
+			("java/lang/Class", "getConstructor"),
+			("java/lang/Class", "getDeclaredConstructor"),
+			("java/lang/reflect/Method", "invoke"),
+			("java/lang/reflect/Constructor", "newInstance"),
+			("java/lang/invoke/MethodHandles", "lookup"),
+			("java/lang/invoke/MethodHandles", "privateLookupIn"),
+			("java/lang/ClassLoader", "loadClass"),
+			("java/lang/ClassLoader", "defineClass"),
+			("java/lang/Runtime", "exec"),
+			("java/lang/Runtime", "load"),
+			("java/lang/Runtime", "loadLibrary"),
+			("java/lang/ProcessBuilder", "start"),
+			("java/lang/System", "load"),
+			("java/lang/System", "loadLibrary")
+		];
+		ScanConfig {
+			apis: apis.iter().map(|(class, name)| ApiMethod::new(class, name)).collect()
+		}
+	}
+}
+
+impl ScanConfig {
+	/// A config that looks for nothing, to build up with [`with_api`](Self::with_api)
+	pub fn empty() -> Self {
+		ScanConfig {
+			apis: Vec::new()
+		}
+	}
+	
+	/// Also looks for calls to the given method
+	pub fn with_api(mut self, class: &str, name: &str) -> Self {
+		self.apis.push(ApiMethod::new(class, name));
+		self
+	}
+	
+	pub fn matches(&self, class: &str, name: &str) -> bool {
+		self.apis.iter().any(|api| api.class == class && api.name == name)
+	}
+}
+
+/// A call to one of the scanned methods
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiCall {
+	pub class: ClassName,
+	pub name: String,
+	pub descriptor: String,
+	/// Index of the instruction naming the method: an invoke, or an invokedynamic or ldc whose
+	/// bootstrap method or method handle points to it
+	pub index: usize,
+	/// The string loaded by the instruction right before an invoke, which is its last argument,
+	/// such as the class name given to `Class.forName`. Labels in between are skipped.
+	pub argument: Option<String>
+}
+
+/// The scanned methods a method calls, see [`MethodAnalysis::scan`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScanReport {
+	pub calls: Vec<ApiCall>
+}
+
+impl ScanReport {
+	pub fn is_empty(&self) -> bool {
+		self.calls.is_empty()
+	}
+	
+	/// The calls to the given method
+	pub fn calls_to<'a>(&'a self, class: &'a str, name: &'a str) -> impl Iterator<Item = &'a ApiCall> + 'a {
+		self.calls.iter().filter(move |x| x.class == class && x.name == name)
+	}
+}
+
+/// The findings of a whole class, see [`ClassFile::scan`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClassScanReport {
+	/// The report of each method that calls a scanned method, by name and descriptor
+	pub methods: Vec<(String, ScanReport)>,
+	/// Name and descriptor of each native method
+	pub native_methods: Vec<String>
+}
+
+impl ClassScanReport {
+	pub fn is_empty(&self) -> bool {
+		self.methods.is_empty() && self.native_methods.is_empty()
+	}
+	
+	/// Every call in the class, along with the name and descriptor of the calling method
+	pub fn calls(&self) -> impl Iterator<Item = (&str, &ApiCall)> {
+		self.methods.iter().flat_map(|(method, report)| report.calls.iter().map(move |call| (method.as_str(), call)))
+	}
+}
+
+pub struct MethodAnalysis;
+
+impl MethodAnalysis {
+	/// Finds the calls to the methods of the [default config](ScanConfig::default)
+	pub fn scan(code: &CodeAttribute) -> ScanReport {
+		MethodAnalysis::scan_with(code, &ScanConfig::default())
+	}
+	
+	pub fn scan_with(code: &CodeAttribute, config: &ScanConfig) -> ScanReport {
+		let mut report = ScanReport::default();
+		let mut last_string: Option<&str> = None;
+		for (index, insn) in code.insns.iter().enumerate() {
+			if let Insn::Label(_) = insn {
+				continue;
+			}
+			for member in insn.member_references() {
+				let method = match member.access {
+					MemberAccess::Field { .. } => false,
+					MemberAccess::Handle(kind) => !kind.is_field(),
+					MemberAccess::Invoke(_) => true
+				};
+				if method && config.matches(member.class, member.name) {
+					report.calls.push(ApiCall {
+						class: ClassName::from(member.class),
+						name: String::from(member.name),
+						descriptor: String::from(member.descriptor),
+						index,
+						argument: match insn {
+							Insn::Invoke(_) => last_string.map(String::from),
+							_ => None
+						}
+					});
+				}
+			}
+			last_string = match insn {
+				Insn::Ldc(LdcInsn { constant: LdcType::String(value) }) => Some(value),
+				_ => None
+			};
+		}
+		report
+	}
+}
+
+impl ClassFile {
+	/// Scans every method for calls to the methods of the [default config](ScanConfig::default),
+	/// and lists the native methods
+	pub fn scan(&self) -> ClassScanReport {
+		self.scan_with(&ScanConfig::default())
+	}
+	
+	pub fn scan_with(&self, config: &ScanConfig) -> ClassScanReport {
+		let mut report = ClassScanReport::default();
+		for method in self.methods.iter() {
+			let name = format!("{}{}", method.name, method.descriptor);
+			if method.access_flags.is_native() {
+				report.native_methods.push(name.clone());
+			}
+			for attribute in method.attributes.iter() {
+				if let Attribute::Code(code) = attribute {
+					let scan = MethodAnalysis::scan_with(code, config);
+					if !scan.is_empty() {
+						report.methods.push((name.clone(), scan));
+					}
+				}
+			}
+		}
+		report
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::access::{ClassAccessFlags, MethodAccessFlags};
+	use crate::ast::OpType;
+	use crate::method::Method;
+	use crate::version::{ClassVersion, MajorVersion};
+	
+	/// Loads a class by name, runs a command and loads a native library
+	fn victim() -> ClassFile {
+		let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("Victim"), Some(ClassName::from("java/lang/Object")));
+		let mut code = CodeAttribute { max_stack: 2, max_locals: 1, ..CodeAttribute::default() };
+		let label = code.insns.new_label();
+		code.insns.ldc_string("evil.Payload")
+			.push(Insn::Label(label))
+			.invoke_static("java/lang/Class", "forName", "(Ljava/lang/String;)Ljava/lang/Class;")
+			.pop()
+			.invoke_static("java/lang/Runtime", "getRuntime", "()Ljava/lang/Runtime;")
+			.ldc_string("calc")
+			.invoke_virtual("java/lang/Runtime", "exec", "(Ljava/lang/String;)Ljava/lang/Process;")
+			.pop()
+			.load(OpType::Reference, 0)
+			.invoke_static("java/lang/System", "loadLibrary", "(Ljava/lang/String;)V")
+			.invoke_static("java/lang/invoke/MethodHandles", "lookup", "()Ljava/lang/invoke/MethodHandles$Lookup;")
+			.pop()
+			.return_void();
+		let mut run = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from("run"), String::from("(Ljava/lang/String;)V"));
+		run.set_code(Some(code));
+		class.add_method(run).unwrap();
+		
+		let mut code = CodeAttribute { max_stack: 1, max_locals: 0, ..CodeAttribute::default() };
+		code.insns.ldc_string("harmless").pop().return_void();
+		let mut quiet = Method::new(MethodAccessFlags::STATIC, String::from("quiet"), String::from("()V"));
+		quiet.set_code(Some(code));
+		class.add_method(quiet).unwrap();
+		
+		class.add_method(Method::new(MethodAccessFlags::PRIVATE | MethodAccessFlags::NATIVE, String::from("hook"), String::from("(I)I"))).unwrap();
+		class
+	}
+	
+	#[test]
+	fn finds_reflection_and_loading() {
+		let class = victim();
+		let report = class.scan();
+		assert_eq!(report.native_methods, vec![String::from("hook(I)I")]);
+		assert_eq!(report.methods.len(), 1);
+		let (method, scan) = &report.methods[0];
+		assert_eq!(method, "run(Ljava/lang/String;)V");
+		
+		let found: Vec<(&str, usize, Option<&str>)> = scan.calls.iter()
+			.map(|call| (call.name.as_str(), call.index, call.argument.as_deref()))
+			.collect();
+		assert_eq!(found, vec![
+			// the label between the ldc and the call does not hide the class name
+			("forName", 2, Some("evil.Payload")),
+			("exec", 6, Some("calc")),
+			// loaded from a local, so not known
+			("loadLibrary", 9, None),
+			("lookup", 10, None)
+		]);
+		assert_eq!(scan.calls_to("java/lang/Runtime", "exec").count(), 1);
+		assert_eq!(report.calls().count(), 4);
+	}
+	
+	#[test]
+	fn apis_are_configurable() {
+		let class = victim();
+		let config = ScanConfig::empty().with_api("java/lang/Runtime", "getRuntime");
+		let report = class.scan_with(&config);
+		let calls: Vec<&str> = report.calls().map(|(_, call)| call.name.as_str()).collect();
+		assert_eq!(calls, vec!["getRuntime"]);
+		// native methods are listed whatever the config
+		assert_eq!(report.native_methods.len(), 1);
+		
+		assert!(!ScanConfig::empty().matches("java/lang/Class", "forName"));
+		let clean = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("Clean"), Some(ClassName::from("java/lang/Object")));
+		assert!(clean.scan().is_empty());
+	}
+	
+	#[test]
+	fn method_handles_are_reported() {
+		use crate::ast::MethodHandle;
+		use crate::constantpool::MethodHandleKind;
+		let handle = |kind, name: &str, descriptor: &str| Insn::Ldc(LdcInsn::new(LdcType::MethodHandle(Box::new(MethodHandle::new(kind, ClassName::from("java/lang/Runtime"), String::from(name), String::from(descriptor), false)))));
+		let mut code = CodeAttribute { max_stack: 2, max_locals: 0, ..CodeAttribute::default() };
+		code.insns.ldc_string("calc")
+			.push(handle(MethodHandleKind::InvokeVirtual, "exec", "(Ljava/lang/String;)Ljava/lang/Process;"))
+			.push(handle(MethodHandleKind::GetStatic, "currentRuntime", "Ljava/lang/Runtime;"))
+			.pop()
+			.pop()
+			.pop()
+			.return_void();
+		let config = ScanConfig::empty().with_api("java/lang/Runtime", "exec").with_api("java/lang/Runtime", "currentRuntime");
+		let scan = MethodAnalysis::scan_with(&code, &config);
+		// the handle is not called here, so the string before it is no argument, and field
+		// handles are not calls
+		assert_eq!(scan.calls, vec![ApiCall {
+			class: ClassName::from("java/lang/Runtime"),
+			name: String::from("exec"),
+			descriptor: String::from("(Ljava/lang/String;)Ljava/lang/Process;"),
+			index: 1,
+			argument: None
+		}]);
+	}
+}
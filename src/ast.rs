@@ -1,10 +1,19 @@
-use crate::types::Type;
+use crate::types::{Type, ClassName, parse_type, parse_method_desc};
+use crate::error::{Result, ParserError};
+use crate::constantpool::MethodHandleKind;
+use crate::version::MajorVersion;
+use crate::utils::{fits_u8, fits_i8};
 use derive_more::Constructor;
+use smallvec::SmallVec;
 use std::collections::{BTreeMap};
-use std::fmt::{Debug, Formatter};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 use enum_display_derive::DisplayDebug;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PrimitiveType {
 	Boolean,
 	Byte,
@@ -17,6 +26,7 @@ pub enum PrimitiveType {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpType {
 	Reference,
 	Boolean,
@@ -30,6 +40,7 @@ pub enum OpType {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReturnType {
 	Void,
 	Reference,
@@ -43,13 +54,127 @@ pub enum ReturnType {
 	Double
 }
 
+impl PrimitiveType {
+	const ALL: [PrimitiveType; 8] = [
+		PrimitiveType::Boolean, PrimitiveType::Byte, PrimitiveType::Char, PrimitiveType::Short,
+		PrimitiveType::Int, PrimitiveType::Long, PrimitiveType::Float, PrimitiveType::Double
+	];
+	
+	/// returns the size of the type as a multiple of a dword
+	pub fn size(&self) -> u8 {
+		match self {
+			PrimitiveType::Long | PrimitiveType::Double => 2,
+			_ => 1
+		}
+	}
+	
+	/// The Java keyword, e.g. `int`
+	pub fn name(&self) -> &'static str {
+		match self {
+			PrimitiveType::Boolean => "boolean",
+			PrimitiveType::Byte => "byte",
+			PrimitiveType::Char => "char",
+			PrimitiveType::Short => "short",
+			PrimitiveType::Int => "int",
+			PrimitiveType::Long => "long",
+			PrimitiveType::Float => "float",
+			PrimitiveType::Double => "double"
+		}
+	}
+}
+
+impl Display for PrimitiveType {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for PrimitiveType {
+	type Err = ParserError;
+	
+	fn from_str(s: &str) -> Result<Self> {
+		PrimitiveType::ALL.iter().copied()
+			.find(|x| x.name() == s)
+			.ok_or_else(|| ParserError::unrecognised("primitive type", s.to_string()))
+	}
+}
+
+impl OpType {
+	const ALL: [OpType; 9] = [
+		OpType::Reference, OpType::Boolean, OpType::Byte, OpType::Char, OpType::Short,
+		OpType::Int, OpType::Long, OpType::Float, OpType::Double
+	];
+	
+	/// returns the size of the type as a multiple of a dword
+	pub fn size(&self) -> u8 {
+		match self {
+			OpType::Long | OpType::Double => 2,
+			_ => 1
+		}
+	}
+	
+	/// The Java keyword of primitive types, and `reference`
+	pub fn name(&self) -> &'static str {
+		match self {
+			OpType::Reference => "reference",
+			OpType::Boolean => "boolean",
+			OpType::Byte => "byte",
+			OpType::Char => "char",
+			OpType::Short => "short",
+			OpType::Int => "int",
+			OpType::Long => "long",
+			OpType::Float => "float",
+			OpType::Double => "double"
+		}
+	}
+}
+
+impl Display for OpType {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for OpType {
+	type Err = ParserError;
+	
+	fn from_str(s: &str) -> Result<Self> {
+		OpType::ALL.iter().copied()
+			.find(|x| x.name() == s)
+			.ok_or_else(|| ParserError::unrecognised("operand type", s.to_string()))
+	}
+}
+
+impl ReturnType {
+	/// returns the size of the type as a multiple of a dword
+	pub fn size(&self) -> u8 {
+		match self {
+			ReturnType::Void => 0,
+			ReturnType::Long | ReturnType::Double => 2,
+			_ => 1
+		}
+	}
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IntegerType {
 	Int,
 	Long
 }
 
+impl IntegerType {
+	/// returns the size of the type as a multiple of a dword
+	pub fn size(&self) -> u8 {
+		match self {
+			IntegerType::Int => 1,
+			IntegerType::Long => 2
+		}
+	}
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LabelInsn {
 	/// unique identifier
 	pub(crate) id: u32
@@ -62,83 +187,155 @@ impl LabelInsn {
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArrayLoadInsn {
 	pub kind: Type,
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArrayStoreInsn {
 	pub kind: Type,
 }
 
+/// A class named by an instruction. The constant pool stores array classes by their descriptor and
+/// every other class by its internal name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClassRef {
+	/// An internal class name such as `java/lang/String`
+	Class(ClassName),
+	/// An array with the given component type, `[[I` is an array of `Type::Array(Type::Int)`
+	Array(Type)
+}
+
+impl ClassRef {
+	/// Interprets the name stored in a class constant
+	pub fn parse(name: &str) -> Result<Self> {
+		if !name.starts_with('[') {
+			return Ok(ClassRef::Class(ClassName::from(name)));
+		}
+		match parse_type(name)? {
+			(Type::Array(component), end) if end == name.len() => Ok(ClassRef::Array(*component)),
+			_ => Err(ParserError::invalid_descriptor(format!("Invalid array class {}", name)))
+		}
+	}
+	
+	/// The name to store in a class constant
+	pub fn name(&self) -> String {
+		match self {
+			ClassRef::Class(name) => name.to_string(),
+			ClassRef::Array(component) => format!("[{}", component.descriptor())
+		}
+	}
+	
+	/// The type of a reference to this class
+	pub fn as_type(&self) -> Type {
+		match self {
+			ClassRef::Class(name) => Type::Reference(Some(name.to_string())),
+			ClassRef::Array(component) => Type::Array(Box::new(component.clone()))
+		}
+	}
+}
+
+impl Display for ClassRef {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ClassRef::Class(name) => f.write_str(name),
+			ClassRef::Array(_) => f.write_str(&self.name())
+		}
+	}
+}
+
+impl From<&str> for ClassRef {
+	fn from(name: &str) -> Self {
+		ClassRef::Class(ClassName::from(name))
+	}
+}
+
 #[derive(Constructor, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LdcInsn {
 	pub constant: LdcType
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Floats and doubles are compared by their bits, so that NaN equals itself and 0.0 differs from
+/// -0.0, as they would in the constant pool
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LdcType {
 	Null,
 	String(String),
 	Int(i32),
-	Float(f32),
+	Float(#[cfg_attr(feature = "serde", serde(with = "crate::serde_bits::float"))] f32),
 	Long(i64),
-	Double(f64),
+	Double(#[cfg_attr(feature = "serde", serde(with = "crate::serde_bits::double"))] f64),
 	Class(String),
 	/// Method Descriptor (java.lang.invoke.MethodType)
 	MethodType(String),
-	/// TODO: Method Handle (java.lang.invoke.MethodHandle)
-	MethodHandle(),
-	// TODO: Constant_Dynamic
-	Dynamic()
+	/// Boxed as it is rare and as large as the instructions naming a member
+	MethodHandle(Box<MethodHandle>),
+	Dynamic(Box<DynamicConstant>)
 }
 
 /// Loads a value from the local array slot
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalLoadInsn {
 	pub kind: OpType,
 	pub index: u16 // u8 with normal load, u16 with wide load
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalStoreInsn {
 	pub kind: OpType,
 	pub index: u16 // u8 with normal load, u16 with wide load
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NewArrayInsn {
+	/// The component type, written as `anewarray` for references and arrays and as `newarray`
+	/// otherwise
 	pub kind: Type,
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReturnInsn {
 	pub kind: ReturnType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArrayLengthInsn {}
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ThrowInsn {}
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CheckCastInsn {
-	pub kind: String
+	pub kind: ClassRef
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConvertInsn {
 	pub from: PrimitiveType,
 	pub to: PrimitiveType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AddInsn {
 	pub kind: PrimitiveType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CompareInsn {
 	pub kind: PrimitiveType,
 	/// If both values are NAN and this flag is set, 1 will be pushed. Otherwise -1 will be pushed.
@@ -146,63 +343,75 @@ pub struct CompareInsn {
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DivideInsn {
 	pub kind: PrimitiveType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MultiplyInsn {
 	pub kind: PrimitiveType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NegateInsn {
 	pub kind: PrimitiveType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RemainderInsn {
 	pub kind: PrimitiveType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubtractInsn {
 	pub kind: PrimitiveType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AndInsn {
 	pub kind: IntegerType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrInsn {
 	pub kind: IntegerType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XorInsn {
 	pub kind: IntegerType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShiftLeftInsn {
 	pub kind: IntegerType
 }
 
 /// Arithmetically shift right
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShiftRightInsn {
 	pub kind: IntegerType
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LogicalShiftRightInsn {
 	pub kind: IntegerType
 }
 
 /// duplicates the value at the top of the stack
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DupInsn {
 	/// The number of items to duplicate
 	pub num: u8,
@@ -211,6 +420,7 @@ pub struct DupInsn {
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PopInsn {
 	/// if false, pop a single 32bit item off the stack (not long or double)
 	/// if true, pop either two 32bit items, or one 64bit item (long or double)
@@ -218,11 +428,12 @@ pub struct PopInsn {
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GetFieldInsn {
 	/// Is this field an instance or static field?
 	pub instance: bool,
 	/// The declaring class
-	pub class: String,
+	pub class: ClassName,
 	/// The field name
 	pub name: String,
 	/// The field descriptor
@@ -230,11 +441,12 @@ pub struct GetFieldInsn {
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PutFieldInsn {
 	/// Is this field an instance or static field?
 	pub instance: bool,
 	/// The declaring class
-	pub class: String,
+	pub class: ClassName,
 	/// The field name
 	pub name: String,
 	/// The field descriptor
@@ -243,17 +455,27 @@ pub struct PutFieldInsn {
 
 /// Unconditional Jump
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JumpInsn {
 	pub jump_to: LabelInsn
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConditionalJumpInsn {
 	pub condition: JumpCondition,
 	pub jump_to: LabelInsn
 }
 
+impl ConditionalJumpInsn {
+	/// Inverts the condition, the jump target stays the same
+	pub fn negate(&mut self) {
+		self.condition = self.condition.negate();
+	}
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum JumpCondition {
 	/// The reference at the top of the stack is null
 	IsNull,
@@ -289,7 +511,81 @@ pub enum JumpCondition {
 	IntGreaterThanOrEqZero,
 }
 
+impl JumpCondition {
+	const ALL: [JumpCondition; 16] = [
+		JumpCondition::IsNull, JumpCondition::NotNull,
+		JumpCondition::ReferencesEqual, JumpCondition::ReferencesNotEqual,
+		JumpCondition::IntsEq, JumpCondition::IntsNotEq,
+		JumpCondition::IntsLessThan, JumpCondition::IntsLessThanOrEq,
+		JumpCondition::IntsGreaterThan, JumpCondition::IntsGreaterThanOrEq,
+		JumpCondition::IntEqZero, JumpCondition::IntNotEqZero,
+		JumpCondition::IntLessThanZero, JumpCondition::IntLessThanOrEqZero,
+		JumpCondition::IntGreaterThanZero, JumpCondition::IntGreaterThanOrEqZero
+	];
+	
+	/// The mnemonic of the jump instruction testing this condition, e.g. `if_icmplt`
+	pub fn mnemonic(&self) -> &'static str {
+		match self {
+			JumpCondition::IsNull => "ifnull",
+			JumpCondition::NotNull => "ifnonnull",
+			JumpCondition::ReferencesEqual => "if_acmpeq",
+			JumpCondition::ReferencesNotEqual => "if_acmpne",
+			JumpCondition::IntsEq => "if_icmpeq",
+			JumpCondition::IntsNotEq => "if_icmpne",
+			JumpCondition::IntsLessThan => "if_icmplt",
+			JumpCondition::IntsLessThanOrEq => "if_icmple",
+			JumpCondition::IntsGreaterThan => "if_icmpgt",
+			JumpCondition::IntsGreaterThanOrEq => "if_icmpge",
+			JumpCondition::IntEqZero => "ifeq",
+			JumpCondition::IntNotEqZero => "ifne",
+			JumpCondition::IntLessThanZero => "iflt",
+			JumpCondition::IntLessThanOrEqZero => "ifle",
+			JumpCondition::IntGreaterThanZero => "ifgt",
+			JumpCondition::IntGreaterThanOrEqZero => "ifge",
+		}
+	}
+	
+	/// The condition that holds exactly when this one does not
+	pub fn negate(self) -> JumpCondition {
+		match self {
+			JumpCondition::IsNull => JumpCondition::NotNull,
+			JumpCondition::NotNull => JumpCondition::IsNull,
+			JumpCondition::ReferencesEqual => JumpCondition::ReferencesNotEqual,
+			JumpCondition::ReferencesNotEqual => JumpCondition::ReferencesEqual,
+			JumpCondition::IntsEq => JumpCondition::IntsNotEq,
+			JumpCondition::IntsNotEq => JumpCondition::IntsEq,
+			JumpCondition::IntsLessThan => JumpCondition::IntsGreaterThanOrEq,
+			JumpCondition::IntsLessThanOrEq => JumpCondition::IntsGreaterThan,
+			JumpCondition::IntsGreaterThan => JumpCondition::IntsLessThanOrEq,
+			JumpCondition::IntsGreaterThanOrEq => JumpCondition::IntsLessThan,
+			JumpCondition::IntEqZero => JumpCondition::IntNotEqZero,
+			JumpCondition::IntNotEqZero => JumpCondition::IntEqZero,
+			JumpCondition::IntLessThanZero => JumpCondition::IntGreaterThanOrEqZero,
+			JumpCondition::IntLessThanOrEqZero => JumpCondition::IntGreaterThanZero,
+			JumpCondition::IntGreaterThanZero => JumpCondition::IntLessThanOrEqZero,
+			JumpCondition::IntGreaterThanOrEqZero => JumpCondition::IntLessThanZero,
+		}
+	}
+}
+
+impl Display for JumpCondition {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.mnemonic())
+	}
+}
+
+impl FromStr for JumpCondition {
+	type Err = ParserError;
+	
+	fn from_str(s: &str) -> Result<Self> {
+		JumpCondition::ALL.iter().copied()
+			.find(|x| x.mnemonic() == s)
+			.ok_or_else(|| ParserError::unrecognised("jump condition", s.to_string()))
+	}
+}
+
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IncrementIntInsn {
 	/// Index of the local variable
 	pub index: u16,
@@ -298,47 +594,163 @@ pub struct IncrementIntInsn {
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InstanceOfInsn {
-	pub class: String
+	pub class: ClassRef
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InvokeDynamicInsn {
 	pub name: String,
 	pub descriptor: String,
 	pub bootstrap_type: BootstrapMethodType,
-	pub bootstrap_class: String,
+	pub bootstrap_class: ClassName,
 	pub bootstrap_method: String,
 	pub bootstrap_descriptor: String,
 	pub bootstrap_arguments: Vec<BootstrapArgument>
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// A static argument to a bootstrap method, which may be any loadable constant
+/// Floats and doubles are compared by their bits, like [`LdcType`]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BootstrapArgument {
 	Int(i32),
-	Float(f32),
+	Float(#[cfg_attr(feature = "serde", serde(with = "crate::serde_bits::float"))] f32),
 	Long(i64),
-	Double(f64),
-	Class(String)
-	// TODO: Continue. Do we have to do this for every constant type? Spec seems to suggest so
+	Double(#[cfg_attr(feature = "serde", serde(with = "crate::serde_bits::double"))] f64),
+	Class(ClassName),
+	String(String),
+	/// Method Descriptor (java.lang.invoke.MethodType)
+	MethodType(String),
+	MethodHandle(MethodHandle),
+	Dynamic(Box<DynamicConstant>)
+}
+
+/// A java.lang.invoke.MethodHandle constant
+#[derive(Constructor, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MethodHandle {
+	pub kind: MethodHandleKind,
+	pub class: ClassName,
+	pub name: String,
+	pub descriptor: String,
+	/// Whether the class is an interface, only used by static and special invocations
+	pub interface: bool
+}
+
+/// A constant computed by a bootstrap method the first time it is loaded (CONSTANT_Dynamic)
+#[derive(Constructor, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DynamicConstant {
+	pub name: String,
+	pub descriptor: String,
+	pub bootstrap_method: MethodHandle,
+	pub bootstrap_arguments: Vec<BootstrapArgument>
+}
+
+impl DynamicConstant {
+	/// Whether the constant is a long or double
+	pub fn double_size(&self) -> bool {
+		self.descriptor == "J" || self.descriptor == "D"
+	}
+}
+
+impl BootstrapArgument {
+	/// Whether the constant takes up two pool indices and two stack slots
+	pub fn double_size(&self) -> bool {
+		match self {
+			BootstrapArgument::Long(_) | BootstrapArgument::Double(_) => true,
+			BootstrapArgument::Dynamic(x) => x.double_size(),
+			_ => false
+		}
+	}
+	
+	/// The first class file version whose constant pool can hold this constant
+	pub fn minimum_version(&self) -> MajorVersion {
+		match self {
+			BootstrapArgument::MethodType(_) | BootstrapArgument::MethodHandle(_) => MajorVersion::JAVA_7,
+			BootstrapArgument::Dynamic(_) => MajorVersion::JAVA_11,
+			_ => MajorVersion::JDK_1_1
+		}
+	}
+}
+
+impl PartialEq for BootstrapArgument {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			(BootstrapArgument::Int(a), BootstrapArgument::Int(b)) => a == b,
+			(BootstrapArgument::Float(a), BootstrapArgument::Float(b)) => a.to_bits() == b.to_bits(),
+			(BootstrapArgument::Long(a), BootstrapArgument::Long(b)) => a == b,
+			(BootstrapArgument::Double(a), BootstrapArgument::Double(b)) => a.to_bits() == b.to_bits(),
+			(BootstrapArgument::Class(a), BootstrapArgument::Class(b)) => a == b,
+			(BootstrapArgument::String(a), BootstrapArgument::String(b)) => a == b,
+			(BootstrapArgument::MethodType(a), BootstrapArgument::MethodType(b)) => a == b,
+			(BootstrapArgument::MethodHandle(a), BootstrapArgument::MethodHandle(b)) => a == b,
+			(BootstrapArgument::Dynamic(a), BootstrapArgument::Dynamic(b)) => a == b,
+			_ => false
+		}
+	}
+}
+
+impl PartialEq for LdcType {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			(LdcType::Null, LdcType::Null) => true,
+			(LdcType::String(a), LdcType::String(b)) => a == b,
+			(LdcType::Int(a), LdcType::Int(b)) => a == b,
+			(LdcType::Float(a), LdcType::Float(b)) => a.to_bits() == b.to_bits(),
+			(LdcType::Long(a), LdcType::Long(b)) => a == b,
+			(LdcType::Double(a), LdcType::Double(b)) => a.to_bits() == b.to_bits(),
+			(LdcType::Class(a), LdcType::Class(b)) => a == b,
+			(LdcType::MethodType(a), LdcType::MethodType(b)) => a == b,
+			(LdcType::MethodHandle(a), LdcType::MethodHandle(b)) => a == b,
+			(LdcType::Dynamic(a), LdcType::Dynamic(b)) => a == b,
+			_ => false
+		}
+	}
+}
+
+impl LdcType {
+	/// The same constant as a bootstrap argument, or None for null which is not in the pool
+	pub fn to_bootstrap_argument(&self) -> Option<BootstrapArgument> {
+		Some(match self {
+			LdcType::Null => return None,
+			LdcType::String(x) => BootstrapArgument::String(x.clone()),
+			LdcType::Int(x) => BootstrapArgument::Int(*x),
+			LdcType::Float(x) => BootstrapArgument::Float(*x),
+			LdcType::Long(x) => BootstrapArgument::Long(*x),
+			LdcType::Double(x) => BootstrapArgument::Double(*x),
+			LdcType::Class(x) => BootstrapArgument::Class(ClassName::from(x.as_str())),
+			LdcType::MethodType(x) => BootstrapArgument::MethodType(x.clone()),
+			LdcType::MethodHandle(x) => BootstrapArgument::MethodHandle((**x).clone()),
+			LdcType::Dynamic(x) => BootstrapArgument::Dynamic(x.clone())
+		})
+	}
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BootstrapMethodType {
 	InvokeStatic,
 	NewInvokeSpecial
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InvokeInsn {
 	pub kind: InvokeType,
-	pub class: String,
+	pub class: ClassName,
 	pub name: String,
 	pub descriptor: String,
+	/// Whether the method is declared by an interface. Static and special calls of interface
+	/// methods need at least a Java 8 class file.
 	pub interface_method: bool
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InvokeType {
 	Instance,
 	Static,
@@ -346,7 +758,42 @@ pub enum InvokeType {
 	Special
 }
 
+impl InvokeType {
+	/// The mnemonic of the invoke instruction, e.g. `invokevirtual`
+	pub fn mnemonic(&self) -> &'static str {
+		match self {
+			InvokeType::Instance => "invokevirtual",
+			InvokeType::Static => "invokestatic",
+			InvokeType::Interface => "invokeinterface",
+			InvokeType::Special => "invokespecial"
+		}
+	}
+}
+
+impl Display for InvokeType {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.mnemonic())
+	}
+}
+
+impl FromStr for InvokeType {
+	type Err = ParserError;
+	
+	/// Accepts the mnemonic, e.g. `invokevirtual`, or the JVMS name of the matching method handle
+	/// kind, e.g. `REF_invokeVirtual`
+	fn from_str(s: &str) -> Result<Self> {
+		Ok(match s {
+			"invokevirtual" | "REF_invokeVirtual" => InvokeType::Instance,
+			"invokestatic" | "REF_invokeStatic" => InvokeType::Static,
+			"invokeinterface" | "REF_invokeInterface" => InvokeType::Interface,
+			"invokespecial" | "REF_invokeSpecial" => InvokeType::Special,
+			_ => return Err(ParserError::unrecognised("invoke type", s.to_string()))
+		})
+	}
+}
+
 #[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LookupSwitchInsn {
 	pub default: LabelInsn,
 	pub(crate) cases: BTreeMap<i32, LabelInsn>
@@ -382,12 +829,13 @@ impl Debug for LookupSwitchInsn {
 		}
 		
 		f.debug_struct("LookupSwitchInsn")
-			.field("cases", &DebugCases{ tbl: &self })
+			.field("cases", &DebugCases{ tbl: self })
 			.finish()
 	}
 }
 
 #[derive(Constructor, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TableSwitchInsn {
 	pub default: LabelInsn,
 	pub(crate) low: i32,
@@ -397,11 +845,7 @@ pub struct TableSwitchInsn {
 impl TableSwitchInsn {
 	#[allow(dead_code)]
 	pub fn get(&self, case: i32) -> Option<LabelInsn> {
-		if let Some(x) = self.cases.get((case - self.low) as usize) {
-			Some(*x)
-		} else {
-			None
-		}
+		self.cases.get((case - self.low) as usize).copied()
 	}
 }
 
@@ -422,48 +866,60 @@ impl Debug for TableSwitchInsn {
 		}
 		
 		f.debug_struct("TableSwitchInsn")
-			.field("cases", &DebugCases{ tbl: &self })
+			.field("cases", &DebugCases{ tbl: self })
 			.finish()
 	}
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MonitorEnterInsn {}
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MonitorExitInsn {}
 
 /// New multi dimensional object array
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MultiNewArrayInsn {
-	pub kind: String,
+	/// The type of the array created, which has at least `dimensions` dimensions
+	pub kind: ClassRef,
 	pub dimensions: u8
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NewObjectInsn {
-	pub kind: String
+	pub kind: ClassRef
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NopInsn {}
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SwapInsn {}
 
 /// Implementation dependent insn
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImpDep1Insn {}
 
 /// Implementation dependent insn
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImpDep2Insn {}
 
 /// Used by debuggers
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BreakPointInsn {}
 
 #[derive(Clone, PartialEq, DisplayDebug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "op"))]
 pub enum Insn {
 	Label(LabelInsn),
 	ArrayLoad(ArrayLoadInsn),
@@ -492,14 +948,16 @@ pub enum Insn {
 	LogicalShiftRight(LogicalShiftRightInsn),
 	Dup(DupInsn),
 	Pop(PopInsn),
-	GetField(GetFieldInsn),
-	PutField(PutFieldInsn),
+	/// Boxed, like the other instructions naming a member, to keep every instruction small
+	GetField(Box<GetFieldInsn>),
+	PutField(Box<PutFieldInsn>),
 	Jump(JumpInsn),
 	ConditionalJump(ConditionalJumpInsn),
 	IncrementInt(IncrementIntInsn),
 	InstanceOf(InstanceOfInsn),
-	InvokeDynamic(InvokeDynamicInsn),
-	Invoke(InvokeInsn),
+	/// Boxed as it is rare but would otherwise double the size of every instruction
+	InvokeDynamic(Box<InvokeDynamicInsn>),
+	Invoke(Box<InvokeInsn>),
 	LookupSwitch(LookupSwitchInsn),
 	TableSwitch(TableSwitchInsn),
 	MonitorEnter(MonitorEnterInsn),
@@ -512,3 +970,1010 @@ pub enum Insn {
 	ImpDep2(ImpDep2Insn),
 	BreakPoint(BreakPointInsn)
 }
+
+impl From<InvokeDynamicInsn> for Insn {
+	fn from(insn: InvokeDynamicInsn) -> Self {
+		Insn::InvokeDynamic(Box::new(insn))
+	}
+}
+
+impl From<InvokeInsn> for Insn {
+	fn from(insn: InvokeInsn) -> Self {
+		Insn::Invoke(Box::new(insn))
+	}
+}
+
+impl From<GetFieldInsn> for Insn {
+	fn from(insn: GetFieldInsn) -> Self {
+		Insn::GetField(Box::new(insn))
+	}
+}
+
+impl From<PutFieldInsn> for Insn {
+	fn from(insn: PutFieldInsn) -> Self {
+		Insn::PutField(Box::new(insn))
+	}
+}
+
+/// The number of operand stack slots an instruction pops and then pushes. Longs and doubles take
+/// two slots.
+#[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StackEffect {
+	pub pops: u8,
+	pub pushes: u8
+}
+
+impl StackEffect {
+	/// The change in stack size
+	pub fn delta(&self) -> i16 {
+		self.pushes as i16 - self.pops as i16
+	}
+}
+
+/// A read or write of a local variable slot
+#[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LocalAccess {
+	pub index: u16,
+	pub kind: OpType,
+	/// `iinc` both reads and writes its local and is reported as a store
+	pub is_store: bool
+}
+
+impl Insn {
+	/// The first class file version that may contain this instruction, along with the constants
+	/// it loads
+	pub fn minimum_version(&self) -> MajorVersion {
+		match self {
+			Insn::InvokeDynamic(x) => x.bootstrap_arguments.iter()
+				.map(BootstrapArgument::minimum_version)
+				.fold(MajorVersion::JAVA_7, std::cmp::max),
+			// class constants could not be loaded before Java 5
+			Insn::Ldc(LdcInsn { constant: LdcType::Class(_) }) => MajorVersion::JAVA_5,
+			Insn::Ldc(x) => x.constant.to_bootstrap_argument()
+				.map_or(MajorVersion::JDK_1_1, |x| x.minimum_version()),
+			// interface method refs are only allowed for static and special calls since Java 8
+			Insn::Invoke(x) if x.interface_method && matches!(x.kind, InvokeType::Static | InvokeType::Special) => MajorVersion::JAVA_8,
+			_ => MajorVersion::JDK_1_1
+		}
+	}
+	
+	/// How many stack slots this instruction pops and pushes when execution continues normally.
+	/// Instructions that leave the method, such as returns and throws, only report their pops.
+	pub fn stack_effect(&self) -> Result<StackEffect> {
+		let size = |typ: &Type| -> Result<u8> {
+			match typ {
+				Type::Void => Err(ParserError::invalid_descriptor("Void is not a value type")),
+				x => Ok(x.size())
+			}
+		};
+		let effect = |pops: u8, pushes: u8| Ok(StackEffect::new(pops, pushes));
+		match self {
+			Insn::Label(_) => effect(0, 0),
+			// array reference and index
+			Insn::ArrayLoad(x) => effect(2, size(&x.kind)?),
+			Insn::ArrayStore(x) => effect(2 + size(&x.kind)?, 0),
+			Insn::Ldc(x) => effect(0, match &x.constant {
+				LdcType::Long(_) | LdcType::Double(_) => 2,
+				LdcType::Dynamic(x) if x.double_size() => 2,
+				_ => 1
+			}),
+			Insn::LocalLoad(x) => effect(0, x.kind.size()),
+			Insn::LocalStore(x) => effect(x.kind.size(), 0),
+			Insn::NewArray(_) => effect(1, 1),
+			Insn::Return(x) => effect(x.kind.size(), 0),
+			Insn::ArrayLength(_) => effect(1, 1),
+			Insn::Throw(_) => effect(1, 0),
+			Insn::CheckCast(_) => effect(1, 1),
+			Insn::Convert(x) => effect(x.from.size(), x.to.size()),
+			Insn::Add(AddInsn { kind }) | Insn::Divide(DivideInsn { kind }) | Insn::Multiply(MultiplyInsn { kind })
+			| Insn::Remainder(RemainderInsn { kind }) | Insn::Subtract(SubtractInsn { kind }) => {
+				effect(kind.size() * 2, kind.size())
+			},
+			Insn::Compare(x) => effect(x.kind.size() * 2, 1),
+			Insn::Negate(x) => effect(x.kind.size(), x.kind.size()),
+			Insn::And(AndInsn { kind }) | Insn::Or(OrInsn { kind }) | Insn::Xor(XorInsn { kind }) => {
+				let size = kind.size();
+				effect(size * 2, size)
+			},
+			// the shift distance is always an int
+			Insn::ShiftLeft(ShiftLeftInsn { kind }) | Insn::ShiftRight(ShiftRightInsn { kind })
+			| Insn::LogicalShiftRight(LogicalShiftRightInsn { kind }) => {
+				let size = kind.size();
+				effect(size + 1, size)
+			},
+			Insn::Dup(x) => effect(x.num + x.down, x.num * 2 + x.down),
+			Insn::Pop(x) => effect(if x.pop_two { 2 } else { 1 }, 0),
+			Insn::GetField(x) => {
+				let (typ, _) = parse_type(&x.descriptor)?;
+				effect(if x.instance { 1 } else { 0 }, size(&typ)?)
+			},
+			Insn::PutField(x) => {
+				let (typ, _) = parse_type(&x.descriptor)?;
+				effect(size(&typ)? + if x.instance { 1 } else { 0 }, 0)
+			},
+			Insn::Jump(_) => effect(0, 0),
+			Insn::ConditionalJump(x) => effect(match x.condition {
+				JumpCondition::IsNull | JumpCondition::NotNull | JumpCondition::IntEqZero | JumpCondition::IntNotEqZero
+				| JumpCondition::IntLessThanZero | JumpCondition::IntLessThanOrEqZero | JumpCondition::IntGreaterThanZero
+				| JumpCondition::IntGreaterThanOrEqZero => 1,
+				_ => 2
+			}, 0),
+			Insn::IncrementInt(_) => effect(0, 0),
+			Insn::InstanceOf(_) => effect(1, 1),
+			Insn::InvokeDynamic(x) => Insn::invoke_effect(&x.descriptor, false),
+			Insn::Invoke(x) => Insn::invoke_effect(&x.descriptor, x.kind != InvokeType::Static),
+			Insn::LookupSwitch(_) | Insn::TableSwitch(_) => effect(1, 0),
+			Insn::MonitorEnter(_) | Insn::MonitorExit(_) => effect(1, 0),
+			Insn::MultiNewArray(x) => effect(x.dimensions, 1),
+			Insn::NewObject(_) => effect(0, 1),
+			Insn::Nop(_) => effect(0, 0),
+			Insn::Swap(_) => effect(2, 2),
+			Insn::ImpDep1(_) | Insn::ImpDep2(_) | Insn::BreakPoint(_) => effect(0, 0)
+		}
+	}
+	
+	fn invoke_effect(descriptor: &str, has_receiver: bool) -> Result<StackEffect> {
+		let (args, ret) = parse_method_desc(descriptor)?;
+		let mut pops: u8 = if has_receiver { 1 } else { 0 };
+		for arg in args.iter() {
+			if *arg == Type::Void {
+				return Err(ParserError::invalid_descriptor("Void is not a value type"));
+			}
+			pops = pops.checked_add(arg.size())
+				.ok_or_else(|| ParserError::invalid_descriptor("Method has too many arguments"))?;
+		}
+		Ok(StackEffect::new(pops, ret.size()))
+	}
+	
+	/// The most bytes this instruction takes in the code of a method when it starts at `pc_hint`,
+	/// which only matters for the padding of switches. Jumps are sized as if the target were too
+	/// far for a 16 bit offset and constant loads as if the constant index did not fit in a byte,
+	/// so the size written can be smaller.
+	pub fn encoded_size(&self, pc_hint: u32) -> Result<u32> {
+		let local = |index: u16| match index {
+			0..=3 => 1,
+			x if fits_u8(x) => 2,
+			_ => 4
+		};
+		Ok(match self {
+			Insn::Label(_) => 0,
+			Insn::Ldc(x) => match x.constant {
+				LdcType::Null => 1,
+				_ => 3
+			},
+			Insn::LocalLoad(x) => local(x.index),
+			Insn::LocalStore(x) => local(x.index),
+			Insn::NewArray(x) => match x.kind {
+				Type::Reference(_) | Type::Array(_) => 3,
+				_ => 2
+			},
+			Insn::CheckCast(_) | Insn::InstanceOf(_) | Insn::NewObject(_) | Insn::GetField(_) | Insn::PutField(_) => 3,
+			// ints are widened and compared as longs
+			Insn::Compare(x) => match x.kind {
+				PrimitiveType::Long | PrimitiveType::Float | PrimitiveType::Double => 1,
+				_ => 2
+			},
+			// goto_w
+			Insn::Jump(_) => 5,
+			// the inverted condition jumping over a goto_w
+			Insn::ConditionalJump(_) => 8,
+			Insn::IncrementInt(x) => if fits_u8(x.index) && fits_i8(x.amount) { 3 } else { 6 },
+			Insn::InvokeDynamic(_) => 5,
+			Insn::Invoke(x) => if x.kind == InvokeType::Interface { 5 } else { 3 },
+			Insn::LookupSwitch(x) => Insn::switch_size(pc_hint, 8, x.cases.len(), 8)?,
+			Insn::TableSwitch(x) => Insn::switch_size(pc_hint, 12, x.cases.len(), 4)?,
+			Insn::MultiNewArray(_) => 4,
+			Insn::ArrayLoad(_) | Insn::ArrayStore(_) | Insn::Return(_) | Insn::ArrayLength(_) | Insn::Throw(_)
+			| Insn::Convert(_) | Insn::Add(_) | Insn::Divide(_) | Insn::Multiply(_) | Insn::Negate(_)
+			| Insn::Remainder(_) | Insn::Subtract(_) | Insn::And(_) | Insn::Or(_) | Insn::Xor(_)
+			| Insn::ShiftLeft(_) | Insn::ShiftRight(_) | Insn::LogicalShiftRight(_) | Insn::Dup(_) | Insn::Pop(_)
+			| Insn::MonitorEnter(_) | Insn::MonitorExit(_) | Insn::Nop(_) | Insn::Swap(_) | Insn::ImpDep1(_)
+			| Insn::ImpDep2(_) | Insn::BreakPoint(_) => 1
+		})
+	}
+	
+	/// The opcode and padding to a multiple of four bytes, then the fixed operands and the cases
+	fn switch_size(pc: u32, fixed: usize, cases: usize, case_size: usize) -> Result<u32> {
+		let pad = 3 - pc % 4;
+		cases.checked_mul(case_size)
+			.and_then(|x| x.checked_add(1 + pad as usize + fixed))
+			.and_then(|x| u32::try_from(x).ok())
+			.ok_or_else(ParserError::too_many_instructions)
+	}
+	
+	/// The local variable this instruction reads or writes, if any
+	pub fn local_access(&self) -> Option<LocalAccess> {
+		match self {
+			Insn::LocalLoad(x) => Some(LocalAccess::new(x.index, x.kind, false)),
+			Insn::LocalStore(x) => Some(LocalAccess::new(x.index, x.kind, true)),
+			Insn::IncrementInt(x) => Some(LocalAccess::new(x.index, OpType::Int, true)),
+			_ => None
+		}
+	}
+	
+	/// Whether execution can never continue to the next instruction
+	pub fn is_terminal(&self) -> bool {
+		matches!(self,
+			Insn::Return(_) | Insn::Throw(_) | Insn::Jump(_) | Insn::LookupSwitch(_) | Insn::TableSwitch(_)
+		)
+	}
+	
+	/// The labels this instruction may transfer control to, not including the next instruction
+	pub fn jump_targets(&self) -> Vec<LabelInsn> {
+		match self {
+			Insn::Jump(x) => vec![x.jump_to],
+			Insn::ConditionalJump(x) => vec![x.jump_to],
+			Insn::LookupSwitch(x) => {
+				let mut targets = vec![x.default];
+				targets.extend(x.cases.values());
+				targets
+			},
+			Insn::TableSwitch(x) => {
+				let mut targets = vec![x.default];
+				targets.extend(x.cases.iter());
+				targets
+			},
+			_ => Vec::new()
+		}
+	}
+	
+	/// Replaces every label this instruction defines or jumps to with the result of `op`
+	pub fn map_labels<F: FnMut(LabelInsn) -> LabelInsn>(&mut self, mut op: F) {
+		match self {
+			Insn::Label(x) => *x = op(*x),
+			Insn::Jump(x) => x.jump_to = op(x.jump_to),
+			Insn::ConditionalJump(x) => x.jump_to = op(x.jump_to),
+			Insn::LookupSwitch(x) => {
+				x.default = op(x.default);
+				for target in x.cases.values_mut() {
+					*target = op(*target);
+				}
+			},
+			Insn::TableSwitch(x) => {
+				x.default = op(x.default);
+				for target in x.cases.iter_mut() {
+					*target = op(*target);
+				}
+			},
+			_ => {}
+		}
+	}
+	
+	/// Every class this instruction names, including the classes inside its descriptors and
+	/// bootstrap arguments. Array classes are reported by their descriptor, such as
+	/// `[Ljava/lang/String;`, as the constant pool names them.
+	pub fn class_references(&self) -> ClassReferences<'_> {
+		let mut refs = SmallVec::new();
+		match self {
+			Insn::ArrayLoad(x) => refs.extend(type_class(&x.kind).map(ClassReference::Class)),
+			Insn::ArrayStore(x) => refs.extend(type_class(&x.kind).map(ClassReference::Class)),
+			Insn::NewArray(x) => refs.extend(type_class(&x.kind).map(ClassReference::Class)),
+			Insn::Ldc(x) => match &x.constant {
+				LdcType::Class(class) => refs.push(ClassReference::Class(Cow::Borrowed(class))),
+				LdcType::MethodType(descriptor) => refs.push(ClassReference::Descriptor(descriptor)),
+				LdcType::MethodHandle(handle) => handle_classes(&mut refs, handle),
+				LdcType::Dynamic(constant) => dynamic_classes(&mut refs, constant),
+				_ => {}
+			},
+			Insn::CheckCast(x) => refs.push(class_ref_class(&x.kind)),
+			Insn::InstanceOf(x) => refs.push(class_ref_class(&x.class)),
+			Insn::MultiNewArray(x) => refs.push(class_ref_class(&x.kind)),
+			Insn::NewObject(x) => refs.push(class_ref_class(&x.kind)),
+			Insn::GetField(x) => member_classes(&mut refs, &x.class, &x.descriptor),
+			Insn::PutField(x) => member_classes(&mut refs, &x.class, &x.descriptor),
+			Insn::Invoke(x) => member_classes(&mut refs, &x.class, &x.descriptor),
+			Insn::InvokeDynamic(x) => {
+				refs.push(ClassReference::Descriptor(&x.descriptor));
+				member_classes(&mut refs, &x.bootstrap_class, &x.bootstrap_descriptor);
+				argument_classes(&mut refs, &x.bootstrap_arguments);
+			},
+			_ => {}
+		}
+		refs
+	}
+	
+	/// [`class_references`](Self::class_references), for rewriting in place. Array classes are
+	/// handed out as their element class, and not at all for arrays of primitives.
+	pub fn class_references_mut(&mut self) -> ClassReferencesMut<'_> {
+		let mut refs = SmallVec::new();
+		match self {
+			Insn::ArrayLoad(x) => refs.extend(type_class_mut(&mut x.kind).map(ClassReferenceMut::Name)),
+			Insn::ArrayStore(x) => refs.extend(type_class_mut(&mut x.kind).map(ClassReferenceMut::Name)),
+			Insn::NewArray(x) => refs.extend(type_class_mut(&mut x.kind).map(ClassReferenceMut::Name)),
+			Insn::Ldc(x) => match &mut x.constant {
+				LdcType::Class(class) => refs.push(ClassReferenceMut::Name(class)),
+				LdcType::MethodType(descriptor) => refs.push(ClassReferenceMut::Descriptor(descriptor)),
+				LdcType::MethodHandle(handle) => handle_classes_mut(&mut refs, handle),
+				LdcType::Dynamic(constant) => dynamic_classes_mut(&mut refs, constant),
+				_ => {}
+			},
+			Insn::CheckCast(x) => refs.extend(class_ref_class_mut(&mut x.kind)),
+			Insn::InstanceOf(x) => refs.extend(class_ref_class_mut(&mut x.class)),
+			Insn::MultiNewArray(x) => refs.extend(class_ref_class_mut(&mut x.kind)),
+			Insn::NewObject(x) => refs.extend(class_ref_class_mut(&mut x.kind)),
+			Insn::GetField(x) => {
+				refs.push(ClassReferenceMut::Class(&mut x.class));
+				refs.push(ClassReferenceMut::Descriptor(&mut x.descriptor));
+			},
+			Insn::PutField(x) => {
+				refs.push(ClassReferenceMut::Class(&mut x.class));
+				refs.push(ClassReferenceMut::Descriptor(&mut x.descriptor));
+			},
+			Insn::Invoke(x) => {
+				refs.push(ClassReferenceMut::Class(&mut x.class));
+				refs.push(ClassReferenceMut::Descriptor(&mut x.descriptor));
+			},
+			Insn::InvokeDynamic(x) => {
+				refs.push(ClassReferenceMut::Descriptor(&mut x.descriptor));
+				refs.push(ClassReferenceMut::Class(&mut x.bootstrap_class));
+				refs.push(ClassReferenceMut::Descriptor(&mut x.bootstrap_descriptor));
+				argument_classes_mut(&mut refs, &mut x.bootstrap_arguments);
+			},
+			_ => {}
+		}
+		refs
+	}
+	
+	/// Every field and method this instruction names and how it uses them: the target of field
+	/// and invoke instructions, bootstrap methods and method handle constants
+	pub fn member_references(&self) -> MemberReferences<'_> {
+		let mut refs = SmallVec::new();
+		match self {
+			Insn::Ldc(x) => match &x.constant {
+				LdcType::MethodHandle(handle) => refs.push(MemberReference::handle(handle)),
+				LdcType::Dynamic(constant) => dynamic_members(&mut refs, constant),
+				_ => {}
+			},
+			Insn::GetField(x) => refs.push(MemberReference::new(&x.class, &x.name, &x.descriptor, MemberAccess::Field { is_static: !x.instance, is_write: false })),
+			Insn::PutField(x) => refs.push(MemberReference::new(&x.class, &x.name, &x.descriptor, MemberAccess::Field { is_static: !x.instance, is_write: true })),
+			Insn::Invoke(x) => refs.push(MemberReference::new(&x.class, &x.name, &x.descriptor, MemberAccess::Invoke(x.kind))),
+			Insn::InvokeDynamic(x) => {
+				let kind = match x.bootstrap_type {
+					BootstrapMethodType::InvokeStatic => InvokeType::Static,
+					BootstrapMethodType::NewInvokeSpecial => InvokeType::Special
+				};
+				refs.push(MemberReference::new(&x.bootstrap_class, &x.bootstrap_method, &x.bootstrap_descriptor, MemberAccess::Invoke(kind)));
+				argument_members(&mut refs, &x.bootstrap_arguments);
+			},
+			_ => {}
+		}
+		refs
+	}
+	
+	/// [`member_references`](Self::member_references), for rewriting in place
+	pub fn member_references_mut(&mut self) -> MemberReferencesMut<'_> {
+		let mut refs = SmallVec::new();
+		match self {
+			Insn::Ldc(x) => match &mut x.constant {
+				LdcType::MethodHandle(handle) => refs.push(MemberReferenceMut::handle(handle)),
+				LdcType::Dynamic(constant) => dynamic_members_mut(&mut refs, constant),
+				_ => {}
+			},
+			Insn::GetField(x) => refs.push(MemberReferenceMut::new(&mut x.class, &mut x.name, &mut x.descriptor)),
+			Insn::PutField(x) => refs.push(MemberReferenceMut::new(&mut x.class, &mut x.name, &mut x.descriptor)),
+			Insn::Invoke(x) => refs.push(MemberReferenceMut::new(&mut x.class, &mut x.name, &mut x.descriptor)),
+			Insn::InvokeDynamic(x) => {
+				refs.push(MemberReferenceMut::new(&mut x.bootstrap_class, &mut x.bootstrap_method, &mut x.bootstrap_descriptor));
+				argument_members_mut(&mut refs, &mut x.bootstrap_arguments);
+			},
+			_ => {}
+		}
+		refs
+	}
+}
+
+/// The classes an instruction names. Most name at most two, so they are kept inline.
+pub type ClassReferences<'a> = SmallVec<[ClassReference<'a>; 4]>;
+pub type ClassReferencesMut<'a> = SmallVec<[ClassReferenceMut<'a>; 4]>;
+/// The members an instruction names. Most name at most one, so they are kept inline.
+pub type MemberReferences<'a> = SmallVec<[MemberReference<'a>; 2]>;
+pub type MemberReferencesMut<'a> = SmallVec<[MemberReferenceMut<'a>; 2]>;
+
+/// A class named by an instruction, see [`Insn::class_references`]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ClassReference<'a> {
+	/// An internal name, or an array descriptor for array classes. Descriptors of arrays stored as
+	/// a [`ClassRef`] or [`Type`] are built on demand.
+	Class(Cow<'a, str>),
+	/// A field or method descriptor
+	Descriptor(&'a str)
+}
+
+/// A class named by an instruction, in whichever form the instruction stores it
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClassReferenceMut<'a> {
+	Class(&'a mut ClassName),
+	/// An internal name kept as a plain string
+	Name(&'a mut String),
+	/// A field or method descriptor
+	Descriptor(&'a mut String)
+}
+
+/// A field or method named by an instruction, see [`Insn::member_references`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Constructor)]
+pub struct MemberReference<'a> {
+	pub class: &'a str,
+	pub name: &'a str,
+	pub descriptor: &'a str,
+	pub access: MemberAccess
+}
+
+impl<'a> MemberReference<'a> {
+	fn handle(handle: &'a MethodHandle) -> Self {
+		MemberReference::new(&handle.class, &handle.name, &handle.descriptor, MemberAccess::Handle(handle.kind))
+	}
+}
+
+/// How an instruction uses the member it names
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MemberAccess {
+	/// A method invoked by an invoke instruction, or the bootstrap method of an invokedynamic
+	Invoke(InvokeType),
+	Field { is_static: bool, is_write: bool },
+	/// A method handle constant, which points to either a field or a method
+	Handle(MethodHandleKind)
+}
+
+/// A mutable [`MemberReference`]
+#[derive(Debug, PartialEq, Eq, Constructor)]
+pub struct MemberReferenceMut<'a> {
+	pub class: &'a mut ClassName,
+	pub name: &'a mut String,
+	pub descriptor: &'a mut String
+}
+
+impl<'a> MemberReferenceMut<'a> {
+	fn handle(handle: &'a mut MethodHandle) -> Self {
+		MemberReferenceMut::new(&mut handle.class, &mut handle.name, &mut handle.descriptor)
+	}
+}
+
+fn type_class(typ: &Type) -> Option<Cow<'_, str>> {
+	match typ {
+		Type::Reference(Some(class)) => Some(Cow::Borrowed(class)),
+		Type::Array(_) => Some(Cow::Owned(typ.descriptor())),
+		_ => None
+	}
+}
+
+fn type_class_mut(typ: &mut Type) -> Option<&mut String> {
+	match typ {
+		Type::Reference(Some(class)) => Some(class),
+		Type::Array(component) => type_class_mut(component),
+		_ => None
+	}
+}
+
+fn class_ref_class(class: &ClassRef) -> ClassReference<'_> {
+	match class {
+		ClassRef::Class(name) => ClassReference::Class(Cow::Borrowed(name)),
+		ClassRef::Array(_) => ClassReference::Class(Cow::Owned(class.name()))
+	}
+}
+
+fn class_ref_class_mut(class: &mut ClassRef) -> Option<ClassReferenceMut<'_>> {
+	match class {
+		ClassRef::Class(name) => Some(ClassReferenceMut::Class(name)),
+		ClassRef::Array(component) => type_class_mut(component).map(ClassReferenceMut::Name)
+	}
+}
+
+fn member_classes<'a>(refs: &mut ClassReferences<'a>, class: &'a str, descriptor: &'a str) {
+	refs.push(ClassReference::Class(Cow::Borrowed(class)));
+	refs.push(ClassReference::Descriptor(descriptor));
+}
+
+fn handle_classes<'a>(refs: &mut ClassReferences<'a>, handle: &'a MethodHandle) {
+	member_classes(refs, &handle.class, &handle.descriptor);
+}
+
+fn handle_classes_mut<'a>(refs: &mut ClassReferencesMut<'a>, handle: &'a mut MethodHandle) {
+	refs.push(ClassReferenceMut::Class(&mut handle.class));
+	refs.push(ClassReferenceMut::Descriptor(&mut handle.descriptor));
+}
+
+fn dynamic_classes<'a>(refs: &mut ClassReferences<'a>, constant: &'a DynamicConstant) {
+	refs.push(ClassReference::Descriptor(&constant.descriptor));
+	handle_classes(refs, &constant.bootstrap_method);
+	argument_classes(refs, &constant.bootstrap_arguments);
+}
+
+fn dynamic_classes_mut<'a>(refs: &mut ClassReferencesMut<'a>, constant: &'a mut DynamicConstant) {
+	refs.push(ClassReferenceMut::Descriptor(&mut constant.descriptor));
+	handle_classes_mut(refs, &mut constant.bootstrap_method);
+	argument_classes_mut(refs, &mut constant.bootstrap_arguments);
+}
+
+fn argument_classes<'a>(refs: &mut ClassReferences<'a>, arguments: &'a [BootstrapArgument]) {
+	for argument in arguments.iter() {
+		match argument {
+			BootstrapArgument::Class(class) => refs.push(ClassReference::Class(Cow::Borrowed(class))),
+			BootstrapArgument::MethodType(descriptor) => refs.push(ClassReference::Descriptor(descriptor)),
+			BootstrapArgument::MethodHandle(handle) => handle_classes(refs, handle),
+			BootstrapArgument::Dynamic(constant) => dynamic_classes(refs, constant),
+			_ => {}
+		}
+	}
+}
+
+fn argument_classes_mut<'a>(refs: &mut ClassReferencesMut<'a>, arguments: &'a mut [BootstrapArgument]) {
+	for argument in arguments.iter_mut() {
+		match argument {
+			BootstrapArgument::Class(class) => refs.push(ClassReferenceMut::Class(class)),
+			BootstrapArgument::MethodType(descriptor) => refs.push(ClassReferenceMut::Descriptor(descriptor)),
+			BootstrapArgument::MethodHandle(handle) => handle_classes_mut(refs, handle),
+			BootstrapArgument::Dynamic(constant) => dynamic_classes_mut(refs, constant),
+			_ => {}
+		}
+	}
+}
+
+fn dynamic_members<'a>(refs: &mut MemberReferences<'a>, constant: &'a DynamicConstant) {
+	refs.push(MemberReference::handle(&constant.bootstrap_method));
+	argument_members(refs, &constant.bootstrap_arguments);
+}
+
+fn dynamic_members_mut<'a>(refs: &mut MemberReferencesMut<'a>, constant: &'a mut DynamicConstant) {
+	refs.push(MemberReferenceMut::handle(&mut constant.bootstrap_method));
+	argument_members_mut(refs, &mut constant.bootstrap_arguments);
+}
+
+fn argument_members<'a>(refs: &mut MemberReferences<'a>, arguments: &'a [BootstrapArgument]) {
+	for argument in arguments.iter() {
+		match argument {
+			BootstrapArgument::MethodHandle(handle) => refs.push(MemberReference::handle(handle)),
+			BootstrapArgument::Dynamic(constant) => dynamic_members(refs, constant),
+			_ => {}
+		}
+	}
+}
+
+fn argument_members_mut<'a>(refs: &mut MemberReferencesMut<'a>, arguments: &'a mut [BootstrapArgument]) {
+	for argument in arguments.iter_mut() {
+		match argument {
+			BootstrapArgument::MethodHandle(handle) => refs.push(MemberReferenceMut::handle(handle)),
+			BootstrapArgument::Dynamic(constant) => dynamic_members_mut(refs, constant),
+			_ => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	const CONDITIONS: [JumpCondition; 16] = [
+		JumpCondition::IsNull,
+		JumpCondition::NotNull,
+		JumpCondition::ReferencesEqual,
+		JumpCondition::ReferencesNotEqual,
+		JumpCondition::IntsEq,
+		JumpCondition::IntsNotEq,
+		JumpCondition::IntsLessThan,
+		JumpCondition::IntsLessThanOrEq,
+		JumpCondition::IntsGreaterThan,
+		JumpCondition::IntsGreaterThanOrEq,
+		JumpCondition::IntEqZero,
+		JumpCondition::IntNotEqZero,
+		JumpCondition::IntLessThanZero,
+		JumpCondition::IntLessThanOrEqZero,
+		JumpCondition::IntGreaterThanZero,
+		JumpCondition::IntGreaterThanOrEqZero,
+	];
+	
+	/// Evaluates an int condition against `a` (second on the stack) and `b` (top of the stack)
+	fn holds(condition: JumpCondition, a: i32, b: i32) -> bool {
+		match condition {
+			JumpCondition::IntsEq => a == b,
+			JumpCondition::IntsNotEq => a != b,
+			JumpCondition::IntsLessThan => a < b,
+			JumpCondition::IntsLessThanOrEq => a <= b,
+			JumpCondition::IntsGreaterThan => a > b,
+			JumpCondition::IntsGreaterThanOrEq => a >= b,
+			JumpCondition::IntEqZero => b == 0,
+			JumpCondition::IntNotEqZero => b != 0,
+			JumpCondition::IntLessThanZero => b < 0,
+			JumpCondition::IntLessThanOrEqZero => b <= 0,
+			JumpCondition::IntGreaterThanZero => b > 0,
+			JumpCondition::IntGreaterThanOrEqZero => b >= 0,
+			// treat 0 as null and equal ints as the same reference
+			JumpCondition::IsNull => b == 0,
+			JumpCondition::NotNull => b != 0,
+			JumpCondition::ReferencesEqual => a == b,
+			JumpCondition::ReferencesNotEqual => a != b,
+		}
+	}
+	
+	#[test]
+	fn negate_twice_is_identity() {
+		for condition in CONDITIONS.iter() {
+			assert_eq!(condition.negate().negate(), *condition);
+			assert_ne!(condition.negate(), *condition);
+		}
+	}
+	
+	#[test]
+	fn negation_matches_semantics() {
+		assert_eq!(JumpCondition::IntsLessThan.negate(), JumpCondition::IntsGreaterThanOrEq);
+		for condition in CONDITIONS.iter() {
+			for a in -2..=2 {
+				for b in -2..=2 {
+					assert_ne!(holds(*condition, a, b), holds(condition.negate(), a, b), "{:?} {} {}", condition, a, b);
+				}
+			}
+		}
+	}
+	
+	#[test]
+	fn negate_keeps_target() {
+		let mut jump = ConditionalJumpInsn::new(JumpCondition::IsNull, LabelInsn::new(3));
+		jump.negate();
+		assert_eq!(jump, ConditionalJumpInsn::new(JumpCondition::NotNull, LabelInsn::new(3)));
+	}
+	
+	#[test]
+	fn terminal_insns() {
+		assert!(Insn::Return(ReturnInsn::new(ReturnType::Void)).is_terminal());
+		assert!(Insn::Throw(ThrowInsn::new()).is_terminal());
+		assert!(Insn::Jump(JumpInsn::new(LabelInsn::new(0))).is_terminal());
+		assert!(Insn::TableSwitch(TableSwitchInsn::new(LabelInsn::new(0), 0, Vec::new())).is_terminal());
+		assert!(!Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IsNull, LabelInsn::new(0))).is_terminal());
+		assert!(!Insn::Nop(NopInsn::new()).is_terminal());
+	}
+	
+	fn effect(insn: Insn) -> (u8, u8) {
+		let effect = insn.stack_effect().unwrap();
+		(effect.pops, effect.pushes)
+	}
+	
+	fn invoke(kind: InvokeType, descriptor: &str) -> Insn {
+		Insn::Invoke(Box::new(InvokeInsn::new(kind, ClassName::from("A"), String::from("m"), String::from(descriptor), false)))
+	}
+	
+	fn field(get: bool, instance: bool, descriptor: &str) -> Insn {
+		let (class, name, descriptor) = (String::from("A"), String::from("f"), String::from(descriptor));
+		if get {
+			Insn::GetField(Box::new(GetFieldInsn::new(instance, class.into(), name, descriptor)))
+		} else {
+			Insn::PutField(Box::new(PutFieldInsn::new(instance, class.into(), name, descriptor)))
+		}
+	}
+	
+	#[test]
+	fn stack_effect_of_values() {
+		let label = LabelInsn::new(0);
+		assert_eq!(effect(Insn::Label(label)), (0, 0));
+		assert_eq!(effect(Insn::Ldc(LdcInsn::new(LdcType::Null))), (0, 1));
+		assert_eq!(effect(Insn::Ldc(LdcInsn::new(LdcType::Int(1)))), (0, 1));
+		assert_eq!(effect(Insn::Ldc(LdcInsn::new(LdcType::Float(1.0)))), (0, 1));
+		assert_eq!(effect(Insn::Ldc(LdcInsn::new(LdcType::String(String::from("a"))))), (0, 1));
+		assert_eq!(effect(Insn::Ldc(LdcInsn::new(LdcType::Class(String::from("A"))))), (0, 1));
+		assert_eq!(effect(Insn::Ldc(LdcInsn::new(LdcType::Long(1)))), (0, 2));
+		assert_eq!(effect(Insn::Ldc(LdcInsn::new(LdcType::Double(1.0)))), (0, 2));
+		assert_eq!(effect(Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0))), (0, 1));
+		assert_eq!(effect(Insn::LocalLoad(LocalLoadInsn::new(OpType::Long, 0))), (0, 2));
+		assert_eq!(effect(Insn::LocalStore(LocalStoreInsn::new(OpType::Int, 0))), (1, 0));
+		assert_eq!(effect(Insn::LocalStore(LocalStoreInsn::new(OpType::Double, 0))), (2, 0));
+		assert_eq!(effect(Insn::IncrementInt(IncrementIntInsn::new(0, 1))), (0, 0));
+		assert_eq!(effect(Insn::NewObject(NewObjectInsn::new(ClassRef::from("A")))), (0, 1));
+		assert_eq!(effect(Insn::Nop(NopInsn::new())), (0, 0));
+		assert_eq!(effect(Insn::ImpDep1(ImpDep1Insn::new())), (0, 0));
+		assert_eq!(effect(Insn::ImpDep2(ImpDep2Insn::new())), (0, 0));
+		assert_eq!(effect(Insn::BreakPoint(BreakPointInsn::new())), (0, 0));
+	}
+	
+	#[test]
+	fn stack_effect_of_arrays() {
+		assert_eq!(effect(Insn::ArrayLoad(ArrayLoadInsn::new(Type::Int))), (2, 1));
+		assert_eq!(effect(Insn::ArrayLoad(ArrayLoadInsn::new(Type::Long))), (2, 2));
+		assert_eq!(effect(Insn::ArrayLoad(ArrayLoadInsn::new(Type::Reference(None)))), (2, 1));
+		assert_eq!(effect(Insn::ArrayStore(ArrayStoreInsn::new(Type::Byte))), (3, 0));
+		assert_eq!(effect(Insn::ArrayStore(ArrayStoreInsn::new(Type::Double))), (4, 0));
+		assert!(Insn::ArrayLoad(ArrayLoadInsn::new(Type::Void)).stack_effect().is_err());
+		assert_eq!(effect(Insn::NewArray(NewArrayInsn::new(Type::Int))), (1, 1));
+		assert_eq!(effect(Insn::ArrayLength(ArrayLengthInsn::new())), (1, 1));
+		assert_eq!(effect(Insn::MultiNewArray(MultiNewArrayInsn::new(ClassRef::from("[[[I"), 3))), (3, 1));
+	}
+	
+	#[test]
+	fn stack_effect_of_arithmetic() {
+		assert_eq!(effect(Insn::Add(AddInsn::new(PrimitiveType::Int))), (2, 1));
+		assert_eq!(effect(Insn::Add(AddInsn::new(PrimitiveType::Long))), (4, 2));
+		assert_eq!(effect(Insn::Subtract(SubtractInsn::new(PrimitiveType::Float))), (2, 1));
+		assert_eq!(effect(Insn::Multiply(MultiplyInsn::new(PrimitiveType::Double))), (4, 2));
+		assert_eq!(effect(Insn::Divide(DivideInsn::new(PrimitiveType::Int))), (2, 1));
+		assert_eq!(effect(Insn::Remainder(RemainderInsn::new(PrimitiveType::Long))), (4, 2));
+		assert_eq!(effect(Insn::Negate(NegateInsn::new(PrimitiveType::Double))), (2, 2));
+		assert_eq!(effect(Insn::Negate(NegateInsn::new(PrimitiveType::Int))), (1, 1));
+		assert_eq!(effect(Insn::Compare(CompareInsn::new(PrimitiveType::Long, false))), (4, 1));
+		assert_eq!(effect(Insn::Compare(CompareInsn::new(PrimitiveType::Float, true))), (2, 1));
+		assert_eq!(effect(Insn::And(AndInsn::new(IntegerType::Int))), (2, 1));
+		assert_eq!(effect(Insn::Or(OrInsn::new(IntegerType::Long))), (4, 2));
+		assert_eq!(effect(Insn::Xor(XorInsn::new(IntegerType::Long))), (4, 2));
+		// the shift distance is an int even when shifting a long
+		assert_eq!(effect(Insn::ShiftLeft(ShiftLeftInsn::new(IntegerType::Int))), (2, 1));
+		assert_eq!(effect(Insn::ShiftRight(ShiftRightInsn::new(IntegerType::Long))), (3, 2));
+		assert_eq!(effect(Insn::LogicalShiftRight(LogicalShiftRightInsn::new(IntegerType::Long))), (3, 2));
+		assert_eq!(effect(Insn::Convert(ConvertInsn::new(PrimitiveType::Int, PrimitiveType::Long))), (1, 2));
+		assert_eq!(effect(Insn::Convert(ConvertInsn::new(PrimitiveType::Double, PrimitiveType::Float))), (2, 1));
+		assert_eq!(effect(Insn::Convert(ConvertInsn::new(PrimitiveType::Int, PrimitiveType::Byte))), (1, 1));
+	}
+	
+	#[test]
+	fn stack_effect_of_stack_manipulation() {
+		// dup, dup_x1, dup_x2, dup2, dup2_x1, dup2_x2
+		assert_eq!(effect(Insn::Dup(DupInsn::new(1, 0))), (1, 2));
+		assert_eq!(effect(Insn::Dup(DupInsn::new(1, 1))), (2, 3));
+		assert_eq!(effect(Insn::Dup(DupInsn::new(1, 2))), (3, 4));
+		assert_eq!(effect(Insn::Dup(DupInsn::new(2, 0))), (2, 4));
+		assert_eq!(effect(Insn::Dup(DupInsn::new(2, 1))), (3, 5));
+		assert_eq!(effect(Insn::Dup(DupInsn::new(2, 2))), (4, 6));
+		assert_eq!(effect(Insn::Pop(PopInsn::new(false))), (1, 0));
+		assert_eq!(effect(Insn::Pop(PopInsn::new(true))), (2, 0));
+		assert_eq!(effect(Insn::Swap(SwapInsn::new())), (2, 2));
+		assert_eq!(Insn::Dup(DupInsn::new(2, 1)).stack_effect().unwrap().delta(), 2);
+		assert_eq!(Insn::Pop(PopInsn::new(true)).stack_effect().unwrap().delta(), -2);
+	}
+	
+	#[test]
+	fn stack_effect_of_control_flow() {
+		let label = LabelInsn::new(0);
+		assert_eq!(effect(Insn::Jump(JumpInsn::new(label))), (0, 0));
+		for condition in CONDITIONS.iter() {
+			let pops = match condition {
+				JumpCondition::IsNull | JumpCondition::NotNull => 1,
+				JumpCondition::ReferencesEqual | JumpCondition::ReferencesNotEqual => 2,
+				JumpCondition::IntsEq | JumpCondition::IntsNotEq | JumpCondition::IntsLessThan | JumpCondition::IntsLessThanOrEq
+				| JumpCondition::IntsGreaterThan | JumpCondition::IntsGreaterThanOrEq => 2,
+				_ => 1
+			};
+			assert_eq!(effect(Insn::ConditionalJump(ConditionalJumpInsn::new(*condition, label))), (pops, 0), "{:?}", condition);
+		}
+		assert_eq!(effect(Insn::TableSwitch(TableSwitchInsn::new(label, 0, Vec::new()))), (1, 0));
+		assert_eq!(effect(Insn::LookupSwitch(LookupSwitchInsn::new(label))), (1, 0));
+		assert_eq!(effect(Insn::Return(ReturnInsn::new(ReturnType::Void))), (0, 0));
+		assert_eq!(effect(Insn::Return(ReturnInsn::new(ReturnType::Reference))), (1, 0));
+		assert_eq!(effect(Insn::Return(ReturnInsn::new(ReturnType::Long))), (2, 0));
+		assert_eq!(effect(Insn::Throw(ThrowInsn::new())), (1, 0));
+		assert_eq!(effect(Insn::MonitorEnter(MonitorEnterInsn::new())), (1, 0));
+		assert_eq!(effect(Insn::MonitorExit(MonitorExitInsn::new())), (1, 0));
+	}
+	
+	#[test]
+	fn stack_effect_of_objects() {
+		assert_eq!(effect(Insn::CheckCast(CheckCastInsn::new(ClassRef::from("A")))), (1, 1));
+		assert_eq!(effect(Insn::InstanceOf(InstanceOfInsn::new(ClassRef::from("A")))), (1, 1));
+		assert_eq!(effect(field(true, false, "I")), (0, 1));
+		assert_eq!(effect(field(true, true, "J")), (1, 2));
+		assert_eq!(effect(field(true, true, "[Ljava/lang/String;")), (1, 1));
+		assert_eq!(effect(field(false, false, "D")), (2, 0));
+		assert_eq!(effect(field(false, true, "Ljava/lang/Object;")), (2, 0));
+		assert!(field(true, true, "V").stack_effect().is_err());
+		assert!(field(false, true, "Q").stack_effect().is_err());
+	}
+	
+	#[test]
+	fn stack_effect_of_invokes() {
+		assert_eq!(effect(invoke(InvokeType::Static, "()V")), (0, 0));
+		assert_eq!(effect(invoke(InvokeType::Static, "(IJ[DLjava/lang/String;)D")), (5, 2));
+		assert_eq!(effect(invoke(InvokeType::Instance, "(J)I")), (3, 1));
+		assert_eq!(effect(invoke(InvokeType::Interface, "(Ljava/lang/Object;)Z")), (2, 1));
+		assert_eq!(effect(invoke(InvokeType::Special, "()V")), (1, 0));
+		assert!(invoke(InvokeType::Static, "(V)V").stack_effect().is_err());
+		assert!(invoke(InvokeType::Static, "").stack_effect().is_err());
+		assert!(invoke(InvokeType::Static, "(I").stack_effect().is_err());
+		
+		let indy = Insn::from(InvokeDynamicInsn::new(
+			String::from("apply"),
+			String::from("(JLjava/lang/Object;)Ljava/util/function/Supplier;"),
+			BootstrapMethodType::InvokeStatic,
+			ClassName::from("java/lang/invoke/LambdaMetafactory"),
+			String::from("metafactory"),
+			String::from("()V"),
+			Vec::new()
+		));
+		assert!(matches!(&indy, Insn::InvokeDynamic(x) if x.name == "apply"));
+		assert_eq!(effect(indy), (3, 1));
+	}
+	
+	#[test]
+	fn local_access() {
+		assert_eq!(Insn::LocalLoad(LocalLoadInsn::new(OpType::Long, 2)).local_access(), Some(LocalAccess::new(2, OpType::Long, false)));
+		assert_eq!(Insn::LocalStore(LocalStoreInsn::new(OpType::Reference, 300)).local_access(), Some(LocalAccess::new(300, OpType::Reference, true)));
+		assert_eq!(Insn::IncrementInt(IncrementIntInsn::new(1, -1)).local_access(), Some(LocalAccess::new(1, OpType::Int, true)));
+		assert_eq!(Insn::Nop(NopInsn::new()).local_access(), None);
+		assert_eq!(Insn::ArrayLoad(ArrayLoadInsn::new(Type::Int)).local_access(), None);
+	}
+	
+	#[test]
+	fn insn_size_budget() {
+		// Every element of an instruction list is as large as the largest variant, so variants
+		// naming a member are boxed. MultiNewArray is the largest now.
+		let size = std::mem::size_of::<Insn>();
+		assert!(size <= 64, "Insn grew to {} bytes", size);
+	}
+	
+	#[test]
+	fn class_and_member_references() {
+		use ClassReference::Descriptor;
+		let class = |name: &str| ClassReference::Class(Cow::Owned(String::from(name)));
+		let handle = |kind: MethodHandleKind, class: &str| MethodHandle::new(kind, ClassName::from(class), String::from("h"), String::from("()LH;"), false);
+		let member = |class, name, descriptor, access| MemberReference::new(class, name, descriptor, access);
+		let field = |is_static, is_write| MemberAccess::Field { is_static, is_write };
+		let bootstrap = vec![
+			BootstrapArgument::String(String::from("S")),
+			BootstrapArgument::Class(ClassName::from("A")),
+			BootstrapArgument::MethodType(String::from("()LM;")),
+			BootstrapArgument::MethodHandle(handle(MethodHandleKind::GetStatic, "H"))
+		];
+		let label = LabelInsn::new(0);
+		let mut lookup = LookupSwitchInsn::new(label);
+		lookup.cases.insert(1, label);
+		let cases: Vec<(Insn, Vec<ClassReference>, Vec<MemberReference>)> = vec![
+			(Insn::Label(label), vec![], vec![]),
+			(Insn::ArrayLoad(ArrayLoadInsn::new(Type::Reference(None))), vec![], vec![]),
+			(Insn::ArrayLoad(ArrayLoadInsn::new(Type::object("E"))), vec![class("E")], vec![]),
+			(Insn::ArrayStore(ArrayStoreInsn::new(Type::Int)), vec![], vec![]),
+			(Insn::ArrayStore(ArrayStoreInsn::new(Type::Array(Box::new(Type::object("S"))))), vec![class("[LS;")], vec![]),
+			(Insn::Ldc(LdcInsn::new(LdcType::Null)), vec![], vec![]),
+			(Insn::Ldc(LdcInsn::new(LdcType::String(String::from("S")))), vec![], vec![]),
+			(Insn::Ldc(LdcInsn::new(LdcType::Int(1))), vec![], vec![]),
+			(Insn::Ldc(LdcInsn::new(LdcType::Class(String::from("[LL;")))), vec![class("[LL;")], vec![]),
+			(Insn::Ldc(LdcInsn::new(LdcType::MethodType(String::from("()LT;")))), vec![Descriptor("()LT;")], vec![]),
+			(Insn::Ldc(LdcInsn::new(LdcType::MethodHandle(Box::new(handle(MethodHandleKind::NewInvokeSpecial, "K"))))), vec![class("K"), Descriptor("()LH;")], vec![
+				member("K", "h", "()LH;", MemberAccess::Handle(MethodHandleKind::NewInvokeSpecial))
+			]),
+			(Insn::Ldc(LdcInsn::new(LdcType::Dynamic(Box::new(DynamicConstant::new(String::from("d"), String::from("LY;"), handle(MethodHandleKind::InvokeStatic, "Z"), bootstrap.clone()))))), vec![
+				Descriptor("LY;"), class("Z"), Descriptor("()LH;"), class("A"), Descriptor("()LM;"), class("H"), Descriptor("()LH;")
+			], vec![
+				member("Z", "h", "()LH;", MemberAccess::Handle(MethodHandleKind::InvokeStatic)),
+				member("H", "h", "()LH;", MemberAccess::Handle(MethodHandleKind::GetStatic))
+			]),
+			(Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0)), vec![], vec![]),
+			(Insn::LocalStore(LocalStoreInsn::new(OpType::Int, 1)), vec![], vec![]),
+			(Insn::NewArray(NewArrayInsn::new(Type::Int)), vec![], vec![]),
+			(Insn::NewArray(NewArrayInsn::new(Type::object("F"))), vec![class("F")], vec![]),
+			(Insn::NewArray(NewArrayInsn::new(Type::Array(Box::new(Type::object("E"))))), vec![class("[LE;")], vec![]),
+			(Insn::NewArray(NewArrayInsn::new(Type::Array(Box::new(Type::Int)))), vec![class("[I")], vec![]),
+			(Insn::Return(ReturnInsn::new(ReturnType::Void)), vec![], vec![]),
+			(Insn::ArrayLength(ArrayLengthInsn::new()), vec![], vec![]),
+			(Insn::Throw(ThrowInsn::new()), vec![], vec![]),
+			(Insn::CheckCast(CheckCastInsn::new(ClassRef::from("C"))), vec![class("C")], vec![]),
+			(Insn::CheckCast(CheckCastInsn::new(ClassRef::Array(Type::object("C")))), vec![class("[LC;")], vec![]),
+			(Insn::Convert(ConvertInsn::new(PrimitiveType::Int, PrimitiveType::Long)), vec![], vec![]),
+			(Insn::Add(AddInsn::new(PrimitiveType::Int)), vec![], vec![]),
+			(Insn::Compare(CompareInsn::new(PrimitiveType::Float, true)), vec![], vec![]),
+			(Insn::Divide(DivideInsn::new(PrimitiveType::Int)), vec![], vec![]),
+			(Insn::Multiply(MultiplyInsn::new(PrimitiveType::Int)), vec![], vec![]),
+			(Insn::Negate(NegateInsn::new(PrimitiveType::Int)), vec![], vec![]),
+			(Insn::Remainder(RemainderInsn::new(PrimitiveType::Int)), vec![], vec![]),
+			(Insn::Subtract(SubtractInsn::new(PrimitiveType::Int)), vec![], vec![]),
+			(Insn::And(AndInsn::new(IntegerType::Int)), vec![], vec![]),
+			(Insn::Or(OrInsn::new(IntegerType::Int)), vec![], vec![]),
+			(Insn::Xor(XorInsn::new(IntegerType::Int)), vec![], vec![]),
+			(Insn::ShiftLeft(ShiftLeftInsn::new(IntegerType::Int)), vec![], vec![]),
+			(Insn::ShiftRight(ShiftRightInsn::new(IntegerType::Int)), vec![], vec![]),
+			(Insn::LogicalShiftRight(LogicalShiftRightInsn::new(IntegerType::Long)), vec![], vec![]),
+			(Insn::Dup(DupInsn::new(1, 0)), vec![], vec![]),
+			(Insn::Pop(PopInsn::new(false)), vec![], vec![]),
+			(Insn::GetField(Box::new(GetFieldInsn::new(true, ClassName::from("G"), String::from("g"), String::from("LF;")))), vec![class("G"), Descriptor("LF;")], vec![
+				member("G", "g", "LF;", field(false, false))
+			]),
+			(Insn::PutField(Box::new(PutFieldInsn::new(false, ClassName::from("P"), String::from("p"), String::from("I")))), vec![class("P"), Descriptor("I")], vec![
+				member("P", "p", "I", field(true, true))
+			]),
+			(Insn::Jump(JumpInsn::new(label)), vec![], vec![]),
+			(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IsNull, label)), vec![], vec![]),
+			(Insn::IncrementInt(IncrementIntInsn::new(0, 1)), vec![], vec![]),
+			(Insn::InstanceOf(InstanceOfInsn::new(ClassRef::from("I"))), vec![class("I")], vec![]),
+			(Insn::InstanceOf(InstanceOfInsn::new(ClassRef::Array(Type::object("I")))), vec![class("[LI;")], vec![]),
+			(Insn::from(InvokeDynamicInsn::new(String::from("run"), String::from("()LR;"), BootstrapMethodType::InvokeStatic, ClassName::from("B"), String::from("b"), String::from("()LD;"), bootstrap)), vec![
+				Descriptor("()LR;"), class("B"), Descriptor("()LD;"), class("A"), Descriptor("()LM;"), class("H"), Descriptor("()LH;")
+			], vec![
+				member("B", "b", "()LD;", MemberAccess::Invoke(InvokeType::Static)),
+				member("H", "h", "()LH;", MemberAccess::Handle(MethodHandleKind::GetStatic))
+			]),
+			(Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, ClassName::from("[I"), String::from("clone"), String::from("()LO;"), false))), vec![class("[I"), Descriptor("()LO;")], vec![
+				member("[I", "clone", "()LO;", MemberAccess::Invoke(InvokeType::Instance))
+			]),
+			(Insn::LookupSwitch(lookup), vec![], vec![]),
+			(Insn::TableSwitch(TableSwitchInsn::new(label, 0, vec![label])), vec![], vec![]),
+			(Insn::MonitorEnter(MonitorEnterInsn::new()), vec![], vec![]),
+			(Insn::MonitorExit(MonitorExitInsn::new()), vec![], vec![]),
+			(Insn::MultiNewArray(MultiNewArrayInsn::new(ClassRef::Array(Type::Array(Box::new(Type::Int))), 2)), vec![class("[[I")], vec![]),
+			(Insn::MultiNewArray(MultiNewArrayInsn::new(ClassRef::Array(Type::Array(Box::new(Type::object("M")))), 2)), vec![class("[[LM;")], vec![]),
+			(Insn::NewObject(NewObjectInsn::new(ClassRef::from("N"))), vec![class("N")], vec![]),
+			(Insn::Nop(NopInsn::new()), vec![], vec![]),
+			(Insn::Swap(SwapInsn::new()), vec![], vec![]),
+			(Insn::ImpDep1(ImpDep1Insn::new()), vec![], vec![]),
+			(Insn::ImpDep2(ImpDep2Insn::new()), vec![], vec![]),
+			(Insn::BreakPoint(BreakPointInsn::new()), vec![], vec![])
+		];
+		
+		// Fails to compile when a variant is added, so that it gets a case above
+		let variant = |insn: &Insn| match insn {
+			Insn::Label(_) => 0, Insn::ArrayLoad(_) => 1, Insn::ArrayStore(_) => 2, Insn::Ldc(_) => 3,
+			Insn::LocalLoad(_) => 4, Insn::LocalStore(_) => 5, Insn::NewArray(_) => 6, Insn::Return(_) => 7,
+			Insn::ArrayLength(_) => 8, Insn::Throw(_) => 9, Insn::CheckCast(_) => 10, Insn::Convert(_) => 11,
+			Insn::Add(_) => 12, Insn::Compare(_) => 13, Insn::Divide(_) => 14, Insn::Multiply(_) => 15,
+			Insn::Negate(_) => 16, Insn::Remainder(_) => 17, Insn::Subtract(_) => 18, Insn::And(_) => 19,
+			Insn::Or(_) => 20, Insn::Xor(_) => 21, Insn::ShiftLeft(_) => 22, Insn::ShiftRight(_) => 23,
+			Insn::LogicalShiftRight(_) => 24, Insn::Dup(_) => 25, Insn::Pop(_) => 26, Insn::GetField(_) => 27,
+			Insn::PutField(_) => 28, Insn::Jump(_) => 29, Insn::ConditionalJump(_) => 30, Insn::IncrementInt(_) => 31,
+			Insn::InstanceOf(_) => 32, Insn::InvokeDynamic(_) => 33, Insn::Invoke(_) => 34, Insn::LookupSwitch(_) => 35,
+			Insn::TableSwitch(_) => 36, Insn::MonitorEnter(_) => 37, Insn::MonitorExit(_) => 38, Insn::MultiNewArray(_) => 39,
+			Insn::NewObject(_) => 40, Insn::Nop(_) => 41, Insn::Swap(_) => 42, Insn::ImpDep1(_) => 43,
+			Insn::ImpDep2(_) => 44, Insn::BreakPoint(_) => 45
+		};
+		let covered: std::collections::BTreeSet<usize> = cases.iter().map(|(insn, ..)| variant(insn)).collect();
+		assert_eq!(covered, (0..46).collect());
+		
+		// prefixes the element class of arrays
+		let rename = |class: &str| {
+			let element = class.trim_start_matches('[');
+			let dimensions = &class[..class.len() - element.len()];
+			match element.strip_prefix('L') {
+				Some(name) if !dimensions.is_empty() => format!("{}Lx/{}", dimensions, name),
+				_ if !dimensions.is_empty() => class.to_string(),
+				_ => format!("x/{}", class)
+			}
+		};
+		for (insn, classes, members) in cases.iter() {
+			assert_eq!(insn.class_references().into_vec(), *classes, "{:?}", insn);
+			assert_eq!(insn.member_references().into_vec(), *members, "{:?}", insn);
+			
+			let mut copy = insn.clone();
+			assert_eq!(copy.member_references_mut().len(), members.len());
+			for reference in copy.class_references_mut() {
+				match reference {
+					ClassReferenceMut::Class(class) => *class = ClassName::from(rename(class)),
+					ClassReferenceMut::Name(class) => *class = rename(class),
+					ClassReferenceMut::Descriptor(_) => {}
+				}
+			}
+			let renamed: Vec<_> = copy.class_references().into_iter().filter_map(|x| match x {
+				ClassReference::Class(class) => Some(class.into_owned()),
+				Descriptor(_) => None
+			}).collect();
+			let expected: Vec<_> = classes.iter().filter_map(|x| match x {
+				ClassReference::Class(class) => Some(rename(class)),
+				Descriptor(_) => None
+			}).collect();
+			assert_eq!(renamed, expected, "{:?}", insn);
+		}
+	}
+	
+	#[test]
+	fn names_round_trip() {
+		for condition in CONDITIONS.iter() {
+			assert_eq!(condition.to_string().parse::<JumpCondition>().unwrap(), *condition);
+		}
+		for kind in [InvokeType::Instance, InvokeType::Static, InvokeType::Interface, InvokeType::Special].iter() {
+			assert_eq!(kind.to_string().parse::<InvokeType>().unwrap(), *kind);
+		}
+		for x in PrimitiveType::ALL.iter() {
+			assert_eq!(x.to_string().parse::<PrimitiveType>().unwrap(), *x);
+		}
+		for x in OpType::ALL.iter() {
+			assert_eq!(x.to_string().parse::<OpType>().unwrap(), *x);
+		}
+		
+		assert_eq!(JumpCondition::IntsLessThan.to_string(), "if_icmplt");
+		assert_eq!("REF_invokeStatic".parse::<InvokeType>().unwrap(), InvokeType::Static);
+		assert_eq!("invokevirtual".parse::<InvokeType>().unwrap(), InvokeType::Instance);
+		assert_eq!("reference".parse::<OpType>().unwrap(), OpType::Reference);
+		assert!("reference".parse::<PrimitiveType>().is_err());
+		assert!("Int".parse::<PrimitiveType>().is_err());
+		assert!("goto".parse::<JumpCondition>().is_err());
+	}
+	
+	#[test]
+	fn float_constants_compare_bits() {
+		assert_eq!(LdcType::Float(f32::NAN), LdcType::Float(f32::NAN));
+		assert_eq!(LdcType::Double(f64::NAN), LdcType::Double(f64::NAN));
+		assert_ne!(LdcType::Double(0.0), LdcType::Double(-0.0));
+		assert_ne!(LdcType::Float(1.0), LdcType::Double(1.0));
+		assert_eq!(BootstrapArgument::Double(f64::NAN), BootstrapArgument::Double(f64::NAN));
+		assert_ne!(BootstrapArgument::Float(0.0), BootstrapArgument::Float(-0.0));
+	}
+}
@@ -1,70 +1,261 @@
-use crate::constantpool::{ConstantPool, ConstantType, ConstantPoolWriter};
+use crate::constantpool::{ConstantPool, ConstantType, ConstantPoolWriter, Utf8Info};
 use crate::version::{MajorVersion, ClassVersion};
-use crate::code::CodeAttribute;
-use crate::error::{Result, ParserError};
+use crate::code::{CodeAttribute, PcLabels};
+use crate::classfile::{ParseOptions, WriteStats};
+use crate::error::{Result, ParserError, ErrorContext};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
-use std::io::{Write, Read, Cursor};
+use std::convert::TryFrom;
+use std::io::{Write, Read, Cursor, ErrorKind};
 use derive_more::Constructor;
 use crate::ast::LabelInsn;
-use crate::utils::{ReadUtils, MapUtils};
+use crate::utils::{ClassRead, CountingReader, CountingWriter, read_slice, with_slice};
+use crate::types::ClassName;
+use crate::access::InnerClassAccessFlags;
+use crate::Serializable;
 use std::collections::HashMap;
+use std::any::Any;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use std::borrow::Cow;
 
 #[allow(non_snake_case)]
 pub mod Attributes {
 	use std::io::{Read, Write};
-	use crate::constantpool::{ConstantPool, ConstantPoolWriter};
+	use crate::constantpool::ConstantPoolWriter;
 	use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
-	use crate::version::{ClassVersion};
-	use crate::attributes::{Attribute, AttributeSource};
+	use crate::attributes::{Attribute, AttributeContext, AttributeKind, AttributeSource, SignatureAttribute, UnknownAttribute, WriteContext};
+	use crate::utils::VecUtils;
 	use std::collections::HashMap;
 	use crate::ast::LabelInsn;
+	use crate::error::{ErrorContext, ParserError};
+	use crate::path::PathSegment;
+	use crate::utils::{ClassRead, CountingReader};
 	
-	pub fn parse<R: Read>(rdr: &mut R, source: AttributeSource, version: &ClassVersion, constant_pool: &ConstantPool, pc_label_map: &mut Option<HashMap<u32, LabelInsn>>) -> crate::Result<Vec<Attribute>> {
+	pub fn parse<R: Read>(rdr: &mut R, context: &mut AttributeContext) -> crate::Result<Vec<Attribute>> {
+		parse_from(&mut CountingReader::new(rdr), context)
+	}
+	
+	pub(crate) fn parse_from<R: ClassRead>(rdr: &mut R, context: &mut AttributeContext) -> crate::Result<Vec<Attribute>> {
+		let num_attributes = rdr.read_u16::<BigEndian>()? as usize;
+		let mut attributes: Vec<Attribute> = Vec::with_capacity(num_attributes);
+		for i in 0..num_attributes {
+			attributes.push(Attribute::parse_from(rdr, context).path_context(|| PathSegment::Attribute(i))?);
+		}
+		Ok(attributes)
+	}
+	
+	/// Parses the attributes of a field or method. A method may have at most one Code, Signature and
+	/// Exceptions attribute, and no Code attribute if `code_allowed` is false. A field may have at
+	/// most one Signature attribute.
+	pub fn parse_member<R: Read>(rdr: &mut R, context: &mut AttributeContext, member: &str, code_allowed: bool) -> crate::Result<Vec<Attribute>> {
+		parse_member_from(&mut CountingReader::new(rdr), context, member, code_allowed)
+	}
+	
+	pub(crate) fn parse_member_from<R: ClassRead>(rdr: &mut R, context: &mut AttributeContext, member: &str, code_allowed: bool) -> crate::Result<Vec<Attribute>> {
 		let num_attributes = rdr.read_u16::<BigEndian>()? as usize;
 		let mut attributes: Vec<Attribute> = Vec::with_capacity(num_attributes);
-		for _ in 0..num_attributes {
-			attributes.push(Attribute::parse(rdr, &source, version, constant_pool, pc_label_map.as_mut())?);
+		let mut seen: Vec<AttributeKind> = Vec::new();
+		for i in 0..num_attributes {
+			let attribute = Attribute::parse_raw(rdr, context.constant_pool).and_then(|(name, bytes)| {
+				let kind = AttributeKind::of(name);
+				let reason = if kind == AttributeKind::Code && !code_allowed {
+					Some("method is abstract or native")
+				} else if seen.contains(&kind) {
+					Some("duplicate")
+				} else {
+					let unique = match context.source {
+						AttributeSource::Method => matches!(kind, AttributeKind::Code | AttributeKind::Signature | AttributeKind::Exceptions),
+						_ => kind == AttributeKind::Signature
+					};
+					if unique {
+						seen.push(kind);
+					}
+					None
+				};
+				match reason {
+					Some(_) if context.options.lenient_member_attributes => Ok(Attribute::Unknown(UnknownAttribute::new(name.to_string(), bytes.into_owned()))),
+					Some(reason) => Err(ParserError::invalid_member_attribute(member.to_string(), name.to_string(), reason)),
+					None => Attribute::parse_contents(name, kind, bytes, context)
+				}
+			});
+			attributes.push(attribute.path_context(|| PathSegment::Attribute(i))?);
 		}
 		Ok(attributes)
 	}
 	
+	/// The signature held by the Signature attribute of a class, field or method
+	pub(crate) fn signature(attributes: &mut [Attribute]) -> Option<&mut String> {
+		for attr in attributes.iter_mut() {
+			if let Attribute::Signature(sig) = attr {
+				return Some(&mut sig.signature)
+			}
+		}
+		None
+	}
+	
+	/// Like [`signature`](Self::signature), through a shared reference
+	pub(crate) fn signature_ref(attributes: &[Attribute]) -> Option<&str> {
+		attributes.iter().find_map(|attr| match attr {
+			Attribute::Signature(sig) => Some(sig.signature.as_str()),
+			_ => None
+		})
+	}
+	
+	/// Replaces the Signature attribute in place, adding it if missing or removing it for None
+	pub(crate) fn set_signature(attributes: &mut Vec<Attribute>, sig: Option<String>) {
+		// According to the JVM spec there must be at most one signature attribute in the attributes table
+		let index = attributes.find_first(|attr| {
+			matches!(attr, Attribute::Signature(_))
+		});
+		if let Some(sig) = sig {
+			let attr = Attribute::Signature(SignatureAttribute::new(sig));
+			if let Some(index) = index {
+				attributes.replace(index, attr);
+			} else {
+				attributes.push(attr);
+			}
+		} else if let Some(index) = index {
+			attributes.remove(index);
+		}
+	}
+	
+	/// Writes the attributes without checking that they are allowed where they are written, see
+	/// [`write_in`]
 	pub fn write<W: Write>(wtr: &mut W, attributes: &[Attribute], constant_pool: &mut ConstantPoolWriter, label_pc_map: Option<&HashMap<LabelInsn, u32>>) -> crate::Result<()> {
+		write_unchecked(wtr, attributes, constant_pool, label_pc_map, &mut WriteContext::default())
+	}
+	
+	/// Writes the attributes of a structure of the given kind, failing if one of them is not
+	/// allowed there
+	pub fn write_in<W: Write>(wtr: &mut W, attributes: &[Attribute], source: AttributeSource, constant_pool: &mut ConstantPoolWriter, label_pc_map: Option<&HashMap<LabelInsn, u32>>) -> crate::Result<()> {
+		write_with_context(wtr, attributes, source, constant_pool, label_pc_map, &mut WriteContext::default())
+	}
+	
+	pub(crate) fn write_with_context<W: Write>(wtr: &mut W, attributes: &[Attribute], source: AttributeSource, constant_pool: &mut ConstantPoolWriter, label_pc_map: Option<&HashMap<LabelInsn, u32>>, context: &mut WriteContext) -> crate::Result<()> {
+		if let Some(attribute) = attributes.iter().find(|attribute| !attribute.allowed_in(source)) {
+			return Err(ParserError::misplaced_attribute(attribute.name().to_string(), source));
+		}
+		write_unchecked(wtr, attributes, constant_pool, label_pc_map, context)
+	}
+	
+	fn write_unchecked<W: Write>(wtr: &mut W, attributes: &[Attribute], constant_pool: &mut ConstantPoolWriter, label_pc_map: Option<&HashMap<LabelInsn, u32>>, context: &mut WriteContext) -> crate::Result<()> {
 		wtr.write_u16::<BigEndian>(attributes.len() as u16)?;
-		for attribute in attributes.iter() {
-			attribute.write(wtr, constant_pool, &label_pc_map)?;
+		for (i, attribute) in attributes.iter().enumerate() {
+			attribute.write_with_context(wtr, constant_pool, &label_pc_map, context).path_context(|| PathSegment::Attribute(i))?;
 		}
 		Ok(())
 	}
 }
 
+/// The contents an attribute was parsed from, kept when parsing with
+/// [`ParseOptions::raw_attributes`]. Only for reading, as the attribute is always written from its
+/// fields. Always equal to any other, so that parsed attributes equal those built by hand.
+#[derive(Clone, Default)]
+pub struct RawBytes(Option<Vec<u8>>);
+
+impl RawBytes {
+	pub fn get(&self) -> Option<&[u8]> {
+		self.0.as_deref()
+	}
+}
+
+impl PartialEq for RawBytes {
+	fn eq(&self, _other: &Self) -> bool {
+		true
+	}
+}
+
+impl Debug for RawBytes {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match &self.0 {
+			Some(bytes) => write!(f, "RawBytes({} bytes)", bytes.len()),
+			None => f.write_str("RawBytes(None)")
+		}
+	}
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConstantValueAttribute {
-	value: ConstantValue
+	pub value: ConstantValue,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Floats and doubles are compared by their bits, like [`LdcType`](crate::ast::LdcType)
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConstantValue {
 	Long(i64),
-	Float(f32),
-	Double(f64),
+	Float(#[cfg_attr(feature = "serde", serde(with = "crate::serde_bits::float"))] f32),
+	Double(#[cfg_attr(feature = "serde", serde(with = "crate::serde_bits::double"))] f64),
 	Int(i32),
 	String(String)
 }
 
+impl PartialEq for ConstantValue {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			(ConstantValue::Long(a), ConstantValue::Long(b)) => a == b,
+			(ConstantValue::Float(a), ConstantValue::Float(b)) => a.to_bits() == b.to_bits(),
+			(ConstantValue::Double(a), ConstantValue::Double(b)) => a.to_bits() == b.to_bits(),
+			(ConstantValue::Int(a), ConstantValue::Int(b)) => a == b,
+			(ConstantValue::String(a), ConstantValue::String(b)) => a == b,
+			_ => false
+		}
+	}
+}
+
+impl ConstantValue {
+	/// Whether the JVM accepts this as the initial value of a field with the given descriptor
+	pub fn matches_descriptor(&self, descriptor: &str) -> bool {
+		match self {
+			ConstantValue::Long(_) => descriptor == "J",
+			ConstantValue::Float(_) => descriptor == "F",
+			ConstantValue::Double(_) => descriptor == "D",
+			ConstantValue::Int(_) => matches!(descriptor, "I" | "S" | "C" | "Z" | "B"),
+			ConstantValue::String(_) => descriptor == "Ljava/lang/String;"
+		}
+	}
+	
+	pub fn kind(&self) -> &'static str {
+		match self {
+			ConstantValue::Long(_) => "long",
+			ConstantValue::Float(_) => "float",
+			ConstantValue::Double(_) => "double",
+			ConstantValue::Int(_) => "int",
+			ConstantValue::String(_) => "String"
+		}
+	}
+}
+
 impl ConstantValueAttribute {
-	pub fn parse(constant_pool: &ConstantPool, buf: Vec<u8>) -> Result<Self> {
-		let index = buf.as_slice().read_u16::<BigEndian>()?;
+	pub fn new(value: ConstantValue) -> Self {
+		ConstantValueAttribute {
+			value,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		let index = buf.read_u16::<BigEndian>()?;
 		let value = match constant_pool.get(index)? {
 			ConstantType::Long(x) => ConstantValue::Long(x.inner()),
 			ConstantType::Float(x) => ConstantValue::Float(x.inner()),
 			ConstantType::Double(x) => ConstantValue::Double(x.inner()),
 			ConstantType::Integer(x) => ConstantValue::Int(x.inner()),
 			ConstantType::String(x) => ConstantValue::String(constant_pool.utf8(x.utf_index)?.str.clone()),
-			x => panic!("Invalid constant value type {:#?} at index {}", x, index)
+			x => return Err(ParserError::incomp_cp("constant value", x, index as usize))
 		};
-		Ok(ConstantValueAttribute {
-			value
-		})
+		Ok(ConstantValueAttribute::new(value))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2)
 	}
 	
 	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
@@ -84,23 +275,33 @@ impl ConstantValueAttribute {
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SignatureAttribute {
-	pub signature: String
+	pub signature: String,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
 }
 
 impl SignatureAttribute {
 	pub fn new(signature: String) -> Self {
 		SignatureAttribute {
-			signature
+			signature,
+			raw: RawBytes::default()
 		}
 	}
 	
-	pub fn parse(constant_pool: &ConstantPool, buf: Vec<u8>) -> Result<Self> {
-		let index = buf.as_slice().read_u16::<BigEndian>()?;
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		let index = buf.read_u16::<BigEndian>()?;
 		let signature = constant_pool.utf8(index)?.str.clone();
-		Ok(SignatureAttribute {
-			signature
-		})
+		Ok(SignatureAttribute::new(signature))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2)
 	}
 	
 	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
@@ -110,50 +311,163 @@ impl SignatureAttribute {
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExceptionsAttribute {
-	pub exceptions: Vec<String>
+	pub exceptions: Vec<String>,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
 }
 
 impl ExceptionsAttribute {
 	pub fn new(exceptions: Vec<String>) -> Self {
 		ExceptionsAttribute {
-			exceptions
+			exceptions,
+			raw: RawBytes::default()
 		}
 	}
 	
-	pub fn parse(constant_pool: &ConstantPool, buf: Vec<u8>) -> Result<Self> {
-		let mut slice = buf.as_slice();
-		let num_exceptions = slice.read_u16::<BigEndian>()?;
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		let num_exceptions = buf.read_u16::<BigEndian>()?;
 		let mut exceptions: Vec<String> = Vec::with_capacity(num_exceptions as usize);
 		for _ in 0..num_exceptions {
-			exceptions.push(constant_pool.utf8(constant_pool.class(slice.read_u16::<BigEndian>()?)?.name_index)?.str.clone());
+			exceptions.push(constant_pool.utf8(constant_pool.class(buf.read_u16::<BigEndian>()?)?.name_index)?.str.clone());
 		}
-		Ok(ExceptionsAttribute {
-			exceptions
-		})
+		Ok(ExceptionsAttribute::new(exceptions))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2 + 2 * self.exceptions.len() as u64)
 	}
 	
 	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
 		let num_exceptions = self.exceptions.len();
 		wtr.write_u16::<BigEndian>(num_exceptions as u16)?;
 		for exception in self.exceptions.iter() {
-			wtr.write_u16::<BigEndian>(constant_pool.utf8(exception.clone()))?;
+			wtr.write_u16::<BigEndian>(constant_pool.class_utf8(exception.clone()))?;
 		}
 		Ok(())
 	}
+	
+	pub fn throws(&mut self) -> ThrowsList<'_> {
+		ThrowsList::from(self)
+	}
+}
+
+/// The classes in an Exceptions attribute, checked to be internal class names as they are added
+#[derive(Debug, PartialEq)]
+pub struct ThrowsList<'a> {
+	exceptions: &'a mut Vec<String>
+}
+
+impl<'a> ThrowsList<'a> {
+	/// Adds `class_name`, such as `java/io/IOException`, unless it is already thrown. Binary names
+	/// such as `java.io.IOException` and array classes are rejected.
+	pub fn add(&mut self, class_name: &str) -> Result<bool> {
+		let name = ClassName::from_internal(class_name)?;
+		if name.is_array() {
+			return Err(ParserError::invalid_descriptor(format!("Array class {} cannot be thrown", name)));
+		}
+		if self.contains(&name) {
+			return Ok(false);
+		}
+		self.exceptions.push(name.into_string());
+		Ok(true)
+	}
+	
+	/// Removes every occurrence of `class_name`, returning whether there was one
+	pub fn remove(&mut self, class_name: &str) -> bool {
+		let len = self.exceptions.len();
+		self.exceptions.retain(|x| x != class_name);
+		self.exceptions.len() != len
+	}
+	
+	pub fn contains(&self, class_name: &str) -> bool {
+		self.exceptions.iter().any(|x| x == class_name)
+	}
+	
+	pub fn iter(&self) -> impl Iterator<Item = &str> {
+		self.exceptions.iter().map(String::as_str)
+	}
+	
+	pub fn len(&self) -> usize {
+		self.exceptions.len()
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.exceptions.is_empty()
+	}
+	
+	/// The names as stored, which are not checked
+	pub fn as_vec_mut(&mut self) -> &mut Vec<String> {
+		self.exceptions
+	}
+	
+	/// A new attribute with the same classes
+	pub fn to_attribute(&self) -> ExceptionsAttribute {
+		ExceptionsAttribute::new(self.exceptions.clone())
+	}
+}
+
+impl<'a> From<&'a mut ExceptionsAttribute> for ThrowsList<'a> {
+	fn from(attribute: &'a mut ExceptionsAttribute) -> Self {
+		ThrowsList {
+			exceptions: &mut attribute.exceptions
+		}
+	}
+}
+
+/// The raw contents of a Code attribute that could not be parsed
+#[derive(Constructor, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UndecodedCodeAttribute {
+	pub buf: Vec<u8>,
+	/// Why parsing failed
+	pub error: String
+}
+
+/// The raw contents of an attribute this library understands that refers to constants of the
+/// wrong type
+#[derive(Constructor, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UndecodedAttribute {
+	pub name: String,
+	pub buf: Vec<u8>,
+	/// Why parsing failed
+	pub error: String
+}
+
+impl UndecodedAttribute {
+	/// The kind of attribute the name refers to
+	pub fn kind(&self) -> AttributeKind {
+		AttributeKind::of(&self.name)
+	}
 }
 
 #[derive(Constructor, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnknownAttribute {
 	pub name: String,
 	pub buf: Vec<u8>
 }
 
 impl UnknownAttribute {
+	/// The kind of attribute the name refers to
+	pub fn kind(&self) -> AttributeKind {
+		AttributeKind::of(&self.name)
+	}
+	
 	pub fn parse(name: String, buf: Vec<u8>) -> Result<Self> {
 		Ok(UnknownAttribute::new(name, buf))
 	}
 	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(self.buf.len() as u64)
+	}
+	
 	pub fn write<T: Write>(&self, wtr: &mut T, _constant_pool: &mut ConstantPoolWriter) -> Result<()> {
 		wtr.write_all(self.buf.as_slice())?;
 		Ok(())
@@ -169,17 +483,33 @@ impl UnknownAttribute {
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceFileAttribute {
-	pub source_file: String
+	pub source_file: String,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
 }
 
 impl SourceFileAttribute {
-	pub fn parse(constant_pool: &ConstantPool, buf: Vec<u8>) -> Result<Self> {
-		let index = buf.as_slice().read_u16::<BigEndian>()?;
+	pub fn new(source_file: String) -> Self {
+		SourceFileAttribute {
+			source_file,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		let index = buf.read_u16::<BigEndian>()?;
 		let source_file = constant_pool.utf8(index)?.str.clone();
-		Ok(SourceFileAttribute {
-			source_file
-		})
+		Ok(SourceFileAttribute::new(source_file))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2)
 	}
 	
 	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
@@ -188,12 +518,324 @@ impl SourceFileAttribute {
 	}
 }
 
+/// Extended debugging information such as a JSR-45 SMAP, emitted by Kotlin and JSP compilers
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceDebugExtensionAttribute {
+	pub debug_extension: String,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
+}
+
+impl SourceDebugExtensionAttribute {
+	pub fn new(debug_extension: String) -> Self {
+		SourceDebugExtensionAttribute {
+			debug_extension,
+			raw: RawBytes::default()
+		}
+	}
+	
+	/// Returns None if the contents are not valid modified UTF-8, so that they can be kept verbatim
+	pub fn parse(buf: &mut Cursor<Vec<u8>>, options: &ParseOptions) -> Result<Option<Self>> {
+		with_slice(buf, |buf| Self::parse_slice(buf, options))
+	}
+	
+	pub(crate) fn parse_slice(buf: &mut Cursor<&[u8]>, options: &ParseOptions) -> Result<Option<Self>> {
+		let remaining = buf.get_ref().len().saturating_sub(buf.position() as usize);
+		let utf = Utf8Info::from_mutf8(read_slice(buf, remaining, "SourceDebugExtension")?, options.strict_utf8)?;
+		if !utf.is_lossless() {
+			return Ok(None);
+		}
+		Ok(Some(SourceDebugExtensionAttribute::new(utf.str)))
+	}
+	
+	/// The length of the contents in modified UTF-8, where NUL takes two bytes and characters
+	/// outside the Basic Multilingual Plane take six
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(self.debug_extension.chars().map(|c| match c as u32 {
+			0 => 2,
+			1..=0x7F => 1,
+			0x80..=0x7FF => 2,
+			0x800..=0xFFFF => 3,
+			_ => 6
+		}).sum())
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, _constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		wtr.write_all(&mutf8::utf8_to_mutf8(self.debug_extension.as_bytes()))?;
+		Ok(())
+	}
+}
+
+/// The nested classes a class refers to, including itself if it is nested
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InnerClassesAttribute {
+	pub classes: Vec<InnerClass>,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InnerClass {
+	pub inner_class: String,
+	/// None for local and anonymous classes
+	pub outer_class: Option<String>,
+	/// The simple name in the source, None for anonymous classes
+	pub inner_name: Option<String>,
+	pub access_flags: InnerClassAccessFlags
+}
+
+impl InnerClassesAttribute {
+	pub fn new(classes: Vec<InnerClass>) -> Self {
+		InnerClassesAttribute {
+			classes,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		let num_classes = buf.read_u16::<BigEndian>()?;
+		let mut classes: Vec<InnerClass> = Vec::with_capacity(num_classes as usize);
+		for _ in 0..num_classes {
+			let inner_class = constant_pool.class_name(buf.read_u16::<BigEndian>()?)?;
+			let outer_class = match buf.read_u16::<BigEndian>()? {
+				0 => None,
+				index => Some(constant_pool.class_name(index)?)
+			};
+			let inner_name = match buf.read_u16::<BigEndian>()? {
+				0 => None,
+				index => Some(constant_pool.utf8_inner(index)?)
+			};
+			let access_flags = InnerClassAccessFlags::parse(buf)?;
+			classes.push(InnerClass {
+				inner_class,
+				outer_class,
+				inner_name,
+				access_flags
+			});
+		}
+		Ok(InnerClassesAttribute::new(classes))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2 + 8 * self.classes.len() as u64)
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		wtr.write_u16::<BigEndian>(self.classes.len() as u16)?;
+		for class in self.classes.iter() {
+			wtr.write_u16::<BigEndian>(constant_pool.class_utf8(class.inner_class.clone()))?;
+			wtr.write_u16::<BigEndian>(class.outer_class.as_ref().map_or(0, |x| constant_pool.class_utf8(x.clone())))?;
+			wtr.write_u16::<BigEndian>(class.inner_name.as_ref().map_or(0, |x| constant_pool.utf8(x.clone())))?;
+			class.access_flags.write(wtr)?;
+		}
+		Ok(())
+	}
+}
+
+/// The class and method that a local or anonymous class is declared in
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnclosingMethodAttribute {
+	pub class: String,
+	/// The name and descriptor of the method, None outside of a method such as in a field
+	/// initializer
+	pub method: Option<(String, String)>,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
+}
+
+impl EnclosingMethodAttribute {
+	pub fn new(class: String, method: Option<(String, String)>) -> Self {
+		EnclosingMethodAttribute {
+			class,
+			method,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		let class = constant_pool.class_name(buf.read_u16::<BigEndian>()?)?;
+		let method = match buf.read_u16::<BigEndian>()? {
+			0 => None,
+			index => {
+				let nat = constant_pool.nameandtype(index)?;
+				Some((constant_pool.utf8_inner(nat.name_index)?, constant_pool.utf8_inner(nat.descriptor_index)?))
+			}
+		};
+		Ok(EnclosingMethodAttribute::new(class, method))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(4)
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		wtr.write_u16::<BigEndian>(constant_pool.class_utf8(self.class.clone()))?;
+		let method = match &self.method {
+			Some((name, descriptor)) => {
+				let name = constant_pool.utf8(name.clone());
+				let descriptor = constant_pool.utf8(descriptor.clone());
+				constant_pool.nameandtype(name, descriptor)
+			},
+			None => 0
+		};
+		wtr.write_u16::<BigEndian>(method)?;
+		Ok(())
+	}
+}
+
+/// The class whose nest this class belongs to
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NestHostAttribute {
+	pub host: String,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
+}
+
+impl NestHostAttribute {
+	pub fn new(host: String) -> Self {
+		NestHostAttribute {
+			host,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		Ok(NestHostAttribute::new(constant_pool.class_name(buf.read_u16::<BigEndian>()?)?))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2)
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		wtr.write_u16::<BigEndian>(constant_pool.class_utf8(self.host.clone()))?;
+		Ok(())
+	}
+}
+
+/// The other classes in the nest this class is the host of
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NestMembersAttribute {
+	pub members: Vec<String>,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
+}
+
+impl NestMembersAttribute {
+	pub fn new(members: Vec<String>) -> Self {
+		NestMembersAttribute {
+			members,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>) -> Result<Self> {
+		let num_members = buf.read_u16::<BigEndian>()?;
+		let mut members: Vec<String> = Vec::with_capacity(num_members as usize);
+		for _ in 0..num_members {
+			members.push(constant_pool.class_name(buf.read_u16::<BigEndian>()?)?);
+		}
+		Ok(NestMembersAttribute::new(members))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2 + 2 * self.members.len() as u64)
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		wtr.write_u16::<BigEndian>(self.members.len() as u16)?;
+		for member in self.members.iter() {
+			wtr.write_u16::<BigEndian>(constant_pool.class_utf8(member.clone()))?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineNumberTableAttribute {
+	pub lines: Vec<LineNumber>,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
+}
+
+/// The source line that starts at a label
+#[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineNumber {
+	pub start: LabelInsn,
+	pub line: u16
+}
+
+impl LineNumberTableAttribute {
+	pub fn new(lines: Vec<LineNumber>) -> Self {
+		LineNumberTableAttribute {
+			lines,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(buf: &mut Cursor<Vec<u8>>, pc_label_map: &mut PcLabels) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(buf, pc_label_map))
+	}
+	
+	pub(crate) fn parse_slice(buf: &mut Cursor<&[u8]>, pc_label_map: &mut PcLabels) -> Result<Self> {
+		let num_lines = buf.read_u16::<BigEndian>()? as usize;
+		let mut lines: Vec<LineNumber> = Vec::with_capacity(num_lines);
+		for _ in 0..num_lines {
+			let start = pc_label_map.label_at(buf.read_u16::<BigEndian>()? as u32);
+			lines.push(LineNumber::new(start, buf.read_u16::<BigEndian>()?));
+		}
+		Ok(LineNumberTableAttribute::new(lines))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2 + 4 * self.lines.len() as u64)
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, label_pc_map: &HashMap<LabelInsn, u32>) -> Result<()> {
+		wtr.write_u16::<BigEndian>(self.lines.len() as u16)?;
+		for line in self.lines.iter() {
+			let start_pc = *label_pc_map.get(&line.start).ok_or_else(ParserError::unmapped_label)?;
+			wtr.write_u16::<BigEndian>(start_pc as u16)?;
+			wtr.write_u16::<BigEndian>(line.line)?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalVariableTableAttribute {
-	pub variables: Vec<LocalVariable>
+	pub variables: Vec<LocalVariable>,
+	#[cfg_attr(feature = "serde", serde(default, skip))]
+	pub raw: RawBytes
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalVariable {
 	pub start: LabelInsn,
 	pub end: LabelInsn,
@@ -203,16 +845,28 @@ pub struct LocalVariable {
 }
 
 impl LocalVariableTableAttribute {
-	pub fn parse(constant_pool: &ConstantPool, buf: Vec<u8>, pc_label_map: &mut HashMap<u32, LabelInsn>) -> Result<Self> {
-		let mut buf = Cursor::new(buf);
+	pub fn new(variables: Vec<LocalVariable>) -> Self {
+		LocalVariableTableAttribute {
+			variables,
+			raw: RawBytes::default()
+		}
+	}
+	
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>, pc_label_map: &mut PcLabels) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf, pc_label_map))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>, pc_label_map: &mut PcLabels) -> Result<Self> {
 		let num_vars = buf.read_u16::<BigEndian>()? as usize;
 		let mut variables: Vec<LocalVariable> = Vec::with_capacity(num_vars);
 		for _ in 0..num_vars {
-			variables.push(LocalVariable::parse(constant_pool, &mut buf, pc_label_map)?)
+			variables.push(LocalVariable::parse_slice(constant_pool, buf, pc_label_map)?)
 		}
-		Ok(LocalVariableTableAttribute {
-			variables
-		})
+		Ok(LocalVariableTableAttribute::new(variables))
+	}
+	
+	pub fn byte_len(&self, _constant_pool: &mut ConstantPoolWriter) -> Result<u64> {
+		Ok(2 + 10 * self.variables.len() as u64)
 	}
 	
 	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter, label_pc_map: &HashMap<LabelInsn, u32>) -> Result<()> {
@@ -225,19 +879,22 @@ impl LocalVariableTableAttribute {
 }
 
 impl LocalVariable {
-	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>, pc_label_map: &mut HashMap<u32, LabelInsn>) -> Result<Self> {
+	pub fn parse(constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>, pc_label_map: &mut PcLabels) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(constant_pool, buf, pc_label_map))
+	}
+	
+	pub(crate) fn parse_slice(constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>, pc_label_map: &mut PcLabels) -> Result<Self> {
 		let start_pc = buf.read_u16::<BigEndian>()? as u32;
 		let end_pc = start_pc + (buf.read_u16::<BigEndian>()? as u32);
-		pc_label_map.insert_if_not_present(start_pc, LabelInsn::new(pc_label_map.len() as u32));
-		pc_label_map.insert_if_not_present(end_pc, LabelInsn::new(pc_label_map.len() as u32));
+		let (start, end) = (pc_label_map.label_at(start_pc), pc_label_map.label_at(end_pc));
 		
 		let name = constant_pool.utf8_inner(buf.read_u16::<BigEndian>()?)?;
 		let descriptor = constant_pool.utf8_inner(buf.read_u16::<BigEndian>()?)?;
 		let index = buf.read_u16::<BigEndian>()?;
 		
 		Ok(LocalVariable {
-			start: *pc_label_map.get(&start_pc).ok_or_else(ParserError::unmapped_label)?,
-			end: *pc_label_map.get(&end_pc).ok_or_else(ParserError::unmapped_label)?,
+			start,
+			end,
 			name,
 			descriptor,
 			index
@@ -258,125 +915,1087 @@ impl LocalVariable {
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "attribute"))]
 pub enum Attribute {
 	ConstantValue(ConstantValueAttribute),
 	Signature(SignatureAttribute),
 	Code(CodeAttribute),
 	Exceptions(ExceptionsAttribute),
 	SourceFile(SourceFileAttribute),
+	SourceDebugExtension(SourceDebugExtensionAttribute),
+	LineNumberTable(LineNumberTableAttribute),
 	LocalVariableTable(LocalVariableTableAttribute),
+	/// Has the same layout as a LocalVariableTable, but each descriptor is the generic signature of
+	/// the variable. See [`CodeAttribute::local_variables`] for both tables together.
+	LocalVariableTypeTable(LocalVariableTableAttribute),
+	InnerClasses(InnerClassesAttribute),
+	EnclosingMethod(EnclosingMethodAttribute),
+	NestHost(NestHostAttribute),
+	NestMembers(NestMembersAttribute),
+	/// A Code attribute that failed to parse, kept when
+	/// [`ParseOptions::lenient_code`](crate::classfile::ParseOptions::lenient_code) is set
+	UndecodedCode(UndecodedCodeAttribute),
+	/// Any other attribute that failed to parse, kept when
+	/// [`ParseOptions::lenient_constant_types`](crate::classfile::ParseOptions::lenient_constant_types)
+	/// is set
+	Undecoded(UndecodedAttribute),
+	/// An attribute parsed by a parser registered in [`ParseOptions::custom_attributes`]. These
+	/// cannot be serialized with serde.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	Custom(Box<dyn CustomAttribute>),
 	Unknown(UnknownAttribute)
 }
 
 impl Attribute {
-	pub fn parse<R: Read>(rdr: &mut R, source: &AttributeSource, version: &ClassVersion, constant_pool: &ConstantPool, pc_label_map: Option<&mut HashMap<u32, LabelInsn>>) -> Result<Attribute> {
-		let name = constant_pool.utf8(rdr.read_u16::<BigEndian>()?)?.str.clone();
+	pub fn parse<R: Read>(rdr: &mut R, context: &mut AttributeContext) -> Result<Attribute> {
+		Attribute::parse_from(&mut CountingReader::new(rdr), context)
+	}
+	
+	pub(crate) fn parse_from<R: ClassRead>(rdr: &mut R, context: &mut AttributeContext) -> Result<Attribute> {
+		let (name, bytes) = Attribute::parse_raw(rdr, context.constant_pool)?;
+		Attribute::parse_contents(name, AttributeKind::of(name), bytes, context)
+	}
+	
+	/// Reads the name and contents of an attribute without interpreting them. The name is borrowed
+	/// from the constant pool, and the name and contents are only copied for attributes that keep
+	/// them.
+	fn parse_raw<'a, 'r, R: ClassRead>(rdr: &'r mut R, constant_pool: &'a ConstantPool) -> Result<(&'a str, Cow<'r, [u8]>)> {
+		let name = &constant_pool.utf8(rdr.read_u16::<BigEndian>()?).context(|| "attribute name")?.str;
 		let attribute_length = rdr.read_u32::<BigEndian>()? as usize;
-		let buf: Vec<u8> = rdr.read_nbytes(attribute_length as usize)?;
-		let str = name.as_str();
-		
-		let attr = match source {
-			AttributeSource::Class => {
-				if str == "SourceFile" {
-					Attribute::SourceFile(SourceFileAttribute::parse(constant_pool, buf)?)
-				} else {
-					Attribute::Unknown(UnknownAttribute::parse(name, buf)?)
-				}
+		Ok((name, rdr.read_contents(attribute_length, "attribute")?))
+	}
+	
+	fn parse_contents(name: &str, kind: AttributeKind, bytes: Cow<[u8]>, context: &mut AttributeContext) -> Result<Attribute> {
+		let attribute_length = bytes.len();
+		let mut buf = Cursor::new(bytes.as_ref());
+		let parsed = Attribute::parse_known(kind, context, &mut buf).and_then(|attr| match attr {
+			Some(attr) => Ok(Some(attr)),
+			None => Attribute::parse_custom(name, context, &mut buf)
+		});
+		let mut attr = match parsed {
+			Ok(Some(attr)) => attr,
+			Ok(None) => return Ok(Attribute::Unknown(UnknownAttribute::new(name.to_string(), bytes.into_owned()))),
+			Err(err) if kind == AttributeKind::Code && context.source == AttributeSource::Method && context.options.lenient_code => {
+				return Ok(Attribute::UndecodedCode(UndecodedCodeAttribute::new(bytes.into_owned(), err.to_string())));
 			},
-			AttributeSource::Field => {
-				if str == "ConstantValue" {
-					Attribute::ConstantValue(ConstantValueAttribute::parse(constant_pool, buf)?)
-				} else if str == "Signature" && version.major >= MajorVersion::JAVA_5 {
-					Attribute::Signature(SignatureAttribute::parse(constant_pool, buf)?)
-				} else {
-					Attribute::Unknown(UnknownAttribute::parse(name, buf)?)
-				}
+			Err(err) if kind != AttributeKind::Code && context.options.lenient_constant_types
+				&& matches!(err.root(), ParserError::IncompatibleCPEntry { .. }) => {
+				return Ok(Attribute::Undecoded(UndecodedAttribute::new(name.to_string(), bytes.into_owned(), err.to_string())));
 			},
-			AttributeSource::Method => {
-				if str == "Code" {
-					Attribute::Code(CodeAttribute::parse(version, constant_pool, buf)?)
-				} else if str == "Signature" && version.major >= MajorVersion::JAVA_5 {
-					Attribute::Signature(SignatureAttribute::parse(constant_pool, buf)?)
-				} else if str == "Exceptions" {
-					Attribute::Exceptions(ExceptionsAttribute::parse(constant_pool, buf)?)
-				} else {
-					Attribute::Unknown(UnknownAttribute::parse(name, buf)?)
-				}
-			}
-			AttributeSource::Code => {
-				let pc_label_map = pc_label_map.unwrap();
-				if str == "LocalVariableTable" {
-					Attribute::LocalVariableTable(LocalVariableTableAttribute::parse(constant_pool, buf, pc_label_map)?)
-				//} else if str == "LocalVariableTypeTable" && version.major >= MajorVersion::JAVA_5 {
-				
-				} else {
-					Attribute::Unknown(UnknownAttribute::parse(name, buf)?)
-				}
+			Err(err) => {
+				return Err(match err.root() {
+					ParserError::IO(x) if x.kind() == ErrorKind::UnexpectedEof => {
+						ParserError::attribute_length_mismatch(name.to_string(), attribute_length, None)
+					}
+					_ => ParserError::at(name, err)
+				});
 			}
 		};
+		
+		let consumed = buf.position() as usize;
+		if consumed != attribute_length {
+			// keep the attribute verbatim so that the trailing bytes survive a round trip
+			return if context.options.lenient_attribute_length {
+				Ok(Attribute::Unknown(UnknownAttribute::new(name.to_string(), bytes.into_owned())))
+			} else {
+				Err(ParserError::attribute_length_mismatch(name.to_string(), attribute_length, Some(consumed)))
+			};
+		}
+		if context.options.raw_attributes {
+			attr.set_raw(bytes.into_owned());
+		}
 		Ok(attr)
 	}
 	
-	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter, label_pc_map: &Option<&HashMap<LabelInsn, u32>>) -> Result<()> {
+	/// Keeps the contents the attribute was parsed from, see [`raw_bytes`](Self::raw_bytes)
+	fn set_raw(&mut self, bytes: Vec<u8>) {
+		let raw = match self {
+			Attribute::ConstantValue(x) => &mut x.raw,
+			Attribute::Signature(x) => &mut x.raw,
+			Attribute::Exceptions(x) => &mut x.raw,
+			Attribute::SourceFile(x) => &mut x.raw,
+			Attribute::SourceDebugExtension(x) => &mut x.raw,
+			Attribute::LineNumberTable(x) => &mut x.raw,
+			Attribute::LocalVariableTable(x) | Attribute::LocalVariableTypeTable(x) => &mut x.raw,
+			Attribute::InnerClasses(x) => &mut x.raw,
+			Attribute::EnclosingMethod(x) => &mut x.raw,
+			Attribute::NestHost(x) => &mut x.raw,
+			Attribute::NestMembers(x) => &mut x.raw,
+			// these keep their contents anyway
+			Attribute::Code(_) | Attribute::UndecodedCode(_) | Attribute::Undecoded(_) | Attribute::Unknown(_) | Attribute::Custom(_) => return
+		};
+		*raw = RawBytes(Some(bytes));
+	}
+	
+	/// The contents this attribute was parsed from, after its name and length. Unknown and undecoded
+	/// attributes always have them. A Code attribute has them when parsed with
+	/// [`ParseOptions::keep_original_code`], until [`CodeAttribute::mark_modified`]. Other attributes
+	/// only have them when parsed with [`ParseOptions::raw_attributes`], which also keeps them for
+	/// Code, and custom attributes never do.
+	pub fn raw_bytes(&self) -> Option<&[u8]> {
 		match self {
-			Attribute::ConstantValue(t) => {
-				let mut buf: Vec<u8> = Vec::new();
-				wtr.write_u16::<BigEndian>(constant_pool.utf8("ConstantValue"))?;
-				t.write(&mut buf, constant_pool)?;
-				wtr.write_u32::<BigEndian>(buf.len() as u32)?;
-				wtr.write_all(buf.as_slice())?;
+			Attribute::ConstantValue(x) => x.raw.get(),
+			Attribute::Signature(x) => x.raw.get(),
+			Attribute::Exceptions(x) => x.raw.get(),
+			Attribute::SourceFile(x) => x.raw.get(),
+			Attribute::SourceDebugExtension(x) => x.raw.get(),
+			Attribute::LineNumberTable(x) => x.raw.get(),
+			Attribute::LocalVariableTable(x) | Attribute::LocalVariableTypeTable(x) => x.raw.get(),
+			Attribute::InnerClasses(x) => x.raw.get(),
+			Attribute::EnclosingMethod(x) => x.raw.get(),
+			Attribute::NestHost(x) => x.raw.get(),
+			Attribute::NestMembers(x) => x.raw.get(),
+			Attribute::Code(x) => x.original.as_ref().map(|x| x.bytes()),
+			Attribute::UndecodedCode(x) => Some(&x.buf),
+			Attribute::Undecoded(x) => Some(&x.buf),
+			Attribute::Unknown(x) => Some(&x.buf),
+			Attribute::Custom(_) => None
+		}
+	}
+	
+	/// Parses the attributes this library understands, returning None for any others
+	fn parse_known(kind: AttributeKind, context: &mut AttributeContext, buf: &mut Cursor<&[u8]>) -> Result<Option<Attribute>> {
+		let (version, constant_pool, options) = (context.version, context.constant_pool, context.options);
+		let generics = version.major >= MajorVersion::JAVA_5;
+		let nests = version.major >= MajorVersion::JAVA_11;
+		let attr = match (context.source, kind) {
+			(AttributeSource::Class, AttributeKind::SourceFile) => Attribute::SourceFile(SourceFileAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Class, AttributeKind::SourceDebugExtension) => match SourceDebugExtensionAttribute::parse_slice(buf, options)? {
+				Some(x) => Attribute::SourceDebugExtension(x),
+				None => return Ok(None)
 			},
-			Attribute::Signature(t) => {
-				let mut buf: Vec<u8> = Vec::new();
-				wtr.write_u16::<BigEndian>(constant_pool.utf8("Signature"))?;
-				t.write(&mut buf, constant_pool)?;
-				wtr.write_u32::<BigEndian>(buf.len() as u32)?;
-				wtr.write_all(buf.as_slice())?;
+			(AttributeSource::Class, AttributeKind::Signature)
+				| (AttributeSource::Field, AttributeKind::Signature)
+				| (AttributeSource::Method, AttributeKind::Signature) if generics => Attribute::Signature(SignatureAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Class, AttributeKind::InnerClasses) => Attribute::InnerClasses(InnerClassesAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Class, AttributeKind::EnclosingMethod) if generics => Attribute::EnclosingMethod(EnclosingMethodAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Class, AttributeKind::NestHost) if nests => Attribute::NestHost(NestHostAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Class, AttributeKind::NestMembers) if nests => Attribute::NestMembers(NestMembersAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Field, AttributeKind::ConstantValue) => Attribute::ConstantValue(ConstantValueAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Method, AttributeKind::Code) => Attribute::Code(CodeAttribute::parse_slice(version, constant_pool, buf, options)?),
+			(AttributeSource::Method, AttributeKind::Exceptions) => Attribute::Exceptions(ExceptionsAttribute::parse_slice(constant_pool, buf)?),
+			(AttributeSource::Code, AttributeKind::LineNumberTable) => {
+				let pc_label_map = context.pc_label_map.as_deref_mut().ok_or_else(|| ParserError::none("pc_label_map"))?;
+				Attribute::LineNumberTable(LineNumberTableAttribute::parse_slice(buf, pc_label_map)?)
 			},
-			Attribute::Code(t) => {
-				let mut buf: Vec<u8> = Vec::new();
-				wtr.write_u16::<BigEndian>(constant_pool.utf8("Code"))?;
-				t.write(&mut buf, constant_pool)?;
-				wtr.write_u32::<BigEndian>(buf.len() as u32)?;
-				wtr.write_all(buf.as_slice())?;
+			(AttributeSource::Code, AttributeKind::LocalVariableTable) | (AttributeSource::Code, AttributeKind::LocalVariableTypeTable)
+				if kind == AttributeKind::LocalVariableTable || generics => {
+				let pc_label_map = context.pc_label_map.as_deref_mut().ok_or_else(|| ParserError::none("pc_label_map"))?;
+				let table = LocalVariableTableAttribute::parse_slice(constant_pool, buf, pc_label_map)?;
+				if kind == AttributeKind::LocalVariableTable {
+					Attribute::LocalVariableTable(table)
+				} else {
+					Attribute::LocalVariableTypeTable(table)
+				}
 			},
-			Attribute::Exceptions(t) => {
-				let mut buf: Vec<u8> = Vec::new();
-				wtr.write_u16::<BigEndian>(constant_pool.utf8("Exceptions"))?;
-				t.write(&mut buf, constant_pool)?;
-				wtr.write_u32::<BigEndian>(buf.len() as u32)?;
-				wtr.write_all(buf.as_slice())?;
+			_ => return Ok(None)
+		};
+		Ok(Some(attr))
+	}
+	
+	/// Parses an attribute with the registered parser for its name, if there is one
+	fn parse_custom(name: &str, context: &mut AttributeContext, buf: &mut Cursor<&[u8]>) -> Result<Option<Attribute>> {
+		let options = context.options;
+		match options.custom_attributes.get(name) {
+			Some(parser) => Ok(Some(Attribute::Custom(parser.parse_slice(context, buf)?))),
+			None => Ok(None)
+		}
+	}
+	
+	/// The name the attribute is written with
+	pub fn name(&self) -> &str {
+		match self {
+			Attribute::ConstantValue(_) => "ConstantValue",
+			Attribute::Signature(_) => "Signature",
+			Attribute::Code(_) | Attribute::UndecodedCode(_) => "Code",
+			Attribute::Exceptions(_) => "Exceptions",
+			Attribute::SourceFile(_) => "SourceFile",
+			Attribute::SourceDebugExtension(_) => "SourceDebugExtension",
+			Attribute::LineNumberTable(_) => "LineNumberTable",
+			Attribute::LocalVariableTable(_) => "LocalVariableTable",
+			Attribute::LocalVariableTypeTable(_) => "LocalVariableTypeTable",
+			Attribute::InnerClasses(_) => "InnerClasses",
+			Attribute::EnclosingMethod(_) => "EnclosingMethod",
+			Attribute::NestHost(_) => "NestHost",
+			Attribute::NestMembers(_) => "NestMembers",
+			Attribute::Custom(t) => t.name(),
+			Attribute::Undecoded(t) => &t.name,
+			Attribute::Unknown(t) => &t.name
+		}
+	}
+	
+	/// The structures this attribute may be written to. Custom and unknown attributes are allowed
+	/// anywhere.
+	pub fn allowed_sources(&self) -> &'static [AttributeSource] {
+		match self {
+			Attribute::ConstantValue(_) => &[AttributeSource::Field],
+			Attribute::Signature(_) => &[AttributeSource::Class, AttributeSource::Field, AttributeSource::Method],
+			Attribute::Code(_) | Attribute::UndecodedCode(_) | Attribute::Exceptions(_) => &[AttributeSource::Method],
+			Attribute::SourceFile(_) | Attribute::SourceDebugExtension(_) | Attribute::InnerClasses(_) | Attribute::EnclosingMethod(_)
+				| Attribute::NestHost(_) | Attribute::NestMembers(_) => &[AttributeSource::Class],
+			Attribute::LineNumberTable(_) | Attribute::LocalVariableTable(_) | Attribute::LocalVariableTypeTable(_) => &[AttributeSource::Code],
+			Attribute::Custom(_) | Attribute::Undecoded(_) | Attribute::Unknown(_) => &[AttributeSource::Class, AttributeSource::Field, AttributeSource::Method, AttributeSource::Code]
+		}
+	}
+	
+	pub fn allowed_in(&self, source: AttributeSource) -> bool {
+		self.allowed_sources().contains(&source)
+	}
+	
+	/// The kind of attribute this is, by its name
+	pub fn kind(&self) -> AttributeKind {
+		match self {
+			Attribute::ConstantValue(_) => AttributeKind::ConstantValue,
+			Attribute::Signature(_) => AttributeKind::Signature,
+			Attribute::Code(_) | Attribute::UndecodedCode(_) => AttributeKind::Code,
+			Attribute::Exceptions(_) => AttributeKind::Exceptions,
+			Attribute::SourceFile(_) => AttributeKind::SourceFile,
+			Attribute::SourceDebugExtension(_) => AttributeKind::SourceDebugExtension,
+			Attribute::LineNumberTable(_) => AttributeKind::LineNumberTable,
+			Attribute::LocalVariableTable(_) => AttributeKind::LocalVariableTable,
+			Attribute::LocalVariableTypeTable(_) => AttributeKind::LocalVariableTypeTable,
+			Attribute::InnerClasses(_) => AttributeKind::InnerClasses,
+			Attribute::EnclosingMethod(_) => AttributeKind::EnclosingMethod,
+			Attribute::NestHost(_) => AttributeKind::NestHost,
+			Attribute::NestMembers(_) => AttributeKind::NestMembers,
+			Attribute::Custom(t) => AttributeKind::of(t.name()),
+			Attribute::Undecoded(t) => t.kind(),
+			Attribute::Unknown(t) => t.kind()
+		}
+	}
+	
+	/// The first class file version this attribute is defined for, see
+	/// [`minimum_version_of`](Self::minimum_version_of)
+	pub fn minimum_version(&self) -> MajorVersion {
+		self.kind().minimum_version()
+	}
+	
+	/// The first class file version the attribute of this name is defined for. Attributes the JVM
+	/// specification does not define are allowed in any version.
+	pub fn minimum_version_of(name: &str) -> MajorVersion {
+		AttributeKind::of(name).minimum_version()
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter, label_pc_map: &Option<&HashMap<LabelInsn, u32>>) -> Result<()> {
+		self.write_with_context(wtr, constant_pool, label_pc_map, &mut WriteContext::default())
+	}
+	
+	/// This attribute on its own, starting with its name and length. Constants are added to
+	/// `constant_pool` as for [`Method::write_to_vec`](crate::method::Method::write_to_vec).
+	/// Attributes that refer to labels, such as a LocalVariableTable, can only be written as part
+	/// of their Code attribute and fail here.
+	pub fn write_to_vec(&self, constant_pool: &mut ConstantPoolWriter) -> Result<Vec<u8>> {
+		let mut bytes = Vec::new();
+		self.write(&mut bytes, constant_pool, &None)?;
+		Ok(bytes)
+	}
+	
+	/// The length of the attribute contents, after its name and length, or None if it can only be
+	/// learnt by writing them. Code is only predictable when it is copied as it was parsed.
+	pub fn byte_len(&self, constant_pool: &mut ConstantPoolWriter) -> Result<Option<u64>> {
+		Ok(Some(match self {
+			Attribute::ConstantValue(t) => t.byte_len(constant_pool)?,
+			Attribute::Signature(t) => t.byte_len(constant_pool)?,
+			Attribute::Code(t) => return t.byte_len(constant_pool),
+			Attribute::Exceptions(t) => t.byte_len(constant_pool)?,
+			Attribute::SourceFile(t) => t.byte_len(constant_pool)?,
+			Attribute::SourceDebugExtension(t) => t.byte_len(constant_pool)?,
+			Attribute::LineNumberTable(t) => t.byte_len(constant_pool)?,
+			Attribute::LocalVariableTable(t) | Attribute::LocalVariableTypeTable(t) => t.byte_len(constant_pool)?,
+			Attribute::InnerClasses(t) => t.byte_len(constant_pool)?,
+			Attribute::EnclosingMethod(t) => t.byte_len(constant_pool)?,
+			Attribute::NestHost(t) => t.byte_len(constant_pool)?,
+			Attribute::NestMembers(t) => t.byte_len(constant_pool)?,
+			Attribute::Custom(t) => return Ok(t.byte_len()),
+			Attribute::UndecodedCode(t) => t.buf.len() as u64,
+			Attribute::Undecoded(t) => t.buf.len() as u64,
+			Attribute::Unknown(t) => t.byte_len(constant_pool)?
+		}))
+	}
+	
+	/// Attributes whose length is known up front are written straight to `wtr`, the rest are
+	/// written to a buffer from `context` first. Fails if the length does not fit in the u32 it is
+	/// written as, or if fewer or more bytes than predicted were written.
+	pub(crate) fn write_with_context<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter, label_pc_map: &Option<&HashMap<LabelInsn, u32>>, context: &mut WriteContext) -> Result<()> {
+		wtr.write_u16::<BigEndian>(constant_pool.utf8(self.name()))?;
+		if let Some(stats) = context.stats.as_mut() {
+			*stats.attributes.entry(self.name().to_string()).or_default() += 1;
+		}
+		if let Some(length) = self.byte_len(constant_pool)? {
+			wtr.write_u32::<BigEndian>(self.checked_length(length)?)?;
+			let mut counter = CountingWriter::new(wtr as &mut dyn Write);
+			self.write_contents(&mut counter, constant_pool, label_pc_map, context)?;
+			if counter.position() != length {
+				return Err(ParserError::attribute_length_mispredicted(self.name().to_string(), length, counter.position()));
+			}
+			return Ok(());
+		}
+		let mut buf = context.take();
+		self.write_contents(&mut buf, constant_pool, label_pc_map, context)?;
+		wtr.write_u32::<BigEndian>(self.checked_length(buf.len() as u64)?)?;
+		wtr.write_all(buf.as_slice())?;
+		context.give(buf);
+		Ok(())
+	}
+	
+	fn checked_length(&self, length: u64) -> Result<u32> {
+		u32::try_from(length).map_err(|_| ParserError::attribute_too_long(self.name().to_string(), length))
+	}
+	
+	/// Writes the contents of the attribute, without its name and length
+	fn write_contents<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter, label_pc_map: &Option<&HashMap<LabelInsn, u32>>, context: &mut WriteContext) -> Result<()> {
+		match self {
+			Attribute::ConstantValue(t) => t.write(wtr, constant_pool),
+			Attribute::Signature(t) => t.write(wtr, constant_pool),
+			Attribute::Code(t) => t.write_with_context(wtr, constant_pool, context),
+			Attribute::Exceptions(t) => t.write(wtr, constant_pool),
+			Attribute::SourceFile(t) => t.write(wtr, constant_pool),
+			Attribute::SourceDebugExtension(t) => t.write(wtr, constant_pool),
+			Attribute::LineNumberTable(t) => {
+				let label_pc_map = label_pc_map.ok_or_else(|| ParserError::none("label_pc_map"))?;
+				t.write(wtr, label_pc_map)
 			},
-			Attribute::SourceFile(t) => {
-				let mut buf: Vec<u8> = Vec::new();
-				wtr.write_u16::<BigEndian>(constant_pool.utf8("SourceFile"))?;
-				t.write(&mut buf, constant_pool)?;
-				wtr.write_u32::<BigEndian>(buf.len() as u32)?;
-				wtr.write_all(buf.as_slice())?;
+			Attribute::LocalVariableTable(t) | Attribute::LocalVariableTypeTable(t) => {
+				let label_pc_map = label_pc_map.ok_or_else(|| ParserError::none("label_pc_map"))?;
+				t.write(wtr, constant_pool, label_pc_map)
 			},
-			Attribute::LocalVariableTable(t) => {
-				let label_pc_map = label_pc_map.unwrap();
-				let mut buf: Vec<u8> = Vec::new();
-				wtr.write_u16::<BigEndian>(constant_pool.utf8("LocalVariableTable"))?;
-				t.write(&mut buf, constant_pool, label_pc_map)?;
-				wtr.write_u32::<BigEndian>(buf.len() as u32)?;
-				wtr.write_all(buf.as_slice())?;
+			Attribute::InnerClasses(t) => t.write(wtr, constant_pool),
+			Attribute::EnclosingMethod(t) => t.write(wtr, constant_pool),
+			Attribute::NestHost(t) => t.write(wtr, constant_pool),
+			Attribute::NestMembers(t) => t.write(wtr, constant_pool),
+			Attribute::Custom(t) => {
+				let mut context = AttributeWriteContext { constant_pool, label_pc_map: *label_pc_map };
+				t.parser().write(t.as_ref(), &mut context, wtr)
 			},
-			Attribute::Unknown(t) => {
-				wtr.write_u16::<BigEndian>(constant_pool.utf8(t.name.clone()))?;
-				wtr.write_u32::<BigEndian>(t.len() as u32)?;
-				t.write(wtr, constant_pool)?;
-			}
-		};
-		Ok(())
+			Attribute::UndecodedCode(t) => Ok(wtr.write_all(t.buf.as_slice())?),
+			Attribute::Undecoded(t) => Ok(wtr.write_all(t.buf.as_slice())?),
+			Attribute::Unknown(t) => t.write(wtr, constant_pool)
+		}
+	}
+}
+
+/// An attribute this library does not know about, parsed and written by a
+/// [`CustomAttributeParser`]
+///
+/// Types that are `Clone` and `PartialEq` get the [`CustomAttributeBase`] methods for free.
+pub trait CustomAttribute: CustomAttributeBase + Debug + Send + Sync {
+	/// The parser that writes this attribute
+	fn parser(&self) -> &dyn CustomAttributeParser;
+	
+	/// The name the attribute is written with
+	fn name(&self) -> &str {
+		self.parser().name()
+	}
+	
+	/// The exact number of bytes [`CustomAttributeParser::write`] produces for this attribute, if
+	/// known without writing them. Attributes that return None are written to a buffer first to
+	/// learn their length. Writing fails if a different number of bytes is written than returned
+	/// here.
+	fn byte_len(&self) -> Option<u64> {
+		None
+	}
+}
+
+/// Cloning, comparison and downcasting for boxed [`CustomAttribute`]s
+pub trait CustomAttributeBase {
+	fn clone_box(&self) -> Box<dyn CustomAttribute>;
+	
+	/// Whether the other attribute is of the same type and equal to this one
+	fn eq_box(&self, other: &dyn CustomAttribute) -> bool;
+	
+	fn as_any(&self) -> &dyn Any;
+	
+	fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl <T: CustomAttribute + Clone + PartialEq + 'static> CustomAttributeBase for T {
+	fn clone_box(&self) -> Box<dyn CustomAttribute> {
+		Box::new(self.clone())
+	}
+	
+	fn eq_box(&self, other: &dyn CustomAttribute) -> bool {
+		other.as_any().downcast_ref::<T>() == Some(self)
+	}
+	
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+	
+	fn as_any_mut(&mut self) -> &mut dyn Any {
+		self
+	}
+}
+
+impl dyn CustomAttribute + '_ {
+	pub fn downcast_ref<T: CustomAttribute + 'static>(&self) -> Option<&T> {
+		self.as_any().downcast_ref()
+	}
+	
+	pub fn downcast_mut<T: CustomAttribute + 'static>(&mut self) -> Option<&mut T> {
+		self.as_any_mut().downcast_mut()
+	}
+}
+
+impl Clone for Box<dyn CustomAttribute> {
+	fn clone(&self) -> Self {
+		self.clone_box()
+	}
+}
+
+impl PartialEq for Box<dyn CustomAttribute> {
+	fn eq(&self, other: &Self) -> bool {
+		self.eq_box(other.as_ref())
+	}
+}
+
+/// Parses the contents of attributes with a particular name
+pub trait CustomAttributeParser: Send + Sync {
+	/// The name of the attributes this parses
+	fn name(&self) -> &str;
+	
+	/// Parses the attribute contents, which are exactly as long as `buf`. Reading fewer or more
+	/// bytes is treated like a length mismatch of a built in attribute.
+	fn parse(&self, context: &mut AttributeContext, buf: &mut Cursor<Vec<u8>>) -> Result<Box<dyn CustomAttribute>>;
+	
+	/// Parses the attribute contents in place, when the class is parsed from a slice with
+	/// [`ClassFile::parse_bytes`](crate::classfile::ClassFile::parse_bytes). By default the
+	/// contents are copied for [`parse`](Self::parse).
+	fn parse_slice(&self, context: &mut AttributeContext, buf: &mut Cursor<&[u8]>) -> Result<Box<dyn CustomAttribute>> {
+		let start = (buf.position() as usize).min(buf.get_ref().len());
+		let mut owned = Cursor::new(buf.get_ref()[start..].to_vec());
+		let parsed = self.parse(context, &mut owned);
+		buf.set_position(start as u64 + owned.position());
+		parsed
+	}
+	
+	/// Writes the contents of an attribute whose [`parser`](CustomAttribute::parser) this is,
+	/// without its name and length
+	fn write(&self, attribute: &dyn CustomAttribute, context: &mut AttributeWriteContext, wtr: &mut dyn Write) -> Result<()>;
+}
+
+/// The custom attribute parsers to use while parsing, by attribute name. Built in attributes are
+/// always parsed by this library, so registering a parser for one of them has no effect where
+/// the library understands it.
+#[derive(Clone, Default)]
+pub struct AttributeRegistry {
+	parsers: Vec<Arc<dyn CustomAttributeParser>>
+}
+
+impl AttributeRegistry {
+	pub fn new() -> Self {
+		AttributeRegistry::default()
+	}
+	
+	/// Adds the parser, replacing any parser registered for the same name
+	pub fn register<P: CustomAttributeParser + 'static>(&mut self, parser: P) {
+		self.parsers.retain(|x| x.name() != parser.name());
+		self.parsers.push(Arc::new(parser));
+	}
+	
+	pub fn get(&self, name: &str) -> Option<&dyn CustomAttributeParser> {
+		self.parsers.iter().find(|x| x.name() == name).map(|x| x.as_ref())
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.parsers.is_empty()
+	}
+}
+
+impl Debug for AttributeRegistry {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.debug_list().entries(self.parsers.iter().map(|x| x.name())).finish()
+	}
+}
+
+impl PartialEq for AttributeRegistry {
+	fn eq(&self, other: &Self) -> bool {
+		self.parsers.len() == other.parsers.len() &&
+			self.parsers.iter().zip(other.parsers.iter()).all(|(a, b)| Arc::ptr_eq(a, b))
 	}
 }
 
 #[allow(dead_code)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AttributeSource {
 	Class,
 	Field,
 	Method,
 	Code
 }
+
+impl Display for AttributeSource {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			AttributeSource::Class => "class",
+			AttributeSource::Field => "field",
+			AttributeSource::Method => "method",
+			AttributeSource::Code => "Code attribute"
+		})
+	}
+}
+
+/// Generates [`AttributeKind`] from the names of the attributes and the first version defining them
+macro_rules! attribute_kinds {
+	($($kind:ident => $version:ident),*) => {
+		/// The attributes defined by the JVM specification, so that a name read from the constant
+		/// pool is only compared once
+		#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+		#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+		pub enum AttributeKind {
+			$($kind,)*
+			/// Any attribute the specification does not define
+			Other
+		}
+		
+		impl AttributeKind {
+			pub fn of(name: &str) -> AttributeKind {
+				match name {
+					$(stringify!($kind) => AttributeKind::$kind,)*
+					_ => AttributeKind::Other
+				}
+			}
+			
+			/// The name attributes of this kind have, None for [`Other`](Self::Other)
+			pub fn name(self) -> Option<&'static str> {
+				match self {
+					$(AttributeKind::$kind => Some(stringify!($kind)),)*
+					AttributeKind::Other => None
+				}
+			}
+			
+			/// The first class file version this kind is defined for, any version for
+			/// [`Other`](Self::Other)
+			pub fn minimum_version(self) -> MajorVersion {
+				match self {
+					$(AttributeKind::$kind => MajorVersion::$version,)*
+					AttributeKind::Other => MajorVersion::JDK_1_1
+				}
+			}
+		}
+	};
+}
+
+attribute_kinds! {
+	ConstantValue => JDK_1_1,
+	Code => JDK_1_1,
+	StackMapTable => JAVA_6,
+	Exceptions => JDK_1_1,
+	InnerClasses => JDK_1_1,
+	EnclosingMethod => JAVA_5,
+	Synthetic => JDK_1_1,
+	Signature => JAVA_5,
+	SourceFile => JDK_1_1,
+	SourceDebugExtension => JAVA_5,
+	LineNumberTable => JDK_1_1,
+	LocalVariableTable => JDK_1_1,
+	LocalVariableTypeTable => JAVA_5,
+	Deprecated => JDK_1_1,
+	RuntimeVisibleAnnotations => JAVA_5,
+	RuntimeInvisibleAnnotations => JAVA_5,
+	RuntimeVisibleParameterAnnotations => JAVA_5,
+	RuntimeInvisibleParameterAnnotations => JAVA_5,
+	RuntimeVisibleTypeAnnotations => JAVA_8,
+	RuntimeInvisibleTypeAnnotations => JAVA_8,
+	AnnotationDefault => JAVA_5,
+	BootstrapMethods => JAVA_7,
+	MethodParameters => JAVA_8,
+	Module => JAVA_9,
+	ModulePackages => JAVA_9,
+	ModuleMainClass => JAVA_9,
+	NestHost => JAVA_11,
+	NestMembers => JAVA_11,
+	Record => JAVA_16,
+	PermittedSubclasses => JAVA_17
+}
+
+/// Everything an attribute may need while it is parsed
+///
+/// ```
+/// use classfile::attributes::{Attributes, Attribute, AttributeContext, AttributeSource};
+/// use classfile::classfile::ParseOptions;
+/// use classfile::constantpool::ConstantPoolWriter;
+/// use classfile::version::{ClassVersion, MajorVersion};
+/// use classfile::Serializable;
+/// use std::io::Cursor;
+///
+/// // a pool holding the attribute name and the source file name
+/// let mut writer = ConstantPoolWriter::new();
+/// let name = writer.utf8("SourceFile");
+/// let file = writer.utf8("Main.java");
+/// let mut pool = Vec::new();
+/// writer.write(&mut pool)?;
+/// let constant_pool = classfile::constantpool::ConstantPool::parse(&mut Cursor::new(pool))?;
+///
+/// let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+/// let options = ParseOptions::default();
+/// let mut context = AttributeContext::new(AttributeSource::Class, &version, &constant_pool, &options);
+///
+/// // attribute count, name, length and the source file index
+/// let bytes = [0, 1, 0, name as u8, 0, 0, 0, 2, 0, file as u8];
+/// let attributes = Attributes::parse(&mut Cursor::new(&bytes[..]), &mut context)?;
+/// assert!(matches!(&attributes[0], Attribute::SourceFile(x) if x.source_file == "Main.java"));
+/// # Ok::<(), classfile::error::ParserError>(())
+/// ```
+///
+/// Attributes of a Code attribute refer to instructions by their pc. The context then carries the
+/// labels for those pcs, adding any new pcs that an attribute refers to:
+///
+/// ```
+/// use classfile::attributes::{AttributeContext, AttributeSource};
+/// use classfile::classfile::ParseOptions;
+/// use classfile::constantpool::ConstantPool;
+/// use classfile::code::PcLabels;
+/// use classfile::version::{ClassVersion, MajorVersion};
+///
+/// let (version, constant_pool, options) = (ClassVersion { major: MajorVersion::JAVA_8, minor: 0 }, ConstantPool::new(), ParseOptions::default());
+/// let mut pc_label_map = PcLabels::new();
+/// let context = AttributeContext::new(AttributeSource::Code, &version, &constant_pool, &options)
+///     .with_labels(&mut pc_label_map);
+/// assert!(context.pc_label_map.is_some());
+/// ```
+pub struct AttributeContext<'a> {
+	/// The structure the attributes belong to
+	pub source: AttributeSource,
+	pub version: &'a ClassVersion,
+	pub constant_pool: &'a ConstantPool,
+	pub options: &'a ParseOptions,
+	/// The label of each pc of the enclosing code, present only when parsing the attributes of a
+	/// Code attribute
+	pub pc_label_map: Option<&'a mut PcLabels>
+}
+
+impl <'a> AttributeContext<'a> {
+	pub fn new(source: AttributeSource, version: &'a ClassVersion, constant_pool: &'a ConstantPool, options: &'a ParseOptions) -> Self {
+		AttributeContext {
+			source,
+			version,
+			constant_pool,
+			options,
+			pc_label_map: None
+		}
+	}
+	
+	pub fn with_labels(mut self, pc_label_map: &'a mut PcLabels) -> Self {
+		self.pc_label_map = Some(pc_label_map);
+		self
+	}
+}
+
+/// What a [`CustomAttributeParser`] writes an attribute with
+pub struct AttributeWriteContext<'a> {
+	pub constant_pool: &'a mut ConstantPoolWriter,
+	/// The pc of each label of the enclosing code, present only when writing the attributes of a
+	/// Code attribute
+	pub label_pc_map: Option<&'a HashMap<LabelInsn, u32>>
+}
+
+/// Buffers reused while writing a class, so that each attribute whose length is not known up front
+/// does not need a new allocation
+#[derive(Default)]
+pub(crate) struct WriteContext {
+	buffers: Vec<Vec<u8>>,
+	/// Present when writing with [`ClassFile::write_with_stats`](crate::classfile::ClassFile::write_with_stats)
+	/// or another `_stats` writer
+	pub(crate) stats: Option<WriteStats>,
+	/// The length of the code last written, taken by its method for the stats
+	pub(crate) code_length: Option<usize>
+}
+
+impl WriteContext {
+	pub(crate) fn collecting_stats() -> Self {
+		WriteContext {
+			stats: Some(WriteStats::default()),
+			..WriteContext::default()
+		}
+	}
+	
+	/// An empty buffer, reusing one given back earlier if there is one. Attributes inside
+	/// attributes each take their own.
+	pub(crate) fn take(&mut self) -> Vec<u8> {
+		let mut buf = self.buffers.pop().unwrap_or_default();
+		buf.clear();
+		buf
+	}
+	
+	pub(crate) fn give(&mut self, buf: Vec<u8>) {
+		self.buffers.push(buf);
+	}
+	
+	/// Counts a jump written as `goto_w`
+	pub(crate) fn wide_jump(&mut self) {
+		if let Some(stats) = self.stats.as_mut() {
+			stats.wide_jumps += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Serializable;
+	use crate::version::MajorVersion;
+	
+	/// Builds a SourceFile attribute declaring `length` bytes followed by `body`, along with its pool
+	fn source_file(length: u32, body: &[u8]) -> (ConstantPool, Vec<u8>) {
+		let mut writer = ConstantPoolWriter::new();
+		let name = writer.utf8("SourceFile");
+		let file = writer.utf8("Test.java");
+		let mut pool = Vec::new();
+		writer.write(&mut pool).unwrap();
+		
+		let mut attr = Vec::new();
+		attr.write_u16::<BigEndian>(name).unwrap();
+		attr.write_u32::<BigEndian>(length).unwrap();
+		attr.write_u16::<BigEndian>(file).unwrap();
+		attr.extend_from_slice(body);
+		(ConstantPool::parse(&mut Cursor::new(pool)).unwrap(), attr)
+	}
+	
+	fn parse(constant_pool: &ConstantPool, bytes: &[u8], options: &ParseOptions) -> Result<Attribute> {
+		let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+		Attribute::parse(&mut Cursor::new(bytes), &mut AttributeContext::new(AttributeSource::Class, &version, constant_pool, options))
+	}
+	
+	#[test]
+	fn trailing_bytes_are_rejected() {
+		let (cp, bytes) = source_file(10, &[0; 8]);
+		match parse(&cp, &bytes, &ParseOptions::default()) {
+			Err(ParserError::AttributeLengthMismatch { name, expected: 10, consumed: Some(2) }) => {
+				assert_eq!(name, "SourceFile")
+			},
+			x => panic!("Expected a length mismatch, found {:?}", x)
+		}
+	}
+	
+	#[test]
+	fn over_read_is_rejected() {
+		let (cp, mut bytes) = source_file(1, &[]);
+		bytes.pop();
+		match parse(&cp, &bytes, &ParseOptions::default()) {
+			Err(ParserError::AttributeLengthMismatch { expected: 1, consumed: None, .. }) => {},
+			x => panic!("Expected a length mismatch, found {:?}", x)
+		}
+	}
+	
+	#[test]
+	fn lenient_preserves_trailing_bytes() {
+		let (cp, bytes) = source_file(10, &[1, 2, 3, 4, 5, 6, 7, 8]);
+		let options = ParseOptions {
+			lenient_attribute_length: true,
+			..Default::default()
+		};
+		let attr = parse(&cp, &bytes, &options).unwrap();
+		match &attr {
+			Attribute::Unknown(x) => {
+				assert_eq!(x.buf.as_slice(), &bytes[6..]);
+				assert_eq!(x.kind(), AttributeKind::SourceFile);
+			},
+			x => panic!("Expected an unknown attribute, found {:?}", x)
+		}
+		
+		let mut writer = ConstantPoolWriter::new();
+		let mut out = Vec::new();
+		attr.write(&mut out, &mut writer, &None).unwrap();
+		assert_eq!(&out[2..], &bytes[2..]);
+	}
+	
+	#[test]
+	fn attribute_kinds_match_names() {
+		for name in ["Code", "LineNumberTable", "PermittedSubclasses", "org.acme.Metadata", "code"] {
+			let kind = AttributeKind::of(name);
+			assert_eq!(kind.name().unwrap_or(name), name);
+			assert_eq!(UnknownAttribute::new(name.to_string(), Vec::new()).kind(), kind);
+		}
+		assert_eq!(AttributeKind::of("code"), AttributeKind::Other);
+		
+		// the kind follows a renamed attribute
+		let mut renamed = UnknownAttribute::new(String::from("org.acme.Metadata"), Vec::new());
+		renamed.name = String::from("Synthetic");
+		assert_eq!(renamed.kind(), AttributeKind::Synthetic);
+		assert_eq!(Attribute::minimum_version_of("Record"), MajorVersion::JAVA_16);
+		assert_eq!(Attribute::minimum_version_of("org.acme.Metadata"), MajorVersion::JDK_1_1);
+		
+		let attr = Attribute::LocalVariableTypeTable(LocalVariableTableAttribute::new(Vec::new()));
+		assert_eq!(attr.kind().name(), Some(attr.name()));
+	}
+	
+	#[test]
+	fn exact_length_parses() {
+		let (cp, bytes) = source_file(2, &[]);
+		match parse(&cp, &bytes, &ParseOptions::default()).unwrap() {
+			Attribute::SourceFile(x) => assert_eq!(x.source_file, "Test.java"),
+			x => panic!("Expected a SourceFile attribute, found {:?}", x)
+		}
+	}
+	
+	#[test]
+	fn invalid_debug_extension_is_kept_verbatim() {
+		let mut writer = ConstantPoolWriter::new();
+		let name = writer.utf8("SourceDebugExtension");
+		let mut pool = Vec::new();
+		writer.write(&mut pool).unwrap();
+		let cp = ConstantPool::parse(&mut Cursor::new(pool)).unwrap();
+		
+		let mut bytes = Vec::new();
+		bytes.write_u16::<BigEndian>(name).unwrap();
+		bytes.write_u32::<BigEndian>(3).unwrap();
+		bytes.extend_from_slice(&[b'a', 0xFF, b'b']);
+		match parse(&cp, &bytes, &ParseOptions::default()).unwrap() {
+			Attribute::Unknown(x) => assert_eq!(x.buf, vec![b'a', 0xFF, b'b']),
+			x => panic!("Expected an unknown attribute, found {:?}", x)
+		}
+		
+		bytes[7] = b'c';
+		match parse(&cp, &bytes, &ParseOptions::default()).unwrap() {
+			Attribute::SourceDebugExtension(x) => assert_eq!(x.debug_extension, "acb"),
+			x => panic!("Expected a SourceDebugExtension attribute, found {:?}", x)
+		}
+	}
+	
+	#[test]
+	fn misplaced_attributes_are_rejected() {
+		use crate::classfile::ClassFile;
+		use crate::field::Field;
+		use crate::method::Method;
+		use crate::access::{ClassAccessFlags, FieldAccessFlags, MethodAccessFlags};
+		use crate::types::ClassName;
+		
+		let class = || {
+			let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("Test"), Some(ClassName::from("java/lang/Object")));
+			let mut code = CodeAttribute::empty();
+			code.insns.return_void();
+			let mut method = Method::new(MethodAccessFlags::STATIC, String::from("run"), String::from("()V"));
+			method.set_code(Some(code));
+			class.add_method(method).unwrap();
+			class.add_field(Field::new(FieldAccessFlags::STATIC, String::from("value"), String::from("I"))).unwrap();
+			class
+		};
+		let misplaced = |class: ClassFile| match class.write(&mut Vec::new()).unwrap_err().root() {
+			ParserError::MisplacedAttribute { name, location } => (name.clone(), *location),
+			x => panic!("Expected a misplaced attribute, found {:?}", x)
+		};
+		let source_file = || Attribute::SourceFile(SourceFileAttribute::new(String::from("Test.java")));
+		assert!(class().write(&mut Vec::new()).is_ok());
+		
+		let mut code_on_class = class();
+		code_on_class.attributes.push(Attribute::Code(CodeAttribute::empty()));
+		assert_eq!(misplaced(code_on_class), (String::from("Code"), AttributeSource::Class));
+		
+		let mut source_file_on_method = class();
+		source_file_on_method.methods[0].attributes.push(source_file());
+		assert_eq!(misplaced(source_file_on_method), (String::from("SourceFile"), AttributeSource::Method));
+		
+		let mut exceptions_on_field = class();
+		exceptions_on_field.fields[0].attributes.push(Attribute::Exceptions(ExceptionsAttribute::new(vec![String::from("java/io/IOException")])));
+		assert_eq!(misplaced(exceptions_on_field), (String::from("Exceptions"), AttributeSource::Field));
+		
+		let mut constant_in_code = class();
+		constant_in_code.methods[0].code().unwrap().attributes.push(Attribute::ConstantValue(ConstantValueAttribute::new(ConstantValue::Int(1))));
+		assert_eq!(misplaced(constant_in_code), (String::from("ConstantValue"), AttributeSource::Code));
+		
+		let mut nested_code = class();
+		nested_code.methods[0].code().unwrap().attributes.push(Attribute::Code(CodeAttribute::empty()));
+		assert_eq!(misplaced(nested_code), (String::from("Code"), AttributeSource::Code));
+		
+		// needs the labels of a Code attribute, so used to panic
+		let mut variables_on_method = class();
+		variables_on_method.methods[0].attributes.push(Attribute::LocalVariableTable(LocalVariableTableAttribute::new(Vec::new())));
+		let err = variables_on_method.write(&mut Vec::new()).unwrap_err();
+		assert_eq!(err.root().to_string(), "LocalVariableTable attribute is not allowed on a method");
+	}
+	
+	#[test]
+	fn write_in_checks_placement() {
+		let attributes = vec![Attribute::SourceFile(SourceFileAttribute::new(String::from("Test.java")))];
+		let mut unchecked = Vec::new();
+		Attributes::write(&mut unchecked, &attributes, &mut ConstantPoolWriter::default(), None).unwrap();
+		let mut checked = Vec::new();
+		Attributes::write_in(&mut checked, &attributes, AttributeSource::Class, &mut ConstantPoolWriter::default(), None).unwrap();
+		assert_eq!(unchecked, checked);
+		
+		let err = Attributes::write_in(&mut Vec::new(), &attributes, AttributeSource::Method, &mut ConstantPoolWriter::default(), None).unwrap_err();
+		assert!(matches!(err, ParserError::MisplacedAttribute { .. }), "{}", err);
+	}
+	
+	#[test]
+	fn single_attributes_write_standalone() {
+		let mut writer = ConstantPoolWriter::default();
+		let bytes = Attribute::SourceFile(SourceFileAttribute::new(String::from("Test.java"))).write_to_vec(&mut writer).unwrap();
+		assert_eq!(bytes, vec![0, 1, 0, 0, 0, 2, 0, 2]);
+		let (_, expected) = source_file(2, &[]);
+		assert_eq!(bytes, expected);
+		
+		// pcs of local variables are only known while writing their code
+		let table = Attribute::LocalVariableTable(LocalVariableTableAttribute::new(Vec::new()));
+		let err = table.write_to_vec(&mut writer).unwrap_err();
+		assert!(matches!(err, ParserError::None("label_pc_map")), "{}", err);
+	}
+	
+	#[test]
+	fn byte_len_matches_written_length() {
+		let mut writer = ConstantPoolWriter::default();
+		let attributes = [
+			Attribute::ConstantValue(ConstantValueAttribute::new(ConstantValue::Long(1))),
+			Attribute::Signature(SignatureAttribute::new(String::from("TT;"))),
+			Attribute::Exceptions(ExceptionsAttribute::new(vec![String::from("java/io/IOException"), String::from("java/lang/Error")])),
+			Attribute::SourceFile(SourceFileAttribute::new(String::from("Test.java"))),
+			Attribute::SourceDebugExtension(SourceDebugExtensionAttribute::new(String::from("SMAP\0é€\u{1F600}"))),
+			Attribute::UndecodedCode(UndecodedCodeAttribute::new(vec![0; 12], String::new())),
+			Attribute::Unknown(UnknownAttribute::new(String::from("Custom"), vec![1, 2, 3]))
+		];
+		for attribute in attributes.iter() {
+			let length = attribute.byte_len(&mut writer).unwrap().unwrap();
+			let bytes = attribute.write_to_vec(&mut writer).unwrap();
+			assert_eq!(length, bytes.len() as u64 - 6, "{:?}", attribute);
+			assert_eq!(bytes[2..6], (length as u32).to_be_bytes());
+		}
+		
+		// new code is only measured by writing it
+		let mut code = CodeAttribute::empty();
+		code.insns.return_void();
+		let code = Attribute::Code(code);
+		assert_eq!(code.byte_len(&mut writer).unwrap(), None);
+		assert_eq!(code.write_to_vec(&mut writer).unwrap().len(), 6 + 13);
+	}
+	
+	/// An attribute claiming to be longer than an attribute can be
+	#[derive(Clone, Debug, PartialEq)]
+	struct Huge;
+	
+	impl CustomAttribute for Huge {
+		fn parser(&self) -> &dyn CustomAttributeParser {
+			&HugeParser
+		}
+		
+		fn byte_len(&self) -> Option<u64> {
+			Some(u64::from(u32::MAX) + 1)
+		}
+	}
+	
+	struct HugeParser;
+	
+	impl CustomAttributeParser for HugeParser {
+		fn name(&self) -> &str {
+			"Huge"
+		}
+		
+		fn parse(&self, _context: &mut AttributeContext, _buf: &mut Cursor<Vec<u8>>) -> Result<Box<dyn CustomAttribute>> {
+			Ok(Box::new(Huge))
+		}
+		
+		fn write(&self, _attribute: &dyn CustomAttribute, _context: &mut AttributeWriteContext, _wtr: &mut dyn Write) -> Result<()> {
+			Ok(())
+		}
+	}
+	
+	#[test]
+	fn overlong_attributes_are_rejected() {
+		let err = Attribute::Custom(Box::new(Huge)).write_to_vec(&mut ConstantPoolWriter::default()).unwrap_err();
+		assert!(matches!(&err, ParserError::AttributeTooLong { name, length: 0x1_0000_0000 } if name == "Huge"), "{}", err);
+	}
+	
+	/// An attribute that writes a different number of bytes than it predicts
+	#[derive(Clone, Debug, PartialEq)]
+	struct Mispredicted(u64);
+	
+	impl CustomAttribute for Mispredicted {
+		fn parser(&self) -> &dyn CustomAttributeParser {
+			&MispredictedParser
+		}
+		
+		fn byte_len(&self) -> Option<u64> {
+			Some(self.0)
+		}
+	}
+	
+	struct MispredictedParser;
+	
+	impl CustomAttributeParser for MispredictedParser {
+		fn name(&self) -> &str {
+			"Mispredicted"
+		}
+		
+		fn parse(&self, _context: &mut AttributeContext, buf: &mut Cursor<Vec<u8>>) -> Result<Box<dyn CustomAttribute>> {
+			buf.set_position(buf.get_ref().len() as u64);
+			Ok(Box::new(Mispredicted(buf.get_ref().len() as u64)))
+		}
+		
+		fn write(&self, _attribute: &dyn CustomAttribute, _context: &mut AttributeWriteContext, wtr: &mut dyn Write) -> Result<()> {
+			Ok(wtr.write_all(&[1, 2, 3])?)
+		}
+	}
+	
+	#[test]
+	fn mispredicted_lengths_are_rejected() {
+		let mut writer = ConstantPoolWriter::default();
+		assert_eq!(Attribute::Custom(Box::new(Mispredicted(3))).write_to_vec(&mut writer).unwrap().len(), 6 + 3);
+		for predicted in [2, 4].iter() {
+			let err = Attribute::Custom(Box::new(Mispredicted(*predicted))).write_to_vec(&mut writer).unwrap_err();
+			assert!(matches!(&err, ParserError::AttributeLengthMispredicted { name, written: 3, .. } if name == "Mispredicted"), "{}", err);
+		}
+	}
+	
+	/// A Code attribute pointing at an instruction through a label
+	#[derive(Clone, Debug, PartialEq)]
+	struct Marker(LabelInsn);
+	
+	impl CustomAttribute for Marker {
+		fn parser(&self) -> &dyn CustomAttributeParser {
+			&MarkerParser
+		}
+	}
+	
+	struct MarkerParser;
+	
+	impl CustomAttributeParser for MarkerParser {
+		fn name(&self) -> &str {
+			"Marker"
+		}
+		
+		fn parse(&self, context: &mut AttributeContext, buf: &mut Cursor<Vec<u8>>) -> Result<Box<dyn CustomAttribute>> {
+			let pc = buf.read_u16::<BigEndian>()?;
+			let labels = context.pc_label_map.as_mut().ok_or_else(|| ParserError::none("pc_label_map"))?;
+			Ok(Box::new(Marker(labels.label_at(u32::from(pc)))))
+		}
+		
+		fn write(&self, attribute: &dyn CustomAttribute, context: &mut AttributeWriteContext, wtr: &mut dyn Write) -> Result<()> {
+			let marker = attribute.downcast_ref::<Marker>().unwrap();
+			let pc = context.label_pc_map.and_then(|map| map.get(&marker.0)).ok_or_else(|| ParserError::none("label_pc_map"))?;
+			wtr.write_u16::<BigEndian>(*pc as u16)?;
+			Ok(())
+		}
+	}
+	
+	#[test]
+	fn custom_attributes_write_labels() {
+		use crate::ast::{Insn, NopInsn};
+		
+		let mut code = CodeAttribute::empty();
+		let label = code.insns.new_label();
+		code.insns.push(Insn::Nop(NopInsn::new()))
+			.push(Insn::Label(label))
+			.return_void();
+		code.attributes.push(Attribute::Custom(Box::new(Marker(label))));
+		let mut writer = ConstantPoolWriter::new();
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut writer).unwrap();
+		
+		let mut pool = Vec::new();
+		writer.write(&mut pool).unwrap();
+		let pool = <ConstantPool as Serializable>::parse(&mut Cursor::new(pool)).unwrap();
+		let mut options = ParseOptions::default();
+		options.custom_attributes.register(MarkerParser);
+		let version = ClassVersion::new_major(MajorVersion::JAVA_8);
+		let parsed = CodeAttribute::parse(&version, &pool, &mut Cursor::new(bytes), &options).unwrap();
+		let marked = match &parsed.attributes[..] {
+			[Attribute::Custom(x)] => x.downcast_ref::<Marker>().unwrap().0,
+			x => panic!("Expected a marker, found {:?}", x)
+		};
+		assert_eq!(parsed.insns.label_index(marked), Some(1));
+		
+		// on its own there is no code for the label to be in
+		let err = Attribute::Custom(Box::new(Marker(label))).write_to_vec(&mut writer).unwrap_err();
+		assert!(matches!(err, ParserError::None("label_pc_map")), "{}", err);
+	}
+}
@@ -1,12 +1,24 @@
 use std::time::Instant;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::env;
+use std::io::{self, BufReader, BufWriter, Cursor};
+use std::fmt::Debug;
+use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::{env, process};
 
-use classfile::classfile::ClassFile;
+use classfile::classfile::{ClassFile, ParseOptions};
+use classfile::constantpool::ConstantPoolWriter;
+use classfile::attributes::Attribute;
+use classfile::code::CodeAttribute;
+use classfile::error::{Result, ParserError};
 
 fn main() {
 	let args: Vec<String> = env::args().collect();
+	// Print javap-like disassembly instead of the debug representation
+	let disasm = args.iter().any(|arg| arg == "--disasm");
+	// Print what writing the class produced
+	let stats = args.iter().any(|arg| arg == "--stats");
+	let args: Vec<String> = args.into_iter().filter(|arg| arg != "--disasm" && arg != "--stats").collect();
 	
 	if let Some(file) = args.get(1) {
 		if file == "-h" {
@@ -14,24 +26,77 @@ fn main() {
 			return;
 		}
 		
+		if file == "verify" {
+			let bisect = args.iter().any(|arg| arg == "--bisect");
+			match args.iter().skip(2).find(|arg| *arg != "--bisect") {
+				Some(path) => {
+					if !verify(path, bisect) {
+						process::exit(1);
+					}
+				},
+				None => print_usage()
+			}
+			return;
+		}
+		
+		if file == "dump" {
+			let format = args.iter().position(|arg| arg == "--format").and_then(|index| args.get(index + 1));
+			let path = args.iter().enumerate().skip(2)
+				.find(|(index, arg)| *arg != "--format" && args[index - 1] != "--format")
+				.map(|(_, arg)| arg);
+			match path {
+				Some(path) => {
+					if let Err(err) = dump(path, format.map_or("json", |x| x.as_str())) {
+						eprintln!("{}", err);
+						process::exit(1);
+					}
+				},
+				None => print_usage()
+			}
+			return;
+		}
+		
+		#[cfg(feature = "jar")]
+		if file.ends_with(".jar") {
+			disassemble_jar(file, disasm);
+			return;
+		}
+		
 		// Read
 		let start = Instant::now();
 		let class = {
 			let f = File::open(file).unwrap();
 			let mut reader = BufReader::new(f);
-			ClassFile::parse(&mut reader)
+			// unchanged code is written back as it was, keeping its stack map frames
+			let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+			ClassFile::parse_with_pool(&mut reader, &options)
 		};
 		
 		let elapsed = start.elapsed();
-		println!("{:#x?}", class);
+		match &class {
+			Ok((class, _)) if disasm => println!("{}", class.disassemble()),
+			Ok((class, _)) => println!("{:#x?}", class),
+			Err(err) => println!("{:#x?}", err)
+		}
 		println!("Finished parsing {} in {:#?}", file, elapsed);
+		if let Err(err) = &class {
+			print_error(err);
+		}
 		
 		// If the user has provided an output file we will write there
-		if let Ok(class) = class {
+		if let Ok((class, constant_pool)) = class {
+			// the original pool keeps unknown attributes valid
+			let constant_pool = ConstantPoolWriter::from_pool(&constant_pool);
 			if let Some(file) = args.get(2) {
 				let f = File::create(file).unwrap();
 				let mut writer = BufWriter::new(f);
-				class.write(&mut writer).unwrap();
+				if stats {
+					println!("{:#?}", class.write_with_pool_stats(&mut writer, constant_pool).unwrap());
+				} else {
+					class.write_with_pool(&mut writer, constant_pool).unwrap();
+				}
+			} else if stats {
+				println!("{:#?}", class.write_with_pool_stats(&mut io::sink(), constant_pool).unwrap());
 			}
 		}
 	} else {
@@ -39,6 +104,222 @@ fn main() {
 	}
 }
 
+#[cfg(feature = "jar")]
+fn disassemble_jar(file: &str, disasm: bool) {
+	use classfile::jar::JarReader;
+	
+	let start = Instant::now();
+	let f = File::open(file).unwrap();
+	let mut jar = JarReader::new(BufReader::new(f)).unwrap();
+	let mut failed = 0;
+	for (name, class) in jar.classes() {
+		let class = class.map(|(class, _)| class);
+		match &class {
+			Ok(class) if disasm => println!("{}:\n{}\n", name, class.disassemble()),
+			class => println!("{}: {:#x?}", name, class)
+		}
+		if let Err(err) = &class {
+			failed += 1;
+			eprint!("{}: ", name);
+			print_error(err);
+		}
+	}
+	println!("Finished parsing {} in {:#?} ({} failed)", file, start.elapsed(), failed);
+}
+
+/// Prints the [dump](classfile::dump) of a class as JSON or YAML
+#[cfg(feature = "dump")]
+fn dump(path: &str, format: &str) -> std::result::Result<(), String> {
+	let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+	let class = ClassFile::parse(&mut Cursor::new(bytes)).map_err(|err| err.to_string())?;
+	let out = match format {
+		"json" => serde_json::to_string_pretty(&class.dump()).map_err(|err| err.to_string())?,
+		"yaml" => serde_yaml::to_string(&class.dump()).map_err(|err| err.to_string())?,
+		format => return Err(format!("Unknown format {}, expected json or yaml", format))
+	};
+	println!("{}", out);
+	Ok(())
+}
+
+#[cfg(not(feature = "dump"))]
+fn dump(_path: &str, _format: &str) -> std::result::Result<(), String> {
+	Err("Dumping needs the dump feature".to_string())
+}
+
+/// How a class fared in [`verify`]
+enum Outcome {
+	Ok,
+	ParseError(String),
+	/// Writing the class failed, or parsing it back gave a different class
+	RewriteMismatch(Option<String>)
+}
+
+/// Parses, writes and parses back every class in the directory or jar, printing the outcome for
+/// each and a summary. Returns whether every class round tripped.
+fn verify(path: &str, bisect: bool) -> bool {
+	let start = Instant::now();
+	let mut counts = [0usize; 3];
+	let mut report = |name: &str, bytes: Result<Vec<u8>>| {
+		let outcome = match bytes {
+			Ok(bytes) => verify_class(&bytes, bisect),
+			Err(err) => Outcome::ParseError(err.to_string())
+		};
+		match outcome {
+			Outcome::Ok => {
+				counts[0] += 1;
+				println!("OK {}", name);
+			},
+			Outcome::ParseError(err) => {
+				counts[1] += 1;
+				println!("PARSE_ERROR {}: {}", name, err);
+			},
+			Outcome::RewriteMismatch(difference) => {
+				counts[2] += 1;
+				match difference {
+					Some(difference) => println!("REWRITE_MISMATCH {}: {}", name, difference),
+					None => println!("REWRITE_MISMATCH {}", name)
+				}
+			}
+		}
+	};
+	
+	if path.ends_with(".jar") {
+		#[cfg(feature = "jar")]
+		{
+			use classfile::jar::JarReader;
+			
+			match File::open(path).map_err(ParserError::from).and_then(|f| JarReader::new(BufReader::new(f))) {
+				Ok(mut jar) => {
+					for (name, bytes) in jar.class_bytes() {
+						report(&name, bytes);
+					}
+				},
+				Err(err) => report(path, Err(err))
+			}
+		}
+		#[cfg(not(feature = "jar"))]
+		report(path, Err(ParserError::other("Reading jars needs the jar feature")));
+	} else if Path::new(path).is_dir() {
+		for (file, bytes) in classfile::bulk::read_dir(path) {
+			report(&file.to_string_lossy(), bytes);
+		}
+	} else {
+		report(path, std::fs::read(path).map_err(ParserError::from));
+	}
+	
+	let [ok, parse_errors, mismatches] = counts;
+	println!(
+		"Verified {} classes in {:#?}: {} OK, {} PARSE_ERROR, {} REWRITE_MISMATCH",
+		ok + parse_errors + mismatches, start.elapsed(), ok, parse_errors, mismatches
+	);
+	parse_errors == 0 && mismatches == 0
+}
+
+fn verify_class(bytes: &[u8], bisect: bool) -> Outcome {
+	// unchanged code is copied, so that its StackMapTable stays valid
+	let options = ParseOptions {
+		keep_original_code: true,
+		..ParseOptions::default()
+	};
+	let parsed = catch_panic(|| ClassFile::parse_with_pool(&mut Cursor::new(bytes), &options));
+	let (class, constant_pool) = match parsed {
+		Ok(Ok(x)) => x,
+		Ok(Err(err)) => return Outcome::ParseError(err.to_string()),
+		Err(panic) => return Outcome::ParseError(panic)
+	};
+	let rewritten = catch_panic(|| {
+		let mut written = Vec::new();
+		// the original pool keeps unknown attributes valid
+		class.write_with_pool(&mut written, ConstantPoolWriter::from_pool(&constant_pool))
+			.map_err(|err| format!("write failed: {}", err))?;
+		ClassFile::parse(&mut Cursor::new(written))
+			.map_err(|err| format!("parsing the written class failed: {}", err))
+	});
+	match rewritten {
+		Ok(Ok(reparsed)) if reparsed == class => Outcome::Ok,
+		Ok(Ok(reparsed)) => Outcome::RewriteMismatch(if bisect { first_difference(&class, &reparsed) } else { None }),
+		Ok(Err(err)) | Err(err) => Outcome::RewriteMismatch(Some(err))
+	}
+}
+
+/// Runs `f`, turning a panic into its message so that one bad class does not end the run
+fn catch_panic<T, F: FnOnce() -> T>(f: F) -> std::result::Result<T, String> {
+	panic::catch_unwind(AssertUnwindSafe(f)).map_err(|err| {
+		let message = err.downcast_ref::<&str>().map(|x| x.to_string())
+			.or_else(|| err.downcast_ref::<String>().cloned())
+			.unwrap_or_default();
+		format!("panicked: {}", message)
+	})
+}
+
+fn differ<T: PartialEq + Debug>(context: &str, a: &T, b: &T) -> Option<String> {
+	if a != b {
+		Some(format!("{}: {:?} != {:?}", context, a, b))
+	} else {
+		None
+	}
+}
+
+/// Describes the first member or attribute that differs between the two classes
+fn first_difference(a: &ClassFile, b: &ClassFile) -> Option<String> {
+	differ("version", &a.version(), &b.version())
+		.or_else(|| differ("access_flags", &a.access_flags(), &b.access_flags()))
+		.or_else(|| differ("this_class", a.this_class(), b.this_class()))
+		.or_else(|| differ("super_class", &a.super_class(), &b.super_class()))
+		.or_else(|| differ("interfaces", &a.interfaces(), &b.interfaces()))
+		.or_else(|| differ("fields.len()", &a.fields().len(), &b.fields().len()))
+		.or_else(|| a.fields().iter().zip(b.fields().iter()).enumerate().find_map(|(i, (a, b))| {
+			let context = format!("fields[{}] {} {}", i, a.name, a.descriptor);
+			differ(&context, &(&a.access_flags, &a.name, &a.descriptor), &(&b.access_flags, &b.name, &b.descriptor))
+				.or_else(|| attributes_difference(&context, &a.attributes, &b.attributes))
+		}))
+		.or_else(|| differ("methods.len()", &a.methods().len(), &b.methods().len()))
+		.or_else(|| a.methods().iter().zip(b.methods().iter()).enumerate().find_map(|(i, (a, b))| {
+			let context = format!("methods[{}] {}{}", i, a.name, a.descriptor);
+			differ(&context, &(&a.access_flags, &a.name, &a.descriptor), &(&b.access_flags, &b.name, &b.descriptor))
+				.or_else(|| attributes_difference(&context, &a.attributes, &b.attributes))
+		}))
+		.or_else(|| attributes_difference("class", a.attributes(), b.attributes()))
+}
+
+fn attributes_difference(context: &str, a: &[Attribute], b: &[Attribute]) -> Option<String> {
+	differ(&format!("{} attributes.len()", context), &a.len(), &b.len())
+		.or_else(|| a.iter().zip(b.iter()).enumerate().find_map(|(i, pair)| match pair {
+			(Attribute::Code(a), Attribute::Code(b)) => code_difference(&format!("{} attributes[{}] Code", context, i), a, b),
+			(a, b) => differ(&format!("{} attributes[{}]", context, i), a, b)
+		}))
+}
+
+fn code_difference(context: &str, a: &CodeAttribute, b: &CodeAttribute) -> Option<String> {
+	differ(&format!("{} max_stack", context), &a.max_stack, &b.max_stack)
+		.or_else(|| differ(&format!("{} max_locals", context), &a.max_locals, &b.max_locals))
+		.or_else(|| a.insns.iter().zip(b.insns.iter()).enumerate().find_map(|(i, (a, b))| {
+			if a != b {
+				Some(format!("{} insns[{}]: {} != {}", context, i, a, b))
+			} else {
+				None
+			}
+		}))
+		.or_else(|| differ(&format!("{} insns.len()", context), &a.insns.len(), &b.insns.len()))
+		.or_else(|| differ(&format!("{} exceptions", context), &a.exceptions, &b.exceptions))
+		.or_else(|| attributes_difference(context, &a.attributes, &b.attributes))
+}
+
+fn print_error(err: &ParserError) {
+	match err.offset() {
+		Some(offset) => eprintln!("Error at offset {:#x} in {}: {}", offset, err.breadcrumb(), err.root()),
+		None if err.contexts().is_empty() => eprintln!("Error: {}", err.root()),
+		None => eprintln!("Error in {}: {}", err.breadcrumb(), err.root())
+	}
+	if let Some(class) = err.describe_class() {
+		eprintln!("  while parsing {}", class);
+	}
+}
+
 fn print_usage() {
-	eprintln!("Usage: ./dissasembler classFileIn.class (classFileOut.class)");
+	eprintln!("Usage: ./dissasembler [--disasm] [--stats] classFileIn.class (classFileOut.class)");
+	#[cfg(feature = "jar")]
+	eprintln!("       ./dissasembler [--disasm] jarIn.jar");
+	eprintln!("       ./dissasembler verify [--bisect] classDirOrJar");
+	eprintln!("       ./dissasembler dump [--format json|yaml] classFileIn.class");
 }
@@ -0,0 +1,188 @@
+//! Parsing every class file in a directory
+//!
+//! All of the parsed types are `Send + Sync`, so classes may be parsed and inspected across threads.
+
+use crate::classfile::ClassFile;
+use crate::constantpool::ConstantPool;
+use crate::code::CodeAttribute;
+use crate::ast::Insn;
+use crate::error::{Result, ParserError};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+// Fails to compile if any of these stop being thread safe
+const _: fn() = || {
+	fn assert_send_sync<T: Send + Sync>() {}
+	assert_send_sync::<ClassFile>();
+	assert_send_sync::<ConstantPool>();
+	assert_send_sync::<CodeAttribute>();
+	assert_send_sync::<Insn>();
+	assert_send_sync::<ParserError>();
+};
+
+/// Lazily parses every `.class` file under the directory, recursing into subdirectories in
+/// name order.
+///
+/// A file or directory that cannot be read is yielded as an error without ending iteration. A
+/// path to a single `.class` file yields just that file, and one to any other file yields nothing.
+pub fn parse_dir<P: AsRef<Path>>(path: P) -> impl Iterator<Item = (PathBuf, Result<ClassFile>)> {
+	class_files(path.as_ref()).map(|(path, file)| {
+		let class = file.and_then(|_| parse_file(&path));
+		(path, class)
+	})
+}
+
+/// Parses every `.class` file under the directory on the rayon thread pool, in the same order as
+/// [`parse_dir`]
+#[cfg(feature = "rayon")]
+pub fn parse_dir_parallel<P: AsRef<Path>>(path: P) -> Vec<(PathBuf, Result<ClassFile>)> {
+	use rayon::prelude::*;
+	
+	let files: Vec<(PathBuf, Result<()>)> = class_files(path.as_ref()).collect();
+	files.into_par_iter()
+		.map(|(path, file)| {
+			let class = file.and_then(|_| parse_file(&path));
+			(path, class)
+		})
+		.collect()
+}
+
+/// Reads every `.class` file under the directory without parsing it, in the same order as
+/// [`parse_dir`]
+pub fn read_dir<P: AsRef<Path>>(path: P) -> impl Iterator<Item = (PathBuf, Result<Vec<u8>>)> {
+	class_files(path.as_ref()).map(|(path, file)| {
+		let bytes = file.and_then(|_| Ok(fs::read(&path)?));
+		(path, bytes)
+	})
+}
+
+fn parse_file(path: &Path) -> Result<ClassFile> {
+	let mut rdr = BufReader::new(File::open(path)?);
+	ClassFile::parse(&mut rdr)
+}
+
+/// Walks the directory depth first, yielding class files and any directories that failed to list
+fn class_files(root: &Path) -> impl Iterator<Item = (PathBuf, Result<()>)> {
+	let mut pending = vec![root.to_path_buf()];
+	if root.is_file() && root.extension() != Some("class".as_ref()) {
+		pending.clear();
+	}
+	std::iter::from_fn(move || {
+		let path = pending.pop()?;
+		if !path.is_dir() {
+			return Some(Some((path, Ok(()))));
+		}
+		let entries = fs::read_dir(&path)
+			.and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<std::io::Result<Vec<PathBuf>>>());
+		match entries {
+			Ok(mut entries) => {
+				entries.retain(|entry| entry.is_dir() || entry.extension() == Some("class".as_ref()));
+				// Reversed so that they are popped in name order
+				entries.sort_unstable_by(|a, b| b.cmp(a));
+				pending.extend(entries);
+				Some(None)
+			},
+			Err(err) => Some(Some((path, Err(err.into()))))
+		}
+	}).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::version::{ClassVersion, MajorVersion};
+	use crate::types::ClassName;
+	use crate::access::ClassAccessFlags;
+	use std::fs;
+	
+	fn write_class(path: &Path, name: &str) {
+		let class = ClassFile {
+			magic: 0xCAFEBABE,
+			version: ClassVersion { major: MajorVersion::JAVA_8, minor: 0 },
+			access_flags: ClassAccessFlags::PUBLIC,
+			this_class: ClassName::from(name),
+			super_class: Some(ClassName::from("java/lang/Object")),
+			interfaces: Vec::new(),
+			fields: Vec::new(),
+			methods: Vec::new(),
+			attributes: Vec::new(),
+			original_indices: None,
+			partial: None,
+			modified: Default::default()
+		};
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		fs::write(path, bytes).unwrap();
+	}
+	
+	fn fixture(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("classfile-rs-bulk-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(dir.join("a/b")).unwrap();
+		write_class(&dir.join("Z.class"), "Z");
+		write_class(&dir.join("a/A.class"), "a/A");
+		write_class(&dir.join("a/b/B.class"), "a/b/B");
+		fs::write(dir.join("a/Broken.class"), [0xCA, 0xFE]).unwrap();
+		fs::write(dir.join("a/readme.txt"), "not a class").unwrap();
+		dir
+	}
+	
+	fn summarise(dir: &Path, results: Vec<(PathBuf, Result<ClassFile>)>) -> Vec<(String, Option<String>)> {
+		results.into_iter()
+			.map(|(path, class)| {
+				let path = path.strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/");
+				(path, class.ok().map(|class| class.this_class.into_string()))
+			})
+			.collect()
+	}
+	
+	fn expected() -> Vec<(String, Option<String>)> {
+		vec![
+			(String::from("Z.class"), Some(String::from("Z"))),
+			(String::from("a/A.class"), Some(String::from("a/A"))),
+			(String::from("a/Broken.class"), None),
+			(String::from("a/b/B.class"), Some(String::from("a/b/B")))
+		]
+	}
+	
+	#[test]
+	fn parse_dir_in_order() {
+		let dir = fixture("sequential");
+		let results = parse_dir(&dir).collect();
+		assert_eq!(summarise(&dir, results), expected());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+	
+	#[test]
+	fn file_roots_are_filtered_like_entries() {
+		let dir = fixture("file-root");
+		assert_eq!(parse_dir(dir.join("a/readme.txt")).count(), 0);
+		let results: Vec<_> = parse_dir(dir.join("Z.class")).collect();
+		assert_eq!(results.len(), 1);
+		assert_eq!(results[0].1.as_ref().unwrap().this_class, "Z");
+		fs::remove_dir_all(&dir).unwrap();
+	}
+	
+	#[test]
+	fn read_dir_matches_parse_dir() {
+		let dir = fixture("raw");
+		let results = read_dir(&dir)
+			.map(|(path, bytes)| {
+				let class = bytes.and_then(|bytes| ClassFile::parse(&mut std::io::Cursor::new(bytes)));
+				(path, class)
+			})
+			.collect();
+		assert_eq!(summarise(&dir, results), expected());
+		assert_eq!(read_dir(&dir).nth(2).unwrap().1.unwrap(), [0xCA, 0xFE]);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+	
+	#[test]
+	#[cfg(feature = "rayon")]
+	fn parallel_matches_sequential() {
+		let dir = fixture("parallel");
+		assert_eq!(summarise(&dir, parse_dir_parallel(&dir)), expected());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
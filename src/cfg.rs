@@ -0,0 +1,387 @@
+use crate::ast::{Insn, LabelInsn};
+use crate::code::CodeAttribute;
+use crate::error::{Result, ParserError};
+use std::collections::{HashMap, BTreeSet};
+
+/// Index of a block within a [`ControlFlowGraph`]
+pub type BlockId = usize;
+
+/// A maximal run of instructions that is only entered at the top and only left at the bottom
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+	/// Index of the first instruction of this block in the [`InsnList`](crate::insnlist::InsnList)
+	pub start: usize,
+	/// Index one past the last instruction of this block
+	pub end: usize
+}
+
+impl BasicBlock {
+	pub fn len(&self) -> usize {
+		self.end - self.start
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.start == self.end
+	}
+	
+	pub fn contains(&self, index: usize) -> bool {
+		index >= self.start && index < self.end
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+	/// Execution continues into the next block
+	Fallthrough,
+	/// A goto, or a conditional jump being taken
+	Jump,
+	/// A switch case with the given key
+	SwitchCase(i32),
+	/// The default branch of a switch
+	SwitchDefault,
+	/// An exception thrown in the source block is caught by the handler at this index in
+	/// [`CodeAttribute::exceptions`]
+	Exception(usize)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+	pub from: BlockId,
+	pub to: BlockId,
+	pub kind: EdgeKind
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlFlowGraph {
+	blocks: Vec<BasicBlock>,
+	edges: Vec<Edge>,
+	/// Indexes into `edges` leaving each block
+	successors: Vec<Vec<usize>>,
+	/// Indexes into `edges` entering each block
+	predecessors: Vec<Vec<usize>>
+}
+
+impl ControlFlowGraph {
+	pub fn build(code: &CodeAttribute) -> Result<Self> {
+		let insns = &code.insns.insns;
+		
+		let mut label_indexes: HashMap<LabelInsn, usize> = HashMap::new();
+		for (i, insn) in insns.iter().enumerate() {
+			if let Insn::Label(x) = insn {
+				label_indexes.insert(*x, i);
+			}
+		}
+		let label_index = |label: &LabelInsn| -> Result<usize> {
+			label_indexes.get(label).copied().ok_or_else(|| ParserError::unplaced_label(code.insns.describe_label(*label)))
+		};
+		
+		// find the instructions that start a new block
+		let mut leaders: BTreeSet<usize> = BTreeSet::new();
+		if !insns.is_empty() {
+			leaders.insert(0);
+		}
+		for (i, insn) in insns.iter().enumerate() {
+			let targets = insn.jump_targets();
+			if !targets.is_empty() || insn.is_terminal() {
+				leaders.insert(i + 1);
+			}
+			for target in targets.iter() {
+				leaders.insert(label_index(target)?);
+			}
+		}
+		for handler in code.exceptions.iter() {
+			leaders.insert(label_index(&handler.start)?);
+			leaders.insert(label_index(&handler.end)?);
+			leaders.insert(label_index(&handler.handler)?);
+		}
+		leaders.retain(|i| *i < insns.len());
+		
+		let leaders: Vec<usize> = leaders.into_iter().collect();
+		let blocks: Vec<BasicBlock> = leaders.iter().enumerate()
+			.map(|(i, start)| BasicBlock {
+				start: *start,
+				end: leaders.get(i + 1).copied().unwrap_or(insns.len())
+			})
+			.collect();
+		
+		let mut cfg = ControlFlowGraph {
+			successors: vec![Vec::new(); blocks.len()],
+			predecessors: vec![Vec::new(); blocks.len()],
+			blocks,
+			edges: Vec::new()
+		};
+		
+		for id in 0..cfg.blocks.len() {
+			let last = &insns[cfg.blocks[id].end - 1];
+			match last {
+				Insn::Jump(x) => {
+					let to = cfg.block_at(label_index(&x.jump_to)?);
+					cfg.add_edge(id, to, EdgeKind::Jump);
+				},
+				Insn::ConditionalJump(x) => {
+					let to = cfg.block_at(label_index(&x.jump_to)?);
+					cfg.add_edge(id, to, EdgeKind::Jump);
+				},
+				Insn::LookupSwitch(x) => {
+					for (key, label) in x.cases.iter() {
+						let to = cfg.block_at(label_index(label)?);
+						cfg.add_edge(id, to, EdgeKind::SwitchCase(*key));
+					}
+					let to = cfg.block_at(label_index(&x.default)?);
+					cfg.add_edge(id, to, EdgeKind::SwitchDefault);
+				},
+				Insn::TableSwitch(x) => {
+					for (i, label) in x.cases.iter().enumerate() {
+						let to = cfg.block_at(label_index(label)?);
+						cfg.add_edge(id, to, EdgeKind::SwitchCase(x.low.wrapping_add(i as i32)));
+					}
+					let to = cfg.block_at(label_index(&x.default)?);
+					cfg.add_edge(id, to, EdgeKind::SwitchDefault);
+				},
+				_ => {}
+			}
+			if !last.is_terminal() && id + 1 < cfg.blocks.len() {
+				cfg.add_edge(id, id + 1, EdgeKind::Fallthrough);
+			}
+		}
+		
+		for (i, handler) in code.exceptions.iter().enumerate() {
+			let start = label_index(&handler.start)?;
+			let end = label_index(&handler.end)?;
+			let to = cfg.block_at(label_index(&handler.handler)?);
+			for from in 0..cfg.blocks.len() {
+				let block = cfg.blocks[from];
+				if block.start >= start && block.end <= end {
+					cfg.add_edge(from, to, EdgeKind::Exception(i));
+				}
+			}
+		}
+		
+		Ok(cfg)
+	}
+	
+	fn add_edge(&mut self, from: BlockId, to: BlockId, kind: EdgeKind) {
+		let index = self.edges.len();
+		self.edges.push(Edge { from, to, kind });
+		self.successors[from].push(index);
+		self.predecessors[to].push(index);
+	}
+	
+	/// The block containing an instruction index that is known to be in range
+	fn block_at(&self, index: usize) -> BlockId {
+		self.block_of(index).expect("instruction index out of range")
+	}
+	
+	pub fn blocks(&self) -> &[BasicBlock] {
+		&self.blocks
+	}
+	
+	pub fn block(&self, id: BlockId) -> &BasicBlock {
+		&self.blocks[id]
+	}
+	
+	pub fn edges(&self) -> &[Edge] {
+		&self.edges
+	}
+	
+	/// The block execution starts in, None if the code is empty
+	pub fn entry(&self) -> Option<BlockId> {
+		if self.blocks.is_empty() {
+			None
+		} else {
+			Some(0)
+		}
+	}
+	
+	/// The block containing the instruction at the given index in the instruction list
+	pub fn block_of(&self, index: usize) -> Option<BlockId> {
+		let id = self.blocks.partition_point(|block| block.end <= index);
+		if id < self.blocks.len() && self.blocks[id].contains(index) {
+			Some(id)
+		} else {
+			None
+		}
+	}
+	
+	pub fn successors(&self, id: BlockId) -> impl Iterator<Item = &Edge> {
+		self.successors[id].iter().map(move |i| &self.edges[*i])
+	}
+	
+	pub fn predecessors(&self, id: BlockId) -> impl Iterator<Item = &Edge> {
+		self.predecessors[id].iter().map(move |i| &self.edges[*i])
+	}
+	
+	/// The blocks reachable from the entry, each appearing before its successors unless the edge
+	/// between them is a back edge
+	pub fn reverse_post_order(&self) -> Vec<BlockId> {
+		let mut order = Vec::with_capacity(self.blocks.len());
+		let entry = match self.entry() {
+			Some(x) => x,
+			None => return order
+		};
+		
+		let mut visited = vec![false; self.blocks.len()];
+		// (block, index of the next successor edge to explore)
+		let mut stack: Vec<(BlockId, usize)> = vec![(entry, 0)];
+		visited[entry] = true;
+		while let Some((id, next)) = stack.pop() {
+			if let Some(edge) = self.successors[id].get(next) {
+				stack.push((id, next + 1));
+				let to = self.edges[*edge].to;
+				if !visited[to] {
+					visited[to] = true;
+					stack.push((to, 0));
+				}
+			} else {
+				order.push(id);
+			}
+		}
+		order.reverse();
+		order
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::ast::*;
+	use crate::types::ClassName;
+	use crate::code::ExceptionHandler;
+	use crate::insnlist::InsnList;
+	
+	fn code(insns: InsnList, exceptions: Vec<ExceptionHandler>) -> CodeAttribute {
+		let mut code = CodeAttribute::empty();
+		code.insns = insns;
+		code.exceptions = exceptions;
+		code
+	}
+	
+	fn successors(cfg: &ControlFlowGraph, id: BlockId) -> Vec<(BlockId, EdgeKind)> {
+		cfg.successors(id).map(|e| (e.to, e.kind)).collect()
+	}
+	
+	fn iconst(x: i32) -> Insn {
+		Insn::Ldc(LdcInsn::new(LdcType::Int(x)))
+	}
+	
+	#[test]
+	fn loop_has_back_edge() {
+		// int i = 0; while (i == 0) { i++; } return;
+		let mut list = InsnList::new();
+		let head = list.new_label();
+		let exit = list.new_label();
+		list.insns = vec![
+			iconst(0),
+			Insn::LocalStore(LocalStoreInsn::new(OpType::Int, 1)),
+			Insn::Label(head),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 1)),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntNotEqZero, exit)),
+			Insn::IncrementInt(IncrementIntInsn::new(1, 1)),
+			Insn::Jump(JumpInsn::new(head)),
+			Insn::Label(exit),
+			Insn::Return(ReturnInsn::new(ReturnType::Void)),
+		];
+		let cfg = ControlFlowGraph::build(&code(list, Vec::new())).unwrap();
+		
+		assert_eq!(cfg.blocks(), &[
+			BasicBlock { start: 0, end: 2 },
+			BasicBlock { start: 2, end: 5 },
+			BasicBlock { start: 5, end: 7 },
+			BasicBlock { start: 7, end: 9 },
+		]);
+		assert_eq!(successors(&cfg, 0), vec![(1, EdgeKind::Fallthrough)]);
+		assert_eq!(successors(&cfg, 1), vec![(3, EdgeKind::Jump), (2, EdgeKind::Fallthrough)]);
+		assert_eq!(successors(&cfg, 2), vec![(1, EdgeKind::Jump)]);
+		assert!(successors(&cfg, 3).is_empty());
+		
+		let mut preds: Vec<BlockId> = cfg.predecessors(1).map(|e| e.from).collect();
+		preds.sort_unstable();
+		assert_eq!(preds, vec![0, 2]);
+		
+		let rpo = cfg.reverse_post_order();
+		assert_eq!(rpo[0], 0);
+		assert_eq!(rpo[1], 1);
+		assert_eq!(rpo.len(), 4);
+	}
+	
+	#[test]
+	fn try_catch_adds_exception_edges() {
+		let mut list = InsnList::new();
+		let start = list.new_label();
+		let end = list.new_label();
+		let handler = list.new_label();
+		let after = list.new_label();
+		list.insns = vec![
+			Insn::Label(start),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Static, ClassName::from("A"), String::from("a"), String::from("()V"), false))),
+			Insn::Label(end),
+			Insn::Jump(JumpInsn::new(after)),
+			Insn::Label(handler),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::Label(after),
+			Insn::Return(ReturnInsn::new(ReturnType::Void)),
+		];
+		let exceptions = vec![ExceptionHandler {
+			start,
+			end,
+			handler,
+			catch_type: Some(String::from("java/lang/Exception"))
+		}];
+		let cfg = ControlFlowGraph::build(&code(list, exceptions)).unwrap();
+		
+		assert_eq!(cfg.blocks().len(), 4);
+		assert_eq!(successors(&cfg, 0), vec![(1, EdgeKind::Fallthrough), (2, EdgeKind::Exception(0))]);
+		assert_eq!(successors(&cfg, 1), vec![(3, EdgeKind::Jump)]);
+		assert_eq!(successors(&cfg, 2), vec![(3, EdgeKind::Fallthrough)]);
+		// only the protected block can reach the handler
+		let preds: Vec<Edge> = cfg.predecessors(2).copied().collect();
+		assert_eq!(preds, vec![Edge { from: 0, to: 2, kind: EdgeKind::Exception(0) }]);
+		
+		let rpo = cfg.reverse_post_order();
+		assert_eq!(rpo.len(), 4);
+		assert_eq!(*rpo.last().unwrap(), 3);
+	}
+	
+	#[test]
+	fn switch_cases() {
+		let mut list = InsnList::new();
+		let one = list.new_label();
+		let two = list.new_label();
+		let default = list.new_label();
+		list.insns = vec![
+			iconst(1),
+			Insn::TableSwitch(TableSwitchInsn::new(default, 1, vec![one, two])),
+			Insn::Label(one),
+			Insn::Return(ReturnInsn::new(ReturnType::Void)),
+			Insn::Label(two),
+			Insn::Return(ReturnInsn::new(ReturnType::Void)),
+			Insn::Label(default),
+			// unreachable
+			Insn::Nop(NopInsn::new()),
+			Insn::Return(ReturnInsn::new(ReturnType::Void)),
+		];
+		let mut cfg = ControlFlowGraph::build(&code(list.clone(), Vec::new())).unwrap();
+		assert_eq!(successors(&cfg, 0), vec![
+			(1, EdgeKind::SwitchCase(1)),
+			(2, EdgeKind::SwitchCase(2)),
+			(3, EdgeKind::SwitchDefault)
+		]);
+		assert_eq!(cfg.block_of(7), Some(3));
+		assert_eq!(cfg.block_of(9), None);
+		
+		let mut lookup = LookupSwitchInsn::new(default);
+		lookup.cases.insert(-5, two);
+		list.insns[1] = Insn::LookupSwitch(lookup);
+		cfg = ControlFlowGraph::build(&code(list, Vec::new())).unwrap();
+		assert_eq!(successors(&cfg, 0), vec![(2, EdgeKind::SwitchCase(-5)), (3, EdgeKind::SwitchDefault)]);
+		assert_eq!(cfg.reverse_post_order(), vec![0, 3, 2]);
+	}
+	
+	#[test]
+	fn unmapped_jump_fails() {
+		let mut list = InsnList::new();
+		let missing = list.new_label();
+		list.insns = vec![Insn::Jump(JumpInsn::new(missing))];
+		assert!(ControlFlowGraph::build(&code(list, Vec::new())).is_err());
+	}
+}
@@ -1,55 +1,310 @@
 use std::io::{Write, Read, Cursor};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
 use crate::Serializable;
-use crate::version::ClassVersion;
-use crate::constantpool::{ConstantPool, ConstantPoolWriter};
-use crate::access::ClassAccessFlags;
+use crate::version::{ClassVersion, MajorVersion};
+use crate::constantpool::{ConstantPool, ConstantPoolWriter, ConstantType, ClassIndices, CPIndex};
+use crate::access::{ClassAccessFlags, FieldAccessFlags, MethodAccessFlags};
 use crate::field::{Field, Fields};
 use crate::method::{Methods, Method};
-use crate::error::{Result, ParserError};
-use crate::attributes::{Attribute, Attributes, AttributeSource};
+use crate::error::{Result, ParserError, ErrorContext};
+use crate::attributes::{Attribute, Attributes, AttributeContext, AttributeKind, AttributeSource, AttributeRegistry, ConstantValueAttribute, ConstantValue, UnknownAttribute, WriteContext};
+use crate::ast::{Insn, LdcInsn, LdcType};
+use crate::utils::{ClassRead, CountingReader, CountingWriter};
+use crate::types::ClassName;
+use crate::refs::MemberRefs;
+use crate::path::{Path, MemberPath, AttrPath};
+use std::collections::{BTreeMap, HashSet};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassFile {
 	/// 0xCAFEBABE
-	pub magic: u32,
-	pub version: ClassVersion,
-	pub access_flags: ClassAccessFlags,
-	pub this_class: String,
+	pub(crate) magic: u32,
+	pub(crate) version: ClassVersion,
+	pub(crate) access_flags: ClassAccessFlags,
+	pub(crate) this_class: ClassName,
 	/// Can be None for example for java/lang/Object
-	pub super_class: Option<String>,
-	pub interfaces: Vec<String>,
-	pub fields: Vec<Field>,
-	pub methods: Vec<Method>,
-	pub attributes: Vec<Attribute>
+	pub(crate) super_class: Option<ClassName>,
+	pub(crate) interfaces: Vec<ClassName>,
+	pub(crate) fields: Vec<Field>,
+	pub(crate) methods: Vec<Method>,
+	/// Written back in this order, as are the attributes of members and code
+	pub(crate) attributes: Vec<Attribute>,
+	/// Where this_class, super_class and interfaces were in the constant pool, when parsed with
+	/// [ParseOptions::original_indices]
+	pub(crate) original_indices: Option<ClassIndices>,
+	/// Where parsing stopped, for a class parsed with [ParseOptions::lenient_pool]. See
+	/// [`is_partial`](Self::is_partial).
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub(crate) partial: Option<PartialParse>,
+	/// Whether the class changed since it was parsed, see [`is_modified`](Self::is_modified)
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub(crate) modified: Modified
+}
+
+/// Ignores the original indices and whether the class was modified, so that a parsed class equals
+/// the same class built by hand
+impl PartialEq for ClassFile {
+	fn eq(&self, other: &Self) -> bool {
+		self.magic == other.magic && self.version == other.version && self.access_flags == other.access_flags
+			&& self.this_class == other.this_class && self.super_class == other.super_class && self.interfaces == other.interfaces
+			&& self.fields == other.fields && self.methods == other.methods && self.attributes == other.attributes
+			&& self.partial == other.partial
+	}
+}
+
+/// Set once a class is changed through its methods. Always equal to any other, so that a changed
+/// class equals the same class parsed again. Defaults to modified, as a class that was not parsed
+/// has not been written either.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Modified(bool);
+
+impl Default for Modified {
+	fn default() -> Self {
+		Modified(true)
+	}
+}
+
+impl PartialEq for Modified {
+	fn eq(&self, _other: &Self) -> bool {
+		true
+	}
+}
+
+/// The member a string constant passed to [`ClassFile::map_string_constants`] belongs to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StringContext<'a> {
+	/// Loaded by an `ldc` in the code of this method
+	Method {
+		name: &'a str,
+		descriptor: &'a str
+	},
+	/// The ConstantValue of this field
+	Field {
+		name: &'a str,
+		descriptor: &'a str
+	}
+}
+
+/// Controls how strictly a class file is parsed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParseOptions {
+	/// Reject Utf8 constants that are not valid modified UTF-8 instead of decoding them lossily
+	pub strict_utf8: bool,
+	/// Reject fields and methods whose descriptors are malformed
+	pub strict_descriptors: bool,
+	/// Keep known attributes whose contents do not match their declared length as
+	/// [Attribute::Unknown](crate::attributes::Attribute::Unknown) instead of failing, so that they
+	/// are written back byte for byte
+	pub lenient_attribute_length: bool,
+	/// Keep a second Code, Signature or Exceptions attribute on a member, or a Code attribute on an
+	/// abstract or native method, as [Attribute::Unknown](crate::attributes::Attribute::Unknown)
+	/// instead of failing
+	pub lenient_member_attributes: bool,
+	/// Keep the Code attribute of a method that fails to parse as
+	/// [Attribute::UndecodedCode](crate::attributes::Attribute::UndecodedCode) instead of failing,
+	/// see [ClassFile::parse_warnings]
+	pub lenient_code: bool,
+	/// Keep attributes other than Code that refer to a constant of the wrong type, such as a
+	/// SourceFile naming a String constant, as [Attribute::Undecoded](crate::attributes::Attribute::Undecoded)
+	/// instead of failing, see [ClassFile::parse_warnings]. Other constant pool errors still fail.
+	pub lenient_constant_types: bool,
+	/// Keep the constants before one with an unrecognised tag instead of failing. The class is
+	/// then only partly parsed, see [ClassFile::is_partial].
+	pub lenient_pool: bool,
+	/// Record the pc each instruction was parsed from, see
+	/// [CodeAttribute::original_pcs](crate::code::CodeAttribute::original_pcs)
+	pub original_pcs: bool,
+	/// Record the constant pool indices names were parsed from, on
+	/// [ClassFile::original_indices],
+	/// [Method::original_indices](crate::method::Method::original_indices),
+	/// [Field::original_indices](crate::field::Field::original_indices) and
+	/// [CodeAttribute::original_indices](crate::code::CodeAttribute::original_indices). Writing
+	/// and comparison ignore them.
+	pub original_indices: bool,
+	/// Keep the contents every attribute was parsed from, see
+	/// [Attribute::raw_bytes](crate::attributes::Attribute::raw_bytes)
+	pub raw_attributes: bool,
+	/// Keep the contents of each Code attribute, so that code which is unchanged when written with
+	/// the constant pool it was parsed with is copied instead of encoded again, see
+	/// [CodeAttribute::is_modified](crate::code::CodeAttribute::is_modified)
+	pub keep_original_code: bool,
+	/// Parsers for attributes this library does not understand, which are otherwise kept as
+	/// [Attribute::Unknown](crate::attributes::Attribute::Unknown)
+	pub custom_attributes: AttributeRegistry
+}
+
+/// Controls how a class file is written. The default writes the class as it is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteOptions {
+	/// Write fields and methods ordered by name and then descriptor
+	pub sort_members: bool,
+	/// Order the constant pool by tag and then content instead of by first use
+	pub sort_pool: bool,
+	/// Leave out debugging information, see [`ClassFile::strip_debug_info`]
+	pub strip_debug: bool,
+	/// Write unknown attributes into the new constant pool even though any constant pool indices
+	/// inside them will refer to the wrong constants
+	pub allow_unknown_attributes: bool,
+	/// Write the class for this older version, see [`ClassFile::downgrade`]
+	pub target_version: Option<ClassVersion>
 }
 
 impl ClassFile {
+	/// A class without members or attributes
+	pub fn new(version: ClassVersion, access_flags: ClassAccessFlags, this_class: ClassName, super_class: Option<ClassName>) -> Self {
+		ClassFile {
+			magic: 0xCAFEBABE,
+			version,
+			access_flags,
+			this_class,
+			super_class,
+			interfaces: Vec::new(),
+			fields: Vec::new(),
+			methods: Vec::new(),
+			attributes: Vec::new(),
+			original_indices: None,
+			partial: None,
+			modified: Modified::default()
+		}
+	}
+	
 	pub fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
-		let magic = rdr.read_u32::<BigEndian>()?;
+		ClassFile::parse_with(rdr, &ParseOptions::default())
+	}
+	
+	pub fn parse_with<R: Read>(rdr: &mut R, options: &ParseOptions) -> Result<Self> {
+		ClassFile::parse_with_pool(rdr, options).map(|(class, _)| class)
+	}
+	
+	/// Also returns the constant pool of the class. Writing the class with
+	/// [`write_with_pool`](Self::write_with_pool) and [`ConstantPoolWriter::from_pool`] keeps every
+	/// constant at its original index, so that unknown attributes stay valid.
+	pub fn parse_with_pool<R: Read>(rdr: &mut R, options: &ParseOptions) -> Result<(Self, ConstantPool)> {
+		let mut rdr = CountingReader::new(rdr);
+		ClassFile::parse_counted(&mut rdr, options).map_err(|err| err.with_offset(rdr.position()))
+	}
+	
+	/// Parses a class that is already in memory. Unlike [`parse`](Self::parse) over a `Cursor`,
+	/// the contents of attributes and code are read in place rather than copied out first.
+	pub fn parse_bytes(bytes: &[u8]) -> Result<Self> {
+		ClassFile::parse_bytes_with(bytes, &ParseOptions::default())
+	}
+	
+	pub fn parse_bytes_with(bytes: &[u8], options: &ParseOptions) -> Result<Self> {
+		ClassFile::parse_bytes_with_pool(bytes, options).map(|(class, _)| class)
+	}
+	
+	/// As [`parse_with_pool`](Self::parse_with_pool), for a class that is already in memory
+	pub fn parse_bytes_with_pool(bytes: &[u8], options: &ParseOptions) -> Result<(Self, ConstantPool)> {
+		let mut rdr = Cursor::new(bytes);
+		ClassFile::parse_counted(&mut rdr, options).map_err(|err| err.with_offset(rdr.position()))
+	}
+	
+	fn parse_counted<R: ClassRead>(rdr: &mut R, options: &ParseOptions) -> Result<(Self, ConstantPool)> {
+		let magic = rdr.read_u32::<BigEndian>().map_err(ParserError::from).context(|| "magic")?;
 		if magic != 0xCAFEBABE {
-			return Err(ParserError::unrecognised("header", magic.to_string()));
+			return Err(ParserError::at("magic", ParserError::unrecognised("header", magic.to_string())));
+		}
+		let version = ClassVersion::parse(rdr).context(|| "version")?;
+		let mut class_name = None;
+		Self::parse_body(rdr, magic, version, options, &mut class_name)
+			.map_err(|err| err.in_class(class_name, version))
+	}
+	
+	/// A class with only the version and, if [`resync`](Self::resync) finds them, the names from
+	/// the header, for a pool that stopped at an unrecognised tag. The names are a guess, recorded
+	/// in [`PartialParse::guessed_length`].
+	fn salvage<R: ClassRead>(rdr: &mut R, magic: u32, version: ClassVersion, constant_pool: ConstantPool, mut partial: PartialParse, options: &ParseOptions) -> Result<(Self, ConstantPool)> {
+		let mut rest = Vec::new();
+		rdr.read_to_end(&mut rest)?;
+		let header = (0..=MAX_UNKNOWN_CONSTANT_LENGTH).find_map(|length| {
+			ClassFile::resync(&rest, length, &constant_pool, partial.index, options).ok().map(|header| (length, header))
+		});
+		let (access_flags, this_class, super_class, interfaces) = match header {
+			Some((length, header)) => {
+				partial.guessed_length = Some(length);
+				header
+			},
+			None => (ClassAccessFlags::empty(), ClassName::from(""), None, Vec::new())
+		};
+		Ok((ClassFile {
+			magic,
+			version,
+			access_flags,
+			this_class,
+			super_class,
+			interfaces,
+			fields: Vec::new(),
+			methods: Vec::new(),
+			attributes: Vec::new(),
+			original_indices: None,
+			partial: Some(partial),
+			modified: Modified(false)
+		}, constant_pool))
+	}
+	
+	/// The header of the class, guessing that the unrecognised constant at `index` has `length`
+	/// bytes after its tag. Only succeeds if the rest of the pool parses and the header names valid
+	/// classes.
+	fn resync(rest: &[u8], length: usize, constant_pool: &ConstantPool, index: CPIndex, options: &ParseOptions) -> Result<(ClassAccessFlags, ClassName, Option<ClassName>, Vec<ClassName>)> {
+		let mut rdr = Cursor::new(rest.get(length..).unwrap_or_default());
+		let mut constant_pool = constant_pool.clone();
+		if let Some(unrecognised) = constant_pool.parse_constants(&mut rdr, index as usize + 1, options)? {
+			return Err(unrecognised.error);
 		}
-		let version = ClassVersion::parse(rdr)?;
-		let constant_pool = ConstantPool::parse(rdr)?;
-		let access_flags = ClassAccessFlags::parse(rdr)?;
-		let this_class = constant_pool.utf8(constant_pool.class(rdr.read_u16::<BigEndian>()?)?.name_index)?.str.clone();
+		let class = |index: CPIndex| constant_pool.class_name(index).and_then(|name| ClassName::from_internal(&name));
+		let access_flags = ClassAccessFlags::parse(&mut rdr)?;
+		let this_class = class(rdr.read_u16::<BigEndian>()?)?;
 		let super_class = match rdr.read_u16::<BigEndian>()? {
 			0 => None,
-			i => Some(constant_pool.utf8(constant_pool.class(i)?.name_index)?.str.clone())
+			index => Some(class(index)?)
+		};
+		let count = rdr.read_u16::<BigEndian>()?;
+		let interfaces = (0..count).map(|_| class(rdr.read_u16::<BigEndian>()?)).collect::<Result<Vec<ClassName>>>()?;
+		Ok((access_flags, this_class, super_class, interfaces))
+	}
+	
+	/// Parses everything after the version, recording the class name in `class_name` once known
+	fn parse_body<R: ClassRead>(rdr: &mut R, magic: u32, version: ClassVersion, options: &ParseOptions, class_name: &mut Option<String>) -> Result<(Self, ConstantPool)> {
+		let constant_pool = match ConstantPool::parse_lenient(rdr, options)? {
+			(constant_pool, None) => constant_pool,
+			(constant_pool, Some(partial)) => return ClassFile::salvage(rdr, magic, version, constant_pool, partial, options)
+		};
+		let access_flags = ClassAccessFlags::parse(rdr).context(|| "access_flags")?;
+		let this_index = rdr.read_u16::<BigEndian>().map_err(ParserError::from).context(|| "this_class")?;
+		let this_class = constant_pool.class_name(this_index).context(|| "this_class")?;
+		*class_name = Some(this_class.clone());
+		let this_class = ClassName::from(this_class);
+		let super_index = rdr.read_u16::<BigEndian>().map_err(ParserError::from).context(|| "super_class")?;
+		let super_class = match super_index {
+			0 => None,
+			i => Some(constant_pool.class_name(i).context(|| "super_class")?.into())
 		};
 		
-		let num_interfaces = rdr.read_u16::<BigEndian>()? as usize;
-		let mut interfaces: Vec<String> = Vec::with_capacity(num_interfaces);
-		for _ in 0..num_interfaces {
-			interfaces.push(constant_pool.utf8(constant_pool.class(rdr.read_u16::<BigEndian>()?)?.name_index)?.str.clone());
+		let num_interfaces = rdr.read_u16::<BigEndian>().map_err(ParserError::from).context(|| "interfaces")? as usize;
+		let mut interfaces: Vec<ClassName> = Vec::with_capacity(num_interfaces);
+		let mut interface_indices = Vec::with_capacity(num_interfaces);
+		for i in 0..num_interfaces {
+			let index = rdr.read_u16::<BigEndian>().map_err(ParserError::from).context(|| format!("interfaces[{}]", i))?;
+			interfaces.push(constant_pool.class_name(index).context(|| format!("interfaces[{}]", i))?.into());
+			interface_indices.push(index);
 		}
+		let original_indices = if options.original_indices {
+			Some(ClassIndices { this_class: this_index, super_class: super_index, interfaces: interface_indices })
+		} else {
+			None
+		};
 		
-		let fields = Fields::parse(rdr, &version, &constant_pool)?;
-		let methods = Methods::parse(rdr, &version, &constant_pool)?;
-		let attributes = Attributes::parse(rdr, AttributeSource::Class, &version, &constant_pool, &mut None)?;
+		let fields = Fields::parse_from(rdr, &version, &constant_pool, options)?;
+		let methods = Methods::parse_from(rdr, &version, &constant_pool, options)?;
+		let mut context = AttributeContext::new(AttributeSource::Class, &version, &constant_pool, options);
+		let attributes = Attributes::parse_from(rdr, &mut context)?;
 		
-		Ok(ClassFile {
+		Ok((ClassFile {
 			magic,
 			version,
 			access_flags,
@@ -58,19 +313,815 @@ impl ClassFile {
 			interfaces,
 			fields,
 			methods,
-			attributes
-		})
+			attributes,
+			original_indices,
+			partial: None,
+			modified: Modified(false)
+		}, constant_pool))
 	}
 	
-	pub fn write<W: Write>(&self, wtr: &mut W) -> Result<()> {
-		wtr.write_u32::<BigEndian>(self.magic)?;
-		self.version.write(wtr)?;
+	/// Whether the class may have changed since it was parsed or passed to
+	/// [`mark_clean`](Self::mark_clean). Classes that were not parsed count as modified.
+	///
+	/// The parts of a class can only be changed through its methods, and every method that changes
+	/// them or hands out a mutable reference, such as [`method_mut`](Self::method_mut) or
+	/// [`methods_mut`](Self::methods_mut), marks the class as modified. Anything done through such a
+	/// reference, such as editing the code of a method, is therefore noticed without comparing the
+	/// class with what was parsed.
+	pub fn is_modified(&self) -> bool {
+		self.modified.0
+	}
+	
+	/// Whether parsing stopped at an unrecognised constant pool tag, with
+	/// [ParseOptions::lenient_pool]. Such a class has no members or attributes, and its name is
+	/// empty unless it could be found past the unrecognised constant, see
+	/// [`PartialParse::guessed_length`]. It cannot be written.
+	pub fn is_partial(&self) -> bool {
+		self.partial.is_some()
+	}
+	
+	pub fn mark_modified(&mut self) {
+		self.modified = Modified(true);
+	}
+	
+	/// Forgets earlier changes, for example once the class has been written out
+	pub fn mark_clean(&mut self) {
+		self.modified = Modified(false);
+	}
+	
+	/// 0xCAFEBABE for any valid class
+	pub fn magic(&self) -> u32 {
+		self.magic
+	}
+	
+	pub fn version(&self) -> ClassVersion {
+		self.version
+	}
+	
+	/// Sets the version the class is written with, without checking that it supports everything
+	/// the class uses. See [`downgrade`](Self::downgrade) to check.
+	pub fn set_version(&mut self, version: ClassVersion) {
+		self.mark_modified();
+		self.version = version;
+	}
+	
+	pub fn access_flags(&self) -> ClassAccessFlags {
+		self.access_flags
+	}
+	
+	pub fn set_access_flags(&mut self, access_flags: ClassAccessFlags) {
+		self.mark_modified();
+		self.access_flags = access_flags;
+	}
+	
+	pub fn this_class(&self) -> &ClassName {
+		&self.this_class
+	}
+	
+	/// Renames the class without changing references to it, see
+	/// [`remap_classes`](Self::remap_classes) to change those too
+	pub fn set_this_class(&mut self, this_class: ClassName) {
+		self.mark_modified();
+		self.this_class = this_class;
+	}
+	
+	/// None for java/lang/Object and module-info
+	pub fn super_class(&self) -> Option<&ClassName> {
+		self.super_class.as_ref()
+	}
+	
+	pub fn set_super_class(&mut self, super_class: Option<ClassName>) {
+		self.mark_modified();
+		self.super_class = super_class;
+	}
+	
+	pub fn interfaces(&self) -> &[ClassName] {
+		&self.interfaces
+	}
+	
+	/// The interfaces, marking the class as modified
+	pub fn interfaces_mut(&mut self) -> &mut Vec<ClassName> {
+		self.mark_modified();
+		&mut self.interfaces
+	}
+	
+	pub fn fields(&self) -> &[Field] {
+		&self.fields
+	}
+	
+	pub fn methods(&self) -> &[Method] {
+		&self.methods
+	}
+	
+	/// Written back in this order, as are the attributes of members and code
+	pub fn attributes(&self) -> &[Attribute] {
+		&self.attributes
+	}
+	
+	/// Where this_class, super_class and interfaces were in the constant pool, when parsed with
+	/// [ParseOptions::original_indices]
+	pub fn original_indices(&self) -> Option<&ClassIndices> {
+		self.original_indices.as_ref()
+	}
+	
+	/// Where parsing stopped, see [`is_partial`](Self::is_partial)
+	pub fn partial(&self) -> Option<&PartialParse> {
+		self.partial.as_ref()
+	}
+	
+	/// The methods, marking the class as modified
+	pub fn methods_mut(&mut self) -> &mut Vec<Method> {
+		self.mark_modified();
+		&mut self.methods
+	}
+	
+	/// The fields, marking the class as modified
+	pub fn fields_mut(&mut self) -> &mut Vec<Field> {
+		self.mark_modified();
+		&mut self.fields
+	}
+	
+	/// The attributes of the class, marking it as modified
+	pub fn attributes_mut(&mut self) -> &mut Vec<Attribute> {
+		self.mark_modified();
+		&mut self.attributes
+	}
+	
+	/// The generic signature of this class, if it has a Signature attribute. Marks the class as
+	/// modified if it does.
+	pub fn signature(&mut self) -> Option<&mut String> {
+		let signature = Attributes::signature(&mut self.attributes);
+		if signature.is_some() {
+			self.modified = Modified(true);
+		}
+		signature
+	}
+	
+	/// Like [`signature`](Self::signature), through a shared reference and without marking the
+	/// class as modified
+	pub fn signature_ref(&self) -> Option<&str> {
+		Attributes::signature_ref(&self.attributes)
+	}
+	
+	pub fn set_signature(&mut self, sig: Option<String>) {
+		self.mark_modified();
+		Attributes::set_signature(&mut self.attributes, sig)
+	}
+	
+	/// Problems the JVM may reject a parsed class for that parsing lets through: names of the class
+	/// and its supertypes that are not valid internal class names, a class that extends itself and
+	/// interfaces listed twice, followed by the attributes kept unparsed because of errors, see
+	/// [ParseOptions::lenient_code] and [ParseOptions::lenient_constant_types]
+	pub fn parse_warnings(&self) -> Vec<ParseWarning> {
+		let mut warnings: Vec<ParseWarning> = self.name_problems().into_iter()
+			.map(|error| ParseWarning { member: String::new(), path: Path::Class, error })
+			.collect();
+		let mut check = |attributes: &[Attribute], member: &str, path: &dyn Fn(usize) -> AttrPath| {
+			for (index, attribute) in attributes.iter().enumerate() {
+				let error = match attribute {
+					Attribute::UndecodedCode(code) => &code.error,
+					Attribute::Undecoded(attribute) => &attribute.error,
+					_ => continue
+				};
+				warnings.push(ParseWarning {
+					member: member.to_string(),
+					path: Path::Attribute(path(index)),
+					error: error.clone()
+				});
+			}
+		};
+		for field in self.fields.iter() {
+			let member = MemberPath::field(field);
+			check(&field.attributes, &format!("{} {}", field.name, field.descriptor), &|index| member.attribute(index));
+		}
+		for method in self.methods.iter() {
+			let member = MemberPath::method(method);
+			let name = format!("{} {}", method.name, method.descriptor);
+			check(&method.attributes, &name, &|index| member.attribute(index));
+			for attribute in method.attributes.iter() {
+				if let Attribute::Code(code) = attribute {
+					check(&code.attributes, &name, &|index| member.code_attribute(index));
+				}
+			}
+		}
+		check(&self.attributes, "", &AttrPath::class);
+		warnings
+	}
+	
+	/// See [`parse_warnings`](Self::parse_warnings)
+	fn name_problems(&self) -> Vec<String> {
+		let mut problems = Vec::new();
+		let mut check = |what: &str, name: &str| {
+			match ClassName::from_internal(name) {
+				Ok(name) if name.is_array() => problems.push(format!("{} {} is an array class", what, name)),
+				Ok(_) => {},
+				Err(_) => problems.push(format!("{} {} is not a valid internal class name", what, name))
+			}
+		};
+		check("this_class", &self.this_class);
+		if let Some(super_class) = &self.super_class {
+			check("super_class", super_class);
+		}
+		for interface in self.interfaces.iter() {
+			check("interface", interface);
+		}
+		if self.super_class.as_ref() == Some(&self.this_class) {
+			problems.push(format!("{} extends itself", self.this_class));
+		}
+		for (i, interface) in self.interfaces.iter().enumerate() {
+			// reported at its first occurrence
+			if !self.interfaces[..i].contains(interface) && self.interfaces[i + 1..].contains(interface) {
+				problems.push(format!("interface {} is listed more than once", interface));
+			}
+		}
+		problems
+	}
+	
+	/// The path of the class in a jar or directory of classes, such as `java/lang/String.class`
+	pub fn expected_file_name(&self) -> String {
+		format!("{}.class", self.this_class)
+	}
+	
+	/// Finds the method with the given name and descriptor
+	pub fn method(&self, name: &str, descriptor: &str) -> Option<&Method> {
+		self.methods.iter().find(|m| m.name == name && m.descriptor == descriptor)
+	}
+	
+	/// Like [`method`](Self::method), marking the class as modified if the method exists
+	pub fn method_mut(&mut self, name: &str, descriptor: &str) -> Option<&mut Method> {
+		let method = self.methods.iter_mut().find(|m| m.name == name && m.descriptor == descriptor);
+		if method.is_some() {
+			self.modified = Modified(true);
+		}
+		method
+	}
+	
+	/// Finds the field with the given name and descriptor
+	pub fn field(&self, name: &str, descriptor: &str) -> Option<&Field> {
+		self.fields.iter().find(|f| f.name == name && f.descriptor == descriptor)
+	}
+	
+	/// Like [`field`](Self::field), marking the class as modified if the field exists
+	pub fn field_mut(&mut self, name: &str, descriptor: &str) -> Option<&mut Field> {
+		let field = self.fields.iter_mut().find(|f| f.name == name && f.descriptor == descriptor);
+		if field.is_some() {
+			self.modified = Modified(true);
+		}
+		field
+	}
+	
+	/// Adds a method, failing if one with the same name and descriptor already exists
+	pub fn add_method(&mut self, method: Method) -> Result<()> {
+		if self.method(&method.name, &method.descriptor).is_some() {
+			return Err(ParserError::other(format!("Duplicate method {}{}", method.name, method.descriptor)));
+		}
+		self.mark_modified();
+		self.methods.push(method);
+		Ok(())
+	}
+	
+	/// Adds a field, failing if one with the same name and descriptor already exists
+	pub fn add_field(&mut self, field: Field) -> Result<()> {
+		if self.field(&field.name, &field.descriptor).is_some() {
+			return Err(ParserError::other(format!("Duplicate field {} {}", field.name, field.descriptor)));
+		}
+		self.mark_modified();
+		self.fields.push(field);
+		Ok(())
+	}
+	
+	pub fn remove_method(&mut self, name: &str, descriptor: &str) -> Option<Method> {
+		let index = self.methods.iter().position(|m| m.name == name && m.descriptor == descriptor)?;
+		self.mark_modified();
+		Some(self.methods.remove(index))
+	}
+	
+	pub fn remove_field(&mut self, name: &str, descriptor: &str) -> Option<Field> {
+		let index = self.fields.iter().position(|f| f.name == name && f.descriptor == descriptor)?;
+		self.mark_modified();
+		Some(self.fields.remove(index))
+	}
+	
+	/// Keeps only the methods matching the predicate, in their original order
+	pub fn retain_methods<F>(&mut self, predicate: F)
+		where F: FnMut(&Method) -> bool {
+		let count = self.methods.len();
+		self.methods.retain(predicate);
+		if self.methods.len() != count {
+			self.mark_modified();
+		}
+	}
+	
+	pub fn retain_fields<F>(&mut self, predicate: F)
+		where F: FnMut(&Field) -> bool {
+		let count = self.fields.len();
+		self.fields.retain(predicate);
+		if self.fields.len() != count {
+			self.mark_modified();
+		}
+	}
+	
+	/// Removes the SourceFile, SourceDebugExtension, LineNumberTable, LocalVariableTable and
+	/// LocalVariableTypeTable attributes, including those inside Code attributes, along with any
+	/// labels only they referred to
+	pub fn strip_debug_info(&mut self) {
+		fn is_debug(attribute: &Attribute) -> bool {
+			match attribute {
+				Attribute::SourceFile(..) | Attribute::SourceDebugExtension(..) | Attribute::LineNumberTable(..) | Attribute::LocalVariableTable(..)
+					| Attribute::LocalVariableTypeTable(..) => true,
+				Attribute::Unknown(_) | Attribute::Undecoded(_) => matches!(attribute.kind(), AttributeKind::SourceDebugExtension | AttributeKind::SourceFile
+					| AttributeKind::LineNumberTable | AttributeKind::LocalVariableTable | AttributeKind::LocalVariableTypeTable),
+				_ => false
+			}
+		}
+		let count = self.attributes.len();
+		self.attributes.retain(|attribute| !is_debug(attribute));
+		if self.attributes.len() != count {
+			self.mark_modified();
+		}
+		for method in self.methods.iter_mut() {
+			for attribute in method.attributes.iter_mut() {
+				if let Attribute::Code(code) = attribute {
+					let count = code.attributes.len();
+					code.attributes.retain(|attribute| !is_debug(attribute));
+					if code.attributes.len() != count {
+						code.mark_modified();
+					}
+					code.remove_unused_labels();
+				}
+			}
+		}
+	}
+	
+	/// Removes synthetic fields and synthetic or bridge methods, returning how many fields and
+	/// methods were removed. Members marked by a Synthetic attribute count as synthetic too.
+	///
+	/// Lambda bodies and accessors for private members are synthetic, so this is only safe when
+	/// nothing still refers to them.
+	pub fn strip_synthetic_members(&mut self) -> (usize, usize) {
+		fn has_synthetic_attribute(attributes: &[Attribute]) -> bool {
+			attributes.iter().any(|attribute| matches!(attribute, Attribute::Unknown(x) if x.kind() == AttributeKind::Synthetic))
+		}
+		let (fields, methods) = (self.fields.len(), self.methods.len());
+		self.fields.retain(|field| {
+			!field.access_flags.contains(FieldAccessFlags::SYNTHETIC) && !has_synthetic_attribute(&field.attributes)
+		});
+		self.methods.retain(|method| {
+			!method.access_flags.intersects(MethodAccessFlags::SYNTHETIC | MethodAccessFlags::BRIDGE) && !has_synthetic_attribute(&method.attributes)
+		});
+		let removed = (fields - self.fields.len(), methods - self.methods.len());
+		if removed != (0, 0) {
+			self.mark_modified();
+		}
+		removed
+	}
+	
+	/// Replaces string constants loaded by methods or initialising fields with the result of `f`,
+	/// if any, returning how many were replaced
+	pub fn map_string_constants<F>(&mut self, mut f: F) -> usize
+		where F: FnMut(&str, &StringContext) -> Option<String> {
+		let mut replaced = 0;
+		for field in self.fields.iter_mut() {
+			let context = StringContext::Field {
+				name: &field.name,
+				descriptor: &field.descriptor
+			};
+			for attribute in field.attributes.iter_mut() {
+				if let Attribute::ConstantValue(ConstantValueAttribute { value: ConstantValue::String(value), .. }) = attribute {
+					if let Some(new) = f(value, &context) {
+						*value = new;
+						replaced += 1;
+					}
+				}
+			}
+		}
+		for method in self.methods.iter_mut() {
+			let context = StringContext::Method {
+				name: &method.name,
+				descriptor: &method.descriptor
+			};
+			for attribute in method.attributes.iter_mut() {
+				if let Attribute::Code(code) = attribute {
+					let before = replaced;
+					for insn in code.insns.insns.iter_mut() {
+						if let Insn::Ldc(LdcInsn { constant: LdcType::String(value) }) = insn {
+							if let Some(new) = f(value, &context) {
+								*value = new;
+								replaced += 1;
+							}
+						}
+					}
+					if replaced != before {
+						code.mark_modified();
+					}
+				}
+			}
+		}
+		if replaced > 0 {
+			self.mark_modified();
+		}
+		replaced
+	}
+	
+	/// The methods invoked, fields accessed and classes instantiated by the code of every method,
+	/// along with the exceptions the methods declare
+	pub fn referenced_members(&self) -> MemberRefs {
+		let mut refs = MemberRefs::default();
+		for method in self.methods.iter() {
+			for attribute in method.attributes.iter() {
+				match attribute {
+					Attribute::Code(code) => code.accept(&mut refs),
+					Attribute::Exceptions(x) => refs.thrown.extend(x.exceptions.iter().cloned()),
+					_ => {}
+				}
+			}
+		}
+		refs
+	}
+	
+	/// The oldest class file version that can hold everything this class uses, which is at least
+	/// the declared version. Newer constants, attributes and instructions, such as invokedynamic,
+	/// calls of static or default interface methods, nest attributes or records, raise it, as do
+	/// interface methods with code and string concatenation through `StringConcatFactory`. A result
+	/// above the declared version means the JVM would reject the class as written.
+	pub fn minimum_runtime_version(&self) -> MajorVersion {
+		self.versioned_features().iter()
+			.map(|feature| feature.version)
+			.fold(self.version.major, std::cmp::max)
+	}
+	
+	/// Everything in the class that needs a newer version than JDK 1.1. This is the one table
+	/// behind [`minimum_runtime_version`](Self::minimum_runtime_version) and
+	/// [`downgrade`](Self::downgrade).
+	fn versioned_features(&self) -> Vec<VersionedFeature<'_>> {
+		fn describe(insn: &Insn) -> String {
+			match insn {
+				Insn::InvokeDynamic(_) => String::from("invokedynamic"),
+				Insn::Ldc(x) => String::from(match x.constant {
+					LdcType::Class(_) => "ldc of a class",
+					LdcType::MethodType(_) => "ldc of a method type",
+					LdcType::MethodHandle(_) => "ldc of a method handle",
+					LdcType::Dynamic(_) => "ldc of a dynamic constant",
+					_ => "ldc"
+				}),
+				Insn::Invoke(x) => format!("{} of an interface method", x.kind),
+				_ => format!("{:?}", insn)
+			}
+		}
 		
+		fn attributes<'a>(features: &mut Vec<VersionedFeature<'a>>, attributes: &'a [Attribute], location: &dyn Fn(&str) -> String, path: &dyn Fn(usize) -> AttrPath) {
+			for (index, attribute) in attributes.iter().enumerate() {
+				let version = attribute.minimum_version();
+				if version > MajorVersion::JDK_1_1 {
+					features.push(VersionedFeature {
+						name: format!("{} attribute", attribute.name()),
+						description: location(attribute.name()),
+						path: Path::Attribute(path(index)),
+						version,
+						source: FeatureSource::Attribute(attribute)
+					});
+				}
+			}
+		}
+		
+		let mut features = Vec::new();
+		attributes(&mut features, &self.attributes, &|name| format!("{} attribute", name), &AttrPath::class);
+		for field in self.fields.iter() {
+			let path = MemberPath::field(field);
+			attributes(&mut features, &field.attributes, &|name| format!("{} attribute on {}", name, field.name), &|index| path.attribute(index));
+		}
+		let interface = self.access_flags.contains(ClassAccessFlags::INTERFACE);
+		for method in self.methods.iter() {
+			let member = format!("{}{}", method.name, method.descriptor);
+			let path = MemberPath::method(method);
+			attributes(&mut features, &method.attributes, &|name| format!("{} attribute on {}", name, member), &|index| path.attribute(index));
+			let code = match method.code_ref() {
+				Some(code) => code,
+				None => continue
+			};
+			if interface && method.name != "<clinit>" {
+				features.push(VersionedFeature {
+					name: String::from("interface method with code"),
+					description: format!("interface method {} with code", member),
+					path: Path::Code(path.clone()),
+					version: MajorVersion::JAVA_8,
+					source: FeatureSource::Code
+				});
+			}
+			attributes(&mut features, &code.attributes, &|name| format!("{} attribute in the code of {}", name, member), &|index| path.code_attribute(index));
+			for (index, insn) in code.insns.iter().enumerate() {
+				let version = insn.minimum_version();
+				if version > MajorVersion::JDK_1_1 {
+					let name = describe(insn);
+					features.push(VersionedFeature {
+						description: format!("{} in {}", name, member),
+						name,
+						path: Path::Insn(path.insn(index)),
+						version,
+						source: FeatureSource::Code
+					});
+				}
+				// the bootstrap method only exists in newer runtimes
+				if matches!(insn, Insn::InvokeDynamic(x) if x.bootstrap_class == "java/lang/invoke/StringConcatFactory") {
+					features.push(VersionedFeature {
+						name: String::from("string concatenation with invokedynamic"),
+						description: format!("string concatenation with invokedynamic in {}", member),
+						path: Path::Insn(path.insn(index)),
+						version: MajorVersion::JAVA_9,
+						source: FeatureSource::Library
+					});
+				}
+			}
+		}
+		features
+	}
+	
+	/// Rewrites the class for the older version `target` where it can, and sets its version to
+	/// `target`. Below Java 11 the NestHost and NestMembers attributes are dropped, so nestmates lose
+	/// access to each other's private members. Below Java 6 StackMapTable attributes are dropped, as
+	/// the old verifier does not use them. Returns a warning for each dropped attribute, such as
+	/// `NestHost attribute (needs Java 11)`.
+	///
+	/// Anything else `target` does not support, such as invokedynamic below Java 7 or interface
+	/// methods with code below Java 8, fails with [`ParserError::UnsupportedByTarget`] listing all of
+	/// them. The class is then left unchanged, as it is for a target newer than the class.
+	pub fn downgrade(&mut self, target: ClassVersion) -> Result<Vec<String>> {
+		if target > self.version {
+			return Err(ParserError::other(format!("Cannot raise the class version from {} to {}", self.version.major, target.major)));
+		}
+		let features = self.unsupported_features(target.major);
+		if !features.is_empty() {
+			return Err(ParserError::unsupported_by_target(target.major, features));
+		}
+		let warnings = self.versioned_features().into_iter()
+			.filter(|feature| matches!(feature.source, FeatureSource::Attribute(x) if ClassFile::dropped_by_downgrade(x, target.major)))
+			.map(|feature| format!("{} (needs {})", feature.description, feature.version))
+			.collect();
+		
+		let dropped = |attribute: &Attribute| ClassFile::dropped_by_downgrade(attribute, target.major);
+		self.attributes.retain(|attribute| !dropped(attribute));
+		for method in self.methods.iter_mut() {
+			for attribute in method.attributes.iter_mut() {
+				if let Attribute::Code(code) = attribute {
+					let count = code.attributes.len();
+					code.attributes.retain(|attribute| !dropped(attribute));
+					if code.attributes.len() != count {
+						code.mark_modified();
+					}
+				}
+			}
+		}
+		self.version = target;
+		self.mark_modified();
+		Ok(warnings)
+	}
+	
+	/// Whether downgrading to `target` drops the attribute rather than failing
+	fn dropped_by_downgrade(attribute: &Attribute, target: MajorVersion) -> bool {
+		match attribute.kind() {
+			AttributeKind::NestHost | AttributeKind::NestMembers => target < MajorVersion::JAVA_11,
+			AttributeKind::StackMapTable => target < MajorVersion::JAVA_6,
+			_ => false
+		}
+	}
+	
+	/// Each feature `target` does not support that a downgrade cannot remove, with the version it
+	/// needs
+	fn unsupported_features(&self, target: MajorVersion) -> Vec<String> {
+		let mut features = Vec::new();
+		for feature in self.versioned_features() {
+			if feature.version <= target || matches!(feature.source, FeatureSource::Attribute(x) if ClassFile::dropped_by_downgrade(x, target)) {
+				continue;
+			}
+			let feature = format!("{} (needs {})", feature.description, feature.version);
+			if !features.contains(&feature) {
+				features.push(feature);
+			}
+		}
+		features
+	}
+	
+	/// Every constant that writing this class would put in the constant pool. The indices inside
+	/// constants are those of the pool that [`write`](Self::write) produces.
+	pub fn referenced_constants(&self) -> Result<HashSet<ConstantType>> {
+		let mut constant_pool = ConstantPoolWriter::new();
+		self.write_body(&mut constant_pool)?;
+		Ok(constant_pool.constants().cloned().collect())
+	}
+	
+	/// Checks for problems the JVM would reject that writing lets through: illegal access flags,
+	/// and methods that are neither abstract nor native but have no Code attribute.
+	///
+	/// Attributes defined in versions newer than the class, such as a Signature in a JDK 1.3 class,
+	/// are ignored by the JVM rather than rejected, and are written as they are. They are returned
+	/// as warnings, as is code newer than the class, such as a static call of an interface method
+	/// before Java 8. The JVM rejects that code, but it is also written as it is, in case the
+	/// version is raised later.
+	pub fn validate(&self) -> Result<Vec<ValidationWarning>> {
+		self.access_flags.validate().context(|| "access_flags")?;
+		for (i, field) in self.fields.iter().enumerate() {
+			field.access_flags.validate().context(|| format!("fields[{}]", i))?;
+		}
+		for (i, method) in self.methods.iter().enumerate() {
+			method.access_flags.validate()
+				.and_then(|_| method.check_code(false))
+				.context(|| format!("methods[{}]", i))?;
+		}
+		Ok(self.version_warnings())
+	}
+	
+	/// The attributes and code newer than the class version, see [`validate`](Self::validate)
+	fn version_warnings(&self) -> Vec<ValidationWarning> {
+		self.versioned_features().into_iter()
+			.filter(|feature| feature.version > self.version.major && !matches!(feature.source, FeatureSource::Library))
+			.map(|feature| ValidationWarning { path: feature.path, feature: feature.name, minimum_version: feature.version })
+			.collect()
+	}
+	
+	/// Writes the class with a new constant pool.
+	///
+	/// Fails if the class has an unknown attribute that may refer to the constant pool, such as an
+	/// annotation or StackMapTable, as its constant pool indices would be wrong in the new pool. Write
+	/// these classes with the pool they were parsed with (see
+	/// [`parse_with_pool`](Self::parse_with_pool)) or set
+	/// [`WriteOptions::allow_unknown_attributes`]. Attributes newer than the class version are
+	/// written too, [`validate`](Self::validate) warns about them.
+	pub fn write<W: Write>(&self, wtr: &mut W) -> Result<()> {
+		self.write_with(wtr, &WriteOptions::default())
+	}
+	
+	/// Writes the class with the given options. With every option enabled the output only depends on
+	/// the contents of the class, not on the order members were added in, making builds
+	/// reproducible.
+	pub fn write_with<W: Write>(&self, wtr: &mut W, options: &WriteOptions) -> Result<()> {
+		let (class, mut constant_pool) = self.prepare_write(options)?;
+		let body = class.write_body(&mut constant_pool)?;
+		class.write_parts(wtr, &mut constant_pool, &body)
+	}
+	
+	/// [`write_with`](Self::write_with), reporting what was written. The pool is new, so
+	/// [`WriteStats::compaction`] is None.
+	pub fn write_with_stats<W: Write>(&self, wtr: &mut W, options: &WriteOptions) -> Result<WriteStats> {
+		let (class, mut constant_pool) = self.prepare_write(options)?;
+		class.write_stats(self, wtr, &mut constant_pool)
+	}
+	
+	/// Applies the options, returning the class to write and the pool to write it with
+	fn prepare_write(&self, options: &WriteOptions) -> Result<(Cow<'_, ClassFile>, ConstantPoolWriter)> {
+		let mut class = Cow::Borrowed(self);
+		if let Some(target) = options.target_version {
+			class.to_mut().downgrade(target)?;
+		}
+		if options.sort_members {
+			let class = class.to_mut();
+			class.fields.sort_by(|a, b| (&a.name, &a.descriptor).cmp(&(&b.name, &b.descriptor)));
+			class.methods.sort_by(|a, b| (&a.name, &a.descriptor).cmp(&(&b.name, &b.descriptor)));
+		}
+		if options.strip_debug {
+			class.to_mut().strip_debug_info();
+		}
+		if !options.allow_unknown_attributes {
+			if let Some(name) = class.opaque_attribute() {
+				return Err(ParserError::other(format!("Cannot write the unknown {} attribute into a new constant pool", name)));
+			}
+		}
 		let mut constant_pool = ConstantPoolWriter::new();
+		if options.sort_pool {
+			class.write_body(&mut constant_pool)?;
+			constant_pool = constant_pool.sorted();
+		}
+		Ok((class, constant_pool))
+	}
+	
+	/// Writes the class using the given constant pool, which may already contain constants (see
+	/// [`ConstantPoolWriter::from_pool`]). Constants the class does not need are still written.
+	///
+	/// Unknown attributes are written as they are, so they are only valid if the pool has their
+	/// constants at the indices they refer to. Code parsed with
+	/// [`ParseOptions::keep_original_code`] is written as it was parsed until it is modified, see
+	/// [`CodeAttribute::is_modified`](crate::code::CodeAttribute::is_modified). Any other code is
+	/// encoded again, which fails if it has a StackMapTable.
+	pub fn write_with_pool<W: Write>(&self, wtr: &mut W, mut constant_pool: ConstantPoolWriter) -> Result<()> {
+		let body = self.write_body(&mut constant_pool)?;
+		self.write_parts(wtr, &mut constant_pool, &body)
+	}
+	
+	/// [`write_with_pool`](Self::write_with_pool), reporting what was written. Every constant of the
+	/// pool is kept, so [`WriteStats::compaction`] is None.
+	pub fn write_with_pool_stats<W: Write>(&self, wtr: &mut W, mut constant_pool: ConstantPoolWriter) -> Result<WriteStats> {
+		self.write_stats(self, wtr, &mut constant_pool)
+	}
+	
+	/// Writes the class, comparing its attributes with those of `original` to find the dropped ones
+	fn write_stats<W: Write>(&self, original: &ClassFile, wtr: &mut W, constant_pool: &mut ConstantPoolWriter) -> Result<WriteStats> {
+		let mut context = WriteContext::collecting_stats();
+		let body = self.write_body_with_context(constant_pool, &mut context)?;
+		let mut counter = CountingWriter::new(wtr);
+		self.write_parts(&mut counter, constant_pool, &body)?;
 		
-		// we need to write fields/methods etc after the constant pool, however they rely upon
-		// mutable access to the constant pool. therefore we will write them to memory and then to
-		// the wtr parameter
+		let mut stats = context.stats.unwrap_or_default();
+		stats.total_bytes = counter.position() as usize;
+		stats.constant_pool_count = constant_pool.constant_count();
+		for (name, count) in original.attribute_counts() {
+			let written = stats.attributes.get(&name).copied().unwrap_or(0);
+			if count > written {
+				stats.dropped_attributes.insert(name, count - written);
+			}
+		}
+		Ok(stats)
+	}
+	
+	/// How many attributes of each name the class, its members and their code have
+	fn attribute_counts(&self) -> BTreeMap<String, usize> {
+		fn count(counts: &mut BTreeMap<String, usize>, attributes: &[Attribute]) {
+			for attribute in attributes.iter() {
+				*counts.entry(attribute.name().to_string()).or_default() += 1;
+				if let Attribute::Code(code) = attribute {
+					count(counts, &code.attributes);
+				}
+			}
+		}
+		let mut counts = BTreeMap::new();
+		count(&mut counts, &self.attributes);
+		for field in self.fields.iter() {
+			count(&mut counts, &field.attributes);
+		}
+		for method in self.methods.iter() {
+			count(&mut counts, &method.attributes);
+		}
+		counts
+	}
+	
+	/// Like [`write_with_pool`](Self::write_with_pool), but first drops every constant the class
+	/// does not reference and renumbers the rest, keeping their order.
+	///
+	/// Fails if the class has a non-empty unknown attribute (such as a StackMapTable), as any
+	/// constant pool indices inside it could not be renumbered.
+	pub fn write_compacted<W: Write>(&self, wtr: &mut W, constant_pool: ConstantPoolWriter) -> Result<CompactionStats> {
+		let (mut constant_pool, before) = self.compact(constant_pool)?;
+		let body = self.write_body(&mut constant_pool)?;
+		let after = constant_pool.constant_count();
+		self.write_parts(wtr, &mut constant_pool, &body)?;
+		Ok(CompactionStats { before, after })
+	}
+	
+	/// [`write_compacted`](Self::write_compacted), reporting what was written along with the
+	/// [compaction](WriteStats::compaction)
+	pub fn write_compacted_stats<W: Write>(&self, wtr: &mut W, constant_pool: ConstantPoolWriter) -> Result<WriteStats> {
+		let (mut constant_pool, before) = self.compact(constant_pool)?;
+		let mut stats = self.write_stats(self, wtr, &mut constant_pool)?;
+		stats.compaction = Some(CompactionStats { before, after: stats.constant_pool_count });
+		Ok(stats)
+	}
+	
+	/// The pool without the constants the class does not reference, and the number of constants
+	/// before dropping them
+	fn compact(&self, mut constant_pool: ConstantPoolWriter) -> Result<(ConstantPoolWriter, usize)> {
+		if let Some(name) = self.opaque_attribute() {
+			return Err(ParserError::other(format!("Cannot compact the constant pool of a class with an unknown {} attribute", name)));
+		}
+		
+		constant_pool.clear_usage();
+		self.write_body(&mut constant_pool)?;
+		let before = constant_pool.constant_count();
+		Ok((constant_pool.compacted(), before))
+	}
+	
+	/// The name of the first unknown attribute that may refer to the constant pool, searching
+	/// members and code too
+	fn opaque_attribute(&self) -> Option<&str> {
+		fn find(attributes: &[Attribute]) -> Option<&str> {
+			attributes.iter().find_map(|attribute| match attribute {
+				Attribute::Unknown(x) if !x.buf.is_empty() => Some(x.name.as_str()),
+				Attribute::Undecoded(x) => Some(x.name.as_str()),
+				Attribute::UndecodedCode(_) => Some("Code"),
+				Attribute::Code(x) => find(&x.attributes),
+				_ => None
+			})
+		}
+		find(&self.attributes)
+			.or_else(|| self.fields.iter().find_map(|field| find(&field.attributes)))
+			.or_else(|| self.methods.iter().find_map(|method| find(&method.attributes)))
+	}
+	
+	fn write_parts<W: Write>(&self, wtr: &mut W, constant_pool: &mut ConstantPoolWriter, body: &[u8]) -> Result<()> {
+		wtr.write_u32::<BigEndian>(self.magic)?;
+		self.version.write(wtr)?;
+		constant_pool.write(wtr)?;
+		wtr.write_all(body)?;
+		Ok(())
+	}
+	
+	/// Everything after the constant pool, which has to be written first so that the constant pool
+	/// is complete
+	fn write_body(&self, constant_pool: &mut ConstantPoolWriter) -> Result<Vec<u8>> {
+		self.write_body_with_context(constant_pool, &mut WriteContext::default())
+	}
+	
+	fn write_body_with_context(&self, constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext) -> Result<Vec<u8>> {
+		if let Some(partial) = &self.partial {
+			return Err(ParserError::other(format!("Cannot write a partly parsed class: {}", partial.error)));
+		}
 		let buf: Vec<u8> = Vec::with_capacity(2 + (self.fields.len() * 8) + (self.methods.len() * 8));
 		let mut cursor = Cursor::new(buf);
 		self.access_flags.write(&mut cursor)?;
@@ -92,13 +1143,1454 @@ impl ClassFile {
 			cursor.write_u16::<BigEndian>(constant_pool.class(utf))?;
 		}
 		
-		Fields::write(&mut cursor, &self.fields, &mut constant_pool)?;
-		Methods::write(&mut cursor, &self.methods, &mut constant_pool)?;
-		Attributes::write(&mut cursor, &self.attributes, &mut constant_pool, None)?;
+		let parsed_bootstrap_methods = self.attributes.iter().position(|x| matches!(x, Attribute::Unknown(x) if x.kind() == AttributeKind::BootstrapMethods));
+		if let Some(index) = parsed_bootstrap_methods {
+			// unmodified code is written as it was parsed, referring to the parsed entries
+			if let Attribute::Unknown(x) = &self.attributes[index] {
+				if constant_pool.preserves_indices() && constant_pool.bootstrap_methods().is_empty() {
+					constant_pool.add_bootstrap_methods(&x.buf).context(|| "BootstrapMethods")?;
+				}
+			}
+		}
 		
-		constant_pool.write(wtr)?;
-		wtr.write_all(cursor.get_ref().as_slice())?;
+		Fields::write_with_context(&mut cursor, &self.fields, constant_pool, context)?;
+		Methods::write_with_context(&mut cursor, &self.methods, constant_pool, context)?;
+		if constant_pool.bootstrap_methods().is_empty() {
+			Attributes::write_with_context(&mut cursor, &self.attributes, AttributeSource::Class, constant_pool, None, context)?;
+		} else {
+			// the bootstrap methods of the code just written replace any the class was parsed with
+			let mut buf = Vec::new();
+			constant_pool.write_bootstrap_methods(&mut buf)?;
+			let generated = Attribute::Unknown(UnknownAttribute::new(String::from("BootstrapMethods"), buf));
+			let mut attributes: Vec<Attribute> = self.attributes.iter()
+				.filter(|x| !matches!(x, Attribute::Unknown(x) if x.kind() == AttributeKind::BootstrapMethods))
+				.cloned()
+				.collect();
+			// in place of the first parsed one, as nothing before it is a BootstrapMethods attribute
+			attributes.insert(parsed_bootstrap_methods.unwrap_or(attributes.len()), generated);
+			Attributes::write_with_context(&mut cursor, &attributes, AttributeSource::Class, constant_pool, None, context)?;
+		}
 		
-		Ok(())
+		Ok(cursor.into_inner())
+	}
+}
+
+/// The most bytes guessed for the contents of a constant with an unrecognised tag, see
+/// [`ClassFile::is_partial`]
+const MAX_UNKNOWN_CONSTANT_LENGTH: usize = 8;
+
+/// Where parsing a class stopped, see [`ClassFile::is_partial`]
+#[derive(Clone, Debug)]
+pub struct PartialParse {
+	/// The index of the constant with the unrecognised tag
+	pub index: CPIndex,
+	pub tag: u8,
+	/// The error the constant was parsed with, an [`Unrecognized`](ParserError::Unrecognized) tag
+	/// in the context of its index
+	pub error: Arc<ParserError>,
+	/// How many bytes the unrecognised constant was guessed to have after its tag, when the rest
+	/// of the pool and the class header parsed with that guess. The access flags and names of the
+	/// class were read past the guess, so a wrong guess that happened to parse gives wrong ones.
+	/// None if no guess worked, leaving the header empty.
+	pub guessed_length: Option<usize>
+}
+
+/// Compares errors by their message
+impl PartialEq for PartialParse {
+	fn eq(&self, other: &Self) -> bool {
+		self.index == other.index && self.tag == other.tag && self.guessed_length == other.guessed_length
+			&& self.error.to_string() == other.error.to_string()
+	}
+}
+
+/// A problem with a parsed class, see [`ClassFile::parse_warnings`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseWarning {
+	/// Name and descriptor of the field or method separated by a space, empty for the class and
+	/// its attributes
+	pub member: String,
+	pub path: Path,
+	pub error: String
+}
+
+/// Something in a class that needs a newer version than JDK 1.1, see
+/// [`ClassFile::versioned_features`]
+struct VersionedFeature<'a> {
+	/// What it is, such as `invokedynamic`
+	name: String,
+	/// What it is and where, such as `invokedynamic in run()V`
+	description: String,
+	path: Path,
+	version: MajorVersion,
+	source: FeatureSource<'a>
+}
+
+enum FeatureSource<'a> {
+	/// An attribute, which older JVMs ignore and a downgrade may drop
+	Attribute(&'a Attribute),
+	/// Code an older class file cannot hold
+	Code,
+	/// A call of a class only newer runtimes have, which any class file version can hold
+	Library
+}
+
+/// An attribute or code newer than the class version, see [`ClassFile::validate`]. The JVM ignores
+/// such attributes but rejects such code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationWarning {
+	pub path: Path,
+	/// What needs the newer version, such as `Signature attribute` or
+	/// `invokestatic of an interface method`
+	pub feature: String,
+	/// The first version that supports it
+	pub minimum_version: MajorVersion
+}
+
+impl Display for ValidationWarning {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self.path {
+			Path::Attribute(_) => write!(f, "{} at {} is ignored before {}", self.feature, self.path, self.minimum_version),
+			_ => write!(f, "{} at {} needs {}", self.feature, self.path, self.minimum_version)
+		}
+	}
+}
+
+/// The number of constants before and after [`ClassFile::write_compacted`], counting long and
+/// double constants once
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CompactionStats {
+	pub before: usize,
+	pub after: usize
+}
+
+impl CompactionStats {
+	pub fn removed(&self) -> usize {
+		self.before - self.after
+	}
+}
+
+/// What [`ClassFile::write_with_stats`] and the other `_stats` writers wrote
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteStats {
+	/// The length of the class file
+	pub total_bytes: usize,
+	/// The number of constants written, counting long and double constants once
+	pub constant_pool_count: usize,
+	/// The size of the pool before and after unreferenced constants were dropped, only present
+	/// for [`ClassFile::write_compacted_stats`]
+	pub compaction: Option<CompactionStats>,
+	/// Every method in the order written
+	pub methods: Vec<MethodWriteStats>,
+	/// Jumps whose offset did not fit in 16 bits, written with a `goto_w`
+	pub wide_jumps: usize,
+	/// How many attributes of each name were written, including those of Code attributes
+	pub attributes: BTreeMap<String, usize>,
+	/// How many attributes of each name the class had but were not written, such as those left
+	/// out by [`WriteOptions::strip_debug`]
+	pub dropped_attributes: BTreeMap<String, usize>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MethodWriteStats {
+	pub name: String,
+	pub descriptor: String,
+	/// The length of the instructions, None for methods without code
+	pub code_length: Option<usize>
+}
+
+impl Serializable for ClassFile {
+	fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
+		ClassFile::parse(rdr)
+	}
+	
+	fn write<W: Write>(&self, wtr: &mut W) -> Result<()> {
+		ClassFile::write(self, wtr)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::access::{FieldAccessFlags, MethodAccessFlags};
+	use crate::constantpool::Utf8Info;
+	use crate::attributes::{AttributeWriteContext, ExceptionsAttribute, CustomAttribute, CustomAttributeParser, UnknownAttribute};
+	use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+	use std::io::Cursor;
+	
+	fn class() -> ClassFile {
+		ClassFile {
+			magic: 0xCAFEBABE,
+			version: ClassVersion { major: MajorVersion::JAVA_8, minor: 0 },
+			access_flags: ClassAccessFlags::PUBLIC | ClassAccessFlags::ABSTRACT,
+			this_class: ClassName::from("Test"),
+			super_class: Some(ClassName::from("java/lang/Object")),
+			interfaces: Vec::new(),
+			fields: vec![Field::new(FieldAccessFlags::PRIVATE, String::from("count"), String::from("I"))],
+			methods: ["first", "second"].iter().map(|name| {
+				Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::ABSTRACT, String::from(*name), String::from("()V"))
+			}).collect(),
+			attributes: Vec::new(),
+			original_indices: None,
+			partial: None,
+			modified: Default::default()
+		}
+	}
+	
+	/// Writes the test class and points the u16 at `offset` bytes from the end to a missing
+	/// constant, returning the resulting parse error and the offset of the corrupted u16
+	fn corrupt(offset: usize) -> (ParserError, u64) {
+		let mut bytes = Vec::new();
+		class().write(&mut bytes).unwrap();
+		let index = bytes.len() - offset;
+		bytes[index] = 0xFF;
+		bytes[index + 1] = 0xFF;
+		(ClassFile::parse(&mut Cursor::new(bytes)).unwrap_err(), index as u64)
+	}
+	
+	fn method(name: &str, descriptor: &str) -> Method {
+		Method {
+			access_flags: MethodAccessFlags::PUBLIC,
+			name: String::from(name),
+			descriptor: String::from(descriptor),
+			attributes: Vec::new(),
+			original_indices: None
+		}
+	}
+	
+	#[test]
+	fn overloaded_methods() {
+		let mut class = class();
+		class.add_method(method("second", "(I)V")).unwrap();
+		class.add_method(method("second", "(J)I")).unwrap();
+		
+		assert_eq!(class.method("second", "(I)V").unwrap().descriptor, "(I)V");
+		assert_eq!(class.method("second", "(J)I").unwrap().descriptor, "(J)I");
+		assert!(class.method("second", "(D)V").is_none());
+		
+		class.method_mut("second", "(J)I").unwrap().access_flags = MethodAccessFlags::PRIVATE;
+		assert_eq!(class.method("second", "(J)I").unwrap().access_flags, MethodAccessFlags::PRIVATE);
+		assert_eq!(class.method("second", "(I)V").unwrap().access_flags, MethodAccessFlags::PUBLIC);
+		
+		let removed = class.remove_method("second", "(I)V").unwrap();
+		assert_eq!(removed.descriptor, "(I)V");
+		assert!(class.method("second", "()V").is_some());
+		assert!(class.method("second", "(J)I").is_some());
+		assert!(class.remove_method("second", "(I)V").is_none());
+	}
+	
+	#[test]
+	fn add_method_rejects_duplicates() {
+		let mut class = class();
+		assert!(class.add_method(method("first", "()V")).is_err());
+		assert_eq!(class.methods.len(), 2);
+		
+		assert!(class.add_method(method("first", "(I)V")).is_ok());
+		assert_eq!(class.methods.len(), 3);
+	}
+	
+	#[test]
+	fn retain_and_field_lookup() {
+		let mut class = class();
+		class.add_method(method("bridge", "()Ljava/lang/Object;")).unwrap();
+		class.method_mut("bridge", "()Ljava/lang/Object;").unwrap().access_flags |= MethodAccessFlags::BRIDGE;
+		class.retain_methods(|m| !m.access_flags.contains(MethodAccessFlags::BRIDGE));
+		let names: Vec<&str> = class.methods.iter().map(|m| m.name.as_str()).collect();
+		assert_eq!(names, vec!["first", "second"]);
+		
+		assert!(class.field("count", "I").is_some());
+		assert!(class.field("count", "J").is_none());
+		assert!(class.add_field(class.fields[0].clone()).is_err());
+	}
+	
+	/// Only uses the Serializable trait, so must not depend on anything else ClassFile provides
+	fn round_trip<T: Serializable, R: Read>(rdr: &mut R) -> Vec<u8> {
+		let parsed = T::parse(rdr).unwrap();
+		let mut out = Vec::new();
+		parsed.write(&mut out).unwrap();
+		out
+	}
+	
+	#[test]
+	fn attribute_order_is_preserved() {
+		use crate::attributes::{SignatureAttribute, SourceDebugExtensionAttribute, SourceFileAttribute, UnknownAttribute};
+		
+		let mut class = class();
+		class.attributes = vec![
+			Attribute::Unknown(UnknownAttribute::new(String::from("Custom"), vec![1, 2, 3])),
+			Attribute::SourceDebugExtension(SourceDebugExtensionAttribute::new(String::from("SMAP\nTest.kt\nKotlin\n*E\n"))),
+			Attribute::SourceFile(SourceFileAttribute::new(String::from("Test.kt"))),
+			Attribute::Unknown(UnknownAttribute::new(String::from("Deprecated"), Vec::new()))
+		];
+		class.fields[0].attributes = vec![
+			Attribute::Unknown(UnknownAttribute::new(String::from("Synthetic"), Vec::new())),
+			Attribute::Signature(SignatureAttribute::new(String::from("TT;")))
+		];
+		class.methods[1].attributes = vec![
+			Attribute::Signature(SignatureAttribute::new(String::from("<T:Ljava/lang/Object;>()V"))),
+			Attribute::Unknown(UnknownAttribute::new(String::from("Deprecated"), Vec::new()))
+		];
+		
+		// the Custom attribute holds no constant pool indices
+		let options = WriteOptions {
+			allow_unknown_attributes: true,
+			..Default::default()
+		};
+		let mut bytes = Vec::new();
+		class.write_with(&mut bytes, &options).unwrap();
+		let parsed = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap();
+		assert_eq!(parsed, class);
+		let mut out = Vec::new();
+		parsed.write_with(&mut out, &options).unwrap();
+		assert_eq!(out, bytes);
+	}
+	
+	/// The test class with a method printing "Hello"
+	fn hello() -> ClassFile {
+		use crate::code::CodeAttribute;
+		use crate::ast::*;
+		
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 2;
+		code.insns.insns = vec![
+			Insn::GetField(Box::new(GetFieldInsn::new(false, ClassName::from("java/lang/System"), String::from("out"), String::from("Ljava/io/PrintStream;")))),
+			Insn::Ldc(LdcInsn::new(LdcType::String(String::from("Hello")))),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, ClassName::from("java/io/PrintStream"), String::from("println"), String::from("(Ljava/lang/String;)V"), false))),
+			Insn::Return(ReturnInsn::new(ReturnType::Void))
+		];
+		let mut class = class();
+		let mut greet = method("greet", "()V");
+		greet.attributes.push(Attribute::Code(code));
+		class.add_method(greet).unwrap();
+		class
+	}
+	
+	#[test]
+	fn compaction_drops_orphaned_constants() {
+		let class = hello();
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		let pool = ConstantPool::parse(&mut Cursor::new(&bytes[8..])).unwrap();
+		let orphaned = || {
+			let mut constant_pool = ConstantPoolWriter::from_pool(&pool);
+			let utf = constant_pool.utf8("Orphan");
+			constant_pool.class(utf);
+			constant_pool.long(1 << 40);
+			constant_pool.string_utf("unused");
+			constant_pool
+		};
+		
+		let mut uncompacted = Vec::new();
+		class.write_with_pool(&mut uncompacted, orphaned()).unwrap();
+		let mut compacted = Vec::new();
+		let stats = class.write_compacted(&mut compacted, orphaned()).unwrap();
+		// a utf8 and its class, a long, and a string and its utf8
+		assert_eq!(stats.removed(), 5);
+		assert_eq!(stats.after, ConstantPoolWriter::from_pool(&pool).constant_count());
+		assert!(compacted.len() < uncompacted.len());
+		assert_eq!(ClassFile::parse(&mut Cursor::new(&compacted)).unwrap(), class);
+		// nothing was orphaned in between the original constants
+		assert_eq!(compacted, bytes);
+		
+		let referenced = class.referenced_constants().unwrap();
+		assert_eq!(referenced.len(), stats.after);
+		assert!(referenced.contains(&ConstantType::Utf8(Utf8Info::new(String::from("Hello")))));
+		assert!(!referenced.contains(&ConstantType::Utf8(Utf8Info::new(String::from("Orphan")))));
+	}
+	
+	#[test]
+	fn write_stats_match_the_written_class() {
+		use crate::attributes::SourceFileAttribute;
+		use crate::code::CodeAttribute;
+		use crate::ast::*;
+		
+		let mut class = hello();
+		class.attributes.push(Attribute::SourceFile(SourceFileAttribute::new(String::from("Hello.java"))));
+		// jumps too far for 16 bit offsets, forwards and back
+		let mut code = CodeAttribute::empty();
+		let (head, exit) = (code.insns.new_label(), code.insns.new_label());
+		code.max_stack = 1;
+		code.insns.insns.push(Insn::Label(head));
+		code.insns.insns.push(Insn::Jump(JumpInsn::new(exit)));
+		code.insns.insns.extend(vec![Insn::Nop(NopInsn::new()); 40_000]);
+		code.insns.insns.push(Insn::Label(exit));
+		code.insns.insns.push(Insn::Ldc(LdcInsn::new(LdcType::Int(0))));
+		code.insns.insns.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, head)));
+		code.insns.insns.push(Insn::Return(ReturnInsn::new(ReturnType::Void)));
+		let mut spin = method("spin", "()V");
+		spin.attributes.push(Attribute::Code(code));
+		class.add_method(spin).unwrap();
+		
+		let options = WriteOptions {
+			strip_debug: true,
+			..Default::default()
+		};
+		let mut bytes = Vec::new();
+		let stats = class.write_with_stats(&mut bytes, &options).unwrap();
+		assert_eq!(stats.total_bytes, bytes.len());
+		assert_eq!(stats.compaction, None);
+		let mut plain = Vec::new();
+		class.write_with(&mut plain, &options).unwrap();
+		assert_eq!(plain, bytes);
+		let pool = ConstantPool::parse(&mut Cursor::new(&bytes[8..])).unwrap();
+		assert_eq!(stats.constant_pool_count, ConstantPoolWriter::from_pool(&pool).constant_count());
+		assert_eq!(stats.wide_jumps, 2);
+		
+		// the code length follows max_stack and max_locals
+		let keep = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		let parsed = ClassFile::parse_with(&mut Cursor::new(&bytes), &keep).unwrap();
+		let expected: Vec<(String, Option<usize>)> = parsed.methods.iter().map(|method| {
+			let length = method.code_ref().and_then(|code| Attribute::Code(code.clone()).raw_bytes().map(|raw| u32::from_be_bytes([raw[4], raw[5], raw[6], raw[7]]) as usize));
+			(method.name.clone(), length)
+		}).collect();
+		let lengths: Vec<(String, Option<usize>)> = stats.methods.iter().map(|method| (method.name.clone(), method.code_length)).collect();
+		assert_eq!(lengths, expected);
+		assert!(lengths[3].1.unwrap() > 40_000, "{:?}", lengths);
+		
+		let names = |pairs: &[(&str, usize)]| pairs.iter().map(|(name, count)| (name.to_string(), *count)).collect::<BTreeMap<_, _>>();
+		assert_eq!(stats.attributes, names(&[("Code", 2)]));
+		assert_eq!(stats.dropped_attributes, names(&[("SourceFile", 1)]));
+		
+		// copied code still counts its attributes
+		let (parsed, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &keep).unwrap();
+		let copied = parsed.write_with_pool_stats(&mut Vec::new(), ConstantPoolWriter::from_pool(&constant_pool)).unwrap();
+		assert_eq!(copied.methods, stats.methods);
+		assert_eq!(copied.attributes, stats.attributes);
+		assert!(copied.dropped_attributes.is_empty());
+		assert_eq!(copied.wide_jumps, 0);
+	}
+	
+	#[test]
+	fn compaction_renumbers_remaining_constants() {
+		let mut class = hello();
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		let pool = ConstantPool::parse(&mut Cursor::new(&bytes[8..])).unwrap();
+		
+		// orphans the field's constants, which come before those of the methods
+		class.fields.clear();
+		let mut compacted = Vec::new();
+		let stats = class.write_compacted(&mut compacted, ConstantPoolWriter::from_pool(&pool)).unwrap();
+		assert_eq!(stats.removed(), 2);
+		assert_eq!(stats.before, ConstantPoolWriter::from_pool(&pool).constant_count());
+		assert_eq!(ClassFile::parse(&mut Cursor::new(&compacted)).unwrap(), class);
+		
+		let mut reported = Vec::new();
+		let write_stats = class.write_compacted_stats(&mut reported, ConstantPoolWriter::from_pool(&pool)).unwrap();
+		assert_eq!(reported, compacted);
+		assert_eq!(write_stats.compaction, Some(stats));
+		assert_eq!(write_stats.total_bytes, compacted.len());
+		let mut fresh = Vec::new();
+		class.write(&mut fresh).unwrap();
+		assert_eq!(compacted, fresh);
+	}
+	
+	#[test]
+	fn compaction_rejects_unknown_attributes() {
+		use crate::attributes::UnknownAttribute;
+		
+		let mut class = hello();
+		// these can not refer to the pool
+		class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Deprecated"), Vec::new())));
+		assert!(class.write_compacted(&mut Vec::new(), ConstantPoolWriter::new()).is_ok());
+		
+		if let Some(Attribute::Code(code)) = class.methods[2].attributes.first_mut() {
+			code.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("StackMapTable"), vec![0, 0])));
+		}
+		let err = class.write_compacted(&mut Vec::new(), ConstantPoolWriter::new()).unwrap_err();
+		assert!(err.to_string().contains("StackMapTable"));
+	}
+	
+	/// The same class as [`hello`], with its members added in a different order
+	fn hello_shuffled() -> (ClassFile, ClassFile) {
+		let mut class = hello();
+		class.add_field(Field {
+			access_flags: FieldAccessFlags::PUBLIC,
+			name: String::from("a"),
+			descriptor: String::from("J"),
+			attributes: Vec::new(),
+			original_indices: None
+		}).unwrap();
+		let mut shuffled = class.clone();
+		shuffled.fields.reverse();
+		shuffled.methods.rotate_left(1);
+		(class, shuffled)
+	}
+	
+	#[test]
+	fn deterministic_write() {
+		let (class, shuffled) = hello_shuffled();
+		let write = |class: &ClassFile, options: &WriteOptions| {
+			let mut bytes = Vec::new();
+			class.write_with(&mut bytes, options).unwrap();
+			bytes
+		};
+		assert_ne!(write(&class, &WriteOptions::default()), write(&shuffled, &WriteOptions::default()));
+		
+		let options = WriteOptions {
+			sort_members: true,
+			sort_pool: true,
+			strip_debug: true,
+			..Default::default()
+		};
+		let bytes = write(&class, &options);
+		assert_eq!(bytes, write(&shuffled, &options));
+		let parsed = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap();
+		let names: Vec<&str> = parsed.methods.iter().map(|m| m.name.as_str()).collect();
+		assert_eq!(names, vec!["first", "greet", "second"]);
+		assert_eq!(parsed.fields[0].name, "a");
+	}
+	
+	#[test]
+	fn sorted_pool_ignores_member_order() {
+		let (class, shuffled) = hello_shuffled();
+		let options = WriteOptions {
+			sort_pool: true,
+			..Default::default()
+		};
+		let pools: Vec<ConstantPool> = [&class, &shuffled].iter()
+			.map(|class| {
+				let mut bytes = Vec::new();
+				class.write_with(&mut bytes, &options).unwrap();
+				assert_eq!(&ClassFile::parse(&mut Cursor::new(&bytes)).unwrap(), *class);
+				ConstantPool::parse(&mut Cursor::new(&bytes[8..])).unwrap()
+			})
+			.collect();
+		assert_eq!(pools[0], pools[1]);
+		// utf8 constants have the lowest tag
+		assert!(pools[0].utf8(1).is_ok());
+	}
+	
+	#[test]
+	fn strip_debug_on_write() {
+		use crate::attributes::{SourceFileAttribute, UnknownAttribute};
+		
+		let mut class = hello();
+		class.attributes.push(Attribute::SourceFile(SourceFileAttribute::new(String::from("Test.java"))));
+		class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Deprecated"), Vec::new())));
+		if let Some(Attribute::Code(code)) = class.methods[2].attributes.first_mut() {
+			code.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("LineNumberTable"), vec![0, 0])));
+		}
+		let options = WriteOptions {
+			strip_debug: true,
+			..Default::default()
+		};
+		let mut bytes = Vec::new();
+		class.write_with(&mut bytes, &options).unwrap();
+		let mut stripped = hello();
+		stripped.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Deprecated"), Vec::new())));
+		assert_eq!(ClassFile::parse(&mut Cursor::new(&bytes)).unwrap(), stripped);
+	}
+	
+	#[test]
+	fn parse_from_non_seekable_reader() {
+		let mut bytes = Vec::new();
+		class().write(&mut bytes).unwrap();
+		// neither half of the chain can seek
+		let (head, tail) = bytes.split_at(bytes.len() / 2);
+		let mut chain = Read::chain(head, tail);
+		assert_eq!(round_trip::<ClassFile, _>(&mut chain), bytes);
+	}
+	
+	#[test]
+	fn bad_method_name_has_context() {
+		// class attribute count (2), then the name of the last method followed by its descriptor and
+		// attribute count
+		let (err, index) = corrupt(2 + 6);
+		assert_eq!(err.contexts(), vec!["methods[1]", "name"]);
+		assert!(matches!(err.root(), ParserError::BadCpIndex(0xFFFF)));
+		// the offset points just past the bad index
+		assert_eq!(err.offset(), Some(index + 2));
+		assert_eq!(err.to_string(), format!("methods[1].name at offset {:#x}: Invalid constant pool index: 65535 (while parsing Test (Java 8))", index + 2));
+	}
+	
+	#[test]
+	fn bad_field_descriptor_has_context() {
+		// class attribute count (2), both methods (16), method count (2), then the descriptor of the
+		// field followed by its attribute count
+		let (err, index) = corrupt(2 + 16 + 2 + 4);
+		assert_eq!(err.breadcrumb(), "fields[0].descriptor");
+		assert!(matches!(err.root(), ParserError::BadCpIndex(0xFFFF)));
+		assert_eq!(err.offset(), Some(index + 2));
+	}
+	
+	#[test]
+	fn bad_constant_tag_has_offset() {
+		let mut bytes = Vec::new();
+		class().write(&mut bytes).unwrap();
+		// magic (4), version (4), pool size (2), then the tag of the first constant
+		bytes[10] = 0xEE;
+		let err = ClassFile::parse(&mut Cursor::new(bytes)).unwrap_err();
+		assert_eq!(err.breadcrumb(), "constant_pool[1]");
+		assert_eq!(err.offset(), Some(11));
+		assert!(err.to_string().starts_with("constant_pool[1] at offset 0xb: "));
+	}
+	
+	#[test]
+	fn exceptions_round_trip() {
+		let mut class = class();
+		let thrown = vec![String::from("java/io/IOException"), String::from("java/lang/InterruptedException")];
+		class.methods[0].attributes.push(Attribute::Exceptions(ExceptionsAttribute::new(thrown.clone())));
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		
+		let parsed = ClassFile::parse(&mut Cursor::new(bytes)).unwrap();
+		assert_eq!(parsed.methods[0].attributes, vec![Attribute::Exceptions(ExceptionsAttribute::new(thrown))]);
+	}
+	
+	/// A toy attribute naming its owner through the constant pool
+	#[derive(Clone, Debug, PartialEq)]
+	struct Metadata {
+		owner: String,
+		revision: u8
+	}
+	
+	impl CustomAttribute for Metadata {
+		fn parser(&self) -> &dyn CustomAttributeParser {
+			&MetadataParser
+		}
+	}
+	
+	struct MetadataParser;
+	
+	impl CustomAttributeParser for MetadataParser {
+		fn name(&self) -> &str {
+			"org.acme.Metadata"
+		}
+		
+		fn parse(&self, context: &mut AttributeContext, buf: &mut Cursor<Vec<u8>>) -> Result<Box<dyn CustomAttribute>> {
+			Ok(Box::new(Metadata {
+				owner: context.constant_pool.utf8_inner(buf.read_u16::<BigEndian>()?)?,
+				revision: buf.read_u8()?
+			}))
+		}
+		
+		fn write(&self, attribute: &dyn CustomAttribute, context: &mut AttributeWriteContext, wtr: &mut dyn Write) -> Result<()> {
+			let metadata = attribute.downcast_ref::<Metadata>().unwrap();
+			wtr.write_u16::<BigEndian>(context.constant_pool.utf8(metadata.owner.clone()))?;
+			wtr.write_u8(metadata.revision)?;
+			Ok(())
+		}
+	}
+	
+	#[test]
+	fn custom_attribute_round_trip() {
+		let mut class = class();
+		class.attributes.push(Attribute::Custom(Box::new(Metadata { owner: String::from("acme"), revision: 1 })));
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		
+		// without a parser the attribute is kept as it is
+		let unparsed = ClassFile::parse(&mut Cursor::new(bytes.clone())).unwrap();
+		assert!(matches!(&unparsed.attributes[..], [Attribute::Unknown(x)] if x.name == "org.acme.Metadata"));
+		
+		let mut options = ParseOptions::default();
+		options.custom_attributes.register(MetadataParser);
+		let mut parsed = ClassFile::parse_with(&mut Cursor::new(bytes.clone()), &options).unwrap();
+		assert_eq!(parsed, class);
+		// parsers that only take an owned buffer are given a copy of the contents
+		assert_eq!(ClassFile::parse_bytes_with(&bytes, &options).unwrap(), class);
+		
+		match &mut parsed.attributes[0] {
+			Attribute::Custom(x) => x.downcast_mut::<Metadata>().unwrap().revision = 2,
+			x => panic!("Expected a custom attribute, found {:?}", x)
+		}
+		let mut bytes = Vec::new();
+		parsed.clone().write(&mut bytes).unwrap();
+		let reparsed = ClassFile::parse_with(&mut Cursor::new(bytes), &options).unwrap();
+		assert_eq!(reparsed, parsed);
+		assert_ne!(reparsed, class);
+		match &reparsed.attributes[0] {
+			Attribute::Custom(x) => assert_eq!(x.downcast_ref::<Metadata>(), Some(&Metadata { owner: String::from("acme"), revision: 2 })),
+			x => panic!("Expected a custom attribute, found {:?}", x)
+		}
+	}
+	
+	fn rot13(value: &str) -> String {
+		value.chars().map(|c| match c {
+			'a'..='m' | 'A'..='M' => (c as u8 + 13) as char,
+			'n'..='z' | 'N'..='Z' => (c as u8 - 13) as char,
+			c => c
+		}).collect()
+	}
+	
+	#[test]
+	fn map_string_constants() {
+		use crate::ast::{Insn, LdcInsn, LdcType};
+		
+		let mut class = hello();
+		class.fields[0].descriptor = String::from("Ljava/lang/String;");
+		class.fields[0].attributes.push(Attribute::ConstantValue(ConstantValueAttribute::new(ConstantValue::String(String::from("Jbeyq")))));
+		let insns = &mut class.methods[2].code().unwrap().insns.insns;
+		insns[1] = Insn::Ldc(LdcInsn::new(LdcType::String(String::from("Uryyb"))));
+		insns.insert(1, Insn::Ldc(LdcInsn::new(LdcType::String(String::from("plain")))));
+		
+		let mut seen = Vec::new();
+		let replaced = class.map_string_constants(|value, context| {
+			seen.push((value.to_string(), format!("{:?}", context)));
+			if value == "plain" {
+				None
+			} else {
+				Some(rot13(value))
+			}
+		});
+		assert_eq!(replaced, 2);
+		assert_eq!(seen, vec![
+			(String::from("Jbeyq"), String::from(r#"Field { name: "count", descriptor: "Ljava/lang/String;" }"#)),
+			(String::from("plain"), String::from(r#"Method { name: "greet", descriptor: "()V" }"#)),
+			(String::from("Uryyb"), String::from(r#"Method { name: "greet", descriptor: "()V" }"#))
+		]);
+		
+		assert_eq!(class.fields[0].constant_value_ref(), Some(&ConstantValue::String(String::from("World"))));
+		let insns = &class.methods[2].code_ref().unwrap().insns.insns;
+		assert_eq!(insns[1], Insn::Ldc(LdcInsn::new(LdcType::String(String::from("plain")))));
+		assert_eq!(insns[2], Insn::Ldc(LdcInsn::new(LdcType::String(String::from("Hello")))));
+	}
+	
+	#[test]
+	fn oversized_attribute_length_is_truncated() {
+		let mut class = class();
+		class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Blob"), vec![1, 2, 3])));
+		let mut bytes = Vec::new();
+		class.write_with(&mut bytes, &WriteOptions { allow_unknown_attributes: true, ..Default::default() }).unwrap();
+		// the length of the last attribute, claiming nearly 4GiB
+		let index = bytes.len() - 7;
+		bytes[index..index + 4].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xF0]);
+		let err = ClassFile::parse(&mut Cursor::new(bytes)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::Truncated { what: "attribute", wanted: 0xFFFFFFF0 }), "{}", err);
+	}
+	
+	#[test]
+	fn oversized_code_length_is_truncated() {
+		let mut bytes = Vec::new();
+		hello().write(&mut bytes).unwrap();
+		// max_stack, max_locals and code_length of greet, followed by its getstatic
+		let at = bytes.windows(9).position(|x| x == [0, 2, 0, 0, 0, 0, 0, 9, 0xB2]).unwrap();
+		bytes[at + 4..at + 8].copy_from_slice(&[0, 0, 0xFF, 0xFF]);
+		let err = ClassFile::parse(&mut Cursor::new(bytes.clone())).unwrap_err();
+		assert!(matches!(err.root(), ParserError::Truncated { what: "code", wanted: 0xFFFF }), "{}", err);
+		// longer than any method may be, which is rejected before reading it
+		bytes[at + 4..at + 8].copy_from_slice(&[0x7F, 0xFF, 0xFF, 0xFF]);
+		let err = ClassFile::parse(&mut Cursor::new(bytes)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::MethodTooLarge { size: 0x7FFFFFFF }), "{}", err);
+	}
+	
+	#[test]
+	fn unknown_attributes_keep_their_constants() {
+		// @Marker, whose type refers to the first constant
+		let mut constant_pool = ConstantPoolWriter::new();
+		assert_eq!(constant_pool.utf8("LMarker;"), 1);
+		let mut class = class();
+		class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("RuntimeVisibleAnnotations"), vec![0, 1, 0, 1, 0, 0])));
+		let mut bytes = Vec::new();
+		class.write_with_pool(&mut bytes, constant_pool).unwrap();
+		let annotation_type = |bytes: &[u8]| {
+			let pool = ConstantPool::parse(&mut Cursor::new(&bytes[8..])).unwrap();
+			pool.utf8(1).unwrap().str.clone()
+		};
+		assert_eq!(annotation_type(&bytes), "LMarker;");
+		let (parsed, pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default()).unwrap();
+		
+		// a new pool puts something else at the index the annotation refers to
+		let mut rebuilt = Vec::new();
+		parsed.write_with(&mut rebuilt, &WriteOptions { allow_unknown_attributes: true, ..Default::default() }).unwrap();
+		assert_ne!(annotation_type(&rebuilt), "LMarker;");
+		
+		// so that is refused by default
+		let err = parsed.write(&mut Vec::new()).unwrap_err();
+		assert!(err.to_string().contains("RuntimeVisibleAnnotations"), "{}", err);
+		
+		// while writing with the original pool keeps the annotation intact
+		let mut preserved = Vec::new();
+		parsed.write_with_pool(&mut preserved, ConstantPoolWriter::from_pool(&pool)).unwrap();
+		assert_eq!(annotation_type(&preserved), "LMarker;");
+		assert_eq!(preserved, bytes);
+	}
+	
+	/// The test class with code in both methods, the second starting with an unknown opcode
+	fn bad_second_method() -> Vec<u8> {
+		use crate::code::CodeAttribute;
+		
+		let mut class = class();
+		class.access_flags = ClassAccessFlags::PUBLIC;
+		for (i, method) in class.methods.iter_mut().enumerate() {
+			method.access_flags = MethodAccessFlags::PUBLIC;
+			let mut code = CodeAttribute { max_stack: 1 + i as u16, max_locals: 1, ..CodeAttribute::default() };
+			code.insns.ldc_int(1).pop().return_void();
+			method.set_code(Some(code));
+		}
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		// max_stack, max_locals and code_length of the second method, then its first opcode
+		let at = bytes.windows(7).position(|x| x == [0, 2, 0, 1, 0, 0, 0]).unwrap();
+		bytes[at + 8] = 0xCB;
+		bytes
+	}
+	
+	#[test]
+	fn bad_instruction_names_its_method() {
+		let err = ClassFile::parse(&mut Cursor::new(bad_second_method())).unwrap_err();
+		match &err {
+			ParserError::InClass { class, version, member, .. } => {
+				assert_eq!(class.as_deref(), Some("Test"));
+				assert_eq!(*version, Some(ClassVersion::new_major(MajorVersion::JAVA_8)));
+				assert_eq!(member.as_ref().map(|x| x.to_string()).as_deref(), Some("method:second()V"));
+			},
+			x => panic!("{:?}", x)
+		}
+		assert!(matches!(err.root(), ParserError::UnknownInstruction { opcode: 0xCB }));
+		assert_eq!(err.contexts()[0], "methods[1]");
+		assert_eq!(err.describe_class().unwrap(), "method second()V of Test (Java 8)");
+		assert!(err.to_string().ends_with("(while parsing method second()V of Test (Java 8))"), "{}", err);
+	}
+	
+	#[test]
+	fn lenient_code_keeps_other_methods() {
+		let bytes = bad_second_method();
+		let options = ParseOptions { lenient_code: true, ..Default::default() };
+		let (mut class, pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options).unwrap();
+		
+		let first = class.method_mut("first", "()V").unwrap().code().unwrap();
+		assert_eq!(first.insns.len(), 3);
+		let second = class.method("second", "()V").unwrap();
+		assert!(matches!(&second.attributes[..], [Attribute::UndecodedCode(x)] if x.buf[8] == 0xCB));
+		
+		let warnings = class.parse_warnings();
+		assert_eq!(warnings.len(), 1);
+		assert_eq!(warnings[0].member, "second ()V");
+		assert_eq!(warnings[0].path.to_string(), "method:second()V/attribute[0]");
+		assert!(warnings[0].error.contains("Unknown Instruction CB"), "{}", warnings[0].error);
+		
+		// the undecoded code is written back as it was
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&pool)).unwrap();
+		assert_eq!(out, bytes);
+		assert!(class.write(&mut Vec::new()).is_err());
+	}
+	
+	/// The test class with attributes referring to constants of the wrong type: a SourceFile naming
+	/// a String, a Signature and a ConstantValue naming a Class, and a local variable named by a
+	/// String. Also returns the indices of the String constant and of the name of the first method.
+	fn type_confused() -> (Vec<u8>, u16, u16) {
+		use crate::code::CodeAttribute;
+		
+		let mut pool = ConstantPoolWriter::new();
+		let utf8 = pool.utf8("Test.java");
+		let string = pool.string(utf8);
+		let name = pool.utf8("java/lang/Object");
+		let class_constant = pool.class(name);
+		let descriptor = pool.utf8("LTest;");
+		let first = pool.utf8("first");
+		
+		let mut class = class();
+		class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("SourceFile"), string.to_be_bytes().to_vec())));
+		class.fields[0].attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("ConstantValue"), class_constant.to_be_bytes().to_vec())));
+		class.methods[0].attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Signature"), class_constant.to_be_bytes().to_vec())));
+		let second = &mut class.methods[1];
+		second.access_flags = MethodAccessFlags::PUBLIC;
+		let mut code = CodeAttribute { max_stack: 0, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.return_void();
+		// start_pc, length, name, descriptor and index of one local variable
+		let mut table = vec![0, 1, 0, 0, 0, 1];
+		table.extend_from_slice(&string.to_be_bytes());
+		table.extend_from_slice(&descriptor.to_be_bytes());
+		table.extend_from_slice(&[0, 0]);
+		code.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("LocalVariableTable"), table)));
+		second.set_code(Some(code));
+		
+		let mut bytes = Vec::new();
+		class.write_with_pool(&mut bytes, pool).unwrap();
+		(bytes, string, first)
+	}
+	
+	#[test]
+	fn lenient_constant_types_keep_confused_attributes() {
+		let (bytes, _, _) = type_confused();
+		let err = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::IncompatibleCPEntry { .. }), "{}", err);
+		
+		let options = ParseOptions { lenient_constant_types: true, ..Default::default() };
+		let (class, pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options).unwrap();
+		let warnings = class.parse_warnings();
+		let found: Vec<(&str, String)> = warnings.iter().map(|x| (x.member.as_str(), x.path.to_string())).collect();
+		assert_eq!(found, vec![
+			("count I", String::from("field:count:I/attribute[0]")),
+			("first ()V", String::from("method:first()V/attribute[0]")),
+			("second ()V", String::from("method:second()V/code/attribute[0]")),
+			("", String::from("class/attribute[0]"))
+		]);
+		assert!(warnings[0].error.contains("expected \"constant value\""), "{}", warnings[0].error);
+		assert!(warnings.iter().skip(1).all(|x| x.error.contains("expected \"Utf8\"")), "{:?}", warnings);
+		assert!(matches!(&class.attributes[..], [Attribute::Undecoded(x)] if x.kind() == AttributeKind::SourceFile));
+		
+		// the attributes are written back as they were
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&pool)).unwrap();
+		assert_eq!(out, bytes);
+		
+		let mut stripped = class.clone();
+		stripped.strip_debug_info();
+		assert!(stripped.attributes.is_empty());
+	}
+	
+	#[test]
+	fn lenient_constant_types_still_reject_bad_members() {
+		let options = ParseOptions { lenient_constant_types: true, ..Default::default() };
+		let (bytes, string, first) = type_confused();
+		
+		// a method named by a String
+		let mut renamed = bytes.clone();
+		let mut method = vec![0x04, 0x01];
+		method.extend_from_slice(&first.to_be_bytes());
+		let at = renamed.windows(4).position(|x| x == method.as_slice()).unwrap();
+		renamed[at + 2..at + 4].copy_from_slice(&string.to_be_bytes());
+		let err = ClassFile::parse_with(&mut Cursor::new(&renamed), &options).unwrap_err();
+		assert!(matches!(err.root(), ParserError::IncompatibleCPEntry { .. }), "{}", err);
+		assert_eq!(err.contexts()[0], "methods[0]");
+		
+		// a SourceFile naming a missing constant, the last two bytes of the class
+		let mut missing = bytes;
+		let end = missing.len();
+		missing[end - 2..].copy_from_slice(&[0xFF, 0xFF]);
+		let err = ClassFile::parse_with(&mut Cursor::new(&missing), &options).unwrap_err();
+		assert!(matches!(err.root(), ParserError::BadCpIndex(0xFFFF)), "{}", err);
+	}
+	
+	#[test]
+	fn lenient_pool_salvages_unrecognised_tags() {
+		let mut bytes = Vec::new();
+		hello().write(&mut bytes).unwrap();
+		let count = u16::from_be_bytes([bytes[8], bytes[9]]);
+		let mut rdr = Cursor::new(&bytes[8..]);
+		ConstantPool::parse(&mut rdr).unwrap();
+		let end = 8 + rdr.position() as usize;
+		// an extra constant at the end of the pool, so that no index moves
+		let with_constant = |contents: &[u8]| {
+			let mut bogus = bytes[..8].to_vec();
+			bogus.extend_from_slice(&(count + 1).to_be_bytes());
+			bogus.extend_from_slice(&bytes[10..end]);
+			bogus.push(99);
+			bogus.extend_from_slice(contents);
+			bogus.extend_from_slice(&bytes[end..]);
+			bogus
+		};
+		
+		let bogus = with_constant(&[0xAB, 0xCD]);
+		let err = ClassFile::parse(&mut Cursor::new(&bogus)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::Unrecognized("constant tag", tag) if tag == "99"), "{}", err);
+		
+		let options = ParseOptions { lenient_pool: true, ..Default::default() };
+		let (class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bogus), &options).unwrap();
+		assert!(class.is_partial());
+		let partial = class.partial.clone().unwrap();
+		assert_eq!((partial.index, partial.tag, partial.guessed_length), (count, 99, Some(2)));
+		assert_eq!(partial.error.to_string(), format!("constant_pool[{}]: Unrecognized constant tag: 99", count));
+		assert!(matches!(partial.error.root(), ParserError::Unrecognized("constant tag", tag) if tag == "99"), "{}", partial.error);
+		assert_eq!(class.this_class, ClassName::from("Test"));
+		assert_eq!(class.super_class, Some(ClassName::from("java/lang/Object")));
+		assert!(class.methods.is_empty());
+		assert!((1..count).all(|index| constant_pool.get(index).is_ok()));
+		assert!(constant_pool.get(count).is_err());
+		let err = class.write(&mut Vec::new()).unwrap_err();
+		assert!(err.to_string().contains("partly parsed"), "{}", err);
+		
+		// too long to guess, so only the constants before it are known
+		let bogus = with_constant(&[0; 20]);
+		let (class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bogus), &options).unwrap();
+		assert!(class.is_partial());
+		assert_eq!(class.partial.unwrap().guessed_length, None);
+		assert_eq!(class.this_class, ClassName::from(""));
+		assert_eq!(constant_pool.utf8_inner(1).unwrap(), "Test");
+		
+		// in the middle of the pool, in place of the Utf8 "Code", the rest of the pool is found
+		let code = [1, 0, 4, b'C', b'o', b'd', b'e'];
+		let at = bytes[..end].windows(code.len()).position(|window| window == code).unwrap();
+		let mut bogus = bytes.clone();
+		bogus[at] = 99;
+		let (class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bogus), &options).unwrap();
+		let partial = class.partial.clone().unwrap();
+		assert!(partial.index < count - 1, "{:?}", partial);
+		assert_eq!(partial.guessed_length, Some(6));
+		assert_eq!(class.this_class, ClassName::from("Test"));
+		assert_eq!(class.super_class, Some(ClassName::from("java/lang/Object")));
+		assert!((1..partial.index).all(|index| constant_pool.get(index).is_ok()));
+		
+		// a complete pool is parsed as usual
+		let class = ClassFile::parse_with(&mut Cursor::new(&bytes), &options).unwrap();
+		assert!(!class.is_partial());
+		assert_eq!(class, hello());
+	}
+	
+	#[test]
+	fn strip_debug_info_and_synthetic_members() {
+		use crate::ast::Insn;
+		use crate::attributes::{LineNumber, LineNumberTableAttribute, LocalVariable, LocalVariableTableAttribute, SignatureAttribute, SourceDebugExtensionAttribute, SourceFileAttribute};
+		
+		let mut class = hello();
+		class.attributes.push(Attribute::SourceFile(SourceFileAttribute::new(String::from("Test.java"))));
+		class.attributes.push(Attribute::SourceDebugExtension(SourceDebugExtensionAttribute::new(String::from("SMAP"))));
+		class.attributes.push(Attribute::Signature(SignatureAttribute::new(String::from("Ljava/lang/Object;"))));
+		class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Deprecated"), Vec::new())));
+		let code = class.method_mut("greet", "()V").unwrap().code().unwrap();
+		let (start, end) = (code.insns.new_label(), code.insns.new_label());
+		code.insns.insns.insert(0, Insn::Label(start));
+		code.insns.insns.push(Insn::Label(end));
+		code.attributes.push(Attribute::LocalVariableTable(LocalVariableTableAttribute::new(vec![LocalVariable { start, end, name: String::from("this"), descriptor: String::from("LTest;"), index: 0 }])));
+		code.attributes.push(Attribute::LineNumberTable(LineNumberTableAttribute::new(vec![LineNumber::new(start, 1)])));
+		code.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("LocalVariableTypeTable"), vec![0, 0])));
+		
+		let mut stripped = class.clone();
+		stripped.strip_debug_info();
+		assert_eq!(stripped.attributes, class.attributes[2..]);
+		let code = stripped.method_mut("greet", "()V").unwrap().code().unwrap();
+		assert!(code.attributes.is_empty());
+		// the labels only the local variable and line number used are gone
+		assert_eq!(code.insns.len(), 4);
+		let code = code.clone();
+		
+		let options = WriteOptions { allow_unknown_attributes: true, ..Default::default() };
+		let mut full = Vec::new();
+		class.write_with(&mut full, &options).unwrap();
+		let mut bytes = Vec::new();
+		stripped.write(&mut bytes).unwrap();
+		assert!(bytes.len() < full.len());
+		let mut parsed = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap();
+		assert_eq!(parsed.attributes, stripped.attributes);
+		assert_eq!(parsed.method_mut("greet", "()V").unwrap().code().unwrap().insns.insns, code.insns.insns);
+		let mut rewritten = Vec::new();
+		parsed.write(&mut rewritten).unwrap();
+		assert_eq!(rewritten, bytes);
+		
+		stripped.add_field(Field {
+			access_flags: FieldAccessFlags::PRIVATE | FieldAccessFlags::SYNTHETIC,
+			name: String::from("this$0"),
+			descriptor: String::from("LOuter;"),
+			attributes: Vec::new(),
+			original_indices: None
+		}).unwrap();
+		let mut bridge = method("compareTo", "(Ljava/lang/Object;)I");
+		bridge.access_flags |= MethodAccessFlags::BRIDGE | MethodAccessFlags::ABSTRACT;
+		stripped.add_method(bridge).unwrap();
+		let mut marked = method("access$000", "()V");
+		marked.access_flags |= MethodAccessFlags::ABSTRACT;
+		marked.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Synthetic"), Vec::new())));
+		stripped.add_method(marked).unwrap();
+		let members = (stripped.fields.clone(), stripped.methods.clone());
+		
+		assert_eq!(stripped.strip_synthetic_members(), (1, 2));
+		assert_eq!(stripped.fields, members.0[..1]);
+		assert_eq!(stripped.methods, members.1[..3]);
+		assert_eq!(stripped.strip_synthetic_members(), (0, 0));
+	}
+	
+	#[test]
+	fn minimum_runtime_version_follows_features() {
+		use crate::ast::{BootstrapArgument, BootstrapMethodType, DynamicConstant, InvokeDynamicInsn, InvokeInsn, InvokeType, MethodHandle, OpType};
+		use crate::code::CodeAttribute;
+		use crate::constantpool::MethodHandleKind;
+		
+		let mut class = class();
+		class.version = ClassVersion::new_major(MajorVersion::JAVA_6);
+		assert_eq!(class.minimum_runtime_version(), MajorVersion::JAVA_6);
+		
+		let mut indy = InvokeDynamicInsn::new(
+			String::from("get"),
+			String::from("()Ljava/util/function/Supplier;"),
+			BootstrapMethodType::InvokeStatic,
+			ClassName::from("java/lang/invoke/LambdaMetafactory"),
+			String::from("metafactory"),
+			String::from("()V"),
+			Vec::new()
+		);
+		let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.push(Insn::InvokeDynamic(Box::new(indy.clone()))).pop().return_void();
+		let mut lambda = method("lambda", "()V");
+		lambda.set_code(Some(code.clone()));
+		class.methods.push(lambda);
+		assert_eq!(class.minimum_runtime_version(), MajorVersion::JAVA_7);
+		
+		// calling a default method through super needs an interface method ref
+		let mut call = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+		call.insns.load(OpType::Reference, 0)
+			.push(Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Special, ClassName::from("java/util/Iterator"), String::from("remove"), String::from("()V"), true))))
+			.return_void();
+		let mut remove = method("remove", "()V");
+		remove.set_code(Some(call));
+		class.methods.push(remove);
+		assert_eq!(class.minimum_runtime_version(), MajorVersion::JAVA_8);
+		class.methods.pop();
+		
+		// a dynamic constant as a bootstrap argument needs condy support
+		let handle = MethodHandle::new(MethodHandleKind::InvokeStatic, ClassName::from("Test"), String::from("bsm"), String::from("()V"), false);
+		indy.bootstrap_arguments.push(BootstrapArgument::Dynamic(Box::new(DynamicConstant::new(String::from("x"), String::from("I"), handle, Vec::new()))));
+		code.insns.insns[0] = Insn::InvokeDynamic(Box::new(indy));
+		class.methods[2].set_code(Some(code));
+		assert_eq!(class.minimum_runtime_version(), MajorVersion::JAVA_11);
+		
+		for (name, version) in [("NestHost", MajorVersion::JAVA_11), ("Record", MajorVersion::JAVA_16), ("PermittedSubclasses", MajorVersion::JAVA_17)].iter() {
+			class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from(*name), Vec::new())));
+			assert_eq!(class.minimum_runtime_version(), *version);
+		}
+		
+		// never lower than declared
+		class.version = ClassVersion::new_major(MajorVersion::JAVA_21);
+		assert_eq!(class.minimum_runtime_version(), MajorVersion::JAVA_21);
+	}
+	
+	#[test]
+	fn minimum_runtime_version_matches_downgrade() {
+		use crate::ast::{BootstrapMethodType, InvokeDynamicInsn, LdcInsn, PopInsn};
+		use crate::code::CodeAttribute;
+		
+		// hand-built, so the declared version is older than the features
+		let mut class = class();
+		class.version = ClassVersion::JDK_1_4;
+		let with_code = |name: &str, insns: Vec<Insn>| {
+			let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+			code.insns.insns = insns;
+			code.insns.return_void();
+			let mut method = method(name, "()V");
+			method.set_code(Some(code));
+			method
+		};
+		let ldc_class = Insn::Ldc(LdcInsn::new(LdcType::Class(String::from("java/lang/String"))));
+		class.methods.push(with_code("type", vec![ldc_class, Insn::Pop(PopInsn::new(false))]));
+		assert_eq!(class.minimum_runtime_version(), MajorVersion::JAVA_5);
+		
+		let concat = InvokeDynamicInsn::new(
+			String::from("makeConcatWithConstants"),
+			String::from("()Ljava/lang/String;"),
+			BootstrapMethodType::InvokeStatic,
+			ClassName::from("java/lang/invoke/StringConcatFactory"),
+			String::from("makeConcatWithConstants"),
+			String::from("()Ljava/lang/invoke/CallSite;"),
+			Vec::new()
+		);
+		let mut concat = with_code("concat", vec![Insn::from(concat), Insn::Pop(PopInsn::new(false))]);
+		concat.access_flags = MethodAccessFlags::PUBLIC;
+		let mut interface = class.clone();
+		interface.access_flags = ClassAccessFlags::PUBLIC | ClassAccessFlags::INTERFACE | ClassAccessFlags::ABSTRACT;
+		interface.methods = vec![with_code("run", Vec::new())];
+		assert_eq!(interface.minimum_runtime_version(), MajorVersion::JAVA_8);
+		interface.methods.push(concat);
+		assert_eq!(interface.minimum_runtime_version(), MajorVersion::JAVA_9);
+		
+		// the same table lists what a downgrade cannot handle
+		interface.version = ClassVersion::JAVA_21;
+		assert!(interface.unsupported_features(MajorVersion::JAVA_9).is_empty());
+		assert_eq!(interface.unsupported_features(MajorVersion::JAVA_8), vec![String::from("string concatenation with invokedynamic in concat()V (needs Java 9)")]);
+		assert_eq!(interface.unsupported_features(MajorVersion::JDK_1_4).len(), 4);
+	}
+	
+	#[test]
+	fn shared_classes_are_read_concurrently() {
+		use std::sync::Arc;
+		
+		/// The instructions, declared exceptions and signature of every method
+		fn analyse(class: &ClassFile) -> Vec<String> {
+			class.methods.iter().map(|method| format!("{}{} {:?} {:?} {:?}",
+				method.name,
+				method.descriptor,
+				method.code_ref().map(|code| code.insns.iter().filter(|insn| !matches!(insn, Insn::Label(_))).count()),
+				method.exceptions_ref(),
+				method.signature_ref()
+			)).collect()
+		}
+		
+		let bytes = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let class = Arc::new(ClassFile::parse(&mut Cursor::new(bytes)).unwrap());
+		let expected = analyse(&class);
+		assert!(expected.iter().any(|method| method.contains("Some(")));
+		let threads: Vec<_> = (0..4).map(|_| {
+			let class = Arc::clone(&class);
+			std::thread::spawn(move || (analyse(&class), class.referenced_members()))
+		}).collect();
+		for thread in threads {
+			let (analysis, refs) = thread.join().unwrap();
+			assert_eq!(analysis, expected);
+			assert_eq!(refs, class.referenced_members());
+		}
+		assert!(!class.is_modified());
+	}
+	
+	#[test]
+	fn modifications_are_tracked() {
+		use crate::ast::NopInsn;
+		
+		let bytes = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(bytes), &options).unwrap();
+		assert!(!class.is_modified());
+		
+		// reading changes nothing
+		class.summary();
+		class.referenced_members();
+		for method in class.methods() {
+			if let Some(code) = method.code_ref() {
+				assert!(code.insns.iter().count() > 0);
+			}
+		}
+		class.write_with_pool(&mut Vec::new(), ConstantPoolWriter::from_pool(&constant_pool)).unwrap();
+		assert!(!class.is_modified());
+		
+		let name = class.methods().iter().find(|method| method.code_ref().is_some()).map(|method| (method.name.clone(), method.descriptor.clone())).unwrap();
+		let code = class.method_mut(&name.0, &name.1).unwrap().code().unwrap();
+		let index = code.insns.iter().position(|insn| !matches!(insn, Insn::Label(_))).unwrap();
+		code.insns.replace(index, Insn::Nop(NopInsn::new()));
+		assert!(class.is_modified());
+		class.mark_clean();
+		assert!(!class.is_modified());
+		
+		// so is any other change through the code of a method
+		class.method_mut(&name.0, &name.1).unwrap().code().unwrap().max_stack += 1;
+		assert!(class.is_modified());
+		class.mark_clean();
+		class.method_mut(&name.0, &name.1).unwrap().code().unwrap().exceptions.clear();
+		assert!(class.is_modified());
+		class.mark_clean();
+		
+		assert!(class.method_mut("missing", "()V").is_none());
+		assert!(!class.is_modified());
+		class.methods_mut().pop();
+		assert!(class.is_modified());
+		class.mark_clean();
+		class.fields_mut().clear();
+		assert!(class.is_modified());
+		class.mark_clean();
+		class.set_access_flags(class.access_flags() | ClassAccessFlags::FINAL);
+		assert!(class.is_modified());
+		
+		assert!(ClassFile::new(class.version(), class.access_flags(), class.this_class().clone(), None).is_modified());
+	}
+	
+	#[test]
+	fn downgrading_drops_nest_attributes_and_reports_the_rest() {
+		use crate::ast::{BootstrapMethodType, InvokeDynamicInsn};
+		use crate::code::CodeAttribute;
+		
+		let mut class = class();
+		class.version = ClassVersion::new_major(MajorVersion::JAVA_17);
+		class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("NestHost"), vec![0, 1])));
+		let concat = InvokeDynamicInsn::new(
+			String::from("makeConcatWithConstants"),
+			String::from("()Ljava/lang/String;"),
+			BootstrapMethodType::InvokeStatic,
+			ClassName::from("java/lang/invoke/StringConcatFactory"),
+			String::from("makeConcatWithConstants"),
+			String::from("(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/String;[Ljava/lang/Object;)Ljava/lang/invoke/CallSite;"),
+			Vec::new()
+		);
+		let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.push(Insn::from(concat)).pop().return_void();
+		let mut concat = method("concat", "()V");
+		concat.set_code(Some(code));
+		class.methods.push(concat);
+		
+		let target = |major: MajorVersion| WriteOptions {
+			target_version: Some(ClassVersion::new_major(major)),
+			..WriteOptions::default()
+		};
+		let err = class.write_with(&mut Vec::new(), &target(MajorVersion::JAVA_6)).unwrap_err();
+		match err.root() {
+			ParserError::UnsupportedByTarget { target: MajorVersion::JAVA_6, features } => assert_eq!(features, &vec![
+				String::from("invokedynamic in concat()V (needs Java 7)"),
+				String::from("string concatenation with invokedynamic in concat()V (needs Java 9)")
+			]),
+			x => panic!("Expected unsupported features, found {:?}", x)
+		}
+		assert!(class.write_with(&mut Vec::new(), &target(MajorVersion::JAVA_8)).is_err());
+		
+		// the unknown NestHost attribute is dropped before it could stop the write
+		class.methods.pop();
+		assert_eq!(class.clone().downgrade(ClassVersion::JAVA_8).unwrap(), vec![String::from("NestHost attribute (needs Java 11)")]);
+		assert!(class.clone().downgrade(ClassVersion::JAVA_11).unwrap().is_empty());
+		let mut bytes = Vec::new();
+		class.write_with(&mut bytes, &target(MajorVersion::JAVA_8)).unwrap();
+		let parsed = ClassFile::parse(&mut Cursor::new(bytes)).unwrap();
+		assert_eq!(parsed.version, ClassVersion::new_major(MajorVersion::JAVA_8));
+		assert!(parsed.attributes.is_empty());
+		assert_eq!(parsed.minimum_runtime_version(), MajorVersion::JAVA_8);
+		
+		assert!(parsed.clone().downgrade(ClassVersion::new_major(MajorVersion::JAVA_11)).is_err());
+		let mut interface = parsed;
+		interface.access_flags |= ClassAccessFlags::INTERFACE;
+		let mut code = CodeAttribute { max_stack: 0, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.return_void();
+		let mut default = method("run", "()V");
+		default.set_code(Some(code));
+		interface.methods.push(default);
+		let mut call = CodeAttribute { max_stack: 0, max_locals: 1, ..CodeAttribute::default() };
+		call.insns.invoke_static_interface("java/util/Comparator", "naturalOrder", "()Ljava/util/Comparator;").pop().return_void();
+		let mut natural = method("natural", "()V");
+		natural.set_code(Some(call));
+		interface.methods.push(natural);
+		assert!(matches!(interface.downgrade(ClassVersion::new_major(MajorVersion::JAVA_7)).unwrap_err(),
+			ParserError::UnsupportedByTarget { features, .. } if features == vec![
+				String::from("interface method run()V with code (needs Java 8)"),
+				String::from("interface method natural()V with code (needs Java 8)"),
+				String::from("invokestatic of an interface method in natural()V (needs Java 8)")
+			]));
+	}
+	
+	#[test]
+	fn methods_without_code_are_flagged() {
+		use crate::code::CodeAttribute;
+		use crate::ast::Insn;
+		
+		let mut class = hello();
+		class.validate().unwrap();
+		let is_empty_code = |err: &ParserError| matches!(err.root(), ParserError::EmptyCode { method } if method == "greet()V");
+		
+		// written as it is, as the code may be added later, but not valid
+		class.method_mut("greet", "()V").unwrap().set_code(None);
+		class.write(&mut Vec::new()).unwrap();
+		let err = class.validate().unwrap_err();
+		assert!(is_empty_code(&err), "{}", err);
+		assert_eq!(err.contexts(), vec![String::from("methods[2]")]);
+		
+		// a code array of zero bytes is never written
+		let mut code = CodeAttribute::empty();
+		let label = code.insns.new_label();
+		code.insns.insns.push(Insn::Label(label));
+		class.method_mut("greet", "()V").unwrap().set_code(Some(code));
+		let err = class.write(&mut Vec::new()).unwrap_err();
+		assert!(is_empty_code(&err), "{}", err);
+		assert!(is_empty_code(&class.validate().unwrap_err()));
+		
+		// native methods have no code
+		class.method_mut("greet", "()V").unwrap().set_code(None);
+		class.method_mut("greet", "()V").unwrap().access_flags |= MethodAccessFlags::NATIVE;
+		class.validate().unwrap();
+		
+		class.access_flags |= ClassAccessFlags::FINAL;
+		let err = class.validate().unwrap_err();
+		assert!(matches!(err.root(), ParserError::IllegalAccessFlags { kind: "class", .. }), "{}", err);
+	}
+	
+	#[test]
+	fn interface_method_refs_before_java_8_are_warned_about() {
+		use crate::code::CodeAttribute;
+		
+		let mut class = class();
+		class.version = ClassVersion::JAVA_7;
+		let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.invoke_interface("java/util/List", "size", "()I")
+			.pop()
+			.invoke_static_interface("java/util/Comparator", "naturalOrder", "()Ljava/util/Comparator;")
+			.pop()
+			.return_void();
+		let mut run = method("run", "()V");
+		run.set_code(Some(code));
+		let path = MemberPath::method(&run);
+		class.methods.push(run);
+		
+		let warnings = class.validate().unwrap();
+		assert_eq!(warnings, vec![ValidationWarning {
+			path: Path::Insn(path.insn(2)),
+			feature: String::from("invokestatic of an interface method"),
+			minimum_version: MajorVersion::JAVA_8
+		}]);
+		assert_eq!(warnings[0].to_string(), "invokestatic of an interface method at method:run()V/code/insn[2] needs Java 8");
+		
+		class.version = ClassVersion::JAVA_8;
+		assert!(class.validate().unwrap().is_empty());
+	}
+	
+	#[test]
+	fn attributes_newer_than_the_class_are_warned_about() {
+		use crate::attributes::SignatureAttribute;
+		
+		let mut class = class();
+		class.version = ClassVersion::JDK_1_3;
+		assert!(class.validate().unwrap().is_empty());
+		class.attributes.push(Attribute::Signature(SignatureAttribute::new(String::from("Ljava/lang/Object;"))));
+		class.methods[0].attributes.push(Attribute::Exceptions(ExceptionsAttribute::new(vec![String::from("java/io/IOException")])));
+		let warnings = class.validate().unwrap();
+		assert_eq!(warnings, vec![ValidationWarning {
+			path: Path::Attribute(AttrPath::class(0)),
+			feature: String::from("Signature attribute"),
+			minimum_version: MajorVersion::JAVA_5
+		}]);
+		assert_eq!(warnings[0].to_string(), "Signature attribute at class/attribute[0] is ignored before Java 5");
+		
+		// written as it is, and read back as an unknown attribute that is still warned about
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		let parsed = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap();
+		assert!(matches!(&parsed.attributes[..], [Attribute::Unknown(x)] if x.kind() == AttributeKind::Signature));
+		assert_eq!(parsed.validate().unwrap(), warnings);
+		
+		class.version = ClassVersion::JAVA_5;
+		assert!(class.validate().unwrap().is_empty());
+	}
+	
+	#[test]
+	fn minor_versions_survive_a_round_trip() {
+		let mut class = class();
+		class.version = ClassVersion::new(MajorVersion::JDK_1_1, 3);
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		assert_eq!(bytes[4..8], [0, 3, 0, 45]);
+		let (parsed, pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default()).unwrap();
+		assert_eq!(parsed.version, ClassVersion { major: MajorVersion::JDK_1_1, minor: 3 });
+		assert!(parsed.version > ClassVersion::JDK_1_1 && parsed.version < ClassVersion::JDK_1_2);
+		let mut out = Vec::new();
+		parsed.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&pool)).unwrap();
+		assert_eq!(out, bytes);
+	}
+	
+	#[test]
+	fn class_names_are_checked() {
+		let mut class = class();
+		class.interfaces = vec![ClassName::from("a/I"), ClassName::from("a/J"), ClassName::from("a/I"), ClassName::from("a/I")];
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		let options = ParseOptions { original_indices: true, ..Default::default() };
+		let (parsed, pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options).unwrap();
+		let warnings = parsed.parse_warnings();
+		assert_eq!(warnings, vec![ParseWarning {
+			member: String::new(),
+			path: Path::Class,
+			error: String::from("interface a/I is listed more than once")
+		}]);
+		let indices = parsed.original_indices.unwrap();
+		assert_eq!(pool.class_name(indices.this_class).unwrap(), "Test");
+		assert_eq!(pool.class_name(indices.super_class).unwrap(), "java/lang/Object");
+		assert_eq!(indices.interfaces.len(), 4);
+		assert_eq!(indices.interfaces[0], indices.interfaces[2]);
+		assert_ne!(indices.interfaces[0], indices.interfaces[1]);
+		
+		class.this_class = ClassName::from("a/b;c");
+		class.super_class = Some(class.this_class.clone());
+		class.interfaces = vec![ClassName::from("[La/I;")];
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		let parsed = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap();
+		assert!(parsed.original_indices.is_none());
+		let errors: Vec<String> = parsed.parse_warnings().into_iter().map(|x| x.error).collect();
+		assert_eq!(errors, vec![
+			"this_class a/b;c is not a valid internal class name",
+			"super_class a/b;c is not a valid internal class name",
+			"interface [La/I; is an array class",
+			"a/b;c extends itself"
+		]);
+		assert_eq!(parsed.expected_file_name(), "a/b;c.class");
+	}
+	
+	#[test]
+	fn parse_bytes_matches_parse() {
+		let bytes = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let options = ParseOptions { raw_attributes: true, original_indices: true, ..ParseOptions::default() };
+		let (class, pool) = ClassFile::parse_bytes_with_pool(bytes, &options).unwrap();
+		let (expected, expected_pool) = ClassFile::parse_with_pool(&mut Cursor::new(bytes), &options).unwrap();
+		assert_eq!(class, expected);
+		assert_eq!(pool, expected_pool);
+		assert_eq!(ClassFile::parse_bytes(bytes).unwrap(), ClassFile::parse(&mut Cursor::new(bytes)).unwrap());
+		
+		// errors, including where they occurred, are the same wherever the bytes end
+		for length in 0..bytes.len() {
+			let err = ClassFile::parse_bytes(&bytes[..length]).unwrap_err();
+			let expected = ClassFile::parse(&mut Cursor::new(&bytes[..length])).unwrap_err();
+			assert_eq!(err.to_string(), expected.to_string(), "{}", length);
+		}
 	}
 }
@@ -1,122 +1,787 @@
-use crate::attributes::{Attribute, AttributeSource, Attributes};
-use crate::constantpool::{ConstantPool, ConstantType, CPIndex, ConstantPoolWriter};
+use crate::attributes::{Attribute, AttributeContext, AttributeKind, AttributeSource, Attributes, LocalVariable, LocalVariableTableAttribute, WriteContext};
+use crate::constantpool::{ConstantPool, ConstantType, CPIndex, ConstantPoolWriter, MethodHandleInfo, MethodHandleKind};
 use crate::version::ClassVersion;
-use crate::error::{Result, ParserError};
+use crate::classfile::ParseOptions;
+use crate::error::{Result, ParserError, ErrorContext};
+use crate::path::PathSegment;
 use crate::ast::*;
-use crate::insnlist::InsnList;
-use crate::utils::{ReadUtils, MapUtils};
-use crate::types::{Type, parse_method_desc};
+use crate::insnlist::{InsnList, LabelBijection};
+use crate::cfg::ControlFlowGraph;
+use crate::visitor::InsnVisitor;
+use crate::utils::{ReadUtils, fits_u8, fits_i8, read_slice, with_slice};
+use crate::types::{Type, ClassName, parse_field_desc, parse_method_desc};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{Read, Write, Cursor, Seek, SeekFrom};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use derive_more::Constructor;
 use std::convert::TryFrom;
+use std::fmt::{Debug, Formatter};
 
-#[derive(Constructor, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CodeAttribute {
 	pub max_stack: u16,
 	pub max_locals: u16,
 	pub insns: InsnList,
 	pub exceptions: Vec<ExceptionHandler>,
-	pub attributes: Vec<Attribute>
+	pub attributes: Vec<Attribute>,
+	/// The pc each instruction was parsed from, when parsed with
+	/// [ParseOptions::original_pcs](crate::classfile::ParseOptions::original_pcs). Labels have the
+	/// pc of the instruction after them.
+	pub original_pcs: Option<Vec<u32>>,
+	/// The constant pool index each instruction referred to, when parsed with
+	/// [ParseOptions::original_indices](crate::classfile::ParseOptions::original_indices)
+	pub original_indices: Option<Vec<Option<CPIndex>>>,
+	/// The instructions the original pcs and indices were recorded for, so that they are no longer
+	/// reported once the instructions change
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub original_insns: Option<Vec<Insn>>,
+	/// The attribute this code was parsed from, when parsed with
+	/// [ParseOptions::keep_original_code](crate::classfile::ParseOptions::keep_original_code) or
+	/// [ParseOptions::raw_attributes](crate::classfile::ParseOptions::raw_attributes). It is written
+	/// instead of the code while the code is unmodified, see [`is_modified`](Self::is_modified).
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub original: Option<Box<OriginalCode>>
+}
+
+/// Ignores the original attribute, pcs and indices, so that parsed code equals the same code built
+/// by hand
+impl PartialEq for CodeAttribute {
+	fn eq(&self, other: &Self) -> bool {
+		self.max_stack == other.max_stack && self.max_locals == other.max_locals && self.insns == other.insns
+			&& self.exceptions == other.exceptions && self.attributes == other.attributes
+	}
+}
+
+impl Default for CodeAttribute {
+	fn default() -> Self {
+		CodeAttribute::empty()
+	}
 }
 
 impl CodeAttribute {
+	/// The largest code array the JVM accepts, in bytes
+	pub const MAX_CODE_LENGTH: usize = 0xFFFF;
+	
+	pub fn new(max_stack: u16, max_locals: u16, insns: InsnList, exceptions: Vec<ExceptionHandler>, attributes: Vec<Attribute>) -> Self {
+		CodeAttribute {
+			max_stack,
+			max_locals,
+			insns,
+			exceptions,
+			attributes,
+			original_pcs: None,
+			original_indices: None,
+			original_insns: None,
+			original: None
+		}
+	}
+	
 	pub fn empty() -> Self {
 		CodeAttribute {
 			max_stack: 0,
 			max_locals: 0,
 			insns: InsnList::with_capacity(0),
 			exceptions: Vec::with_capacity(0),
-			attributes: Vec::with_capacity(0)
+			attributes: Vec::with_capacity(0),
+			original_pcs: None,
+			original_indices: None,
+			original_insns: None,
+			original: None
+		}
+	}
+	
+	/// Whether this code has to be encoded again when written. Code that kept the attribute it was
+	/// parsed from (see [`original`](Self::original)) is written exactly as it was read while it
+	/// still equals the code that was parsed, as long as the class is written with the constant
+	/// pool it was parsed with (see [`ConstantPoolWriter::preserves_indices`]). This keeps
+	/// attributes such as StackMapTable valid, while writing a StackMapTable for code that is
+	/// encoded again fails, as its frame offsets would be stale.
+	///
+	/// Changes made directly to the public fields are noticed too, by comparing the code with what
+	/// was parsed.
+	pub fn is_modified(&self) -> bool {
+		match &self.original {
+			Some(original) => self.insns.modified || !original.matches(self),
+			None => true
+		}
+	}
+	
+	/// Drops the original attribute, so that the code is encoded again when written
+	pub fn mark_modified(&mut self) {
+		self.original = None;
+	}
+	
+	/// The pc each instruction was parsed from, indexed like [`insns`](Self::insns). None unless
+	/// parsed with [ParseOptions::original_pcs](crate::classfile::ParseOptions::original_pcs), or
+	/// once any instruction has changed, other than by [`remove_dead_code`](Self::remove_dead_code).
+	pub fn original_pcs(&self) -> Option<&[u32]> {
+		self.original_pcs.as_deref().filter(|_| self.insns_as_parsed())
+	}
+	
+	/// The constant pool index each instruction referred to, indexed like [`insns`](Self::insns),
+	/// with None for instructions that refer to no constant. None unless parsed with
+	/// [ParseOptions::original_indices](crate::classfile::ParseOptions::original_indices), or once
+	/// any instruction has changed, as for [`original_pcs`](Self::original_pcs).
+	pub fn original_indices(&self) -> Option<&[Option<CPIndex>]> {
+		self.original_indices.as_deref().filter(|_| self.insns_as_parsed())
+	}
+	
+	/// Whether the instructions are still those the original pcs and indices were recorded for
+	fn insns_as_parsed(&self) -> bool {
+		self.original_insns.as_deref() == Some(&self.insns.insns[..])
+	}
+	
+	/// The constant pool index the instruction at `index` referred to, if known
+	pub fn original_index(&self, index: usize) -> Option<CPIndex> {
+		self.original_indices()?.get(index).copied().flatten()
+	}
+	
+	/// The index of the instruction parsed from the given pc, if any
+	pub fn insn_at_pc(&self, pc: u32) -> Option<usize> {
+		let pcs = self.original_pcs()?;
+		// labels come before the instruction sharing their pc
+		let index = pcs.partition_point(|x| *x <= pc).checked_sub(1)?;
+		if pcs[index] == pc && !matches!(self.insns.insns[index], Insn::Label(_)) {
+			Some(index)
+		} else {
+			None
 		}
 	}
 	
-	pub fn parse(version: &ClassVersion, constant_pool: &ConstantPool, buf: Vec<u8>) -> Result<Self> {
-		let mut buf = Cursor::new(buf);
+	/// Calls the visitor for each instruction in order
+	pub fn accept<V: InsnVisitor + ?Sized>(&self, visitor: &mut V) {
+		self.insns.accept(visitor);
+	}
+	
+	/// Whether both have the same instructions, exception handlers and attributes once labels are
+	/// renumbered, see [`InsnList::structurally_equal`]. Original pcs are ignored.
+	pub fn structurally_equal(&self, other: &CodeAttribute) -> bool {
+		let mut labels = LabelBijection::default();
+		self.max_stack == other.max_stack && self.max_locals == other.max_locals
+			&& self.insns.structurally_equal_with(&other.insns, &mut labels)
+			&& self.exceptions.len() == other.exceptions.len()
+			&& self.exceptions.iter().zip(other.exceptions.iter()).all(|(a, b)| {
+				a.catch_type == b.catch_type && labels.pair(a.start, b.start) && labels.pair(a.end, b.end) && labels.pair(a.handler, b.handler)
+			})
+			&& self.attributes.len() == other.attributes.len()
+			&& self.attributes.iter().zip(other.attributes.iter()).all(|(a, b)| match (a, b) {
+				(Attribute::LocalVariableTable(a), Attribute::LocalVariableTable(b)) | (Attribute::LocalVariableTypeTable(a), Attribute::LocalVariableTypeTable(b)) => {
+					a.variables.len() == b.variables.len() && a.variables.iter().zip(b.variables.iter()).all(|(a, b)| {
+						a.name == b.name && a.descriptor == b.descriptor && a.index == b.index && labels.pair(a.start, b.start) && labels.pair(a.end, b.end)
+					})
+				},
+				(Attribute::LineNumberTable(a), Attribute::LineNumberTable(b)) => {
+					a.lines.len() == b.lines.len() && a.lines.iter().zip(b.lines.iter()).all(|(a, b)| a.line == b.line && labels.pair(a.start, b.start))
+				},
+				_ => a == b
+			})
+	}
+	
+	/// Removes instructions that can never be executed, along with any labels and exception handlers
+	/// left without a purpose.
+	///
+	/// Reachability starts at the method entry and follows jumps, switches and fallthrough; exception
+	/// handlers are reachable through the instructions they protect. Labels are kept as long as a
+	/// jump, switch, exception handler or local variable still refers to them.
+	///
+	/// Returns the number of removed instructions, not counting labels.
+	pub fn remove_dead_code(&mut self) -> Result<usize> {
+		let cfg = ControlFlowGraph::build(self)?;
+		let handlers = self.exceptions.len();
+		let mut live = vec![false; self.insns.len()];
+		for id in cfg.reverse_post_order() {
+			let block = cfg.block(id);
+			for x in live[block.start..block.end].iter_mut() {
+				*x = true;
+			}
+		}
+		
+		let mut removed = 0;
+		let mut index = 0;
+		self.retain_insns(|insn| {
+			let keep = live[index] || matches!(insn, Insn::Label(_));
+			index += 1;
+			if !keep {
+				removed += 1;
+			}
+			keep
+		});
 		
+		// handlers that no longer protect any instructions
+		let insns = &self.insns;
+		self.exceptions.retain(|handler| {
+			match (insns.label_index(handler.start), insns.label_index(handler.end)) {
+				(Some(start), Some(end)) if start < end => {
+					insns.insns[start..end].iter().any(|insn| !matches!(insn, Insn::Label(_)))
+				},
+				_ => false
+			}
+		});
+		if self.exceptions.len() != handlers {
+			self.mark_modified();
+		}
+		
+		self.remove_unused_labels();
+		Ok(removed)
+	}
+	
+	/// Removes labels that no jump, switch, exception handler, local variable or line number refers
+	/// to, returning how many were removed
+	pub fn remove_unused_labels(&mut self) -> usize {
+		let mut referenced: HashSet<LabelInsn> = self.insns.jump_targets().into_iter().collect();
+		for handler in self.exceptions.iter() {
+			referenced.insert(handler.start);
+			referenced.insert(handler.end);
+			referenced.insert(handler.handler);
+		}
+		for attr in self.attributes.iter() {
+			match attr {
+				Attribute::LocalVariableTable(x) | Attribute::LocalVariableTypeTable(x) => {
+					for var in x.variables.iter() {
+						referenced.insert(var.start);
+						referenced.insert(var.end);
+					}
+				},
+				Attribute::LineNumberTable(x) => referenced.extend(x.lines.iter().map(|line| line.start)),
+				_ => {}
+			}
+		}
+		let before = self.insns.len();
+		self.retain_insns(|insn| match insn {
+			Insn::Label(x) => referenced.contains(x),
+			_ => true
+		});
+		before - self.insns.len()
+	}
+	
+	/// The handlers protecting the instruction at `index`, in the order the JVM tries them. The
+	/// position of a label can be found with [`InsnList::label_index`].
+	pub fn handlers_covering(&self, index: usize) -> Vec<&ExceptionHandler> {
+		self.exceptions.iter().filter(|handler| {
+			match (self.insns.label_index(handler.start), self.insns.label_index(handler.end)) {
+				(Some(start), Some(end)) => start <= index && index < end,
+				_ => false
+			}
+		}).collect()
+	}
+	
+	/// Appends a handler protecting the instructions from `start` up to, but not including, `end`
+	/// that jumps to the instruction at `handler`. Labels already in place are reused, others are
+	/// inserted, so indices after them shift. As the handler is tried after every existing one,
+	/// it does not apply where an earlier handler catches the same exception.
+	pub fn add_handler(&mut self, start: usize, end: usize, handler: usize, catch_type: Option<String>) -> Result<&ExceptionHandler> {
+		let index = self.exceptions.len();
+		if start >= end || end > self.insns.len() {
+			return Err(ParserError::invalid_exception_handler(index, format!("{}..{} is not a range of instructions", start, end)));
+		}
+		if handler >= self.insns.len() {
+			return Err(ParserError::invalid_exception_handler(index, format!("handler {} is not an instruction", handler)));
+		}
+		
+		// from the last position back, so that inserted labels do not move the others
+		let mut positions = [(start, 0), (end, 1), (handler, 2)];
+		positions.sort_unstable_by(|a, b| b.cmp(a));
+		let mut labels = [None; 3];
+		for (position, slot) in positions.iter() {
+			labels[*slot] = Some(self.label_at_index(*position));
+		}
+		if let [Some(start), Some(end), Some(handler)] = labels {
+			self.exceptions.push(ExceptionHandler { start, end, handler, catch_type });
+		}
+		self.mark_modified();
+		Ok(&self.exceptions[index])
+	}
+	
+	/// A label at the same pc as the instruction at `index`, inserting one if there is none
+	fn label_at_index(&mut self, index: usize) -> LabelInsn {
+		// a label right before the instruction shares its pc
+		let insns = &self.insns.insns;
+		for position in [Some(index), index.checked_sub(1)].iter().flatten() {
+			if let Some(Insn::Label(label)) = insns.get(*position) {
+				return *label;
+			}
+		}
+		let label = self.insns.new_label();
+		self.insns.insert(index, Insn::Label(label));
+		label
+	}
+	
+	/// Collapses consecutive entries of the exception table with the same handler and catch type
+	/// whose ranges touch or overlap into one, returning how many entries were removed. Only
+	/// entries next to each other in the table are merged, so the order handlers are tried in is
+	/// kept.
+	pub fn merge_adjacent_handlers(&mut self) -> usize {
+		// the number of instructions before each position, which is the same for all labels
+		// sharing a pc
+		let mut offsets = Vec::with_capacity(self.insns.len() + 1);
+		let mut offset = 0;
+		for insn in self.insns.iter() {
+			offsets.push(offset);
+			if !matches!(insn, Insn::Label(_)) {
+				offset += 1;
+			}
+		}
+		offsets.push(offset);
+		let range = |insns: &InsnList, handler: &ExceptionHandler| {
+			match (insns.label_index(handler.start), insns.label_index(handler.end)) {
+				(Some(start), Some(end)) => Some((offsets[start], offsets[end])),
+				_ => None
+			}
+		};
+		
+		let before = self.exceptions.len();
+		let mut merged: Vec<ExceptionHandler> = Vec::with_capacity(before);
+		for handler in self.exceptions.drain(..) {
+			if let Some(last) = merged.last_mut() {
+				if last.handler == handler.handler && last.catch_type == handler.catch_type {
+					if let (Some(a), Some(b)) = (range(&self.insns, last), range(&self.insns, &handler)) {
+						if b.0 <= a.1 && a.0 <= b.1 {
+							if b.0 < a.0 {
+								last.start = handler.start;
+							}
+							if b.1 > a.1 {
+								last.end = handler.end;
+							}
+							continue;
+						}
+					}
+				}
+			}
+			merged.push(handler);
+		}
+		self.exceptions = merged;
+		if self.exceptions.len() != before {
+			self.mark_modified();
+		}
+		before - self.exceptions.len()
+	}
+	
+	/// Every variable of the LocalVariableTable attributes in order, with the signature of its entry
+	/// in a LocalVariableTypeTable. Variables repeated across tables are only returned once, and
+	/// type table entries without a matching variable are ignored.
+	pub fn local_variables(&self) -> Vec<ResolvedLocalVariable> {
+		let mut variables: Vec<ResolvedLocalVariable> = Vec::new();
+		for attr in self.attributes.iter() {
+			if let Attribute::LocalVariableTable(x) = attr {
+				for var in x.variables.iter() {
+					let resolved = ResolvedLocalVariable::new(var.name.clone(), var.descriptor.clone(), None, var.start, var.end, var.index);
+					if !variables.contains(&resolved) {
+						variables.push(resolved);
+					}
+				}
+			}
+		}
+		for attr in self.attributes.iter() {
+			if let Attribute::LocalVariableTypeTable(x) = attr {
+				for var in x.variables.iter() {
+					let matching = variables.iter_mut()
+						.find(|x| x.signature.is_none() && x.index == var.index && x.start == var.start && x.end == var.end && x.name == var.name);
+					if let Some(matching) = matching {
+						matching.signature = Some(var.descriptor.clone());
+					}
+				}
+			}
+		}
+		variables
+	}
+	
+	/// The variable in the given slot while the instruction at `insn` runs
+	pub fn local_variable_at(&self, index: u16, insn: usize) -> Option<ResolvedLocalVariable> {
+		self.local_variables().into_iter().find(|var| {
+			var.index == index && match (self.insns.label_index(var.start), self.insns.label_index(var.end)) {
+				(Some(start), Some(end)) => start <= insn && insn < end,
+				_ => false
+			}
+		})
+	}
+	
+	/// Replaces the LocalVariableTable and LocalVariableTypeTable attributes with one of each where
+	/// the first of them was. The type table is left out when no variable has a signature.
+	pub fn set_local_variables(&mut self, variables: Vec<ResolvedLocalVariable>) {
+		self.mark_modified();
+		let is_table = |attr: &Attribute| matches!(attr, Attribute::LocalVariableTable(_) | Attribute::LocalVariableTypeTable(_));
+		let position = self.attributes.iter().position(is_table).unwrap_or(self.attributes.len());
+		self.attributes.retain(|attr| !is_table(attr));
+		
+		let entry = |var: &ResolvedLocalVariable, descriptor: &str| LocalVariable {
+			start: var.start,
+			end: var.end,
+			name: var.name.clone(),
+			descriptor: descriptor.to_string(),
+			index: var.index
+		};
+		let types: Vec<LocalVariable> = variables.iter()
+			.filter_map(|var| var.signature.as_ref().map(|signature| entry(var, signature)))
+			.collect();
+		let mut tables = Vec::new();
+		if !variables.is_empty() {
+			tables.push(Attribute::LocalVariableTable(LocalVariableTableAttribute::new(variables.iter().map(|var| entry(var, &var.descriptor)).collect())));
+		}
+		if !types.is_empty() {
+			tables.push(Attribute::LocalVariableTypeTable(LocalVariableTableAttribute::new(types)));
+		}
+		self.attributes.splice(position..position, tables);
+	}
+	
+	/// Keeps the instructions matching the predicate, along with their original pcs
+	fn retain_insns<F: FnMut(&Insn) -> bool>(&mut self, mut predicate: F) {
+		fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+			let mut index = 0;
+			values.retain(|_| {
+				index += 1;
+				keep[index - 1]
+			});
+		}
+		
+		let keep: Vec<bool> = self.insns.insns.iter().map(&mut predicate).collect();
+		if keep.contains(&false) {
+			self.mark_modified();
+		}
+		if self.insns_as_parsed() {
+			self.original_pcs.iter_mut().for_each(|pcs| retain(pcs, &keep));
+			self.original_indices.iter_mut().for_each(|indices| retain(indices, &keep));
+			self.original_insns.iter_mut().for_each(|insns| retain(insns, &keep));
+		}
+		retain(&mut self.insns.insns, &keep);
+	}
+	
+	/// Names each label at the start of a line in the LineNumberTable `line_<number>`, unless
+	/// another label already has that name
+	fn name_line_labels(insns: &mut InsnList, attributes: &[Attribute]) {
+		let mut used: HashSet<String> = HashSet::new();
+		for attr in attributes.iter() {
+			if let Attribute::LineNumberTable(x) = attr {
+				for line in x.lines.iter() {
+					let name = format!("line_{}", line.line);
+					if !insns.label_names.contains_key(&line.start.id) && used.insert(name.clone()) {
+						insns.label_names.insert(line.start.id, name);
+					}
+				}
+			}
+		}
+	}
+	
+	pub fn parse(version: &ClassVersion, constant_pool: &ConstantPool, buf: &mut Cursor<Vec<u8>>, options: &ParseOptions) -> Result<Self> {
+		with_slice(buf, |buf| Self::parse_slice(version, constant_pool, buf, options))
+	}
+	
+	pub(crate) fn parse_slice(version: &ClassVersion, constant_pool: &ConstantPool, buf: &mut Cursor<&[u8]>, options: &ParseOptions) -> Result<Self> {
+		let contents: &[u8] = buf.get_ref();
 		let max_stack = buf.read_u16::<BigEndian>()?;
 		let max_locals = buf.read_u16::<BigEndian>()?;
 		
 		let code_length = buf.read_u32::<BigEndian>()?;
+		if code_length as usize > CodeAttribute::MAX_CODE_LENGTH {
+			return Err(ParserError::method_too_large(code_length as usize));
+		}
 		
-		let code: Vec<u8> = buf.read_nbytes(code_length as usize)?;
-		let mut code = Cursor::new(code);
+		let mut code = Cursor::new(read_slice(buf, code_length as usize, "code")?);
 		
-		let mut pc_label_map: HashMap<u32, LabelInsn> = HashMap::new();
+		let mut pc_label_map = PcLabels::new();
 		InsnParser::find_insn_refs(&mut code, code_length, &mut pc_label_map)?;
 		
 		let num_exceptions = buf.read_u16::<BigEndian>()?;
 		let mut exceptions: Vec<ExceptionHandler> = Vec::with_capacity(num_exceptions as usize);
-		for _ in 0..num_exceptions {
-			exceptions.push(ExceptionHandler::parse(constant_pool, &mut buf)?);
+		for i in 0..num_exceptions {
+			exceptions.push(ExceptionHandler::parse(constant_pool, buf, &mut pc_label_map).context(|| format!("exceptions[{}]", i))?);
 		}
 		
-		let mut pc_label_map = Some(pc_label_map);
-		let attributes = Attributes::parse(&mut buf, AttributeSource::Code, version, constant_pool, &mut pc_label_map)?;
-		let mut pc_label_map = pc_label_map.unwrap();
+		// attributes add the pcs they refer to, so must all be parsed before the instructions place
+		// the labels
+		let mut context = AttributeContext::new(AttributeSource::Code, version, constant_pool, options)
+			.with_labels(&mut pc_label_map);
+		let attributes = Attributes::parse_from(buf, &mut context)?;
 		
 		code.set_position(0);
-		let code = InsnParser::parse_insns(constant_pool, &mut code, code_length, &mut pc_label_map)?;
+		let mut pcs = if options.original_pcs || options.original_indices { Some(Vec::new()) } else { None };
+		let mut insns = InsnParser::parse_insns(constant_pool, &mut code, code_length, &mut pc_label_map, pcs.as_mut())
+			.context(|| "insns")?;
+		CodeAttribute::name_line_labels(&mut insns, &attributes);
+		let original_indices = match &pcs {
+			Some(pcs) if options.original_indices => Some(InsnParser::constant_indices(code.get_ref(), &insns.insns, pcs)),
+			_ => None
+		};
+		let original_insns = pcs.as_ref().map(|_| insns.insns.clone());
 		
-		Ok(CodeAttribute {
+		let mut code = CodeAttribute {
 			max_stack,
 			max_locals,
-			insns: code,
+			insns,
 			exceptions,
-			attributes
-		})
+			attributes,
+			original_pcs: pcs.filter(|_| options.original_pcs),
+			original_insns,
+			original_indices,
+			original: None
+		};
+		if options.keep_original_code || options.raw_attributes {
+			code.original = Some(Box::new(OriginalCode::new(contents.to_vec(), &code)));
+		}
+		Ok(code)
 	}
 	
 	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		self.write_with_context(wtr, constant_pool, &mut WriteContext::default())
+	}
+	
+	/// The contents of this Code attribute, without its name and length. Constants are added to
+	/// `constant_pool` as for [`Method::write_to_vec`](crate::method::Method::write_to_vec), and
+	/// labels only need to be in these instructions.
+	pub fn write_to_vec(&self, constant_pool: &mut ConstantPoolWriter) -> Result<Vec<u8>> {
+		let mut bytes = Vec::new();
+		self.write(&mut bytes, constant_pool)?;
+		Ok(bytes)
+	}
+	
+	/// The length of the attribute when it is copied as it was parsed, see
+	/// [`is_modified`](Self::is_modified). Otherwise the length is only known once the
+	/// instructions are assembled, and this returns None.
+	pub fn byte_len(&self, constant_pool: &mut ConstantPoolWriter) -> Result<Option<u64>> {
+		Ok(self.copied_original(constant_pool).map(|bytes| bytes.len() as u64))
+	}
+	
+	/// The bytes the attribute was parsed from, if it is written by copying them
+	fn copied_original(&self, constant_pool: &ConstantPoolWriter) -> Option<&[u8]> {
+		match &self.original {
+			Some(original) if constant_pool.preserves_indices() && !self.is_modified() => Some(&original.bytes),
+			_ => None
+		}
+	}
+	
+	pub(crate) fn write_with_context<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext) -> Result<()> {
+		if let Some(original) = self.copied_original(constant_pool) {
+			if let Some(stats) = context.stats.as_mut() {
+				// copied along with the code
+				for attr in self.attributes.iter() {
+					*stats.attributes.entry(attr.name().to_string()).or_default() += 1;
+				}
+				// after max_stack and max_locals
+				context.code_length = Some(u32::from_be_bytes([original[4], original[5], original[6], original[7]]) as usize);
+			}
+			wtr.write_all(original)?;
+			return Ok(());
+		}
+		// its frames are at raw pcs, which encoding the code again moves
+		if self.attributes.iter().any(|attr| attr.kind() == AttributeKind::StackMapTable) {
+			return Err(ParserError::other("Cannot write a StackMapTable for code that is encoded again, as its frame offsets would be stale"));
+		}
+		if self.attributes.iter().any(|attr| matches!(attr, Attribute::Unknown(x) if x.kind() == AttributeKind::LineNumberTable)) {
+			return Err(ParserError::other("Cannot write an unparsed LineNumberTable for code that is encoded again, as its pcs would be stale"));
+		}
 		wtr.write_u16::<BigEndian>(self.max_stack)?;
 		wtr.write_u16::<BigEndian>(self.max_locals)?;
-		let (code_bytes, label_pc_map) = InsnParser::write_insns(self, constant_pool)?;
+		let (code_bytes, label_pc_map) = InsnParser::write_insns(self, constant_pool, context)?;
+		if code_bytes.len() > CodeAttribute::MAX_CODE_LENGTH {
+			return Err(ParserError::method_too_large(code_bytes.len()));
+		}
+		if context.stats.is_some() {
+			context.code_length = Some(code_bytes.len());
+		}
 		wtr.write_u32::<BigEndian>(code_bytes.len() as u32)?;
 		wtr.write_all(code_bytes.as_slice())?;
 		wtr.write_u16::<BigEndian>(self.exceptions.len() as u16)?;
-		for excep in self.exceptions.iter() {
-			excep.write(wtr, constant_pool)?;
+		for (i, excep) in self.exceptions.iter().enumerate() {
+			excep.check_in(i, &label_pc_map, code_bytes.len(), &self.insns)?;
+			excep.write(wtr, constant_pool, &label_pc_map)?;
+		}
+		for attr in self.attributes.iter() {
+			let unplaced = match attr {
+				Attribute::LocalVariableTable(x) | Attribute::LocalVariableTypeTable(x) => {
+					x.variables.iter().flat_map(|var| vec![var.start, var.end]).find(|x| !label_pc_map.contains_key(x))
+				},
+				Attribute::LineNumberTable(x) => x.lines.iter().map(|line| line.start).find(|x| !label_pc_map.contains_key(x)),
+				_ => None
+			};
+			if let Some(label) = unplaced {
+				return Err(ParserError::unplaced_label(self.insns.describe_label(label)));
+			}
 		}
-		Attributes::write(wtr, &self.attributes, constant_pool, Some(&label_pc_map))?;
+		context.give(code_bytes);
+		Attributes::write_with_context(wtr, &self.attributes, AttributeSource::Code, constant_pool, Some(&label_pc_map), context)?;
 		Ok(())
 	}
 }
 
 
+/// The contents of a parsed Code attribute, along with the code parsed from them to tell whether
+/// the code changed since. See [`CodeAttribute::is_modified`].
+#[derive(Clone, PartialEq)]
+pub struct OriginalCode {
+	bytes: Vec<u8>,
+	max_stack: u16,
+	max_locals: u16,
+	insns: Vec<Insn>,
+	exceptions: Vec<ExceptionHandler>,
+	attributes: Vec<Attribute>
+}
+
+impl OriginalCode {
+	fn new(bytes: Vec<u8>, code: &CodeAttribute) -> Self {
+		OriginalCode {
+			bytes,
+			max_stack: code.max_stack,
+			max_locals: code.max_locals,
+			insns: code.insns.insns.clone(),
+			exceptions: code.exceptions.clone(),
+			attributes: code.attributes.clone()
+		}
+	}
+	
+	pub fn bytes(&self) -> &[u8] {
+		&self.bytes
+	}
+	
+	/// Whether `code` is still the code parsed from these bytes
+	fn matches(&self, code: &CodeAttribute) -> bool {
+		self.max_stack == code.max_stack && self.max_locals == code.max_locals && self.insns == code.insns.insns
+			&& self.exceptions == code.exceptions && self.attributes == code.attributes
+	}
+}
+
+impl Debug for OriginalCode {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "OriginalCode({} bytes)", self.bytes.len())
+	}
+}
+
+/// A local variable with its LocalVariableTable and LocalVariableTypeTable entries combined, see
+/// [`CodeAttribute::local_variables`]
+#[derive(Constructor, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResolvedLocalVariable {
+	pub name: String,
+	pub descriptor: String,
+	/// The generic signature, for variables with an entry in a LocalVariableTypeTable
+	pub signature: Option<String>,
+	/// Start of the range the variable is in scope (inclusive)
+	pub start: LabelInsn,
+	/// End of the range the variable is in scope (exclusive)
+	pub end: LabelInsn,
+	/// The local variable slot
+	pub index: u16
+}
+
+/// The labels of the code being parsed by the pc they are at. Ids come from a counter, so a label
+/// is never given the id of another.
+///
+/// This replaces the `HashMap<u32, LabelInsn>` that [`ExceptionHandler::parse`],
+/// [`LocalVariableTableAttribute::parse`](crate::attributes::LocalVariableTableAttribute::parse),
+/// [`LocalVariable::parse`](crate::attributes::LocalVariable::parse) and
+/// [`AttributeContext`](crate::attributes::AttributeContext) used to take. An existing map converts
+/// with [`From`], and new labels then get ids after the highest one in it.
+#[derive(Clone, Debug, Default)]
+pub struct PcLabels {
+	labels: HashMap<u32, LabelInsn>,
+	next_id: u32
+}
+
+impl PcLabels {
+	pub fn new() -> Self {
+		PcLabels::default()
+	}
+	
+	/// The label at `pc`, creating one if there is none yet
+	pub fn label_at(&mut self, pc: u32) -> LabelInsn {
+		let next_id = &mut self.next_id;
+		*self.labels.entry(pc).or_insert_with(|| {
+			*next_id += 1;
+			LabelInsn::new(*next_id - 1)
+		})
+	}
+	
+	pub fn get(&self, pc: &u32) -> Option<&LabelInsn> {
+		self.labels.get(pc)
+	}
+	
+	pub fn iter(&self) -> impl Iterator<Item = (&u32, &LabelInsn)> {
+		self.labels.iter()
+	}
+	
+	pub fn len(&self) -> usize {
+		self.labels.len()
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.labels.is_empty()
+	}
+	
+	/// The id the next new label will get
+	pub fn next_id(&self) -> u32 {
+		self.next_id
+	}
+}
+
+impl From<HashMap<u32, LabelInsn>> for PcLabels {
+	fn from(labels: HashMap<u32, LabelInsn>) -> Self {
+		let next_id = labels.values().map(|label| label.id + 1).max().unwrap_or(0);
+		PcLabels { labels, next_id }
+	}
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExceptionHandler {
-	pub start_pc: u16,
-	pub end_pc: u16,
-	pub handler_pc: u16,
+	/// Start of the protected range (inclusive)
+	pub start: LabelInsn,
+	/// End of the protected range (exclusive)
+	pub end: LabelInsn,
+	/// Where control is transferred to when a matching exception is thrown
+	pub handler: LabelInsn,
+	/// None to catch any exception
 	pub catch_type: Option<String>
 }
 
 impl ExceptionHandler {
-	// TODO: exception handlers should use labels
-	pub fn parse<T: Read>(constant_pool: &ConstantPool, buf: &mut T) -> Result<Self> {
-		let start_pc = buf.read_u16::<BigEndian>()?;
-		let end_pc = buf.read_u16::<BigEndian>()?;
-		let handler_pc = buf.read_u16::<BigEndian>()?;
+	pub fn parse<T: Read>(constant_pool: &ConstantPool, buf: &mut T, pc_label_map: &mut PcLabels) -> Result<Self> {
+		let start_pc = buf.read_u16::<BigEndian>()? as u32;
+		let end_pc = buf.read_u16::<BigEndian>()? as u32;
+		let handler_pc = buf.read_u16::<BigEndian>()? as u32;
 		let catch_index = buf.read_u16::<BigEndian>()?;
 		let catch_type = if catch_index > 0 {
-			Some(constant_pool.utf8(constant_pool.class(catch_index)?.name_index)?.str.clone())
+			Some(constant_pool.class_name(catch_index).context(|| "catch_type")?)
 		} else {
 			None
 		};
 		
 		Ok(ExceptionHandler {
-			start_pc,
-			end_pc,
-			handler_pc,
+			start: pc_label_map.label_at(start_pc),
+			end: pc_label_map.label_at(end_pc),
+			handler: pc_label_map.label_at(handler_pc),
 			catch_type
 		})
 	}
 	
-	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
-		wtr.write_u16::<BigEndian>(self.start_pc)?;
-		wtr.write_u16::<BigEndian>(self.end_pc)?;
-		wtr.write_u16::<BigEndian>(self.handler_pc)?;
+	/// Checks that the handler protects a non empty range and jumps to an instruction, as the JVM
+	/// rejects the class otherwise. `index` is the position of this handler in the exception table.
+	pub fn check(&self, index: usize, label_pc_map: &HashMap<LabelInsn, u32>, code_length: usize) -> Result<()> {
+		self.check_in(index, label_pc_map, code_length, &InsnList::default())
+	}
+	
+	/// Like [`check`](Self::check), describing labels by their names in `insns`
+	pub(crate) fn check_in(&self, index: usize, label_pc_map: &HashMap<LabelInsn, u32>, code_length: usize, insns: &InsnList) -> Result<()> {
+		let mut pcs = [0u16; 3];
+		for (pc, (label, name)) in pcs.iter_mut().zip([(self.start, "start"), (self.end, "end"), (self.handler, "handler")].iter()) {
+			let label_pc = *label_pc_map.get(label)
+				.ok_or_else(|| ParserError::invalid_exception_handler(index, format!("{} label {} is not in the instructions", name, insns.describe_label(*label))))?;
+			*pc = u16::try_from(label_pc)
+				.map_err(|_| ParserError::invalid_exception_handler(index, format!("{} pc {} does not fit in a u16", name, label_pc)))?;
+		}
+		let [start, end, handler] = pcs;
+		if start >= end {
+			return Err(ParserError::invalid_exception_handler(index, format!("start pc {} is not before end pc {}", start, end)));
+		}
+		if handler as usize >= code_length {
+			return Err(ParserError::invalid_exception_handler(index, format!("handler pc {} is not an instruction", handler)));
+		}
+		Ok(())
+	}
+	
+	pub fn write<T: Write>(&self, wtr: &mut T, constant_pool: &mut ConstantPoolWriter, label_pc_map: &HashMap<LabelInsn, u32>) -> Result<()> {
+		for label in [self.start, self.end, self.handler].iter() {
+			let pc = *label_pc_map.get(label).ok_or_else(ParserError::unmapped_label)?;
+			wtr.write_u16::<BigEndian>(pc as u16)?;
+		}
 		let catch_type = match self.catch_type.clone() {
 			Some(x) => constant_pool.class_utf8(x),
 			None => 0
@@ -126,6 +791,94 @@ impl ExceptionHandler {
 	}
 }
 
+/// A Code attribute whose instructions are kept exactly as they were encoded, so that writing it
+/// reproduces the original bytes. See [`CodeAttribute::parse_raw`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawCodeAttribute {
+	pub max_stack: u16,
+	pub max_locals: u16,
+	pub insns: Vec<RawInsn>,
+	/// The exception table and attributes that follow the code, unparsed
+	pub tables: Vec<u8>
+}
+
+/// A single instruction exactly as it was encoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawInsn {
+	/// Offset of the instruction when it was parsed
+	pub pc: u32,
+	pub opcode: u8,
+	/// Every byte after the opcode, including the opcode modified by `wide` and the padding of
+	/// switches. Operands are written as is, so jump offsets and padding are not adjusted when
+	/// instructions move.
+	pub operands: Vec<u8>
+}
+
+impl RawInsn {
+	/// The encoded length in bytes, including the opcode
+	pub fn size(&self) -> usize {
+		1 + self.operands.len()
+	}
+}
+
+impl CodeAttribute {
+	/// Parses the contents of a Code attribute without normalising any encodings: `iconst_3` and
+	/// `bipush 3`, or `aload_0` and `aload 0`, stay distinct. Operands are not resolved against
+	/// the constant pool.
+	pub fn parse_raw(bytes: &[u8]) -> Result<RawCodeAttribute> {
+		let mut rdr = Cursor::new(bytes);
+		let max_stack = rdr.read_u16::<BigEndian>()?;
+		let max_locals = rdr.read_u16::<BigEndian>()?;
+		let code_length = rdr.read_u32::<BigEndian>()? as usize;
+		let start = rdr.position() as usize;
+		let code = bytes.get(start..start + code_length)
+			.ok_or_else(|| ParserError::invalid_insn(0, "Code is longer than its attribute"))?;
+		
+		let mut insns = Vec::with_capacity(code_length / 3);
+		let mut pc = 0;
+		while pc < code.len() {
+			let length = InsnParser::insn_length(code, pc as u32)? as usize;
+			let insn = code.get(pc..pc + length)
+				.ok_or_else(|| ParserError::invalid_insn(pc as u32, "Truncated instruction"))?;
+			insns.push(RawInsn {
+				pc: pc as u32,
+				opcode: insn[0],
+				operands: insn[1..].to_vec()
+			});
+			pc += length;
+		}
+		
+		Ok(RawCodeAttribute {
+			max_stack,
+			max_locals,
+			insns,
+			tables: bytes[start + code_length..].to_vec()
+		})
+	}
+}
+
+impl RawCodeAttribute {
+	pub fn write<T: Write>(&self, wtr: &mut T) -> Result<()> {
+		let code_length: usize = self.insns.iter().map(RawInsn::size).sum();
+		if code_length > CodeAttribute::MAX_CODE_LENGTH {
+			return Err(ParserError::method_too_large(code_length));
+		}
+		wtr.write_u16::<BigEndian>(self.max_stack)?;
+		wtr.write_u16::<BigEndian>(self.max_locals)?;
+		wtr.write_u32::<BigEndian>(code_length as u32)?;
+		for insn in self.insns.iter() {
+			wtr.write_u8(insn.opcode)?;
+			wtr.write_all(&insn.operands)?;
+		}
+		wtr.write_all(&self.tables)?;
+		Ok(())
+	}
+}
+
+/// The bootstrap class and method of a parsed invokedynamic or dynamic constant, as the
+/// BootstrapMethods attribute is not parsed yet
+const UNRESOLVED_BOOTSTRAP: &str = "Unimplemented";
+
 struct InsnParser {}
 #[allow(unused_variables)]
 #[allow(dead_code)]
@@ -336,134 +1089,226 @@ impl InsnParser {
 	const TABLESWITCH: u8 = 0xAA;
 	const WIDE: u8 = 0xC4;
 	
+	/// The `atype` operand of newarray for each primitive type, read and written from this one table
+	const ARRAY_TYPES: [(Type, u8); 8] = [
+		(Type::Boolean, 4),
+		(Type::Char, 5),
+		(Type::Float, 6),
+		(Type::Double, 7),
+		(Type::Byte, 8),
+		(Type::Short, 9),
+		(Type::Int, 10),
+		(Type::Long, 11)
+	];
+	
+	fn array_type(atype: u8) -> Option<Type> {
+		InsnParser::ARRAY_TYPES.iter().find(|(_, x)| *x == atype).map(|(kind, _)| kind.clone())
+	}
+	
+	fn array_type_code(kind: &Type) -> Option<u8> {
+		InsnParser::ARRAY_TYPES.iter().find(|(x, _)| x == kind).map(|(_, atype)| *atype)
+	}
+	
+	/// The padding after the opcode of a switch at `pc`, so that its operands start at a multiple of
+	/// four bytes from the start of the code
+	fn switch_padding(pc: u32) -> u32 {
+		3 - (pc % 4)
+	}
+	
+	/// The number of match-offset pairs of the lookupswitch at `pc`, which must fit in the rest of
+	/// the code
+	fn lookupswitch_pairs(pc: u32, npairs: i32, code_length: u32) -> Result<u32> {
+		let remaining = code_length.saturating_sub(pc + 1 + InsnParser::switch_padding(pc) + 8);
+		if npairs < 0 || npairs as u32 > remaining / 8 {
+			return Err(ParserError::invalid_insn(pc, format!("Invalid lookupswitch pair count {} with {} bytes left", npairs, remaining)));
+		}
+		Ok(npairs as u32)
+	}
+	
+	/// The number of cases of the tableswitch at `pc` from `low` to `high`, which must fit in the
+	/// rest of the code
+	fn tableswitch_cases(pc: u32, low: i32, high: i32, code_length: u32) -> Result<u32> {
+		if high < low {
+			return Err(ParserError::invalid_insn(pc, format!("Invalid tableswitch range {} to {}", low, high)));
+		}
+		let remaining = code_length.saturating_sub(pc + 1 + InsnParser::switch_padding(pc) + 12);
+		let num_cases = high as i64 - low as i64 + 1;
+		if num_cases > (remaining / 4) as i64 {
+			return Err(ParserError::invalid_insn(pc, format!("Invalid tableswitch range {} to {} with {} bytes left", low, high, remaining)));
+		}
+		Ok(num_cases as u32)
+	}
+	
+	/// The encoded length of the instruction at `pc`, including its opcode
+	fn insn_length(code: &[u8], pc: u32) -> Result<u32> {
+		let read_i32 = |at: u32| -> Result<i32> {
+			let at = at as usize;
+			code.get(at..at + 4)
+				.map(|x| i32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+				.ok_or_else(|| ParserError::invalid_insn(pc, "Truncated switch"))
+		};
+		let opcode = *code.get(pc as usize).ok_or_else(|| ParserError::invalid_insn(pc, "Truncated instruction"))?;
+		Ok(match opcode {
+			InsnParser::LOOKUPSWITCH => {
+				let pad = InsnParser::switch_padding(pc);
+				let npairs = InsnParser::lookupswitch_pairs(pc, read_i32(pc + 1 + pad + 4)?, code.len() as u32)?;
+				1 + pad + 8 + npairs * 8
+			}
+			InsnParser::TABLESWITCH => {
+				let pad = InsnParser::switch_padding(pc);
+				let low = read_i32(pc + 1 + pad + 4)?;
+				let high = read_i32(pc + 1 + pad + 8)?;
+				let num_cases = InsnParser::tableswitch_cases(pc, low, high, code.len() as u32)?;
+				1 + pad + 12 + num_cases * 4
+			}
+			InsnParser::WIDE => match code.get(pc as usize + 1) {
+				Some(&InsnParser::IINC) => 6,
+				Some(&InsnParser::ILOAD) | Some(&InsnParser::FLOAD) | Some(&InsnParser::ALOAD) | Some(&InsnParser::LLOAD) |
+				Some(&InsnParser::DLOAD) | Some(&InsnParser::ISTORE) | Some(&InsnParser::FSTORE) | Some(&InsnParser::ASTORE) |
+				Some(&InsnParser::LSTORE) | Some(&InsnParser::DSTORE) | Some(&InsnParser::RET) => 4,
+				Some(x) => return Err(ParserError::invalid_insn(pc, format!("Invalid wide opcode {:x}", x))),
+				None => return Err(ParserError::invalid_insn(pc, "Truncated instruction"))
+			},
+			InsnParser::ALOAD | InsnParser::ASTORE | InsnParser::BIPUSH | InsnParser::DLOAD |
+			InsnParser::DSTORE | InsnParser::FLOAD | InsnParser::FSTORE | InsnParser::ILOAD |
+			InsnParser::ISTORE | InsnParser::LDC | InsnParser::LLOAD | InsnParser::LSTORE |
+			InsnParser::NEWARRAY | InsnParser::RET => 2,
+			InsnParser::ANEWARRAY | InsnParser::CHECKCAST | InsnParser::GETFIELD |
+			InsnParser::GETSTATIC | InsnParser::IINC | InsnParser::INSTANCEOF |
+			InsnParser::INVOKESPECIAL | InsnParser::INVOKESTATIC | InsnParser::INVOKEVIRTUAL |
+			InsnParser::LDC_W | InsnParser::LDC2_W | InsnParser::NEW | InsnParser::PUTFIELD |
+			InsnParser::PUTSTATIC | InsnParser::SIPUSH | InsnParser::GOTO | InsnParser::JSR |
+			InsnParser::IF_ACMPEQ | InsnParser::IF_ACMPNE | InsnParser::IF_ICMPEQ | InsnParser::IF_ICMPGE |
+			InsnParser::IF_ICMPGT | InsnParser::IF_ICMPLE | InsnParser::IF_ICMPLT | InsnParser::IF_ICMPNE |
+			InsnParser::IFEQ | InsnParser::IFGE | InsnParser::IFGT | InsnParser::IFLE | InsnParser::IFLT |
+			InsnParser::IFNE | InsnParser::IFNONNULL | InsnParser::IFNULL => 3,
+			InsnParser::MULTIANEWARRAY => 4,
+			InsnParser::INVOKEDYNAMIC | InsnParser::INVOKEINTERFACE | InsnParser::GOTO_W | InsnParser::JSR_W => 5,
+			// every other opcode up to breakpoint is assigned and has no operands
+			x if x <= InsnParser::BREAKPOINT || x >= InsnParser::IMPDEP1 => 1,
+			x => return Err(ParserError::unknown_insn(x))
+		})
+	}
+	
+	/// The pc a jump at `pc` by `offset` lands on, which must be inside the code
+	fn jump_target(pc: u32, offset: i32, length: u32) -> Result<u32> {
+		let target = pc as i64 + offset as i64;
+		if target < 0 || target >= length as i64 {
+			return Err(ParserError::out_of_bounds_jump(i32::try_from(target).unwrap_or(offset)));
+		}
+		Ok(target as u32)
+	}
+	
 	/// Iterate all instructions and collect any pcs that are referenced - i.e. need to have relevant Labels
-	fn find_insn_refs<T: Read + Seek>(rdr: &mut T, length: u32, pc_label_map: &mut HashMap<u32, LabelInsn>) -> Result<()> {
+	/// Seeks over the already buffered code, so that parsing a class never requires a seekable reader.
+	/// Fails if a jump lands inside an instruction.
+	fn find_insn_refs(rdr: &mut Cursor<&[u8]>, length: u32, pc_label_map: &mut PcLabels) -> Result<()> {
+		let mut starts = vec![false; length as usize];
 		let mut pc: u32 = 0;
 		while pc < length {
 			let this_pc = pc;
+			starts[this_pc as usize] = true;
 			let opcode = rdr.read_u8()?;
 			pc += 1;
 			
 			match opcode {
 				InsnParser::GOTO => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::GOTO_W => {
-					let to = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i32::<BigEndian>()?, length)?);
 					pc += 4;
 				}
 				InsnParser::IF_ACMPEQ => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IF_ACMPNE => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IF_ICMPEQ => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IF_ICMPGE => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IF_ICMPGT => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IF_ICMPLE => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IF_ICMPLT => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IF_ICMPNE => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFEQ => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFGE => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFGT => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFLE => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFLT => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFNE => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFNONNULL => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::IFNULL => {
-					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(to, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i16::<BigEndian>()? as i32, length)?);
 					pc += 2;
 				}
 				InsnParser::LOOKUPSWITCH => {
-					let pad = 3 - (this_pc % 4);
+					let pad = InsnParser::switch_padding(this_pc);
 					rdr.seek(SeekFrom::Current(pad as i64))?;
 					
-					let default = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(default, LabelInsn::new(pc_label_map.len() as u32));
-					let npairs = rdr.read_i32::<BigEndian>()? as u32;
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i32::<BigEndian>()?, length)?);
+					let npairs = InsnParser::lookupswitch_pairs(this_pc, rdr.read_i32::<BigEndian>()?, length)?;
 					
 					for i in 0..npairs {
 						let matc = rdr.read_i32::<BigEndian>()?;
-						let jump = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
-						pc_label_map.insert_if_not_present(jump, LabelInsn::new(pc_label_map.len() as u32));
+						pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i32::<BigEndian>()?, length)?);
 					}
 					
 					pc += pad + (2 * 4) + (npairs * 2 * 4);
 				}
 				InsnParser::TABLESWITCH => {
-					let pad = 3 - (this_pc % 4);
+					let pad = InsnParser::switch_padding(this_pc);
 					rdr.seek(SeekFrom::Current(pad as i64))?;
 					
-					let default = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
-					pc_label_map.insert_if_not_present(default, LabelInsn::new(pc_label_map.len() as u32));
+					pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i32::<BigEndian>()?, length)?);
 					
 					let low = rdr.read_i32::<BigEndian>()?;
 					let high = rdr.read_i32::<BigEndian>()?;
-					let num_cases = (high - low + 1) as u32;
+					let num_cases = InsnParser::tableswitch_cases(this_pc, low, high, length)?;
 					for i in 0..num_cases {
-						let case = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
-						pc_label_map.insert_if_not_present(case, LabelInsn::new(pc_label_map.len() as u32));
+						pc_label_map.label_at(InsnParser::jump_target(this_pc, rdr.read_i32::<BigEndian>()?, length)?);
 					}
 					
 					pc += pad + ((3 + num_cases) * 4);
@@ -471,7 +1316,7 @@ impl InsnParser {
 				InsnParser::AALOAD | InsnParser::AASTORE | InsnParser::ACONST_NULL |
 				InsnParser::ALOAD_0 | InsnParser::ALOAD_1 | InsnParser::ALOAD_2 |
 				InsnParser::ALOAD_3 | InsnParser::ARETURN | InsnParser::ARRAYLENGTH |
-				InsnParser::ASTORE_0 | InsnParser::ASTORE_2 | InsnParser::ASTORE_3 |
+				InsnParser::ASTORE_0 | InsnParser::ASTORE_1 | InsnParser::ASTORE_2 | InsnParser::ASTORE_3 |
 				InsnParser::ATHROW | InsnParser::BALOAD | InsnParser::BASTORE |
 				InsnParser::BREAKPOINT | InsnParser::CALOAD | InsnParser::CASTORE |
 				InsnParser::D2F | InsnParser::D2I | InsnParser::D2L | InsnParser::DADD |
@@ -534,29 +1379,61 @@ impl InsnParser {
 					pc += 4;
 					rdr.seek(SeekFrom::Current(4))?;
 				}
+				// the modified opcode has been read, leaving its operands
 				InsnParser::WIDE => match rdr.read_u8()? {
 					InsnParser::ILOAD | InsnParser::FLOAD | InsnParser::ALOAD | InsnParser::LLOAD |
-					InsnParser::DLOAD | InsnParser::ISTORE | InsnParser::FSTORE |
-					InsnParser::LSTORE | InsnParser::DSTORE => {
+					InsnParser::DLOAD | InsnParser::ISTORE | InsnParser::FSTORE | InsnParser::ASTORE |
+					InsnParser::LSTORE | InsnParser::DSTORE | InsnParser::RET => {
 						pc += 3;
-						rdr.seek(SeekFrom::Current(3))?;
+						rdr.seek(SeekFrom::Current(2))?;
 					}
 					InsnParser::IINC => {
 						pc += 5;
-						rdr.seek(SeekFrom::Current(5))?;
+						rdr.seek(SeekFrom::Current(4))?;
 					}
-					_ => return Err(ParserError::invalid_insn(this_pc, format!("Invalid wide opcode {:x}", opcode)))
+					x => return Err(ParserError::invalid_insn(this_pc, format!("Invalid wide opcode {:x}", x)))
 				},
 				_ => return Err(ParserError::unknown_insn(opcode))
 			}
 		}
-		Ok(())
+		// the map only holds jump targets so far
+		match pc_label_map.iter().map(|(pc, _)| *pc).filter(|pc| !starts[*pc as usize]).min() {
+			Some(target) => Err(ParserError::invalid_insn(target, "is jumped to but inside an instruction")),
+			None => Ok(())
+		}
 	}
 	
-	fn parse_insns<T: Read>(constant_pool: &ConstantPool, mut rdr: T, length: u32, pc_label_map: &mut HashMap<u32, LabelInsn>) -> Result<InsnList> {
+	/// The constant pool index each of the instructions parsed from `code` refers to, given the pc
+	/// of each
+	fn constant_indices(code: &[u8], insns: &[Insn], pcs: &[u32]) -> Vec<Option<CPIndex>> {
+		insns.iter().zip(pcs.iter()).map(|(insn, pc)| {
+			if let Insn::Label(_) = insn {
+				return None;
+			}
+			let pc = *pc as usize;
+			match code[pc] {
+				InsnParser::LDC => Some(code[pc + 1] as CPIndex),
+				InsnParser::LDC_W | InsnParser::LDC2_W
+				| InsnParser::GETFIELD | InsnParser::GETSTATIC | InsnParser::PUTFIELD | InsnParser::PUTSTATIC
+				| InsnParser::INVOKEDYNAMIC | InsnParser::INVOKEINTERFACE | InsnParser::INVOKESPECIAL
+				| InsnParser::INVOKESTATIC | InsnParser::INVOKEVIRTUAL
+				| InsnParser::NEW | InsnParser::ANEWARRAY | InsnParser::MULTIANEWARRAY
+				| InsnParser::CHECKCAST | InsnParser::INSTANCEOF => Some(u16::from_be_bytes([code[pc + 1], code[pc + 2]])),
+				_ => None
+			}
+		}).collect()
+	}
+	
+	/// Parses the instructions, pushing the pc of each onto `original_pcs` if given
+	fn parse_insns<T: Read>(constant_pool: &ConstantPool, mut rdr: T, length: u32, pc_label_map: &mut PcLabels, mut original_pcs: Option<&mut Vec<u32>>) -> Result<InsnList> {
 		let num_insns_estimate = length as usize / 3; // estimate an average 3 bytes per insn
 		let mut insns: Vec<Insn> = Vec::with_capacity(num_insns_estimate);
 		
+		// labels by descending pc, so that the next one to place is at the end. A pc inside an
+		// instruction, which only attributes can refer to, is placed before the next instruction
+		let mut labels: Vec<(u32, LabelInsn)> = pc_label_map.iter().map(|(pc, lbl)| (*pc, *lbl)).collect();
+		labels.sort_unstable_by_key(|(pc, _)| std::cmp::Reverse(*pc));
+		
 		let mut pc: u32 = 0;
 		while pc < length {
 			let this_pc = pc;
@@ -564,8 +1441,13 @@ impl InsnParser {
 			pc += 1;
 			
 			// does this pc need an associated label?
-			if let Some(lbl) = pc_label_map.get(&this_pc) {
+			while let Some((_, lbl)) = labels.last().filter(|(label_pc, _)| *label_pc <= this_pc) {
 				insns.push(Insn::Label(*lbl));
+				labels.pop();
+			}
+			if let Some(pcs) = original_pcs.as_deref_mut() {
+				// for the label, if any, and the instruction
+				pcs.resize(insns.len() + 1, this_pc);
 			}
 			
 			let insn = match opcode {
@@ -582,9 +1464,9 @@ impl InsnParser {
 				InsnParser::ALOAD_2 => Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 2)),
 				InsnParser::ALOAD_3 => Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 3)),
 				InsnParser::ANEWARRAY => {
-					let kind = constant_pool.utf8(constant_pool.class(rdr.read_u16::<BigEndian>()?)?.name_index)?.str.clone();
+					let kind = ClassRef::parse(&constant_pool.class_name(rdr.read_u16::<BigEndian>()?)?)?;
 					pc += 2;
-					Insn::NewArray(NewArrayInsn::new(Type::Reference(Some(kind))))
+					Insn::NewArray(NewArrayInsn::new(kind.as_type()))
 				},
 				InsnParser::ARETURN => Insn::Return(ReturnInsn::new(ReturnType::Reference)),
 				InsnParser::ARRAYLENGTH => Insn::ArrayLength(ArrayLengthInsn::new()),
@@ -610,7 +1492,7 @@ impl InsnParser {
 				InsnParser::CALOAD => Insn::ArrayLoad(ArrayLoadInsn::new(Type::Char)),
 				InsnParser::CASTORE => Insn::ArrayStore(ArrayStoreInsn::new(Type::Char)),
 				InsnParser::CHECKCAST => {
-					let kind = constant_pool.utf8(constant_pool.class(rdr.read_u16::<BigEndian>()?)?.name_index)?.str.clone();
+					let kind = ClassRef::parse(&constant_pool.class_name(rdr.read_u16::<BigEndian>()?)?)?;
 					pc += 2;
 					Insn::CheckCast(CheckCastInsn::new(kind))
 				},
@@ -696,7 +1578,7 @@ impl InsnParser {
 					let name_type = constant_pool.nameandtype(field_ref.name_and_type_index)?;
 					let name = constant_pool.utf8(name_type.name_index)?.str.clone();
 					let descriptor = constant_pool.utf8(name_type.descriptor_index)?.str.clone();
-					Insn::GetField(GetFieldInsn::new(true, class, name, descriptor))
+					Insn::GetField(Box::new(GetFieldInsn::new(true, class.into(), name, descriptor)))
 				},
 				InsnParser::GETSTATIC => {
 					let field_ref = constant_pool.fieldref(rdr.read_u16::<BigEndian>()?)?;
@@ -705,7 +1587,7 @@ impl InsnParser {
 					let name_type = constant_pool.nameandtype(field_ref.name_and_type_index)?;
 					let name = constant_pool.utf8(name_type.name_index)?.str.clone();
 					let descriptor = constant_pool.utf8(name_type.descriptor_index)?.str.clone();
-					Insn::GetField(GetFieldInsn::new(false, class, name, descriptor))
+					Insn::GetField(Box::new(GetFieldInsn::new(false, class.into(), name, descriptor)))
 				},
 				InsnParser::GOTO => {
 					let to = (rdr.read_i16::<BigEndian>()? as i32 + this_pc as i32) as u32;
@@ -713,7 +1595,7 @@ impl InsnParser {
 					Insn::Jump(JumpInsn::new(*pc_label_map.get(&to).ok_or_else(ParserError::unmapped_label)?))
 				},
 				InsnParser::GOTO_W => {
-					let to = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
+					let to = rdr.read_i32::<BigEndian>()?.wrapping_add(this_pc as i32) as u32;
 					pc += 4;
 					Insn::Jump(JumpInsn::new(*pc_label_map.get(&to).ok_or_else(ParserError::unmapped_label)?))
 				},
@@ -835,7 +1717,7 @@ impl InsnParser {
 				InsnParser::IMUL => Insn::Multiply(MultiplyInsn::new(PrimitiveType::Int)),
 				InsnParser::INEG => Insn::Negate(NegateInsn::new(PrimitiveType::Int)),
 				InsnParser::INSTANCEOF => {
-					let class = constant_pool.utf8(constant_pool.class(rdr.read_u16::<BigEndian>()?)?.name_index)?.str.clone();
+					let class = ClassRef::parse(&constant_pool.class_name(rdr.read_u16::<BigEndian>()?)?)?;
 					pc += 2;
 					Insn::InstanceOf(InstanceOfInsn::new(class))
 				},
@@ -848,7 +1730,7 @@ impl InsnParser {
 					let name_and_type = constant_pool.nameandtype(dyn_info.name_and_type_index)?;
 					let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
 					let descriptor = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
-					Insn::InvokeDynamic(InvokeDynamicInsn::new(name, descriptor, BootstrapMethodType::InvokeStatic, String::from("Unimplemented"), String::from("Unimplemented"), String::from("Unimplemented"), Vec::new()))
+					Insn::from(InvokeDynamicInsn::new(name, descriptor, BootstrapMethodType::InvokeStatic, ClassName::from(UNRESOLVED_BOOTSTRAP), String::from(UNRESOLVED_BOOTSTRAP), String::from(UNRESOLVED_BOOTSTRAP), Vec::new()))
 				},
 				InsnParser::INVOKEINTERFACE => {
 					let method = constant_pool.interfacemethodref(rdr.read_u16::<BigEndian>()?)?;
@@ -860,7 +1742,7 @@ impl InsnParser {
 					let class = constant_pool.utf8(constant_pool.class(method.class_index)?.name_index)?.str.clone();
 					let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
 					let descriptor = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
-					Insn::Invoke(InvokeInsn::new(InvokeType::Instance, class, name, descriptor, true))
+					Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Interface, class.into(), name, descriptor, true)))
 				}
 				InsnParser::INVOKESPECIAL => {
 					let method_index = rdr.read_u16::<BigEndian>()?;
@@ -872,7 +1754,7 @@ impl InsnParser {
 					let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
 					let descriptor = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
 					
-					Insn::Invoke(InvokeInsn::new(InvokeType::Special, class, name, descriptor, interface_method))
+					Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Special, class.into(), name, descriptor, interface_method)))
 				},
 				InsnParser::INVOKESTATIC => {
 					let method_index = rdr.read_u16::<BigEndian>()?;
@@ -884,7 +1766,7 @@ impl InsnParser {
 					let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
 					let descriptor = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
 					
-					Insn::Invoke(InvokeInsn::new(InvokeType::Static, class, name, descriptor, interface_method))
+					Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Static, class.into(), name, descriptor, interface_method)))
 				},
 				InsnParser::INVOKEVIRTUAL => {
 					let method_index = rdr.read_u16::<BigEndian>()?;
@@ -896,7 +1778,7 @@ impl InsnParser {
 					let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
 					let descriptor = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
 					
-					Insn::Invoke(InvokeInsn::new(InvokeType::Instance, class, name, descriptor, interface_method))
+					Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, class.into(), name, descriptor, interface_method)))
 				},
 				InsnParser::IOR => Insn::Or(OrInsn::new(IntegerType::Int)),
 				InsnParser::IREM => Insn::Remainder(RemainderInsn::new(PrimitiveType::Int)),
@@ -930,23 +1812,23 @@ impl InsnParser {
 				InsnParser::LDC => {
 					let index = rdr.read_u8()? as u16;
 					pc += 1;
-					InsnParser::parse_ldc(index, constant_pool)?
+					InsnParser::parse_ldc(this_pc, index, false, constant_pool)?
 				},
 				InsnParser::LDC_W => {
 					let index = rdr.read_u16::<BigEndian>()?;
 					pc += 2;
-					InsnParser::parse_ldc(index, constant_pool)?
+					InsnParser::parse_ldc(this_pc, index, false, constant_pool)?
 				},
 				InsnParser::LDC2_W => {
 					let index = rdr.read_u16::<BigEndian>()?;
 					pc += 2;
-					InsnParser::parse_ldc(index, constant_pool)?
+					InsnParser::parse_ldc(this_pc, index, true, constant_pool)?
 				},
 				InsnParser::LDIV => Insn::Divide(DivideInsn::new(PrimitiveType::Long)),
 				InsnParser::LLOAD => {
 					let index = rdr.read_u8()?;
 					pc += 1;
-					Insn::LocalLoad(LocalLoadInsn::new(OpType::Double, index as u16))
+					Insn::LocalLoad(LocalLoadInsn::new(OpType::Long, index as u16))
 				},
 				InsnParser::LLOAD_0 => Insn::LocalLoad(LocalLoadInsn::new(OpType::Long, 0)),
 				InsnParser::LLOAD_1 => Insn::LocalLoad(LocalLoadInsn::new(OpType::Long, 1)),
@@ -955,17 +1837,17 @@ impl InsnParser {
 				InsnParser::LMUL => Insn::Multiply(MultiplyInsn::new(PrimitiveType::Long)),
 				InsnParser::LNEG => Insn::Negate(NegateInsn::new(PrimitiveType::Long)),
 				InsnParser::LOOKUPSWITCH => {
-					let pad = 3 - (this_pc % 4);
-					rdr.read_nbytes(pad as usize)?;
+					let pad = InsnParser::switch_padding(this_pc);
+					rdr.read_nbytes(pad as usize, "switch padding")?;
 					
-					let default = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
-					let npairs = rdr.read_i32::<BigEndian>()? as u32;
+					let default = rdr.read_i32::<BigEndian>()?.wrapping_add(this_pc as i32) as u32;
+					let npairs = InsnParser::lookupswitch_pairs(this_pc, rdr.read_i32::<BigEndian>()?, length)?;
 					
 					let mut insn = LookupSwitchInsn::new(*pc_label_map.get(&default).ok_or_else(ParserError::unmapped_label)?);
 					
 					for i in 0..npairs {
 						let matc = rdr.read_i32::<BigEndian>()?;
-						let jump = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
+						let jump = rdr.read_i32::<BigEndian>()?.wrapping_add(this_pc as i32) as u32;
 						insn.cases.insert(matc, *pc_label_map.get(&jump).ok_or_else(ParserError::unmapped_label)?);
 					}
 					
@@ -993,30 +1875,20 @@ impl InsnParser {
 				InsnParser::MONITORENTER => Insn::MonitorEnter(MonitorEnterInsn::new()),
 				InsnParser::MONITOREXIT => Insn::MonitorExit(MonitorExitInsn::new()),
 				InsnParser::MULTIANEWARRAY => {
-					let kind = constant_pool.utf8(constant_pool.class(rdr.read_u16::<BigEndian>()?)?.name_index)?.str.clone();
+					let kind = ClassRef::parse(&constant_pool.class_name(rdr.read_u16::<BigEndian>()?)?)?;
 					let dimensions = rdr.read_u8()?;
 					pc += 3;
 					Insn::MultiNewArray(MultiNewArrayInsn::new(kind, dimensions))
 				},
 				InsnParser::NEW => {
-					let kind = constant_pool.utf8(constant_pool.class(rdr.read_u16::<BigEndian>()?)?.name_index)?.str.clone();
+					let kind = ClassRef::parse(&constant_pool.class_name(rdr.read_u16::<BigEndian>()?)?)?;
 					pc += 2;
 					Insn::NewObject(NewObjectInsn::new(kind))
 				},
 				InsnParser::NEWARRAY => {
 					let atype = rdr.read_u8()?;
 					pc += 1;
-					let kind = match atype {
-						4 => Type::Boolean,
-						5 => Type::Char,
-						6 => Type::Float,
-						7 => Type::Double,
-						8 => Type::Byte,
-						9 => Type::Short,
-						10 => Type::Int,
-						11 => Type::Long,
-						_ => return Err(ParserError::other("Unknown Primitive Type"))
-					};
+					let kind = InsnParser::array_type(atype).ok_or_else(|| ParserError::other("Unknown Primitive Type"))?;
 					Insn::NewArray(NewArrayInsn::new(kind))
 				},
 				InsnParser::NOP => Insn::Nop(NopInsn::new()),
@@ -1029,7 +1901,7 @@ impl InsnParser {
 					let class = constant_pool.utf8(constant_pool.class(field_ref.class_index)?.name_index)?.str.clone();
 					let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
 					let desc = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
-					Insn::PutField(PutFieldInsn::new(true, class, name, desc))
+					Insn::PutField(Box::new(PutFieldInsn::new(true, class.into(), name, desc)))
 				},
 				InsnParser::PUTSTATIC => {
 					let field_ref = constant_pool.fieldref(rdr.read_u16::<BigEndian>()?)?;
@@ -1038,7 +1910,7 @@ impl InsnParser {
 					let class = constant_pool.utf8(constant_pool.class(field_ref.class_index)?.name_index)?.str.clone();
 					let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
 					let desc = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
-					Insn::PutField(PutFieldInsn::new(false, class, name, desc))
+					Insn::PutField(Box::new(PutFieldInsn::new(false, class.into(), name, desc)))
 				},
 				//InsnParser::RET =>
 				InsnParser::RETURN => Insn::Return(ReturnInsn::new(ReturnType::Void)),
@@ -1051,17 +1923,17 @@ impl InsnParser {
 				},
 				InsnParser::SWAP => Insn::Swap(SwapInsn::new()),
 				InsnParser::TABLESWITCH => {
-					let pad = 3 - (this_pc % 4);
-					rdr.read_nbytes(pad as usize)?;
+					let pad = InsnParser::switch_padding(this_pc);
+					rdr.read_nbytes(pad as usize, "switch padding")?;
 					
-					let default = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
+					let default = rdr.read_i32::<BigEndian>()?.wrapping_add(this_pc as i32) as u32;
 					
 					let low = rdr.read_i32::<BigEndian>()?;
 					let high = rdr.read_i32::<BigEndian>()?;
-					let num_cases = (high - low + 1) as u32;
+					let num_cases = InsnParser::tableswitch_cases(this_pc, low, high, length)?;
 					let mut cases: Vec<LabelInsn> = Vec::with_capacity(num_cases as usize);
 					for i in 0..num_cases {
-						let case = (rdr.read_i32::<BigEndian>()? + this_pc as i32) as u32;
+						let case = rdr.read_i32::<BigEndian>()?.wrapping_add(this_pc as i32) as u32;
 						cases.push(*pc_label_map.get(&case).ok_or_else(ParserError::unmapped_label)?);
 					}
 					
@@ -1112,6 +1984,11 @@ impl InsnParser {
 							pc += 2;
 							Insn::LocalStore(LocalStoreInsn::new(OpType::Float, index))
 						},
+						InsnParser::ASTORE => {
+							let index = rdr.read_u16::<BigEndian>()?;
+							pc += 2;
+							Insn::LocalStore(LocalStoreInsn::new(OpType::Reference, index))
+						},
 						InsnParser::LSTORE => {
 							let index = rdr.read_u16::<BigEndian>()?;
 							pc += 2;
@@ -1128,7 +2005,7 @@ impl InsnParser {
 							pc += 4;
 							Insn::IncrementInt(IncrementIntInsn::new(index, amount))
 						}
-						InsnParser::RET => unimplemented!("Wide Ret instructions are not implemented"),
+						InsnParser::RET => return Err(ParserError::unimplemented("Wide Ret instructions")),
 						_ => return Err(ParserError::invalid_insn(this_pc, format!("Invalid wide opcode {:x}", opcode)))
 					}
 				}
@@ -1137,14 +2014,19 @@ impl InsnParser {
 			insns.push(insn);
 		}
 		
-		// there can be a label at the end of the code space, e.g. for an end exception handler
-		if let Some(lbl) = pc_label_map.get(&pc) {
-			insns.push(Insn::Label(*lbl));
+		// there can be labels at the end of the code space, e.g. for an end exception handler
+		while let Some((_, lbl)) = labels.pop() {
+			insns.push(Insn::Label(lbl));
+			if let Some(pcs) = original_pcs.as_deref_mut() {
+				pcs.push(pc);
+			}
 		}
 		
 		let list = InsnList {
 			insns,
-			labels: pc_label_map.len() as u32
+			labels: pc_label_map.next_id(),
+			label_names: HashMap::new(),
+			modified: false
 		};
 		
 		Ok(list)
@@ -1173,8 +2055,39 @@ impl InsnParser {
 		Ok(())
 	}
 	
-	fn parse_ldc(index: CPIndex, constant_pool: &ConstantPool) -> Result<Insn> {
+	/// Resolves the field or method a method handle constant refers to
+	fn parse_method_handle(handle: &MethodHandleInfo, constant_pool: &ConstantPool) -> Result<MethodHandle> {
+		let (class_index, name_and_type_index, interface) = match handle.kind {
+			MethodHandleKind::GetField | MethodHandleKind::GetStatic | MethodHandleKind::PutField | MethodHandleKind::PutStatic => {
+				let field = constant_pool.fieldref(handle.reference)?;
+				(field.class_index, field.name_and_type_index, false)
+			},
+			_ => {
+				let (method, interface) = constant_pool.any_method(handle.reference)?;
+				(method.class_index, method.name_and_type_index, interface)
+			}
+		};
+		let name_and_type = constant_pool.nameandtype(name_and_type_index)?;
+		Ok(MethodHandle::new(
+			handle.kind,
+			constant_pool.class_name(class_index)?.into(),
+			constant_pool.utf8(name_and_type.name_index)?.str.clone(),
+			constant_pool.utf8(name_and_type.descriptor_index)?.str.clone(),
+			interface
+		))
+	}
+	
+	/// Parses the constant of an ldc, ldc_w or ldc2_w. Only ldc2_w may load longs and doubles,
+	/// and it may load nothing else.
+	fn parse_ldc(pc: u32, index: CPIndex, double_size: bool, constant_pool: &ConstantPool) -> Result<Insn> {
 		let constant = constant_pool.get(index)?;
+		if constant.double_size() != double_size {
+			return Err(ParserError::invalid_insn(pc, if double_size {
+				"ldc2_w must load a long or double"
+			} else {
+				"ldc and ldc_w cannot load a long or double"
+			}));
+		}
 		let ldc_type = match constant {
 			ConstantType::String(x) => LdcType::String(constant_pool.utf8(x.utf_index)?.str.clone()),
 			ConstantType::Integer(x) => LdcType::Int(x.inner()),
@@ -1183,8 +2096,14 @@ impl InsnParser {
 			ConstantType::Long(x) => LdcType::Long(x.inner()),
 			ConstantType::Class(x) => LdcType::Class(constant_pool.utf8(x.name_index)?.str.clone()),
 			ConstantType::MethodType(x) => LdcType::MethodType(constant_pool.utf8(x.descriptor_index)?.str.clone()),
-			ConstantType::MethodHandle(x) => return Err(ParserError::unimplemented("MethodHandle LDC")),
-			ConstantType::Dynamic(x) => return Err(ParserError::unimplemented("Dynamic LDC")),
+			ConstantType::MethodHandle(x) => LdcType::MethodHandle(Box::new(InsnParser::parse_method_handle(x, constant_pool)?)),
+			ConstantType::Dynamic(x) => {
+				let name_and_type = constant_pool.nameandtype(x.name_and_type_index)?;
+				let name = constant_pool.utf8(name_and_type.name_index)?.str.clone();
+				let descriptor = constant_pool.utf8(name_and_type.descriptor_index)?.str.clone();
+				let bootstrap_method = MethodHandle::new(MethodHandleKind::InvokeStatic, ClassName::from(UNRESOLVED_BOOTSTRAP), String::from(UNRESOLVED_BOOTSTRAP), String::from(UNRESOLVED_BOOTSTRAP), false);
+				LdcType::Dynamic(Box::new(DynamicConstant::new(name, descriptor, bootstrap_method, Vec::new())))
+			},
 			x => return Err(ParserError::incomp_cp(
 				"LDC Constant Type",
 				constant,
@@ -1194,8 +2113,42 @@ impl InsnParser {
 		Ok(Insn::Ldc(LdcInsn::new(ldc_type)))
 	}
 	
-	fn write_insns(code: &CodeAttribute, constant_pool: &mut ConstantPoolWriter) -> Result<(Vec<u8>, HashMap<LabelInsn, u32>)> {
-		let mut wtr: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(code.insns.len()));
+	/// The opcode of a conditional jump
+	fn condition_opcode(condition: JumpCondition) -> u8 {
+		match condition {
+			JumpCondition::IsNull => InsnParser::IFNULL,
+			JumpCondition::NotNull => InsnParser::IFNONNULL,
+			JumpCondition::ReferencesEqual => InsnParser::IF_ACMPEQ,
+			JumpCondition::ReferencesNotEqual => InsnParser::IF_ACMPNE,
+			JumpCondition::IntsEq => InsnParser::IF_ICMPEQ,
+			JumpCondition::IntsNotEq => InsnParser::IF_ICMPNE,
+			JumpCondition::IntsLessThan => InsnParser::IF_ICMPLT,
+			JumpCondition::IntsLessThanOrEq => InsnParser::IF_ICMPLE,
+			JumpCondition::IntsGreaterThan => InsnParser::IF_ICMPGT,
+			JumpCondition::IntsGreaterThanOrEq => InsnParser::IF_ICMPGE,
+			JumpCondition::IntEqZero => InsnParser::IFEQ,
+			JumpCondition::IntNotEqZero => InsnParser::IFNE,
+			JumpCondition::IntLessThanZero => InsnParser::IFLT,
+			JumpCondition::IntLessThanOrEqZero => InsnParser::IFLE,
+			JumpCondition::IntGreaterThanZero => InsnParser::IFGT,
+			JumpCondition::IntGreaterThanOrEqZero => InsnParser::IFGE
+		}
+	}
+	
+	/// Writes the instructions into `buf`, which must be empty
+	fn write_insns(code: &CodeAttribute, constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext) -> Result<(Vec<u8>, HashMap<LabelInsn, u32>)> {
+		let mut index = None;
+		InsnParser::write_insns_at(code, constant_pool, context, &mut index).map_err(|err| match index {
+			Some(index) => ParserError::at_segment(PathSegment::Insn(index), err),
+			None => err
+		})
+	}
+	
+	/// [`write_insns`](Self::write_insns), keeping `index` at the instruction being written
+	fn write_insns_at(code: &CodeAttribute, constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext, index: &mut Option<usize>) -> Result<(Vec<u8>, HashMap<LabelInsn, u32>)> {
+		let mut buf = context.take();
+		buf.reserve(code.insns.len());
+		let mut wtr: Cursor<Vec<u8>> = Cursor::new(buf);
 		
 		let mut label_pc_map: HashMap<LabelInsn, u32> = HashMap::new();
 		
@@ -1214,18 +2167,38 @@ impl InsnParser {
 			/// 5: NOP
 			/// 6: NOP
 			/// 7: NOP
-			Conditional(u32),
+			/// The condition is kept to invert it if the offset needs a GOTO_W
+			Conditional(u32, JumpCondition),
 			/// 0: indexbyte_1
 			/// 1: indexbyte_2
 			/// 2: indexbyte_3
 			/// 3: indexbyte_4
-			Direct(u32)
+			/// The second value is the pc of the switch the offset is relative to
+			Direct(u32, u32)
+		}
+		
+		/// Writes the offset of `label` from the switch at `from`, or a placeholder filled in once
+		/// the label is written if it is further on
+		fn write_switch_offset(wtr: &mut Cursor<Vec<u8>>, label_pc_map: &HashMap<LabelInsn, u32>, forward_references: &mut HashMap<LabelInsn, Vec<ReferenceType>>, label: LabelInsn, from: u32, at: u32) -> Result<()> {
+			let offset = match label_pc_map.get(&label) {
+				Some(to) => *to as i32 - from as i32,
+				None => {
+					forward_references.entry(label).or_default().push(ReferenceType::Direct(at, from));
+					0
+				}
+			};
+			wtr.write_i32::<BigEndian>(offset)?;
+			Ok(())
 		}
 		
 		let mut forward_references: HashMap<LabelInsn, Vec<ReferenceType>> = HashMap::new();
 		
 		let mut pc = 0u32;
-		for insn in code.insns.iter() {
+		for (i, insn) in code.insns.iter().enumerate() {
+			*index = Some(i);
+			let this_pc = pc;
+			// jumps and constant loads can take less than this
+			let max_size = insn.encoded_size(this_pc)?;
 			match insn {
 				Insn::Label(x) => {
 					label_pc_map.insert(*x, pc);
@@ -1237,11 +2210,12 @@ impl InsnParser {
 									let i = *at as usize;
 									let offset: i32 = pc as i32 - i as i32;
 									let off_bytes = offset.to_be_bytes();
-									if off_bytes[0] == 0 && off_bytes[1] == 0 {
+									if i16::try_from(offset).is_ok() {
 										vec_mut[i + 1] = off_bytes[2];
 										vec_mut[i + 2] = off_bytes[3];
 									} else {
 										// need to replace with a GOTO_W
+										context.wide_jump();
 										vec_mut[i] = InsnParser::GOTO_W;
 										vec_mut[i + 1] = off_bytes[0];
 										vec_mut[i + 2] = off_bytes[1];
@@ -1249,16 +2223,18 @@ impl InsnParser {
 										vec_mut[i + 4] = off_bytes[3];
 									}
 								}
-								ReferenceType::Conditional(at) => {
+								ReferenceType::Conditional(at, condition) => {
 									let i = *at as usize;
 									let offset_1: i32 = pc as i32 - i as i32;
 									let off_bytes = offset_1.to_be_bytes();
-									if off_bytes[0] == 0 && off_bytes[1] == 0 {
+									if i16::try_from(offset_1).is_ok() {
 										vec_mut[i + 1] = off_bytes[2];
 										vec_mut[i + 2] = off_bytes[3];
 									} else {
-										// need to add a a GOTO_W
-										let off_bytes_1 = 3i32.to_be_bytes();
+										// the inverted condition skips a GOTO_W to the target
+										context.wide_jump();
+										vec_mut[i] = InsnParser::condition_opcode(condition.negate());
+										let off_bytes_1 = 8i32.to_be_bytes();
 										vec_mut[i + 1] = off_bytes_1[2];
 										vec_mut[i + 2] = off_bytes_1[3];
 										let offset_2: i32 = pc as i32 - i as i32 - 3;
@@ -1270,9 +2246,9 @@ impl InsnParser {
 										vec_mut[i + 7] = off_bytes_2[3];
 									}
 								}
-								ReferenceType::Direct(at) => {
+								ReferenceType::Direct(at, from) => {
 									let i = *at as usize;
-									let offset: i32 = pc as i32 - i as i32;
+									let offset: i32 = pc as i32 - *from as i32;
 									let off_bytes = offset.to_be_bytes();
 									vec_mut[i]     = off_bytes[0];
 									vec_mut[i + 1] = off_bytes[1];
@@ -1285,7 +2261,7 @@ impl InsnParser {
 				}
 				Insn::ArrayLoad(x) => {
 					wtr.write_u8(match &x.kind {
-						Type::Reference(x) => InsnParser::AALOAD,
+						Type::Reference(_) | Type::Array(_) => InsnParser::AALOAD,
 						Type::Byte | Type::Boolean => InsnParser::BALOAD,
 						Type::Char => InsnParser::CALOAD,
 						Type::Short => InsnParser::SALOAD,
@@ -1295,11 +2271,10 @@ impl InsnParser {
 						Type::Double => InsnParser::DALOAD,
 						Type::Void => return Err(ParserError::invalid_insn(pc, "Cannot use type Void in array load"))
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::ArrayStore(x) => {
 					wtr.write_u8(match &x.kind {
-						Type::Reference(x) => InsnParser::AASTORE,
+						Type::Reference(_) | Type::Array(_) => InsnParser::AASTORE,
 						Type::Byte | Type::Boolean => InsnParser::BASTORE,
 						Type::Char => InsnParser::CASTORE,
 						Type::Short => InsnParser::SASTORE,
@@ -1309,63 +2284,27 @@ impl InsnParser {
 						Type::Double => InsnParser::DASTORE,
 						Type::Void => return Err(ParserError::invalid_insn(pc, "Cannot use type Void in array store"))
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Ldc(x) => {
-					pc = pc.checked_add(match &x.constant {
-						LdcType::Null => {
-							wtr.write_u8(InsnParser::ACONST_NULL)?;
-							1
+					if let LdcType::Dynamic(constant) = &x.constant {
+						if constant.bootstrap_method.class == UNRESOLVED_BOOTSTRAP && constant.bootstrap_method.name == UNRESOLVED_BOOTSTRAP {
+							return Err(ParserError::unimplemented("Writing a dynamic constant whose bootstrap method was not parsed"));
 						}
-						LdcType::String(x) => InsnParser::write_ldc(&mut wtr, constant_pool.string_utf(x.clone()), false)?,
-						LdcType::Int(x) => InsnParser::write_ldc(&mut wtr, constant_pool.integer(*x), false)?,
-						LdcType::Float(x) => InsnParser::write_ldc(&mut wtr, constant_pool.float(*x), false)?,
-						LdcType::Long(x) => InsnParser::write_ldc(&mut wtr, constant_pool.long(*x), false)?,
-						LdcType::Double(x) => InsnParser::write_ldc(&mut wtr, constant_pool.double(*x), false)?,
-						LdcType::Class(x) => InsnParser::write_ldc(&mut wtr, constant_pool.class_utf8(x.clone()), false)?,
-						LdcType::MethodType(x) => InsnParser::write_ldc(&mut wtr, constant_pool.methodtype_utf8(x.clone()), false)?,
-						LdcType::MethodHandle() => return Err(ParserError::invalid_insn(pc, "MethodHandle LDC")),
-						LdcType::Dynamic() => return Err(ParserError::invalid_insn(pc, "Dynamic LDC")),
-					}).ok_or_else(ParserError::too_many_instructions)?;
+					}
+					match x.constant.to_bootstrap_argument() {
+						None => wtr.write_u8(InsnParser::ACONST_NULL)?,
+						Some(constant) => InsnParser::write_ldc(&mut wtr, constant_pool.loadable_constant(&constant), constant.double_size())?
+					}
 				}
 				Insn::LocalLoad(x) => {
-					let (op0, op1, op2, op3, opx) = match &x.kind {
-						OpType::Reference => (InsnParser::ALOAD_0, InsnParser::ALOAD_1, InsnParser::ALOAD_2, InsnParser::ALOAD_3, InsnParser::ALOAD),
-						OpType::Short | OpType::Char | OpType::Byte | OpType::Boolean | OpType::Int => (InsnParser::ILOAD_0, InsnParser::ILOAD_1, InsnParser::ILOAD_2, InsnParser::ILOAD_3, InsnParser::ILOAD),
-						OpType::Float => (InsnParser::FLOAD_0, InsnParser::FLOAD_1, InsnParser::FLOAD_2, InsnParser::FLOAD_3, InsnParser::FLOAD),
-						OpType::Double => (InsnParser::DLOAD_0, InsnParser::DLOAD_1, InsnParser::DLOAD_2, InsnParser::DLOAD_3, InsnParser::DLOAD),
-						OpType::Long => (InsnParser::LLOAD_0, InsnParser::LLOAD_1, InsnParser::LLOAD_2, InsnParser::LLOAD_3, InsnParser::LLOAD),
-					};
-					match x.index {
-						0 => {
-							wtr.write_u8(op0)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						1 => {
-							wtr.write_u8(op1)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						2 => {
-							wtr.write_u8(op2)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						3 => {
-							wtr.write_u8(op3)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						index => {
-							if index <= 0xFF {
-								wtr.write_u8(opx)?;
-								wtr.write_u8(index as u8)?;
-								pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
-							} else {
-								wtr.write_u8(InsnParser::WIDE)?;
-								wtr.write_u8(opx)?;
-								wtr.write_u16::<BigEndian>(index)?;
-								pc = pc.checked_add(4).ok_or_else(ParserError::too_many_instructions)?;
-							}
-						}
-					}
+					let (op0, op1, op2, op3, opx) = match &x.kind {
+						OpType::Reference => (InsnParser::ALOAD_0, InsnParser::ALOAD_1, InsnParser::ALOAD_2, InsnParser::ALOAD_3, InsnParser::ALOAD),
+						OpType::Short | OpType::Char | OpType::Byte | OpType::Boolean | OpType::Int => (InsnParser::ILOAD_0, InsnParser::ILOAD_1, InsnParser::ILOAD_2, InsnParser::ILOAD_3, InsnParser::ILOAD),
+						OpType::Float => (InsnParser::FLOAD_0, InsnParser::FLOAD_1, InsnParser::FLOAD_2, InsnParser::FLOAD_3, InsnParser::FLOAD),
+						OpType::Double => (InsnParser::DLOAD_0, InsnParser::DLOAD_1, InsnParser::DLOAD_2, InsnParser::DLOAD_3, InsnParser::DLOAD),
+						OpType::Long => (InsnParser::LLOAD_0, InsnParser::LLOAD_1, InsnParser::LLOAD_2, InsnParser::LLOAD_3, InsnParser::LLOAD),
+					};
+					InsnParser::write_local(&mut wtr, [op0, op1, op2, op3], opx, x.index)?;
 				}
 				Insn::LocalStore(x) => {
 					let (op0, op1, op2, op3, opx) = match &x.kind {
@@ -1375,36 +2314,7 @@ impl InsnParser {
 						OpType::Double => (InsnParser::DSTORE_0, InsnParser::DSTORE_1, InsnParser::DSTORE_2, InsnParser::DSTORE_3, InsnParser::DSTORE),
 						OpType::Long => (InsnParser::LSTORE_0, InsnParser::LSTORE_1, InsnParser::LSTORE_2, InsnParser::LSTORE_3, InsnParser::LSTORE)
 					};
-					match x.index {
-						0 => {
-							wtr.write_u8(op0)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						1 => {
-							wtr.write_u8(op1)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						2 => {
-							wtr.write_u8(op2)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						3 => {
-							wtr.write_u8(op3)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						index => {
-							if index <= 0xFF {
-								wtr.write_u8(opx)?;
-								wtr.write_u8(index as u8)?;
-								pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
-							} else {
-								wtr.write_u8(InsnParser::WIDE)?;
-								wtr.write_u8(opx)?;
-								wtr.write_u16::<BigEndian>(index)?;
-								pc = pc.checked_add(4).ok_or_else(ParserError::too_many_instructions)?;
-							}
-						}
-					}
+					InsnParser::write_local(&mut wtr, [op0, op1, op2, op3], opx, x.index)?;
 				}
 				Insn::NewArray(x) => {
 					match &x.kind {
@@ -1418,49 +2328,17 @@ impl InsnParser {
 							};
 							wtr.write_u8(InsnParser::ANEWARRAY)?;
 							wtr.write_u16::<BigEndian>(constant_pool.class_utf8(cls))?;
-							pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						Type::Boolean => {
-							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(4)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						Type::Byte => {
-							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(8)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						Type::Char => {
-							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(5)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						Type::Short => {
-							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(9)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
-						}
-						Type::Int => {
-							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(10)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
 						}
-						Type::Long => {
-							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(11)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
+						Type::Array(component) => {
+							wtr.write_u8(InsnParser::ANEWARRAY)?;
+							wtr.write_u16::<BigEndian>(constant_pool.class_utf8(format!("[{}", component.descriptor())))?;
 						}
-						Type::Float => {
+						primitive => {
+							let atype = InsnParser::array_type_code(primitive)
+								.ok_or_else(|| ParserError::invalid_insn(pc, "Cannot use type Void in newarray"))?;
 							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(6)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
+							wtr.write_u8(atype)?;
 						}
-						Type::Double => {
-							wtr.write_u8(InsnParser::NEWARRAY)?;
-							wtr.write_u8(7)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
-						},
-						Type::Void => return Err(ParserError::invalid_insn(pc, "Cannot use type Void in newarray"))
 					}
 				}
 				Insn::Return(x) => {
@@ -1477,20 +2355,16 @@ impl InsnParser {
 						ReturnType::Float => wtr.write_u8(InsnParser::FRETURN)?,
 						ReturnType::Double => wtr.write_u8(InsnParser::DRETURN)?,
 					}
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::ArrayLength(x) => {
 					wtr.write_u8(InsnParser::ARRAYLENGTH)?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Throw(x) => {
 					wtr.write_u8(InsnParser::ATHROW)?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::CheckCast(x) => {
 					wtr.write_u8(InsnParser::CHECKCAST)?;
-					wtr.write_u16::<BigEndian>(constant_pool.class_utf8(x.kind.clone()))?;
-					pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
+					wtr.write_u16::<BigEndian>(constant_pool.class_utf8(x.kind.name()))?;
 				}
 				Insn::Convert(x) => {
 					match &x.from {
@@ -1504,7 +2378,6 @@ impl InsnParser {
 								PrimitiveType::Float => InsnParser::I2F,
 								PrimitiveType::Double => InsnParser::I2D
 							})?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 						}
 						PrimitiveType::Long => {
 							wtr.write_u8(match &x.to {
@@ -1513,7 +2386,6 @@ impl InsnParser {
 								PrimitiveType::Float => InsnParser::L2F,
 								PrimitiveType::Double => InsnParser::L2D
 							})?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 						}
 						PrimitiveType::Float => {
 							wtr.write_u8(match &x.to {
@@ -1522,7 +2394,6 @@ impl InsnParser {
 								PrimitiveType::Float => InsnParser::NOP,
 								PrimitiveType::Double => InsnParser::F2D
 							})?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 						}
 						PrimitiveType::Double => {
 							wtr.write_u8(match &x.to {
@@ -1531,7 +2402,6 @@ impl InsnParser {
 								PrimitiveType::Float => InsnParser::D2F,
 								PrimitiveType::Double => InsnParser::NOP
 							})?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 						}
 					}
 				}
@@ -1546,7 +2416,6 @@ impl InsnParser {
 						PrimitiveType::Float => InsnParser::FADD,
 						PrimitiveType::Double => InsnParser::DADD
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Compare(x) => {
 					match &x.kind {
@@ -1554,19 +2423,15 @@ impl InsnParser {
 							// there's no int comparison opcode, but we can use long comparison
 							wtr.write_u8(InsnParser::I2L)?;
 							wtr.write_u8(InsnParser::LCMP)?;
-							pc = pc.checked_add(2).ok_or_else(ParserError::too_many_instructions)?;
 						}
 						PrimitiveType::Long => {
 							wtr.write_u8(InsnParser::LCMP)?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 						}
 						PrimitiveType::Float => {
 							wtr.write_u8(if x.pos_on_nan { InsnParser::FCMPG } else { InsnParser::FCMPL })?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 						}
 						PrimitiveType::Double => {
 							wtr.write_u8(if x.pos_on_nan { InsnParser::DCMPG } else { InsnParser::DCMPL })?;
-							pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 						}
 					}
 				}
@@ -1577,7 +2442,6 @@ impl InsnParser {
 						PrimitiveType::Float => InsnParser::FDIV,
 						PrimitiveType::Double => InsnParser::DDIV
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Multiply(x) => {
 					wtr.write_u8(match &x.kind {
@@ -1586,7 +2450,6 @@ impl InsnParser {
 						PrimitiveType::Float => InsnParser::FMUL,
 						PrimitiveType::Double => InsnParser::DMUL
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Negate(x) => {
 					wtr.write_u8(match &x.kind {
@@ -1595,7 +2458,6 @@ impl InsnParser {
 						PrimitiveType::Float => InsnParser::FNEG,
 						PrimitiveType::Double => InsnParser::DNEG
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Remainder(x) => {
 					wtr.write_u8(match &x.kind {
@@ -1604,7 +2466,6 @@ impl InsnParser {
 						PrimitiveType::Float => InsnParser::FREM,
 						PrimitiveType::Double => InsnParser::DREM
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Subtract(x) => {
 					wtr.write_u8(match &x.kind {
@@ -1613,49 +2474,42 @@ impl InsnParser {
 						PrimitiveType::Float => InsnParser::FSUB,
 						PrimitiveType::Double => InsnParser::DSUB
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::And(x) => {
 					wtr.write_u8(match &x.kind {
 						IntegerType::Int => InsnParser::IAND,
 						IntegerType::Long => InsnParser::LAND
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Or(x) => {
 					wtr.write_u8(match &x.kind {
 						IntegerType::Int => InsnParser::IOR,
 						IntegerType::Long => InsnParser::LOR
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Xor(x) => {
 					wtr.write_u8(match &x.kind {
 						IntegerType::Int => InsnParser::IXOR,
 						IntegerType::Long => InsnParser::LXOR
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::ShiftLeft(x) => {
 					wtr.write_u8(match &x.kind {
 						IntegerType::Int => InsnParser::ISHL,
 						IntegerType::Long => InsnParser::LSHL
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::ShiftRight(x) => {
 					wtr.write_u8(match &x.kind {
 						IntegerType::Int => InsnParser::ISHR,
 						IntegerType::Long => InsnParser::LSHR
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::LogicalShiftRight(x) => {
 					wtr.write_u8(match &x.kind {
 						IntegerType::Int => InsnParser::IUSHR,
 						IntegerType::Long => InsnParser::LUSHR
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Dup(x) => {
 					wtr.write_u8(match x.num {
@@ -1677,46 +2531,42 @@ impl InsnParser {
 						}
 						_ => return Err(ParserError::invalid_insn(pc, "DupInsn::num must be in the range 1-2"))
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Pop(x) => {
 					wtr.write_u8(match x.pop_two {
 						false => InsnParser::POP,
 						true => InsnParser::POP2,
 					})?;
-					pc = pc.checked_add(1).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::GetField(x) => {
+					parse_field_desc(&x.descriptor).context(|| insn.to_string())?;
 					wtr.write_u8(if x.instance { InsnParser::GETFIELD } else { InsnParser::GETSTATIC })?;
 					let class_ref = constant_pool.class_utf8(x.class.clone());
 					let name_ref = constant_pool.utf8(x.name.clone());
 					let desc_ref = constant_pool.utf8(x.descriptor.clone());
 					let nametype_ref = constant_pool.nameandtype(name_ref, desc_ref);
 					wtr.write_u16::<BigEndian>(constant_pool.fieldref(class_ref, nametype_ref))?;
-					pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::PutField(x) => {
+					parse_field_desc(&x.descriptor).context(|| insn.to_string())?;
 					wtr.write_u8(if x.instance { InsnParser::PUTFIELD } else { InsnParser::PUTSTATIC })?;
 					let class_ref = constant_pool.class_utf8(x.class.clone());
 					let name_ref = constant_pool.utf8(x.name.clone());
 					let desc_ref = constant_pool.utf8(x.descriptor.clone());
 					let nametype_ref = constant_pool.nameandtype(name_ref, desc_ref);
 					wtr.write_u16::<BigEndian>(constant_pool.fieldref(class_ref, nametype_ref))?;
-					pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
 				}
 				Insn::Jump(x) => {
 					if let Some(to) = label_pc_map.get(&x.jump_to) {
-						let offset: i32 = pc as i32 - (*to) as i32;
-						let off_bytes = offset.to_be_bytes();
 						// backwards reference
-						if off_bytes[0] == 0 && off_bytes[1] == 0 {
+						let offset: i32 = (*to) as i32 - pc as i32;
+						if let Ok(offset) = i16::try_from(offset) {
 							wtr.write_u8(InsnParser::GOTO)?;
-							wtr.write_i16::<BigEndian>(offset as i16)?;
-							pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
+							wtr.write_i16::<BigEndian>(offset)?;
 						} else {
+							context.wide_jump();
 							wtr.write_u8(InsnParser::GOTO_W)?;
 							wtr.write_i32::<BigEndian>(offset)?;
-							pc = pc.checked_add(5).ok_or_else(ParserError::too_many_instructions)?;
 						}
 					} else {
 						if let Some(vec) = forward_references.get_mut(&x.jump_to) {
@@ -1729,49 +2579,30 @@ impl InsnParser {
 						wtr.write_u16::<BigEndian>(0)?;
 						wtr.write_u8(InsnParser::NOP)?;
 						wtr.write_u8(InsnParser::NOP)?;
-						pc = pc.checked_add(8).ok_or_else(ParserError::too_many_instructions)?;
 					}
 				}
 				Insn::ConditionalJump(x) => {
-					let opcode = match x.condition {
-						JumpCondition::IsNull => InsnParser::IFNULL,
-						JumpCondition::NotNull => InsnParser::IFNONNULL,
-						JumpCondition::ReferencesEqual => InsnParser::IF_ACMPEQ,
-						JumpCondition::ReferencesNotEqual => InsnParser::IF_ACMPNE,
-						JumpCondition::IntsEq => InsnParser::IF_ICMPEQ,
-						JumpCondition::IntsNotEq => InsnParser::IF_ICMPNE,
-						JumpCondition::IntsLessThan => InsnParser::IF_ICMPLT,
-						JumpCondition::IntsLessThanOrEq => InsnParser::IF_ICMPLE,
-						JumpCondition::IntsGreaterThan => InsnParser::IF_ICMPGT,
-						JumpCondition::IntsGreaterThanOrEq => InsnParser::IF_ICMPGE,
-						JumpCondition::IntEqZero => InsnParser::IFEQ,
-						JumpCondition::IntNotEqZero => InsnParser::IFNE,
-						JumpCondition::IntLessThanZero => InsnParser::IFLT,
-						JumpCondition::IntLessThanOrEqZero => InsnParser::IFLE,
-						JumpCondition::IntGreaterThanZero => InsnParser::IFGT,
-						JumpCondition::IntGreaterThanOrEqZero => InsnParser::IFGE
-					};
+					let opcode = InsnParser::condition_opcode(x.condition);
 					
 					if let Some(to) = label_pc_map.get(&x.jump_to) {
-						let offset: i32 = pc as i32 - (*to) as i32;
-						let off_bytes = offset.to_be_bytes();
 						// backwards reference
-						if off_bytes[0] == 0 && off_bytes[1] == 0 {
+						let offset: i32 = (*to) as i32 - pc as i32;
+						if let Ok(short) = i16::try_from(offset) {
 							wtr.write_u8(opcode)?;
-							wtr.write_i16::<BigEndian>(offset as i16)?;
-							pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
+							wtr.write_i16::<BigEndian>(short)?;
 						} else {
-							wtr.write_u8(opcode)?;
-							wtr.write_u16::<BigEndian>(3)?;
+							// the inverted condition skips a GOTO_W to the target
+							context.wide_jump();
+							wtr.write_u8(InsnParser::condition_opcode(x.condition.negate()))?;
+							wtr.write_u16::<BigEndian>(8)?;
 							wtr.write_u8(InsnParser::GOTO_W)?;
 							wtr.write_i32::<BigEndian>(offset - 3)?;
-							pc = pc.checked_add(8).ok_or_else(ParserError::too_many_instructions)?;
 						}
 					} else {
 						if let Some(vec) = forward_references.get_mut(&x.jump_to) {
-							vec.push(ReferenceType::Conditional(pc));
+							vec.push(ReferenceType::Conditional(pc, x.condition));
 						} else {
-							let vec = vec![ReferenceType::Conditional(pc)];
+							let vec = vec![ReferenceType::Conditional(pc, x.condition)];
 							forward_references.insert(x.jump_to, vec);
 						}
 						wtr.write_u8(opcode)?;
@@ -1781,35 +2612,47 @@ impl InsnParser {
 						wtr.write_u8(InsnParser::NOP)?;
 						wtr.write_u8(InsnParser::NOP)?;
 						wtr.write_u8(InsnParser::NOP)?;
-						pc = pc.checked_add(8).ok_or_else(ParserError::too_many_instructions)?;
 					}
 				}
 				Insn::IncrementInt(x) => {
 					let index = x.index;
 					let amount = x.amount;
-					// need to check if we can fit the amount into 1 byte
-					if let (Ok(index), Ok(amount)) = (u8::try_from(index), i8::try_from(amount)) {
+					if fits_u8(index) && fits_i8(amount) {
 						wtr.write_u8(InsnParser::IINC)?;
-						wtr.write_u8(index)?;
-						wtr.write_i8(amount)?;
-						pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
+						wtr.write_u8(index as u8)?;
+						wtr.write_i8(amount as i8)?;
 					} else {
 						wtr.write_u8(InsnParser::WIDE)?;
 						wtr.write_u8(InsnParser::IINC)?;
 						wtr.write_u16::<BigEndian>(index)?;
 						wtr.write_i16::<BigEndian>(amount)?;
-						pc = pc.checked_add(6).ok_or_else(ParserError::too_many_instructions)?;
 					}
 				}
 				Insn::InstanceOf(x) => {
 					wtr.write_u8(InsnParser::INSTANCEOF)?;
-					wtr.write_u16::<BigEndian>(constant_pool.class_utf8(x.class.clone()))?;
-					pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
+					wtr.write_u16::<BigEndian>(constant_pool.class_utf8(x.class.name()))?;
 				}
 				Insn::InvokeDynamic(x) => {
-					return Err(ParserError::unimplemented("Invokedynamic writing unimplemented"));
+					if x.bootstrap_class == UNRESOLVED_BOOTSTRAP && x.bootstrap_method == UNRESOLVED_BOOTSTRAP {
+						return Err(ParserError::unimplemented("Writing an invokedynamic whose bootstrap method was not parsed"));
+					}
+					parse_method_desc(&x.descriptor).context(|| insn.to_string())?;
+					let kind = match x.bootstrap_type {
+						BootstrapMethodType::InvokeStatic => MethodHandleKind::InvokeStatic,
+						BootstrapMethodType::NewInvokeSpecial => MethodHandleKind::NewInvokeSpecial
+					};
+					let handle = MethodHandle::new(kind, x.bootstrap_class.clone(), x.bootstrap_method.clone(), x.bootstrap_descriptor.clone(), false);
+					let bootstrap_method = constant_pool.bootstrap_method_of(&handle, &x.bootstrap_arguments);
+					let name = constant_pool.utf8(x.name.clone());
+					let desc = constant_pool.utf8(x.descriptor.clone());
+					let nandt = constant_pool.nameandtype(name, desc);
+					wtr.write_u8(InsnParser::INVOKEDYNAMIC)?;
+					wtr.write_u16::<BigEndian>(constant_pool.invokedynamicinfo(bootstrap_method, nandt))?;
+					// reserved, always zero
+					wtr.write_u16::<BigEndian>(0)?;
 				}
 				Insn::Invoke(x) => {
+					let (args, _) = parse_method_desc(&x.descriptor).context(|| insn.to_string())?;
 					let opcode = match x.kind {
 						InvokeType::Instance => InsnParser::INVOKEVIRTUAL,
 						InvokeType::Static => InsnParser::INVOKESTATIC,
@@ -1827,102 +2670,1397 @@ impl InsnParser {
 						// the difference between the size of the operand stack before and after the instruction
 						// executes.
 						let mut count = 1; // interface methods are virtual so there is always at least one
-						let (args, _) = parse_method_desc(&x.descriptor)?;
 						for arg in args.iter() {
 							count += arg.size();
 						}
 						wtr.write_u8(count)?;
 						wtr.write_u8(0)?;
-						pc = pc.checked_add(5).ok_or_else(ParserError::too_many_instructions)?;
 					} else {
 						let class = constant_pool.class_utf8(x.class.clone());
 						let name = constant_pool.utf8(x.name.clone());
 						let desc = constant_pool.utf8(x.descriptor.clone());
 						let nandt = constant_pool.nameandtype(name, desc);
-						wtr.write_u16::<BigEndian>(constant_pool.methodref(class, nandt))?;
-						pc = pc.checked_add(3).ok_or_else(ParserError::too_many_instructions)?;
+						// static and super calls of interface methods, such as defaults, need an
+						// interface method ref, while invokevirtual only takes a method ref
+						let method = if x.interface_method && x.kind != InvokeType::Instance {
+							constant_pool.interfacemethodref(class, nandt)
+						} else {
+							constant_pool.methodref(class, nandt)
+						};
+						wtr.write_u16::<BigEndian>(method)?;
 					}
 				}
 				Insn::LookupSwitch(x) => {
+					let pad = InsnParser::switch_padding(this_pc);
 					wtr.write_u8(InsnParser::LOOKUPSWITCH)?;
-					let pad = (4 - (pc % 4)) % 4;
-					for i in 0..pad {
+					for _ in 0..pad {
 						wtr.write_u8(0)?;
 					}
-					
-					if let Some(at) = label_pc_map.get(&x.default) {
-						let offset: i32 = pc as i32 - (*at) as i32;
-						wtr.write_i32::<BigEndian>(offset)?;
-					} else {
-						if let Some(vec) = forward_references.get_mut(&x.default) {
-							vec.push(ReferenceType::Direct(pc + 2));
-						} else {
-							let vec = vec![ReferenceType::Direct(pc + 2)];
-							forward_references.insert(x.default, vec);
-						}
-						wtr.write_i32::<BigEndian>(0)?;
-					}
-					
+					let mut at = this_pc + 1 + pad;
+					write_switch_offset(&mut wtr, &label_pc_map, &mut forward_references, x.default, this_pc, at)?;
 					wtr.write_i32::<BigEndian>(x.cases.len() as i32)?;
-					
-					pc = pc.checked_add(10).ok_or_else(ParserError::too_many_instructions)?;
-					
+					at += 8;
 					for (case, to) in x.cases.iter() {
 						wtr.write_i32::<BigEndian>(*case)?;
-						if let Some(at) = label_pc_map.get(to) {
-							let offset: i32 = (pc + 4) as i32 - (*at) as i32;
-							wtr.write_i32::<BigEndian>(offset)?;
-						} else {
-							if let Some(vec) = forward_references.get_mut(to) {
-								vec.push(ReferenceType::Direct(pc + 4));
-							} else {
-								let vec = vec![ReferenceType::Direct(pc + 4)];
-								forward_references.insert(*to, vec);
-							}
-							wtr.write_i32::<BigEndian>(0)?;
-						}
-						pc = pc.checked_add(8).ok_or_else(ParserError::too_many_instructions)?;
+						write_switch_offset(&mut wtr, &label_pc_map, &mut forward_references, *to, this_pc, at + 4)?;
+						at += 8;
 					}
 				}
 				Insn::TableSwitch(x) => {
+					let pad = InsnParser::switch_padding(this_pc);
+					if x.cases.is_empty() {
+						return Err(ParserError::invalid_insn(pc, "tableswitch must have at least one case"));
+					}
 					wtr.write_u8(InsnParser::TABLESWITCH)?;
-					let pad = (4 - (pc % 4)) % 4;
-					for i in 0..pad {
+					for _ in 0..pad {
 						wtr.write_u8(0)?;
 					}
+					let mut at = this_pc + 1 + pad;
+					write_switch_offset(&mut wtr, &label_pc_map, &mut forward_references, x.default, this_pc, at)?;
+					let high = x.low as i64 + x.cases.len() as i64 - 1;
+					wtr.write_i32::<BigEndian>(x.low)?;
+					wtr.write_i32::<BigEndian>(i32::try_from(high).map_err(|_| ParserError::other("Too many tableswitch cases"))?)?;
+					at += 12;
+					for to in x.cases.iter() {
+						write_switch_offset(&mut wtr, &label_pc_map, &mut forward_references, *to, this_pc, at)?;
+						at += 4;
+					}
+				}
+				Insn::MonitorEnter(_) => {
+					wtr.write_u8(InsnParser::MONITORENTER)?;
+				}
+				Insn::MonitorExit(_) => {
+					wtr.write_u8(InsnParser::MONITOREXIT)?;
+				}
+				Insn::MultiNewArray(x) => {
+					wtr.write_u8(InsnParser::MULTIANEWARRAY)?;
+					wtr.write_u16::<BigEndian>(constant_pool.class_utf8(x.kind.name()))?;
+					wtr.write_u8(x.dimensions)?;
+				}
+				Insn::NewObject(x) => {
+					wtr.write_u8(InsnParser::NEW)?;
+					wtr.write_u16::<BigEndian>(constant_pool.class_utf8(x.kind.name()))?;
+				}
+				Insn::Nop(_) => {
+					wtr.write_u8(InsnParser::NOP)?;
+				}
+				Insn::Swap(_) => {
+					wtr.write_u8(InsnParser::SWAP)?;
+				}
+				Insn::ImpDep1(_) => {
+					wtr.write_u8(InsnParser::IMPDEP1)?;
+				}
+				Insn::ImpDep2(_) => {
+					wtr.write_u8(InsnParser::IMPDEP2)?;
+				}
+				Insn::BreakPoint(_) => {
+					wtr.write_u8(InsnParser::BREAKPOINT)?;
 				}
-				Insn::MonitorEnter(_) => {}
-				Insn::MonitorExit(_) => {}
-				Insn::MultiNewArray(_) => {}
-				Insn::NewObject(_) => {}
-				Insn::Nop(_) => {}
-				Insn::Swap(_) => {}
-				Insn::ImpDep1(_) => {}
-				Insn::ImpDep2(_) => {}
-				Insn::BreakPoint(_) => {}
 			}
+			pc = u32::try_from(wtr.position()).map_err(|_| ParserError::too_many_instructions())?;
+			debug_assert!(pc - this_pc <= max_size, "{} took {} bytes, more than its encoded size of {}", insn, pc - this_pc, max_size);
 		}
+		*index = None;
+		// jumped to but never placed
+		if let Some(label) = forward_references.keys().filter(|x| !label_pc_map.contains_key(x)).min_by_key(|x| x.id) {
+			return Err(ParserError::unplaced_label(code.insns.describe_label(*label)));
+		}
+		
 		
 		Ok((wtr.into_inner(), label_pc_map))
 	}
 	
-	fn write_ldc<T: Write>(wtr: &mut T, constant: u16, double_size: bool) -> Result<u32> {
+	/// Writes a local variable load or store, using the form without an operand for indices 0 to 3
+	/// and `wide` only when the index does not fit in a byte
+	fn write_local<T: Write>(wtr: &mut T, short: [u8; 4], op: u8, index: u16) -> Result<()> {
+		match short.get(index as usize) {
+			Some(short) => wtr.write_u8(*short)?,
+			None if fits_u8(index) => {
+				wtr.write_u8(op)?;
+				wtr.write_u8(index as u8)?;
+			},
+			None => {
+				wtr.write_u8(InsnParser::WIDE)?;
+				wtr.write_u8(op)?;
+				wtr.write_u16::<BigEndian>(index)?;
+			}
+		}
+		Ok(())
+	}
+	
+	/// Writes the load of the constant
+	fn write_ldc<T: Write>(wtr: &mut T, constant: u16, double_size: bool) -> Result<()> {
 		// double sized constants must use LDC2 (only wide variant exists)
 		if double_size {
 			wtr.write_u8(InsnParser::LDC2_W)?;
 			wtr.write_u16::<BigEndian>(constant)?;
-			Ok(5)
+			Ok(())
 		} else {
 			// If we can fit the constant index into a u8 then use LDC otherwise use LDC_W
-			if constant <= 0xFF {
+			if fits_u8(constant) {
 				wtr.write_u8(InsnParser::LDC)?;
 				wtr.write_u8(constant as u8)?;
-				Ok(3)
+				Ok(())
 			} else {
 				wtr.write_u8(InsnParser::LDC_W)?;
 				wtr.write_u16::<BigEndian>(constant)?;
-				Ok(5)
+				Ok(())
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::attributes::{LineNumber, LineNumberTableAttribute, LocalVariableTableAttribute, LocalVariable};
+	use crate::constantpool::MemberIndices;
+	use crate::method::Method;
+	use crate::access::MethodAccessFlags;
+	use crate::Serializable;
+	use crate::classfile::ClassFile;
+	use crate::version::MajorVersion;
+	
+	fn ret() -> Insn {
+		Insn::Return(ReturnInsn::new(ReturnType::Void))
+	}
+	
+	fn call() -> Insn {
+		Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Static, ClassName::from("A"), String::from("a"), String::from("()V"), false)))
+	}
+	
+	#[test]
+	fn handler_only_reachable_through_exception_edge() {
+		let mut code = CodeAttribute::empty();
+		let start = code.insns.new_label();
+		let end = code.insns.new_label();
+		let handler = code.insns.new_label();
+		let junk = code.insns.new_label();
+		code.insns.insns = vec![
+			Insn::Label(start),
+			call(),
+			Insn::Label(end),
+			ret(),
+			Insn::Label(handler),
+			Insn::Pop(PopInsn::new(false)),
+			ret(),
+			// nothing jumps here
+			Insn::Label(junk),
+			Insn::Nop(NopInsn::new()),
+			Insn::Jump(JumpInsn::new(handler)),
+		];
+		code.exceptions.push(ExceptionHandler {
+			start,
+			end,
+			handler,
+			catch_type: None
+		});
+		
+		assert_eq!(code.remove_dead_code().unwrap(), 2);
+		assert_eq!(code.insns.insns, vec![
+			Insn::Label(start),
+			call(),
+			Insn::Label(end),
+			ret(),
+			Insn::Label(handler),
+			Insn::Pop(PopInsn::new(false)),
+			ret(),
+		]);
+		assert_eq!(code.exceptions.len(), 1);
+	}
+	
+	#[test]
+	fn dead_handlers_and_labels_are_pruned() {
+		let mut code = CodeAttribute::empty();
+		let start = code.insns.new_label();
+		let end = code.insns.new_label();
+		let handler = code.insns.new_label();
+		let var_end = code.insns.new_label();
+		code.insns.insns = vec![
+			ret(),
+			Insn::Label(start),
+			call(),
+			Insn::Label(end),
+			Insn::Nop(NopInsn::new()),
+			Insn::Label(var_end),
+			Insn::Label(handler),
+			Insn::Throw(ThrowInsn::new()),
+		];
+		code.exceptions.push(ExceptionHandler {
+			start,
+			end,
+			handler,
+			catch_type: Some(String::from("java/lang/Exception"))
+		});
+		code.attributes.push(Attribute::LocalVariableTable(LocalVariableTableAttribute::new(vec![LocalVariable {
+			start,
+			end: var_end,
+			name: String::from("x"),
+			descriptor: String::from("I"),
+			index: 0
+		}])));
+		
+		assert_eq!(code.remove_dead_code().unwrap(), 3);
+		assert!(code.exceptions.is_empty());
+		// the local variable labels survive, the handler labels do not
+		assert_eq!(code.insns.insns, vec![ret(), Insn::Label(start), Insn::Label(var_end)]);
+		assert_eq!(code.remove_dead_code().unwrap(), 0);
+	}
+	
+	/// A method with `size` bytes of code
+	fn sized_method(size: usize) -> Method {
+		let mut code = CodeAttribute::empty();
+		code.insns.insns = vec![Insn::Pop(PopInsn::new(false)); size - 1];
+		code.insns.insns.push(ret());
+		Method {
+			access_flags: MethodAccessFlags::STATIC,
+			name: String::from("big"),
+			descriptor: String::from("()V"),
+			attributes: vec![Attribute::Code(code)],
+			original_indices: None
+		}
+	}
+	
+	#[test]
+	fn method_at_size_limit_writes() {
+		let mut out = Vec::new();
+		sized_method(CodeAttribute::MAX_CODE_LENGTH).write(&mut out, &mut ConstantPoolWriter::new()).unwrap();
+	}
+	
+	#[test]
+	fn method_over_size_limit_fails() {
+		let err = sized_method(CodeAttribute::MAX_CODE_LENGTH + 1).write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err();
+		assert_eq!(err.contexts(), vec!["big()V", "attributes[0]"]);
+		assert!(matches!(err.root(), ParserError::MethodTooLarge { size: 0x10000 }));
+	}
+	
+	/// Fails unless the instructions only differ in label ids
+	fn assert_same_insns(a: &InsnList, b: &InsnList) {
+		assert!(a.structurally_equal(b), "{:?}\n!=\n{:?}", a, b);
+	}
+	
+	/// Writes the code and parses it back against the pool it was written with
+	fn round_trip(code: &CodeAttribute) -> (CodeAttribute, ConstantPool, u16) {
+		let mut writer = ConstantPoolWriter::new();
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut writer).unwrap();
+		let mut pool = Vec::new();
+		writer.write(&mut pool).unwrap();
+		let pool = <ConstantPool as Serializable>::parse(&mut Cursor::new(pool)).unwrap();
+		let version = ClassVersion { major: crate::version::MajorVersion::JAVA_8, minor: 0 };
+		let parsed = CodeAttribute::parse(&version, &pool, &mut Cursor::new(bytes.clone()), &ParseOptions::default()).unwrap();
+		(parsed, pool, writer.len())
+	}
+	
+	#[test]
+	fn labels_are_named_by_line() {
+		let mut code = CodeAttribute::empty();
+		let zero = code.insns.new_label();
+		let other = code.insns.new_label();
+		code.insns.insns = vec![
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 0)),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, zero)),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 0)),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntNotEqZero, other)),
+			Insn::Label(zero),
+			ret(),
+			Insn::Label(other),
+			ret()
+		];
+		// lines 7 and 8 start at the first return, 9 at the second
+		code.attributes.push(Attribute::LineNumberTable(LineNumberTableAttribute::new(vec![
+			LineNumber::new(zero, 7),
+			LineNumber::new(zero, 8),
+			LineNumber::new(other, 9)
+		])));
+		let (parsed, _, _) = round_trip(&code);
+		let labels: Vec<LabelInsn> = parsed.insns.iter().filter_map(|insn| match insn {
+			Insn::Label(x) => Some(*x),
+			_ => None
+		}).collect();
+		assert_eq!(labels.len(), 2);
+		assert_eq!(parsed.insns.label_name(labels[0]), Some("line_7"));
+		assert_eq!(parsed.insns.label_name(labels[1]), Some("line_9"));
+		assert!(parsed.disassemble().contains("    ifne line_9\n"), "{}", parsed.disassemble());
+	}
+	
+	#[test]
+	fn unplaced_labels_are_reported_by_name() {
+		let mut code = CodeAttribute::empty();
+		let missing = code.insns.new_named_label("cleanup");
+		code.insns.insns = vec![Insn::Jump(JumpInsn::new(missing)), ret()];
+		let err = code.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err();
+		assert!(matches!(err.root(), ParserError::UnplacedLabel { label } if label == "cleanup"), "{}", err);
+		
+		let start = code.insns.new_named_label("start");
+		code.insns.insns = vec![Insn::Label(start), ret()];
+		code.exceptions.push(ExceptionHandler { start, end: missing, handler: start, catch_type: None });
+		let err = code.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err();
+		assert!(err.to_string().contains("end label cleanup is not in the instructions"), "{}", err);
+		
+		let graph = ControlFlowGraph::build(&CodeAttribute {
+			insns: {
+				let mut insns = code.insns.clone();
+				insns.insns.insert(0, Insn::Jump(JumpInsn::new(missing)));
+				insns
+			},
+			..CodeAttribute::empty()
+		});
+		assert!(matches!(graph.unwrap_err().root(), ParserError::UnplacedLabel { label } if label == "cleanup"));
+	}
+	
+	#[test]
+	fn array_class_references_round_trip() {
+		let string = || Type::Reference(Some(String::from("java/lang/String")));
+		let array = |component: Type| Type::Array(Box::new(component));
+		let mut code = CodeAttribute::empty();
+		code.insns.insns = vec![
+			// (String[][]) o
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0)),
+			Insn::CheckCast(CheckCastInsn::new(ClassRef::Array(array(string())))),
+			Insn::InstanceOf(InstanceOfInsn::new(ClassRef::from("java/lang/Runnable"))),
+			Insn::Pop(PopInsn::new(false)),
+			// new int[2][3][4]
+			Insn::Ldc(LdcInsn::new(LdcType::Int(2))),
+			Insn::Ldc(LdcInsn::new(LdcType::Int(3))),
+			Insn::Ldc(LdcInsn::new(LdcType::Int(4))),
+			Insn::MultiNewArray(MultiNewArrayInsn::new(ClassRef::Array(array(array(Type::Int))), 3)),
+			Insn::Pop(PopInsn::new(false)),
+			// new String[1][]
+			Insn::Ldc(LdcInsn::new(LdcType::Int(1))),
+			Insn::NewArray(NewArrayInsn::new(array(string()))),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::NewObject(NewObjectInsn::new(ClassRef::from("java/lang/Object"))),
+			ret()
+		];
+		
+		let (parsed, pool, len) = round_trip(&code);
+		assert_same_insns(&parsed.insns, &code.insns);
+		let classes: Vec<String> = (1..=len).filter_map(|i| pool.class_name(i).ok()).collect();
+		assert_eq!(classes, vec!["[[Ljava/lang/String;", "java/lang/Runnable", "[[[I", "[Ljava/lang/String;", "java/lang/Object"]);
+	}
+	
+	#[test]
+	fn single_byte_insns_round_trip() {
+		let mut code = CodeAttribute::empty();
+		code.insns.insns = vec![
+			Insn::Nop(NopInsn::new()),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0)),
+			Insn::MonitorEnter(MonitorEnterInsn::new()),
+			Insn::NewObject(NewObjectInsn::new(ClassRef::from("java/lang/Object"))),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0)),
+			Insn::Swap(SwapInsn::new()),
+			Insn::MonitorExit(MonitorExitInsn::new()),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::BreakPoint(BreakPointInsn::new()),
+			Insn::ImpDep1(ImpDep1Insn::new()),
+			Insn::ImpDep2(ImpDep2Insn::new()),
+			ret()
+		];
+		
+		let mut writer = ConstantPoolWriter::new();
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut writer).unwrap();
+		// max stack, max locals and code length
+		assert_eq!(&bytes[..8], &[0, 0, 0, 0, 0, 0, 0, 14]);
+		assert_eq!(&bytes[8..22], &[0x00, 0x2A, 0xC2, 0xBB, 0, 2, 0x2A, 0x5F, 0xC3, 0x57, 0xCA, 0xFE, 0xFF, 0xB1][..]);
+		
+		let (parsed, _, _) = round_trip(&code);
+		assert_same_insns(&parsed.insns, &code.insns);
+	}
+	
+	#[test]
+	fn jumps_inside_insns_are_rejected() {
+		let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+		let parse = |code: &[u8]| {
+			let mut bytes = vec![0, 0, 0, 0, 0, 0, 0, code.len() as u8];
+			bytes.extend_from_slice(code);
+			bytes.extend_from_slice(&[0, 0, 0, 0]);
+			CodeAttribute::parse(&version, &ConstantPool::new(), &mut Cursor::new(bytes), &ParseOptions::default())
+		};
+		
+		// goto 3, return
+		assert!(parse(&[0xA7, 0, 3, 0xB1]).is_ok());
+		// goto 1, the middle of the goto itself
+		match parse(&[0xA7, 0, 1, 0xB1]) {
+			Err(ParserError::InvalidInstruction { pc: 1, .. }) => {},
+			x => panic!("{:?}", x)
+		}
+	}
+	
+	#[test]
+	fn local_variable_labels_between_insns() {
+		let mut writer = ConstantPoolWriter::new();
+		let table = writer.utf8("LocalVariableTable");
+		let name = writer.utf8("x");
+		let descriptor = writer.utf8("I");
+		let mut pool = Vec::new();
+		writer.write(&mut pool).unwrap();
+		let pool = <ConstantPool as Serializable>::parse(&mut Cursor::new(pool)).unwrap();
+		
+		// bipush 3, pop, return
+		let mut bytes = vec![0, 1, 0, 1, 0, 0, 0, 4, 0x10, 3, 0x57, 0xB1, 0, 0, 0, 1];
+		bytes.extend_from_slice(&table.to_be_bytes());
+		bytes.extend_from_slice(&[0, 0, 0, 12, 0, 1]);
+		// starting inside bipush and ending past the code
+		bytes.extend_from_slice(&[0, 1, 0, 9]);
+		bytes.extend_from_slice(&name.to_be_bytes());
+		bytes.extend_from_slice(&descriptor.to_be_bytes());
+		bytes.extend_from_slice(&[0, 0]);
+		
+		let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+		let code = CodeAttribute::parse(&version, &pool, &mut Cursor::new(bytes.clone()), &ParseOptions::default()).unwrap();
+		let (parsed, _, _) = round_trip(&code);
+		let variable = match &parsed.attributes[0] {
+			Attribute::LocalVariableTable(x) => &x.variables[0],
+			x => panic!("{:?}", x)
+		};
+		let at = |label| parsed.insns.iter().position(|insn| *insn == Insn::Label(label)).unwrap();
+		// moved to the next instruction and the end of the code
+		assert_eq!(at(variable.start), 1);
+		assert_eq!(at(variable.end), parsed.insns.len() - 1);
+	}
+	
+	#[test]
+	fn newarray_types_match_spec() {
+		// T_BOOLEAN to T_LONG from the JVM specification
+		let spec = [(Type::Boolean, 4), (Type::Char, 5), (Type::Float, 6), (Type::Double, 7), (Type::Byte, 8), (Type::Short, 9), (Type::Int, 10), (Type::Long, 11)];
+		for (kind, atype) in spec.iter() {
+			let mut code = CodeAttribute::empty();
+			code.insns.insns = vec![
+				Insn::NewArray(NewArrayInsn::new(kind.clone())),
+				ret()
+			];
+			let mut bytes = Vec::new();
+			code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+			assert_eq!(&bytes[8..10], &[InsnParser::NEWARRAY, *atype], "{:?}", kind);
+			
+			let (parsed, _, _) = round_trip(&code);
+			assert_same_insns(&parsed.insns, &code.insns);
+		}
+		
+		let mut code = CodeAttribute::empty();
+		code.insns.insns = vec![Insn::NewArray(NewArrayInsn::new(Type::Void))];
+		assert!(code.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).is_err());
+	}
+	
+	#[test]
+	fn class_ref_parse() {
+		assert_eq!(ClassRef::parse("java/lang/String").unwrap(), ClassRef::from("java/lang/String"));
+		assert_eq!(ClassRef::parse("[[I").unwrap(), ClassRef::Array(Type::Array(Box::new(Type::Int))));
+		assert_eq!(ClassRef::parse("[[I").unwrap().to_string(), "[[I");
+		assert_eq!(ClassRef::parse("[Ljava/lang/Object;").unwrap().as_type().descriptor(), "[Ljava/lang/Object;");
+		for invalid in ["[", "[V", "[Ljava/lang/Object", "[II"].iter() {
+			assert!(ClassRef::parse(invalid).is_err(), "{}", invalid);
+		}
+	}
+	
+	#[test]
+	fn raw_round_trip_keeps_encodings() {
+		let code = [
+			0x06, // iconst_3
+			0x10, 3, // bipush 3
+			0x2A, // aload_0
+			0x19, 0, // aload 0
+			0xC4, 0x15, 0, 1, // wide iload 1
+			0xC4, 0x84, 0, 1, 0xFF, 0xFF, // wide iinc 1 -1
+			// tableswitch with non zero padding
+			0xAA, 1, 2, 3,
+			0, 0, 0, 20, // default
+			0, 0, 0, 0, 0, 0, 0, 0, // 0 to 0
+			0, 0, 0, 20,
+			0xB1 // return
+		];
+		let mut bytes = vec![0, 4, 0, 2, 0, 0, 0, code.len() as u8];
+		bytes.extend_from_slice(&code);
+		// no exception handlers and one attribute
+		bytes.extend_from_slice(&[0, 0, 0, 1, 0, 9, 0, 0, 0, 2, 0xAB, 0xCD]);
+		
+		let raw = CodeAttribute::parse_raw(&bytes).unwrap();
+		let opcodes: Vec<u8> = raw.insns.iter().map(|x| x.opcode).collect();
+		assert_eq!(opcodes, vec![0x06, 0x10, 0x2A, 0x19, 0xC4, 0xC4, 0xAA, 0xB1]);
+		let pcs: Vec<u32> = raw.insns.iter().map(|x| x.pc).collect();
+		assert_eq!(pcs, vec![0, 1, 3, 4, 6, 10, 16, 36]);
+		assert_eq!(raw.insns[6].operands[..3], [1, 2, 3]);
+		
+		let mut written = Vec::new();
+		raw.write(&mut written).unwrap();
+		assert_eq!(written, bytes);
+	}
+	
+	#[test]
+	fn raw_round_trip_snapshot() {
+		let class = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let lazy = ClassFile::parse_lazy(class).unwrap();
+		for method in lazy.methods.iter() {
+			for attribute in method.attributes.iter().filter(|x| x.name == "Code") {
+				let bytes = &class[attribute.span.start + 6..attribute.span.end];
+				let raw = CodeAttribute::parse_raw(bytes).unwrap();
+				let mut written = Vec::new();
+				raw.write(&mut written).unwrap();
+				assert_eq!(written, bytes, "{}{}", method.name, method.descriptor);
+			}
+		}
+	}
+	
+	#[test]
+	fn raw_rejects_malformed_code() {
+		let parse = |code: &[u8]| {
+			let mut bytes = vec![0, 0, 0, 0, 0, 0, 0, code.len() as u8];
+			bytes.extend_from_slice(code);
+			CodeAttribute::parse_raw(&bytes)
+		};
+		assert!(parse(&[0xB1]).is_ok());
+		assert!(matches!(parse(&[0xCB]), Err(ParserError::UnknownInstruction { opcode: 0xCB })));
+		assert!(parse(&[0x10]).is_err());
+		assert!(parse(&[0xC4, 0x10, 0, 0]).is_err());
+		// tableswitch whose high is below its low
+		assert!(parse(&[0xAA, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+		assert!(parse(&[0xAA, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]).is_err());
+		assert!(CodeAttribute::parse_raw(&[0, 0, 0, 0, 0, 0, 0, 5, 0xB1]).is_err());
+	}
+	
+	#[test]
+	fn switches_round_trip_at_every_alignment() {
+		for nops in 0..4 {
+			let mut code = CodeAttribute::empty();
+			code.max_stack = 1;
+			code.max_locals = 1;
+			let back = code.insns.new_label();
+			let one = code.insns.new_label();
+			let two = code.insns.new_label();
+			let end = code.insns.new_label();
+			let mut lookup = LookupSwitchInsn::new(two);
+			lookup.cases.insert(-5, one);
+			lookup.cases.insert(1000, end);
+			code.insns.insns = vec![Insn::Nop(NopInsn::new()); nops];
+			code.insns.insns.extend(vec![
+				Insn::Label(back),
+				Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 0)),
+				Insn::TableSwitch(TableSwitchInsn::new(back, 0, vec![one, two])),
+				Insn::Label(one),
+				Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 0)),
+				Insn::LookupSwitch(lookup),
+				Insn::Label(two),
+				ret(),
+				Insn::Label(end),
+				ret()
+			]);
+			let (parsed, _, _) = round_trip(&code);
+			assert!(parsed.insns.structurally_equal(&code.insns), "{} nops: {:?}", nops, parsed.insns);
+			
+			let mut bytes = Vec::new();
+			code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+			let raw = CodeAttribute::parse_raw(&bytes).unwrap();
+			for insn in raw.insns.iter().filter(|x| x.opcode == InsnParser::TABLESWITCH || x.opcode == InsnParser::LOOKUPSWITCH) {
+				let pad = 3 - insn.pc as usize % 4;
+				assert_eq!((insn.pc as usize + 1 + pad) % 4, 0);
+				assert!(insn.operands[..pad].iter().all(|x| *x == 0), "{} nops", nops);
+			}
+			// the tableswitch defaults to the label just before its iload
+			let table = raw.insns.iter().find(|x| x.opcode == InsnParser::TABLESWITCH).unwrap();
+			let pad = 3 - table.pc as usize % 4;
+			assert_eq!(&table.operands[pad..pad + 4], &(-1i32).to_be_bytes());
+		}
+	}
+	
+	#[test]
+	fn encoded_sizes_match_written_sizes() {
+		let mut code = CodeAttribute::empty();
+		let end = code.insns.new_label();
+		let mut lookup = LookupSwitchInsn::new(end);
+		lookup.cases.insert(3, end);
+		let sizes = vec![
+			(Insn::Nop(NopInsn::new()), 1),
+			(Insn::Ldc(LdcInsn::new(LdcType::Null)), 1),
+			(Insn::LocalLoad(LocalLoadInsn::new(OpType::Long, 2)), 1),
+			(Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 200)), 2),
+			(Insn::LocalStore(LocalStoreInsn::new(OpType::Reference, 300)), 4),
+			(Insn::IncrementInt(IncrementIntInsn::new(1, -128)), 3),
+			(Insn::IncrementInt(IncrementIntInsn::new(1, 128)), 6),
+			(Insn::Compare(CompareInsn::new(PrimitiveType::Int, false)), 2),
+			(Insn::Compare(CompareInsn::new(PrimitiveType::Double, true)), 1),
+			(Insn::NewArray(NewArrayInsn::new(Type::Int)), 2),
+			(Insn::NewArray(NewArrayInsn::new(Type::object("java/lang/String"))), 3),
+			(Insn::MultiNewArray(MultiNewArrayInsn::new(ClassRef::from("[[I"), 2)), 4),
+			(Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Interface, ClassName::from("A"), String::from("a"), String::from("(J)V"), true))), 5),
+			(call(), 3),
+			(Insn::TableSwitch(TableSwitchInsn::new(end, 0, vec![end, end])), 0),
+			(Insn::LookupSwitch(lookup), 0),
+			(Insn::Label(end), 0),
+			(ret(), 1)
+		];
+		code.insns.insns = sizes.iter().map(|(insn, _)| insn.clone()).collect();
+		
+		let (bytes, label_pcs) = InsnParser::write_insns(&code, &mut ConstantPoolWriter::new(), &mut WriteContext::default()).unwrap();
+		let mut pc = 0;
+		for (insn, size) in sizes.iter() {
+			let encoded = insn.encoded_size(pc).unwrap();
+			match insn {
+				// the padding depends on where the switch starts
+				Insn::TableSwitch(_) => assert_eq!(encoded, 1 + (3 - pc % 4) + 12 + 8),
+				Insn::LookupSwitch(_) => assert_eq!(encoded, 1 + (3 - pc % 4) + 8 + 8),
+				_ => assert_eq!(encoded, *size, "{}", insn)
+			}
+			pc += encoded;
+		}
+		assert_eq!(label_pcs[&end] + 1, pc);
+		assert_eq!(bytes.len() as u32, pc);
+		assert_eq!(code.insns.estimated_code_size().unwrap(), pc);
+		
+		// jumps and constant loads are estimated at their widest
+		let mut code = CodeAttribute::empty();
+		let start = code.insns.new_label();
+		code.insns.insns = vec![
+			Insn::Label(start),
+			Insn::Ldc(LdcInsn::new(LdcType::String(String::from("a")))),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::Jump(JumpInsn::new(start))
+		];
+		let (bytes, _) = InsnParser::write_insns(&code, &mut ConstantPoolWriter::new(), &mut WriteContext::default()).unwrap();
+		assert_eq!(bytes.len(), 2 + 1 + 3);
+		assert_eq!(code.insns.estimated_code_size().unwrap(), 3 + 1 + 5);
+	}
+	
+	#[test]
+	fn every_local_variable_form_round_trips() {
+		let kinds = [OpType::Int, OpType::Long, OpType::Float, OpType::Double, OpType::Reference];
+		let indices = [0, 1, 2, 3, 4, 255, 256, 0xFFFE];
+		let mut code = CodeAttribute::empty();
+		for kind in kinds.iter() {
+			for index in indices.iter() {
+				code.insns.insns.push(Insn::LocalLoad(LocalLoadInsn::new(*kind, *index)));
+				code.insns.insns.push(Insn::LocalStore(LocalStoreInsn::new(*kind, *index)));
+			}
+		}
+		code.insns.insns.push(ret());
+		let (parsed, _, _) = round_trip(&code);
+		assert_same_insns(&parsed.insns, &code.insns);
+		
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+		let raw = CodeAttribute::parse_raw(&bytes).unwrap();
+		let longs: Vec<(u8, &[u8])> = raw.insns[16..32].iter().map(|x| (x.opcode, &x.operands[..])).collect();
+		assert_eq!(longs, vec![
+			(InsnParser::LLOAD_0, &[][..]), (InsnParser::LSTORE_0, &[][..]),
+			(InsnParser::LLOAD_1, &[][..]), (InsnParser::LSTORE_1, &[][..]),
+			(InsnParser::LLOAD_2, &[][..]), (InsnParser::LSTORE_2, &[][..]),
+			(InsnParser::LLOAD_3, &[][..]), (InsnParser::LSTORE_3, &[][..]),
+			(InsnParser::LLOAD, &[4][..]), (InsnParser::LSTORE, &[4][..]),
+			(InsnParser::LLOAD, &[255][..]), (InsnParser::LSTORE, &[255][..]),
+			(InsnParser::WIDE, &[InsnParser::LLOAD, 1, 0][..]), (InsnParser::WIDE, &[InsnParser::LSTORE, 1, 0][..]),
+			(InsnParser::WIDE, &[InsnParser::LLOAD, 0xFF, 0xFE][..]), (InsnParser::WIDE, &[InsnParser::LSTORE, 0xFF, 0xFE][..])
+		]);
+	}
+	
+	#[test]
+	fn malformed_code_is_rejected() {
+		let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+		let parse_attribute = |bytes: Vec<u8>| {
+			CodeAttribute::parse(&version, &ConstantPool::new(), &mut Cursor::new(bytes.clone()), &ParseOptions::default())
+		};
+		let parse = |code: &[u8]| {
+			let mut bytes = vec![0, 0, 0, 0];
+			bytes.extend_from_slice(&(code.len() as u32).to_be_bytes());
+			bytes.extend_from_slice(code);
+			bytes.extend_from_slice(&[0, 0, 0, 0]);
+			parse_attribute(bytes)
+		};
+		// tableswitch from i32::MIN to i32::MAX, whose case count overflows
+		let err = parse(&[0xAA, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0, 0, 0x7F, 0xFF, 0xFF, 0xFF]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{}", err);
+		// lookupswitch with a negative pair count
+		let err = parse(&[0xAB, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{}", err);
+		// tableswitch with no cases, as high is one below low
+		let err = parse(&[0xAA, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{}", err);
+		// tableswitch from 0 to i32::MAX
+		let err = parse(&[0xAA, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x7F, 0xFF, 0xFF, 0xFF]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{}", err);
+		// tableswitch with four cases, which fit in the whole code but not after the switch
+		let mut code = vec![0xB1, 0xB1, 0xB1, 0xB1, 0xAA, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3];
+		code.extend_from_slice(&[0; 12]);
+		let err = parse(&code).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 4, .. }), "{}", err);
+		// lookupswitch with two pairs and room for one
+		let mut code = vec![0xAB, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+		code.extend_from_slice(&[0; 12]);
+		let err = parse(&code).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{}", err);
+		// lookupswitch claiming i32::MAX pairs
+		let err = parse(&[0xAB, 0, 0, 0, 0, 0, 0, 0, 0x7F, 0xFF, 0xFF, 0xFF]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{}", err);
+		// goto_w whose target overflows an i32
+		let err = parse(&[0x00, 0xC8, 0x7F, 0xFF, 0xFF, 0xFF]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::OutOfBoundsJumpIndex(_)), "{}", err);
+		// code_length larger than the attribute
+		let err = parse_attribute(vec![0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xB1]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::Truncated { what: "code", wanted: 0xFFFF }), "{}", err);
+		// code_length larger than a method may be
+		let err = parse_attribute(vec![0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xB1]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::MethodTooLarge { size: 0xFFFFFFFF }), "{}", err);
+		
+		// a tableswitch needs at least one case, so an empty one cannot be written either
+		let mut code = CodeAttribute::empty();
+		let end = code.insns.new_label();
+		code.insns.insns = vec![Insn::TableSwitch(TableSwitchInsn::new(end, 0, Vec::new())), Insn::Label(end), ret()];
+		let err = code.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{}", err);
+	}
+	
+	#[test]
+	fn jumps_must_land_inside_the_code() {
+		let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+		let parse = |code: &[u8], exceptions: &[[u16; 4]]| {
+			let mut bytes = vec![0, 0, 0, 0];
+			bytes.extend_from_slice(&(code.len() as u32).to_be_bytes());
+			bytes.extend_from_slice(code);
+			bytes.extend_from_slice(&(exceptions.len() as u16).to_be_bytes());
+			for handler in exceptions.iter().flatten() {
+				bytes.extend_from_slice(&handler.to_be_bytes());
+			}
+			bytes.extend_from_slice(&[0, 0]);
+			CodeAttribute::parse(&version, &ConstantPool::new(), &mut Cursor::new(bytes.clone()), &ParseOptions::default())
+		};
+		
+		// goto just past the end
+		let err = parse(&[0xA7, 0, 4, 0xB1], &[]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::OutOfBoundsJumpIndex(4)), "{}", err);
+		// ifeq back before the start
+		let err = parse(&[0x03, 0x99, 0xFF, 0xFE, 0xB1], &[]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::OutOfBoundsJumpIndex(-1)), "{}", err);
+		// tableswitch whose only case is past the end
+		let err = parse(&[0xAA, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0xB1], &[]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::OutOfBoundsJumpIndex(256)), "{}", err);
+		
+		// handlers share pcs with jumps, and each label still gets its own id
+		let code = parse(&[0xA7, 0, 3, 0xB1, 0xB1], &[[0, 3, 4, 0], [3, 4, 0, 0]]).unwrap();
+		let labels: Vec<LabelInsn> = code.insns.iter()
+			.filter_map(|insn| match insn {
+				Insn::Label(x) => Some(*x),
+				_ => None
+			})
+			.collect();
+		assert_eq!(labels.len(), 3);
+		assert!(labels.iter().all(|x| x.id < code.insns.labels));
+		let ids: HashSet<u32> = labels.iter().map(|x| x.id).collect();
+		assert_eq!(ids.len(), labels.len());
+		for handler in code.exceptions.iter() {
+			assert!(labels.contains(&handler.start) && labels.contains(&handler.handler));
+		}
+		// the end of the first handler is the target of the goto
+		assert_eq!(code.exceptions[0].end, code.exceptions[1].start);
+		
+		// a backward goto is written with a negative offset
+		let mut code = parse(&[0x00, 0xA7, 0xFF, 0xFF], &[]).unwrap();
+		code.mark_modified();
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+		assert!(bytes.windows(4).any(|x| x == [0x00, 0xA7, 0xFF, 0xFF]), "{:?}", bytes);
+	}
+	
+	#[test]
+	fn pc_labels_from_a_map() {
+		let map: HashMap<u32, LabelInsn> = vec![(0, LabelInsn::new(4)), (7, LabelInsn::new(2))].into_iter().collect();
+		let mut labels = PcLabels::from(map);
+		assert_eq!(labels.len(), 2);
+		assert_eq!(labels.label_at(7), LabelInsn::new(2));
+		assert_eq!(labels.label_at(3), LabelInsn::new(5));
+		assert_eq!(PcLabels::from(HashMap::new()).next_id(), 0);
+	}
+	
+	#[test]
+	#[allow(clippy::approx_constant)]
+	fn wide_constants_use_ldc2_w() {
+		// long x = 123456789012L; double d = 3.14;
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 2;
+		code.max_locals = 5;
+		code.insns.insns = vec![
+			Insn::Ldc(LdcInsn::new(LdcType::Long(123456789012))),
+			Insn::LocalStore(LocalStoreInsn::new(OpType::Long, 1)),
+			Insn::Ldc(LdcInsn::new(LdcType::Double(3.14))),
+			Insn::LocalStore(LocalStoreInsn::new(OpType::Double, 3)),
+			ret()
+		];
+		let (parsed, _, _) = round_trip(&code);
+		assert_same_insns(&parsed.insns, &code.insns);
+		
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+		let raw = CodeAttribute::parse_raw(&bytes).unwrap();
+		let opcodes: Vec<u8> = raw.insns.iter().map(|x| x.opcode).collect();
+		assert_eq!(opcodes, vec![InsnParser::LDC2_W, InsnParser::LSTORE_1, InsnParser::LDC2_W, InsnParser::DSTORE_3, InsnParser::RETURN]);
+	}
+	
+	#[test]
+	fn method_handle_constants_round_trip() {
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 1;
+		code.insns.insns = vec![
+			Insn::Ldc(LdcInsn::new(LdcType::MethodHandle(Box::new(MethodHandle::new(MethodHandleKind::InvokeStatic, ClassName::from("java/util/List"), String::from("of"), String::from("()Ljava/util/List;"), true))))),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::Ldc(LdcInsn::new(LdcType::MethodHandle(Box::new(MethodHandle::new(MethodHandleKind::GetStatic, ClassName::from("java/lang/System"), String::from("out"), String::from("Ljava/io/PrintStream;"), false))))),
+			Insn::Pop(PopInsn::new(false)),
+			ret()
+		];
+		let (parsed, _, _) = round_trip(&code);
+		assert_same_insns(&parsed.insns, &code.insns);
+	}
+	
+	#[test]
+	fn dynamic_constants_parse_without_their_bootstrap_method() {
+		let bootstrap = MethodHandle::new(MethodHandleKind::InvokeStatic, ClassName::from("java/lang/invoke/ConstantBootstraps"), String::from("nullConstant"), String::from("(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/Class;)Ljava/lang/Object;"), false);
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 1;
+		code.insns.insns = vec![
+			Insn::Ldc(LdcInsn::new(LdcType::Dynamic(Box::new(DynamicConstant::new(String::from("x"), String::from("Ljava/lang/Object;"), bootstrap, Vec::new()))))),
+			Insn::Pop(PopInsn::new(false)),
+			ret()
+		];
+		let (parsed, _, _) = round_trip(&code);
+		match &parsed.insns.insns[0] {
+			Insn::Ldc(LdcInsn { constant: LdcType::Dynamic(x) }) => {
+				assert_eq!((x.name.as_str(), x.descriptor.as_str()), ("x", "Ljava/lang/Object;"));
+				assert_eq!(x.bootstrap_method.class, UNRESOLVED_BOOTSTRAP);
+			},
+			x => panic!("Expected a dynamic constant, found {:?}", x)
+		}
+		let err = parsed.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err();
+		assert!(matches!(err.root(), ParserError::Unimplemented(_)), "{:?}", err);
+	}
+	
+	#[test]
+	fn ldc_category_is_checked() {
+		let mut writer = ConstantPoolWriter::new();
+		let long = writer.long(5);
+		let int = writer.integer(7);
+		let mut pool = Vec::new();
+		writer.write(&mut pool).unwrap();
+		let pool = <ConstantPool as Serializable>::parse(&mut Cursor::new(pool)).unwrap();
+		let version = ClassVersion { major: crate::version::MajorVersion::JAVA_8, minor: 0 };
+		let parse = |code: &[u8]| {
+			let mut bytes = vec![0, 2, 0, 0, 0, 0, 0, code.len() as u8];
+			bytes.extend_from_slice(code);
+			bytes.extend_from_slice(&[0, 0, 0, 0]);
+			CodeAttribute::parse(&version, &pool, &mut Cursor::new(bytes.clone()), &ParseOptions::default())
+		};
+		
+		assert!(parse(&[InsnParser::LDC, int as u8, InsnParser::POP, InsnParser::RETURN]).is_ok());
+		assert!(parse(&[InsnParser::LDC2_W, 0, long as u8, InsnParser::POP2, InsnParser::RETURN]).is_ok());
+		let err = parse(&[InsnParser::LDC, long as u8, InsnParser::POP2, InsnParser::RETURN]).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidInstruction { pc: 0, .. }), "{:?}", err);
+		assert!(parse(&[InsnParser::LDC_W, 0, long as u8, InsnParser::POP2, InsnParser::RETURN]).is_err());
+		assert!(parse(&[InsnParser::LDC2_W, 0, int as u8, InsnParser::POP, InsnParser::RETURN]).is_err());
+	}
+	
+	#[test]
+	fn original_pcs_match_javap() {
+		let options = ParseOptions {
+			original_pcs: true,
+			..Default::default()
+		};
+		let class = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let mut class = ClassFile::parse_with(&mut Cursor::new(class), &options).unwrap();
+		
+		// from javap -c, with labels at the loop start and exit
+		let code = class.method_mut("sum", "([I)I").unwrap().code().unwrap();
+		assert_eq!(code.original_pcs().unwrap(), [0, 1, 2, 3, 4, 5, 6, 8, 9, 11, 11, 13, 15, 18, 19, 21, 22, 24, 25, 27, 28, 29, 32, 35, 35, 36]);
+		assert_eq!(code.insn_at_pc(29), Some(21));
+		assert!(matches!(code.insns.insns[21], Insn::IncrementInt(IncrementIntInsn { index: 5, amount: 1 })));
+		assert_eq!(code.insn_at_pc(11), Some(10));
+		assert_eq!(code.insn_at_pc(7), None);
+		assert_eq!(code.insn_at_pc(37), None);
+		
+		// labels at the start and end of the try block and at the handler
+		let code = class.method_mut("parse", "(Ljava/lang/String;)I").unwrap().code().unwrap();
+		assert_eq!(code.original_pcs().unwrap(), [0, 0, 1, 4, 4, 5, 5, 6, 7]);
+		assert_eq!(code.insn_at_pc(5), Some(6));
+		assert!(matches!(code.insns.insns[6], Insn::LocalStore(_)));
+		
+		// changes that keep the length are noticed too
+		let replaced = std::mem::replace(&mut code.insns.insns[6], Insn::Nop(NopInsn::new()));
+		assert_eq!(code.original_pcs(), None);
+		code.insns.insns[6] = replaced;
+		assert!(code.original_pcs().is_some());
+		
+		// dead code removal keeps the table in step
+		code.insns.insns.insert(4, Insn::Nop(NopInsn::new()));
+		assert_eq!(code.original_pcs(), None);
+		code.insns.insns.remove(4);
+		code.remove_dead_code().unwrap();
+		assert_eq!(code.insn_at_pc(5), Some(code.insns.len() - 3));
+		
+		let default = ClassFile::parse(&mut Cursor::new(&include_bytes!("../classes/snapshot/Snapshot.class")[..])).unwrap();
+		assert_eq!(default.methods[0].attributes.iter().find_map(|x| match x {
+			Attribute::Code(x) => Some(x.original_pcs()),
+			_ => None
+		}), Some(None));
+		let with_pcs = ClassFile::parse_with(&mut Cursor::new(&include_bytes!("../classes/snapshot/Snapshot.class")[..]), &options).unwrap();
+		assert_eq!(with_pcs, default);
+	}
+	
+	/// The pc and constant pool operand of each instruction of a method, as printed by javap.
+	type JavapInsns = Vec<(u32, Option<CPIndex>)>;
+	
+	/// The pc and constant pool operand of each instruction of every method, by name and
+	/// descriptor, as printed by `javap -v`. None if javap could not be run.
+	fn javap_code(path: &str) -> Option<HashMap<(String, String), JavapInsns>> {
+		let output = match std::process::Command::new("javap").args(["-v", "-p", path]).output() {
+			Ok(output) if output.status.success() => output,
+			Ok(output) => panic!("{}", String::from_utf8_lossy(&output.stderr)),
+			Err(err) => {
+				println!("Skipping, could not run javap: {}", err);
+				return None;
+			}
+		};
+		let mut methods = HashMap::new();
+		let mut name = String::new();
+		let mut current: Option<&mut JavapInsns> = None;
+		for line in String::from_utf8_lossy(&output.stdout).lines() {
+			if line.starts_with("  ") && !line.starts_with("   ") && line.contains('(') {
+				// e.g. "  public static void main(java.lang.String[]);"
+				name = line[..line.find('(').unwrap()].split_whitespace().last().unwrap().to_string();
+				// constructors are printed with the class name
+				if name.ends_with("Snapshot") {
+					name = String::from("<init>");
+				}
+			} else if let Some(descriptor) = line.strip_prefix("    descriptor: ") {
+				current = Some(methods.entry((name.clone(), descriptor.to_string())).or_insert_with(Vec::new));
+			} else if let (Some(insns), Some((pc, rest))) = (current.as_deref_mut(), line.trim_start().split_once(": ")) {
+				let mut tokens = rest.split_whitespace();
+				let is_insn = pc.bytes().all(|c| c.is_ascii_digit()) && matches!(tokens.next(), Some(x) if x.starts_with(|c: char| c.is_ascii_alphabetic()));
+				if is_insn {
+					let operand = tokens.next()
+						.and_then(|x| x.strip_prefix('#'))
+						.map(|x| x.trim_end_matches(',').parse().unwrap());
+					insns.push((pc.parse().unwrap(), operand));
+				}
 			}
 		}
+		Some(methods)
+	}
+	
+	#[test]
+	fn original_indices_match_javap() {
+		let options = ParseOptions {
+			original_indices: true,
+			keep_original_code: true,
+			..Default::default()
+		};
+		let path = "classes/snapshot/Snapshot.class";
+		let bytes = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let (mut class, pool) = ClassFile::parse_with_pool(&mut Cursor::new(bytes), &options).unwrap();
+		
+		// member names and descriptors point at their Utf8 constants
+		for (name, descriptor, indices) in class.fields.iter().map(|x| (&x.name, &x.descriptor, x.original_indices))
+			.chain(class.methods.iter().map(|x| (&x.name, &x.descriptor, x.original_indices))) {
+			let indices = indices.unwrap();
+			assert_eq!(&pool.utf8_inner(indices.name).unwrap(), name);
+			assert_eq!(&pool.utf8_inner(indices.descriptor).unwrap(), descriptor);
+		}
+		
+		// labels refer to no constant
+		let code = class.method_mut("parse", "(Ljava/lang/String;)I").unwrap().code().unwrap();
+		assert!(matches!(&code.insns.insns[2], Insn::Invoke(x) if x.name == "parseInt"));
+		let method_ref = pool.methodref(code.original_index(2).unwrap()).unwrap();
+		let name_index = pool.nameandtype(method_ref.name_and_type_index).unwrap().name_index;
+		assert_eq!(pool.utf8_inner(name_index).unwrap(), "parseInt");
+		assert_eq!(code.original_indices().unwrap().iter().filter(|x| x.is_some()).count(), 1);
+		assert_eq!(code.original_pcs(), None);
+		
+		// every instruction has the operand javap prints for it
+		if let Some(javap) = javap_code(path) {
+			for method in class.methods.iter_mut() {
+				let key = (method.name.clone(), method.descriptor.clone());
+				let code = method.code().unwrap();
+				let indices: Vec<Option<CPIndex>> = code.insns.insns.iter().zip(code.original_indices().unwrap())
+					.filter(|(insn, _)| !matches!(insn, Insn::Label(_)))
+					.map(|(_, index)| *index)
+					.collect();
+				let expected: Vec<Option<CPIndex>> = javap[&key].iter().map(|(_, index)| *index).collect();
+				assert_eq!(indices, expected, "{:?}", key);
+			}
+		}
+		
+		// writing ignores them, and so does comparison
+		let keep = ParseOptions { keep_original_code: true, ..Default::default() };
+		let default = ClassFile::parse_with(&mut Cursor::new(bytes), &keep).unwrap();
+		assert_eq!(default.methods[0].original_indices, None);
+		assert_eq!(class, default);
+		class.methods[0].original_indices = Some(MemberIndices { name: 1, descriptor: 1 });
+		let write = |class: &ClassFile| {
+			let mut out = Vec::new();
+			class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&pool)).unwrap();
+			out
+		};
+		assert_eq!(write(&class), write(&default));
+	}
+	
+	/// `call()` protected by a handler that pops the exception, followed by a return
+	fn protected_call(catch_type: Option<&str>) -> CodeAttribute {
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 1;
+		let start = code.insns.new_label();
+		let end = code.insns.new_label();
+		let handler = code.insns.new_label();
+		code.insns.insns = vec![
+			Insn::Label(start),
+			call(),
+			Insn::Label(end),
+			ret(),
+			Insn::Label(handler),
+			Insn::Pop(PopInsn::new(false)),
+			ret()
+		];
+		code.exceptions.push(ExceptionHandler {
+			start,
+			end,
+			handler,
+			catch_type: catch_type.map(String::from)
+		});
+		code
+	}
+	
+	#[test]
+	fn handlers_are_added_and_merged() {
+		let mut code = protected_call(Some("java/lang/Exception"));
+		let original = code.exceptions[0].clone();
+		assert_eq!(code.handlers_covering(1), vec![&original]);
+		assert!(code.handlers_covering(3).is_empty());
+		assert!(code.handlers_covering(5).is_empty());
+		
+		// every position already has a label
+		let added = code.add_handler(1, 2, 5, None).unwrap().clone();
+		assert_eq!((added.start, added.end, added.handler), (original.start, original.end, original.handler));
+		assert_eq!(code.insns.len(), 7);
+		assert_eq!(code.handlers_covering(1), vec![&original, &added]);
+		// the final return has none yet
+		let added = code.add_handler(3, 4, 6, Some(String::from("java/lang/Error"))).unwrap().clone();
+		assert_eq!(added.start, original.end);
+		assert_eq!(code.insns.len(), 8);
+		assert_eq!(code.insns.label_index(added.handler), Some(6));
+		assert_eq!(code.handlers_covering(3), vec![&added]);
+		
+		let err = code.add_handler(2, 2, 5, None).unwrap_err();
+		assert!(matches!(err, ParserError::InvalidExceptionHandler { index: 3, .. }), "{}", err);
+		assert!(code.add_handler(0, 9, 5, None).is_err());
+		assert!(code.add_handler(0, 1, 8, None).is_err());
+		
+		// two calls protected in pieces by the same handler, and one catching something else
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 1;
+		code.insns.insns = vec![call(), call(), ret(), Insn::Pop(PopInsn::new(false)), ret()];
+		code.add_handler(0, 1, 3, None).unwrap();
+		// labels were inserted before both calls, after the first and before the handler
+		assert_eq!(code.insns.len(), 8);
+		code.add_handler(3, 4, 6, None).unwrap();
+		code.add_handler(1, 2, 7, Some(String::from("A"))).unwrap();
+		code.add_handler(3, 4, 7, Some(String::from("A"))).unwrap();
+		assert_eq!(code.insns.len(), 9);
+		assert_eq!(code.exceptions.len(), 4);
+		assert_eq!(code.merge_adjacent_handlers(), 2);
+		assert_eq!(code.merge_adjacent_handlers(), 0);
+		let bytes = code.write_to_vec(&mut ConstantPoolWriter::new()).unwrap();
+		// calls at 0 and 3, the return at 6 and the handler at 7
+		let table = &bytes[bytes.len() - 2 - 2 - 16..bytes.len() - 2];
+		assert_eq!(&table[..2], [0, 2]);
+		assert_eq!(&table[2..10], [0, 0, 0, 6, 0, 7, 0, 0]);
+		assert_eq!(&table[10..16], [0, 0, 0, 6, 0, 7]);
+	}
+	
+	#[test]
+	fn structural_equality_pairs_handler_labels() {
+		let code = protected_call(Some("java/lang/Exception"));
+		let mut relabelled = code.clone();
+		let reverse = |label: LabelInsn| LabelInsn::new(2 - label.id);
+		for insn in relabelled.insns.insns.iter_mut() {
+			insn.map_labels(reverse);
+		}
+		let handler = &mut relabelled.exceptions[0];
+		handler.start = reverse(handler.start);
+		handler.end = reverse(handler.end);
+		handler.handler = reverse(handler.handler);
+		assert_ne!(relabelled, code);
+		assert!(relabelled.structurally_equal(&code));
+		let (parsed, _, _) = round_trip(&relabelled);
+		assert!(parsed.structurally_equal(&code));
+		
+		// handling exceptions at the start of the protected range instead
+		let mut moved = code.clone();
+		moved.exceptions[0].handler = moved.exceptions[0].start;
+		assert!(!moved.structurally_equal(&code));
+		assert!(!code.structurally_equal(&moved));
+	}
+	
+	#[test]
+	fn catch_all_handlers_round_trip() {
+		let code = protected_call(None);
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+		// a single handler from 0 to 3, jumping to 4 and catching anything
+		assert_eq!(bytes[14..24], [0, 1, 0, 0, 0, 3, 0, 4, 0, 0]);
+		let (parsed, _, _) = round_trip(&code);
+		assert_eq!(parsed.exceptions.len(), 1);
+		assert_eq!(parsed.exceptions[0].catch_type, None);
+		
+		let (parsed, _, _) = round_trip(&protected_call(Some("java/lang/Exception")));
+		assert_eq!(parsed.exceptions[0].catch_type.as_deref(), Some("java/lang/Exception"));
+	}
+	
+	#[test]
+	fn invalid_handlers_are_rejected() {
+		let reason = |code: &CodeAttribute| {
+			match code.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err() {
+				ParserError::InvalidExceptionHandler { index, reason } => (index, reason),
+				x => panic!("Expected InvalidExceptionHandler, found {:?}", x)
+			}
+		};
+		
+		// the second handler covers nothing
+		let mut code = protected_call(None);
+		let mut empty = code.exceptions[0].clone();
+		empty.end = empty.start;
+		code.exceptions.push(empty);
+		assert_eq!(reason(&code), (1, String::from("start pc 0 is not before end pc 0")));
+		
+		let mut code = protected_call(None);
+		let handler = &mut code.exceptions[0];
+		std::mem::swap(&mut handler.start, &mut handler.end);
+		assert_eq!(reason(&code), (0, String::from("start pc 3 is not before end pc 0")));
+		
+		// the handler label is at the very end of the code
+		let mut code = protected_call(None);
+		code.insns.insns.truncate(4);
+		code.insns.insns.push(Insn::Label(code.exceptions[0].handler));
+		assert_eq!(reason(&code), (0, String::from("handler pc 4 is not an instruction")));
+		
+		let mut code = protected_call(Some("java/lang/Exception"));
+		code.insns.insns.remove(2);
+		let end = code.exceptions[0].end;
+		assert_eq!(reason(&code), (0, format!("end label L{} is not in the instructions", end.id)));
+	}
+	
+	#[test]
+	fn malformed_insn_descriptors_fail_to_write() {
+		let write = |insn: Insn| {
+			let mut code = CodeAttribute::empty();
+			code.insns.insns = vec![insn, ret()];
+			code.write(&mut Vec::new(), &mut ConstantPoolWriter::new())
+		};
+		
+		assert!(write(Insn::GetField(Box::new(GetFieldInsn::new(false, ClassName::from("A"), String::from("names"), String::from("[Ljava/lang/String;"))))).is_ok());
+		assert!(write(Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Static, ClassName::from("A"), String::from("a"), String::from("([[I)V"), false)))).is_ok());
+		
+		let err = write(Insn::PutField(Box::new(PutFieldInsn::new(true, ClassName::from("A"), String::from("a"), String::from("Q"))))).unwrap_err();
+		assert_eq!(err.contexts(), vec!["insns[0]", "putfield A.a Q"]);
+		assert!(matches!(err.root(), ParserError::InvalidDescriptor(_)));
+		let err = write(Insn::GetField(Box::new(GetFieldInsn::new(false, ClassName::from("A"), String::from("a"), String::from("V"))))).unwrap_err();
+		assert_eq!(err.contexts(), vec!["insns[0]", "getstatic A.a V"]);
+		let err = write(Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Interface, ClassName::from("A"), String::from("a"), String::from("(I)"), true)))).unwrap_err();
+		assert_eq!(err.contexts(), vec!["insns[0]", "invokeinterface A.a (I)"]);
+	}
+	
+	#[test]
+	fn unmodified_code_is_written_as_parsed() {
+		// a wide iload the writer would shorten, then pop and return
+		let bytes = vec![0, 1, 0, 1, 0, 0, 0, 6, 0xC4, 0x15, 0, 0, 0x57, 0xB1, 0, 0, 0, 0];
+		let pool = ConstantPool::new();
+		let version = ClassVersion { major: crate::version::MajorVersion::JAVA_8, minor: 0 };
+		let parse = |options: &ParseOptions| CodeAttribute::parse(&version, &pool, &mut Cursor::new(bytes.clone()), options).unwrap();
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		let code = parse(&options);
+		assert!(!code.is_modified());
+		let write = |code: &CodeAttribute, mut constant_pool: ConstantPoolWriter| {
+			let mut written = Vec::new();
+			code.write(&mut written, &mut constant_pool).unwrap();
+			written
+		};
+		assert_eq!(write(&code, ConstantPoolWriter::from_pool(&pool)), bytes);
+		// a new pool may put constants anywhere, so the code is encoded again
+		let encoded = write(&code, ConstantPoolWriter::new());
+		assert_eq!(&encoded[8..12], [0x1A, 0x57, 0xB1, 0]);
+		// nothing is kept unless asked for
+		let plain = parse(&ParseOptions::default());
+		assert!(plain.original.is_none() && plain.is_modified());
+		assert_eq!(write(&plain, ConstantPoolWriter::from_pool(&pool)), encoded);
+		
+		let mut changed = code.clone();
+		changed.insns.insert(0, Insn::Nop(NopInsn::new()));
+		assert!(changed.is_modified());
+		assert_eq!(write(&changed, ConstantPoolWriter::from_pool(&pool))[8..12], [0, 0x1A, 0x57, 0xB1]);
+		
+		// changes to the public fields are noticed too
+		let mut changed = code.clone();
+		changed.insns.insns.insert(0, Insn::Nop(NopInsn::new()));
+		assert!(changed.is_modified());
+		assert_eq!(write(&changed, ConstantPoolWriter::from_pool(&pool))[8..12], [0, 0x1A, 0x57, 0xB1]);
+		let mut changed = code.clone();
+		changed.max_stack = 2;
+		assert!(changed.is_modified());
+		assert_eq!(write(&changed, ConstantPoolWriter::from_pool(&pool))[..2], [0, 2]);
+		let mut changed = code.clone();
+		changed.insns = InsnList::new();
+		changed.insns.insns.push(Insn::Return(ReturnInsn::new(ReturnType::Void)));
+		assert_eq!(write(&changed, ConstantPoolWriter::from_pool(&pool))[4..9], [0, 0, 0, 1, 0xB1]);
+		
+		// changing the code back makes it unmodified again
+		changed.max_stack = code.max_stack;
+		changed.insns.insns = code.insns.insns.clone();
+		assert!(!changed.is_modified());
+		assert_eq!(write(&changed, ConstantPoolWriter::from_pool(&pool)), bytes);
+		let mut code = code;
+		code.mark_modified();
+		assert!(code.is_modified() && code.original.is_none());
+	}
+	
+	#[test]
+	fn narrow_and_wide_forms_switch_at_byte_boundaries() {
+		let cases = vec![
+			(Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 3)), 1),
+			(Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 4)), 2),
+			(Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 255)), 2),
+			(Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 256)), 4),
+			(Insn::LocalStore(LocalStoreInsn::new(OpType::Reference, 3)), 1),
+			(Insn::LocalStore(LocalStoreInsn::new(OpType::Reference, 255)), 2),
+			(Insn::LocalStore(LocalStoreInsn::new(OpType::Reference, 256)), 4),
+			(Insn::IncrementInt(IncrementIntInsn::new(255, 127)), 3),
+			(Insn::IncrementInt(IncrementIntInsn::new(255, -128)), 3),
+			(Insn::IncrementInt(IncrementIntInsn::new(256, 1)), 6),
+			(Insn::IncrementInt(IncrementIntInsn::new(0, 128)), 6),
+			(Insn::IncrementInt(IncrementIntInsn::new(0, -129)), 6),
+		];
+		for (insn, size) in cases {
+			let mut code = CodeAttribute::empty();
+			code.insns.insns = vec![insn.clone(), ret()];
+			let mut bytes = Vec::new();
+			code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+			assert_eq!(u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), size + 1, "{:?}", insn);
+			assert_eq!(insn.encoded_size(0).unwrap(), size, "{:?}", insn);
+			let (parsed, _, _) = round_trip(&code);
+			assert_eq!(parsed.insns.insns[0], insn);
+		}
+	}
+	
+	#[test]
+	fn far_conditional_jumps_invert_around_goto_w() {
+		let mut code = CodeAttribute::empty();
+		let start = code.insns.new_label();
+		let end = code.insns.new_label();
+		code.insns.insns.push(Insn::Label(start));
+		code.insns.insns.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, end)));
+		code.insns.insns.extend(vec![Insn::Nop(NopInsn::new()); 40000]);
+		code.insns.insns.push(Insn::Label(end));
+		code.insns.insns.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntsLessThan, start)));
+		code.insns.insns.push(ret());
+		
+		let (parsed, _, _) = round_trip(&code);
+		let insns: Vec<&Insn> = parsed.insns.iter().filter(|insn| !matches!(insn, Insn::Nop(_))).collect();
+		// each jump becomes: if !condition skip; goto_w target; skip:
+		let check = |at: usize, condition: JumpCondition, target: &Insn| {
+			let skip = match insns[at] {
+				Insn::ConditionalJump(x) if x.condition == condition => x.jump_to,
+				other => panic!("{:?} at {}", other, at)
+			};
+			match insns[at + 1] {
+				Insn::Jump(x) => assert_eq!(&Insn::Label(x.jump_to), target),
+				other => panic!("{:?} at {}", other, at + 1)
+			}
+			assert_eq!(insns[at + 2], &Insn::Label(skip));
+		};
+		check(1, JumpCondition::IntNotEqZero, insns[4]);
+		check(5, JumpCondition::IntsGreaterThanOrEq, insns[0]);
+		assert_eq!(insns[8], &ret());
+	}
+	
+	#[test]
+	fn astore_1_is_parsed() {
+		// aconst_null, astore_1, return
+		let bytes = vec![0, 1, 0, 2, 0, 0, 0, 3, 0x01, 0x4C, 0xB1, 0, 0, 0, 0];
+		let version = ClassVersion { major: crate::version::MajorVersion::JAVA_8, minor: 0 };
+		let code = CodeAttribute::parse(&version, &ConstantPool::new(), &mut Cursor::new(bytes), &ParseOptions::default()).unwrap();
+		assert_eq!(code.insns.insns[1], Insn::LocalStore(LocalStoreInsn::new(OpType::Reference, 1)));
+	}
+	
+	#[test]
+	fn object_creation_is_written() {
+		let mut code = CodeAttribute::empty();
+		code.insns.insns = vec![
+			Insn::NewObject(NewObjectInsn::new("java/lang/Object".into())),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 1)),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 1)),
+			Insn::MultiNewArray(MultiNewArrayInsn::new("[[I".into(), 2)),
+			Insn::Pop(PopInsn::new(false)),
+			ret()
+		];
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+		// the code follows max_stack, max_locals and its own length
+		assert_eq!(&bytes[4..8], &[0, 0, 0, 12]);
+		assert_eq!(bytes[8], InsnParser::NEW);
+		assert_eq!(bytes[14], InsnParser::MULTIANEWARRAY);
+		assert_eq!(bytes[17], 2);
+	}
+	
+	#[test]
+	fn jumps_over_constant_loads_land_on_their_label() {
+		let mut code = CodeAttribute::empty();
+		let end = code.insns.new_label();
+		code.insns.insns = vec![
+			Insn::Ldc(LdcInsn::new(LdcType::Int(0))),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, end)),
+			Insn::Ldc(LdcInsn::new(LdcType::String(String::from("a")))),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::Label(end),
+			ret()
+		];
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+		let raw = CodeAttribute::parse_raw(&bytes).unwrap();
+		let jump = &raw.insns[1];
+		assert_eq!(jump.opcode, InsnParser::IFEQ);
+		let offset = i16::from_be_bytes([jump.operands[0], jump.operands[1]]);
+		assert_eq!(jump.pc as i32 + offset as i32, raw.insns.last().unwrap().pc as i32);
+	}
+	
+	#[test]
+	fn labels_after_forward_gotos_are_placed_exactly() {
+		let mut code = CodeAttribute::empty();
+		let end = code.insns.new_label();
+		code.insns.insns = vec![
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 0)),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, end)),
+			Insn::Jump(JumpInsn::new(end)),
+			Insn::Label(end),
+			ret()
+		];
+		let mut bytes = Vec::new();
+		code.write(&mut bytes, &mut ConstantPoolWriter::new()).unwrap();
+		let raw = CodeAttribute::parse_raw(&bytes).unwrap();
+		let end = raw.insns.last().unwrap().pc as i32;
+		let jumps: Vec<&RawInsn> = raw.insns.iter()
+			.filter(|x| x.opcode == InsnParser::IFEQ || x.opcode == InsnParser::GOTO)
+			.collect();
+		assert_eq!(jumps.len(), 2);
+		for jump in jumps {
+			let offset = i16::from_be_bytes([jump.operands[0], jump.operands[1]]);
+			assert_eq!(jump.pc as i32 + offset as i32, end, "{:?}", jump);
+		}
+	}
+	
+	#[test]
+	fn backward_jumps_round_trip() {
+		let mut code = CodeAttribute::empty();
+		let start = code.insns.new_label();
+		code.insns.insns = vec![
+			Insn::Label(start),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 0)),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, start)),
+			Insn::Jump(JumpInsn::new(start))
+		];
+		let (parsed, _, _) = round_trip(&code);
+		assert_eq!(parsed.insns.insns, code.insns.insns);
 	}
 }
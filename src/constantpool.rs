@@ -1,18 +1,46 @@
 use crate::Serializable;
+use crate::classfile::{ParseOptions, PartialParse};
+use crate::version::MajorVersion;
 use crate::utils::ReadUtils;
-use crate::error::{Result, ParserError};
-use std::io::{Read, Write};
+use crate::error::{Result, ParserError, ErrorContext};
+use crate::ast::{BootstrapArgument, DynamicConstant, MethodHandle};
+use std::io::{Cursor, Read, Write};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
 use std::borrow::{Cow};
 use derive_more::Constructor;
 use enum_display_derive::DisplayDebug;
-use std::fmt::{Debug, Formatter};
-use linked_hash_map::LinkedHashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash};
+use std::sync::Arc;
+use std::convert::TryFrom;
 
 pub type CPIndex = u16;
 
+/// The constant pool indices a field or method took its name and descriptor from, see
+/// [ParseOptions::original_indices](crate::classfile::ParseOptions::original_indices)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemberIndices {
+	pub name: CPIndex,
+	pub descriptor: CPIndex
+}
+
+/// The constant pool indices of the Class constants naming a class and its supertypes, recorded
+/// with [ParseOptions::original_indices](crate::classfile::ParseOptions::original_indices)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClassIndices {
+	pub this_class: CPIndex,
+	/// 0 for a class without a superclass
+	pub super_class: CPIndex,
+	pub interfaces: Vec<CPIndex>
+}
+
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct ConstantPool {
 	inner: Vec<Option<ConstantType>>
 }
@@ -49,18 +77,55 @@ impl ConstantPool {
 			Some(Some(x)) => {
 				Ok(x)
 			}
+			_ if self.is_phantom(index) => Err(ParserError::phantom_cp_index(index)),
 			_ => Err(ParserError::bad_cp_index(index))
 		}
 	}
 	
+	/// Whether the index is the unusable slot following a long or double. A long or double may be
+	/// the last constant, in which case its phantom slot lies just past the end of the pool.
+	pub fn is_phantom(&self, index: CPIndex) -> bool {
+		if index == 0 || matches!(self.inner.get(index as usize), Some(Some(_))) {
+			return false;
+		}
+		matches!(self.inner.get(index as usize - 1), Some(Some(x)) if x.double_size())
+	}
+	
+	/// A pool holding the given constants in order, see [`push`](Self::push)
+	pub fn from_entries(entries: Vec<ConstantType>) -> Self {
+		let mut pool = ConstantPool::new();
+		for constant in entries {
+			pool.push(constant);
+		}
+		pool
+	}
+	
+	/// Sets the constant at the given index, growing the pool to hold it. A long or double also
+	/// grows the pool over the slot after it.
 	pub fn set(&mut self, index: CPIndex, value: Option<ConstantType>) {
 		let index = index as usize;
-		if index > self.inner.len() - 1 {
-			self.inner.resize(index + 1, None);
+		let len = index + if matches!(value, Some(ref x) if x.double_size()) { 2 } else { 1 };
+		if len > self.inner.len() {
+			self.inner.resize(len, None);
 		}
 		self.inner[index] = value
 	}
 	
+	/// Appends the constant, returning its index. Longs and doubles take up two indices.
+	pub fn push(&mut self, constant: ConstantType) -> CPIndex {
+		if self.inner.is_empty() {
+			// index 0 is never used
+			self.inner.push(None);
+		}
+		let index = self.inner.len() as CPIndex;
+		let double_size = constant.double_size();
+		self.inner.push(Some(constant));
+		if double_size {
+			self.inner.push(None);
+		}
+		index
+	}
+	
 	pub fn class(&self, index: CPIndex) -> Result<&ClassInfo> {
 		match self.get(index)? {
 			ConstantType::Class(t) => Ok(t),
@@ -199,6 +264,11 @@ impl ConstantPool {
 		Ok(utf8_info.str.clone())
 	}
 	
+	/// The internal name of the Class constant at the given index
+	pub fn class_name(&self, index: CPIndex) -> Result<String> {
+		self.utf8_inner(self.class(index)?.name_index)
+	}
+	
 	pub fn methodhandle(&self, index: CPIndex) -> Result<&MethodHandleInfo> {
 		match self.get(index)? {
 			ConstantType::MethodHandle(t) => Ok(t),
@@ -266,55 +336,102 @@ impl ConstantPool {
 	}
 }
 
-impl Serializable for ConstantPool {
-	fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
+impl ConstantPool {
+	pub fn parse_with<R: Read>(rdr: &mut R, options: &ParseOptions) -> Result<Self> {
 		let size = rdr.read_u16::<BigEndian>()? as usize;
 		let mut cp = ConstantPool {
 			inner: vec![None; size]
 		};
-		let mut skip = false;
-		for i in 1..size {
-			if skip {
-				skip = false;
-				continue
-			}
-			let constant = ConstantType::parse(rdr)?;
-			if constant.double_size() {
-				skip = true;
-			}
-			cp.set(i as CPIndex, Some(constant));
+		match cp.parse_constants(rdr, 1, options)? {
+			// only with ParseOptions::lenient_pool
+			Some(unrecognised) => Err(unrecognised.error),
+			None => Ok(cp)
 		}
-		
-		Ok(cp)
+	}
+	
+	/// Parses the pool, stopping at a constant with an unrecognised tag if
+	/// [`ParseOptions::lenient_pool`] is set. The constants before it are kept.
+	pub fn parse_lenient<R: Read>(rdr: &mut R, options: &ParseOptions) -> Result<(Self, Option<PartialParse>)> {
+		let size = rdr.read_u16::<BigEndian>()? as usize;
+		let mut cp = ConstantPool {
+			inner: vec![None; size]
+		};
+		let partial = cp.parse_constants(rdr, 1, options)?.map(|unrecognised| PartialParse {
+			index: unrecognised.index,
+			tag: unrecognised.tag,
+			error: Arc::new(unrecognised.error),
+			guessed_length: None
+		});
+		Ok((cp, partial))
+	}
+	
+	/// Parses the constants from index `from` on, stopping at an unrecognised tag only with
+	/// [`ParseOptions::lenient_pool`]
+	pub(crate) fn parse_constants<R: Read>(&mut self, rdr: &mut R, from: usize, options: &ParseOptions) -> Result<Option<UnrecognisedConstant>> {
+		let mut i = from;
+		while i < self.inner.len() {
+			let tag = rdr.read_u8().map_err(ParserError::from).context(|| format!("constant_pool[{}]", i))?;
+			let constant = match ConstantType::parse_tagged(tag, rdr, options) {
+				Err(err @ ParserError::Unrecognized(..)) if options.lenient_pool => return Ok(Some(UnrecognisedConstant {
+					index: i as CPIndex,
+					tag,
+					error: ParserError::at(format!("constant_pool[{}]", i), err)
+				})),
+				constant => constant.context(|| format!("constant_pool[{}]", i))?
+			};
+			// a long or double as the final constant has its phantom slot past the end of the pool
+			let next = i + if constant.double_size() { 2 } else { 1 };
+			self.inner[i] = Some(constant);
+			i = next;
+		}
+		Ok(None)
+	}
+}
+
+/// The constant [`ConstantPool::parse_constants`] stopped at
+pub(crate) struct UnrecognisedConstant {
+	pub index: CPIndex,
+	pub tag: u8,
+	pub error: ParserError
+}
+
+impl Serializable for ConstantPool {
+	fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
+		ConstantPool::parse_with(rdr, &ParseOptions::default())
 	}
 	
 	fn write<W: Write>(&self, wtr: &mut W) -> Result<()> {
-		wtr.write_u16::<BigEndian>(self.inner.len() as u16)?;
-		Ok(())
+		ConstantPoolWriter::from_pool(self).write(wtr)
 	}
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassInfo {
 	pub name_index: CPIndex
 }
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FieldRefInfo {
 	pub class_index: CPIndex,
 	pub name_and_type_index: CPIndex
 }
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodRefInfo {
 	pub class_index: CPIndex,
 	pub name_and_type_index: CPIndex
 }
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StringInfo {
 	pub utf_index: CPIndex
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IntegerInfo {
+	#[cfg_attr(feature = "serde", serde(rename = "value"))]
 	inner: i32
 }
 impl IntegerInfo {
@@ -324,7 +441,9 @@ impl IntegerInfo {
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LongInfo {
+	#[cfg_attr(feature = "serde", serde(rename = "value"))]
 	inner: i64
 }
 impl LongInfo {
@@ -340,7 +459,9 @@ impl LongInfo {
 /// two constant pool entries and who cares
 /// Because of this we will store the float as an integer and let rust do integer comparisons on it
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FloatInfo {
+	#[cfg_attr(feature = "serde", serde(rename = "bits"))]
 	inner: u32
 }
 impl FloatInfo {
@@ -355,7 +476,9 @@ impl FloatInfo {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DoubleInfo {
+	#[cfg_attr(feature = "serde", serde(rename = "bits"))]
 	inner: u64
 }
 impl DoubleInfo {
@@ -370,22 +493,69 @@ impl DoubleInfo {
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NameAndTypeInfo {
 	pub name_index: CPIndex,
 	pub descriptor_index: CPIndex
 }
-#[derive(Constructor, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Utf8Info {
-	pub str: String
+	pub str: String,
+	/// The original modified UTF-8 bytes, only kept when they could not be decoded losslessly (for
+	/// example unpaired surrogates). These are written back as long as `str` is left untouched.
+	raw: Option<Box<[u8]>>
+}
+
+impl Utf8Info {
+	pub fn new(str: String) -> Self {
+		Utf8Info {
+			str,
+			raw: None
+		}
+	}
+	
+	/// Decodes modified UTF-8 bytes as found in the class file.
+	///
+	/// Invalid sequences are replaced with U+FFFD, unless `strict` is set in which case an error is
+	/// returned.
+	pub fn from_mutf8(bytes: &[u8], strict: bool) -> Result<Self> {
+		let utf = mutf8::mutf8_to_utf8(bytes);
+		match std::str::from_utf8(&utf) {
+			Ok(str) => Ok(Utf8Info::new(String::from(str))),
+			Err(err) if strict => Err(ParserError::invalid_utf8(err)),
+			Err(_) => Ok(Utf8Info {
+				str: String::from_utf8_lossy(&utf).into_owned(),
+				raw: Some(bytes.into())
+			})
+		}
+	}
+	
+	/// The modified UTF-8 bytes this constant will be written as
+	pub fn to_mutf8(&self) -> Cow<'_, [u8]> {
+		if let Some(raw) = &self.raw {
+			if String::from_utf8_lossy(&mutf8::mutf8_to_utf8(raw)) == self.str {
+				return Cow::Borrowed(raw);
+			}
+		}
+		mutf8::utf8_to_mutf8(self.str.as_bytes())
+	}
+	
+	/// Whether `str` is an exact representation of the original bytes
+	pub fn is_lossless(&self) -> bool {
+		self.raw.is_none()
+	}
 }
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodHandleInfo {
 	pub kind: MethodHandleKind,
 	pub reference: CPIndex
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MethodHandleKind {
 	GetField,
 	GetStatic,
@@ -398,44 +568,116 @@ pub enum MethodHandleKind {
 	InvokeInterface
 }
 
-#[allow(non_upper_case_globals)]
 impl MethodHandleKind {
-	const REF_getField: u8 = 1;
-	const REF_getStatic: u8 = 2;
-	const REF_putField: u8 = 3;
-	const REF_putStatic: u8 = 4;
-	const REF_invokeVirtual: u8 = 5;
-	const REF_invokeStatic: u8 = 6;
-	const REF_invokeSpecial: u8 = 7;
-	const REF_newInvokeSpecial: u8 = 8;
-	const REF_invokeInterface: u8 = 9;
+	/// The `reference_kind` of a CONSTANT_MethodHandle
+	pub fn tag(&self) -> u8 {
+		match self {
+			MethodHandleKind::GetField => 1,
+			MethodHandleKind::GetStatic => 2,
+			MethodHandleKind::PutField => 3,
+			MethodHandleKind::PutStatic => 4,
+			MethodHandleKind::InvokeVirtual => 5,
+			MethodHandleKind::InvokeStatic => 6,
+			MethodHandleKind::InvokeSpecial => 7,
+			MethodHandleKind::NewInvokeSpecial => 8,
+			MethodHandleKind::InvokeInterface => 9
+		}
+	}
+	
+	/// The kind with the given `reference_kind`, if it is one of the nine defined by the JVMS
+	pub fn from_tag(tag: u8) -> Option<Self> {
+		Some(match tag {
+			1 => MethodHandleKind::GetField,
+			2 => MethodHandleKind::GetStatic,
+			3 => MethodHandleKind::PutField,
+			4 => MethodHandleKind::PutStatic,
+			5 => MethodHandleKind::InvokeVirtual,
+			6 => MethodHandleKind::InvokeStatic,
+			7 => MethodHandleKind::InvokeSpecial,
+			8 => MethodHandleKind::NewInvokeSpecial,
+			9 => MethodHandleKind::InvokeInterface,
+			_ => return None
+		})
+	}
+	
+	/// The JVMS name, e.g. `REF_invokeVirtual`
+	pub fn name(&self) -> &'static str {
+		match self {
+			MethodHandleKind::GetField => "REF_getField",
+			MethodHandleKind::GetStatic => "REF_getStatic",
+			MethodHandleKind::PutField => "REF_putField",
+			MethodHandleKind::PutStatic => "REF_putStatic",
+			MethodHandleKind::InvokeVirtual => "REF_invokeVirtual",
+			MethodHandleKind::InvokeStatic => "REF_invokeStatic",
+			MethodHandleKind::InvokeSpecial => "REF_invokeSpecial",
+			MethodHandleKind::NewInvokeSpecial => "REF_newInvokeSpecial",
+			MethodHandleKind::InvokeInterface => "REF_invokeInterface"
+		}
+	}
+	
+	/// Whether the handle reads or writes a field rather than calling a method
+	pub fn is_field(&self) -> bool {
+		matches!(self, MethodHandleKind::GetField | MethodHandleKind::GetStatic | MethodHandleKind::PutField | MethodHandleKind::PutStatic)
+	}
+}
+
+impl Display for MethodHandleKind {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for MethodHandleKind {
+	type Err = ParserError;
+	
+	/// Accepts the JVMS name, e.g. `REF_invokeVirtual`, or its lowercase mnemonic, e.g. `invokevirtual`
+	fn from_str(s: &str) -> Result<Self> {
+		(1..=9).filter_map(MethodHandleKind::from_tag)
+			.find(|kind| kind.name() == s || kind.name()[4..].to_ascii_lowercase() == s)
+			.ok_or_else(|| ParserError::unrecognised("method handle kind", s.to_string()))
+	}
 }
 
 
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodTypeInfo {
 	pub descriptor_index: CPIndex
 }
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DynamicInfo {
 	pub bootstrap_method_attr_index: CPIndex,
 	pub name_and_type_index: CPIndex
 }
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InvokeDynamicInfo {
 	pub bootstrap_method_attr_index: CPIndex,
 	pub name_and_type_index: CPIndex
 }
+/// An entry of the BootstrapMethods attribute: a method handle constant and the loadable
+/// constants passed to it
+#[derive(Constructor, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BootstrapMethodInfo {
+	pub method_ref: CPIndex,
+	pub arguments: Vec<CPIndex>
+}
+
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModuleInfo {
 	pub name_index: CPIndex
 }
 #[derive(Constructor, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PackageInfo {
 	pub name_index: CPIndex
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, DisplayDebug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "tag"))]
 pub enum ConstantType {
 	Class (ClassInfo),
 	Fieldref (FieldRefInfo),
@@ -477,7 +719,16 @@ impl ConstantType {
 	const CONSTANT_Package: u8 = 20;
 	
 	pub fn parse<R: Read>(rdr: &mut R) -> Result<Self> {
+		ConstantType::parse_with(rdr, &ParseOptions::default())
+	}
+	
+	pub fn parse_with<R: Read>(rdr: &mut R, options: &ParseOptions) -> Result<Self> {
 		let tag = rdr.read_u8()?;
+		ConstantType::parse_tagged(tag, rdr, options)
+	}
+	
+	/// Parses the contents of a constant after its tag
+	fn parse_tagged<R: Read>(tag: u8, rdr: &mut R, options: &ParseOptions) -> Result<Self> {
 		Ok(match tag {
 			ConstantType::CONSTANT_Class => ConstantType::Class (
 				ClassInfo {
@@ -527,29 +778,15 @@ impl ConstantType {
 			),
 			ConstantType::CONSTANT_Utf8 => {
 				let length = rdr.read_u16::<BigEndian>()? as usize;
-				let bytes = rdr.read_nbytes(length)?;
-				let utf = match mutf8::mutf8_to_utf8(bytes.as_slice()) {
-					Cow::Borrowed(_data) => bytes.into(),
-					Cow::Owned(data) => data.into_boxed_slice(),
-				};
-				
-				let str = String::from_utf8_lossy(&utf);
-				let str = String::from(&*str);
-				ConstantType::Utf8 ( Utf8Info { str } )
+				let bytes = rdr.read_nbytes(length, "Utf8 constant")?;
+				ConstantType::Utf8 (
+					Utf8Info::from_mutf8(&bytes, options.strict_utf8)?
+				)
 			},
 			ConstantType::CONSTANT_MethodHandle => {
-				let kind = match rdr.read_u8()? {
-					MethodHandleKind::REF_getField => MethodHandleKind::GetField,
-					MethodHandleKind::REF_getStatic => MethodHandleKind::GetStatic,
-					MethodHandleKind::REF_putField => MethodHandleKind::PutField,
-					MethodHandleKind::REF_putStatic => MethodHandleKind::PutStatic,
-					MethodHandleKind::REF_invokeVirtual => MethodHandleKind::InvokeVirtual,
-					MethodHandleKind::REF_invokeStatic => MethodHandleKind::InvokeStatic,
-					MethodHandleKind::REF_invokeSpecial => MethodHandleKind::InvokeSpecial,
-					MethodHandleKind::REF_newInvokeSpecial => MethodHandleKind::NewInvokeSpecial,
-					MethodHandleKind::REF_invokeInterface => MethodHandleKind::InvokeInterface,
-					x => return Err(ParserError::other(format!("Unknown method handle type {}", x)))
-				};
+				let kind = rdr.read_u8()?;
+				let kind = MethodHandleKind::from_tag(kind)
+					.ok_or_else(|| ParserError::other(format!("Unknown method handle type {}", kind)))?;
 				let reference = rdr.read_u16::<BigEndian>()?;
 				ConstantType::MethodHandle(MethodHandleInfo::new(kind, reference))
 			},
@@ -632,30 +869,14 @@ impl ConstantType {
 			}
 			ConstantType::Utf8(x) => {
 				wtr.write_u8(ConstantType::CONSTANT_Utf8)?;
-				let bytes = x.str.as_bytes();
-				let mutf = match mutf8::utf8_to_mutf8(bytes) {
-					Cow::Borrowed(_data) => bytes.into(),
-					Cow::Owned(data) => data.into_boxed_slice(),
-				};
+				let mutf = x.to_mutf8();
 				wtr.write_u16::<BigEndian>(mutf.len() as u16)?;
-				wtr.write_all(&*mutf)?;
+				wtr.write_all(&mutf)?;
 			}
 			ConstantType::MethodHandle(x) => {
 				wtr.write_u8(ConstantType::CONSTANT_MethodHandle)?;
 				
-				let reference_kind = match x.kind {
-					MethodHandleKind::GetField => MethodHandleKind::REF_getField,
-					MethodHandleKind::GetStatic => MethodHandleKind::REF_getStatic,
-					MethodHandleKind::PutField => MethodHandleKind::REF_putField,
-					MethodHandleKind::PutStatic => MethodHandleKind::REF_putStatic,
-					MethodHandleKind::InvokeVirtual => MethodHandleKind::REF_invokeVirtual,
-					MethodHandleKind::InvokeStatic => MethodHandleKind::REF_invokeStatic,
-					MethodHandleKind::InvokeSpecial => MethodHandleKind::REF_invokeSpecial,
-					MethodHandleKind::NewInvokeSpecial => MethodHandleKind::REF_newInvokeSpecial,
-					MethodHandleKind::InvokeInterface => MethodHandleKind::REF_invokeInterface,
-				};
-				
-				wtr.write_u8(reference_kind)?;
+				wtr.write_u8(x.kind.tag())?;
 				wtr.write_u16::<BigEndian>(x.reference)?;
 			}
 			ConstantType::MethodType(x) => {
@@ -687,18 +908,73 @@ impl ConstantType {
 	pub fn double_size(&self) -> bool {
 		matches!(self, ConstantType::Double(..) | ConstantType::Long(..))
 	}
+	
+	/// The first class file version that may contain this kind of constant
+	pub fn minimum_version(&self) -> MajorVersion {
+		match self {
+			ConstantType::MethodHandle(..) | ConstantType::MethodType(..) | ConstantType::InvokeDynamic(..) => MajorVersion::JAVA_7,
+			ConstantType::Module(..) | ConstantType::Package(..) => MajorVersion::JAVA_9,
+			ConstantType::Dynamic(..) => MajorVersion::JAVA_11,
+			_ => MajorVersion::JDK_1_1
+		}
+	}
+	
+	/// Calls the function with every constant pool index this constant refers to
+	fn for_each_index<F: FnMut(&mut CPIndex)>(&mut self, mut f: F) {
+		match self {
+			ConstantType::Class(x) => f(&mut x.name_index),
+			ConstantType::Fieldref(x) => {
+				f(&mut x.class_index);
+				f(&mut x.name_and_type_index);
+			},
+			ConstantType::Methodref(x) | ConstantType::InterfaceMethodref(x) => {
+				f(&mut x.class_index);
+				f(&mut x.name_and_type_index);
+			},
+			ConstantType::String(x) => f(&mut x.utf_index),
+			ConstantType::NameAndType(x) => {
+				f(&mut x.name_index);
+				f(&mut x.descriptor_index);
+			},
+			ConstantType::MethodHandle(x) => f(&mut x.reference),
+			ConstantType::MethodType(x) => f(&mut x.descriptor_index),
+			// the bootstrap method index points into the BootstrapMethods attribute, not the pool
+			ConstantType::Dynamic(x) => f(&mut x.name_and_type_index),
+			ConstantType::InvokeDynamic(x) => f(&mut x.name_and_type_index),
+			ConstantType::Module(x) => f(&mut x.name_index),
+			ConstantType::Package(x) => f(&mut x.name_index),
+			ConstantType::Integer(..) | ConstantType::Float(..) | ConstantType::Long(..) |
+			ConstantType::Double(..) | ConstantType::Utf8(..) => {}
+		}
+	}
 }
 
 pub struct ConstantPoolWriter {
-	inner: LinkedHashMap<ConstantType, u16>,
-	index: CPIndex
+	/// Every constant in index order, long and double constants take up two indices
+	entries: Vec<ConstantType>,
+	/// The index of the first occurrence of each constant
+	indices: HashMap<ConstantType, CPIndex>,
+	/// Indices returned by `put` since the writer was created or last compacted
+	used: HashSet<CPIndex>,
+	index: CPIndex,
+	/// The constant pool count needed once more constants were added than fit, see `write`
+	overflow: Option<usize>,
+	/// Entries of the BootstrapMethods attribute, referred to by dynamic constants
+	bootstrap_methods: Vec<BootstrapMethodInfo>,
+	/// Whether the writer was made by `from_pool`, see `preserves_indices`
+	preserves_indices: bool
 }
 
 impl Default for ConstantPoolWriter {
 	fn default() -> Self {
 		ConstantPoolWriter {
-			inner: LinkedHashMap::with_capacity(5),
-			index: 1
+			entries: Vec::with_capacity(5),
+			indices: HashMap::with_capacity(5),
+			used: HashSet::new(),
+			index: 1,
+			overflow: None,
+			bootstrap_methods: Vec::new(),
+			preserves_indices: false
 		}	
 	}
 }
@@ -708,16 +984,71 @@ impl ConstantPoolWriter {
 		ConstantPoolWriter::default()
 	}
 	
+	/// Starts with every constant of the given pool at its original index, so that writing without
+	/// adding anything reproduces the pool exactly and any new constants are appended to the end.
+	///
+	/// Unused indices between constants that do not follow a long or double are filled with an
+	/// empty Utf8 constant, which later empty strings reuse. Unused indices at the end are dropped.
+	pub fn from_pool(pool: &ConstantPool) -> Self {
+		let mut writer = ConstantPoolWriter {
+			entries: Vec::with_capacity(pool.inner.len()),
+			indices: HashMap::with_capacity(pool.inner.len()),
+			used: HashSet::new(),
+			index: 1,
+			overflow: None,
+			bootstrap_methods: Vec::new(),
+			preserves_indices: true
+		};
+		let len = pool.inner.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+		let mut skip = false;
+		for constant in pool.inner.iter().take(len).skip(1) {
+			if skip {
+				skip = false;
+				continue;
+			}
+			let filler = ConstantType::Utf8(Utf8Info::new(String::new()));
+			let constant = constant.as_ref().unwrap_or(&filler);
+			writer.indices.entry(constant.clone()).or_insert(writer.index);
+			writer.push(constant.clone());
+			skip = constant.double_size();
+		}
+		writer
+	}
+	
+	/// Whether every constant of the pool this writer was made from is at its original index, so
+	/// that bytes parsed against that pool can be written as they are. Only true for writers made
+	/// by [`from_pool`](Self::from_pool).
+	pub fn preserves_indices(&self) -> bool {
+		self.preserves_indices
+	}
+	
+	/// The index of the given constant, if it has been added
+	pub fn get(&self, constant: &ConstantType) -> Option<CPIndex> {
+		self.indices.get(constant).copied()
+	}
+	
 	pub fn put(&mut self, constant: ConstantType) -> CPIndex {
-		match self.inner.get(&constant) {
+		let index = match self.indices.get(&constant) {
 			Some(x) => *x,
 			None => {
 				let this_index = self.index;
-				self.index += if constant.double_size() { 2	} else { 1 };
-				self.inner.insert(constant, this_index);
+				self.indices.insert(constant.clone(), this_index);
+				self.push(constant);
 				this_index
 			}
+		};
+		self.used.insert(index);
+		index
+	}
+	
+	fn push(&mut self, constant: ConstantType) {
+		let size = if constant.double_size() { 2 } else { 1 };
+		match self.index.checked_add(size) {
+			Some(index) => self.index = index,
+			// later constants share the last index, writing fails anyway
+			None => self.overflow = Some(self.overflow.unwrap_or(self.index as usize) + size as usize)
 		}
+		self.entries.push(constant);
 	}
 	
 	pub fn len(&self) -> u16 {
@@ -728,6 +1059,128 @@ impl ConstantPoolWriter {
 		self.index == 0
 	}
 	
+	/// The number of constants, counting long and double constants once
+	pub fn constant_count(&self) -> usize {
+		self.entries.len()
+	}
+	
+	/// The constants in index order
+	pub fn constants(&self) -> impl Iterator<Item = &ConstantType> {
+		self.entries.iter()
+	}
+	
+	/// Forgets which constants have been used, so that only those put afterwards survive
+	/// [`compacted`](Self::compacted)
+	pub fn clear_usage(&mut self) {
+		self.used.clear();
+	}
+	
+	/// A copy of this pool containing only the constants put since creation (or the last
+	/// [`clear_usage`](Self::clear_usage)) and the constants they refer to, in their original order.
+	/// Indices inside the constants are remapped, so anything written against this pool must be
+	/// written again against the compacted one.
+	pub fn compacted(&self) -> ConstantPoolWriter {
+		let slots = self.slots();
+		let positions: HashMap<CPIndex, usize> = slots.iter().enumerate().map(|(position, index)| (*index, position)).collect();
+		// follow references from the used constants, some of which may point forwards
+		let mut keep = self.used.clone();
+		let mut pending: Vec<CPIndex> = keep.iter().copied().collect();
+		while let Some(index) = pending.pop() {
+			if let Some(position) = positions.get(&index) {
+				self.entries[*position].clone().for_each_index(|reference| {
+					if keep.insert(*reference) {
+						pending.push(*reference);
+					}
+				});
+			}
+		}
+		
+		let order: Vec<usize> = (0..self.entries.len()).filter(|position| keep.contains(&slots[*position])).collect();
+		self.rebuild(&order)
+	}
+	
+	/// A copy of this pool with the constants sorted by tag and then by content, so that the order
+	/// does not depend on the order they were added in. As with [`compacted`](Self::compacted),
+	/// anything written against this pool must be written again against the sorted one.
+	pub fn sorted(&self) -> ConstantPoolWriter {
+		let slots = self.slots();
+		let positions: HashMap<CPIndex, usize> = slots.iter().enumerate().map(|(position, index)| (*index, position)).collect();
+		let keys: Vec<Vec<u8>> = self.entries.iter()
+			.map(|constant| {
+				let mut key = Vec::new();
+				self.canonical_key(constant, &positions, 0, &mut key);
+				key
+			})
+			.collect();
+		let mut order: Vec<usize> = (0..self.entries.len()).collect();
+		// stable, so that duplicates keep their relative order
+		order.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+		self.rebuild(&order)
+	}
+	
+	/// The constant's bytes with its references zeroed, followed by the keys of the constants it
+	/// refers to. Each constant starts with its tag, so constants are ordered by tag first.
+	fn canonical_key(&self, constant: &ConstantType, positions: &HashMap<CPIndex, usize>, depth: usize, key: &mut Vec<u8>) {
+		let mut references = Vec::new();
+		let mut zeroed = constant.clone();
+		zeroed.for_each_index(|reference| {
+			references.push(*reference);
+			*reference = 0;
+		});
+		// writing to a vec cannot fail
+		let _ = zeroed.write(key);
+		// valid pools are at most four constants deep, this only stops cycles in invalid ones
+		if depth > 8 {
+			return;
+		}
+		for reference in references {
+			let mut nested = Vec::new();
+			if let Some(position) = positions.get(&reference) {
+				self.canonical_key(&self.entries[*position], positions, depth + 1, &mut nested);
+			}
+			key.extend_from_slice(&(nested.len() as u32).to_be_bytes());
+			key.extend(nested);
+		}
+	}
+	
+	/// Copies the constants at the given positions of `entries` into a new pool in that order,
+	/// renumbering the references between them
+	fn rebuild(&self, order: &[usize]) -> ConstantPoolWriter {
+		let slots = self.slots();
+		let mut remapped: HashMap<CPIndex, CPIndex> = HashMap::with_capacity(order.len());
+		let mut next: CPIndex = 1;
+		for position in order.iter() {
+			remapped.insert(slots[*position], next);
+			next = next.saturating_add(if self.entries[*position].double_size() { 2 } else { 1 });
+		}
+		
+		let mut writer = ConstantPoolWriter::new();
+		// the indices in anything written against an overflowing pool are already wrong
+		writer.overflow = self.overflow;
+		for position in order.iter() {
+			let mut constant = self.entries[*position].clone();
+			// a reference to a missing constant is left dangling
+			constant.for_each_index(|reference| if let Some(x) = remapped.get(reference) {
+				*reference = *x;
+			});
+			writer.indices.entry(constant.clone()).or_insert(writer.index);
+			writer.push(constant);
+		}
+		writer
+	}
+	
+	/// The index of each constant in `entries`
+	fn slots(&self) -> Vec<CPIndex> {
+		let mut index: CPIndex = 1;
+		self.entries.iter()
+			.map(|constant| {
+				let this_index = index;
+				index = index.saturating_add(if constant.double_size() { 2 } else { 1 });
+				this_index
+			})
+			.collect()
+	}
+	
 	pub fn class(&mut self, name_index: CPIndex) -> CPIndex {
 		self.put(ConstantType::Class(ClassInfo::new(name_index)))
 	}
@@ -803,6 +1256,86 @@ impl ConstantPoolWriter {
 		self.put(ConstantType::InvokeDynamic(InvokeDynamicInfo::new(bootstrap_method_attr_index, name_and_type_index)))
 	}
 	
+	/// The index of the bootstrap method with the given method handle and arguments in the
+	/// BootstrapMethods attribute, adding it if needed. Once more methods were added than the
+	/// attribute can hold, later ones share the last index and writing the attribute fails.
+	pub fn bootstrap_method(&mut self, method_ref: CPIndex, arguments: Vec<CPIndex>) -> u16 {
+		let method = BootstrapMethodInfo::new(method_ref, arguments);
+		let index = match self.bootstrap_methods.iter().position(|x| *x == method) {
+			Some(index) => index,
+			None => {
+				self.bootstrap_methods.push(method);
+				self.bootstrap_methods.len() - 1
+			}
+		};
+		u16::try_from(index).unwrap_or(u16::MAX)
+	}
+	
+	/// Appends the entries of a parsed BootstrapMethods attribute as they are, so that
+	/// invokedynamic instructions and dynamic constants written as they were parsed keep referring
+	/// to the right entries
+	pub(crate) fn add_bootstrap_methods(&mut self, attribute: &[u8]) -> Result<()> {
+		let mut rdr = Cursor::new(attribute);
+		let count = rdr.read_u16::<BigEndian>()?;
+		for _ in 0..count {
+			let method_ref = rdr.read_u16::<BigEndian>()?;
+			let num_arguments = rdr.read_u16::<BigEndian>()?;
+			let arguments = (0..num_arguments).map(|_| rdr.read_u16::<BigEndian>()).collect::<std::io::Result<Vec<CPIndex>>>()?;
+			self.bootstrap_methods.push(BootstrapMethodInfo::new(method_ref, arguments));
+		}
+		Ok(())
+	}
+	
+	/// The bootstrap methods added while writing, which the class must store in its
+	/// BootstrapMethods attribute
+	pub fn bootstrap_methods(&self) -> &[BootstrapMethodInfo] {
+		&self.bootstrap_methods
+	}
+	
+	pub fn method_handle(&mut self, handle: &MethodHandle) -> CPIndex {
+		let class = self.class_utf8(handle.class.clone());
+		let name = self.utf8(handle.name.clone());
+		let descriptor = self.utf8(handle.descriptor.clone());
+		let name_and_type = self.nameandtype(name, descriptor);
+		let reference = match handle.kind {
+			MethodHandleKind::GetField | MethodHandleKind::GetStatic | MethodHandleKind::PutField | MethodHandleKind::PutStatic => self.fieldref(class, name_and_type),
+			MethodHandleKind::InvokeInterface => self.interfacemethodref(class, name_and_type),
+			MethodHandleKind::InvokeStatic | MethodHandleKind::InvokeSpecial if handle.interface => self.interfacemethodref(class, name_and_type),
+			_ => self.methodref(class, name_and_type)
+		};
+		self.methodhandle(handle.kind, reference)
+	}
+	
+	pub fn dynamic(&mut self, constant: &DynamicConstant) -> CPIndex {
+		let bootstrap_method = self.bootstrap_method_of(&constant.bootstrap_method, &constant.bootstrap_arguments);
+		let name = self.utf8(constant.name.clone());
+		let descriptor = self.utf8(constant.descriptor.clone());
+		let name_and_type = self.nameandtype(name, descriptor);
+		self.dynamicinfo(bootstrap_method, name_and_type)
+	}
+	
+	/// The bootstrap method index of the given method handle and arguments, adding their constants
+	pub fn bootstrap_method_of(&mut self, handle: &MethodHandle, arguments: &[BootstrapArgument]) -> u16 {
+		let method_ref = self.method_handle(handle);
+		let arguments = arguments.iter().map(|argument| self.loadable_constant(argument)).collect();
+		self.bootstrap_method(method_ref, arguments)
+	}
+	
+	/// The constant that an ldc instruction or bootstrap method argument loads
+	pub fn loadable_constant(&mut self, constant: &BootstrapArgument) -> CPIndex {
+		match constant {
+			BootstrapArgument::Int(x) => self.integer(*x),
+			BootstrapArgument::Float(x) => self.float(*x),
+			BootstrapArgument::Long(x) => self.long(*x),
+			BootstrapArgument::Double(x) => self.double(*x),
+			BootstrapArgument::Class(x) => self.class_utf8(x.clone()),
+			BootstrapArgument::String(x) => self.string_utf(x.clone()),
+			BootstrapArgument::MethodType(x) => self.methodtype_utf8(x.clone()),
+			BootstrapArgument::MethodHandle(x) => self.method_handle(x),
+			BootstrapArgument::Dynamic(x) => self.dynamic(x)
+		}
+	}
+	
 	pub fn module(&mut self, name_index: CPIndex) -> CPIndex {
 		self.put(ConstantType::Module(ModuleInfo::new(name_index)))
 	}
@@ -811,12 +1344,294 @@ impl ConstantPoolWriter {
 		self.put(ConstantType::Package(PackageInfo::new(name_index)))
 	}
 	
+	/// Fails if more constants were added than a class can hold
 	pub fn write<W: Write>(&mut self, wtr: &mut W) -> Result<()> {
-		wtr.write_u16::<BigEndian>(self.index as u16)?;
-		for (constant, _index) in self.inner.iter() {
+		if let Some(needed) = self.overflow {
+			return Err(ParserError::constant_pool_overflow(needed));
+		}
+		wtr.write_u16::<BigEndian>(self.index)?;
+		for constant in self.entries.iter() {
 			constant.write(wtr)?;
 		}
 		
 		Ok(())
 	}
+	
+	/// Writes the contents of the BootstrapMethods attribute
+	pub fn write_bootstrap_methods<W: Write>(&self, wtr: &mut W) -> Result<()> {
+		let count = u16::try_from(self.bootstrap_methods.len())
+			.map_err(|_| ParserError::other(format!("{} bootstrap methods, the limit is 65535", self.bootstrap_methods.len())))?;
+		wtr.write_u16::<BigEndian>(count)?;
+		for (index, method) in self.bootstrap_methods.iter().enumerate() {
+			wtr.write_u16::<BigEndian>(method.method_ref)?;
+			let arguments = u16::try_from(method.arguments.len())
+				.map_err(|_| ParserError::other(format!("Bootstrap method {} has {} arguments, the limit is 65535", index, method.arguments.len())))?;
+			wtr.write_u16::<BigEndian>(arguments)?;
+			for argument in method.arguments.iter() {
+				wtr.write_u16::<BigEndian>(*argument)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::classfile::ParseOptions;
+	use crate::types::ClassName;
+	use std::io::Cursor;
+	
+	/// A Utf8 constant of "a" followed by a lone high surrogate (U+D800) and "b"
+	const UNPAIRED_SURROGATE: [u8; 8] = [1, 0, 5, b'a', 0xED, 0xA0, 0x80, b'b'];
+	
+	#[test]
+	fn unpaired_surrogate_round_trips() {
+		let constant = ConstantType::parse(&mut Cursor::new(&UNPAIRED_SURROGATE[..])).unwrap();
+		let utf8 = match &constant {
+			ConstantType::Utf8(x) => x,
+			x => panic!("Expected Utf8, found {:?}", x)
+		};
+		assert!(!utf8.is_lossless());
+		assert!(utf8.str.starts_with('a') && utf8.str.ends_with('b'));
+		
+		let mut out = Vec::new();
+		constant.write(&mut out).unwrap();
+		assert_eq!(out, UNPAIRED_SURROGATE);
+	}
+	
+	#[test]
+	fn modified_surrogate_string_is_reencoded() {
+		let mut constant = ConstantType::parse(&mut Cursor::new(&UNPAIRED_SURROGATE[..])).unwrap();
+		if let ConstantType::Utf8(x) = &mut constant {
+			x.str = String::from("ab");
+		}
+		let mut out = Vec::new();
+		constant.write(&mut out).unwrap();
+		assert_eq!(out, [1, 0, 2, b'a', b'b']);
+	}
+	
+	#[test]
+	fn strict_rejects_unpaired_surrogate() {
+		let options = ParseOptions {
+			strict_utf8: true,
+			..Default::default()
+		};
+		match ConstantType::parse_with(&mut Cursor::new(&UNPAIRED_SURROGATE[..]), &options) {
+			Err(ParserError::InvalidUtf8(_)) => {},
+			x => panic!("Expected InvalidUtf8, found {:?}", x)
+		}
+	}
+	
+	#[test]
+	fn loadable_constants_register_bootstrap_methods() {
+		let bootstrap = MethodHandle::new(MethodHandleKind::InvokeStatic, ClassName::from("java/lang/invoke/ConstantBootstraps"), String::from("nullConstant"), String::from("(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/Class;)Ljava/lang/Object;"), false);
+		let inner = BootstrapArgument::Dynamic(Box::new(DynamicConstant::new(String::from("inner"), String::from("Ljava/lang/Object;"), bootstrap.clone(), Vec::new())));
+		let outer = BootstrapArgument::Dynamic(Box::new(DynamicConstant::new(String::from("outer"), String::from("J"), bootstrap, vec![inner.clone(), BootstrapArgument::Int(3)])));
+		assert!(outer.double_size());
+		
+		let mut writer = ConstantPoolWriter::new();
+		let outer_index = writer.loadable_constant(&outer);
+		let inner_index = writer.loadable_constant(&inner);
+		assert_eq!(writer.loadable_constant(&outer), outer_index);
+		let three = writer.integer(3);
+		// the inner constant's bootstrap method is added first, as the outer one refers to it
+		assert_eq!(writer.bootstrap_methods().len(), 2);
+		assert!(writer.bootstrap_methods()[0].arguments.is_empty());
+		assert_eq!(writer.bootstrap_methods()[1].arguments, vec![inner_index, three]);
+		assert_eq!(writer.bootstrap_methods()[0].method_ref, writer.bootstrap_methods()[1].method_ref);
+		
+		// static methods of interfaces are referred to by an InterfaceMethodref
+		let handle = writer.loadable_constant(&BootstrapArgument::MethodHandle(MethodHandle::new(MethodHandleKind::InvokeStatic, ClassName::from("java/util/List"), String::from("of"), String::from("()Ljava/util/List;"), true)));
+		let class = writer.class_utf8("java/util/List");
+		let name = writer.utf8("of");
+		let descriptor = writer.utf8("()Ljava/util/List;");
+		let name_and_type = writer.nameandtype(name, descriptor);
+		let reference = writer.interfacemethodref(class, name_and_type);
+		assert_eq!(writer.get(&ConstantType::MethodHandle(MethodHandleInfo::new(MethodHandleKind::InvokeStatic, reference))), Some(handle));
+	}
+	
+	#[test]
+	fn too_many_bootstrap_methods_are_rejected() {
+		let mut writer = ConstantPoolWriter::new();
+		writer.bootstrap_methods = (0..65535).map(|i| BootstrapMethodInfo::new(i, Vec::new())).collect();
+		writer.write_bootstrap_methods(&mut Vec::new()).unwrap();
+		// the attribute holds at most 65535 methods, so this one already does not fit
+		assert_eq!(writer.bootstrap_method(0, vec![1]), 65535);
+		// and later ones share its index
+		assert_eq!(writer.bootstrap_method(0, vec![2]), 65535);
+		let err = writer.write_bootstrap_methods(&mut Vec::new()).unwrap_err();
+		assert_eq!(err.to_string(), "65537 bootstrap methods, the limit is 65535");
+	}
+	
+	#[test]
+	fn overflowing_pool_is_rejected() {
+		let mut writer = ConstantPoolWriter::new();
+		for i in 0..65534 {
+			writer.utf8(i.to_string());
+		}
+		// indices 1 to 65534 are the most a class can have
+		assert_eq!(writer.len(), 65535);
+		writer.write(&mut Vec::new()).unwrap();
+		writer.utf8("one more");
+		match writer.write(&mut Vec::new()) {
+			Err(ParserError::ConstantPoolOverflow { needed: 65536 }) => {},
+			x => panic!("Expected ConstantPoolOverflow, found {:?}", x)
+		}
+		// the indices handed out already alias, so compacting cannot fix it
+		assert!(matches!(writer.compacted().write(&mut Vec::new()), Err(ParserError::ConstantPoolOverflow { .. })));
+		
+		// a long takes two slots, so it does not fit in the last one
+		let mut writer = ConstantPoolWriter::new();
+		for i in 0..65533 {
+			writer.utf8(i.to_string());
+		}
+		writer.long(7);
+		match writer.write(&mut Vec::new()) {
+			Err(ParserError::ConstantPoolOverflow { needed: 65536 }) => {},
+			x => panic!("Expected ConstantPoolOverflow, found {:?}", x)
+		}
+	}
+	
+	#[test]
+	fn writer_from_pool_keeps_layout() {
+		let mut bytes = Vec::new();
+		for constant in [
+			ConstantType::Utf8(Utf8Info::new(String::from("a"))),
+			ConstantType::Long(LongInfo::new(7)),
+			ConstantType::Class(ClassInfo::new(1)),
+			// javac never does this, but obfuscators may
+			ConstantType::Utf8(Utf8Info::new(String::from("a"))),
+			ConstantType::Double(DoubleInfo::new(0.5))
+		].iter() {
+			constant.write(&mut bytes).unwrap();
+		}
+		let mut original = vec![0, 8];
+		original.extend(bytes);
+		let pool = ConstantPool::parse(&mut Cursor::new(&original)).unwrap();
+		
+		let mut writer = ConstantPoolWriter::from_pool(&pool);
+		let mut out = Vec::new();
+		writer.write(&mut out).unwrap();
+		assert_eq!(out, original);
+		
+		assert_eq!(writer.get(&ConstantType::Class(ClassInfo::new(1))), Some(4));
+		assert_eq!(writer.get(&ConstantType::Utf8(Utf8Info::new(String::from("b")))), None);
+		assert_eq!(writer.utf8("a"), 1);
+		assert_eq!(writer.double(0.5), 6);
+		assert_eq!(writer.utf8("b"), 8);
+		assert_eq!(writer.len(), 9);
+	}
+	
+	#[test]
+	fn writer_from_class_pool_is_identical() {
+		let class = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let mut rdr = Cursor::new(&class[8..]);
+		let pool = ConstantPool::parse(&mut rdr).unwrap();
+		let section = &class[8..8 + rdr.position() as usize];
+		
+		let mut out = Vec::new();
+		ConstantPoolWriter::from_pool(&pool).write(&mut out).unwrap();
+		assert_eq!(out, section);
+	}
+	
+	#[test]
+	fn writer_from_pool_fills_gaps_once() {
+		let mut pool = ConstantPool::new();
+		pool.set(2, Some(ConstantType::Utf8(Utf8Info::new(String::from("a")))));
+		pool.set(4, Some(ConstantType::Class(ClassInfo::new(2))));
+		pool.set(6, None);
+		
+		let mut writer = ConstantPoolWriter::from_pool(&pool);
+		assert_eq!(writer.len(), 5);
+		assert_eq!(writer.utf8(""), 1);
+		assert_eq!(writer.utf8("a"), 2);
+		assert_eq!(writer.class(2), 4);
+		assert_eq!(writer.len(), 5);
+	}
+	
+	#[test]
+	fn pools_can_be_built_by_hand() {
+		let mut pool = ConstantPool::new();
+		pool.set(1, Some(ConstantType::Utf8(Utf8Info::new(String::from("a")))));
+		assert_eq!(pool.utf8(1).unwrap().str, "a");
+		assert!(pool.get(2).is_err());
+		// a long set last grows the pool over its phantom slot
+		pool.set(2, Some(ConstantType::Long(LongInfo::new(7))));
+		assert!(pool.is_phantom(3));
+		assert_eq!(pool.push(ConstantType::Class(ClassInfo::new(1))), 4);
+		
+		let mut pool = ConstantPool::new();
+		assert_eq!(pool.push(ConstantType::Utf8(Utf8Info::new(String::from("a")))), 1);
+		assert_eq!(pool.push(ConstantType::Double(DoubleInfo::new(0.5))), 2);
+		assert_eq!(pool.push(ConstantType::Long(LongInfo::new(7))), 4);
+		assert_eq!(pool.push(ConstantType::Class(ClassInfo::new(1))), 6);
+		assert!(pool.is_phantom(3) && pool.is_phantom(5));
+		assert_eq!(pool.class_name(6).unwrap(), "a");
+		assert_eq!(pool, ConstantPool::from_entries(vec![
+			ConstantType::Utf8(Utf8Info::new(String::from("a"))),
+			ConstantType::Double(DoubleInfo::new(0.5)),
+			ConstantType::Long(LongInfo::new(7)),
+			ConstantType::Class(ClassInfo::new(1))
+		]));
+		
+		let mut bytes = Vec::new();
+		Serializable::write(&pool, &mut bytes).unwrap();
+		assert_eq!(&bytes[..2], [0, 7]);
+		let parsed = <ConstantPool as Serializable>::parse(&mut Cursor::new(&bytes)).unwrap();
+		assert_eq!(parsed, pool);
+		
+		// a pool ending in a long has the count after its phantom slot
+		let pool = ConstantPool::from_entries(vec![ConstantType::Long(LongInfo::new(1))]);
+		let mut bytes = Vec::new();
+		Serializable::write(&pool, &mut bytes).unwrap();
+		assert_eq!(<ConstantPool as Serializable>::parse(&mut Cursor::new(&bytes)).unwrap(), pool);
+		assert_eq!(&bytes[..2], [0, 3]);
+	}
+	
+	#[test]
+	fn trailing_wide_constant() {
+		// count 3: a Utf8 then a Long whose phantom slot would be index 3
+		let bytes = [0, 3, 1, 0, 1, b'a', 5, 0, 0, 0, 0, 0, 0, 0, 7];
+		let mut rdr = Cursor::new(&bytes[..]);
+		let pool = <ConstantPool as Serializable>::parse(&mut rdr).unwrap();
+		assert_eq!(rdr.position() as usize, bytes.len());
+		assert_eq!(pool.get(2).unwrap(), &ConstantType::Long(LongInfo::new(7)));
+		assert!(pool.is_phantom(3));
+		assert!(!pool.is_phantom(2));
+		assert!(!pool.is_phantom(4));
+		assert!(matches!(pool.get(3), Err(ParserError::PhantomCpIndex(3))));
+		assert!(matches!(pool.get(4), Err(ParserError::BadCpIndex(4))));
+	}
+	
+	#[test]
+	fn phantom_slot_reference() {
+		// count 4: a Double, its phantom slot, then a Class naming the phantom slot
+		let bytes = [0, 4, 6, 0x3F, 0xF0, 0, 0, 0, 0, 0, 0, 7, 0, 2];
+		let pool = <ConstantPool as Serializable>::parse(&mut Cursor::new(&bytes[..])).unwrap();
+		assert!(pool.is_phantom(2));
+		assert!(!pool.is_phantom(0));
+		assert!(!pool.is_phantom(1));
+		assert!(!pool.is_phantom(3));
+		let err = pool.class_name(3).unwrap_err();
+		assert!(matches!(err.root(), ParserError::PhantomCpIndex(2)), "{:?}", err);
+		assert_eq!(pool.get(2).unwrap_err().to_string(), "Constant pool index 2 refers to the unusable slot after a long or double");
+	}
+	
+	#[test]
+	fn method_handle_kinds_round_trip() {
+		let kinds: Vec<MethodHandleKind> = (0..=u8::MAX).filter_map(MethodHandleKind::from_tag).collect();
+		assert_eq!(kinds.len(), 9);
+		for kind in kinds {
+			assert_eq!(MethodHandleKind::from_tag(kind.tag()), Some(kind));
+			assert_eq!(kind.to_string().parse::<MethodHandleKind>().unwrap(), kind);
+			assert_eq!(kind.to_string()[4..].to_ascii_lowercase().parse::<MethodHandleKind>().unwrap(), kind);
+		}
+		
+		assert_eq!(MethodHandleKind::InvokeVirtual.tag(), 5);
+		assert_eq!(MethodHandleKind::NewInvokeSpecial.to_string(), "REF_newInvokeSpecial");
+		assert_eq!("getstatic".parse::<MethodHandleKind>().unwrap(), MethodHandleKind::GetStatic);
+		assert!("REF_getstatic".parse::<MethodHandleKind>().is_err());
+		assert!("invoke".parse::<MethodHandleKind>().is_err());
+	}
 }
@@ -0,0 +1,337 @@
+//! Classes generated for the tests, covering constructs javac does not emit. Each case is either
+//! built with the crate's own writer or assembled by hand, so none of them need a JDK.
+
+use crate::access::{ClassAccessFlags, FieldAccessFlags, MethodAccessFlags};
+use crate::ast::*;
+use crate::attributes::*;
+use crate::classfile::{ClassFile, WriteOptions};
+use crate::code::CodeAttribute;
+use crate::error::{Result, ParserError};
+use crate::field::Field;
+use crate::method::Method;
+use crate::types::ClassName;
+use crate::version::{ClassVersion, MajorVersion};
+use std::collections::{BTreeMap, HashMap};
+
+/// A generated class and what it is expected to do
+pub(crate) struct Case {
+	pub name: &'static str,
+	pub bytes: Vec<u8>,
+	/// Whether every method with code passes [`CodeAttribute::type_check`]
+	pub type_checks: bool
+}
+
+/// Every case that parses and round trips
+pub(crate) fn cases() -> Result<Vec<Case>> {
+	Ok(vec![
+		Case { name: "maximal_written_pool", bytes: write(maximal_written_pool(false))?, type_checks: true },
+		Case { name: "maximal_raw_pool", bytes: maximal_raw_pool(), type_checks: true },
+		Case { name: "code_at_limit", bytes: write(code_at_limit(CodeAttribute::MAX_CODE_LENGTH))?, type_checks: true },
+		Case { name: "nested_switches", bytes: write(nested_switches(40))?, type_checks: true },
+		Case { name: "wide_locals", bytes: write(wide_locals())?, type_checks: true },
+		Case { name: "attribute_combinations", bytes: write(attribute_combinations())?, type_checks: true },
+		Case { name: "old_version", bytes: old_version(false), type_checks: true }
+	])
+}
+
+/// The unknown attributes generated hold no constant pool indices, so they can be written into a
+/// new pool
+pub(crate) const WRITE_OPTIONS: WriteOptions = WriteOptions {
+	sort_members: false,
+	sort_pool: false,
+	strip_debug: false,
+	allow_unknown_attributes: true,
+	target_version: None
+};
+
+fn write(class: ClassFile) -> Result<Vec<u8>> {
+	let mut bytes = Vec::new();
+	class.write_with(&mut bytes, &WRITE_OPTIONS)?;
+	Ok(bytes)
+}
+
+fn class(name: &str, version: MajorVersion) -> ClassFile {
+	ClassFile::new(ClassVersion::new_major(version), ClassAccessFlags::PUBLIC, ClassName::from(name), Some(ClassName::from("java/lang/Object")))
+}
+
+fn static_method(name: &str, descriptor: &str, code: CodeAttribute) -> Method {
+	let mut method = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from(name), String::from(descriptor));
+	method.set_code(Some(code));
+	method
+}
+
+/// Exercises the pool writer at its limit: the class, its super class, the shared descriptor and
+/// one name per field use exactly 65534 indices. With `overflow` there is one field too many,
+/// which the writer rejects with [`ParserError::ConstantPoolOverflow`].
+pub(crate) fn maximal_written_pool(overflow: bool) -> ClassFile {
+	let mut class = class("MaximalPool", MajorVersion::JAVA_8);
+	let fields = 0xFFFF - 1 - 5 + overflow as usize;
+	// pushed directly, add_field checks for duplicates one field at a time
+	class.fields = (0..fields)
+		.map(|i| Field::new(FieldAccessFlags::PUBLIC, format!("f{}", i), String::from("I")))
+		.collect();
+	class
+}
+
+/// Exercises parsing and writing back a full pool that the writer did not build, ending with a
+/// long whose second index is the last one allowed. Written with
+/// [`ConstantPoolWriter::from_pool`](crate::constantpool::ConstantPoolWriter::from_pool) the bytes
+/// come back unchanged.
+pub(crate) fn maximal_raw_pool() -> Vec<u8> {
+	let mut raw = RawClass::new(49, "MaximalRawPool");
+	while raw.count < 0xFFFF - 2 {
+		let filler = format!("unused{}", raw.count);
+		raw.utf8(&filler);
+	}
+	raw.constant(&[5, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], 2);
+	raw.bytes()
+}
+
+/// Exercises the code length check and the writer's jump sizing on a method of exactly `length`
+/// bytes, ending with a goto_w back over the whole method
+pub(crate) fn code_at_limit(length: usize) -> ClassFile {
+	let mut code = CodeAttribute::default();
+	let start = code.insns.new_label();
+	code.insns.push(Insn::Label(start));
+	for _ in 0..length - 5 {
+		code.insns.push(Insn::Nop(NopInsn::new()));
+	}
+	code.insns.push(Insn::Jump(JumpInsn::new(start)));
+	let mut class = class("CodeAtLimit", MajorVersion::JAVA_5);
+	class.methods.push(static_method("run", "()V", code));
+	class
+}
+
+/// Exercises switch parsing, padding and label resolution with `depth` table switches, each
+/// jumping into the next, around a lookup switch with keys at the extremes of the int range. The
+/// outermost table switch covers the top of the int range.
+pub(crate) fn nested_switches(depth: usize) -> ClassFile {
+	let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+	let end = code.insns.new_label();
+	for level in 0..depth {
+		let next = code.insns.new_label();
+		let low = if level == 0 { i32::MAX - 1 } else { -(level as i32) };
+		code.insns.load(OpType::Int, 0)
+			.push(Insn::TableSwitch(TableSwitchInsn::new(end, low, vec![next, end])))
+			// an odd number of bytes before the next switch moves its padding
+			.push(Insn::Label(next))
+			.push(Insn::Nop(NopInsn::new()));
+	}
+	let mut cases = BTreeMap::new();
+	for key in [i32::MIN, -1, 0, i32::MAX].iter() {
+		cases.insert(*key, end);
+	}
+	code.insns.load(OpType::Int, 0)
+		.push(Insn::LookupSwitch(LookupSwitchInsn { default: end, cases }))
+		.push(Insn::Label(end))
+		.return_void();
+	let mut class = class("NestedSwitches", MajorVersion::JAVA_5);
+	class.methods.push(static_method("run", "(I)V", code));
+	class
+}
+
+/// Exercises the wide prefix on loads, stores and iinc, which javac only emits in methods with
+/// more than 256 locals
+pub(crate) fn wide_locals() -> ClassFile {
+	let mut code = CodeAttribute { max_stack: 2, max_locals: 400, ..CodeAttribute::default() };
+	code.insns.ldc_int(5)
+		.store(OpType::Int, 300)
+		.push(Insn::IncrementInt(IncrementIntInsn::new(300, 1000)))
+		.push(Insn::IncrementInt(IncrementIntInsn::new(300, -1)))
+		.push(Insn::Ldc(LdcInsn::new(LdcType::Long(1))))
+		.store(OpType::Long, 398)
+		.load(OpType::Long, 398)
+		.push(Insn::Pop(PopInsn::new(true)))
+		.load(OpType::Int, 300)
+		.return_value(ReturnType::Int);
+	let mut class = class("WideLocals", MajorVersion::JAVA_5);
+	class.methods.push(static_method("run", "()I", code));
+	class
+}
+
+/// Exercises attribute parsing and writing at every level: unknown and empty attributes next to
+/// known ones, a field with both a constant value and a signature, an abstract method with only
+/// Exceptions and Signature, and a Code attribute with a nested unknown attribute and a handler
+/// catching anything
+pub(crate) fn attribute_combinations() -> ClassFile {
+	let mut class = class("Attributes", MajorVersion::JAVA_8);
+	class.access_flags |= ClassAccessFlags::ABSTRACT;
+	class.attributes.push(Attribute::SourceFile(SourceFileAttribute::new(String::from("Attributes.kt"))));
+	class.attributes.push(Attribute::SourceDebugExtension(SourceDebugExtensionAttribute::new(String::from("SMAP\nAttributes.kt\n"))));
+	class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Deprecated"), Vec::new())));
+	class.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("org.example.Empty"), Vec::new())));
+	
+	let mut field = Field::new(FieldAccessFlags::PUBLIC | FieldAccessFlags::STATIC | FieldAccessFlags::FINAL, String::from("LIMIT"), String::from("J"));
+	field.attributes.push(Attribute::ConstantValue(ConstantValueAttribute::new(ConstantValue::Long(i64::MIN))));
+	field.attributes.push(Attribute::Signature(SignatureAttribute::new(String::from("J"))));
+	field.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("Synthetic"), Vec::new())));
+	class.fields.push(field);
+	
+	let mut abstract_method = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::ABSTRACT, String::from("run"), String::from("()Ljava/util/List;"));
+	abstract_method.attributes.push(Attribute::Exceptions(ExceptionsAttribute::new(vec![String::from("java/io/IOException"), String::from("java/lang/Exception")])));
+	abstract_method.attributes.push(Attribute::Signature(SignatureAttribute::new(String::from("<T:Ljava/lang/Object;>()Ljava/util/List<TT;>;^Ljava/io/IOException;"))));
+	class.methods.push(abstract_method);
+	
+	let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+	let (start, end, handler) = (code.insns.new_label(), code.insns.new_label(), code.insns.new_label());
+	code.insns.push(Insn::Label(start))
+		.ldc_int(1)
+		.return_value(ReturnType::Int)
+		.push(Insn::Label(end))
+		.push(Insn::Label(handler))
+		.push(Insn::Throw(ThrowInsn::new()));
+	code.exceptions.push(crate::code::ExceptionHandler { start, end, handler, catch_type: None });
+	code.attributes.push(Attribute::Unknown(UnknownAttribute::new(String::from("org.example.CodeNote"), vec![1, 2, 3])));
+	class.methods.push(static_method("one", "()I", code));
+	class
+}
+
+/// A JDK 1.2 class, parsed without a StackMapTable. With `subroutine` a second method calls a
+/// subroutine with jsr and returns from it with ret, which this crate does not model: it is
+/// rejected with [`ParserError::UnknownInstruction`], or kept undecoded with
+/// [`ParseOptions::lenient_code`](crate::classfile::ParseOptions::lenient_code).
+pub(crate) fn old_version(subroutine: bool) -> Vec<u8> {
+	let mut raw = RawClass::new(46, "Old");
+	// return
+	raw.method("plain", "()V", 0, 0, &[0xB1]);
+	if subroutine {
+		// jsr +4, return, astore_0, ret 0
+		raw.method("subroutine", "()V", 1, 1, &[0xA8, 0x00, 0x04, 0xB1, 0x4B, 0xA9, 0x00]);
+	}
+	raw.bytes()
+}
+
+/// Assembles a class by hand, extending Object with no fields and only static methods
+struct RawClass {
+	major: u16,
+	pool: Vec<u8>,
+	/// The constant pool count, one more than the last index used
+	count: u16,
+	this_class: u16,
+	super_class: u16,
+	code: u16,
+	methods: Vec<Vec<u8>>,
+	utf8s: HashMap<String, u16>
+}
+
+impl RawClass {
+	fn new(major: u16, name: &str) -> Self {
+		let mut raw = RawClass { major, pool: Vec::new(), count: 1, this_class: 0, super_class: 0, code: 0, methods: Vec::new(), utf8s: HashMap::new() };
+		raw.this_class = raw.class(name);
+		raw.super_class = raw.class("java/lang/Object");
+		raw.code = raw.utf8("Code");
+		raw
+	}
+	
+	fn constant(&mut self, bytes: &[u8], slots: u16) -> u16 {
+		self.pool.extend_from_slice(bytes);
+		self.count += slots;
+		self.count - slots
+	}
+	
+	/// Reuses an existing entry, as the writer would
+	fn utf8(&mut self, value: &str) -> u16 {
+		if let Some(index) = self.utf8s.get(value) {
+			return *index;
+		}
+		let mut bytes = vec![1];
+		bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+		bytes.extend_from_slice(value.as_bytes());
+		let index = self.constant(&bytes, 1);
+		self.utf8s.insert(String::from(value), index);
+		index
+	}
+	
+	fn class(&mut self, name: &str) -> u16 {
+		let name = self.utf8(name);
+		self.constant(&[7, (name >> 8) as u8, name as u8], 1)
+	}
+	
+	fn method(&mut self, name: &str, descriptor: &str, max_stack: u16, max_locals: u16, code: &[u8]) {
+		let name = self.utf8(name);
+		let descriptor = self.utf8(descriptor);
+		let mut method = Vec::new();
+		// public static, one attribute
+		for x in [0x0009, name, descriptor, 1, self.code].iter() {
+			method.extend_from_slice(&x.to_be_bytes());
+		}
+		method.extend_from_slice(&(12 + code.len() as u32).to_be_bytes());
+		method.extend_from_slice(&max_stack.to_be_bytes());
+		method.extend_from_slice(&max_locals.to_be_bytes());
+		method.extend_from_slice(&(code.len() as u32).to_be_bytes());
+		method.extend_from_slice(code);
+		// no exception handlers or attributes
+		method.extend_from_slice(&[0, 0, 0, 0]);
+		self.methods.push(method);
+	}
+	
+	fn bytes(&self) -> Vec<u8> {
+		let mut bytes = vec![0xCA, 0xFE, 0xBA, 0xBE, 0, 0];
+		bytes.extend_from_slice(&self.major.to_be_bytes());
+		bytes.extend_from_slice(&self.count.to_be_bytes());
+		bytes.extend_from_slice(&self.pool);
+		// public, no interfaces or fields
+		for x in [0x0001, self.this_class, self.super_class, 0, 0, self.methods.len() as u16].iter() {
+			bytes.extend_from_slice(&x.to_be_bytes());
+		}
+		for method in self.methods.iter() {
+			bytes.extend_from_slice(method);
+		}
+		bytes.extend_from_slice(&[0, 0]);
+		bytes
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::classfile::ParseOptions;
+	use crate::constantpool::ConstantPoolWriter;
+	use std::io::Cursor;
+	
+	#[test]
+	fn synthetic_corpus_round_trips() -> Result<()> {
+		for case in cases()? {
+			let (class, pool) = ClassFile::parse_with_pool(&mut Cursor::new(&case.bytes), &ParseOptions::default())
+				.unwrap_or_else(|err| panic!("{}: {}", case.name, err));
+			class.validate().unwrap_or_else(|err| panic!("{}: {}", case.name, err));
+			if case.type_checks {
+				for method in class.methods.iter() {
+					if let Some(Attribute::Code(code)) = method.attributes.iter().find(|x| matches!(x, Attribute::Code(_))) {
+						code.type_check(&method.descriptor, method.access_flags.is_static())
+							.unwrap_or_else(|err| panic!("{}: {}{}: {}", case.name, method.name, method.descriptor, err));
+					}
+				}
+			}
+			
+			// the original pool gives back the same bytes
+			let mut same = Vec::new();
+			class.write_with_pool(&mut same, ConstantPoolWriter::from_pool(&pool))?;
+			assert!(same == case.bytes, "{} changed when written with its own pool", case.name);
+			// a new pool gives the same class
+			let mut written = Vec::new();
+			class.write_with(&mut written, &WRITE_OPTIONS)?;
+			let reparsed = ClassFile::parse(&mut Cursor::new(&written))?;
+			assert!(reparsed == class, "{} changed when written with a new pool", case.name);
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn synthetic_corpus_limits_are_enforced() {
+		let err = write(maximal_written_pool(true)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::ConstantPoolOverflow { needed: 0x10000 }), "{}", err);
+		let err = write(code_at_limit(CodeAttribute::MAX_CODE_LENGTH + 1)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::MethodTooLarge { size: 0x10000 }), "{}", err);
+		
+		let bytes = old_version(true);
+		let err = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::UnknownInstruction { opcode: 0xA8 }), "{}", err);
+		let options = ParseOptions { lenient_code: true, ..ParseOptions::default() };
+		let (class, pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options).unwrap();
+		assert!(matches!(class.methods[1].attributes[..], [Attribute::UndecodedCode(_)]));
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&pool)).unwrap();
+		assert_eq!(out, bytes);
+	}
+}
@@ -0,0 +1,565 @@
+use crate::classfile::ClassFile;
+use crate::attributes::Attribute;
+use crate::code::CodeAttribute;
+use crate::ast::{Insn, LabelInsn};
+use crate::path::{MemberKind, MemberPath, InsnPath};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+/// Above this many instruction pairs left after removing the common start and end, a changed
+/// method reports all of them as removed and added instead of finding the longest common sequence
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// Whether something is only in the second class, only in the first, or in both but different
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+	Added,
+	Removed,
+	Changed
+}
+
+impl ChangeKind {
+	fn sign(&self) -> char {
+		match self {
+			ChangeKind::Added => '+',
+			ChangeKind::Removed => '-',
+			ChangeKind::Changed => '~'
+		}
+	}
+}
+
+/// A value that differs between the two classes, rendered for display
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueDiff {
+	pub what: &'static str,
+	pub before: String,
+	pub after: String
+}
+
+/// The attributes of one name, which may appear more than once, differ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeDiff {
+	pub name: String,
+	pub kind: ChangeKind
+}
+
+/// One step of turning the instructions of the first method into those of the second
+#[derive(Clone, Debug, PartialEq)]
+pub enum InsnDiff {
+	/// The instruction at this index of the first method is not in the second
+	Removed {
+		index: usize,
+		insn: Insn
+	},
+	/// The instruction at this index of the second method is not in the first
+	Added {
+		index: usize,
+		insn: Insn
+	}
+}
+
+/// A field or method, matched by name and descriptor, that differs between the two classes
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemberDiff {
+	pub member: MemberKind,
+	pub name: String,
+	pub descriptor: String,
+	pub kind: ChangeKind,
+	/// The access flags and, for methods, the max stack, max locals and exception table
+	pub values: Vec<ValueDiff>,
+	pub attributes: Vec<AttributeDiff>,
+	/// The attributes of the Code attribute
+	pub code_attributes: Vec<AttributeDiff>,
+	/// Labels are matched up between the two methods first, so inserting a label only reports
+	/// that label and not every label after it
+	pub insns: Vec<InsnDiff>
+}
+
+/// The structural differences between two classes, see [`diff`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClassDiff {
+	/// The version, access flags, name, super class and interfaces
+	pub header: Vec<ValueDiff>,
+	pub fields: Vec<MemberDiff>,
+	pub methods: Vec<MemberDiff>,
+	pub attributes: Vec<AttributeDiff>
+}
+
+impl MemberDiff {
+	pub fn path(&self) -> MemberPath {
+		MemberPath { kind: self.member, name: self.name.clone(), descriptor: self.descriptor.clone() }
+	}
+	
+	/// Where a changed instruction is: removed instructions are in the first class and added ones
+	/// in the second
+	pub fn insn_path(&self, insn: &InsnDiff) -> InsnPath {
+		match insn {
+			InsnDiff::Removed { index, .. } | InsnDiff::Added { index, .. } => self.path().insn(*index)
+		}
+	}
+}
+
+impl ClassDiff {
+	pub fn is_empty(&self) -> bool {
+		self.header.is_empty() && self.fields.is_empty() && self.methods.is_empty() && self.attributes.is_empty()
+	}
+	
+	pub fn field(&self, name: &str, descriptor: &str) -> Option<&MemberDiff> {
+		self.fields.iter().find(|x| x.name == name && x.descriptor == descriptor)
+	}
+	
+	pub fn method(&self, name: &str, descriptor: &str) -> Option<&MemberDiff> {
+		self.methods.iter().find(|x| x.name == name && x.descriptor == descriptor)
+	}
+	
+	/// Whether the field was added, removed or changed
+	pub fn field_changed(&self, name: &str, descriptor: &str) -> bool {
+		self.field(name, descriptor).is_some()
+	}
+	
+	/// Whether the method was added, removed or changed
+	pub fn method_changed(&self, name: &str, descriptor: &str) -> bool {
+		self.method(name, descriptor).is_some()
+	}
+	
+	/// Whether a class attribute of this name was added, removed or changed
+	pub fn attribute_changed(&self, name: &str) -> bool {
+		self.attributes.iter().any(|x| x.name == name)
+	}
+}
+
+impl Display for ClassDiff {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		if self.is_empty() {
+			return writeln!(f, "No differences");
+		}
+		for value in self.header.iter() {
+			writeln!(f, "{}: {} -> {}", value.what, value.before, value.after)?;
+		}
+		for (kind, members) in [("field", &self.fields), ("method", &self.methods)].iter() {
+			for member in members.iter() {
+				writeln!(f, "{} {} {} {}", member.kind.sign(), kind, member.name, member.descriptor)?;
+				for value in member.values.iter() {
+					writeln!(f, "    {}: {} -> {}", value.what, value.before, value.after)?;
+				}
+				for attribute in member.attributes.iter() {
+					writeln!(f, "    {} attribute {}", attribute.kind.sign(), attribute.name)?;
+				}
+				for attribute in member.code_attributes.iter() {
+					writeln!(f, "    {} Code attribute {}", attribute.kind.sign(), attribute.name)?;
+				}
+				for insn in member.insns.iter() {
+					match insn {
+						InsnDiff::Removed { index, insn } => writeln!(f, "    - {}: {}", index, insn)?,
+						InsnDiff::Added { index, insn } => writeln!(f, "    + {}: {}", index, insn)?
+					}
+				}
+			}
+		}
+		for attribute in self.attributes.iter() {
+			writeln!(f, "{} attribute {}", attribute.kind.sign(), attribute.name)?;
+		}
+		Ok(())
+	}
+}
+
+/// Compares two classes member by member. Fields and methods are matched by name and descriptor
+/// and attributes by name.
+pub fn diff(a: &ClassFile, b: &ClassFile) -> ClassDiff {
+	let mut header = Vec::new();
+	let version = |class: &ClassFile| format!("{}.{}", u16::from(class.version.major), class.version.minor);
+	let super_class = |class: &ClassFile| class.super_class.as_ref().map_or_else(|| String::from("none"), |x| x.to_string());
+	let interfaces = |class: &ClassFile| class.interfaces.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(", ");
+	push_value(&mut header, "version", version(a), version(b));
+	push_value(&mut header, "access_flags", format!("{:?}", a.access_flags), format!("{:?}", b.access_flags));
+	push_value(&mut header, "this_class", a.this_class.to_string(), b.this_class.to_string());
+	push_value(&mut header, "super_class", super_class(a), super_class(b));
+	push_value(&mut header, "interfaces", interfaces(a), interfaces(b));
+	
+	let fields = diff_members(
+		MemberKind::Field,
+		&a.fields.iter().map(|x| (&x.name, &x.descriptor, format!("{:?}", x.access_flags), &x.attributes)).collect::<Vec<_>>(),
+		&b.fields.iter().map(|x| (&x.name, &x.descriptor, format!("{:?}", x.access_flags), &x.attributes)).collect::<Vec<_>>()
+	);
+	let methods = diff_members(
+		MemberKind::Method,
+		&a.methods.iter().map(|x| (&x.name, &x.descriptor, format!("{:?}", x.access_flags), &x.attributes)).collect::<Vec<_>>(),
+		&b.methods.iter().map(|x| (&x.name, &x.descriptor, format!("{:?}", x.access_flags), &x.attributes)).collect::<Vec<_>>()
+	);
+	
+	ClassDiff {
+		header,
+		fields,
+		methods,
+		attributes: diff_attributes(&a.attributes, &b.attributes)
+	}
+}
+
+fn push_value(values: &mut Vec<ValueDiff>, what: &'static str, before: String, after: String) {
+	if before != after {
+		values.push(ValueDiff { what, before, after });
+	}
+}
+
+/// A field or method as its name, descriptor, rendered access flags and attributes
+type Member<'a> = (&'a String, &'a String, String, &'a Vec<Attribute>);
+
+fn diff_members(kind: MemberKind, a: &[Member], b: &[Member]) -> Vec<MemberDiff> {
+	let find = |members: &[Member], (name, descriptor, _, _): &Member| -> Option<usize> {
+		members.iter().position(|(x, y, _, _)| x == name && y == descriptor)
+	};
+	let mut diffs = Vec::new();
+	for member in a.iter() {
+		let mut diff = MemberDiff {
+			member: kind,
+			name: member.0.clone(),
+			descriptor: member.1.clone(),
+			kind: ChangeKind::Removed,
+			values: Vec::new(),
+			attributes: Vec::new(),
+			code_attributes: Vec::new(),
+			insns: Vec::new()
+		};
+		if let Some(index) = find(b, member) {
+			let other = &b[index];
+			push_value(&mut diff.values, "access_flags", member.2.clone(), other.2.clone());
+			let (attributes, other_attributes) = (normalize_attributes(member.3), normalize_attributes(other.3));
+			diff.attributes = diff_attributes(&attributes, &other_attributes);
+			if let (Some(code), Some(other_code)) = (code(&attributes), code(&other_attributes)) {
+				diff_code(&mut diff, code, other_code);
+			}
+			if diff.values.is_empty() && diff.attributes.is_empty() {
+				continue;
+			}
+			diff.kind = ChangeKind::Changed;
+		}
+		diffs.push(diff);
+	}
+	for member in b.iter().filter(|member| find(a, member).is_none()) {
+		diffs.push(MemberDiff {
+			member: kind,
+			name: member.0.clone(),
+			descriptor: member.1.clone(),
+			kind: ChangeKind::Added,
+			values: Vec::new(),
+			attributes: Vec::new(),
+			code_attributes: Vec::new(),
+			insns: Vec::new()
+		});
+	}
+	diffs
+}
+
+fn code(attributes: &[Attribute]) -> Option<&CodeAttribute> {
+	attributes.iter().find_map(|x| match x {
+		Attribute::Code(code) => Some(code),
+		_ => None
+	})
+}
+
+fn diff_code(diff: &mut MemberDiff, a: &CodeAttribute, b: &CodeAttribute) {
+	let b = &align_labels(a, b);
+	push_value(&mut diff.values, "max_stack", a.max_stack.to_string(), b.max_stack.to_string());
+	push_value(&mut diff.values, "max_locals", a.max_locals.to_string(), b.max_locals.to_string());
+	push_value(&mut diff.values, "exceptions", format!("{:?}", a.exceptions), format!("{:?}", b.exceptions));
+	diff.code_attributes = diff_attributes(&a.attributes, &b.attributes);
+	diff.insns = diff_insns(&a.insns.insns, &b.insns.insns);
+}
+
+/// Attributes in the order their names first appear in either list
+fn diff_attributes(a: &[Attribute], b: &[Attribute]) -> Vec<AttributeDiff> {
+	let mut names: Vec<&str> = Vec::new();
+	for attribute in a.iter().chain(b.iter()) {
+		if !names.contains(&attribute.name()) {
+			names.push(attribute.name());
+		}
+	}
+	names.into_iter()
+		.filter_map(|name| {
+			let a: Vec<&Attribute> = a.iter().filter(|x| x.name() == name).collect();
+			let b: Vec<&Attribute> = b.iter().filter(|x| x.name() == name).collect();
+			let kind = if a.is_empty() {
+				ChangeKind::Added
+			} else if b.is_empty() {
+				ChangeKind::Removed
+			} else if a != b {
+				ChangeKind::Changed
+			} else {
+				return None;
+			};
+			Some(AttributeDiff { name: name.to_string(), kind })
+		})
+		.collect()
+}
+
+/// The attributes with labels inside Code attributes numbered in order of appearance
+fn normalize_attributes(attributes: &[Attribute]) -> Vec<Attribute> {
+	attributes.iter()
+		.map(|attribute| match attribute {
+			Attribute::Code(code) => Attribute::Code(normalize_code(code)),
+			x => x.clone()
+		})
+		.collect()
+}
+
+fn normalize_code(code: &CodeAttribute) -> CodeAttribute {
+	let order: HashMap<LabelInsn, LabelInsn> = code.insns.iter()
+		.filter_map(|insn| match insn {
+			Insn::Label(x) => Some(*x),
+			_ => None
+		})
+		.enumerate()
+		.map(|(i, label)| (label, LabelInsn::new(i as u32)))
+		.collect();
+	// labels that are never placed are numbered after the placed ones
+	relabel_code(code, |label| order.get(&label).copied().unwrap_or_else(|| LabelInsn::new(order.len() as u32 + label.id)))
+}
+
+/// `b` with its labels renamed to the labels of `a` they line up with. The instructions are
+/// aligned with every label treated as the same, and labels of `b` without a partner get ids no
+/// label of `a` has.
+fn align_labels(a: &CodeAttribute, b: &CodeAttribute) -> CodeAttribute {
+	let erase = |insns: &[Insn]| -> Vec<Insn> {
+		insns.iter()
+			.map(|insn| {
+				let mut insn = insn.clone();
+				insn.map_labels(|_| LabelInsn::new(0));
+				insn
+			})
+			.collect()
+	};
+	let mut partners: HashMap<LabelInsn, LabelInsn> = HashMap::new();
+	for (i, j) in common_insns(&erase(&a.insns.insns), &erase(&b.insns.insns)) {
+		if let (Insn::Label(x), Insn::Label(y)) = (&a.insns.insns[i], &b.insns.insns[j]) {
+			partners.insert(*y, *x);
+		}
+	}
+	
+	let mut fresh = 0;
+	for insn in a.insns.iter() {
+		insn.clone().map_labels(|label| {
+			fresh = fresh.max(label.id + 1);
+			label
+		});
+	}
+	for handler in a.exceptions.iter() {
+		fresh = fresh.max(handler.start.id + 1).max(handler.end.id + 1).max(handler.handler.id + 1);
+	}
+	relabel_code(b, |label| partners.get(&label).copied().unwrap_or_else(|| LabelInsn::new(fresh + label.id)))
+}
+
+fn relabel_code<F: Fn(LabelInsn) -> LabelInsn>(code: &CodeAttribute, map: F) -> CodeAttribute {
+	let mut code = code.clone();
+	code.insns.label_names = code.insns.label_names.iter()
+		.map(|(id, name)| (map(LabelInsn::new(*id)).id, name.clone()))
+		.collect();
+	for insn in code.insns.insns.iter_mut() {
+		insn.map_labels(&map);
+	}
+	for handler in code.exceptions.iter_mut() {
+		handler.start = map(handler.start);
+		handler.end = map(handler.end);
+		handler.handler = map(handler.handler);
+	}
+	for attribute in code.attributes.iter_mut() {
+		match attribute {
+			Attribute::LocalVariableTable(x) | Attribute::LocalVariableTypeTable(x) => {
+				for variable in x.variables.iter_mut() {
+					variable.start = map(variable.start);
+					variable.end = map(variable.end);
+				}
+			},
+			Attribute::LineNumberTable(x) => {
+				for line in x.lines.iter_mut() {
+					line.start = map(line.start);
+				}
+			},
+			_ => {}
+		}
+	}
+	code
+}
+
+/// The instructions to remove from `a` and add from `b` along a longest common subsequence
+fn diff_insns(a: &[Insn], b: &[Insn]) -> Vec<InsnDiff> {
+	let mut diffs = Vec::new();
+	let (mut i, mut j) = (0, 0);
+	for (next_i, next_j) in common_insns(a, b).into_iter().chain(std::iter::once((a.len(), b.len()))) {
+		diffs.extend((i..next_i).map(|index| InsnDiff::Removed { index, insn: a[index].clone() }));
+		diffs.extend((j..next_j).map(|index| InsnDiff::Added { index, insn: b[index].clone() }));
+		i = next_i + 1;
+		j = next_j + 1;
+	}
+	diffs
+}
+
+/// The index pairs of a longest common subsequence of `a` and `b`, in order
+fn common_insns(a: &[Insn], b: &[Insn]) -> Vec<(usize, usize)> {
+	let prefix = a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count();
+	let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+	let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+	let (n, m) = (a_mid.len(), b_mid.len());
+	
+	let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+	if (n + 1).saturating_mul(m + 1) <= MAX_LCS_CELLS {
+		// lengths[i][j] is the length of the longest common subsequence of a_mid[i..] and b_mid[j..]
+		let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+		let at = |i: usize, j: usize| i * (m + 1) + j;
+		for i in (0..n).rev() {
+			for j in (0..m).rev() {
+				lengths[at(i, j)] = if a_mid[i] == b_mid[j] {
+					lengths[at(i + 1, j + 1)] + 1
+				} else {
+					lengths[at(i + 1, j)].max(lengths[at(i, j + 1)])
+				};
+			}
+		}
+		
+		let (mut i, mut j) = (0, 0);
+		while i < n && j < m {
+			if a_mid[i] == b_mid[j] {
+				pairs.push((prefix + i, prefix + j));
+				i += 1;
+				j += 1;
+			} else if lengths[at(i + 1, j)] >= lengths[at(i, j + 1)] {
+				i += 1;
+			} else {
+				j += 1;
+			}
+		}
+	}
+	pairs.extend((0..suffix).map(|k| (prefix + n + k, prefix + m + k)));
+	pairs
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::attributes::SourceFileAttribute;
+	use crate::ast::{JumpInsn, LdcInsn, LdcType, NopInsn};
+	use crate::field::Field;
+	use crate::path::{Element, Path};
+	use crate::access::{FieldAccessFlags, MethodAccessFlags};
+	use std::io::Cursor;
+	
+	fn snapshot() -> ClassFile {
+		ClassFile::parse(&mut Cursor::new(&include_bytes!("../classes/snapshot/Snapshot.class")[..])).unwrap()
+	}
+	
+	#[test]
+	fn renumbered_labels_are_not_differences() {
+		let class = snapshot();
+		let mut renumbered = class.clone();
+		let map = |label: LabelInsn| LabelInsn::new(label.id + 100);
+		for method in renumbered.methods.iter_mut() {
+			if let Some(code) = method.code() {
+				for insn in code.insns.insns.iter_mut() {
+					insn.map_labels(map);
+				}
+				for handler in code.exceptions.iter_mut() {
+					handler.start = map(handler.start);
+					handler.end = map(handler.end);
+					handler.handler = map(handler.handler);
+				}
+			}
+		}
+		assert_ne!(class, renumbered);
+		let diff = diff(&class, &renumbered);
+		assert!(diff.is_empty(), "{}", diff);
+		assert_eq!(diff.to_string(), "No differences\n");
+	}
+	
+	#[test]
+	fn reports_edits() {
+		let before = snapshot();
+		let mut after = before.clone();
+		let main = after.method_mut("main", "([Ljava/lang/String;)V").unwrap();
+		let insns = &mut main.code().unwrap().insns.insns;
+		let hello = insns.iter().position(|x| matches!(x, Insn::Ldc(_))).unwrap();
+		let hello_insn = insns[hello].clone();
+		insns[hello] = Insn::Ldc(LdcInsn::new(LdcType::String(String::from("Bye"))));
+		let parse = after.method_mut("parse", "(Ljava/lang/String;)I").unwrap();
+		parse.access_flags = MethodAccessFlags::STATIC;
+		parse.code().unwrap().insns.insns.insert(0, Insn::Nop(NopInsn::new()));
+		after.remove_method("sum", "([I)I").unwrap();
+		after.add_field(Field::new(FieldAccessFlags::PRIVATE, String::from("name"), String::from("Ljava/lang/String;"))).unwrap();
+		after.attributes.push(Attribute::SourceFile(SourceFileAttribute::new(String::from("Snapshot.java"))));
+		
+		let diff = diff(&before, &after);
+		assert!(diff.header.is_empty());
+		assert_eq!(diff.fields.len(), 1);
+		assert_eq!(diff.field("name", "Ljava/lang/String;").unwrap().kind, ChangeKind::Added);
+		assert!(!diff.field_changed("count", "I"));
+		
+		assert_eq!(diff.methods.len(), 3);
+		assert!(!diff.method_changed("<init>", "()V"));
+		let main = diff.method("main", "([Ljava/lang/String;)V").unwrap();
+		assert_eq!(main.kind, ChangeKind::Changed);
+		assert_eq!(main.attributes, vec![AttributeDiff { name: String::from("Code"), kind: ChangeKind::Changed }]);
+		assert_eq!(main.insns, vec![
+			InsnDiff::Removed { index: hello, insn: hello_insn.clone() },
+			InsnDiff::Added { index: hello, insn: Insn::Ldc(LdcInsn::new(LdcType::String(String::from("Bye")))) }
+		]);
+		assert_eq!(main.path().to_string(), "method:main([Ljava/lang/String;)V");
+		let removed: Path = main.insn_path(&main.insns[0]).into();
+		assert_eq!(removed.to_string(), format!("method:main([Ljava/lang/String;)V/code/insn[{}]", hello));
+		assert_eq!(before.resolve(&removed), Some(Element::Insn(&hello_insn)));
+		assert!(matches!(after.resolve(&main.insn_path(&main.insns[1]).into()), Some(Element::Insn(Insn::Ldc(_)))));
+		assert_eq!(diff.method("sum", "([I)I").unwrap().kind, ChangeKind::Removed);
+		// the exception table and line numbers of parse point at the same labels, so only the nop shows
+		let parse = diff.method("parse", "(Ljava/lang/String;)I").unwrap();
+		assert_eq!(parse.values.len(), 1);
+		assert_eq!(parse.values[0].what, "access_flags");
+		assert!(parse.code_attributes.is_empty());
+		assert_eq!(parse.insns, vec![InsnDiff::Added { index: 0, insn: Insn::Nop(NopInsn::new()) }]);
+		
+		assert!(diff.attribute_changed("SourceFile"));
+		assert_eq!(diff.attributes, vec![AttributeDiff { name: String::from("SourceFile"), kind: ChangeKind::Added }]);
+		
+		let report = diff.to_string();
+		assert!(report.contains("+ field name Ljava/lang/String;\n"), "{}", report);
+		assert!(report.contains("~ method main ([Ljava/lang/String;)V\n"), "{}", report);
+		assert!(report.contains("- method sum ([I)I\n"), "{}", report);
+		assert!(report.contains("    + 0: nop\n"), "{}", report);
+		assert!(report.ends_with("+ attribute SourceFile\n"), "{}", report);
+	}
+	
+	fn code_of(insns: Vec<Insn>) -> CodeAttribute {
+		let mut code = CodeAttribute::empty();
+		code.insns.insns = insns;
+		code
+	}
+	
+	fn diff_of(a: Vec<Insn>, b: Vec<Insn>) -> Vec<InsnDiff> {
+		let a = code_of(a);
+		diff_insns(&a.insns.insns, &align_labels(&a, &code_of(b)).insns.insns)
+	}
+	
+	#[test]
+	fn moved_labels_are_reported() {
+		let a = vec![Insn::Label(LabelInsn::new(0)), Insn::Nop(NopInsn::new()), Insn::Label(LabelInsn::new(1))];
+		let b = vec![Insn::Nop(NopInsn::new()), Insn::Label(LabelInsn::new(0)), Insn::Label(LabelInsn::new(1))];
+		assert_eq!(diff_of(a, b), vec![
+			InsnDiff::Removed { index: 0, insn: Insn::Label(LabelInsn::new(0)) },
+			InsnDiff::Added { index: 1, insn: Insn::Label(LabelInsn::new(2)) }
+		]);
+	}
+	
+	#[test]
+	fn inserted_labels_do_not_renumber_later_ones() {
+		let label = |id| Insn::Label(LabelInsn::new(id));
+		let goto = |id| Insn::Jump(JumpInsn::new(LabelInsn::new(id)));
+		let a = vec![label(0), Insn::Nop(NopInsn::new()), label(1), goto(0), goto(1)];
+		// as parsed, the new label takes the id after label 0 and pushes label 1 to 2
+		let b = vec![label(0), label(1), Insn::Nop(NopInsn::new()), label(2), goto(0), goto(2)];
+		// and is renamed past the labels of the first method
+		assert_eq!(diff_of(a.clone(), b), vec![InsnDiff::Added { index: 1, insn: label(3) }]);
+		
+		// a jump moved to another label is still reported
+		let b = vec![label(0), Insn::Nop(NopInsn::new()), label(1), goto(1), goto(1)];
+		assert_eq!(diff_of(a, b), vec![
+			InsnDiff::Removed { index: 3, insn: goto(0) },
+			InsnDiff::Added { index: 3, insn: goto(1) }
+		]);
+	}
+}
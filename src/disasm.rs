@@ -0,0 +1,427 @@
+//! Human readable, javap-like rendering of classes and instructions
+
+use crate::ast::*;
+use crate::attributes::Attribute;
+use crate::classfile::ClassFile;
+use crate::code::CodeAttribute;
+use crate::types::Type;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter, Write};
+
+impl Display for Insn {
+	/// Labels are named by their raw id, use [`CodeAttribute::disassemble`] for names in order
+	/// of appearance
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write_insn(f, self, &|label| format!("L{}", label.id))
+	}
+}
+
+impl CodeAttribute {
+	/// Renders the instructions one per line, followed by the exception table. Labels are shown by
+	/// their [name](crate::insnlist::InsnList::label_name) if they have one, and otherwise named
+	/// `L0`, `L1`, ... in the order they appear.
+	pub fn disassemble(&self) -> String {
+		let name = self.label_names();
+		let mut out = format!("stack={}, locals={}\n", self.max_stack, self.max_locals);
+		for insn in self.insns.iter() {
+			if !matches!(insn, Insn::Label(_)) {
+				out.push_str("    ");
+			}
+			write_insn(&mut out, insn, &name).unwrap();
+			out.push('\n');
+		}
+		if !self.exceptions.is_empty() {
+			out.push_str("Exception table:\n");
+			for handler in self.exceptions.iter() {
+				writeln!(out, "    {} {} {} {}",
+					name(handler.start),
+					name(handler.end),
+					name(handler.handler),
+					handler.catch_type.as_deref().unwrap_or("any")
+				).unwrap();
+			}
+		}
+		out
+	}
+	
+	/// Names labels as [`disassemble`](Self::disassemble) does
+	pub(crate) fn label_names(&self) -> impl Fn(LabelInsn) -> String + '_ {
+		let names: HashMap<LabelInsn, usize> = self.insns.iter()
+			.filter_map(|insn| match insn {
+				Insn::Label(x) => Some(*x),
+				_ => None
+			})
+			.enumerate()
+			.map(|(index, label)| (label, index))
+			.collect();
+		move |label| match (self.insns.label_name(label), names.get(&label)) {
+			(Some(name), _) => name.to_string(),
+			(None, Some(index)) => format!("L{}", index),
+			// not placed in the list
+			(None, None) => format!("L?{}", label.id)
+		}
+	}
+}
+
+impl ClassFile {
+	/// Renders the class declaration and its members, without any code
+	pub fn summary(&self) -> String {
+		let mut out = keywords(self.access_flags.to_java_modifiers());
+		let flags = self.access_flags;
+		out.push_str(if flags.is_annotation() {
+			"@interface"
+		} else if flags.is_interface() {
+			"interface"
+		} else if flags.is_enum() {
+			"enum"
+		} else {
+			"class"
+		});
+		write!(out, " {}", self.this_class).unwrap();
+		if let Some(super_class) = &self.super_class {
+			write!(out, " extends {}", super_class).unwrap();
+		}
+		if !self.interfaces.is_empty() {
+			write!(out, " implements {}", self.interfaces.join(", ")).unwrap();
+		}
+		out.push_str(" {\n");
+		
+		for field in self.fields.iter() {
+			let keywords = keywords(field.access_flags.to_java_modifiers());
+			writeln!(out, "  {}{} {}", keywords, field.name, field.descriptor).unwrap();
+		}
+		for method in self.methods.iter() {
+			let keywords = keywords(method.access_flags.to_java_modifiers());
+			writeln!(out, "  {}{} {}", keywords, method.name, method.descriptor).unwrap();
+		}
+		out.push('}');
+		out
+	}
+	
+	/// The [`summary`](Self::summary) followed by the disassembly of every method with code
+	pub fn disassemble(&self) -> String {
+		let mut out = self.summary();
+		for method in self.methods.iter() {
+			for attr in method.attributes.iter() {
+				if let Attribute::Code(code) = attr {
+					write!(out, "\n\n{} {}:\n", method.name, method.descriptor).unwrap();
+					for line in code.disassemble().lines() {
+						writeln!(out, "  {}", line).unwrap();
+					}
+					out.pop();
+				}
+			}
+		}
+		out
+	}
+}
+
+/// The modifiers followed by a space, if there are any
+fn keywords(mut modifiers: String) -> String {
+	if !modifiers.is_empty() {
+		modifiers.push(' ');
+	}
+	modifiers
+}
+
+fn op_prefix(kind: OpType) -> &'static str {
+	match kind {
+		OpType::Reference => "a",
+		OpType::Boolean | OpType::Byte | OpType::Char | OpType::Short | OpType::Int => "i",
+		OpType::Long => "l",
+		OpType::Float => "f",
+		OpType::Double => "d"
+	}
+}
+
+fn primitive_prefix(kind: PrimitiveType) -> &'static str {
+	match kind {
+		PrimitiveType::Boolean | PrimitiveType::Byte | PrimitiveType::Char | PrimitiveType::Short | PrimitiveType::Int => "i",
+		PrimitiveType::Long => "l",
+		PrimitiveType::Float => "f",
+		PrimitiveType::Double => "d"
+	}
+}
+
+fn integer_prefix(kind: IntegerType) -> &'static str {
+	match kind {
+		IntegerType::Int => "i",
+		IntegerType::Long => "l"
+	}
+}
+
+/// The prefix of the array load and store instructions
+fn array_prefix(kind: &Type) -> &'static str {
+	match kind {
+		Type::Reference(_) | Type::Array(_) => "a",
+		Type::Boolean | Type::Byte => "b",
+		Type::Char => "c",
+		Type::Short => "s",
+		Type::Int => "i",
+		Type::Long => "l",
+		Type::Float => "f",
+		Type::Double => "d",
+		Type::Void => "v"
+	}
+}
+
+fn primitive_name(kind: &Type) -> &'static str {
+	match kind {
+		Type::Reference(_) | Type::Array(_) => "java/lang/Object",
+		Type::Boolean => "boolean",
+		Type::Byte => "byte",
+		Type::Char => "char",
+		Type::Short => "short",
+		Type::Int => "int",
+		Type::Long => "long",
+		Type::Float => "float",
+		Type::Double => "double",
+		Type::Void => "void"
+	}
+}
+
+fn write_ldc<W: Write>(f: &mut W, constant: &LdcType) -> fmt::Result {
+	match constant {
+		LdcType::Null => write!(f, "aconst_null"),
+		LdcType::String(x) => write!(f, "ldc {:?}", x),
+		LdcType::Int(-1) => write!(f, "iconst_m1"),
+		LdcType::Int(x) if (0..=5).contains(x) => write!(f, "iconst_{}", x),
+		LdcType::Int(x) if *x as i8 as i32 == *x => write!(f, "bipush {}", x),
+		LdcType::Int(x) if *x as i16 as i32 == *x => write!(f, "sipush {}", x),
+		LdcType::Int(x) => write!(f, "ldc {}", x),
+		LdcType::Float(x) if *x == 0.0 && x.is_sign_positive() || *x == 1.0 || *x == 2.0 => write!(f, "fconst_{}", *x as i32),
+		LdcType::Float(x) => write!(f, "ldc {:?}f", x),
+		LdcType::Long(x) if *x == 0 || *x == 1 => write!(f, "lconst_{}", x),
+		LdcType::Long(x) => write!(f, "ldc2_w {}l", x),
+		LdcType::Double(x) if *x == 0.0 && x.is_sign_positive() || *x == 1.0 => write!(f, "dconst_{}", *x as i32),
+		LdcType::Double(x) => write!(f, "ldc2_w {:?}d", x),
+		LdcType::Class(x) => write!(f, "ldc class {}", x),
+		LdcType::MethodType(x) => write!(f, "ldc methodtype {}", x),
+		LdcType::MethodHandle(x) => write!(f, "ldc methodhandle {:?} {}.{}:{}", x.kind, x.class, x.name, x.descriptor),
+		LdcType::Dynamic(x) => write!(f, "ldc dynamic {}:{} {}.{}", x.name, x.descriptor, x.bootstrap_method.class, x.bootstrap_method.name)
+	}
+}
+
+pub(crate) fn write_insn<W: Write>(f: &mut W, insn: &Insn, label: &dyn Fn(LabelInsn) -> String) -> fmt::Result {
+	match insn {
+		Insn::Label(x) => write!(f, "{}:", label(*x)),
+		Insn::ArrayLoad(x) => write!(f, "{}aload", array_prefix(&x.kind)),
+		Insn::ArrayStore(x) => write!(f, "{}astore", array_prefix(&x.kind)),
+		Insn::Ldc(x) => write_ldc(f, &x.constant),
+		Insn::LocalLoad(x) if x.index <= 3 => write!(f, "{}load_{}", op_prefix(x.kind), x.index),
+		Insn::LocalLoad(x) => write!(f, "{}load {}", op_prefix(x.kind), x.index),
+		Insn::LocalStore(x) if x.index <= 3 => write!(f, "{}store_{}", op_prefix(x.kind), x.index),
+		Insn::LocalStore(x) => write!(f, "{}store {}", op_prefix(x.kind), x.index),
+		Insn::NewArray(x) => match &x.kind {
+			Type::Reference(class) => write!(f, "anewarray {}", class.as_deref().unwrap_or("java/lang/Object")),
+			Type::Array(component) => write!(f, "anewarray [{}", component.descriptor()),
+			kind => write!(f, "newarray {}", primitive_name(kind))
+		},
+		Insn::Return(x) => match x.kind {
+			ReturnType::Void => write!(f, "return"),
+			ReturnType::Reference => write!(f, "areturn"),
+			ReturnType::Long => write!(f, "lreturn"),
+			ReturnType::Float => write!(f, "freturn"),
+			ReturnType::Double => write!(f, "dreturn"),
+			_ => write!(f, "ireturn")
+		},
+		Insn::ArrayLength(_) => write!(f, "arraylength"),
+		Insn::Throw(_) => write!(f, "athrow"),
+		Insn::CheckCast(x) => write!(f, "checkcast {}", x.kind),
+		Insn::Convert(x) => {
+			let from = primitive_prefix(x.from);
+			let to = match x.to {
+				PrimitiveType::Boolean | PrimitiveType::Byte if from == "i" => "b",
+				PrimitiveType::Char if from == "i" => "c",
+				PrimitiveType::Short if from == "i" => "s",
+				to => primitive_prefix(to)
+			};
+			// a conversion to the same kind has no opcode, and is written as a nop
+			if from == to {
+				write!(f, "nop")
+			} else {
+				write!(f, "{}2{}", from, to)
+			}
+		},
+		Insn::Add(x) => write!(f, "{}add", primitive_prefix(x.kind)),
+		Insn::Compare(x) => match x.kind {
+			PrimitiveType::Long => write!(f, "lcmp"),
+			PrimitiveType::Float | PrimitiveType::Double => {
+				write!(f, "{}cmp{}", primitive_prefix(x.kind), if x.pos_on_nan { "g" } else { "l" })
+			},
+			// there is no int comparison opcode, so it is written as a widening then a long comparison
+			_ => write!(f, "i2l; lcmp")
+		},
+		Insn::Divide(x) => write!(f, "{}div", primitive_prefix(x.kind)),
+		Insn::Multiply(x) => write!(f, "{}mul", primitive_prefix(x.kind)),
+		Insn::Negate(x) => write!(f, "{}neg", primitive_prefix(x.kind)),
+		Insn::Remainder(x) => write!(f, "{}rem", primitive_prefix(x.kind)),
+		Insn::Subtract(x) => write!(f, "{}sub", primitive_prefix(x.kind)),
+		Insn::And(x) => write!(f, "{}and", integer_prefix(x.kind)),
+		Insn::Or(x) => write!(f, "{}or", integer_prefix(x.kind)),
+		Insn::Xor(x) => write!(f, "{}xor", integer_prefix(x.kind)),
+		Insn::ShiftLeft(x) => write!(f, "{}shl", integer_prefix(x.kind)),
+		Insn::ShiftRight(x) => write!(f, "{}shr", integer_prefix(x.kind)),
+		Insn::LogicalShiftRight(x) => write!(f, "{}ushr", integer_prefix(x.kind)),
+		Insn::Dup(x) => {
+			write!(f, "dup")?;
+			if x.num != 1 {
+				write!(f, "{}", x.num)?;
+			}
+			if x.down != 0 {
+				write!(f, "_x{}", x.down)?;
+			}
+			Ok(())
+		},
+		Insn::Pop(x) => write!(f, "{}", if x.pop_two { "pop2" } else { "pop" }),
+		Insn::GetField(x) => {
+			write!(f, "{} {}.{} {}", if x.instance { "getfield" } else { "getstatic" }, x.class, x.name, x.descriptor)
+		},
+		Insn::PutField(x) => {
+			write!(f, "{} {}.{} {}", if x.instance { "putfield" } else { "putstatic" }, x.class, x.name, x.descriptor)
+		},
+		Insn::Jump(x) => write!(f, "goto {}", label(x.jump_to)),
+		Insn::ConditionalJump(x) => write!(f, "{} {}", x.condition, label(x.jump_to)),
+		Insn::IncrementInt(x) => write!(f, "iinc {}, {}", x.index, x.amount),
+		Insn::InstanceOf(x) => write!(f, "instanceof {}", x.class),
+		Insn::InvokeDynamic(x) => {
+			write!(f, "invokedynamic {} {} // {}.{} {}", x.name, x.descriptor, x.bootstrap_class, x.bootstrap_method, x.bootstrap_descriptor)?;
+			if !x.bootstrap_arguments.is_empty() {
+				write!(f, " {:?}", x.bootstrap_arguments)?;
+			}
+			Ok(())
+		},
+		Insn::Invoke(x) => {
+			write!(f, "{} {}.{} {}", x.kind, x.class, x.name, x.descriptor)
+		},
+		Insn::LookupSwitch(x) => {
+			write!(f, "lookupswitch {{")?;
+			for (key, target) in x.cases.iter() {
+				write!(f, " {}: {},", key, label(*target))?;
+			}
+			write!(f, " default: {} }}", label(x.default))
+		},
+		Insn::TableSwitch(x) => {
+			write!(f, "tableswitch {{")?;
+			for (index, target) in x.cases.iter().enumerate() {
+				write!(f, " {}: {},", x.low.wrapping_add(index as i32), label(*target))?;
+			}
+			write!(f, " default: {} }}", label(x.default))
+		},
+		Insn::MonitorEnter(_) => write!(f, "monitorenter"),
+		Insn::MonitorExit(_) => write!(f, "monitorexit"),
+		Insn::MultiNewArray(x) => write!(f, "multianewarray {} {}", x.kind, x.dimensions),
+		Insn::NewObject(x) => write!(f, "new {}", x.kind),
+		Insn::Nop(_) => write!(f, "nop"),
+		Insn::Swap(_) => write!(f, "swap"),
+		Insn::ImpDep1(_) => write!(f, "impdep1"),
+		Insn::ImpDep2(_) => write!(f, "impdep2"),
+		Insn::BreakPoint(_) => write!(f, "breakpoint")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::types::ClassName;
+	use std::io::Cursor;
+	
+	/// `classes/snapshot/Snapshot.java` compiled with `javac --release 8 -g:none`
+	const SNAPSHOT: &[u8] = include_bytes!("../classes/snapshot/Snapshot.class");
+	
+	fn snapshot() -> ClassFile {
+		ClassFile::parse(&mut Cursor::new(SNAPSHOT)).unwrap()
+	}
+	
+	fn code<'a>(class: &'a ClassFile, name: &str) -> &'a CodeAttribute {
+		let method = class.methods.iter().find(|method| method.name == name).unwrap();
+		method.code_ref().unwrap()
+	}
+	
+	#[test]
+	fn insn_display() {
+		let invoke = Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, ClassName::from("java/io/PrintStream"), String::from("println"), String::from("(Ljava/lang/String;)V"), false)));
+		assert_eq!(invoke.to_string(), "invokevirtual java/io/PrintStream.println (Ljava/lang/String;)V");
+		assert_eq!(Insn::LocalLoad(LocalLoadInsn::new(OpType::Long, 4)).to_string(), "lload 4");
+		assert_eq!(Insn::Ldc(LdcInsn::new(LdcType::Int(200))).to_string(), "sipush 200");
+		assert_eq!(Insn::Ldc(LdcInsn::new(LdcType::Double(2.5))).to_string(), "ldc2_w 2.5d");
+		assert_eq!(Insn::Convert(ConvertInsn::new(PrimitiveType::Int, PrimitiveType::Char)).to_string(), "i2c");
+		assert_eq!(Insn::Convert(ConvertInsn::new(PrimitiveType::Double, PrimitiveType::Byte)).to_string(), "d2i");
+		assert_eq!(Insn::Convert(ConvertInsn::new(PrimitiveType::Long, PrimitiveType::Long)).to_string(), "nop");
+		assert_eq!(Insn::Compare(CompareInsn::new(PrimitiveType::Int, false)).to_string(), "i2l; lcmp");
+		assert_eq!(Insn::Compare(CompareInsn::new(PrimitiveType::Long, false)).to_string(), "lcmp");
+		assert_eq!(Insn::Compare(CompareInsn::new(PrimitiveType::Float, true)).to_string(), "fcmpg");
+		assert_eq!(Insn::Dup(DupInsn::new(2, 1)).to_string(), "dup2_x1");
+		assert_eq!(Insn::Jump(JumpInsn::new(LabelInsn::new(7))).to_string(), "goto L7");
+		let mut switch = LookupSwitchInsn::new(LabelInsn::new(0));
+		switch.cases.insert(-3, LabelInsn::new(1));
+		assert_eq!(Insn::LookupSwitch(switch).to_string(), "lookupswitch { -3: L1, default: L0 }");
+	}
+	
+	#[test]
+	fn summary_snapshot() {
+		assert_eq!(snapshot().summary(), "\
+public class Snapshot extends java/lang/Object {
+  private static count I
+  public <init> ()V
+  public static main ([Ljava/lang/String;)V
+  sum ([I)I
+  static synchronized parse (Ljava/lang/String;)I
+}");
+	}
+	
+	#[test]
+	fn disassemble_snapshot() {
+		let class = snapshot();
+		assert_eq!(code(&class, "main").disassemble(), "\
+stack=2, locals=1
+    getstatic java/lang/System.out Ljava/io/PrintStream;
+    ldc \"Hello\"
+    invokevirtual java/io/PrintStream.println (Ljava/lang/String;)V
+    return
+");
+		assert_eq!(code(&class, "sum").disassemble(), "\
+stack=2, locals=7
+    iconst_0
+    istore_2
+    aload_1
+    astore_3
+    aload_3
+    arraylength
+    istore 4
+    iconst_0
+    istore 5
+L0:
+    iload 5
+    iload 4
+    if_icmpge L1
+    aload_3
+    iload 5
+    iaload
+    istore 6
+    iload_2
+    iload 6
+    iadd
+    istore_2
+    iinc 5, 1
+    goto L0
+L1:
+    iload_2
+    ireturn
+");
+		assert_eq!(code(&class, "parse").disassemble(), "\
+stack=1, locals=2
+L0:
+    aload_0
+    invokestatic java/lang/Integer.parseInt (Ljava/lang/String;)I
+L1:
+    ireturn
+L2:
+    astore_1
+    iconst_m1
+    ireturn
+Exception table:
+    L0 L1 L2 java/lang/NumberFormatException
+");
+	}
+}
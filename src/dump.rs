@@ -0,0 +1,211 @@
+//! A stable, flattened view of a class for scripts, serialized as JSON or YAML by the `dump`
+//! subcommand. Unlike the derived serde representation, this only changes together with
+//! [`SCHEMA_VERSION`].
+
+use crate::ast::{Insn, LabelInsn};
+use crate::attributes::Attribute;
+use crate::classfile::ClassFile;
+use crate::code::CodeAttribute;
+use serde::Serialize;
+
+/// Bumped whenever a field is renamed, removed or changes meaning. Adding a field does not bump it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ClassDump {
+	pub schema_version: u32,
+	pub name: String,
+	pub super_class: Option<String>,
+	pub interfaces: Vec<String>,
+	pub version: VersionDump,
+	/// The raw access flags
+	pub access_flags: u16,
+	/// The Java source modifiers, e.g. `["public", "final"]`
+	pub modifiers: Vec<String>,
+	/// Attribute names, in class file order
+	pub attributes: Vec<String>,
+	pub fields: Vec<MemberDump>,
+	pub methods: Vec<MemberDump>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VersionDump {
+	pub major: u16,
+	pub minor: u16
+}
+
+/// A field or method
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MemberDump {
+	pub name: String,
+	pub descriptor: String,
+	pub access_flags: u16,
+	pub modifiers: Vec<String>,
+	pub attributes: Vec<String>,
+	/// Only for methods with a Code attribute
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub code: Option<CodeDump>
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CodeDump {
+	pub max_stack: u16,
+	pub max_locals: u16,
+	pub insns: Vec<InsnDump>,
+	pub exceptions: Vec<HandlerDump>
+}
+
+/// Either a label, named `L0`, `L1`, ... in order of appearance, or an instruction. Fields that
+/// do not apply are left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct InsnDump {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub label: Option<String>,
+	/// The mnemonic, as shown by javap
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub op: Option<String>,
+	/// The rest of the disassembled instruction
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub operands: Option<String>,
+	/// Labels this instruction may jump to
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub targets: Vec<String>,
+	/// The field or method of field and invoke instructions
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub member: Option<MemberRefDump>,
+	/// The class of `new`, `checkcast` and `instanceof`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub class: Option<String>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MemberRefDump {
+	pub owner: String,
+	pub name: String,
+	pub descriptor: String
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HandlerDump {
+	pub start: String,
+	pub end: String,
+	pub handler: String,
+	/// None to catch any exception
+	pub catch_type: Option<String>
+}
+
+impl ClassFile {
+	/// The [`ClassDump`] of this class
+	pub fn dump(&self) -> ClassDump {
+		ClassDump {
+			schema_version: SCHEMA_VERSION,
+			name: self.this_class.to_string(),
+			super_class: self.super_class.as_ref().map(|x| x.to_string()),
+			interfaces: self.interfaces.iter().map(|x| x.to_string()).collect(),
+			version: VersionDump {
+				major: self.version.major.into(),
+				minor: self.version.minor
+			},
+			access_flags: self.access_flags.bits(),
+			modifiers: modifiers(&self.access_flags.to_java_modifiers()),
+			attributes: attribute_names(&self.attributes),
+			fields: self.fields.iter().map(|field| MemberDump {
+				name: field.name.clone(),
+				descriptor: field.descriptor.clone(),
+				access_flags: field.access_flags.bits(),
+				modifiers: modifiers(&field.access_flags.to_java_modifiers()),
+				attributes: attribute_names(&field.attributes),
+				code: None
+			}).collect(),
+			methods: self.methods.iter().map(|method| MemberDump {
+				name: method.name.clone(),
+				descriptor: method.descriptor.clone(),
+				access_flags: method.access_flags.bits(),
+				modifiers: modifiers(&method.access_flags.to_java_modifiers()),
+				attributes: attribute_names(&method.attributes),
+				code: method.code_ref().map(CodeAttribute::dump)
+			}).collect()
+		}
+	}
+}
+
+impl CodeAttribute {
+	/// The [`CodeDump`] of this code, with labels named as by [`disassemble`](Self::disassemble)
+	pub fn dump(&self) -> CodeDump {
+		let name = self.label_names();
+		CodeDump {
+			max_stack: self.max_stack,
+			max_locals: self.max_locals,
+			insns: self.insns.iter().map(|insn| dump_insn(insn, &name)).collect(),
+			exceptions: self.exceptions.iter().map(|handler| HandlerDump {
+				start: name(handler.start),
+				end: name(handler.end),
+				handler: name(handler.handler),
+				catch_type: handler.catch_type.clone()
+			}).collect()
+		}
+	}
+}
+
+fn dump_insn(insn: &Insn, name: &dyn Fn(LabelInsn) -> String) -> InsnDump {
+	if let Insn::Label(label) = insn {
+		return InsnDump {
+			label: Some(name(*label)),
+			..InsnDump::default()
+		};
+	}
+	
+	let mut text = String::new();
+	crate::disasm::write_insn(&mut text, insn, name).unwrap();
+	let (op, operands) = match text.find(' ') {
+		Some(split) => (text[..split].to_string(), Some(text[split + 1..].to_string())),
+		None => (text, None)
+	};
+	let member = |owner: &str, name: &str, descriptor: &str| Some(MemberRefDump {
+		owner: owner.to_string(),
+		name: name.to_string(),
+		descriptor: descriptor.to_string()
+	});
+	InsnDump {
+		op: Some(op),
+		operands,
+		targets: insn.jump_targets().into_iter().map(name).collect(),
+		member: match insn {
+			Insn::GetField(x) => member(&x.class, &x.name, &x.descriptor),
+			Insn::PutField(x) => member(&x.class, &x.name, &x.descriptor),
+			Insn::Invoke(x) => member(&x.class, &x.name, &x.descriptor),
+			_ => None
+		},
+		class: match insn {
+			Insn::NewObject(x) => Some(x.kind.to_string()),
+			Insn::CheckCast(x) => Some(x.kind.to_string()),
+			Insn::InstanceOf(x) => Some(x.class.to_string()),
+			_ => None
+		},
+		..InsnDump::default()
+	}
+}
+
+fn modifiers(modifiers: &str) -> Vec<String> {
+	modifiers.split_whitespace().map(String::from).collect()
+}
+
+fn attribute_names(attributes: &[Attribute]) -> Vec<String> {
+	attributes.iter().map(|attr| attr.name().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	
+	/// `classes/snapshot/Snapshot.class` as printed by `dissasembler dump --format json`
+	const SNAPSHOT_JSON: &str = include_str!("../classes/snapshot/Snapshot.json");
+	
+	#[test]
+	fn dump_schema_snapshot() {
+		let class = ClassFile::parse(&mut Cursor::new(&include_bytes!("../classes/snapshot/Snapshot.class")[..])).unwrap();
+		let json = serde_json::to_string_pretty(&class.dump()).unwrap();
+		assert_eq!(json.trim_end(), SNAPSHOT_JSON.trim_end());
+	}
+}
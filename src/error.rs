@@ -2,6 +2,9 @@ use thiserror::Error;
 use std::{io, result};
 use std::fmt::{Debug};
 use crate::constantpool::ConstantType;
+use crate::version::{ClassVersion, MajorVersion};
+use crate::attributes::AttributeSource;
+use crate::path::{Path, MemberPath, MemberKind, AttrPath, PathSegment};
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
@@ -19,6 +22,8 @@ pub enum ParserError {
     Unrecognized(&'static str, String),
     #[error("Invalid constant pool index: {0}")]
     BadCpIndex(u16),
+    #[error("Constant pool index {0} refers to the unusable slot after a long or double")]
+    PhantomCpIndex(u16),
     #[error("{0} was none!")]
     None(&'static str),
     #[error("Unknown Instruction {opcode:X}")]
@@ -41,7 +46,114 @@ pub enum ParserError {
 	#[error("Invalid Descriptor: {0}")]
 	InvalidDescriptor(String),
 	#[error("{0}")]
-	Other(String)
+	Other(String),
+	#[error("Attribute {name} has a length of {expected} but {} bytes were parsed", .consumed.map_or_else(|| String::from("more"), |x| x.to_string()))]
+	AttributeLengthMismatch {
+		name: String,
+		expected: usize,
+		/// None if parsing ran past the end of the attribute
+		consumed: Option<usize>
+	},
+	#[error("Method code is {size} bytes, the limit is 65535")]
+	MethodTooLarge {
+		size: usize
+	},
+	#[error("Attribute {name} is {length} bytes, the limit is 4294967295")]
+	AttributeTooLong {
+		name: String,
+		length: u64
+	},
+	#[error("Attribute {name} was predicted to be {predicted} bytes but {written} were written")]
+	AttributeLengthMispredicted {
+		name: String,
+		predicted: u64,
+		written: u64
+	},
+	#[error("Invalid {attribute} attribute on {member}: {reason}")]
+	InvalidMemberAttribute {
+		/// Name and descriptor of the field or method
+		member: String,
+		attribute: String,
+		reason: &'static str
+	},
+	#[error("Constant value of type {kind} cannot initialise a field of type {descriptor}")]
+	ConstantValueMismatch {
+		kind: &'static str,
+		descriptor: String
+	},
+	#[error("Illegal {kind} access flags: {reason}")]
+	IllegalAccessFlags {
+		/// "class", "field" or "method"
+		kind: &'static str,
+		reason: &'static str
+	},
+	#[error("Instruction {index} jumps outside of the range being copied")]
+	JumpOutOfRange {
+		/// Index of the jump in the list it was copied from
+		index: usize
+	},
+	#[error("Invalid exception handler {index}: {reason}")]
+	InvalidExceptionHandler {
+		/// Index of the handler in the exception table
+		index: usize,
+		reason: String
+	},
+	#[error("{name} attribute is not allowed on a {location}")]
+	MisplacedAttribute {
+		name: String,
+		location: AttributeSource
+	},
+	#[error("Label {label} is not in the instructions")]
+	UnplacedLabel {
+		/// The name of the label, or its id
+		label: String
+	},
+	#[error("The constant pool needs {needed} entries, the limit is 65535")]
+	ConstantPoolOverflow {
+		/// The constant pool count the class would need, one more than the number of indices used
+		needed: usize
+	},
+	#[error("Input ended before the {wanted} bytes of {what}")]
+	Truncated {
+		what: &'static str,
+		wanted: usize
+	},
+	#[error("Method {method} is neither abstract nor native but has no code")]
+	EmptyCode {
+		/// Name and descriptor of the method
+		method: String
+	},
+	#[error("Type check failed at instruction {index}: {reason}")]
+	TypeCheck {
+		/// Index of the instruction in the instruction list, counting labels
+		index: usize,
+		reason: String
+	},
+	#[error("Cannot target {target}, which does not support: {}", .features.join(", "))]
+	UnsupportedByTarget {
+		target: MajorVersion,
+		/// Each feature along with the version it needs
+		features: Vec<String>
+	},
+	#[error("{source} (while parsing {})", self.describe_class().unwrap_or_default())]
+	InClass {
+		/// Internal name of the class, if parsing got as far as it
+		class: Option<String>,
+		version: Option<ClassVersion>,
+		/// The field or method being parsed, if any
+		member: Option<MemberPath>,
+		source: Box<ParserError>
+	},
+	#[error("{}", self.describe_at())]
+	At {
+		/// Number of bytes of the class file consumed when the error occurred, if known
+		offset: Option<u64>,
+		/// One step of the breadcrumb leading to the error, e.g. `methods[3]`
+		context: String,
+		/// The context as a step of a [`Path`], when it names a member, attribute or instruction
+		segment: Option<PathSegment>,
+		source: Box<ParserError>
+	}
 }
 
 impl ParserError {
@@ -75,6 +187,10 @@ impl ParserError {
 		ParserError::BadCpIndex(index.into()).check_panic()
 	}
 	
+	pub fn phantom_cp_index(index: u16) -> Self {
+		ParserError::PhantomCpIndex(index).check_panic()
+	}
+	
 	pub fn none(name: &'static str) -> Self {
 		ParserError::None(name).check_panic()
 	}
@@ -120,9 +236,302 @@ impl ParserError {
 	
 	
 	
+	pub fn attribute_length_mismatch(name: String, expected: usize, consumed: Option<usize>) -> Self {
+		ParserError::AttributeLengthMismatch {
+			name,
+			expected,
+			consumed
+		}.check_panic()
+	}
+	
+	pub fn method_too_large(size: usize) -> Self {
+		ParserError::MethodTooLarge { size }.check_panic()
+	}
+	
+	pub fn attribute_too_long(name: String, length: u64) -> Self {
+		ParserError::AttributeTooLong { name, length }.check_panic()
+	}
+	
+	pub fn attribute_length_mispredicted(name: String, predicted: u64, written: u64) -> Self {
+		ParserError::AttributeLengthMispredicted { name, predicted, written }.check_panic()
+	}
+	
+	pub fn invalid_member_attribute(member: String, attribute: String, reason: &'static str) -> Self {
+		ParserError::InvalidMemberAttribute {
+			member,
+			attribute,
+			reason
+		}.check_panic()
+	}
+	
+	pub fn constant_value_mismatch(kind: &'static str, descriptor: String) -> Self {
+		ParserError::ConstantValueMismatch {
+			kind,
+			descriptor
+		}.check_panic()
+	}
+	
+	pub fn illegal_access_flags(kind: &'static str, reason: &'static str) -> Self {
+		ParserError::IllegalAccessFlags {
+			kind,
+			reason
+		}.check_panic()
+	}
+	
+	pub fn jump_out_of_range(index: usize) -> Self {
+		ParserError::JumpOutOfRange { index }.check_panic()
+	}
+	
+	pub fn invalid_exception_handler<T: Into<String>>(index: usize, reason: T) -> Self {
+		ParserError::InvalidExceptionHandler {
+			index,
+			reason: reason.into()
+		}.check_panic()
+	}
+	
+	pub fn misplaced_attribute(name: String, location: AttributeSource) -> Self {
+		ParserError::MisplacedAttribute { name, location }.check_panic()
+	}
+	
+	pub fn constant_pool_overflow(needed: usize) -> Self {
+		ParserError::ConstantPoolOverflow { needed }.check_panic()
+	}
+	
+	pub fn truncated(what: &'static str, wanted: usize) -> Self {
+		ParserError::Truncated { what, wanted }.check_panic()
+	}
+	
+	pub fn empty_code(method: String) -> Self {
+		ParserError::EmptyCode { method }.check_panic()
+	}
+	
+	pub fn type_check<T: Into<String>>(index: usize, reason: T) -> Self {
+		ParserError::TypeCheck {
+			index,
+			reason: reason.into()
+		}.check_panic()
+	}
+	
+	pub fn unsupported_by_target(target: MajorVersion, features: Vec<String>) -> Self {
+		ParserError::UnsupportedByTarget {
+			target,
+			features
+		}.check_panic()
+	}
+	
 	pub fn unmapped_label() -> Self {
 		ParserError::other("No mapping found for label")
 	}
+	
+	pub fn unplaced_label<T: Into<String>>(label: T) -> Self {
+		ParserError::UnplacedLabel { label: label.into() }.check_panic()
+	}
+	
+	/// Wraps an error with a description of the structure that was being parsed when it occurred
+	pub fn at<T>(context: T, source: ParserError) -> Self
+		where T: Into<String> {
+		ParserError::At {
+			offset: None,
+			context: context.into(),
+			segment: None,
+			source: Box::new(source)
+		}
+	}
+	
+	/// Like [`at`](Self::at), with a context that is a step of a [`Path`]
+	pub fn at_segment(segment: PathSegment, source: ParserError) -> Self {
+		ParserError::At {
+			offset: None,
+			context: segment.to_string(),
+			segment: Some(segment),
+			source: Box::new(source)
+		}
+	}
+	
+	/// Wraps an error from parsing the attributes of a field or method with the member
+	pub fn in_member(member: MemberPath, source: ParserError) -> Self {
+		ParserError::InClass {
+			class: None,
+			version: None,
+			member: Some(member),
+			source: Box::new(source)
+		}
+	}
+	
+	/// Wraps an error from parsing a class with what is known about it, moving the member from any
+	/// [`in_member`](Self::in_member) wrapper inside to the new outermost one
+	pub fn in_class(self, class: Option<String>, version: ClassVersion) -> Self {
+		let (source, member) = self.take_member();
+		ParserError::InClass {
+			class,
+			version: Some(version),
+			member,
+			source: Box::new(source)
+		}
+	}
+	
+	fn take_member(self) -> (Self, Option<MemberPath>) {
+		match self {
+			ParserError::At { offset, context, segment, source } => {
+				let (source, member) = source.take_member();
+				(ParserError::At { offset, context, segment, source: Box::new(source) }, member)
+			},
+			ParserError::InClass { member, source, .. } => (*source, member),
+			x => (x, None)
+		}
+	}
+	
+	/// Where an [InClass](ParserError::InClass) error happened, e.g.
+	/// `method main([Ljava/lang/String;)V of com/foo/Bar (Java 17)`
+	pub fn describe_class(&self) -> Option<String> {
+		match self {
+			ParserError::InClass { class, version, member, .. } => {
+				let mut description = match member {
+					Some(member) if member.kind == MemberKind::Field => format!("field {} {} of ", member.name, member.descriptor),
+					Some(member) => format!("method {}{} of ", member.name, member.descriptor),
+					None => String::new()
+				};
+				description.push_str(class.as_deref().unwrap_or("a class"));
+				if let Some(version) = version {
+					description.push_str(&format!(" ({})", version.major));
+				}
+				Some(description)
+			},
+			ParserError::At { source, .. } => source.describe_class(),
+			_ => None
+		}
+	}
+	
+	/// Records how far into the class file parsing had got when this error occurred
+	pub fn with_offset(self, offset: u64) -> Self {
+		match self {
+			ParserError::InClass { class, version, member, source } => ParserError::InClass {
+				class,
+				version,
+				member,
+				source: Box::new(source.with_offset(offset))
+			},
+			ParserError::At { offset: None, context, segment, source } => ParserError::At {
+				offset: Some(offset),
+				context,
+				segment,
+				source
+			},
+			x @ ParserError::At { .. } => x,
+			x => ParserError::At {
+				offset: Some(offset),
+				context: String::from("class"),
+				segment: None,
+				source: Box::new(x)
+			}
+		}
+	}
+	
+	pub fn offset(&self) -> Option<u64> {
+		let mut err = self;
+		loop {
+			match err {
+				ParserError::At { offset: Some(offset), .. } => return Some(*offset),
+				ParserError::At { source, .. } | ParserError::InClass { source, .. } => err = source,
+				_ => return None
+			}
+		}
+	}
+	
+	/// The path to the structure that failed to parse, e.g. `methods[17].attributes[0].Code.insns`
+	pub fn breadcrumb(&self) -> String {
+		self.contexts().join(".")
+	}
+	
+	fn describe_at(&self) -> String {
+		match self.offset() {
+			Some(offset) => format!("{} at offset {:#x}: {}", self.breadcrumb(), offset, self.root()),
+			None => format!("{}: {}", self.breadcrumb(), self.root())
+		}
+	}
+	
+	/// The error with all context stripped away
+	pub fn root(&self) -> &ParserError {
+		match self {
+			ParserError::At { source, .. } | ParserError::InClass { source, .. } => source.root(),
+			x => x
+		}
+	}
+	
+	/// The member, attribute or instruction the error happened in, as far as the contexts name it.
+	/// Members are only known once parsing has read their name and descriptor, or when writing.
+	pub fn path(&self) -> Option<Path> {
+		let mut err = self;
+		let mut member = None;
+		let (mut attribute, mut code_attribute, mut insn) = (None, None, None);
+		loop {
+			match err {
+				ParserError::At { segment, source, .. } => {
+					match segment {
+						Some(PathSegment::Member(path)) if member.is_none() => member = Some(path.clone()),
+						Some(PathSegment::Attribute(index)) if attribute.is_none() => attribute = Some(*index),
+						Some(PathSegment::Attribute(index)) => code_attribute = Some(*index),
+						Some(PathSegment::Insn(index)) => insn = Some(*index),
+						_ => {}
+					}
+					err = source;
+				},
+				ParserError::InClass { member: Some(path), source, .. } if member.is_none() => {
+					member = Some(path.clone());
+					err = source;
+				},
+				ParserError::InClass { source, .. } => err = source,
+				_ => break
+			}
+		}
+		
+		match (member, insn, code_attribute, attribute) {
+			(Some(method), Some(insn), _, _) if method.kind == MemberKind::Method => Some(method.insn(insn).into()),
+			(Some(method), _, Some(index), _) => Some(method.code_attribute(index).into()),
+			(Some(member), _, _, Some(index)) => Some(member.attribute(index).into()),
+			(Some(member), ..) => Some(member.into()),
+			(None, _, _, Some(index)) => Some(AttrPath::class(index).into()),
+			(None, ..) => None
+		}
+	}
+	
+	/// The contexts attached to this error, from outermost to innermost
+	pub fn contexts(&self) -> Vec<&str> {
+		let mut contexts = Vec::new();
+		let mut err = self;
+		loop {
+			match err {
+				ParserError::At { context, source, .. } => {
+					contexts.push(context.as_str());
+					err = source;
+				},
+				ParserError::InClass { source, .. } => err = source,
+				_ => return contexts
+			}
+		}
+	}
+}
+
+pub trait ErrorContext<T> {
+	/// Attaches a lazily computed context to the error, if any
+	fn context<C, F>(self, f: F) -> Result<T>
+		where C: Into<String>, F: FnOnce() -> C;
+	
+	/// Attaches a lazily computed step of the [path](ParserError::path) to the error, if any
+	fn path_context<F>(self, f: F) -> Result<T>
+		where F: FnOnce() -> PathSegment;
+}
+
+impl <T> ErrorContext<T> for Result<T> {
+	fn context<C, F>(self, f: F) -> Result<T>
+		where C: Into<String>, F: FnOnce() -> C {
+		self.map_err(|err| ParserError::at(f(), err))
+	}
+	
+	fn path_context<F>(self, f: F) -> Result<T>
+		where F: FnOnce() -> PathSegment {
+		self.map_err(|err| ParserError::at_segment(f(), err))
+	}
 }
 
 impl From<io::Error> for ParserError {
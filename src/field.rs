@@ -1,10 +1,13 @@
 use crate::Serializable;
 use crate::access::FieldAccessFlags;
-use crate::constantpool::{ConstantPool, ConstantPoolWriter};
-use crate::attributes::{Attributes, Attribute, AttributeSource, SignatureAttribute};
+use crate::constantpool::{ConstantPool, ConstantPoolWriter, MemberIndices};
+use crate::attributes::{Attributes, Attribute, AttributeContext, AttributeSource, ConstantValue, ConstantValueAttribute, WriteContext};
 use crate::version::ClassVersion;
-use crate::error::Result;
-use crate::utils::{VecUtils};
+use crate::classfile::ParseOptions;
+use crate::error::{Result, ParserError, ErrorContext};
+use crate::path::{MemberPath, MemberKind, PathSegment};
+use crate::types::parse_field_desc;
+use crate::utils::{VecUtils, ClassRead, CountingReader};
 use std::io::{Read, Write};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
 
@@ -15,65 +18,136 @@ pub mod Fields {
 	use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 	use crate::version::ClassVersion;
 	use crate::constantpool::{ConstantPool, ConstantPoolWriter};
+	use crate::attributes::WriteContext;
+	use crate::utils::{ClassRead, CountingReader};
+	use crate::error::ErrorContext;
+	use crate::classfile::ParseOptions;
 	
-	pub fn parse<T: Read>(rdr: &mut T, version: &ClassVersion, constant_pool: &ConstantPool) -> crate::Result<Vec<Field>> {
+	pub fn parse<T: Read>(rdr: &mut T, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> crate::Result<Vec<Field>> {
+		parse_from(&mut CountingReader::new(rdr), version, constant_pool, options)
+	}
+	
+	pub(crate) fn parse_from<T: ClassRead>(rdr: &mut T, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> crate::Result<Vec<Field>> {
 		let num_fields = rdr.read_u16::<BigEndian>()? as usize;
 		let mut fields: Vec<Field> = Vec::with_capacity(num_fields);
-		for _ in 0..num_fields {
-			fields.push(Field::parse(rdr, version, constant_pool)?);
+		for i in 0..num_fields {
+			fields.push(Field::parse_from(rdr, version, constant_pool, options).context(|| format!("fields[{}]", i))?);
 		}
 		Ok(fields)
 	}
 	
 	pub fn write<T: Write>(wtr: &mut T, fields: &[Field], constant_pool: &mut ConstantPoolWriter) -> crate::Result<()> {
+		write_with_context(wtr, fields, constant_pool, &mut WriteContext::default())
+	}
+	
+	pub(crate) fn write_with_context<T: Write>(wtr: &mut T, fields: &[Field], constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext) -> crate::Result<()> {
 		wtr.write_u16::<BigEndian>(fields.len() as u16)?;
 		for field in fields.iter() {
-			field.write(wtr, constant_pool)?;
+			field.write_with_context(wtr, constant_pool, context)?;
 		}
 		Ok(())
 	}
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Field {
 	pub access_flags: FieldAccessFlags,
 	pub name: String,
 	pub descriptor: String,
-	pub attributes: Vec<Attribute>
+	pub attributes: Vec<Attribute>,
+	/// Where the name and descriptor were in the constant pool, when parsed with
+	/// [ParseOptions::original_indices](crate::classfile::ParseOptions::original_indices)
+	pub original_indices: Option<MemberIndices>
+}
+
+/// Ignores the original indices, so that a parsed field equals the same field built by hand
+impl PartialEq for Field {
+	fn eq(&self, other: &Self) -> bool {
+		self.access_flags == other.access_flags && self.name == other.name && self.descriptor == other.descriptor
+			&& self.attributes == other.attributes
+	}
 }
 
 impl Field {
-	pub fn parse<R: Read>(rdr: &mut R, version: &ClassVersion, constant_pool: &ConstantPool) -> Result<Self> {
+	/// A field without attributes
+	pub fn new(access_flags: FieldAccessFlags, name: String, descriptor: String) -> Self {
+		Field {
+			access_flags,
+			name,
+			descriptor,
+			attributes: Vec::new(),
+			original_indices: None
+		}
+	}
+	
+	pub fn parse<R: Read>(rdr: &mut R, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> Result<Self> {
+		Field::parse_from(&mut CountingReader::new(rdr), version, constant_pool, options)
+	}
+	
+	pub(crate) fn parse_from<R: ClassRead>(rdr: &mut R, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> Result<Self> {
 		let access_flags = FieldAccessFlags::parse(rdr)?;
-		let name = constant_pool.utf8(rdr.read_u16::<BigEndian>()?)?.str.clone();
-		let descriptor = constant_pool.utf8(rdr.read_u16::<BigEndian>()?)?.str.clone();
-		let attributes = Attributes::parse(rdr, AttributeSource::Field, version, constant_pool, &mut None)?;
+		let name_index = rdr.read_u16::<BigEndian>()?;
+		let name = constant_pool.utf8_inner(name_index).context(|| "name")?;
+		let descriptor_index = rdr.read_u16::<BigEndian>()?;
+		let descriptor = constant_pool.utf8_inner(descriptor_index).context(|| "descriptor")?;
+		let member = format!("{} {}", name, descriptor);
+		if options.strict_descriptors {
+			parse_field_desc(&descriptor).context(|| member.clone())?;
+		}
+		let mut context = AttributeContext::new(AttributeSource::Field, version, constant_pool, options);
+		let attributes = Attributes::parse_member_from(rdr, &mut context, &member, true)
+			.map_err(|err| ParserError::in_member(MemberPath { kind: MemberKind::Field, name: name.clone(), descriptor: descriptor.clone() }, err))?;
 		
 		Ok(Field {
 			access_flags,
 			name,
 			descriptor,
-			attributes
+			attributes,
+			original_indices: if options.original_indices {
+				Some(MemberIndices { name: name_index, descriptor: descriptor_index })
+			} else {
+				None
+			}
 		})
 	}
 	
 	pub fn signature(&mut self) -> Option<&mut String> {
+		Attributes::signature(&mut self.attributes)
+	}
+	
+	/// The generic signature of this field, for reading through a shared reference
+	pub fn signature_ref(&self) -> Option<&str> {
+		Attributes::signature_ref(&self.attributes)
+	}
+	
+	pub fn set_signature(&mut self, sig: Option<String>) {
+		Attributes::set_signature(&mut self.attributes, sig)
+	}
+	
+	pub fn constant_value(&mut self) -> Option<&mut ConstantValue> {
 		for attr in self.attributes.iter_mut() {
-			if let Attribute::Signature(sig) = attr {
-				return Some(&mut sig.signature)
+			if let Attribute::ConstantValue(x) = attr {
+				return Some(&mut x.value)
 			}
 		}
 		None
 	}
 	
-	pub fn set_signature(&mut self, sig: Option<String>) {
-		// According to the JVM spec there must be at most one signature attribute in the attributes table
-		// first find the index of the existing sig
+	/// Like [`constant_value`](Self::constant_value), for reading through a shared reference
+	pub fn constant_value_ref(&self) -> Option<&ConstantValue> {
+		self.attributes.iter().find_map(|attr| match attr {
+			Attribute::ConstantValue(x) => Some(&x.value),
+			_ => None
+		})
+	}
+	
+	pub fn set_constant_value(&mut self, value: Option<ConstantValue>) {
 		let index = self.attributes.find_first(|attr| {
-			matches!(attr, Attribute::Signature(_))
+			matches!(attr, Attribute::ConstantValue(_))
 		});
-		if let Some(sig) = sig {
-			let attr = Attribute::Signature(SignatureAttribute::new(sig));
+		if let Some(value) = value {
+			let attr = Attribute::ConstantValue(ConstantValueAttribute::new(value));
 			if let Some(index) = index {
 				self.attributes.replace(index, attr);
 			} else {
@@ -85,10 +159,108 @@ impl Field {
 	}
 	
 	pub fn write<W: Write>(&self, wtr: &mut W, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		self.write_with_context(wtr, constant_pool, &mut WriteContext::default())
+	}
+	
+	pub(crate) fn write_with_context<W: Write>(&self, wtr: &mut W, constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext) -> Result<()> {
+		self.check_constant_value().path_context(|| PathSegment::Member(MemberPath::field(self)))?;
 		self.access_flags.write(wtr)?;
 		wtr.write_u16::<BigEndian>(constant_pool.utf8(self.name.clone()))?;
 		wtr.write_u16::<BigEndian>(constant_pool.utf8(self.descriptor.clone()))?;
-		Attributes::write(wtr, &self.attributes, constant_pool, None)?;
+		Attributes::write_with_context(wtr, &self.attributes, AttributeSource::Field, constant_pool, None, context)
+			.path_context(|| PathSegment::Member(MemberPath::field(self)))?;
 		Ok(())
 	}
+	
+	/// The JVM refuses to load a class whose constant values do not match their field's type
+	fn check_constant_value(&self) -> Result<()> {
+		for attr in self.attributes.iter() {
+			if let Attribute::ConstantValue(x) = attr {
+				if !x.value.matches_descriptor(&self.descriptor) {
+					return Err(ParserError::constant_value_mismatch(x.value.kind(), self.descriptor.clone()));
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn constant(descriptor: &str) -> Field {
+		Field::new(FieldAccessFlags::PUBLIC | FieldAccessFlags::STATIC | FieldAccessFlags::FINAL, String::from("VALUE"), String::from(descriptor))
+	}
+	
+	#[test]
+	fn set_constant_value() {
+		let mut field = constant("J");
+		assert_eq!(field.constant_value(), None);
+		field.set_constant_value(Some(ConstantValue::Long(1)));
+		field.set_constant_value(Some(ConstantValue::Long(2)));
+		assert_eq!(field.attributes.len(), 1);
+		*field.constant_value().unwrap() = ConstantValue::Long(3);
+		assert_eq!(field.constant_value_ref(), Some(&ConstantValue::Long(3)));
+		assert_eq!(field.attributes[0], Attribute::ConstantValue(ConstantValueAttribute::new(ConstantValue::Long(3))));
+		field.set_constant_value(None);
+		assert!(field.attributes.is_empty());
+	}
+	
+	#[test]
+	fn constant_value_must_match_descriptor() {
+		let valid = [
+			("J", ConstantValue::Long(1)),
+			("F", ConstantValue::Float(1.0)),
+			("D", ConstantValue::Double(1.0)),
+			("Z", ConstantValue::Int(1)),
+			("C", ConstantValue::Int(65)),
+			("Ljava/lang/String;", ConstantValue::String(String::from("a")))
+		];
+		for (descriptor, value) in valid.iter() {
+			let mut field = constant(descriptor);
+			field.set_constant_value(Some(value.clone()));
+			assert!(field.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).is_ok(), "{} {:?}", descriptor, value);
+		}
+		
+		let mut field = constant("J");
+		field.set_constant_value(Some(ConstantValue::Int(1)));
+		let err = field.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err();
+		assert_eq!(err.to_string(), "VALUE J: Constant value of type int cannot initialise a field of type J");
+		
+		let mut field = constant("Ljava/lang/Object;");
+		field.set_constant_value(Some(ConstantValue::String(String::from("a"))));
+		assert!(matches!(field.write(&mut Vec::new(), &mut ConstantPoolWriter::new()).unwrap_err().root(), ParserError::ConstantValueMismatch { kind: "String", .. }));
+	}
+	
+	#[test]
+	fn strict_descriptors() {
+		use crate::version::MajorVersion;
+		use std::io::Cursor;
+		
+		let parse = |descriptor: &str, strict_descriptors: bool| {
+			let mut writer = ConstantPoolWriter::new();
+			let mut bytes = vec![0, 0];
+			bytes.write_u16::<BigEndian>(writer.utf8("value")).unwrap();
+			bytes.write_u16::<BigEndian>(writer.utf8(descriptor)).unwrap();
+			bytes.write_u16::<BigEndian>(0).unwrap();
+			let mut pool = Vec::new();
+			writer.write(&mut pool).unwrap();
+			let pool = ConstantPool::parse(&mut Cursor::new(pool)).unwrap();
+			let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+			let options = ParseOptions { strict_descriptors, ..Default::default() };
+			Field::parse(&mut Cursor::new(bytes), &version, &pool, &options)
+		};
+		
+		for descriptor in ["[Ljava/lang/String;", "[[I", "Ljava/util/List;", "Z"].iter() {
+			assert!(parse(descriptor, true).is_ok(), "{}", descriptor);
+		}
+		for descriptor in ["Q", "V", "[V", "Ljava/lang/String", "II"].iter() {
+			let err = parse(descriptor, true).unwrap_err();
+			assert_eq!(err.contexts(), vec![format!("value {}", descriptor)]);
+			assert!(matches!(err.root(), ParserError::InvalidDescriptor(_)));
+			// still readable when not strict
+			assert_eq!(parse(descriptor, false).unwrap().descriptor, *descriptor);
+		}
+	}
 }
@@ -0,0 +1,381 @@
+use crate::ast::{Insn, LdcInsn, LdcType, PrimitiveType, IntegerType};
+use crate::insnlist::InsnList;
+
+/// An arithmetic operation on two constants of the same type
+#[derive(Copy, Clone)]
+enum BinaryOp {
+	Add,
+	Subtract,
+	Multiply,
+	Divide,
+	Remainder,
+	And,
+	Or,
+	Xor
+}
+
+#[derive(Copy, Clone)]
+enum ShiftOp {
+	Left,
+	Right,
+	LogicalRight
+}
+
+impl InsnList {
+	/// Replaces arithmetic on constants with the constant result, for example
+	/// `Ldc(Int 2), Ldc(Int 3), Add(Int)` becomes `Ldc(Int 5)`.
+	///
+	/// Results follow the JVM: integer arithmetic wraps, shift distances are masked and floating
+	/// point follows IEEE 754. Integer division or remainder by zero is left alone so that it still
+	/// throws at runtime. Any instruction in between, including a label, prevents folding.
+	///
+	/// Returns the number of operations that were folded.
+	pub fn fold_constants(&mut self) -> usize {
+		let mut folded = 0;
+		let mut out: Vec<Insn> = Vec::with_capacity(self.insns.len());
+		for insn in std::mem::take(&mut self.insns) {
+			out.push(insn);
+			// folding one operation may make the next one foldable, e.g. 1 + 2 + 3
+			while let Some(insn) = fold_tail(&out) {
+				let operands = match &out[out.len() - 1] {
+					Insn::Convert(_) | Insn::Negate(_) => 1,
+					_ => 2
+				};
+				out.truncate(out.len() - operands - 1);
+				out.push(insn);
+				folded += 1;
+			}
+		}
+		self.insns = out;
+		if folded > 0 {
+			self.touch();
+		}
+		folded
+	}
+}
+
+/// Folds the instruction at the end of the list with the constants before it, if possible
+fn fold_tail(insns: &[Insn]) -> Option<Insn> {
+	let (op, rest) = insns.split_last()?;
+	let result = match op {
+		Insn::Add(x) => binary(rest, x.kind, BinaryOp::Add),
+		Insn::Subtract(x) => binary(rest, x.kind, BinaryOp::Subtract),
+		Insn::Multiply(x) => binary(rest, x.kind, BinaryOp::Multiply),
+		Insn::Divide(x) => binary(rest, x.kind, BinaryOp::Divide),
+		Insn::Remainder(x) => binary(rest, x.kind, BinaryOp::Remainder),
+		Insn::And(x) => binary(rest, integer_type(x.kind), BinaryOp::And),
+		Insn::Or(x) => binary(rest, integer_type(x.kind), BinaryOp::Or),
+		Insn::Xor(x) => binary(rest, integer_type(x.kind), BinaryOp::Xor),
+		Insn::ShiftLeft(x) => shift(rest, x.kind, ShiftOp::Left),
+		Insn::ShiftRight(x) => shift(rest, x.kind, ShiftOp::Right),
+		Insn::LogicalShiftRight(x) => shift(rest, x.kind, ShiftOp::LogicalRight),
+		Insn::Negate(x) => negate(constant(rest.last()?)?, x.kind),
+		Insn::Convert(x) => convert(constant(rest.last()?)?, x.from, x.to),
+		_ => None
+	}?;
+	Some(Insn::Ldc(LdcInsn::new(result)))
+}
+
+fn constant(insn: &Insn) -> Option<&LdcType> {
+	match insn {
+		Insn::Ldc(x) => Some(&x.constant),
+		_ => None
+	}
+}
+
+/// The two constants at the end of the list, the first being deeper in the stack
+fn operands(insns: &[Insn]) -> Option<(&LdcType, &LdcType)> {
+	match insns {
+		[.., a, b] => Some((constant(a)?, constant(b)?)),
+		_ => None
+	}
+}
+
+fn integer_type(kind: IntegerType) -> PrimitiveType {
+	match kind {
+		IntegerType::Int => PrimitiveType::Int,
+		IntegerType::Long => PrimitiveType::Long
+	}
+}
+
+fn binary(insns: &[Insn], kind: PrimitiveType, op: BinaryOp) -> Option<LdcType> {
+	Some(match (operands(insns)?, kind) {
+		((LdcType::Int(a), LdcType::Int(b)), PrimitiveType::Int) => {
+			let (a, b) = (*a, *b);
+			LdcType::Int(match op {
+				BinaryOp::Add => a.wrapping_add(b),
+				BinaryOp::Subtract => a.wrapping_sub(b),
+				BinaryOp::Multiply => a.wrapping_mul(b),
+				BinaryOp::Divide => if b == 0 { return None } else { a.wrapping_div(b) },
+				BinaryOp::Remainder => if b == 0 { return None } else { a.wrapping_rem(b) },
+				BinaryOp::And => a & b,
+				BinaryOp::Or => a | b,
+				BinaryOp::Xor => a ^ b
+			})
+		},
+		((LdcType::Long(a), LdcType::Long(b)), PrimitiveType::Long) => {
+			let (a, b) = (*a, *b);
+			LdcType::Long(match op {
+				BinaryOp::Add => a.wrapping_add(b),
+				BinaryOp::Subtract => a.wrapping_sub(b),
+				BinaryOp::Multiply => a.wrapping_mul(b),
+				BinaryOp::Divide => if b == 0 { return None } else { a.wrapping_div(b) },
+				BinaryOp::Remainder => if b == 0 { return None } else { a.wrapping_rem(b) },
+				BinaryOp::And => a & b,
+				BinaryOp::Or => a | b,
+				BinaryOp::Xor => a ^ b
+			})
+		},
+		((LdcType::Float(a), LdcType::Float(b)), PrimitiveType::Float) => {
+			let (a, b) = (*a, *b);
+			LdcType::Float(match op {
+				BinaryOp::Add => a + b,
+				BinaryOp::Subtract => a - b,
+				BinaryOp::Multiply => a * b,
+				BinaryOp::Divide => a / b,
+				// truncating remainder, the same as Java's %
+				BinaryOp::Remainder => a % b,
+				_ => return None
+			})
+		},
+		((LdcType::Double(a), LdcType::Double(b)), PrimitiveType::Double) => {
+			let (a, b) = (*a, *b);
+			LdcType::Double(match op {
+				BinaryOp::Add => a + b,
+				BinaryOp::Subtract => a - b,
+				BinaryOp::Multiply => a * b,
+				BinaryOp::Divide => a / b,
+				BinaryOp::Remainder => a % b,
+				_ => return None
+			})
+		},
+		_ => return None
+	})
+}
+
+fn shift(insns: &[Insn], kind: IntegerType, op: ShiftOp) -> Option<LdcType> {
+	// the shift distance is always an int, only the low 5 (int) or 6 (long) bits are used
+	Some(match (operands(insns)?, kind) {
+		((LdcType::Int(a), LdcType::Int(b)), IntegerType::Int) => {
+			let b = (*b & 0x1F) as u32;
+			LdcType::Int(match op {
+				ShiftOp::Left => a.wrapping_shl(b),
+				ShiftOp::Right => a.wrapping_shr(b),
+				ShiftOp::LogicalRight => (*a as u32).wrapping_shr(b) as i32
+			})
+		},
+		((LdcType::Long(a), LdcType::Int(b)), IntegerType::Long) => {
+			let b = (*b & 0x3F) as u32;
+			LdcType::Long(match op {
+				ShiftOp::Left => a.wrapping_shl(b),
+				ShiftOp::Right => a.wrapping_shr(b),
+				ShiftOp::LogicalRight => (*a as u64).wrapping_shr(b) as i64
+			})
+		},
+		_ => return None
+	})
+}
+
+fn negate(value: &LdcType, kind: PrimitiveType) -> Option<LdcType> {
+	Some(match (value, kind) {
+		(LdcType::Int(x), PrimitiveType::Int) => LdcType::Int(x.wrapping_neg()),
+		(LdcType::Long(x), PrimitiveType::Long) => LdcType::Long(x.wrapping_neg()),
+		(LdcType::Float(x), PrimitiveType::Float) => LdcType::Float(-x),
+		(LdcType::Double(x), PrimitiveType::Double) => LdcType::Double(-x),
+		_ => return None
+	})
+}
+
+fn convert(value: &LdcType, from: PrimitiveType, to: PrimitiveType) -> Option<LdcType> {
+	// `as` casts from floating point saturate and map NaN to 0, exactly like the JVM
+	Some(match (value, from, to) {
+		(LdcType::Int(x), PrimitiveType::Int, PrimitiveType::Byte) => LdcType::Int(*x as i8 as i32),
+		(LdcType::Int(x), PrimitiveType::Int, PrimitiveType::Char) => LdcType::Int(*x as u16 as i32),
+		(LdcType::Int(x), PrimitiveType::Int, PrimitiveType::Short) => LdcType::Int(*x as i16 as i32),
+		(LdcType::Int(x), PrimitiveType::Int, PrimitiveType::Long) => LdcType::Long(*x as i64),
+		(LdcType::Int(x), PrimitiveType::Int, PrimitiveType::Float) => LdcType::Float(*x as f32),
+		(LdcType::Int(x), PrimitiveType::Int, PrimitiveType::Double) => LdcType::Double(*x as f64),
+		(LdcType::Long(x), PrimitiveType::Long, PrimitiveType::Int) => LdcType::Int(*x as i32),
+		(LdcType::Long(x), PrimitiveType::Long, PrimitiveType::Float) => LdcType::Float(*x as f32),
+		(LdcType::Long(x), PrimitiveType::Long, PrimitiveType::Double) => LdcType::Double(*x as f64),
+		(LdcType::Float(x), PrimitiveType::Float, PrimitiveType::Int) => LdcType::Int(*x as i32),
+		(LdcType::Float(x), PrimitiveType::Float, PrimitiveType::Long) => LdcType::Long(*x as i64),
+		(LdcType::Float(x), PrimitiveType::Float, PrimitiveType::Double) => LdcType::Double(*x as f64),
+		(LdcType::Double(x), PrimitiveType::Double, PrimitiveType::Int) => LdcType::Int(*x as i32),
+		(LdcType::Double(x), PrimitiveType::Double, PrimitiveType::Long) => LdcType::Long(*x as i64),
+		(LdcType::Double(x), PrimitiveType::Double, PrimitiveType::Float) => LdcType::Float(*x as f32),
+		_ => return None
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::ast::*;
+	
+	fn ldc(x: LdcType) -> Insn {
+		Insn::Ldc(LdcInsn::new(x))
+	}
+	
+	/// Folds the given instructions, expecting a single constant to remain
+	fn fold(insns: Vec<Insn>) -> Option<LdcType> {
+		let mut list = InsnList::new();
+		list.insns = insns;
+		list.fold_constants();
+		match list.insns.as_slice() {
+			[Insn::Ldc(x)] => Some(x.constant.clone()),
+			_ => None
+		}
+	}
+	
+	fn int_op(a: i32, b: i32, op: Insn) -> Option<LdcType> {
+		fold(vec![ldc(LdcType::Int(a)), ldc(LdcType::Int(b)), op])
+	}
+	
+	fn long_op(a: i64, b: i64, op: Insn) -> Option<LdcType> {
+		fold(vec![ldc(LdcType::Long(a)), ldc(LdcType::Long(b)), op])
+	}
+	
+	#[test]
+	fn ints() {
+		let int = PrimitiveType::Int;
+		assert_eq!(int_op(2, 3, Insn::Add(AddInsn::new(int))), Some(LdcType::Int(5)));
+		assert_eq!(int_op(i32::MAX, 1, Insn::Add(AddInsn::new(int))), Some(LdcType::Int(i32::MIN)));
+		assert_eq!(int_op(i32::MIN, 1, Insn::Subtract(SubtractInsn::new(int))), Some(LdcType::Int(i32::MAX)));
+		assert_eq!(int_op(0x10000, 0x10000, Insn::Multiply(MultiplyInsn::new(int))), Some(LdcType::Int(0)));
+		assert_eq!(int_op(-7, 2, Insn::Divide(DivideInsn::new(int))), Some(LdcType::Int(-3)));
+		assert_eq!(int_op(i32::MIN, -1, Insn::Divide(DivideInsn::new(int))), Some(LdcType::Int(i32::MIN)));
+		assert_eq!(int_op(-7, 2, Insn::Remainder(RemainderInsn::new(int))), Some(LdcType::Int(-1)));
+		assert_eq!(int_op(i32::MIN, -1, Insn::Remainder(RemainderInsn::new(int))), Some(LdcType::Int(0)));
+		assert_eq!(int_op(0b1100, 0b1010, Insn::And(AndInsn::new(IntegerType::Int))), Some(LdcType::Int(0b1000)));
+		assert_eq!(int_op(0b1100, 0b1010, Insn::Or(OrInsn::new(IntegerType::Int))), Some(LdcType::Int(0b1110)));
+		assert_eq!(int_op(0b1100, 0b1010, Insn::Xor(XorInsn::new(IntegerType::Int))), Some(LdcType::Int(0b0110)));
+		assert_eq!(fold(vec![ldc(LdcType::Int(i32::MIN)), Insn::Negate(NegateInsn::new(int))]), Some(LdcType::Int(i32::MIN)));
+	}
+	
+	#[test]
+	fn division_by_zero_is_kept() {
+		assert_eq!(int_op(1, 0, Insn::Divide(DivideInsn::new(PrimitiveType::Int))), None);
+		assert_eq!(int_op(1, 0, Insn::Remainder(RemainderInsn::new(PrimitiveType::Int))), None);
+		assert_eq!(long_op(1, 0, Insn::Divide(DivideInsn::new(PrimitiveType::Long))), None);
+		assert_eq!(long_op(1, 0, Insn::Remainder(RemainderInsn::new(PrimitiveType::Long))), None);
+		
+		let mut list = InsnList::new();
+		list.insns = vec![ldc(LdcType::Int(1)), ldc(LdcType::Int(0)), Insn::Divide(DivideInsn::new(PrimitiveType::Int))];
+		assert_eq!(list.fold_constants(), 0);
+		assert_eq!(list.len(), 3);
+	}
+	
+	#[test]
+	fn longs() {
+		let long = PrimitiveType::Long;
+		assert_eq!(long_op(i64::MAX, 1, Insn::Add(AddInsn::new(long))), Some(LdcType::Long(i64::MIN)));
+		assert_eq!(long_op(i64::MIN, -1, Insn::Divide(DivideInsn::new(long))), Some(LdcType::Long(i64::MIN)));
+		assert_eq!(long_op(i64::MIN, -1, Insn::Remainder(RemainderInsn::new(long))), Some(LdcType::Long(0)));
+		assert_eq!(long_op(3, 4, Insn::Multiply(MultiplyInsn::new(long))), Some(LdcType::Long(12)));
+		assert_eq!(long_op(5, 3, Insn::Xor(XorInsn::new(IntegerType::Long))), Some(LdcType::Long(6)));
+		// operand types must match the instruction
+		assert_eq!(long_op(1, 2, Insn::Add(AddInsn::new(PrimitiveType::Int))), None);
+	}
+	
+	#[test]
+	fn shifts_are_masked() {
+		let shl = |kind| Insn::ShiftLeft(ShiftLeftInsn::new(kind));
+		assert_eq!(int_op(1, 33, shl(IntegerType::Int)), Some(LdcType::Int(2)));
+		assert_eq!(int_op(1, -1, shl(IntegerType::Int)), Some(LdcType::Int(i32::MIN)));
+		assert_eq!(int_op(-8, 1, Insn::ShiftRight(ShiftRightInsn::new(IntegerType::Int))), Some(LdcType::Int(-4)));
+		assert_eq!(int_op(-1, 28, Insn::LogicalShiftRight(LogicalShiftRightInsn::new(IntegerType::Int))), Some(LdcType::Int(0xF)));
+		
+		let long_shift = |a: i64, b: i32, op| fold(vec![ldc(LdcType::Long(a)), ldc(LdcType::Int(b)), op]);
+		assert_eq!(long_shift(1, 65, shl(IntegerType::Long)), Some(LdcType::Long(2)));
+		assert_eq!(long_shift(-1, 60, Insn::LogicalShiftRight(LogicalShiftRightInsn::new(IntegerType::Long))), Some(LdcType::Long(0xF)));
+		assert_eq!(long_shift(i64::MIN, 63, Insn::ShiftRight(ShiftRightInsn::new(IntegerType::Long))), Some(LdcType::Long(-1)));
+	}
+	
+	#[test]
+	fn floating_point() {
+		let float = |a: f32, b: f32, op| fold(vec![ldc(LdcType::Float(a)), ldc(LdcType::Float(b)), op]);
+		let double = |a: f64, b: f64, op| fold(vec![ldc(LdcType::Double(a)), ldc(LdcType::Double(b)), op]);
+		
+		assert_eq!(float(1.5, 2.0, Insn::Multiply(MultiplyInsn::new(PrimitiveType::Float))), Some(LdcType::Float(3.0)));
+		assert_eq!(float(1.0, 0.0, Insn::Divide(DivideInsn::new(PrimitiveType::Float))), Some(LdcType::Float(f32::INFINITY)));
+		assert_eq!(float(-5.5, 2.0, Insn::Remainder(RemainderInsn::new(PrimitiveType::Float))), Some(LdcType::Float(-1.5)));
+		match double(0.0, 0.0, Insn::Divide(DivideInsn::new(PrimitiveType::Double))) {
+			Some(LdcType::Double(x)) => assert!(x.is_nan()),
+			x => panic!("Expected NaN, found {:?}", x)
+		}
+		match double(f64::NAN, 1.0, Insn::Add(AddInsn::new(PrimitiveType::Double))) {
+			Some(LdcType::Double(x)) => assert!(x.is_nan()),
+			x => panic!("Expected NaN, found {:?}", x)
+		}
+		assert_eq!(double(1.0, 0.0, Insn::Remainder(RemainderInsn::new(PrimitiveType::Double))).map(|x| matches!(x, LdcType::Double(x) if x.is_nan())), Some(true));
+		match fold(vec![ldc(LdcType::Double(0.0)), Insn::Negate(NegateInsn::new(PrimitiveType::Double))]) {
+			Some(LdcType::Double(x)) => assert!(x == 0.0 && x.is_sign_negative()),
+			x => panic!("Expected -0.0, found {:?}", x)
+		}
+		// bitwise operations do not exist for floating point
+		assert_eq!(float(1.0, 2.0, Insn::And(AndInsn::new(IntegerType::Int))), None);
+	}
+	
+	#[test]
+	fn conversions() {
+		let conv = |x: LdcType, from, to| fold(vec![ldc(x), Insn::Convert(ConvertInsn::new(from, to))]);
+		use PrimitiveType::*;
+		assert_eq!(conv(LdcType::Int(0x1FF), Int, Byte), Some(LdcType::Int(-1)));
+		assert_eq!(conv(LdcType::Int(-1), Int, Char), Some(LdcType::Int(0xFFFF)));
+		assert_eq!(conv(LdcType::Int(0x18000), Int, Short), Some(LdcType::Int(-0x8000)));
+		assert_eq!(conv(LdcType::Int(-3), Int, Long), Some(LdcType::Long(-3)));
+		assert_eq!(conv(LdcType::Int(16_777_217), Int, Float), Some(LdcType::Float(16_777_216.0)));
+		assert_eq!(conv(LdcType::Long(0x1_0000_0005), Long, Int), Some(LdcType::Int(5)));
+		assert_eq!(conv(LdcType::Float(f32::NAN), Float, Int), Some(LdcType::Int(0)));
+		assert_eq!(conv(LdcType::Float(1e20), Float, Int), Some(LdcType::Int(i32::MAX)));
+		assert_eq!(conv(LdcType::Double(f64::NEG_INFINITY), Double, Long), Some(LdcType::Long(i64::MIN)));
+		assert_eq!(conv(LdcType::Double(-2.9), Double, Int), Some(LdcType::Int(-2)));
+		assert_eq!(conv(LdcType::Double(1e300), Double, Float), Some(LdcType::Float(f32::INFINITY)));
+		assert_eq!(conv(LdcType::Float(0.5), Float, Double), Some(LdcType::Double(0.5)));
+		// the constant does not match the source type
+		assert_eq!(conv(LdcType::Long(1), Int, Long), None);
+	}
+	
+	#[test]
+	fn chained_folds() {
+		let int = PrimitiveType::Int;
+		let mut list = InsnList::new();
+		list.insns = vec![
+			ldc(LdcType::Int(1)),
+			ldc(LdcType::Int(2)),
+			Insn::Add(AddInsn::new(int)),
+			ldc(LdcType::Int(3)),
+			Insn::Multiply(MultiplyInsn::new(int)),
+			Insn::Convert(ConvertInsn::new(int, PrimitiveType::Long)),
+		];
+		assert_eq!(list.fold_constants(), 3);
+		assert_eq!(list.insns, vec![ldc(LdcType::Long(9))]);
+	}
+	
+	#[test]
+	fn labels_block_folding() {
+		let int = PrimitiveType::Int;
+		let mut list = InsnList::new();
+		let label = list.new_label();
+		list.insns = vec![
+			ldc(LdcType::Int(2)),
+			Insn::Label(label),
+			ldc(LdcType::Int(3)),
+			Insn::Add(AddInsn::new(int)),
+		];
+		let expected = list.insns.clone();
+		assert_eq!(list.fold_constants(), 0);
+		assert_eq!(list.insns, expected);
+		
+		list.insns = vec![
+			ldc(LdcType::Int(2)),
+			ldc(LdcType::Int(3)),
+			Insn::Label(label),
+			Insn::Add(AddInsn::new(int)),
+		];
+		let expected = list.insns.clone();
+		assert_eq!(list.fold_constants(), 0);
+		assert_eq!(list.insns, expected);
+	}
+}
@@ -1,19 +1,37 @@
-use crate::ast::{Insn, LabelInsn};
+use crate::ast::*;
+use crate::visitor::InsnVisitor;
+use crate::error::{Result, ParserError};
+use crate::types::ClassName;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter,};
+use std::ops::Range;
 use std::slice::Iter;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InsnList {
 	pub insns: Vec<Insn>,
-	pub(crate) labels: u32
+	pub(crate) labels: u32,
+	/// Names of labels by id, only used to describe them. See [`InsnList::new_named_label`]
+	#[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "HashMap::is_empty"))]
+	pub(crate) label_names: HashMap<u32, String>,
+	/// Set by every method that changes the instructions, see [`CodeAttribute::is_modified`](crate::code::CodeAttribute::is_modified)
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub(crate) modified: bool
 }
 
-impl Default for InsnList {
-	fn default() -> Self {
-		InsnList {
-			insns: Vec::new(),
-			labels: 0
-		}
+/// Hands out labels for an [`InsnList`] while its instructions are borrowed by
+/// [`InsnList::map_insns`]
+pub struct LabelAllocator<'a> {
+	labels: &'a mut u32
+}
+
+impl LabelAllocator<'_> {
+	/// The given label will be valid for the lifetime of the list being mapped
+	pub fn new_label(&mut self) -> LabelInsn {
+		let id = *self.labels;
+		*self.labels += 1;
+		LabelInsn::new(id)
 	}
 }
 
@@ -26,10 +44,17 @@ impl InsnList {
 	pub fn with_capacity(capacity: usize) -> Self {
 		InsnList {
 			insns: Vec::with_capacity(capacity),
-			labels: 0
+			labels: 0,
+			label_names: HashMap::new(),
+			modified: false
 		}
 	}
 	
+	/// Records a change to the instructions
+	pub(crate) fn touch(&mut self) {
+		self.modified = true;
+	}
+	
 	/// The given label will be valid for the lifetime of this list
 	pub fn new_label(&mut self) -> LabelInsn {
 		let id = self.labels;
@@ -37,6 +62,130 @@ impl InsnList {
 		LabelInsn::new(id)
 	}
 	
+	/// Like [`new_label`](Self::new_label), but the label is shown by this name in disassembly and
+	/// errors
+	pub fn new_named_label(&mut self, name: &str) -> LabelInsn {
+		let label = self.new_label();
+		self.label_names.insert(label.id, name.to_string());
+		label
+	}
+	
+	pub fn label_name(&self, label: LabelInsn) -> Option<&str> {
+		self.label_names.get(&label.id).map(|x| x.as_str())
+	}
+	
+	/// The name of the label, or its id if it has none
+	pub(crate) fn describe_label(&self, label: LabelInsn) -> String {
+		match self.label_name(label) {
+			Some(name) => name.to_string(),
+			None => format!("L{}", label.id)
+		}
+	}
+	
+	/// Replaces every instruction with the instructions returned by `op`.
+	/// Returning an empty Vec removes the instruction, returning several inserts them in its place.
+	/// Any labels the new instructions need should be allocated through the given [`LabelAllocator`]
+	/// so that they stay unique within this list.
+	pub fn map_insns<F>(&mut self, mut op: F)
+		where F: FnMut(Insn, &mut LabelAllocator) -> Vec<Insn> {
+		let old = std::mem::take(&mut self.insns);
+		let mut insns = Vec::with_capacity(old.len());
+		let mut labels = LabelAllocator {
+			labels: &mut self.labels
+		};
+		for insn in old {
+			insns.extend(op(insn, &mut labels));
+		}
+		self.insns = insns;
+		self.touch();
+	}
+	
+	/// Appends a copy of `other.insns[range]` to this list. Labels defined in the range are
+	/// replaced by fresh labels of this list with the same names, and the returned map from old to new labels can be
+	/// used to copy across exception handlers or local variables.
+	///
+	/// Fails without modifying this list if an instruction in the range jumps to a label that is
+	/// not defined in the range.
+	pub fn splice_from(&mut self, other: &InsnList, range: Range<usize>) -> Result<HashMap<LabelInsn, LabelInsn>> {
+		let start = range.start;
+		let insns = other.insns.get(range.clone())
+			.ok_or_else(|| ParserError::other(format!("Range {:?} is out of bounds for {} instructions", range, other.len())))?;
+		
+		let defined: HashSet<LabelInsn> = insns.iter()
+			.filter_map(|insn| match insn {
+				Insn::Label(x) => Some(*x),
+				_ => None
+			})
+			.collect();
+		for (index, insn) in insns.iter().enumerate() {
+			if insn.jump_targets().iter().any(|target| !defined.contains(target)) {
+				return Err(ParserError::jump_out_of_range(start + index));
+			}
+		}
+		
+		let mut mapping = HashMap::with_capacity(defined.len());
+		self.touch();
+		self.insns.reserve(insns.len());
+		for insn in insns.iter() {
+			let mut insn = insn.clone();
+			insn.map_labels(|label| *mapping.entry(label).or_insert_with(|| {
+				let id = self.labels;
+				self.labels += 1;
+				LabelInsn::new(id)
+			}));
+			self.insns.push(insn);
+		}
+		for (old, new) in mapping.iter() {
+			if let Some(name) = other.label_names.get(&old.id) {
+				self.label_names.insert(new.id, name.clone());
+			}
+		}
+		Ok(mapping)
+	}
+	
+	pub fn accept<V: InsnVisitor + ?Sized>(&self, visitor: &mut V) {
+		for insn in self.insns.iter() {
+			visitor.visit_insn(insn);
+		}
+	}
+	
+	/// Every label jumped to by an instruction in this list, in order of first use
+	pub fn jump_targets(&self) -> Vec<LabelInsn> {
+		let mut targets = Vec::new();
+		for insn in self.insns.iter() {
+			for target in insn.jump_targets() {
+				if !targets.contains(&target) {
+					targets.push(target);
+				}
+			}
+		}
+		targets
+	}
+	
+	/// The position of the given label in this list
+	pub fn label_index(&self, label: LabelInsn) -> Option<usize> {
+		self.insns.iter().position(|insn| matches!(insn, Insn::Label(x) if *x == label))
+	}
+	
+	/// Whether both lists have the same instructions once labels are renumbered, so lists that only
+	/// differ in label ids (such as after a splice or a round trip) are equal. Each label must
+	/// correspond to exactly one label of the other list. Label names are ignored.
+	pub fn structurally_equal(&self, other: &InsnList) -> bool {
+		self.structurally_equal_with(other, &mut LabelBijection::default())
+	}
+	
+	/// Like [`structurally_equal`](Self::structurally_equal), continuing from labels already paired
+	pub(crate) fn structurally_equal_with(&self, other: &InsnList, labels: &mut LabelBijection) -> bool {
+		self.insns.len() == other.insns.len() && self.insns.iter().zip(other.insns.iter()).all(|(a, b)| labels.insns_equal(a, b))
+	}
+	
+	/// The most bytes these instructions take when written, see [`Insn::encoded_size`]
+	pub fn estimated_code_size(&self) -> Result<u32> {
+		self.insns.iter().try_fold(0u32, |pc, insn| {
+			pc.checked_add(insn.encoded_size(pc)?).ok_or_else(ParserError::too_many_instructions)
+		})
+	}
+	
 	pub fn iter(&self) -> Iter<'_, Insn> {
 		self.insns.iter()
 	}
@@ -48,8 +197,185 @@ impl InsnList {
 	pub fn is_empty(&self) -> bool {
 		self.insns.is_empty()
 	}
+	
+	/// Inserts the instruction at `index`, shifting the ones after it
+	pub fn insert(&mut self, index: usize, insn: Insn) {
+		self.touch();
+		self.insns.insert(index, insn);
+	}
+	
+	pub fn remove(&mut self, index: usize) -> Insn {
+		self.touch();
+		self.insns.remove(index)
+	}
+	
+	/// Puts the instruction at `index`, returning the one that was there
+	pub fn replace(&mut self, index: usize, insn: Insn) -> Insn {
+		self.touch();
+		std::mem::replace(&mut self.insns[index], insn)
+	}
+	
+	/// The instructions for any other change, which marks the list as modified
+	pub fn insns_mut(&mut self) -> &mut Vec<Insn> {
+		self.touch();
+		&mut self.insns
+	}
+}
+
+/// Shorthands that append a single instruction and return the list for chaining. Descriptors can be
+/// built with [`method_desc`](crate::types::method_desc) and [`Type::descriptor`](crate::types::Type::descriptor).
+///
+/// ```
+/// use classfile::insnlist::InsnList;
+/// use classfile::types::{Type, method_desc};
+///
+/// // System.out.println("Hello")
+/// let mut list = InsnList::new();
+/// list.get_static("java/lang/System", "out", "Ljava/io/PrintStream;")
+///     .ldc_string("Hello")
+///     .invoke_virtual("java/io/PrintStream", "println", &method_desc(&[Type::object("java/lang/String")], &Type::Void))
+///     .return_void();
+/// assert_eq!(list.len(), 4);
+/// ```
+impl InsnList {
+	/// Appends the given instruction
+	pub fn push(&mut self, insn: Insn) -> &mut Self {
+		self.touch();
+		self.insns.push(insn);
+		self
+	}
+	
+	fn invoke(&mut self, kind: InvokeType, class: &str, name: &str, descriptor: &str, interface_method: bool) -> &mut Self {
+		self.push(Insn::Invoke(Box::new(InvokeInsn::new(kind, ClassName::from(class), name.to_string(), descriptor.to_string(), interface_method))))
+	}
+	
+	/// Calls a static method of a class. Static interface methods need
+	/// [`invoke_static_interface`](Self::invoke_static_interface).
+	pub fn invoke_static(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.invoke(InvokeType::Static, class, name, descriptor, false)
+	}
+	
+	pub fn invoke_static_interface(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.invoke(InvokeType::Static, class, name, descriptor, true)
+	}
+	
+	pub fn invoke_virtual(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.invoke(InvokeType::Instance, class, name, descriptor, false)
+	}
+	
+	pub fn invoke_interface(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.invoke(InvokeType::Interface, class, name, descriptor, true)
+	}
+	
+	/// Calls a constructor, private method or super method of a class
+	pub fn invoke_special(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.invoke(InvokeType::Special, class, name, descriptor, false)
+	}
+	
+	pub fn get_static(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.push(Insn::GetField(Box::new(GetFieldInsn::new(false, ClassName::from(class), name.to_string(), descriptor.to_string()))))
+	}
+	
+	pub fn put_static(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.push(Insn::PutField(Box::new(PutFieldInsn::new(false, ClassName::from(class), name.to_string(), descriptor.to_string()))))
+	}
+	
+	pub fn get_field(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.push(Insn::GetField(Box::new(GetFieldInsn::new(true, ClassName::from(class), name.to_string(), descriptor.to_string()))))
+	}
+	
+	pub fn put_field(&mut self, class: &str, name: &str, descriptor: &str) -> &mut Self {
+		self.push(Insn::PutField(Box::new(PutFieldInsn::new(true, ClassName::from(class), name.to_string(), descriptor.to_string()))))
+	}
+	
+	pub fn ldc_string(&mut self, value: &str) -> &mut Self {
+		self.push(Insn::Ldc(LdcInsn::new(LdcType::String(value.to_string()))))
+	}
+	
+	/// Loads an int. Like every int constant, it is written as ldc from the constant pool, even
+	/// where iconst, bipush or sipush would be shorter.
+	pub fn ldc_int(&mut self, value: i32) -> &mut Self {
+		self.push(Insn::Ldc(LdcInsn::new(LdcType::Int(value))))
+	}
+	
+	/// Creates an uninitialised object, to be initialised by a call to one of its constructors
+	pub fn new_object(&mut self, class: &str) -> &mut Self {
+		self.push(Insn::NewObject(NewObjectInsn::new(ClassRef::from(class))))
+	}
+	
+	pub fn dup(&mut self) -> &mut Self {
+		self.push(Insn::Dup(DupInsn::new(1, 0)))
+	}
+	
+	pub fn pop(&mut self) -> &mut Self {
+		self.push(Insn::Pop(PopInsn::new(false)))
+	}
+	
+	pub fn load(&mut self, kind: OpType, index: u16) -> &mut Self {
+		self.push(Insn::LocalLoad(LocalLoadInsn::new(kind, index)))
+	}
+	
+	pub fn store(&mut self, kind: OpType, index: u16) -> &mut Self {
+		self.push(Insn::LocalStore(LocalStoreInsn::new(kind, index)))
+	}
+	
+	pub fn return_void(&mut self) -> &mut Self {
+		self.push(Insn::Return(ReturnInsn::new(ReturnType::Void)))
+	}
+	
+	pub fn return_value(&mut self, kind: ReturnType) -> &mut Self {
+		self.push(Insn::Return(ReturnInsn::new(kind)))
+	}
 }
 
+/// Pairs the labels of two instruction lists one to one
+#[derive(Default)]
+pub(crate) struct LabelBijection {
+	forward: HashMap<LabelInsn, LabelInsn>,
+	backward: HashMap<LabelInsn, LabelInsn>
+}
+
+impl LabelBijection {
+	/// Pairs `a` with `b`, failing if either is already paired with another label
+	pub(crate) fn pair(&mut self, a: LabelInsn, b: LabelInsn) -> bool {
+		match (self.forward.get(&a), self.backward.get(&b)) {
+			(None, None) => {
+				self.forward.insert(a, b);
+				self.backward.insert(b, a);
+				true
+			},
+			(Some(x), Some(y)) => *x == b && *y == a,
+			_ => false
+		}
+	}
+	
+	/// Whether the instructions are equal once the labels of `a` are replaced by those paired with
+	/// them, pairing any new labels in the order they are visited
+	pub(crate) fn insns_equal(&mut self, a: &Insn, b: &Insn) -> bool {
+		// in the order map_labels visits them
+		let labels = |insn: &Insn| match insn {
+			Insn::Label(x) => vec![*x],
+			_ => insn.jump_targets()
+		};
+		let (a_labels, b_labels) = (labels(a), labels(b));
+		if a_labels.is_empty() {
+			return a == b;
+		}
+		if a_labels.len() != b_labels.len() || !a_labels.iter().zip(b_labels.iter()).all(|(a, b)| self.pair(*a, *b)) {
+			return false;
+		}
+		let mut mapped = a.clone();
+		mapped.map_labels(|label| self.forward[&label]);
+		mapped == *b
+	}
+}
+
+/// Label names are not compared, as they only describe the labels
+impl PartialEq for InsnList {
+	fn eq(&self, other: &Self) -> bool {
+		self.insns == other.insns && self.labels == other.labels
+	}
+}
 
 impl Debug for InsnList {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -58,3 +384,199 @@ impl Debug for InsnList {
 			.finish()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn jump_targets_and_label_index() {
+		let mut list = InsnList::new();
+		let start = list.new_label();
+		let end = list.new_label();
+		list.insns.push(Insn::Label(start));
+		list.insns.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, end)));
+		list.insns.push(Insn::Nop(NopInsn::new()));
+		list.insns.push(Insn::Jump(JumpInsn::new(start)));
+		list.insns.push(Insn::Jump(JumpInsn::new(end)));
+		list.insns.push(Insn::Label(end));
+		
+		assert_eq!(list.jump_targets(), vec![end, start]);
+		assert_eq!(list.label_index(start), Some(0));
+		assert_eq!(list.label_index(end), Some(5));
+		assert_eq!(list.label_index(LabelInsn::new(7)), None);
+	}
+	
+	/// `while (x != 0) { x++; }` followed by a return, as labels `[start, end]`
+	fn looping() -> (InsnList, [LabelInsn; 2]) {
+		let mut list = InsnList::new();
+		// shift the ids so they differ from the destination's
+		for _ in 0..5 {
+			list.new_label();
+		}
+		let start = list.new_label();
+		let end = list.new_label();
+		list.insns.push(Insn::Label(start));
+		list.insns.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, end)));
+		list.insns.push(Insn::IncrementInt(IncrementIntInsn::new(0, 1)));
+		list.insns.push(Insn::Jump(JumpInsn::new(start)));
+		list.insns.push(Insn::Label(end));
+		list.insns.push(Insn::Return(ReturnInsn::new(ReturnType::Void)));
+		(list, [start, end])
+	}
+	
+	#[test]
+	fn splice_loop_into_other_method() {
+		let (source, [start, end]) = looping();
+		let mut dest = InsnList::new();
+		let existing = dest.new_label();
+		dest.insns.push(Insn::Label(existing));
+		dest.insns.push(Insn::Nop(NopInsn::new()));
+		
+		let mapping = dest.splice_from(&source, 0..5).unwrap();
+		assert_eq!(mapping.len(), 2);
+		let (new_start, new_end) = (mapping[&start], mapping[&end]);
+		assert!(new_start != existing && new_end != existing && new_start != new_end);
+		assert_eq!(dest.insns[2..], [
+			Insn::Label(new_start),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, new_end)),
+			Insn::IncrementInt(IncrementIntInsn::new(0, 1)),
+			Insn::Jump(JumpInsn::new(new_start)),
+			Insn::Label(new_end)
+		]);
+		// every jump stays within the copied instructions
+		for target in dest.jump_targets() {
+			assert!(dest.label_index(target).unwrap() >= 2);
+		}
+		// labels handed out later do not collide with the copied ones
+		let next = dest.new_label();
+		assert!(next != new_start && next != new_end);
+	}
+	
+	#[test]
+	fn label_names_survive_splice_and_map() {
+		let mut source = InsnList::new();
+		let head = source.new_named_label("loop_head");
+		let exit = source.new_label();
+		source.insns = vec![
+			Insn::Label(head),
+			Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, exit)),
+			Insn::Jump(JumpInsn::new(head)),
+			Insn::Label(exit)
+		];
+		assert_eq!(source.label_name(head), Some("loop_head"));
+		assert_eq!(source.label_name(exit), None);
+		
+		let mut dest = InsnList::new();
+		dest.new_named_label("unrelated");
+		let mapping = dest.splice_from(&source, 0..4).unwrap();
+		assert_ne!(mapping[&head], head);
+		assert_eq!(dest.label_name(mapping[&head]), Some("loop_head"));
+		assert_eq!(dest.label_name(mapping[&exit]), None);
+		
+		dest.map_insns(|insn, labels| match insn {
+			Insn::Jump(_) => vec![Insn::Label(labels.new_label()), insn],
+			insn => vec![insn]
+		});
+		assert_eq!(dest.label_name(mapping[&head]), Some("loop_head"));
+		assert_eq!(dest.describe_label(mapping[&exit]), format!("L{}", mapping[&exit].id));
+		
+		// names only describe labels
+		let mut renamed = dest.clone();
+		renamed.label_names.clear();
+		assert_eq!(renamed, dest);
+	}
+	
+	#[test]
+	fn splice_rejects_jumps_out_of_range() {
+		let (source, _) = looping();
+		let mut dest = InsnList::new();
+		// the loop body jumps back to the start label, which is not copied
+		match dest.splice_from(&source, 1..4) {
+			Err(ParserError::JumpOutOfRange { index: 1 }) => {},
+			x => panic!("{:?}", x)
+		}
+		assert!(dest.is_empty());
+		assert!(dest.splice_from(&source, 3..9).is_err());
+	}
+	
+	#[test]
+	fn emission_helpers() {
+		let mut list = InsnList::new();
+		list.new_object("java/lang/StringBuilder")
+			.dup()
+			.invoke_special("java/lang/StringBuilder", "<init>", "()V")
+			.load(OpType::Int, 1)
+			.invoke_virtual("java/lang/StringBuilder", "append", "(I)Ljava/lang/StringBuilder;")
+			.invoke_interface("java/lang/CharSequence", "length", "()I")
+			.invoke_static("java/lang/Integer", "valueOf", "(I)Ljava/lang/Integer;")
+			.put_static("A", "boxed", "Ljava/lang/Integer;")
+			.load(OpType::Reference, 0)
+			.ldc_int(-1)
+			.put_field("A", "count", "I")
+			.ldc_string("x")
+			.pop()
+			.return_value(ReturnType::Int);
+		assert_eq!(list.insns, vec![
+			Insn::NewObject(NewObjectInsn::new(ClassRef::from("java/lang/StringBuilder"))),
+			Insn::Dup(DupInsn::new(1, 0)),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Special, ClassName::from("java/lang/StringBuilder"), String::from("<init>"), String::from("()V"), false))),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Int, 1)),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, ClassName::from("java/lang/StringBuilder"), String::from("append"), String::from("(I)Ljava/lang/StringBuilder;"), false))),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Interface, ClassName::from("java/lang/CharSequence"), String::from("length"), String::from("()I"), true))),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Static, ClassName::from("java/lang/Integer"), String::from("valueOf"), String::from("(I)Ljava/lang/Integer;"), false))),
+			Insn::PutField(Box::new(PutFieldInsn::new(false, ClassName::from("A"), String::from("boxed"), String::from("Ljava/lang/Integer;")))),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0)),
+			Insn::Ldc(LdcInsn::new(LdcType::Int(-1))),
+			Insn::PutField(Box::new(PutFieldInsn::new(true, ClassName::from("A"), String::from("count"), String::from("I")))),
+			Insn::Ldc(LdcInsn::new(LdcType::String(String::from("x")))),
+			Insn::Pop(PopInsn::new(false)),
+			Insn::Return(ReturnInsn::new(ReturnType::Int))
+		]);
+		
+		let mut list = InsnList::new();
+		list.get_field("A", "count", "I").store(OpType::Int, 2).invoke_static_interface("java/util/List", "of", "()Ljava/util/List;").return_void();
+		assert!(matches!(&list.insns[0], Insn::GetField(x) if x.instance));
+		assert!(matches!(&list.insns[2], Insn::Invoke(x) if x.kind == InvokeType::Static && x.interface_method));
+	}
+	
+	#[test]
+	fn structural_equality_ignores_label_ids() {
+		// a lookupswitch whose cases jump to the labels at the given positions
+		let list = |ids: [u32; 3], cases: [usize; 2]| {
+			let labels: Vec<LabelInsn> = ids.iter().map(|id| LabelInsn::new(*id)).collect();
+			let mut switch = LookupSwitchInsn::new(labels[0]);
+			switch.cases.insert(1, labels[cases[0]]);
+			switch.cases.insert(2, labels[cases[1]]);
+			let mut list = InsnList::new();
+			list.insns = vec![
+				Insn::LookupSwitch(switch),
+				Insn::Label(labels[0]),
+				Insn::Jump(JumpInsn::new(labels[2])),
+				Insn::Label(labels[1]),
+				Insn::Nop(NopInsn::new()),
+				Insn::Label(labels[2]),
+				Insn::Return(ReturnInsn::new(ReturnType::Void))
+			];
+			list.labels = 8;
+			list
+		};
+		let original = list([0, 1, 2], [1, 2]);
+		let renumbered = list([7, 3, 5], [1, 2]);
+		assert_ne!(original, renumbered);
+		assert!(original.structurally_equal(&renumbered));
+		assert!(renumbered.structurally_equal(&original));
+		
+		// a case jumping elsewhere
+		let retargeted = list([7, 3, 5], [1, 1]);
+		assert!(!original.structurally_equal(&retargeted));
+		assert!(!retargeted.structurally_equal(&original));
+		// one label standing for two
+		let merged = list([0, 1, 1], [1, 2]);
+		assert!(!original.structurally_equal(&merged));
+		assert!(!merged.structurally_equal(&original));
+		let mut longer = renumbered.clone();
+		longer.push(Insn::Nop(NopInsn::new()));
+		assert!(!original.structurally_equal(&longer));
+	}
+}
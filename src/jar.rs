@@ -0,0 +1,410 @@
+//! Reading and writing the classes inside a jar, enabled with the `jar` feature
+
+use crate::classfile::{ClassFile, ParseOptions};
+use crate::constantpool::{ConstantPool, ConstantPoolWriter};
+use crate::error::{Result, ParserError};
+use crate::search::{SearchQuery, Match};
+use crate::utils::READ_CHUNK;
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+use zip::{ZipArchive, ZipWriter, CompressionMethod};
+use zip::result::ZipError;
+use zip::write::FileOptions;
+
+const VERSIONS_PREFIX: &str = "META-INF/versions/";
+
+impl From<ZipError> for ParserError {
+	fn from(err: ZipError) -> Self {
+		match err {
+			ZipError::Io(x) => ParserError::io(x),
+			x => ParserError::other(format!("Invalid jar: {}", x))
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JarOptions {
+	/// Do not yield `module-info.class` entries
+	pub skip_module_info: bool,
+	/// Resolve multi-release jars for this Java feature release (e.g. 11): for every class the
+	/// entry from the highest `META-INF/versions/N` directory with N no greater than the release
+	/// is used in place of the base entry. If None, every entry is yielded as is.
+	pub release: Option<u32>,
+	pub parse: ParseOptions
+}
+
+pub struct JarReader<R: Read + Seek> {
+	archive: ZipArchive<R>,
+	options: JarOptions
+}
+
+impl <R: Read + Seek> JarReader<R> {
+	pub fn new(rdr: R) -> Result<Self> {
+		JarReader::with_options(rdr, JarOptions::default())
+	}
+	
+	pub fn with_options(rdr: R, options: JarOptions) -> Result<Self> {
+		Ok(JarReader {
+			archive: ZipArchive::new(rdr)?,
+			options
+		})
+	}
+	
+	/// Parses every class in the jar, along with the constant pool it was parsed from. The name
+	/// yielded is that of the zip entry. Pass the pool to [`JarWriter::write_class_with_pool`] to
+	/// write the class back.
+	///
+	/// A class that fails to read or parse is yielded as an error without ending iteration.
+	pub fn classes(&mut self) -> JarClasses<'_, R> {
+		let mut chosen: Vec<(String, u32, usize)> = Vec::new();
+		// class name to its position in `chosen`
+		let mut by_class: HashMap<String, usize> = HashMap::new();
+		for (index, name) in self.entry_names().into_iter().enumerate() {
+			let name = match name {
+				Some(name) if name.ends_with(".class") => name,
+				_ => continue
+			};
+			let name = name.as_str();
+			let (class, version) = match (self.options.release, versioned(name)) {
+				(Some(release), Some((version, class))) => {
+					if version > release {
+						continue;
+					}
+					(class, version)
+				},
+				(_, _) => (name, 0)
+			};
+			if self.options.skip_module_info && class.rsplit('/').next() == Some("module-info.class") {
+				continue;
+			}
+			
+			match by_class.get(class) {
+				Some(i) if chosen[*i].1 >= version => {},
+				Some(i) => chosen[*i] = (name.to_string(), version, index),
+				None => {
+					by_class.insert(class.to_string(), chosen.len());
+					chosen.push((name.to_string(), version, index));
+				}
+			}
+		}
+		chosen.sort_unstable_by_key(|(_, _, index)| *index);
+		
+		JarClasses {
+			bytes: JarClassBytes {
+				reader: self,
+				entries: chosen.into_iter().map(|(name, _, index)| (name, index)).collect::<Vec<_>>().into_iter()
+			}
+		}
+	}
+	
+	/// Reads every class that [`classes`](Self::classes) would parse, without parsing it
+	pub fn class_bytes(&mut self) -> JarClassBytes<'_, R> {
+		self.classes().bytes
+	}
+	
+	/// [`ClassFile::search`] over every class that [`classes`](Self::classes) would parse, parsing
+	/// one entry at a time. Only entries with matches, or that failed to parse, are yielded.
+	pub fn search<'a>(&'a mut self, query: &'a SearchQuery) -> JarSearch<'a, R> {
+		JarSearch {
+			classes: self.classes(),
+			query
+		}
+	}
+	
+	/// Reads every entry that is not a class or a directory, so that it can be passed through to
+	/// a [`JarWriter`]
+	pub fn resources(&mut self) -> Vec<(String, Result<Vec<u8>>)> {
+		let mut resources = Vec::new();
+		for index in 0..self.archive.len() {
+			let name = match self.archive.by_index(index) {
+				Ok(file) if file.is_dir() || file.name().ends_with(".class") => continue,
+				Ok(file) => file.name().to_string(),
+				Err(err) => {
+					resources.push((format!("#{}", index), Err(err.into())));
+					continue;
+				}
+			};
+			let bytes = self.read_entry(index);
+			resources.push((name, bytes));
+		}
+		resources
+	}
+	
+	/// Entry names in archive order, the names from `ZipArchive::file_names` are unordered
+	fn entry_names(&mut self) -> Vec<Option<String>> {
+		(0..self.archive.len())
+			.map(|index| self.archive.by_index(index).ok().map(|file| file.name().to_string()))
+			.collect()
+	}
+	
+	fn read_entry(&mut self, index: usize) -> Result<Vec<u8>> {
+		let mut file = self.archive.by_index(index)?;
+		// the size is only what the entry claims
+		let mut bytes = Vec::with_capacity((file.size() as usize).min(READ_CHUNK));
+		file.read_to_end(&mut bytes)?;
+		Ok(bytes)
+	}
+}
+
+/// Splits `META-INF/versions/N/name` into N and name
+fn versioned(name: &str) -> Option<(u32, &str)> {
+	let rest = name.strip_prefix(VERSIONS_PREFIX)?;
+	let slash = rest.find('/')?;
+	let version = rest[..slash].parse().ok()?;
+	Some((version, &rest[slash + 1..]))
+}
+
+pub struct JarClasses<'a, R: Read + Seek> {
+	bytes: JarClassBytes<'a, R>
+}
+
+impl <R: Read + Seek> Iterator for JarClasses<'_, R> {
+	type Item = (String, Result<(ClassFile, ConstantPool)>);
+	
+	fn next(&mut self) -> Option<Self::Item> {
+		let (name, bytes) = self.bytes.next()?;
+		let options = &self.bytes.reader.options.parse;
+		let class = bytes.and_then(|bytes| ClassFile::parse_bytes_with_pool(&bytes, options));
+		Some((name, class))
+	}
+	
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.bytes.size_hint()
+	}
+}
+
+pub struct JarSearch<'a, R: Read + Seek> {
+	classes: JarClasses<'a, R>,
+	query: &'a SearchQuery
+}
+
+impl <R: Read + Seek> Iterator for JarSearch<'_, R> {
+	type Item = (String, Result<Vec<Match>>);
+	
+	fn next(&mut self) -> Option<Self::Item> {
+		for (name, class) in &mut self.classes {
+			match class {
+				Ok((class, _)) => {
+					let matches = class.search(self.query);
+					if !matches.is_empty() {
+						return Some((name, Ok(matches)));
+					}
+				}
+				Err(err) => return Some((name, Err(err)))
+			}
+		}
+		None
+	}
+}
+
+pub struct JarClassBytes<'a, R: Read + Seek> {
+	reader: &'a mut JarReader<R>,
+	entries: std::vec::IntoIter<(String, usize)>
+}
+
+impl <R: Read + Seek> Iterator for JarClassBytes<'_, R> {
+	type Item = (String, Result<Vec<u8>>);
+	
+	fn next(&mut self) -> Option<Self::Item> {
+		let (name, index) = self.entries.next()?;
+		Some((name, self.reader.read_entry(index)))
+	}
+	
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.entries.size_hint()
+	}
+}
+
+pub struct JarWriter<W: Write + Seek> {
+	zip: ZipWriter<W>,
+	options: FileOptions
+}
+
+impl <W: Write + Seek> JarWriter<W> {
+	pub fn new(wtr: W) -> Self {
+		JarWriter {
+			zip: ZipWriter::new(wtr),
+			options: FileOptions::default().compression_method(CompressionMethod::Deflated)
+		}
+	}
+	
+	/// Writes the class to the entry matching its name, e.g. `java/lang/Object.class`
+	///
+	/// The class is written with a new constant pool, see [`ClassFile::write`], which fails for
+	/// most compiled classes. Write a class read from a jar with
+	/// [`write_class_with_pool`](Self::write_class_with_pool) instead.
+	pub fn write_class(&mut self, class: &ClassFile) -> Result<()> {
+		self.write_class_as(class.expected_file_name(), class)
+	}
+	
+	/// Writes the class to the given entry, for example one inside `META-INF/versions`. Like
+	/// [`write_class`](Self::write_class), the class is written with a new constant pool.
+	pub fn write_class_as<S: Into<String>>(&mut self, name: S, class: &ClassFile) -> Result<()> {
+		let mut bytes = Vec::new();
+		class.write(&mut bytes)?;
+		self.write_resource(name, &bytes)
+	}
+	
+	/// Writes the class to the entry matching its name, keeping the constant pool it was parsed
+	/// from, see [`ClassFile::write_with_pool`]
+	pub fn write_class_with_pool(&mut self, class: &ClassFile, constant_pool: &ConstantPool) -> Result<()> {
+		self.write_class_as_with_pool(class.expected_file_name(), class, constant_pool)
+	}
+	
+	/// Writes the class to the given entry, keeping the constant pool it was parsed from
+	pub fn write_class_as_with_pool<S: Into<String>>(&mut self, name: S, class: &ClassFile, constant_pool: &ConstantPool) -> Result<()> {
+		let mut bytes = Vec::new();
+		class.write_with_pool(&mut bytes, ConstantPoolWriter::from_pool(constant_pool))?;
+		self.write_resource(name, &bytes)
+	}
+	
+	/// Writes any other file, such as `META-INF/MANIFEST.MF`
+	pub fn write_resource<S: Into<String>>(&mut self, name: S, bytes: &[u8]) -> Result<()> {
+		self.zip.start_file(name, self.options)?;
+		self.zip.write_all(bytes)?;
+		Ok(())
+	}
+	
+	/// Writes the central directory, the jar is not valid until this is called
+	pub fn finish(mut self) -> Result<W> {
+		Ok(self.zip.finish()?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::version::{ClassVersion, MajorVersion};
+	use crate::types::ClassName;
+	use crate::access::ClassAccessFlags;
+	use std::io::Cursor;
+	
+	fn class(name: &str, major: MajorVersion) -> ClassFile {
+		ClassFile {
+			magic: 0xCAFEBABE,
+			version: ClassVersion { major, minor: 0 },
+			access_flags: ClassAccessFlags::PUBLIC,
+			this_class: ClassName::from(name),
+			super_class: Some(ClassName::from("java/lang/Object")),
+			interfaces: Vec::new(),
+			fields: Vec::new(),
+			methods: Vec::new(),
+			attributes: Vec::new(),
+			original_indices: None,
+			partial: None,
+			modified: Default::default()
+		}
+	}
+	
+	fn fixture() -> Vec<u8> {
+		let mut jar = JarWriter::new(Cursor::new(Vec::new()));
+		jar.write_resource("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0\r\nMulti-Release: true\r\n").unwrap();
+		jar.write_class(&class("a/A", MajorVersion::JAVA_8)).unwrap();
+		jar.write_class(&class("a/B", MajorVersion::JAVA_8)).unwrap();
+		jar.write_resource("a/Broken.class", &[0xCA, 0xFE]).unwrap();
+		jar.write_class(&class("module-info", MajorVersion::JAVA_9)).unwrap();
+		jar.write_class_as("META-INF/versions/9/a/A.class", &class("a/A", MajorVersion::JAVA_9)).unwrap();
+		jar.write_class_as("META-INF/versions/11/a/A.class", &class("a/A", MajorVersion::JAVA_11)).unwrap();
+		jar.write_resource("a/data.txt", b"hello").unwrap();
+		jar.finish().unwrap().into_inner()
+	}
+	
+	fn names(jar: &[u8], options: JarOptions) -> Vec<(String, bool)> {
+		let mut reader = JarReader::with_options(Cursor::new(jar), options).unwrap();
+		reader.classes().map(|(name, class)| (name, class.is_ok())).collect()
+	}
+	
+	#[test]
+	fn round_trip() {
+		let jar = fixture();
+		let mut reader = JarReader::new(Cursor::new(&jar)).unwrap();
+		let classes: Vec<(String, Result<(ClassFile, ConstantPool)>)> = reader.classes().collect();
+		assert_eq!(classes.len(), 6);
+		assert_eq!(classes[0].1.as_ref().unwrap().0, class("a/A", MajorVersion::JAVA_8));
+		// a broken entry does not stop the rest being read
+		assert_eq!(classes[2].0, "a/Broken.class");
+		assert!(classes[2].1.is_err());
+		assert_eq!(classes[3].1.as_ref().unwrap().0.this_class, "module-info");
+		
+		let resources = reader.resources();
+		let resources: Vec<(&str, &[u8])> = resources.iter()
+			.map(|(name, bytes)| (name.as_str(), bytes.as_ref().unwrap().as_slice()))
+			.collect();
+		assert_eq!(resources, vec![
+			("META-INF/MANIFEST.MF", &b"Manifest-Version: 1.0\r\nMulti-Release: true\r\n"[..]),
+			("a/data.txt", &b"hello"[..])
+		]);
+		
+		let bytes: Vec<(String, Result<Vec<u8>>)> = reader.class_bytes().collect();
+		assert_eq!(bytes.len(), 6);
+		assert_eq!(bytes[2].0, "a/Broken.class");
+		for ((name, bytes), (class_name, class)) in bytes.iter().zip(classes.iter()) {
+			assert_eq!(name, class_name);
+			let parsed = ClassFile::parse(&mut Cursor::new(bytes.as_ref().unwrap()));
+			assert_eq!(parsed.ok().as_ref(), class.as_ref().ok().map(|(class, _)| class));
+		}
+	}
+	
+	#[test]
+	fn compiled_class_round_trips() {
+		let snapshot = include_bytes!("../classes/snapshot/Snapshot.class");
+		let mut jar = JarWriter::new(Cursor::new(Vec::new()));
+		jar.write_resource("Snapshot.class", snapshot).unwrap();
+		let jar = jar.finish().unwrap().into_inner();
+		
+		let options = JarOptions {
+			parse: ParseOptions { keep_original_code: true, ..Default::default() },
+			..Default::default()
+		};
+		let mut reader = JarReader::with_options(Cursor::new(&jar), options).unwrap();
+		let (class, constant_pool) = reader.classes().next().unwrap().1.unwrap();
+		let mut jar = JarWriter::new(Cursor::new(Vec::new()));
+		// a new pool would leave the indices inside its StackMapTable stale
+		assert!(jar.write_class(&class).is_err());
+		jar.write_class_with_pool(&class, &constant_pool).unwrap();
+		let jar = jar.finish().unwrap().into_inner();
+		
+		let mut reader = JarReader::new(Cursor::new(&jar)).unwrap();
+		let written: Vec<(String, Result<Vec<u8>>)> = reader.class_bytes().collect();
+		assert_eq!(written.len(), 1);
+		assert_eq!(written[0].0, "Snapshot.class");
+		let written = written[0].1.as_ref().unwrap();
+		assert_eq!(written.len(), snapshot.len());
+		assert_eq!(ClassFile::parse_bytes(written).unwrap(), ClassFile::parse_bytes(snapshot).unwrap());
+	}
+	
+	#[test]
+	fn multi_release() {
+		let jar = fixture();
+		let options = |release| JarOptions {
+			skip_module_info: true,
+			release,
+			..Default::default()
+		};
+		assert_eq!(names(&jar, options(Some(8))), vec![
+			(String::from("a/A.class"), true),
+			(String::from("a/B.class"), true),
+			(String::from("a/Broken.class"), false)
+		]);
+		assert_eq!(names(&jar, options(Some(10))), vec![
+			(String::from("a/B.class"), true),
+			(String::from("a/Broken.class"), false),
+			(String::from("META-INF/versions/9/a/A.class"), true)
+		]);
+		assert_eq!(names(&jar, options(Some(17)))[2].0, "META-INF/versions/11/a/A.class");
+		assert_eq!(names(&jar, options(None)).len(), 5);
+	}
+	
+	#[test]
+	fn search() {
+		let jar = fixture();
+		let mut reader = JarReader::new(Cursor::new(&jar)).unwrap();
+		let query = SearchQuery::exact("a/B");
+		let found: Vec<(String, Result<Vec<Match>>)> = reader.search(&query).collect();
+		assert_eq!(found.len(), 2);
+		assert_eq!(found[0].0, "a/B.class");
+		assert_eq!(found[0].1.as_ref().unwrap()[0].text, "a/B");
+		assert_eq!(found[1].0, "a/Broken.class");
+		assert!(found[1].1.is_err());
+	}
+}
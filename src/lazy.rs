@@ -0,0 +1,357 @@
+//! Locating methods inside a class file without parsing them, so that single methods can be
+//! replaced without rewriting the rest of the file
+
+use crate::classfile::ClassFile;
+use crate::constantpool::{ConstantPool, ConstantPoolWriter};
+use crate::version::ClassVersion;
+use crate::code::CodeAttribute;
+use crate::error::{Result, ParserError, ErrorContext};
+use crate::path::PathSegment;
+use crate::Serializable;
+use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
+use std::io::{Cursor, Seek, SeekFrom};
+use std::ops::Range;
+
+/// A class file of which only the constant pool and the layout of the methods and class
+/// attributes have been parsed
+pub struct LazyClassFile<'a> {
+	bytes: &'a [u8],
+	pub version: ClassVersion,
+	pub constant_pool: ConstantPool,
+	/// Span of the constant pool, including its count
+	pub constant_pool_span: Range<usize>,
+	pub methods: Vec<MethodSpan>,
+	/// The attributes of the class itself
+	pub attributes: Vec<AttributeSpan>,
+	/// Position of the class attribute count, right after the methods
+	attributes_start: usize
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MethodSpan {
+	pub name: String,
+	pub descriptor: String,
+	/// Span of the whole method_info
+	pub span: Range<usize>,
+	pub attributes: Vec<AttributeSpan>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeSpan {
+	pub name: String,
+	/// Span of the whole attribute, including its name and length
+	pub span: Range<usize>
+}
+
+impl ClassFile {
+	/// Reads the constant pool and the position of every method, method attribute and class
+	/// attribute, without parsing any attribute contents
+	pub fn parse_lazy(bytes: &[u8]) -> Result<LazyClassFile<'_>> {
+		let mut rdr = Cursor::new(bytes);
+		LazyClassFile::parse(&mut rdr).map_err(|err| err.with_offset(rdr.position()))
+	}
+	
+	/// Replaces the Code attribute of each (name, descriptor, code) method, copying everything
+	/// else from the original bytes. Constants the new code needs are appended to the end of the
+	/// constant pool, so existing constants keep their indices and any unknown attributes stay
+	/// valid. Bootstrap methods the new code needs are likewise appended to the BootstrapMethods
+	/// attribute, which is added if the class has none. Fails if a method is missing or patched
+	/// more than once.
+	pub fn write_patched(original_bytes: &[u8], patches: &[(&str, &str, CodeAttribute)]) -> Result<Vec<u8>> {
+		ClassFile::parse_lazy(original_bytes)?.write_patched(patches)
+	}
+}
+
+impl <'a> LazyClassFile<'a> {
+	fn parse(rdr: &mut Cursor<&'a [u8]>) -> Result<Self> {
+		let magic = rdr.read_u32::<BigEndian>().map_err(ParserError::from).context(|| "magic")?;
+		if magic != 0xCAFEBABE {
+			return Err(ParserError::at("magic", ParserError::unrecognised("header", magic.to_string())));
+		}
+		let version = ClassVersion::parse(rdr).context(|| "version")?;
+		let pool_start = rdr.position() as usize;
+		let constant_pool = ConstantPool::parse(rdr)?;
+		let constant_pool_span = pool_start..rdr.position() as usize;
+		
+		// access flags, this class and super class
+		rdr.seek(SeekFrom::Current(6))?;
+		let num_interfaces = rdr.read_u16::<BigEndian>().map_err(ParserError::from).context(|| "interfaces")?;
+		rdr.seek(SeekFrom::Current(num_interfaces as i64 * 2))?;
+		
+		let num_fields = rdr.read_u16::<BigEndian>().map_err(ParserError::from).context(|| "fields")?;
+		for i in 0..num_fields {
+			skip_member(rdr, &constant_pool).context(|| format!("fields[{}]", i))?;
+		}
+		
+		let num_methods = rdr.read_u16::<BigEndian>().map_err(ParserError::from).context(|| "methods")? as usize;
+		let mut methods = Vec::with_capacity(num_methods);
+		for i in 0..num_methods {
+			let start = rdr.position() as usize;
+			let (name, descriptor, attributes) = skip_member(rdr, &constant_pool).context(|| format!("methods[{}]", i))?;
+			methods.push(MethodSpan {
+				name,
+				descriptor,
+				span: start..rdr.position() as usize,
+				attributes
+			});
+		}
+		let attributes_start = rdr.position() as usize;
+		let attributes = skip_attributes(rdr, &constant_pool).context(|| "attributes")?;
+		
+		Ok(LazyClassFile {
+			bytes: rdr.get_ref(),
+			version,
+			constant_pool,
+			constant_pool_span,
+			methods,
+			attributes,
+			attributes_start
+		})
+	}
+	
+	pub fn method(&self, name: &str, descriptor: &str) -> Option<&MethodSpan> {
+		self.methods.iter().find(|m| m.name == name && m.descriptor == descriptor)
+	}
+	
+	/// See [`ClassFile::write_patched`]
+	pub fn write_patched(&self, patches: &[(&str, &str, CodeAttribute)]) -> Result<Vec<u8>> {
+		let mut constant_pool = ConstantPoolWriter::from_pool(&self.constant_pool);
+		// new entries go after those the existing dynamic constants refer to
+		let bootstrap_methods = self.attributes.iter().find(|attribute| attribute.name == "BootstrapMethods");
+		if let Some(attribute) = bootstrap_methods {
+			// after the name and length
+			constant_pool.add_bootstrap_methods(&self.bytes[attribute.span.start + 6..attribute.span.end])
+				.context(|| "BootstrapMethods")?;
+		}
+		let num_bootstrap_methods = constant_pool.bootstrap_methods().len();
+		
+		// the replacement of each patched method_info, and of the class attributes that change
+		let mut replaced: Vec<(Range<usize>, Vec<u8>)> = Vec::with_capacity(patches.len() + 2);
+		let mut patched: Vec<&MethodSpan> = Vec::with_capacity(patches.len());
+		for (name, descriptor, code) in patches.iter() {
+			let method = self.method(name, descriptor)
+				.ok_or_else(|| ParserError::other(format!("No method {}{} to patch", name, descriptor)))?;
+			if patched.iter().any(|x| std::ptr::eq(*x, method)) {
+				return Err(ParserError::other(format!("Method {}{} is patched more than once", name, descriptor)));
+			}
+			let bytes = self.patch_method(method, code, &mut constant_pool)
+				.context(|| format!("{}{}", name, descriptor))?;
+			patched.push(method);
+			replaced.push((method.span.clone(), bytes));
+		}
+		
+		if constant_pool.bootstrap_methods().len() > num_bootstrap_methods {
+			let mut attribute = Vec::new();
+			attribute.write_u16::<BigEndian>(constant_pool.utf8("BootstrapMethods"))?;
+			let mut buf = Vec::new();
+			constant_pool.write_bootstrap_methods(&mut buf)?;
+			attribute.write_u32::<BigEndian>(buf.len() as u32)?;
+			attribute.extend(buf);
+			match bootstrap_methods {
+				Some(existing) => replaced.push((existing.span.clone(), attribute)),
+				None => {
+					// one more attribute, added after the others
+					let count = self.attributes_start;
+					let end = self.attributes.last().map_or(count + 2, |last| last.span.end);
+					let mut num_attributes = Vec::new();
+					num_attributes.write_u16::<BigEndian>(self.attributes.len() as u16 + 1)?;
+					replaced.push((count..count + 2, num_attributes));
+					replaced.push((end..end, attribute));
+				}
+			}
+		}
+		replaced.sort_by_key(|(span, _)| span.start);
+		
+		let mut out = Vec::with_capacity(self.bytes.len() + 64);
+		out.extend_from_slice(&self.bytes[..self.constant_pool_span.start]);
+		constant_pool.write(&mut out)?;
+		let mut copied = self.constant_pool_span.end;
+		for (span, bytes) in replaced.iter() {
+			out.extend_from_slice(&self.bytes[copied..span.start]);
+			out.extend_from_slice(bytes);
+			copied = span.end;
+		}
+		out.extend_from_slice(&self.bytes[copied..]);
+		Ok(out)
+	}
+	
+	/// The method_info with its Code attribute replaced, or added if it had none
+	fn patch_method(&self, method: &MethodSpan, code: &CodeAttribute, constant_pool: &mut ConstantPoolWriter) -> Result<Vec<u8>> {
+		let mut code_attribute = Vec::new();
+		code_attribute.write_u16::<BigEndian>(constant_pool.utf8("Code"))?;
+		let mut buf = Vec::new();
+		code.write(&mut buf, constant_pool)?;
+		code_attribute.write_u32::<BigEndian>(buf.len() as u32)?;
+		code_attribute.extend(buf);
+		
+		let mut out = Vec::with_capacity(method.span.len() + code_attribute.len());
+		// access flags, name and descriptor
+		out.extend_from_slice(&self.bytes[method.span.start..method.span.start + 6]);
+		let has_code = method.attributes.iter().any(|attribute| attribute.name == "Code");
+		let count = method.attributes.len() + if has_code { 0 } else { 1 };
+		out.write_u16::<BigEndian>(count as u16)?;
+		for attribute in method.attributes.iter() {
+			if attribute.name == "Code" {
+				out.extend_from_slice(&code_attribute);
+			} else {
+				out.extend_from_slice(&self.bytes[attribute.span.clone()]);
+			}
+		}
+		if !has_code {
+			out.extend(code_attribute);
+		}
+		Ok(out)
+	}
+}
+
+/// Skips over a field_info or method_info, returning its name, descriptor and attribute spans
+fn skip_member(rdr: &mut Cursor<&[u8]>, constant_pool: &ConstantPool) -> Result<(String, String, Vec<AttributeSpan>)> {
+	rdr.seek(SeekFrom::Current(2))?;
+	let name = constant_pool.utf8_inner(rdr.read_u16::<BigEndian>()?).context(|| "name")?;
+	let descriptor = constant_pool.utf8_inner(rdr.read_u16::<BigEndian>()?).context(|| "descriptor")?;
+	Ok((name, descriptor, skip_attributes(rdr, constant_pool)?))
+}
+
+/// Skips over an attribute count and the attributes after it, returning their spans
+fn skip_attributes(rdr: &mut Cursor<&[u8]>, constant_pool: &ConstantPool) -> Result<Vec<AttributeSpan>> {
+	let num_attributes = rdr.read_u16::<BigEndian>()? as usize;
+	let mut attributes = Vec::with_capacity(num_attributes);
+	for i in 0..num_attributes {
+		let start = rdr.position() as usize;
+		let attribute = rdr.read_u16::<BigEndian>().map_err(ParserError::from)
+			.and_then(|index| constant_pool.utf8_inner(index))
+			.and_then(|name| {
+				let length = rdr.read_u32::<BigEndian>()? as u64;
+				if rdr.position() + length > rdr.get_ref().len() as u64 {
+					return Err(ParserError::attribute_length_mismatch(name, length as usize, None));
+				}
+				rdr.seek(SeekFrom::Current(length as i64))?;
+				Ok(AttributeSpan {
+					name,
+					span: start..rdr.position() as usize
+				})
+			})
+			.path_context(|| PathSegment::Attribute(i))?;
+		attributes.push(attribute);
+	}
+	Ok(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::attributes::Attribute;
+	use crate::attributes::AttributeKind;
+	use crate::ast::{BootstrapMethodType, Insn, InvokeDynamicInsn, LdcInsn, LdcType, ReturnInsn, ReturnType};
+	use crate::types::ClassName;
+	
+	const SNAPSHOT: &[u8] = include_bytes!("../classes/snapshot/Snapshot.class");
+	
+	fn answer() -> CodeAttribute {
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 1;
+		code.max_locals = 1;
+		code.insns.insns = vec![
+			Insn::Ldc(LdcInsn::new(LdcType::Int(123456))),
+			Insn::Return(ReturnInsn::new(ReturnType::Int))
+		];
+		code
+	}
+	
+	#[test]
+	fn spans_match_layout() {
+		let lazy = ClassFile::parse_lazy(SNAPSHOT).unwrap();
+		let names: Vec<String> = lazy.methods.iter().map(|m| format!("{}{}", m.name, m.descriptor)).collect();
+		assert_eq!(names, vec!["<init>()V", "main([Ljava/lang/String;)V", "sum([I)I", "parse(Ljava/lang/String;)I"]);
+		// methods are followed by the class attribute count, which is zero without debug info
+		let last = lazy.methods.last().unwrap();
+		assert_eq!(&SNAPSHOT[last.span.end..], [0, 0]);
+		for method in lazy.methods.iter() {
+			assert_eq!(method.attributes[0].name, "Code");
+			assert_eq!(method.attributes.last().unwrap().span.end, method.span.end);
+		}
+	}
+	
+	#[test]
+	fn patch_single_method() {
+		let patched = ClassFile::write_patched(SNAPSHOT, &[("sum", "([I)I", answer())]).unwrap();
+		let original = ClassFile::parse(&mut Cursor::new(SNAPSHOT)).unwrap();
+		let mut class = ClassFile::parse(&mut Cursor::new(&patched)).unwrap();
+		
+		assert_eq!(class.method_mut("sum", "([I)I").unwrap().attributes, vec![Attribute::Code(answer())]);
+		// everything else is the same, the other methods were copied byte for byte
+		class.method_mut("sum", "([I)I").unwrap().attributes = original.method("sum", "([I)I").unwrap().attributes.clone();
+		assert_eq!(class, original);
+		
+		// the original constants keep their indices, with the new integer after them
+		let lazy = ClassFile::parse_lazy(SNAPSHOT).unwrap();
+		let pool = lazy.constant_pool_span.clone();
+		assert_eq!(patched[pool.start + 2..pool.end], SNAPSHOT[pool.start + 2..pool.end]);
+		assert_eq!(patched[pool.end], 3);
+		let sum = lazy.method("sum", "([I)I").unwrap();
+		let added = 5;
+		assert_eq!(patched[pool.end + added..sum.span.start + added], SNAPSHOT[pool.end..sum.span.start]);
+	}
+	
+	fn concat(bootstrap: &str) -> CodeAttribute {
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 1;
+		code.max_locals = 1;
+		code.insns.insns = vec![
+			Insn::from(InvokeDynamicInsn::new(
+				String::from("size"),
+				String::from("()I"),
+				BootstrapMethodType::InvokeStatic,
+				ClassName::from("Bootstraps"),
+				String::from(bootstrap),
+				String::from("(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;)Ljava/lang/invoke/CallSite;"),
+				Vec::new()
+			)),
+			Insn::Return(ReturnInsn::new(ReturnType::Int))
+		];
+		code
+	}
+	
+	/// The name of the bootstrap method that the invokedynamic starting a method's code calls
+	fn bootstrap_method(bytes: &[u8], name: &str, descriptor: &str) -> String {
+		let lazy = ClassFile::parse_lazy(bytes).unwrap();
+		let attribute = lazy.attributes.iter().find(|attribute| attribute.name == "BootstrapMethods").unwrap();
+		let mut bootstrap_methods = ConstantPoolWriter::from_pool(&lazy.constant_pool);
+		bootstrap_methods.add_bootstrap_methods(&bytes[attribute.span.start + 6..attribute.span.end]).unwrap();
+		
+		// after the name, length, max stack, max locals, code length and opcode
+		let code = lazy.method(name, descriptor).unwrap().attributes[0].span.start + 15;
+		let pool = &lazy.constant_pool;
+		let indy = pool.invokedynamicinfo(u16::from_be_bytes([bytes[code], bytes[code + 1]])).unwrap();
+		let method_ref = bootstrap_methods.bootstrap_methods()[indy.bootstrap_method_attr_index as usize].method_ref;
+		let method = pool.methodref(pool.methodhandle(method_ref).unwrap().reference).unwrap();
+		pool.utf8_inner(pool.nameandtype(method.name_and_type_index).unwrap().name_index).unwrap()
+	}
+	
+	#[test]
+	fn patch_adds_bootstrap_methods() {
+		// Snapshot has no class attributes, so BootstrapMethods is added
+		let patched = ClassFile::write_patched(SNAPSHOT, &[("sum", "([I)I", concat("first"))]).unwrap();
+		let class = ClassFile::parse(&mut Cursor::new(&patched)).unwrap();
+		assert!(class.attributes.iter().any(|attribute| attribute.kind() == AttributeKind::BootstrapMethods));
+		assert_eq!(bootstrap_method(&patched, "sum", "([I)I"), "first");
+		
+		// the existing bootstrap method keeps its index, with the new one appended
+		let twice = ClassFile::write_patched(&patched, &[("parse", "(Ljava/lang/String;)I", concat("second"))]).unwrap();
+		assert_eq!(ClassFile::parse_lazy(&twice).unwrap().attributes.len(), 1);
+		assert_eq!(bootstrap_method(&twice, "sum", "([I)I"), "first");
+		assert_eq!(bootstrap_method(&twice, "parse", "(Ljava/lang/String;)I"), "second");
+	}
+	
+	#[test]
+	fn patch_missing_method_fails() {
+		let err = ClassFile::write_patched(SNAPSHOT, &[("sum", "([J)J", answer())]).unwrap_err();
+		assert_eq!(err.to_string(), "No method sum([J)J to patch");
+	}
+	
+	#[test]
+	fn patch_same_method_twice_fails() {
+		let err = ClassFile::write_patched(SNAPSHOT, &[("sum", "([I)I", answer()), ("sum", "([I)I", answer())]).unwrap_err();
+		assert_eq!(err.to_string(), "Method sum([I)I is patched more than once");
+	}
+}
@@ -15,7 +15,29 @@ pub mod ast;
 pub mod insnlist;
 pub mod error;
 pub mod types;
+pub mod signature;
+pub mod visitor;
+pub mod cfg;
+pub mod bulk;
+pub mod lazy;
+pub mod refs;
+pub mod diff;
+pub mod analysis;
+pub mod path;
+pub mod typecheck;
+pub mod search;
+#[cfg(feature = "jar")]
+pub mod jar;
+#[cfg(feature = "serde")]
+pub mod dump;
 mod utils;
+mod fold;
+mod disasm;
+mod remap;
+#[cfg(test)]
+mod corpus;
+#[cfg(feature = "serde")]
+mod serde_bits;
 
 
 pub trait Serializable : Sized {
@@ -25,29 +47,37 @@ pub trait Serializable : Sized {
 
 #[cfg(test)]
 mod tests {
-	use crate::classfile::ClassFile;
+	use crate::classfile::{ClassFile, ParseOptions};
+	use crate::constantpool::{ConstantPool, ConstantPoolWriter};
 	use crate::error::Result;
+	use crate::types::ClassName;
 	use std::fs::{self, File, DirEntry, OpenOptions};
 	use std::io::{BufReader, BufWriter};
+	use std::ffi::OsStr;
+	use std::ops::Deref;
+	use std::path::{Path, PathBuf};
 	use std::process::Command;
 	
-	fn read(dir: &str) -> Result<ClassFile> {
+	fn read(dir: &str) -> Result<(ClassFile, ConstantPool)> {
 		// Read
 		let f = File::open(dir).unwrap();
 		let mut reader = BufReader::new(f);
-		ClassFile::parse(&mut reader)
+		// unchanged code is copied along with its StackMapTable
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		ClassFile::parse_with_pool(&mut reader, &options)
 	}
 	
-	fn write(class: ClassFile, dir: &String) -> Result<()> {
+	fn write(class: ClassFile, constant_pool: &ConstantPool, dir: &str) -> Result<()> {
 		let f = OpenOptions::new().write(true).open(dir).unwrap();
 		let mut writer = BufWriter::new(f);
-		class.write(&mut writer)
+		// keeps the constant pool indices inside StackMapTables and other unknown attributes valid
+		class.write_with_pool(&mut writer, ConstantPoolWriter::from_pool(constant_pool))
 	}
 	
-    fn print_read(dir: &String) -> Result<ClassFile> {
-	    let class = read(dir)?;
+    fn print_read(dir: &str) -> Result<(ClassFile, ConstantPool)> {
+	    let (class, constant_pool) = read(dir)?;
 		println!("{:#x?}", class);
-	    Ok(class)
+	    Ok((class, constant_pool))
     }
 	
 	fn walk(dir: &str, op: &dyn Fn(DirEntry) -> Result<()>) -> Result<()> {
@@ -58,6 +88,69 @@ mod tests {
 		Ok(())
 	}
 	
+	/// A directory under the system temp dir, removed when dropped so that a failing test does not
+	/// leave it behind
+	struct TempDir(PathBuf);
+	
+	impl TempDir {
+		fn new(name: &str) -> Result<Self> {
+			let path = std::env::temp_dir().join(format!("classfile-rs-{}-{}", name, std::process::id()));
+			fs::create_dir_all(&path)?;
+			Ok(TempDir(path))
+		}
+	}
+	
+	impl Deref for TempDir {
+		type Target = Path;
+		
+		fn deref(&self) -> &Path {
+			&self.0
+		}
+	}
+	
+	impl AsRef<OsStr> for TempDir {
+		fn as_ref(&self) -> &OsStr {
+			self.0.as_os_str()
+		}
+	}
+	
+	impl Drop for TempDir {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.0);
+		}
+	}
+	
+	/// Writes the sources, given by file name, into `dir` and compiles them there. Returns false if
+	/// there is no javac to run, and fails the test if compiling fails.
+	fn compile_java_in(dir: &Path, args: &[&str], sources: &[(&str, &str)]) -> bool {
+		let mut javac = Command::new("javac");
+		javac.args(args).arg("-d").arg(dir);
+		for (name, source) in sources.iter() {
+			let path = dir.join(name);
+			fs::write(&path, source).unwrap();
+			javac.arg(path);
+		}
+		match javac.output() {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				true
+			}
+			Err(err) => {
+				println!("Skipping, could not run javac: {}", err);
+				false
+			}
+		}
+	}
+	
+	/// The bytes of the public class `name` compiled from `source`, or None if there is no javac
+	fn compile_java(name: &str, args: &[&str], source: &str) -> Option<Vec<u8>> {
+		let dir = TempDir::new(&name.to_lowercase()).unwrap();
+		if !compile_java_in(&dir, args, &[(&format!("{}.java", name), source)]) {
+			return None;
+		}
+		Some(fs::read(dir.join(format!("{}.class", name))).unwrap())
+	}
+	
 	#[test]
 	fn test_classes() -> Result<()> {
 		/*walk("classes/benchmarking/", &|entry| {
@@ -86,7 +179,7 @@ mod tests {
 				let extension = path.extension().unwrap().to_str().unwrap();
 				if extension == "java" {
 					let output = Command::new("javac")
-						.args(&[path.into_os_string().to_str().unwrap()])
+						.args([path.into_os_string().to_str().unwrap()])
 						.output()
 						.unwrap();
 					if !output.stderr.is_empty() {
@@ -102,12 +195,968 @@ mod tests {
 				let extension = path.extension().unwrap().to_str().unwrap();
 				if extension == "class" {
 					let dir = path.into_os_string().into_string().unwrap();
-					let class = print_read(&dir).unwrap();
-					write(class, &dir)?;
+					let (class, constant_pool) = print_read(&dir).unwrap();
+					write(class, &constant_pool, &dir)?;
 				}
 			}
 			Ok(())
 		})?;
 		Ok(())
 	}
+	
+	#[test]
+	fn written_class_runs() -> Result<()> {
+		use crate::version::{ClassVersion, MajorVersion};
+		use crate::access::{ClassAccessFlags, MethodAccessFlags};
+		use crate::method::Method;
+		use crate::code::CodeAttribute;
+		use crate::types::{Type, method_desc};
+		
+		let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("HelloWorld"), Some(ClassName::from("java/lang/Object")));
+		let mut code = CodeAttribute { max_stack: 2, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.get_static("java/lang/System", "out", "Ljava/io/PrintStream;")
+			.ldc_string("Hello, World!")
+			.invoke_virtual("java/io/PrintStream", "println", &method_desc(&[Type::object("java/lang/String")], &Type::Void))
+			.return_void();
+		let mut main = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from("main"), String::from("([Ljava/lang/String;)V"));
+		main.set_code(Some(code));
+		class.add_method(main)?;
+		
+		let dir = TempDir::new("hello")?;
+		class.write(&mut BufWriter::new(File::create(dir.join("HelloWorld.class"))?))?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("HelloWorld")
+			.output();
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "Hello, World!");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn written_lambda_runs() -> Result<()> {
+		use crate::version::{ClassVersion, MajorVersion};
+		use crate::access::{ClassAccessFlags, MethodAccessFlags};
+		use crate::method::Method;
+		use crate::code::CodeAttribute;
+		use crate::ast::{BootstrapArgument, BootstrapMethodType, Insn, InvokeDynamicInsn, MethodHandle};
+		use crate::constantpool::MethodHandleKind;
+		
+		let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("Lambda"), Some(ClassName::from("java/lang/Object")));
+		let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.push(Insn::from(InvokeDynamicInsn::new(
+			String::from("run"),
+			String::from("()Ljava/lang/Runnable;"),
+			BootstrapMethodType::InvokeStatic,
+			ClassName::from("java/lang/invoke/LambdaMetafactory"),
+			String::from("metafactory"),
+			String::from("(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodHandle;Ljava/lang/invoke/MethodType;)Ljava/lang/invoke/CallSite;"),
+			vec![
+				BootstrapArgument::MethodType(String::from("()V")),
+				BootstrapArgument::MethodHandle(MethodHandle::new(MethodHandleKind::InvokeStatic, ClassName::from("Lambda"), String::from("lambda$main$0"), String::from("()V"), false)),
+				BootstrapArgument::MethodType(String::from("()V"))
+			]
+		)))
+			.invoke_interface("java/lang/Runnable", "run", "()V")
+			.return_void();
+		let mut main = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from("main"), String::from("([Ljava/lang/String;)V"));
+		main.set_code(Some(code));
+		class.add_method(main)?;
+		
+		let mut code = CodeAttribute { max_stack: 2, max_locals: 0, ..CodeAttribute::default() };
+		code.insns.get_static("java/lang/System", "out", "Ljava/io/PrintStream;")
+			.ldc_string("Hello from a lambda")
+			.invoke_virtual("java/io/PrintStream", "println", "(Ljava/lang/String;)V")
+			.return_void();
+		let mut lambda = Method::new(MethodAccessFlags::PRIVATE | MethodAccessFlags::STATIC | MethodAccessFlags::SYNTHETIC, String::from("lambda$main$0"), String::from("()V"));
+		lambda.set_code(Some(code));
+		class.add_method(lambda)?;
+		
+		let dir = TempDir::new("lambda")?;
+		class.write(&mut BufWriter::new(File::create(dir.join("Lambda.class"))?))?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("Lambda")
+			.output();
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "Hello from a lambda");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn class_signature_round_trips() -> Result<()> {
+		use crate::attributes::Attribute;
+		use std::io::Cursor;
+		
+		let bytes = match compile_java("Box", &[], "public class Box<T extends Comparable<T>> implements java.util.function.Supplier<T> {\n\tT value;\n\tpublic T get() { return value; }\n}\n") {
+			Some(bytes) => bytes,
+			None => return Ok(())
+		};
+		
+		let signature = "<T::Ljava/lang/Comparable<TT;>;>Ljava/lang/Object;Ljava/util/function/Supplier<TT;>;";
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default())?;
+		assert!(class.attributes.iter().any(|attr| matches!(attr, Attribute::Signature(x) if x.signature == signature)));
+		assert_eq!(class.signature_ref(), Some(signature));
+		assert_eq!(class.field("value", "Ljava/lang/Comparable;").unwrap().signature_ref(), Some("TT;"));
+		
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		assert_eq!(ClassFile::parse(&mut Cursor::new(&out))?, class);
+		
+		let generic = "<U:Ljava/lang/Object;>Ljava/lang/Object;";
+		class.set_signature(Some(String::from(generic)));
+		let mut out = Vec::new();
+		class.write(&mut out)?;
+		let mut parsed = ClassFile::parse(&mut Cursor::new(&out))?;
+		assert_eq!(parsed.signature_ref(), Some(generic));
+		assert_eq!(parsed, class);
+		
+		parsed.set_signature(None);
+		assert!(parsed.signature_ref().is_none());
+		assert_eq!(parsed.attributes.len(), class.attributes.len() - 1);
+		Ok(())
+	}
+	
+	#[test]
+	fn local_variable_ranges_round_trip() -> Result<()> {
+		use crate::ast::Insn;
+		use crate::attributes::Attribute;
+		use std::io::Cursor;
+		
+		let bytes = match compile_java("Locals", &["-g"], "public class Locals {\n\tstatic int sum(int a, int b) {\n\t\tint c = a + b;\n\t\t{\n\t\t\tint d = c * 2;\n\t\t\tc += d;\n\t\t}\n\t\t{\n\t\t\tString s = \"x\";\n\t\t\tc += s.length();\n\t\t}\n\t\treturn c;\n\t}\n}\n") {
+			Some(bytes) => bytes,
+			None => return Ok(())
+		};
+		
+		// the name of each local variable of sum, and the instructions its range starts and ends at
+		let ranges = |class: &mut ClassFile| -> Vec<(String, usize, usize)> {
+			let code = class.method_mut("sum", "(II)I").unwrap().code().unwrap();
+			let at = |label| code.insns.iter().position(|insn| *insn == Insn::Label(label)).unwrap();
+			code.attributes.iter()
+				.filter_map(|attr| match attr {
+					Attribute::LocalVariableTable(x) => Some(x),
+					_ => None
+				})
+				.flat_map(|x| x.variables.iter())
+				.map(|var| (var.name.clone(), at(var.start), at(var.end)))
+				.collect()
+		};
+		
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default())?;
+		let expected = ranges(&mut class);
+		let names: Vec<&str> = expected.iter().map(|(name, _, _)| name.as_str()).collect();
+		for name in ["a", "b", "c", "d", "s"].iter() {
+			assert!(names.contains(name), "{:?}", expected);
+		}
+		
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		let mut parsed = ClassFile::parse(&mut Cursor::new(&out))?;
+		assert_eq!(ranges(&mut parsed), expected);
+		Ok(())
+	}
+	
+	#[test]
+	fn shadowed_local_variables_are_merged() -> Result<()> {
+		use crate::ast::Insn;
+		use crate::attributes::{Attribute, AttributeKind, LocalVariableTableAttribute};
+		use std::io::Cursor;
+		
+		let bytes = match compile_java("Scopes", &["-g"], "import java.util.List;\n\npublic class Scopes {\n\tstatic int count(List<String> items) {\n\t\tint total = 0;\n\t\t{\n\t\t\tint x = 1;\n\t\t\ttotal += x;\n\t\t}\n\t\t{\n\t\t\tString x = \"ab\";\n\t\t\ttotal += x.length();\n\t\t}\n\t\tfor (String item : items) {\n\t\t\tList<String> x = List.of(item);\n\t\t\ttotal += x.size();\n\t\t}\n\t\treturn total;\n\t}\n}\n") {
+			Some(bytes) => bytes,
+			None => return Ok(())
+		};
+		
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default())?;
+		let code = class.method_mut("count", "(Ljava/util/List;)I").unwrap().code().unwrap();
+		let variables = code.local_variables();
+		let shadowed: Vec<(&str, Option<&str>)> = variables.iter()
+			.filter(|var| var.name == "x")
+			.map(|var| (var.descriptor.as_str(), var.signature.as_deref()))
+			.collect();
+		assert_eq!(shadowed, vec![
+			("I", None),
+			("Ljava/lang/String;", None),
+			("Ljava/util/List;", Some("Ljava/util/List<Ljava/lang/String;>;"))
+		]);
+		let items = variables.iter().find(|var| var.name == "items").unwrap();
+		assert_eq!(items.signature.as_deref(), Some("Ljava/util/List<Ljava/lang/String;>;"));
+		
+		// the String x is in scope where its length is taken, and nothing is in its slot on return
+		let length = code.insns.iter().position(|insn| matches!(insn, Insn::Invoke(x) if x.name == "length")).unwrap();
+		let x = code.local_variable_at(2, length).unwrap();
+		assert_eq!((x.name.as_str(), x.descriptor.as_str()), ("x", "Ljava/lang/String;"));
+		let ret = code.insns.iter().rposition(|insn| matches!(insn, Insn::Return(_))).unwrap();
+		assert_eq!(code.local_variable_at(2, ret), None);
+		
+		// a table split in two, repeating one entry, reads the same
+		let position = code.attributes.iter().position(|attr| matches!(attr, Attribute::LocalVariableTable(_))).unwrap();
+		let mut table = match code.attributes.remove(position) {
+			Attribute::LocalVariableTable(x) => x.variables,
+			x => panic!("{:?}", x)
+		};
+		let rest = table.split_off(table.len() / 2);
+		table.push(rest[0].clone());
+		code.attributes.insert(position, Attribute::LocalVariableTable(LocalVariableTableAttribute::new(rest)));
+		code.attributes.push(Attribute::LocalVariableTable(LocalVariableTableAttribute::new(table)));
+		let mut split = code.local_variables();
+		let mut sorted = variables.clone();
+		let key = |var: &crate::code::ResolvedLocalVariable| (var.index, var.start.id, var.name.clone());
+		split.sort_by_key(key);
+		sorted.sort_by_key(key);
+		assert_eq!(split, sorted);
+		
+		code.set_local_variables(variables.clone());
+		let names: Vec<&str> = code.attributes.iter().map(|attr| attr.name()).collect();
+		assert_eq!(names.iter().filter(|name| name.starts_with("LocalVariable")).collect::<Vec<_>>(), vec![&"LocalVariableTable", &"LocalVariableTypeTable"]);
+		
+		// the loop has stack map frames, which the code encoded again would leave stale
+		code.attributes.retain(|attr| attr.kind() != AttributeKind::StackMapTable);
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		let mut parsed = ClassFile::parse(&mut Cursor::new(&out))?;
+		let code = parsed.method_mut("count", "(Ljava/util/List;)I").unwrap().code().unwrap();
+		assert_eq!(code.local_variables().len(), variables.len());
+		assert_eq!(code.local_variables().iter().filter(|var| var.signature.is_some()).count(), 2);
+		Ok(())
+	}
+	
+	#[test]
+	fn attribute_labels_are_placed_once() -> Result<()> {
+		use crate::ast::{Insn, LabelInsn};
+		use crate::attributes::{Attribute, AttributeKind};
+		use crate::code::CodeAttribute;
+		use std::io::Cursor;
+		
+		let bytes = match compile_java("Debug", &["-g"], "public class Debug {\n\tstatic int run(String[] args) {\n\t\tint total = 0;\n\t\tfor (int i = 0; i < args.length; i++) {\n\t\t\tString arg = args[i];\n\t\t\ttry {\n\t\t\t\ttotal += Integer.parseInt(arg);\n\t\t\t} catch (NumberFormatException e) {\n\t\t\t\tlong penalty = arg.length();\n\t\t\t\ttotal -= (int) penalty;\n\t\t\t}\n\t\t}\n\t\tswitch (total) {\n\t\t\tcase 1: { double d = 1.5; total += (int) d; break; }\n\t\t\tcase 7: return 0;\n\t\t\tdefault: break;\n\t\t}\n\t\treturn total;\n\t}\n}\n") {
+			Some(bytes) => bytes,
+			None => return Ok(())
+		};
+		
+		// every label a handler, jump or local variable refers to is in the list exactly once
+		let check = |code: &CodeAttribute| {
+			let mut referenced: Vec<LabelInsn> = code.insns.iter().flat_map(Insn::jump_targets).collect();
+			for handler in code.exceptions.iter() {
+				referenced.extend(&[handler.start, handler.end, handler.handler]);
+			}
+			for attr in code.attributes.iter() {
+				if let Attribute::LocalVariableTable(x) | Attribute::LocalVariableTypeTable(x) = attr {
+					referenced.extend(x.variables.iter().flat_map(|var| vec![var.start, var.end]));
+				}
+			}
+			assert!(code.attributes.iter().any(|attr| matches!(attr, Attribute::LocalVariableTable(x) if x.variables.len() >= 6)));
+			for label in referenced {
+				assert_eq!(code.insns.iter().filter(|insn| **insn == Insn::Label(label)).count(), 1, "{:?}", label);
+			}
+		};
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default())?;
+		let code = class.method_mut("run", "([Ljava/lang/String;)I").unwrap().code().unwrap();
+		check(code);
+		
+		let mut out = Vec::new();
+		// encoded again, so without its stack map frames
+		code.mark_modified();
+		code.attributes.retain(|attr| attr.kind() != AttributeKind::StackMapTable);
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		let mut parsed = ClassFile::parse(&mut Cursor::new(&out))?;
+		check(parsed.method_mut("run", "([Ljava/lang/String;)I").unwrap().code().unwrap());
+		Ok(())
+	}
+	
+	#[test]
+	fn unmodified_code_keeps_stack_frames() -> Result<()> {
+		use crate::ast::{Insn, NopInsn};
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("frames")?;
+		if !compile_java_in(&dir, &[], &[("Frames.java", "import java.util.function.IntPredicate;\n\npublic class Frames {\n\tstatic String greeting() {\n\t\treturn \"Hello\";\n\t}\n\n\tstatic int classify(int x) {\n\t\tif (x > 10) {\n\t\t\treturn 1;\n\t\t} else if (x < 0) {\n\t\t\treturn -1;\n\t\t}\n\t\treturn 0;\n\t}\n\n\tpublic static void main(String[] args) {\n\t\tIntPredicate even = x -> x % 2 == 0;\n\t\tint total = 0;\n\t\tfor (int i = 0; i < 5; i++) {\n\t\t\tif (even.test(i)) {\n\t\t\t\ttotal += classify(i * 7 - 3);\n\t\t\t}\n\t\t}\n\t\tSystem.out.println(greeting() + \" \" + total);\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Frames.class"))?;
+		
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options)?;
+		assert!(class.methods.iter_mut().filter_map(|method| method.code()).all(|code| !code.is_modified()));
+		// straight line code, so it has no stack frames to go stale
+		let greeting = class.method_mut("greeting", "()Ljava/lang/String;").unwrap().code().unwrap();
+		greeting.insns.insert(0, Insn::Nop(NopInsn::new()));
+		assert!(greeting.is_modified());
+		
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		fs::write(dir.join("Frames.class"), &out)?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("Frames")
+			.output();
+		
+		let mut written = ClassFile::parse_with(&mut Cursor::new(&out), &options)?;
+		let original = |class: &mut ClassFile, name: &str, descriptor: &str| class.method_mut(name, descriptor).unwrap().code().unwrap().original.clone();
+		assert_eq!(original(&mut written, "main", "([Ljava/lang/String;)V"), original(&mut class, "main", "([Ljava/lang/String;)V"));
+		// the nop, the label of the line and the two instructions of the body
+		assert_eq!(written.method_mut("greeting", "()Ljava/lang/String;").unwrap().code().unwrap().insns.len(), 4);
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "Hello 1");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn nan_constants_are_unmodified() -> Result<()> {
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("nan")?;
+		if !compile_java_in(&dir, &[], &[("Nan.java", "public class Nan {\n\tstatic final float F = Float.NaN;\n\tstatic final double D = Double.NaN;\n\n\tpublic static void main(String[] args) {\n\t\tdouble total = 0.0 / 0.0;\n\t\tfor (int i = 0; i < 3; i++) {\n\t\t\ttotal += F * i;\n\t\t}\n\t\tSystem.out.println(Double.isNaN(total) && Double.isNaN(D));\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Nan.class"))?;
+		
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options)?;
+		// NaN never equals itself as a float, but is the same constant
+		assert!(class.methods.iter_mut().filter_map(|method| method.code()).all(|code| !code.is_modified()));
+		assert_eq!(class, class.clone());
+		
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		// no NaN was added to the pool again
+		let pool = ClassFile::parse_lazy(&bytes)?.constant_pool_span;
+		assert_eq!(ClassFile::parse_lazy(&out)?.constant_pool_span, pool);
+		assert_eq!(out[pool.clone()], bytes[pool]);
+		assert_eq!(ClassFile::parse(&mut Cursor::new(&out))?, class);
+		Ok(())
+	}
+	
+	#[test]
+	fn stack_frames_stay_on_instructions() -> Result<()> {
+		use crate::attributes::{Attribute, AttributeKind};
+		use crate::ast::Insn;
+		use byteorder::{BigEndian, ReadBytesExt};
+		use std::io::Cursor;
+		
+		// the pc of each frame in the contents of a StackMapTable
+		fn frame_pcs(buf: &[u8]) -> Result<Vec<u32>> {
+			fn skip_types(rdr: &mut Cursor<&[u8]>, count: u16) -> Result<()> {
+				for _ in 0..count {
+					// Object and Uninitialized have a u16 operand
+					if rdr.read_u8()? >= 7 {
+						rdr.read_u16::<BigEndian>()?;
+					}
+				}
+				Ok(())
+			}
+			let mut rdr = Cursor::new(buf);
+			let mut pcs: Vec<u32> = Vec::new();
+			for _ in 0..rdr.read_u16::<BigEndian>()? {
+				let kind = rdr.read_u8()?;
+				let delta = match kind {
+					0..=63 => kind as u32,
+					64..=127 => {
+						skip_types(&mut rdr, 1)?;
+						kind as u32 - 64
+					}
+					_ => {
+						let delta = rdr.read_u16::<BigEndian>()? as u32;
+						match kind {
+							247 => skip_types(&mut rdr, 1)?,
+							252..=254 => skip_types(&mut rdr, kind as u16 - 251)?,
+							255 => {
+								let locals = rdr.read_u16::<BigEndian>()?;
+								skip_types(&mut rdr, locals)?;
+								let stack = rdr.read_u16::<BigEndian>()?;
+								skip_types(&mut rdr, stack)?;
+							}
+							_ => {}
+						}
+						delta
+					}
+				};
+				pcs.push(pcs.last().map_or(delta, |pc| pc + delta + 1));
+			}
+			Ok(pcs)
+		}
+		
+		let dir = TempDir::new("loop")?;
+		if !compile_java_in(&dir, &[], &[("Loop.java", "public class Loop {\n\tpublic static void main(String[] args) {\n\t\tint total = 0;\n\t\tfor (int i = 0; i < 10; i++) {\n\t\t\ttotal += i;\n\t\t}\n\t\tSystem.out.println(total);\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Loop.class"))?;
+		
+		// encoding the loop again would move its frames off their instructions
+		let (class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &ParseOptions::default())?;
+		let err = class.write_with_pool(&mut Vec::new(), ConstantPoolWriter::from_pool(&constant_pool)).unwrap_err();
+		assert!(err.to_string().contains("StackMapTable"), "{}", err);
+		
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		let (class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options)?;
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		fs::write(dir.join("Loop.class"), &out)?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("Loop")
+			.output();
+		
+		let options = ParseOptions { original_pcs: true, ..ParseOptions::default() };
+		let mut written = ClassFile::parse_with(&mut Cursor::new(&out), &options)?;
+		let code = written.method_mut("main", "([Ljava/lang/String;)V").unwrap().code().unwrap();
+		let starts: Vec<u32> = code.insns.iter().zip(code.original_pcs().unwrap())
+			.filter(|(insn, _)| !matches!(insn, Insn::Label(_)))
+			.map(|(_, pc)| *pc)
+			.collect();
+		let frames = code.attributes.iter().find_map(|attr| match attr {
+			Attribute::Unknown(x) if x.kind() == AttributeKind::StackMapTable => Some(frame_pcs(&x.buf)),
+			_ => None
+		}).unwrap()?;
+		assert!(!frames.is_empty());
+		for pc in frames {
+			assert!(starts.contains(&pc), "frame at {} is not at an instruction: {:?}", pc, starts);
+		}
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "45");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn line_numbers_stay_on_instructions() -> Result<()> {
+		use crate::ast::{Insn, LdcInsn, LdcType, PopInsn};
+		use crate::attributes::AttributeKind;
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("lines")?;
+		if !compile_java_in(&dir, &[], &[("Lines.java", "public class Lines {\n\tstatic int compute(int x) {\n\t\tint a = x * 2;\n\t\tint b = a + 3;\n\t\treturn a * b;\n\t}\n\n\tpublic static void main(String[] args) {\n\t\tSystem.out.println(compute(4));\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Lines.class"))?;
+		
+		// three bytes in front of the first line move every line start
+		let mut class = ClassFile::parse(&mut Cursor::new(&bytes))?;
+		let code = class.method_mut("compute", "(I)I").unwrap().code().unwrap();
+		code.insns.insert(0, Insn::Pop(PopInsn::new(false)));
+		code.insns.insert(0, Insn::Ldc(LdcInsn::new(LdcType::Int(123456))));
+		let mut out = Vec::new();
+		class.write(&mut out)?;
+		fs::write(dir.join("Lines.class"), &out)?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("Lines")
+			.output();
+		
+		let options = ParseOptions { original_pcs: true, raw_attributes: true, ..ParseOptions::default() };
+		let mut written = ClassFile::parse_with(&mut Cursor::new(&out), &options)?;
+		let code = written.method_mut("compute", "(I)I").unwrap().code().unwrap();
+		let starts: Vec<u32> = code.insns.iter().zip(code.original_pcs().unwrap())
+			.filter(|(insn, _)| !matches!(insn, Insn::Label(_)))
+			.map(|(_, pc)| *pc)
+			.collect();
+		let table = code.attributes.iter().find(|attr| attr.kind() == AttributeKind::LineNumberTable).unwrap().raw_bytes().unwrap();
+		// a u16 length, then the u16 start pc and line number of each entry
+		let lines: Vec<(u32, u16)> = table[2..].chunks_exact(4)
+			.map(|entry| (u16::from_be_bytes([entry[0], entry[1]]) as u32, u16::from_be_bytes([entry[2], entry[3]])))
+			.collect();
+		assert_eq!(lines.iter().map(|(_, line)| *line).collect::<Vec<u16>>(), vec![3, 4, 5]);
+		assert_eq!(lines[0].0, 3);
+		for (pc, line) in lines {
+			assert!(starts.contains(&pc), "line {} starts at {}, which is not an instruction: {:?}", line, pc, starts);
+		}
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "88");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn duplicated_class_runs_beside_original() -> Result<()> {
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("duplicate")?;
+		if !compile_java_in(&dir, &["-g:none"], &[("Original.java", "public class Original {\n\tstatic int count = 41;\n\tfinal int value;\n\n\tOriginal() {\n\t\tthis(next());\n\t}\n\n\tOriginal(int value) {\n\t\tthis.value = value;\n\t}\n\n\tstatic int next() {\n\t\treturn count + 1;\n\t}\n\n\tpublic static void main(String[] args) {\n\t\tOriginal original = new Original();\n\t\tSystem.out.print(original.getClass().getName());\n\t\tSystem.out.print(' ');\n\t\tSystem.out.println(original.value);\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Original.class"))?;
+		
+		let class = ClassFile::parse(&mut Cursor::new(&bytes))?;
+		let copy = class.duplicate_as("Copy")?;
+		let mut out = Vec::new();
+		copy.write(&mut out)?;
+		assert!(!out.windows(8).any(|x| x == b"Original"));
+		fs::write(dir.join("Copy.class"), &out)?;
+		let run = |name: &str| Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg(name)
+			.output();
+		let outputs = (run("Original"), run("Copy"));
+		
+		match outputs {
+			(Ok(original), Ok(copy)) => {
+				assert!(original.status.success(), "{}", String::from_utf8_lossy(&original.stderr));
+				assert!(copy.status.success(), "{}", String::from_utf8_lossy(&copy.stderr));
+				assert_eq!(String::from_utf8_lossy(&original.stdout).trim_end(), "Original 42");
+				assert_eq!(String::from_utf8_lossy(&copy.stdout).trim_end(), "Copy 42");
+			}
+			// no JVM to run it on
+			(Err(err), _) | (_, Err(err)) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn default_method_super_call_runs() -> Result<()> {
+		use crate::ast::{Insn, InvokeType};
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("greeter")?;
+		if !compile_java_in(&dir, &["-g:none"], &[("Greeter.java", "interface Named {\n\tdefault String name() {\n\t\treturn \"default\";\n\t}\n}\n\npublic class Greeter implements Named {\n\tpublic String name() {\n\t\treturn Named.super.name();\n\t}\n\n\tpublic static void main(String[] args) {\n\t\tSystem.out.println(new Greeter().name());\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Greeter.class"))?;
+		
+		let mut class = ClassFile::parse(&mut Cursor::new(&bytes))?;
+		let code = class.method_mut("name", "()Ljava/lang/String;").unwrap().code().unwrap();
+		assert!(code.insns.iter().any(|insn| matches!(insn, Insn::Invoke(x) if x.kind == InvokeType::Special && x.interface_method)));
+		code.mark_modified();
+		let mut out = Vec::new();
+		class.write(&mut out)?;
+		fs::write(dir.join("Greeter.class"), &out)?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("Greeter")
+			.output();
+		
+		let mut written = ClassFile::parse(&mut Cursor::new(&out))?;
+		let code = written.method_mut("name", "()Ljava/lang/String;").unwrap().code().unwrap();
+		assert!(code.insns.iter().any(|insn| matches!(insn, Insn::Invoke(x) if x.kind == InvokeType::Special && x.interface_method)));
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "default");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn interface_calls_run() -> Result<()> {
+		use crate::ast::{Insn, InvokeType};
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("lists")?;
+		if !compile_java_in(&dir, &["-g:none"], &[("Lists.java", "import java.util.ArrayList;\nimport java.util.List;\n\npublic class Lists {\n\tpublic static void main(String[] args) {\n\t\tList<String> list = new ArrayList<>();\n\t\tlist.add(\"a\");\n\t\tSystem.out.println(list.size());\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Lists.class"))?;
+		let is_add = |insn: &Insn| matches!(insn, Insn::Invoke(x) if x.kind == InvokeType::Interface && x.interface_method && x.name == "add");
+		
+		let mut class = ClassFile::parse(&mut Cursor::new(&bytes))?;
+		let code = class.method_mut("main", "([Ljava/lang/String;)V").unwrap().code().unwrap();
+		assert!(code.insns.iter().any(is_add));
+		code.mark_modified();
+		let mut out = Vec::new();
+		class.write(&mut out)?;
+		fs::write(dir.join("Lists.class"), &out)?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("Lists")
+			.output();
+		
+		// invokeinterface only parses against an InterfaceMethodref
+		let mut written = ClassFile::parse(&mut Cursor::new(&out))?;
+		let code = written.method_mut("main", "([Ljava/lang/String;)V").unwrap().code().unwrap();
+		assert!(code.insns.iter().any(is_add));
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "1");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn compiled_exception_tables_are_normalized() -> Result<()> {
+		use crate::ast::Insn;
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("handlers")?;
+		if !compile_java_in(&dir, &["-g:none"], &[("Handlers.java", "import java.io.StringReader;\n\npublic class Handlers {\n\tstatic int parse(String value) {\n\t\ttry {\n\t\t\treturn Integer.parseInt(value);\n\t\t} catch (NumberFormatException | NullPointerException e) {\n\t\t\treturn -1;\n\t\t}\n\t}\n\n\tstatic int read(String value) throws Exception {\n\t\ttry (StringReader first = new StringReader(value); StringReader second = new StringReader(value)) {\n\t\t\treturn first.read() + second.read();\n\t\t} finally {\n\t\t\tSystem.out.print('.');\n\t\t}\n\t}\n\n\tpublic static void main(String[] args) throws Exception {\n\t\tSystem.out.print(parse(\"7\"));\n\t\tSystem.out.print(parse(null));\n\t\tSystem.out.print(parse(\"x\"));\n\t\tSystem.out.println(read(\"ab\"));\n\t}\n}\n")]) {
+			return Ok(());
+		}
+		let bytes = fs::read(dir.join("Handlers.class"))?;
+		
+		let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+		let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(&bytes), &options)?;
+		let call = |code: &crate::code::CodeAttribute, name: &str| code.insns.iter().position(|insn| matches!(insn, Insn::Invoke(x) if x.name == name)).unwrap();
+		
+		// try-with-resources nests a handler for each resource inside the finally block
+		let read = class.method_mut("read", "(Ljava/lang/String;)I").unwrap().code().unwrap();
+		let covering: Vec<Option<&str>> = read.handlers_covering(call(read, "read")).iter().map(|x| x.catch_type.as_deref()).collect();
+		assert_eq!(covering, vec![Some("java/lang/Throwable"), Some("java/lang/Throwable"), None]);
+		assert_eq!(read.merge_adjacent_handlers(), 0);
+		assert!(!read.is_modified());
+		
+		// a multi-catch is one entry per type, which split in two around the call merge back. On a
+		// copy, as the code would be encoded again and its StackMapTable left stale.
+		let parse = &mut class.method_mut("parse", "(Ljava/lang/String;)I").unwrap().code().unwrap().clone();
+		let original = parse.exceptions.clone();
+		assert_eq!(original.len(), 2);
+		assert_eq!(parse.handlers_covering(call(parse, "parseInt")).len(), 2);
+		parse.exceptions.clear();
+		let handler = &original[0];
+		let index = |code: &crate::code::CodeAttribute, label| code.insns.label_index(label).unwrap();
+		let split = call(parse, "parseInt");
+		parse.add_handler(index(parse, handler.start), split, index(parse, handler.handler), handler.catch_type.clone())?;
+		let split = call(parse, "parseInt") - 1;
+		parse.add_handler(split, index(parse, handler.end), index(parse, handler.handler), handler.catch_type.clone())?;
+		parse.exceptions.push(original[1].clone());
+		assert_eq!(parse.merge_adjacent_handlers(), 1);
+		assert_eq!(parse.exceptions, original);
+		
+		let mut out = Vec::new();
+		class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+		fs::write(dir.join("Handlers.class"), &out)?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("Handlers")
+			.output();
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "7-1-1.194");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn far_conditional_jumps_run() -> Result<()> {
+		use crate::version::{ClassVersion, MajorVersion};
+		use crate::access::{ClassAccessFlags, MethodAccessFlags};
+		use crate::method::Method;
+		use crate::code::CodeAttribute;
+		use crate::ast::{ConditionalJumpInsn, IncrementIntInsn, Insn, JumpCondition, NopInsn, OpType, ReturnType};
+		
+		// version 49 needs no StackMapTable for the branches
+		let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_5), ClassAccessFlags::PUBLIC, ClassName::from("FarJumps"), Some(ClassName::from("java/lang/Object")));
+		let nops = vec![Insn::Nop(NopInsn::new()); 40000];
+		
+		// far(x) = x == 0 ? 1 : 2, jumping forwards over the nops
+		let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+		let zero = code.insns.new_label();
+		code.insns.load(OpType::Int, 0)
+			.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, zero)));
+		code.insns.insns.extend(nops.clone());
+		code.insns.ldc_int(2)
+			.return_value(ReturnType::Int)
+			.push(Insn::Label(zero))
+			.ldc_int(1)
+			.return_value(ReturnType::Int);
+		let mut far = Method::new(MethodAccessFlags::STATIC, String::from("far"), String::from("(I)I"));
+		far.set_code(Some(code));
+		class.add_method(far)?;
+		
+		// back(x) counts x down to 0, jumping backwards over the nops
+		let mut code = CodeAttribute { max_stack: 1, max_locals: 1, ..CodeAttribute::default() };
+		let loop_start = code.insns.new_label();
+		code.insns.push(Insn::Label(loop_start))
+			.push(Insn::IncrementInt(IncrementIntInsn::new(0, -1)));
+		code.insns.insns.extend(nops);
+		code.insns.load(OpType::Int, 0)
+			.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntGreaterThanZero, loop_start)))
+			.load(OpType::Int, 0)
+			.return_value(ReturnType::Int);
+		let mut back = Method::new(MethodAccessFlags::STATIC, String::from("back"), String::from("(I)I"));
+		back.set_code(Some(code));
+		class.add_method(back)?;
+		
+		let mut code = CodeAttribute { max_stack: 2, max_locals: 1, ..CodeAttribute::default() };
+		for (method, arg) in [("far", 0), ("far", 5), ("back", 3)] {
+			code.insns.get_static("java/lang/System", "out", "Ljava/io/PrintStream;")
+				.ldc_int(arg)
+				.invoke_static("FarJumps", method, "(I)I")
+				.invoke_virtual("java/io/PrintStream", "println", "(I)V");
+		}
+		code.insns.return_void();
+		let mut main = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from("main"), String::from("([Ljava/lang/String;)V"));
+		main.set_code(Some(code));
+		class.add_method(main)?;
+		
+		let dir = TempDir::new("far-jumps")?;
+		class.write(&mut BufWriter::new(File::create(dir.join("FarJumps.class"))?))?;
+		let output = Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg("FarJumps")
+			.output();
+		match output {
+			Ok(output) => {
+				assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+				assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), "1\n2\n0");
+			}
+			// no JVM to run it on
+			Err(err) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn nested_classes_run_after_downgrading() -> Result<()> {
+		use crate::attributes::AttributeKind;
+		use crate::version::{ClassVersion, MajorVersion};
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("downgrade")?;
+		let sources = [
+			("Nested.java", "public class Nested {\n\tstatic class Inner {\n\t\tString greet(int n) { return n > 0 ? \"Hello from Inner\" : \"none\"; }\n\t}\n\tpublic static void main(String[] args) { System.out.println(new Inner().greet(args.length + 8)); }\n}\n"),
+			// reads a private field of a nestmate, which needs the nest attributes
+			("Secret.java", "public class Secret {\n\tstatic class Inner {\n\t\tprivate String secret = \"hidden\";\n\t}\n\tpublic static void main(String[] args) { System.out.println(new Inner().secret); }\n}\n")
+		];
+		if !compile_java_in(&dir, &[], &sources) {
+			return Ok(());
+		}
+		
+		for (name, dropped) in [("Nested", "NestMembers"), ("Nested$Inner", "NestHost"), ("Secret", "NestMembers"), ("Secret$Inner", "NestHost")] {
+			let path = dir.join(format!("{}.class", name));
+			// unchanged code keeps its stack map frames
+			let options = ParseOptions { keep_original_code: true, ..ParseOptions::default() };
+			let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(fs::read(&path)?), &options)?;
+			let warnings = class.downgrade(ClassVersion::new_major(MajorVersion::JAVA_8))?;
+			assert_eq!(warnings, vec![format!("{} attribute (needs Java 11)", dropped)]);
+			
+			let mut out = Vec::new();
+			class.write_with_pool(&mut out, ConstantPoolWriter::from_pool(&constant_pool))?;
+			let parsed = ClassFile::parse(&mut Cursor::new(&out))?;
+			assert_eq!(parsed.version, ClassVersion::new_major(MajorVersion::JAVA_8));
+			assert!(parsed.attributes.iter().all(|attr| !matches!(attr.kind(), AttributeKind::NestHost | AttributeKind::NestMembers)));
+			fs::write(&path, out)?;
+		}
+		let run = |class: &str| Command::new("java")
+			.args(["-Xverify:all", "-cp"])
+			.arg(&dir)
+			.arg(class)
+			.output();
+		let outputs = (run("Nested"), run("Secret"));
+		match outputs {
+			(Ok(nested), Ok(secret)) => {
+				assert!(nested.status.success(), "{}", String::from_utf8_lossy(&nested.stderr));
+				assert_eq!(String::from_utf8_lossy(&nested.stdout).trim_end(), "Hello from Inner");
+				// as warned, the classes are no longer nestmates
+				assert!(!secret.status.success());
+				assert!(String::from_utf8_lossy(&secret.stderr).contains("IllegalAccessError"), "{}", String::from_utf8_lossy(&secret.stderr));
+			}
+			// no JVM to run it on
+			(Err(err), _) | (_, Err(err)) => println!("Skipping, could not run java: {}", err)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn raw_attributes_match_the_class_bytes() -> Result<()> {
+		use crate::attributes::Attribute;
+		use crate::Serializable;
+		use byteorder::{BigEndian, ReadBytesExt};
+		use std::io::{Cursor, Read, Seek, SeekFrom};
+		
+		/// The name and contents of each attribute in order, followed by those inside it if it is a
+		/// Code attribute
+		fn contents(rdr: &mut Cursor<&[u8]>, pool: &ConstantPool, out: &mut Vec<(String, Vec<u8>)>) -> Result<()> {
+			for _ in 0..rdr.read_u16::<BigEndian>()? {
+				let name = pool.utf8_inner(rdr.read_u16::<BigEndian>()?)?;
+				let mut bytes = vec![0; rdr.read_u32::<BigEndian>()? as usize];
+				rdr.read_exact(&mut bytes)?;
+				out.push((name.clone(), bytes.clone()));
+				if name == "Code" {
+					let mut code = Cursor::new(&bytes[..]);
+					code.seek(SeekFrom::Start(4))?;
+					let length = code.read_u32::<BigEndian>()? as i64;
+					code.seek(SeekFrom::Current(length))?;
+					let handlers = code.read_u16::<BigEndian>()? as i64;
+					code.seek(SeekFrom::Current(handlers * 8))?;
+					contents(&mut code, pool, out)?;
+				}
+			}
+			Ok(())
+		}
+		
+		fn parsed(attributes: &[Attribute], out: &mut Vec<(String, Option<Vec<u8>>)>) {
+			for attribute in attributes.iter() {
+				out.push((attribute.name().to_string(), attribute.raw_bytes().map(|x| x.to_vec())));
+				if let Attribute::Code(code) = attribute {
+					parsed(&code.attributes, out);
+				}
+			}
+		}
+		
+		let bytes = match compile_java("Audited", &["-g"], "import java.io.IOException;\nimport java.util.List;\n\npublic class Audited<T> {\n\tstatic final int LIMIT = 42;\n\tList<T> items;\n\n\t<E extends Exception> void check(List<String> names) throws IOException, E {\n\t\tfor (String name : names) {\n\t\t\tif (name.isEmpty()) {\n\t\t\t\tthrow new IOException(name);\n\t\t\t}\n\t\t}\n\t}\n\n\tclass Inner {\n\t}\n}\n") {
+			Some(bytes) => bytes,
+			None => return Ok(())
+		};
+		
+		let mut rdr = Cursor::new(&bytes[..]);
+		rdr.seek(SeekFrom::Start(8))?;
+		let pool = ConstantPool::parse(&mut rdr)?;
+		// access flags, this class and super class, then the interfaces
+		rdr.seek(SeekFrom::Current(6))?;
+		let interfaces = rdr.read_u16::<BigEndian>()? as i64;
+		rdr.seek(SeekFrom::Current(interfaces * 2))?;
+		let mut expected = Vec::new();
+		for _ in 0..2 {
+			for _ in 0..rdr.read_u16::<BigEndian>()? {
+				rdr.seek(SeekFrom::Current(6))?;
+				contents(&mut rdr, &pool, &mut expected)?;
+			}
+		}
+		contents(&mut rdr, &pool, &mut expected)?;
+		
+		let options = ParseOptions { raw_attributes: true, ..ParseOptions::default() };
+		let class = ClassFile::parse_with(&mut Cursor::new(&bytes), &options)?;
+		let mut found = Vec::new();
+		for attributes in class.fields.iter().map(|x| &x.attributes).chain(class.methods.iter().map(|x| &x.attributes)) {
+			parsed(attributes, &mut found);
+		}
+		parsed(&class.attributes, &mut found);
+		let expected: Vec<(String, Option<Vec<u8>>)> = expected.into_iter().map(|(name, bytes)| (name, Some(bytes))).collect();
+		assert_eq!(found, expected);
+		for name in ["ConstantValue", "Signature", "Exceptions", "LocalVariableTable", "LocalVariableTypeTable", "StackMapTable", "SourceFile", "InnerClasses"].iter() {
+			assert!(found.iter().any(|(x, _)| x == name), "no {} attribute", name);
+		}
+		
+		// without the option only the attributes that keep their contents anyway have them
+		let class = ClassFile::parse(&mut Cursor::new(&bytes))?;
+		let field = class.fields.iter().find(|x| x.name == "items").unwrap();
+		assert_eq!(field.attributes[0].raw_bytes(), None);
+		Ok(())
+	}
+	
+	#[cfg(feature = "serde")]
+	#[test]
+	fn json_round_trips() -> Result<()> {
+		use crate::ast::{Insn, LdcInsn, LdcType};
+		use crate::constantpool::{ConstantType, FloatInfo};
+		use std::io::Cursor;
+		
+		let bytes = &include_bytes!("../classes/snapshot/Snapshot.class")[..];
+		let (class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(bytes), &ParseOptions::default())?;
+		let json = serde_json::to_string(&class).unwrap();
+		assert!(json.contains(r#""op":"Label","id":0"#), "{}", json);
+		assert_eq!(serde_json::from_str::<ClassFile>(&json).unwrap(), class);
+		let json = serde_json::to_string(&constant_pool).unwrap();
+		assert_eq!(serde_json::from_str::<ConstantPool>(&json).unwrap(), constant_pool);
+		
+		// a NaN with a payload, which JSON cannot hold as a number
+		let nan = f32::from_bits(0x7FC0_0001);
+		let json = serde_json::to_string(&ConstantType::Float(FloatInfo::new(nan))).unwrap();
+		assert_eq!(json, r#"{"tag":"Float","bits":2143289345}"#);
+		match serde_json::from_str(&json).unwrap() {
+			ConstantType::Float(x) => assert_eq!(x.inner().to_bits(), nan.to_bits()),
+			x => panic!("{:?}", x)
+		}
+		let json = serde_json::to_string(&Insn::Ldc(LdcInsn::new(LdcType::Double(f64::NAN)))).unwrap();
+		match serde_json::from_str(&json).unwrap() {
+			Insn::Ldc(LdcInsn { constant: LdcType::Double(x) }) => assert_eq!(x.to_bits(), f64::NAN.to_bits()),
+			x => panic!("{:?}", x)
+		}
+		Ok(())
+	}
+	
+	#[test]
+	fn estimated_code_size_bounds_written_size() -> Result<()> {
+		use crate::ast::{Insn, LdcType};
+		use crate::attributes::AttributeKind;
+		use std::io::Cursor;
+		
+		let dir = TempDir::new("sizes")?;
+		let mut javac = Command::new("javac");
+		javac.arg("-d").arg(&dir);
+		for entry in fs::read_dir("classes/testing/")? {
+			let path = entry?.path();
+			if path.extension() == Some("java".as_ref()) {
+				javac.arg(path);
+			}
+		}
+		let mut classes = vec![fs::read("classes/snapshot/Snapshot.class")?];
+		match javac.output() {
+			Ok(output) if output.status.success() => {
+				for entry in fs::read_dir(&*dir)? {
+					classes.push(fs::read(entry?.path())?);
+				}
+			}
+			Ok(output) => panic!("{}", String::from_utf8_lossy(&output.stderr)),
+			Err(err) => println!("Not checking classes/testing, could not run javac: {}", err)
+		}
+		
+		for bytes in classes.iter() {
+			let (mut class, constant_pool) = ClassFile::parse_with_pool(&mut Cursor::new(bytes), &ParseOptions::default())?;
+			for method in class.methods.iter_mut() {
+				let context = format!("{}{}", method.name, method.descriptor);
+				let code = match method.code() {
+					Some(code) => code,
+					None => continue
+				};
+				// encoded again rather than copied, so without its stack map frames
+				code.mark_modified();
+				code.attributes.retain(|attr| attr.kind() != AttributeKind::StackMapTable);
+				let mut written = Vec::new();
+				code.write(&mut written, &mut ConstantPoolWriter::from_pool(&constant_pool))?;
+				// after max stack and max locals
+				let length = u32::from_be_bytes([written[4], written[5], written[6], written[7]]);
+				let estimated = code.insns.estimated_code_size()?;
+				// how much smaller than their encoded size each instruction can be written
+				let slack: u32 = code.insns.iter().map(|insn| match insn {
+					Insn::Jump(_) => 2,
+					Insn::ConditionalJump(_) => 5,
+					Insn::Ldc(x) => match x.constant {
+						LdcType::Null | LdcType::Long(_) | LdcType::Double(_) => 0,
+						_ => 1
+					},
+					Insn::LookupSwitch(_) | Insn::TableSwitch(_) => 3,
+					_ => 0
+				}).sum();
+				assert!(length <= estimated && estimated <= length + slack, "{}: wrote {} bytes, estimated {}", context, length, estimated);
+			}
+		}
+		Ok(())
+	}
 }
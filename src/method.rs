@@ -1,11 +1,15 @@
 use crate::access::MethodAccessFlags;
-use crate::attributes::{Attribute, Attributes, AttributeSource, SignatureAttribute, ExceptionsAttribute};
+use crate::attributes::{Attribute, Attributes, AttributeContext, AttributeSource, ExceptionsAttribute, ThrowsList, WriteContext};
 use crate::version::ClassVersion;
-use crate::constantpool::{ConstantPool, ConstantPoolWriter};
+use crate::classfile::{ParseOptions, MethodWriteStats};
+use crate::constantpool::{ConstantPool, ConstantPoolWriter, MemberIndices};
 use crate::Serializable;
-use crate::error::Result;
-use crate::utils::{VecUtils};
+use crate::error::{Result, ErrorContext, ParserError};
+use crate::path::{MemberPath, MemberKind, PathSegment};
+use crate::types::parse_method_desc;
+use crate::utils::{VecUtils, ClassRead, CountingReader};
 use crate::code::CodeAttribute;
+use crate::ast::Insn;
 use std::io::{Read, Write};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
@@ -16,89 +20,159 @@ pub mod Methods {
 	use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 	use crate::version::ClassVersion;
 	use crate::constantpool::{ConstantPool, ConstantPoolWriter};
+	use crate::attributes::WriteContext;
+	use crate::utils::{ClassRead, CountingReader};
+	use crate::error::ErrorContext;
+	use crate::classfile::ParseOptions;
 	
-	pub fn parse<T: Read>(rdr: &mut T, version: &ClassVersion, constant_pool: &ConstantPool) -> crate::Result<Vec<Method>> {
+	pub fn parse<T: Read>(rdr: &mut T, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> crate::Result<Vec<Method>> {
+		parse_from(&mut CountingReader::new(rdr), version, constant_pool, options)
+	}
+	
+	pub(crate) fn parse_from<T: ClassRead>(rdr: &mut T, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> crate::Result<Vec<Method>> {
 		let num_fields = rdr.read_u16::<BigEndian>()? as usize;
 		let mut fields: Vec<Method> = Vec::with_capacity(num_fields);
-		for _ in 0..num_fields {
-			fields.push(Method::parse(rdr, version, constant_pool)?);
+		for i in 0..num_fields {
+			fields.push(Method::parse_from(rdr, version, constant_pool, options).context(|| format!("methods[{}]", i))?);
 		}
 		Ok(fields)
 	}
 	
 	pub fn write<T: Write>(wtr: &mut T, fields: &[Method], constant_pool: &mut ConstantPoolWriter) -> crate::Result<()> {
+		write_with_context(wtr, fields, constant_pool, &mut WriteContext::default())
+	}
+	
+	pub(crate) fn write_with_context<T: Write>(wtr: &mut T, fields: &[Method], constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext) -> crate::Result<()> {
 		wtr.write_u16::<BigEndian>(fields.len() as u16)?;
 		for field in fields.iter() {
-			field.write(wtr, constant_pool)?;
+			field.write_with_context(wtr, constant_pool, context)?;
 		}
 		Ok(())
 	}
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Method {
 	pub access_flags: MethodAccessFlags,
 	pub name: String,
 	pub descriptor: String,
-	pub attributes: Vec<Attribute>
+	pub attributes: Vec<Attribute>,
+	/// Where the name and descriptor were in the constant pool, when parsed with
+	/// [ParseOptions::original_indices](crate::classfile::ParseOptions::original_indices)
+	pub original_indices: Option<MemberIndices>
+}
+
+/// Ignores the original indices, so that a parsed method equals the same method built by hand
+impl PartialEq for Method {
+	fn eq(&self, other: &Self) -> bool {
+		self.access_flags == other.access_flags && self.name == other.name && self.descriptor == other.descriptor
+			&& self.attributes == other.attributes
+	}
 }
 
 impl Method {
-	pub fn parse<R: Read>(rdr: &mut R, version: &ClassVersion, constant_pool: &ConstantPool) -> Result<Self> {
+	/// A method without attributes. Non abstract methods need a Code attribute, see
+	/// [`set_code`](Self::set_code).
+	pub fn new(access_flags: MethodAccessFlags, name: String, descriptor: String) -> Self {
+		Method {
+			access_flags,
+			name,
+			descriptor,
+			attributes: Vec::new(),
+			original_indices: None
+		}
+	}
+	
+	pub fn parse<R: Read>(rdr: &mut R, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> Result<Self> {
+		Method::parse_from(&mut CountingReader::new(rdr), version, constant_pool, options)
+	}
+	
+	pub(crate) fn parse_from<R: ClassRead>(rdr: &mut R, version: &ClassVersion, constant_pool: &ConstantPool, options: &ParseOptions) -> Result<Self> {
 		let access_flags = MethodAccessFlags::parse(rdr)?;
-		let name = constant_pool.utf8(rdr.read_u16::<BigEndian>()?)?.str.clone();
-		let descriptor = constant_pool.utf8(rdr.read_u16::<BigEndian>()?)?.str.clone();
+		let name_index = rdr.read_u16::<BigEndian>()?;
+		let name = constant_pool.utf8_inner(name_index).context(|| "name")?;
+		let descriptor_index = rdr.read_u16::<BigEndian>()?;
+		let descriptor = constant_pool.utf8_inner(descriptor_index).context(|| "descriptor")?;
 		
-		let attributes = Attributes::parse(rdr, AttributeSource::Method, version, constant_pool, &mut None)?;
+		let member = format!("{}{}", name, descriptor);
+		let mut context = AttributeContext::new(AttributeSource::Method, version, constant_pool, options);
+		let attributes = Self::parse_attributes(rdr, access_flags, &member, &descriptor, &mut context)
+			.map_err(|err| ParserError::in_member(MemberPath { kind: MemberKind::Method, name: name.clone(), descriptor: descriptor.clone() }, err))?;
 		
 		Ok(Method {
 			access_flags,
 			name,
 			descriptor,
-			attributes
+			attributes,
+			original_indices: if options.original_indices {
+				Some(MemberIndices { name: name_index, descriptor: descriptor_index })
+			} else {
+				None
+			}
 		})
 	}
 	
-	pub fn signature(&mut self) -> Option<&mut String> {
-		for attr in self.attributes.iter_mut() {
-			if let Attribute::Signature(sig) = attr {
-				return Some(&mut sig.signature)
-			}
+	fn parse_attributes<R: ClassRead>(rdr: &mut R, access_flags: MethodAccessFlags, member: &str, descriptor: &str, context: &mut AttributeContext) -> Result<Vec<Attribute>> {
+		if context.options.strict_descriptors {
+			parse_method_desc(descriptor).context(|| member.to_string())?;
 		}
-		None
+		let code_allowed = !access_flags.intersects(MethodAccessFlags::ABSTRACT | MethodAccessFlags::NATIVE);
+		Attributes::parse_member_from(rdr, context, member, code_allowed)
+	}
+	
+	pub fn signature(&mut self) -> Option<&mut String> {
+		Attributes::signature(&mut self.attributes)
+	}
+	
+	/// The generic signature of this method, for reading through a shared reference
+	pub fn signature_ref(&self) -> Option<&str> {
+		Attributes::signature_ref(&self.attributes)
 	}
 	
 	pub fn set_signature(&mut self, sig: Option<String>) {
-		let index = self.attributes.find_first(|attr| {
-			matches!(attr, Attribute::Signature(_))
-		});
-		if let Some(sig) = sig {
-			let attr = Attribute::Signature(SignatureAttribute::new(sig));
-			if let Some(index) = index {
-				self.attributes.replace(index, attr);
-			} else {
-				self.attributes.push(attr);
-			}
-		} else if let Some(index) = index {
-			self.attributes.remove(index);
-		}
+		Attributes::set_signature(&mut self.attributes, sig)
 	}
 	
-	pub fn exceptions(&mut self) -> Option<&mut Vec<String>> {
+	/// The classes this method declares it throws
+	pub fn exceptions(&mut self) -> Option<ThrowsList<'_>> {
 		for attr in self.attributes.iter_mut() {
 			if let Attribute::Exceptions(x) = attr {
-				return Some(&mut x.exceptions)
+				return Some(x.throws())
 			}
 		}
 		None
 	}
 	
-	pub fn set_exceptions(&mut self, exc: Option<Vec<String>>) {
+	/// Like [`exceptions`](Self::exceptions), for reading through a shared reference
+	pub fn exceptions_ref(&self) -> Option<&[String]> {
+		self.attributes.iter().find_map(|attr| match attr {
+			Attribute::Exceptions(x) => Some(x.exceptions.as_slice()),
+			_ => None
+		})
+	}
+	
+	/// Like [`exceptions`](Self::exceptions), adding an empty Exceptions attribute if there is none
+	pub fn exceptions_or_default(&mut self) -> ThrowsList<'_> {
+		if self.exceptions().is_none() {
+			self.attributes.push(Attribute::Exceptions(ExceptionsAttribute::new(Vec::new())));
+		}
+		self.exceptions().unwrap()
+	}
+	
+	/// Replaces the classes this method declares it throws, failing without changing anything if
+	/// one is not an internal class name. Use [`ThrowsList::as_vec_mut`] to set names unchecked.
+	pub fn set_exceptions(&mut self, exc: Option<Vec<String>>) -> Result<()> {
 		let index = self.attributes.find_first(|attr| {
 			matches!(attr, Attribute::Exceptions(_))
 		});
 		if let Some(exc) = exc {
-			let attr = Attribute::Exceptions(ExceptionsAttribute::new(exc));
+			let mut attr = ExceptionsAttribute::new(Vec::with_capacity(exc.len()));
+			let mut throws = attr.throws();
+			for name in exc.iter() {
+				throws.add(name)?;
+			}
+			let attr = Attribute::Exceptions(attr);
 			if let Some(index) = index {
 				self.attributes.replace(index, attr);
 			} else {
@@ -107,6 +181,7 @@ impl Method {
 		} else if let Some(index) = index {
 			self.attributes.remove(index);
 		}
+		Ok(())
 	}
 	
 	pub fn code(&mut self) -> Option<&mut CodeAttribute> {
@@ -118,6 +193,14 @@ impl Method {
 		None
 	}
 	
+	/// Like [`code`](Self::code), for reading through a shared reference
+	pub fn code_ref(&self) -> Option<&CodeAttribute> {
+		self.attributes.iter().find_map(|attr| match attr {
+			Attribute::Code(x) => Some(x),
+			_ => None
+		})
+	}
+	
 	pub fn set_code(&mut self, code: Option<CodeAttribute>) {
 		let index = self.attributes.find_first(|attr| {
 			matches!(attr, Attribute::Code(_))
@@ -135,10 +218,242 @@ impl Method {
 	}
 	
 	pub fn write<W: Write>(&self, wtr: &mut W, constant_pool: &mut ConstantPoolWriter) -> Result<()> {
+		self.write_with_context(wtr, constant_pool, &mut WriteContext::default())
+	}
+	
+	/// The method_info of this method on its own. Constants it refers to are added to
+	/// `constant_pool`, so the bytes are only valid in a class written with that same pool, which
+	/// may be a fresh [`ConstantPoolWriter::default`]. Parsed code is only copied as it was read if
+	/// the pool came from [`ConstantPoolWriter::from_pool`] on the pool the method was parsed with.
+	pub fn write_to_vec(&self, constant_pool: &mut ConstantPoolWriter) -> Result<Vec<u8>> {
+		let mut bytes = Vec::new();
+		self.write(&mut bytes, constant_pool)?;
+		Ok(bytes)
+	}
+	
+	/// Whether the method must have code, which is when it is neither abstract nor native
+	pub fn needs_code(&self) -> bool {
+		!self.access_flags.intersects(MethodAccessFlags::ABSTRACT | MethodAccessFlags::NATIVE)
+	}
+	
+	/// Fails with [`ParserError::EmptyCode`] if the method needs code but has a Code attribute
+	/// without instructions, or none at all unless `allow_missing`
+	pub(crate) fn check_code(&self, allow_missing: bool) -> Result<()> {
+		if !self.needs_code() {
+			return Ok(());
+		}
+		let empty = match self.attributes.iter().find(|attr| attr.name() == "Code") {
+			Some(Attribute::Code(code)) => code.insns.iter().all(|insn| matches!(insn, Insn::Label(_))),
+			Some(_) => false,
+			None => !allow_missing
+		};
+		if empty {
+			return Err(ParserError::empty_code(format!("{}{}", self.name, self.descriptor)));
+		}
+		Ok(())
+	}
+	
+	pub(crate) fn write_with_context<W: Write>(&self, wtr: &mut W, constant_pool: &mut ConstantPoolWriter, context: &mut WriteContext) -> Result<()> {
+		self.check_code(true)?;
 		self.access_flags.write(wtr)?;
 		wtr.write_u16::<BigEndian>(constant_pool.utf8(self.name.clone()))?;
 		wtr.write_u16::<BigEndian>(constant_pool.utf8(self.descriptor.clone()))?;
-		Attributes::write(wtr, &self.attributes, constant_pool, None)?;
+		Attributes::write_with_context(wtr, &self.attributes, AttributeSource::Method, constant_pool, None, context)
+			.path_context(|| PathSegment::Member(MemberPath::method(self)))?;
+		if let Some(stats) = context.stats.as_mut() {
+			stats.methods.push(MethodWriteStats {
+				name: self.name.clone(),
+				descriptor: self.descriptor.clone(),
+				code_length: context.code_length.take()
+			});
+		}
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::version::MajorVersion;
+	use crate::error::ParserError;
+	use crate::ast::{ReturnInsn, ReturnType};
+	use std::io::Cursor;
+	
+	/// A method_info named `run()V` with a valid attribute for each of the given names, along with
+	/// its pool
+	fn method_info(access_flags: MethodAccessFlags, attributes: &[&str]) -> (ConstantPool, Vec<u8>) {
+		let mut writer = ConstantPoolWriter::new();
+		let mut bytes = Vec::new();
+		access_flags.write(&mut bytes).unwrap();
+		bytes.write_u16::<BigEndian>(writer.utf8("run")).unwrap();
+		bytes.write_u16::<BigEndian>(writer.utf8("()V")).unwrap();
+		bytes.write_u16::<BigEndian>(attributes.len() as u16).unwrap();
+		for name in attributes.iter() {
+			let contents: Vec<u8> = match *name {
+				// max_stack, max_locals, a single return and no exception handlers or attributes
+				"Code" => vec![0, 1, 0, 1, 0, 0, 0, 1, 0xB1, 0, 0, 0, 0],
+				"Signature" => writer.utf8("()V").to_be_bytes().to_vec(),
+				"Exceptions" => [1, writer.class_utf8("java/lang/Exception")].iter().flat_map(|x: &u16| x.to_be_bytes()).collect(),
+				x => panic!("No contents for {}", x)
+			};
+			bytes.write_u16::<BigEndian>(writer.utf8(*name)).unwrap();
+			bytes.write_u32::<BigEndian>(contents.len() as u32).unwrap();
+			bytes.extend(contents);
+		}
+		
+		let mut pool = Vec::new();
+		writer.write(&mut pool).unwrap();
+		(ConstantPool::parse(&mut Cursor::new(pool)).unwrap(), bytes)
+	}
+	
+	fn parse(access_flags: MethodAccessFlags, attributes: &[&str], options: &ParseOptions) -> Result<Method> {
+		let (pool, bytes) = method_info(access_flags, attributes);
+		let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+		Method::parse(&mut Cursor::new(bytes), &version, &pool, options)
+	}
+	
+	fn lenient() -> ParseOptions {
+		ParseOptions {
+			lenient_member_attributes: true,
+			..Default::default()
+		}
+	}
+	
+	#[test]
+	fn one_of_each_attribute() {
+		let method = parse(MethodAccessFlags::PUBLIC, &["Code", "Signature", "Exceptions"], &ParseOptions::default()).unwrap();
+		assert!(matches!(method.attributes[..], [Attribute::Code(_), Attribute::Signature(_), Attribute::Exceptions(_)]));
+		
+		let method = parse(MethodAccessFlags::ABSTRACT, &["Signature", "Exceptions"], &ParseOptions::default()).unwrap();
+		assert_eq!(method.attributes.len(), 2);
+	}
+	
+	#[test]
+	fn duplicate_attributes_are_rejected() {
+		for name in ["Code", "Signature", "Exceptions"].iter() {
+			let err = parse(MethodAccessFlags::PUBLIC, &[name, "Code", name], &ParseOptions::default()).unwrap_err();
+			let index = if *name == "Code" { 1 } else { 2 };
+			assert_eq!(err.contexts(), vec![format!("attributes[{}]", index)]);
+			match err.root() {
+				ParserError::InvalidMemberAttribute { member, attribute, reason } => {
+					assert_eq!(member, "run()V");
+					assert_eq!(attribute, name);
+					assert_eq!(*reason, "duplicate");
+				},
+				x => panic!("Expected InvalidMemberAttribute, found {:?}", x)
+			}
+		}
+	}
+	
+	#[test]
+	fn code_on_abstract_or_native_is_rejected() {
+		for access_flags in [MethodAccessFlags::ABSTRACT, MethodAccessFlags::NATIVE | MethodAccessFlags::STATIC].iter() {
+			let err = parse(*access_flags, &["Code"], &ParseOptions::default()).unwrap_err();
+			assert_eq!(err.root().to_string(), "Invalid Code attribute on run()V: method is abstract or native");
+		}
+	}
+	
+	#[test]
+	fn lenient_keeps_extras_as_unknown() {
+		let method = parse(MethodAccessFlags::PUBLIC, &["Code", "Signature", "Code", "Signature"], &lenient()).unwrap();
+		match &method.attributes[..] {
+			[Attribute::Code(_), Attribute::Signature(_), Attribute::Unknown(code), Attribute::Unknown(signature)] => {
+				assert_eq!(code.name, "Code");
+				assert_eq!(code.buf, [0, 1, 0, 1, 0, 0, 0, 1, 0xB1, 0, 0, 0, 0]);
+				assert_eq!(signature.name, "Signature");
+			},
+			x => panic!("Unexpected attributes {:?}", x)
+		}
+		
+		let method = parse(MethodAccessFlags::ABSTRACT, &["Code"], &lenient()).unwrap();
+		assert!(matches!(&method.attributes[..], [Attribute::Unknown(x)] if x.name == "Code"));
+	}
+	
+	#[test]
+	fn strict_descriptors() {
+		let parse = |descriptor: &str, strict_descriptors: bool| {
+			let mut writer = ConstantPoolWriter::new();
+			let mut bytes = vec![0, 0x04]; // abstract, so no Code is needed
+			bytes.write_u16::<BigEndian>(writer.utf8("run")).unwrap();
+			bytes.write_u16::<BigEndian>(writer.utf8(descriptor)).unwrap();
+			bytes.write_u16::<BigEndian>(0).unwrap();
+			let mut pool = Vec::new();
+			writer.write(&mut pool).unwrap();
+			let pool = ConstantPool::parse(&mut Cursor::new(pool)).unwrap();
+			let version = ClassVersion { major: MajorVersion::JAVA_8, minor: 0 };
+			let options = ParseOptions { strict_descriptors, ..Default::default() };
+			Method::parse(&mut Cursor::new(bytes), &version, &pool, &options)
+		};
+		
+		for descriptor in ["([Ljava/lang/String;)V", "([[IJ)[Ljava/lang/Object;", "()Z"].iter() {
+			assert!(parse(descriptor, true).is_ok(), "{}", descriptor);
+		}
+		for descriptor in ["()", "(I", "(Q)V", "(V)V", "()VV", "V"].iter() {
+			let err = parse(descriptor, true).unwrap_err();
+			assert_eq!(err.contexts(), vec![format!("run{}", descriptor)]);
+			assert!(matches!(err.root(), ParserError::InvalidDescriptor(_)));
+			assert!(parse(descriptor, false).is_ok());
+		}
+	}
+	
+	#[test]
+	fn single_method_writes_standalone() {
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 1;
+		code.max_locals = 2;
+		code.insns.insns = vec![Insn::Return(ReturnInsn::new(ReturnType::Void))];
+		let mut method = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from("run"), String::from("()V"));
+		method.set_code(Some(code.clone()));
+		
+		let mut writer = ConstantPoolWriter::default();
+		let bytes = method.write_to_vec(&mut writer).unwrap();
+		// the pool is empty, so run, ()V and Code get the first three indices
+		let code_bytes = [0, 1, 0, 2, 0, 0, 0, 1, 0xB1, 0, 0, 0, 0];
+		let mut expected = vec![0, 0x09, 0, 1, 0, 2, 0, 1, 0, 3, 0, 0, 0, 13];
+		expected.extend_from_slice(&code_bytes);
+		assert_eq!(bytes, expected);
+		assert_eq!(writer.len(), 4);
+		
+		// the Code attribute on its own, reusing the constants already in the pool
+		assert_eq!(code.write_to_vec(&mut writer).unwrap(), code_bytes);
+		let attr = Attribute::Code(code).write_to_vec(&mut writer).unwrap();
+		assert_eq!(&attr[..6], &[0, 3, 0, 0, 0, 13]);
+		assert_eq!(&attr[6..], &code_bytes);
+		assert_eq!(writer.len(), 4);
+	}
+	
+	#[test]
+	fn throws_list_checks_names() {
+		let mut method = Method::new(MethodAccessFlags::PUBLIC, String::from("run"), String::from("()V"));
+		assert!(method.exceptions().is_none());
+		let mut throws = method.exceptions_or_default();
+		assert!(throws.add("java/io/IOException").unwrap());
+		assert!(!throws.add("java/io/IOException").unwrap());
+		// binary names and arrays are rejected rather than written as they are
+		for name in ["java.io.IOException", "[Ljava/lang/Exception;", "", "java//Foo"].iter() {
+			let err = throws.add(name).unwrap_err();
+			assert!(matches!(err, ParserError::InvalidDescriptor(_)), "{}", name);
+		}
+		assert!(throws.contains("java/io/IOException"));
+		assert_eq!(throws.iter().collect::<Vec<&str>>(), vec!["java/io/IOException"]);
+		assert_eq!(throws.to_attribute(), ExceptionsAttribute::new(vec![String::from("java/io/IOException")]));
+		
+		let err = method.set_exceptions(Some(vec![String::from("java/lang/Exception"), String::from("java.lang.Error")])).unwrap_err();
+		assert!(matches!(err, ParserError::InvalidDescriptor(_)), "{}", err);
+		assert_eq!(method.exceptions().unwrap().iter().collect::<Vec<&str>>(), vec!["java/io/IOException"]);
+		assert_eq!(method.exceptions_ref(), Some(&[String::from("java/io/IOException")][..]));
+		
+		method.set_exceptions(Some(vec![String::from("java/lang/Exception")])).unwrap();
+		let mut throws = method.exceptions().unwrap();
+		assert!(throws.remove("java/lang/Exception"));
+		assert!(!throws.remove("java/lang/Exception"));
+		assert!(throws.is_empty());
+		// names set through the Vec are taken as they are
+		throws.as_vec_mut().push(String::from("java.lang.Error"));
+		assert!(method.exceptions().unwrap().contains("java.lang.Error"));
+		
+		method.set_exceptions(None).unwrap();
+		assert!(method.exceptions().is_none());
+		assert!(method.attributes.is_empty());
+	}
+}
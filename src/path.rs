@@ -0,0 +1,366 @@
+//! Stable addresses of the members, attributes and instructions of a class, for reporting where
+//! something was found. Paths display as strings such as `method:main([Ljava/lang/String;)V/code/insn[17]`
+//! and parse back from them.
+
+use crate::classfile::ClassFile;
+use crate::field::Field;
+use crate::method::Method;
+use crate::attributes::Attribute;
+use crate::code::CodeAttribute;
+use crate::ast::Insn;
+use crate::error::{Result, ParserError};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MemberKind {
+	Field,
+	Method
+}
+
+/// A field or method, by name and descriptor
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MemberPath {
+	pub kind: MemberKind,
+	pub name: String,
+	pub descriptor: String
+}
+
+/// Where a list of attributes is
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AttrOwner {
+	Class,
+	Member(MemberPath),
+	/// The Code attribute of the method
+	Code(MemberPath)
+}
+
+/// An attribute, by its index in the list of its owner
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AttrPath {
+	pub owner: AttrOwner,
+	pub index: usize
+}
+
+/// An instruction, by its index in the code of a method. Labels count as instructions.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InsnPath {
+	pub method: MemberPath,
+	pub index: usize
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Path {
+	Class,
+	Member(MemberPath),
+	/// The Code attribute of the method
+	Code(MemberPath),
+	Attribute(AttrPath),
+	Insn(InsnPath)
+}
+
+/// One step towards where an error happened, attached to the error along with its context, see
+/// [`ParserError::path`]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+	Member(MemberPath),
+	/// An attribute by its index, in the attributes of the class, the member or its code
+	Attribute(usize),
+	/// An instruction by its index in the code
+	Insn(usize)
+}
+
+/// What a [`Path`] refers to, see [`ClassFile::resolve`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Element<'a> {
+	Class(&'a ClassFile),
+	Field(&'a Field),
+	Method(&'a Method),
+	Code(&'a CodeAttribute),
+	Attribute(&'a Attribute),
+	Insn(&'a Insn)
+}
+
+impl MemberPath {
+	pub fn field(field: &Field) -> Self {
+		MemberPath { kind: MemberKind::Field, name: field.name.clone(), descriptor: field.descriptor.clone() }
+	}
+	
+	pub fn method(method: &Method) -> Self {
+		MemberPath { kind: MemberKind::Method, name: method.name.clone(), descriptor: method.descriptor.clone() }
+	}
+	
+	/// The attribute at `index` in the attributes of this member
+	pub fn attribute(&self, index: usize) -> AttrPath {
+		AttrPath { owner: AttrOwner::Member(self.clone()), index }
+	}
+	
+	/// The attribute at `index` in the attributes of the code of this method
+	pub fn code_attribute(&self, index: usize) -> AttrPath {
+		AttrPath { owner: AttrOwner::Code(self.clone()), index }
+	}
+	
+	/// The instruction at `index` in the code of this method
+	pub fn insn(&self, index: usize) -> InsnPath {
+		InsnPath { method: self.clone(), index }
+	}
+}
+
+impl AttrPath {
+	/// The attribute at `index` in the attributes of the class
+	pub fn class(index: usize) -> Self {
+		AttrPath { owner: AttrOwner::Class, index }
+	}
+}
+
+impl From<MemberPath> for Path {
+	fn from(path: MemberPath) -> Self {
+		Path::Member(path)
+	}
+}
+
+impl From<AttrPath> for Path {
+	fn from(path: AttrPath) -> Self {
+		Path::Attribute(path)
+	}
+}
+
+impl From<InsnPath> for Path {
+	fn from(path: InsnPath) -> Self {
+		Path::Insn(path)
+	}
+}
+
+impl Display for MemberPath {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self.kind {
+			MemberKind::Field => write!(f, "field:{}:{}", self.name, self.descriptor),
+			MemberKind::Method => write!(f, "method:{}{}", self.name, self.descriptor)
+		}
+	}
+}
+
+/// Displays the segment as it appears in error contexts, such as `main([Ljava/lang/String;)V`,
+/// `count I` or `attributes[2]`
+impl Display for PathSegment {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			PathSegment::Member(member) if member.kind == MemberKind::Field => write!(f, "{} {}", member.name, member.descriptor),
+			PathSegment::Member(member) => write!(f, "{}{}", member.name, member.descriptor),
+			PathSegment::Attribute(index) => write!(f, "attributes[{}]", index),
+			PathSegment::Insn(index) => write!(f, "insns[{}]", index)
+		}
+	}
+}
+
+impl Display for AttrPath {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match &self.owner {
+			AttrOwner::Class => write!(f, "class/attribute[{}]", self.index),
+			AttrOwner::Member(member) => write!(f, "{}/attribute[{}]", member, self.index),
+			AttrOwner::Code(method) => write!(f, "{}/code/attribute[{}]", method, self.index)
+		}
+	}
+}
+
+impl Display for InsnPath {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/code/insn[{}]", self.method, self.index)
+	}
+}
+
+impl Display for Path {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Path::Class => write!(f, "class"),
+			Path::Member(x) => x.fmt(f),
+			Path::Code(x) => write!(f, "{}/code", x),
+			Path::Attribute(x) => x.fmt(f),
+			Path::Insn(x) => x.fmt(f)
+		}
+	}
+}
+
+impl FromStr for MemberPath {
+	type Err = ParserError;
+	
+	/// Names are split from descriptors at the first `:` of a field or `(` of a method
+	fn from_str(s: &str) -> Result<Self> {
+		if let Some(rest) = s.strip_prefix("field:") {
+			if let Some(at) = rest.find(':') {
+				return Ok(MemberPath { kind: MemberKind::Field, name: rest[..at].to_string(), descriptor: rest[at + 1..].to_string() });
+			}
+		} else if let Some(rest) = s.strip_prefix("method:") {
+			if let Some(at) = rest.find('(') {
+				return Ok(MemberPath { kind: MemberKind::Method, name: rest[..at].to_string(), descriptor: rest[at..].to_string() });
+			}
+		}
+		Err(ParserError::other(format!("Invalid member path {}", s)))
+	}
+}
+
+impl FromStr for Path {
+	type Err = ParserError;
+	
+	fn from_str(s: &str) -> Result<Self> {
+		// descriptors contain slashes, so the steps after the member are taken from the end
+		fn indexed<'a>(s: &'a str, step: &str) -> Option<(&'a str, usize)> {
+			let s = s.strip_suffix(']')?;
+			let at = s.rfind(step)?;
+			let index = s[at + step.len()..].parse().ok()?;
+			Some((&s[..at], index))
+		}
+		
+		if s == "class" {
+			return Ok(Path::Class);
+		}
+		if let Some((rest, index)) = indexed(s, "/code/insn[") {
+			return Ok(Path::Insn(InsnPath { method: rest.parse()?, index }));
+		}
+		if let Some((rest, index)) = indexed(s, "/attribute[") {
+			let owner = if rest == "class" {
+				AttrOwner::Class
+			} else if let Some(method) = rest.strip_suffix("/code") {
+				AttrOwner::Code(method.parse()?)
+			} else {
+				AttrOwner::Member(rest.parse()?)
+			};
+			return Ok(Path::Attribute(AttrPath { owner, index }));
+		}
+		if let Some(method) = s.strip_suffix("/code") {
+			return Ok(Path::Code(method.parse()?));
+		}
+		Ok(Path::Member(s.parse()?))
+	}
+}
+
+impl ClassFile {
+	/// The element `path` refers to, or None if the class has no such element
+	pub fn resolve(&self, path: &Path) -> Option<Element<'_>> {
+		Some(match path {
+			Path::Class => Element::Class(self),
+			Path::Member(member) => match member.kind {
+				MemberKind::Field => Element::Field(self.field(&member.name, &member.descriptor)?),
+				MemberKind::Method => Element::Method(self.method(&member.name, &member.descriptor)?)
+			},
+			Path::Code(method) => Element::Code(self.code_of(method)?),
+			Path::Attribute(attribute) => {
+				let attributes = match &attribute.owner {
+					AttrOwner::Class => &self.attributes,
+					AttrOwner::Member(member) => match member.kind {
+						MemberKind::Field => &self.field(&member.name, &member.descriptor)?.attributes,
+						MemberKind::Method => &self.method(&member.name, &member.descriptor)?.attributes
+					},
+					AttrOwner::Code(method) => &self.code_of(method)?.attributes
+				};
+				Element::Attribute(attributes.get(attribute.index)?)
+			},
+			Path::Insn(insn) => Element::Insn(self.code_of(&insn.method)?.insns.insns.get(insn.index)?)
+		})
+	}
+	
+	/// The path of every member, attribute and instruction of the class, in order
+	pub fn paths(&self) -> Vec<Path> {
+		let mut paths = vec![Path::Class];
+		paths.extend((0..self.attributes.len()).map(|i| Path::Attribute(AttrPath::class(i))));
+		let members = self.fields.iter().map(|x| (MemberPath::field(x), &x.attributes))
+			.chain(self.methods.iter().map(|x| (MemberPath::method(x), &x.attributes)));
+		for (member, attributes) in members {
+			paths.push(Path::Member(member.clone()));
+			paths.extend((0..attributes.len()).map(|i| Path::Attribute(member.attribute(i))));
+			if let Some(code) = attributes.iter().find_map(code) {
+				paths.push(Path::Code(member.clone()));
+				paths.extend((0..code.attributes.len()).map(|i| Path::Attribute(member.code_attribute(i))));
+				paths.extend((0..code.insns.len()).map(|i| Path::Insn(member.insn(i))));
+			}
+		}
+		paths
+	}
+	
+	fn code_of(&self, method: &MemberPath) -> Option<&CodeAttribute> {
+		if method.kind != MemberKind::Method {
+			return None;
+		}
+		self.method(&method.name, &method.descriptor)?.attributes.iter().find_map(code)
+	}
+}
+
+fn code(attribute: &Attribute) -> Option<&CodeAttribute> {
+	match attribute {
+		Attribute::Code(code) => Some(code),
+		_ => None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::ast::{GetFieldInsn, NopInsn};
+	use crate::access::{ClassAccessFlags, FieldAccessFlags};
+	use crate::version::{ClassVersion, MajorVersion};
+	use crate::attributes::{SignatureAttribute, ConstantValueAttribute, ConstantValue};
+	use crate::types::ClassName;
+	use crate::constantpool::ConstantPoolWriter;
+	use std::io::Cursor;
+	
+	fn snapshot() -> ClassFile {
+		ClassFile::parse(&mut Cursor::new(&include_bytes!("../classes/snapshot/Snapshot.class")[..])).unwrap()
+	}
+	
+	#[test]
+	fn paths_round_trip_through_strings() {
+		let class = snapshot();
+		let paths = class.paths();
+		assert!(paths.iter().any(|x| matches!(x, Path::Insn(_))));
+		for path in paths.iter() {
+			let parsed: Path = path.to_string().parse().unwrap();
+			assert_eq!(&parsed, path);
+			assert!(class.resolve(&parsed).is_some(), "{}", path);
+		}
+		
+		let main = MemberPath { kind: MemberKind::Method, name: String::from("main"), descriptor: String::from("([Ljava/lang/String;)V") };
+		assert_eq!(main.insn(1).to_string(), "method:main([Ljava/lang/String;)V/code/insn[1]");
+		let main_method = class.method("main", "([Ljava/lang/String;)V").unwrap();
+		assert_eq!(class.resolve(&main.clone().into()), Some(Element::Method(main_method)));
+		match class.resolve(&"method:main([Ljava/lang/String;)V/code/insn[1]".parse().unwrap()) {
+			Some(Element::Insn(insn)) => assert!(std::ptr::eq(insn, &class.code_of(&main).unwrap().insns.insns[1])),
+			x => panic!("{:?}", x)
+		}
+		let count: Path = "field:count:I/attribute[0]".parse().unwrap();
+		assert_eq!(count, Path::Attribute(AttrPath { owner: AttrOwner::Member(MemberPath { kind: MemberKind::Field, name: String::from("count"), descriptor: String::from("I") }), index: 0 }));
+		
+		// elements the class does not have
+		assert_eq!(class.resolve(&main.insn(100_000).into()), None);
+		assert_eq!(class.resolve(&"method:missing()V".parse().unwrap()), None);
+		assert!("method:main".parse::<Path>().is_err());
+	}
+	
+	#[test]
+	fn errors_name_where_they_happened() {
+		let mut class = snapshot();
+		let code = class.method_mut("main", "([Ljava/lang/String;)V").unwrap().code().unwrap();
+		code.insns.insert(0, Insn::Nop(NopInsn::new()));
+		code.insns.insert(1, Insn::GetField(Box::new(GetFieldInsn::new(false, ClassName::from("Snapshot"), String::from("x"), String::from("Q")))));
+		let err = class.write_with_pool(&mut Vec::new(), ConstantPoolWriter::new()).unwrap_err();
+		assert_eq!(err.path().unwrap().to_string(), "method:main([Ljava/lang/String;)V/code/insn[1]");
+		let err = class.remap_classes(&|_| None).unwrap_err();
+		assert_eq!(err.path().unwrap().to_string(), "method:main([Ljava/lang/String;)V/code/insn[1]");
+		
+		// taken from the error rather than its text, so a name with separators in it is kept whole
+		let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("Odd"), Some(ClassName::from("java/lang/Object")));
+		let mut field = Field::new(FieldAccessFlags::STATIC, String::from("odd:name(I)V"), String::from("I"));
+		field.attributes.push(Attribute::Signature(SignatureAttribute::new(String::from("I"))));
+		field.attributes.push(Attribute::Signature(SignatureAttribute::new(String::from("I"))));
+		class.fields.push(field);
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		let err = ClassFile::parse(&mut Cursor::new(&bytes)).unwrap_err();
+		assert!(matches!(err.root(), ParserError::InvalidMemberAttribute { .. }), "{}", err);
+		let odd = MemberPath { kind: MemberKind::Field, name: String::from("odd:name(I)V"), descriptor: String::from("I") };
+		assert_eq!(err.path(), Some(odd.attribute(1).into()));
+		
+		class.fields.last_mut().unwrap().attributes = vec![Attribute::ConstantValue(ConstantValueAttribute::new(ConstantValue::Long(1)))];
+		let err = class.write(&mut Vec::new()).unwrap_err();
+		assert_eq!(err.path(), Some(odd.into()));
+	}
+}
@@ -0,0 +1,206 @@
+use crate::ast::{Insn, InvokeType, MemberAccess, MemberReference};
+use crate::constantpool::MethodHandleKind;
+use crate::visitor::InsnVisitor;
+use crate::types::ClassName;
+use std::collections::HashSet;
+
+/// A method invoked by an instruction, the bootstrap method of an invokedynamic or the target of a
+/// method handle
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MethodRef {
+	pub class: ClassName,
+	pub name: String,
+	pub descriptor: String,
+	pub kind: InvokeType
+}
+
+/// A field read or written by an instruction or a method handle
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FieldRef {
+	pub class: ClassName,
+	pub name: String,
+	pub descriptor: String,
+	pub is_static: bool,
+	pub is_write: bool
+}
+
+/// The members a class refers to, see [`ClassFile::referenced_members`](crate::classfile::ClassFile::referenced_members)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemberRefs {
+	pub methods: HashSet<MethodRef>,
+	pub fields: HashSet<FieldRef>,
+	/// Classes created with a `new` instruction
+	pub instantiated: HashSet<String>,
+	/// Classes named by the Exceptions attribute of a method
+	pub thrown: HashSet<String>
+}
+
+impl MemberRefs {
+	/// Whether any method of this name declared by the class is invoked
+	pub fn calls_method(&self, class: &str, name: &str) -> bool {
+		self.methods.iter().any(|x| x.class == class && x.name == name)
+	}
+	
+	/// Whether the field is read
+	pub fn reads_field(&self, class: &str, name: &str) -> bool {
+		self.fields.iter().any(|x| x.class == class && x.name == name && !x.is_write)
+	}
+	
+	/// Whether the field is written
+	pub fn writes_field(&self, class: &str, name: &str) -> bool {
+		self.fields.iter().any(|x| x.class == class && x.name == name && x.is_write)
+	}
+	
+	pub fn instantiates(&self, class: &str) -> bool {
+		self.instantiated.contains(class)
+	}
+	
+	/// The invoked methods declared by the class
+	pub fn methods_of<'a>(&'a self, class: &'a str) -> impl Iterator<Item = &'a MethodRef> + 'a {
+		self.methods.iter().filter(move |x| x.class == class)
+	}
+}
+
+impl MemberRefs {
+	fn add(&mut self, member: MemberReference<'_>) {
+		let kind = match member.access {
+			MemberAccess::Invoke(kind) => kind,
+			MemberAccess::Field { is_static, is_write } => return self.add_field(member, is_static, is_write),
+			MemberAccess::Handle(kind) => match kind {
+				MethodHandleKind::GetField => return self.add_field(member, false, false),
+				MethodHandleKind::GetStatic => return self.add_field(member, true, false),
+				MethodHandleKind::PutField => return self.add_field(member, false, true),
+				MethodHandleKind::PutStatic => return self.add_field(member, true, true),
+				MethodHandleKind::InvokeVirtual => InvokeType::Instance,
+				MethodHandleKind::InvokeStatic => InvokeType::Static,
+				MethodHandleKind::InvokeSpecial | MethodHandleKind::NewInvokeSpecial => InvokeType::Special,
+				MethodHandleKind::InvokeInterface => InvokeType::Interface
+			}
+		};
+		self.methods.insert(MethodRef {
+			class: ClassName::from(member.class),
+			name: String::from(member.name),
+			descriptor: String::from(member.descriptor),
+			kind
+		});
+	}
+	
+	fn add_field(&mut self, member: MemberReference<'_>, is_static: bool, is_write: bool) {
+		self.fields.insert(FieldRef {
+			class: ClassName::from(member.class),
+			name: String::from(member.name),
+			descriptor: String::from(member.descriptor),
+			is_static,
+			is_write
+		});
+	}
+}
+
+/// Collects the [member references](Insn::member_references) of every instruction, so method
+/// handle targets such as lambda bodies are included
+impl InsnVisitor for MemberRefs {
+	fn visit_insn(&mut self, insn: &Insn) {
+		for member in insn.member_references() {
+			self.add(member);
+		}
+		if let Insn::NewObject(x) = insn {
+			self.instantiated.insert(x.kind.name());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::classfile::ClassFile;
+	use crate::code::CodeAttribute;
+	use crate::method::Method;
+	use crate::access::{ClassAccessFlags, MethodAccessFlags};
+	use crate::version::{ClassVersion, MajorVersion};
+	use std::io::Cursor;
+	
+	fn method(class: &str, name: &str, descriptor: &str, kind: InvokeType) -> MethodRef {
+		MethodRef { class: ClassName::from(class), name: String::from(name), descriptor: String::from(descriptor), kind }
+	}
+	
+	fn field(class: &str, name: &str, descriptor: &str, is_static: bool, is_write: bool) -> FieldRef {
+		FieldRef { class: ClassName::from(class), name: String::from(name), descriptor: String::from(descriptor), is_static, is_write }
+	}
+	
+	#[test]
+	fn snapshot_members() {
+		let class = ClassFile::parse(&mut Cursor::new(&include_bytes!("../classes/snapshot/Snapshot.class")[..])).unwrap();
+		let refs = class.referenced_members();
+		assert_eq!(refs.methods, vec![
+			method("java/lang/Object", "<init>", "()V", InvokeType::Special),
+			method("java/io/PrintStream", "println", "(Ljava/lang/String;)V", InvokeType::Instance),
+			method("java/lang/Integer", "parseInt", "(Ljava/lang/String;)I", InvokeType::Static)
+		].into_iter().collect());
+		assert_eq!(refs.fields, vec![field("java/lang/System", "out", "Ljava/io/PrintStream;", true, false)].into_iter().collect());
+		assert!(refs.instantiated.is_empty());
+		assert!(refs.thrown.is_empty());
+		
+		assert!(refs.calls_method("java/lang/Integer", "parseInt"));
+		assert!(!refs.calls_method("java/lang/Integer", "valueOf"));
+		assert!(refs.reads_field("java/lang/System", "out"));
+		assert!(!refs.writes_field("java/lang/System", "out"));
+		assert_eq!(refs.methods_of("java/io/PrintStream").count(), 1);
+	}
+	
+	#[test]
+	fn deduplicates_and_tracks_writes() {
+		let mut class = ClassFile::new(ClassVersion::new_major(MajorVersion::JAVA_8), ClassAccessFlags::PUBLIC, ClassName::from("Runner"), Some(ClassName::from("java/lang/Object")));
+		let mut code = CodeAttribute { max_stack: 3, max_locals: 1, ..CodeAttribute::default() };
+		code.insns.invoke_static("java/lang/Runtime", "getRuntime", "()Ljava/lang/Runtime;")
+			.ldc_string("calc")
+			.invoke_virtual("java/lang/Runtime", "exec", "(Ljava/lang/String;)Ljava/lang/Process;")
+			.put_static("Runner", "process", "Ljava/lang/Process;")
+			.invoke_static("java/lang/Runtime", "getRuntime", "()Ljava/lang/Runtime;")
+			.pop()
+			.new_object("java/lang/StringBuilder")
+			.dup()
+			.invoke_special("java/lang/StringBuilder", "<init>", "()V")
+			.get_field("java/lang/StringBuilder", "count", "I")
+			.pop()
+			.return_void();
+		let mut run = Method::new(MethodAccessFlags::STATIC, String::from("run"), String::from("()V"));
+		run.set_code(Some(code));
+		run.set_exceptions(Some(vec![String::from("java/io/IOException")])).unwrap();
+		class.add_method(run).unwrap();
+		
+		let refs = class.referenced_members();
+		assert_eq!(refs.methods, vec![
+			method("java/lang/Runtime", "getRuntime", "()Ljava/lang/Runtime;", InvokeType::Static),
+			method("java/lang/Runtime", "exec", "(Ljava/lang/String;)Ljava/lang/Process;", InvokeType::Instance),
+			method("java/lang/StringBuilder", "<init>", "()V", InvokeType::Special)
+		].into_iter().collect());
+		assert_eq!(refs.fields, vec![
+			field("Runner", "process", "Ljava/lang/Process;", true, true),
+			field("java/lang/StringBuilder", "count", "I", false, false)
+		].into_iter().collect());
+		assert_eq!(refs.instantiated, vec![String::from("java/lang/StringBuilder")].into_iter().collect());
+		assert_eq!(refs.thrown, vec![String::from("java/io/IOException")].into_iter().collect());
+		
+		assert!(refs.calls_method("java/lang/Runtime", "exec"));
+		assert!(refs.writes_field("Runner", "process"));
+		assert!(!refs.reads_field("Runner", "process"));
+		assert!(refs.instantiates("java/lang/StringBuilder"));
+		assert_eq!(refs.methods_of("java/lang/Runtime").count(), 2);
+	}
+	
+	#[test]
+	fn method_handles_are_included() {
+		use crate::ast::{LdcInsn, LdcType, MethodHandle};
+		let handle = |kind, name: &str, descriptor: &str| Insn::Ldc(LdcInsn::new(LdcType::MethodHandle(Box::new(MethodHandle::new(kind, ClassName::from("Runner"), String::from(name), String::from(descriptor), false)))));
+		let mut code = CodeAttribute { max_stack: 2, max_locals: 0, ..CodeAttribute::default() };
+		code.insns.push(handle(MethodHandleKind::InvokeStatic, "lambda$run$0", "()V"))
+			.push(handle(MethodHandleKind::PutStatic, "process", "Ljava/lang/Process;"))
+			.pop()
+			.pop()
+			.return_void();
+		let mut refs = MemberRefs::default();
+		code.accept(&mut refs);
+		assert_eq!(refs.methods, vec![method("Runner", "lambda$run$0", "()V", InvokeType::Static)].into_iter().collect());
+		assert_eq!(refs.fields, vec![field("Runner", "process", "Ljava/lang/Process;", true, true)].into_iter().collect());
+	}
+}
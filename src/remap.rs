@@ -0,0 +1,343 @@
+//! Renaming classes throughout a class file
+
+use crate::classfile::ClassFile;
+use crate::attributes::{Attribute, AttributeSource};
+use crate::code::CodeAttribute;
+use crate::ast::{Insn, ClassReferenceMut};
+use crate::types::remap_types;
+use crate::signature::{ClassSignature, FieldSignature, MethodSignature};
+use crate::error::{Result, ErrorContext};
+use crate::path::{MemberPath, PathSegment};
+use std::collections::HashMap;
+
+impl ClassFile {
+	/// Renames classes everywhere they are referred to: the class and its supertypes, member
+	/// descriptors and signatures, instructions, exception handlers, local variables and the
+	/// attributes describing nested classes and nests. `mapper` returns the new internal name of a
+	/// class, or None to keep it.
+	///
+	/// Class names inside unknown and custom attributes are left alone, as are the simple names of
+	/// inner classes.
+	pub fn remap_classes(&mut self, mapper: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+		self.mark_modified();
+		self.this_class = remap_class(&self.this_class, mapper)?.into();
+		if let Some(super_class) = &mut self.super_class {
+			*super_class = remap_class(super_class, mapper)?.into();
+		}
+		for interface in self.interfaces.iter_mut() {
+			*interface = remap_class(interface, mapper)?.into();
+		}
+		for field in self.fields.iter_mut() {
+			let path = MemberPath::field(field);
+			field.descriptor = remap_types(&field.descriptor, mapper).path_context(|| PathSegment::Member(path.clone()))?;
+			remap_attributes(&mut field.attributes, AttributeSource::Field, mapper).path_context(|| PathSegment::Member(path))?;
+		}
+		for method in self.methods.iter_mut() {
+			let path = MemberPath::method(method);
+			method.descriptor = remap_types(&method.descriptor, mapper).path_context(|| PathSegment::Member(path.clone()))?;
+			remap_attributes(&mut method.attributes, AttributeSource::Method, mapper).path_context(|| PathSegment::Member(path))?;
+		}
+		remap_attributes(&mut self.attributes, AttributeSource::Class, mapper)
+	}
+	
+	/// [`remap_classes`](Self::remap_classes) with the new name of each renamed class
+	pub fn remap_classes_with(&mut self, names: &HashMap<String, String>) -> Result<()> {
+		self.remap_classes(&|name| names.get(name).cloned())
+	}
+	
+	/// A copy of the class named `new_internal_name`, with every reference to the class itself
+	/// renamed and references to other classes, including its inner classes, left alone.
+	///
+	/// The InnerClasses, EnclosingMethod, NestHost and NestMembers attributes are kept with the
+	/// class renamed in them. The classes they name do not list the copy in turn, so a copy of a
+	/// nested class is not a nestmate of its host and cannot reach its private members.
+	pub fn duplicate_as(&self, new_internal_name: &str) -> Result<ClassFile> {
+		let mut copy = self.clone();
+		let old_name = self.this_class.to_string();
+		copy.remap_classes(&|name| if name == old_name { Some(new_internal_name.to_string()) } else { None })?;
+		Ok(copy)
+	}
+}
+
+impl CodeAttribute {
+	/// See [`ClassFile::remap_classes`]
+	pub fn remap_classes(&mut self, mapper: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+		self.mark_modified();
+		for (i, insn) in self.insns.insns.iter_mut().enumerate() {
+			insn.remap_classes(mapper).path_context(|| PathSegment::Insn(i))?;
+		}
+		for handler in self.exceptions.iter_mut() {
+			if let Some(catch_type) = &mut handler.catch_type {
+				*catch_type = remap_class(catch_type, mapper)?;
+			}
+		}
+		remap_attributes(&mut self.attributes, AttributeSource::Code, mapper)
+	}
+}
+
+impl Insn {
+	/// See [`ClassFile::remap_classes`]
+	pub fn remap_classes(&mut self, mapper: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+		for reference in self.class_references_mut() {
+			match reference {
+				ClassReferenceMut::Class(class) => *class = remap_class(class, mapper)?.into(),
+				ClassReferenceMut::Name(class) => *class = remap_class(class, mapper)?,
+				ClassReferenceMut::Descriptor(descriptor) => *descriptor = remap_types(descriptor, mapper)?
+			}
+		}
+		Ok(())
+	}
+}
+
+fn remap_attributes(attributes: &mut [Attribute], source: AttributeSource, mapper: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+	for attribute in attributes.iter_mut() {
+		match attribute {
+			Attribute::Signature(x) => x.signature = remap_signature(&x.signature, source, mapper).context(|| "Signature")?,
+			Attribute::Exceptions(x) => {
+				for exception in x.exceptions.iter_mut() {
+					*exception = remap_class(exception, mapper)?;
+				}
+			},
+			Attribute::Code(x) => x.remap_classes(mapper)?,
+			Attribute::LocalVariableTable(x) => {
+				for variable in x.variables.iter_mut() {
+					variable.descriptor = remap_types(&variable.descriptor, mapper).context(|| "LocalVariableTable")?;
+				}
+			},
+			Attribute::LocalVariableTypeTable(x) => {
+				for variable in x.variables.iter_mut() {
+					variable.descriptor = remap_signature(&variable.descriptor, AttributeSource::Field, mapper).context(|| "LocalVariableTypeTable")?;
+				}
+			},
+			Attribute::InnerClasses(x) => {
+				for class in x.classes.iter_mut() {
+					class.inner_class = remap_class(&class.inner_class, mapper)?;
+					if let Some(outer_class) = &mut class.outer_class {
+						*outer_class = remap_class(outer_class, mapper)?;
+					}
+				}
+			},
+			Attribute::EnclosingMethod(x) => {
+				x.class = remap_class(&x.class, mapper)?;
+				if let Some((_, descriptor)) = &mut x.method {
+					*descriptor = remap_types(descriptor, mapper).context(|| "EnclosingMethod")?;
+				}
+			},
+			Attribute::NestHost(x) => x.host = remap_class(&x.host, mapper)?,
+			Attribute::NestMembers(x) => {
+				for member in x.members.iter_mut() {
+					*member = remap_class(member, mapper)?;
+				}
+			},
+			_ => {}
+		}
+	}
+	Ok(())
+}
+
+/// Remaps a generic signature structurally, so inner classes are renamed by their binary names
+fn remap_signature(signature: &str, source: AttributeSource, mapper: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+	Ok(match source {
+		AttributeSource::Class => {
+			let mut signature = ClassSignature::parse(signature)?;
+			signature.map_class_names(mapper);
+			signature.to_string()
+		},
+		AttributeSource::Method => {
+			let mut signature = MethodSignature::parse(signature)?;
+			signature.map_class_names(mapper);
+			signature.to_string()
+		},
+		_ => {
+			let mut signature = FieldSignature::parse(signature)?;
+			signature.map_class_names(mapper);
+			signature.to_string()
+		}
+	})
+}
+
+/// Remaps an internal class name, or the descriptor of an array class
+fn remap_class(name: &str, mapper: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+	if name.starts_with('[') {
+		remap_types(name, mapper)
+	} else {
+		Ok(mapper(name).unwrap_or_else(|| name.to_string()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::types::{ClassName, Type};
+	use crate::access::{ClassAccessFlags, FieldAccessFlags, MethodAccessFlags};
+	use crate::ast::*;
+	use crate::attributes::{SignatureAttribute, ExceptionsAttribute, LocalVariableTableAttribute, LocalVariable, InnerClassesAttribute, InnerClass,
+		EnclosingMethodAttribute, NestHostAttribute, NestMembersAttribute};
+	use crate::access::InnerClassAccessFlags;
+	use crate::code::ExceptionHandler;
+	use crate::field::Field;
+	use crate::method::Method;
+	use crate::version::{ClassVersion, MajorVersion};
+	use std::io::Cursor;
+	
+	const FOO: &str = "com/example/Foo";
+	
+	fn foo() -> Type {
+		Type::Reference(Some(String::from(FOO)))
+	}
+	
+	/// A class referring to Foo in every place a class name can appear
+	fn uses_foo() -> ClassFile {
+		let mut code = CodeAttribute::empty();
+		let (start, end, handler) = (code.insns.new_label(), code.insns.new_label(), code.insns.new_label());
+		code.max_stack = 4;
+		code.max_locals = 2;
+		code.insns.insns = vec![
+			Insn::Label(start),
+			Insn::NewObject(NewObjectInsn::new(ClassRef::from(FOO))),
+			Insn::Dup(DupInsn::new(1, 0)),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Special, ClassName::from(FOO), String::from("<init>"), String::from("()V"), false))),
+			Insn::CheckCast(CheckCastInsn::new(ClassRef::Array(foo()))),
+			Insn::InstanceOf(InstanceOfInsn::new(ClassRef::from(FOO))),
+			Insn::GetField(Box::new(GetFieldInsn::new(false, ClassName::from(FOO), String::from("INSTANCE"), format!("L{};", FOO)))),
+			Insn::PutField(Box::new(PutFieldInsn::new(false, ClassName::from("Test"), String::from("foo"), format!("L{};", FOO)))),
+			Insn::Ldc(LdcInsn::new(LdcType::Class(format!("[L{};", FOO)))),
+			Insn::Ldc(LdcInsn::new(LdcType::MethodType(format!("(L{};)V", FOO)))),
+			Insn::MultiNewArray(MultiNewArrayInsn::new(ClassRef::Array(Type::Array(Box::new(foo()))), 2)),
+			Insn::NewArray(NewArrayInsn::new(foo())),
+			Insn::Label(end),
+			Insn::Return(ReturnInsn::new(ReturnType::Void)),
+			Insn::Label(handler),
+			Insn::Throw(ThrowInsn::new())
+		];
+		code.exceptions.push(ExceptionHandler {
+			start,
+			end,
+			handler,
+			catch_type: Some(String::from(FOO))
+		});
+		code.attributes.push(Attribute::LocalVariableTable(LocalVariableTableAttribute::new(vec![LocalVariable {
+			start,
+			end,
+			name: String::from("foo"),
+			descriptor: format!("L{};", FOO),
+			index: 1
+		}])));
+		
+		ClassFile {
+			magic: 0xCAFEBABE,
+			version: ClassVersion { major: MajorVersion::JAVA_8, minor: 0 },
+			access_flags: ClassAccessFlags::PUBLIC,
+			this_class: ClassName::from("Test"),
+			super_class: Some(ClassName::from(FOO)),
+			interfaces: vec![ClassName::from(format!("{}$Listener", FOO)), ClassName::from(FOO)],
+			fields: vec![Field {
+				access_flags: FieldAccessFlags::PRIVATE,
+				name: String::from("foo"),
+				descriptor: format!("L{};", FOO),
+				attributes: vec![Attribute::Signature(SignatureAttribute::new(format!("Ljava/util/List<L{};>;", FOO)))],
+				original_indices: None
+			}],
+			methods: vec![Method {
+				access_flags: MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC,
+				name: String::from("make"),
+				descriptor: format!("(L{};)[L{};", FOO, FOO),
+				attributes: vec![
+					Attribute::Code(code),
+					Attribute::Signature(SignatureAttribute::new(format!("<T:L{};>(TT;)[L{};^L{};", FOO, FOO, FOO))),
+					Attribute::Exceptions(ExceptionsAttribute::new(vec![String::from(FOO)]))
+				],
+				original_indices: None
+			}],
+			attributes: Vec::new(),
+			original_indices: None,
+			partial: None,
+			modified: Default::default()
+		}
+	}
+	
+	fn contains(haystack: &[u8], needle: &str) -> bool {
+		haystack.windows(needle.len()).any(|x| x == needle.as_bytes())
+	}
+	
+	#[test]
+	fn rename_everywhere() {
+		let mut class = uses_foo();
+		let mut names = HashMap::new();
+		names.insert(String::from(FOO), String::from("com/example/Bar"));
+		class.remap_classes_with(&names).unwrap();
+		
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		assert!(!contains(&bytes, "com/example/Foo;"));
+		assert!(!contains(&bytes, "Lcom/example/Foo"));
+		assert!(contains(&bytes, "com/example/Bar"));
+		// only the unmapped nested class still starts with the old name
+		let mut without_nested = bytes.clone();
+		let nested = "com/example/Foo$Listener";
+		let at = without_nested.windows(nested.len()).position(|x| x == nested.as_bytes()).unwrap();
+		without_nested.drain(at..at + nested.len());
+		assert!(!contains(&without_nested, FOO));
+		
+		let parsed = ClassFile::parse(&mut Cursor::new(bytes)).unwrap();
+		assert_eq!(parsed, class);
+		assert_eq!(parsed.super_class.as_deref(), Some("com/example/Bar"));
+		assert_eq!(parsed.methods[0].descriptor, "(Lcom/example/Bar;)[Lcom/example/Bar;");
+	}
+	
+	#[test]
+	fn unmapped_classes_are_unchanged() {
+		let mut class = uses_foo();
+		class.remap_classes(&|_| None).unwrap();
+		assert_eq!(class, uses_foo());
+	}
+	
+	#[test]
+	fn duplicate_renames_only_self_references() {
+		let nested = |inner_class: &str, inner_name: &str| InnerClass {
+			inner_class: inner_class.to_string(),
+			outer_class: Some(String::from(FOO)),
+			inner_name: Some(inner_name.to_string()),
+			access_flags: InnerClassAccessFlags::PUBLIC | InnerClassAccessFlags::STATIC
+		};
+		let nest_attributes = |name: &str| vec![
+			Attribute::InnerClasses(InnerClassesAttribute::new(vec![nested(name, "Nested"), nested(&format!("{}$Listener", FOO), "Listener")])),
+			Attribute::EnclosingMethod(EnclosingMethodAttribute::new(String::from(FOO), Some((String::from("make"), format!("(L{};)V", name))))),
+			Attribute::NestHost(NestHostAttribute::new(String::from(FOO)))
+		];
+		let mut class = uses_foo();
+		class.version.major = MajorVersion::JAVA_11;
+		class.attributes = nest_attributes("Test");
+		let copy = class.duplicate_as("Copy").unwrap();
+		assert_eq!(copy.attributes, nest_attributes("Copy"));
+		
+		let mut bytes = Vec::new();
+		copy.write(&mut bytes).unwrap();
+		assert!(!contains(&bytes, "Test"));
+		assert!(contains(&bytes, FOO));
+		let mut parsed = ClassFile::parse(&mut Cursor::new(bytes)).unwrap();
+		assert_eq!(parsed.this_class, ClassName::from("Copy"));
+		assert_eq!(parsed.super_class, Some(ClassName::from(FOO)));
+		assert_eq!(parsed.attributes, nest_attributes("Copy"));
+		let code = parsed.methods[0].code().unwrap();
+		assert!(code.insns.iter().any(|insn| matches!(insn, Insn::PutField(x) if x.class.as_str() == "Copy")));
+		assert!(code.insns.iter().any(|insn| matches!(insn, Insn::NewObject(x) if x.kind == ClassRef::from(FOO))));
+		// the original is untouched
+		assert_eq!(class.this_class, ClassName::from("Test"));
+	}
+	
+	#[test]
+	fn nest_members_are_remapped() {
+		let mut class = uses_foo();
+		class.version.major = MajorVersion::JAVA_11;
+		class.attributes.push(Attribute::NestMembers(NestMembersAttribute::new(vec![String::from(FOO), format!("{}$Listener", FOO)])));
+		class.remap_classes(&|name| if name == FOO { Some(String::from("com/example/Bar")) } else { None }).unwrap();
+		
+		let mut bytes = Vec::new();
+		class.write(&mut bytes).unwrap();
+		let parsed = ClassFile::parse(&mut Cursor::new(bytes)).unwrap();
+		assert_eq!(parsed.attributes, vec![
+			Attribute::NestMembers(NestMembersAttribute::new(vec![String::from("com/example/Bar"), format!("{}$Listener", FOO)]))
+		]);
+	}
+}
@@ -0,0 +1,323 @@
+//! Grep-style searching of the strings a class refers to: string constants, class references,
+//! member names and descriptors, each reported with the [`Path`] it was found at.
+
+use crate::classfile::ClassFile;
+use crate::attributes::{Attribute, ConstantValue};
+use crate::code::CodeAttribute;
+use crate::ast::{Insn, LdcType, BootstrapArgument, DynamicConstant, ClassReference};
+use crate::path::{Path, MemberPath, AttrPath};
+
+/// How a [`SearchQuery`] matches text
+#[derive(Clone, Debug)]
+pub enum Pattern {
+	/// The whole text
+	Exact(String),
+	/// Any part of the text
+	Substring(String),
+	#[cfg(feature = "regex")]
+	Regex(regex::Regex)
+}
+
+impl Pattern {
+	pub fn is_match(&self, text: &str) -> bool {
+		match self {
+			Pattern::Exact(x) => text == x,
+			Pattern::Substring(x) => text.contains(x.as_str()),
+			#[cfg(feature = "regex")]
+			Pattern::Regex(x) => x.is_match(text)
+		}
+	}
+}
+
+/// The kinds of text a query looks at
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SearchTarget {
+	/// String constants, from `ldc` and ConstantValue attributes
+	Strings,
+	/// Internal names of referenced classes, including array classes such as `[Ljava/lang/String;`
+	Classes,
+	/// Names of the members of the class and of referenced members
+	Names,
+	/// Descriptors and generic signatures
+	Descriptors
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchQuery {
+	pub pattern: Pattern,
+	/// The targets to look at, all of them when empty
+	pub targets: Vec<SearchTarget>
+}
+
+impl SearchQuery {
+	pub fn new(pattern: Pattern) -> Self {
+		SearchQuery { pattern, targets: Vec::new() }
+	}
+	
+	pub fn exact<S: Into<String>>(text: S) -> Self {
+		SearchQuery::new(Pattern::Exact(text.into()))
+	}
+	
+	pub fn substring<S: Into<String>>(text: S) -> Self {
+		SearchQuery::new(Pattern::Substring(text.into()))
+	}
+	
+	#[cfg(feature = "regex")]
+	pub fn regex(pattern: &str) -> std::result::Result<Self, regex::Error> {
+		Ok(SearchQuery::new(Pattern::Regex(regex::Regex::new(pattern)?)))
+	}
+	
+	/// Only looks at the given targets
+	pub fn in_targets(mut self, targets: &[SearchTarget]) -> Self {
+		self.targets = targets.to_vec();
+		self
+	}
+	
+	fn looks_at(&self, target: SearchTarget) -> bool {
+		self.targets.is_empty() || self.targets.contains(&target)
+	}
+}
+
+/// Text that matched a [`SearchQuery`]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Match {
+	pub path: Path,
+	pub target: SearchTarget,
+	pub text: String
+}
+
+impl ClassFile {
+	/// Every place in the class whose text matches `query`, in class file order.
+	///
+	/// Unknown and custom attributes, and annotations, are not searched.
+	pub fn search(&self, query: &SearchQuery) -> Vec<Match> {
+		let mut search = Search { query, matches: Vec::new() };
+		search.check(&Path::Class, SearchTarget::Classes, &self.this_class);
+		if let Some(super_class) = &self.super_class {
+			search.check(&Path::Class, SearchTarget::Classes, super_class);
+		}
+		for interface in self.interfaces.iter() {
+			search.check(&Path::Class, SearchTarget::Classes, interface);
+		}
+		for field in self.fields.iter() {
+			let member = MemberPath::field(field);
+			search.member(&member);
+			search.attributes(&field.attributes, |index| member.attribute(index), None);
+		}
+		for method in self.methods.iter() {
+			let member = MemberPath::method(method);
+			search.member(&member);
+			search.attributes(&method.attributes, |index| member.attribute(index), Some(&member));
+		}
+		search.attributes(&self.attributes, AttrPath::class, None);
+		search.matches
+	}
+}
+
+struct Search<'a> {
+	query: &'a SearchQuery,
+	matches: Vec<Match>
+}
+
+impl Search<'_> {
+	fn check(&mut self, path: &Path, target: SearchTarget, text: &str) {
+		if self.query.looks_at(target) && self.query.pattern.is_match(text) {
+			self.matches.push(Match { path: path.clone(), target, text: text.to_string() });
+		}
+	}
+	
+	fn member(&mut self, member: &MemberPath) {
+		let path = Path::Member(member.clone());
+		self.check(&path, SearchTarget::Names, &member.name);
+		self.check(&path, SearchTarget::Descriptors, &member.descriptor);
+	}
+	
+	/// `method` is the method owning the attributes, for its Code attribute
+	fn attributes(&mut self, attributes: &[Attribute], attr_path: impl Fn(usize) -> AttrPath, method: Option<&MemberPath>) {
+		for (index, attribute) in attributes.iter().enumerate() {
+			let path = Path::Attribute(attr_path(index));
+			match attribute {
+				Attribute::ConstantValue(x) => if let ConstantValue::String(value) = &x.value {
+					self.check(&path, SearchTarget::Strings, value);
+				},
+				Attribute::Signature(x) => self.check(&path, SearchTarget::Descriptors, &x.signature),
+				Attribute::Exceptions(x) => for exception in x.exceptions.iter() {
+					self.check(&path, SearchTarget::Classes, exception);
+				},
+				Attribute::Code(code) => if let Some(method) = method {
+					self.code(code, method);
+				},
+				_ => {}
+			}
+		}
+	}
+	
+	fn code(&mut self, code: &CodeAttribute, method: &MemberPath) {
+		for (index, insn) in code.insns.iter().enumerate() {
+			self.insn(&Path::Insn(method.insn(index)), insn);
+		}
+		let path = Path::Code(method.clone());
+		for handler in code.exceptions.iter() {
+			if let Some(catch_type) = &handler.catch_type {
+				self.check(&path, SearchTarget::Classes, catch_type);
+			}
+		}
+		self.attributes(&code.attributes, |index| method.code_attribute(index), None);
+	}
+	
+	fn insn(&mut self, path: &Path, insn: &Insn) {
+		for reference in insn.class_references() {
+			match reference {
+				ClassReference::Class(class) => self.check(path, SearchTarget::Classes, &class),
+				ClassReference::Descriptor(descriptor) => self.check(path, SearchTarget::Descriptors, descriptor)
+			}
+		}
+		for member in insn.member_references() {
+			self.check(path, SearchTarget::Names, member.name);
+		}
+		match insn {
+			Insn::Ldc(x) => match &x.constant {
+				LdcType::String(value) => self.check(path, SearchTarget::Strings, value),
+				LdcType::Dynamic(constant) => self.dynamic(path, constant),
+				_ => {}
+			},
+			Insn::InvokeDynamic(x) => {
+				self.check(path, SearchTarget::Names, &x.name);
+				self.arguments(path, &x.bootstrap_arguments);
+			}
+			_ => {}
+		}
+	}
+	
+	/// The parts of a dynamic constant not covered by the class and member references
+	fn dynamic(&mut self, path: &Path, constant: &DynamicConstant) {
+		self.check(path, SearchTarget::Names, &constant.name);
+		self.arguments(path, &constant.bootstrap_arguments);
+	}
+	
+	fn arguments(&mut self, path: &Path, arguments: &[BootstrapArgument]) {
+		for argument in arguments.iter() {
+			match argument {
+				BootstrapArgument::String(value) => self.check(path, SearchTarget::Strings, value),
+				BootstrapArgument::Dynamic(constant) => self.dynamic(path, constant),
+				_ => {}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::access::{ClassAccessFlags, FieldAccessFlags, MethodAccessFlags};
+	use crate::ast::*;
+	use crate::attributes::ConstantValueAttribute;
+	use crate::field::Field;
+	use crate::method::Method;
+	use crate::types::{ClassName, Type};
+	use crate::version::{ClassVersion, MajorVersion};
+	
+	const GREETING: &str = "Hello, world";
+	
+	/// A class with GREETING as the value of a constant and in the code of a method printing it
+	fn greeter() -> ClassFile {
+		let mut greeting = Field::new(FieldAccessFlags::PUBLIC | FieldAccessFlags::STATIC | FieldAccessFlags::FINAL, String::from("GREETING"), String::from("Ljava/lang/String;"));
+		greeting.attributes.push(Attribute::ConstantValue(ConstantValueAttribute::new(ConstantValue::String(String::from(GREETING)))));
+		
+		let mut code = CodeAttribute::empty();
+		code.max_stack = 2;
+		code.insns.insns = vec![
+			Insn::GetField(Box::new(GetFieldInsn::new(false, ClassName::from("java/lang/System"), String::from("out"), String::from("Ljava/io/PrintStream;")))),
+			Insn::Ldc(LdcInsn::new(LdcType::String(String::from(GREETING)))),
+			Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, ClassName::from("java/io/PrintStream"), String::from("println"), String::from("(Ljava/lang/String;)V"), false))),
+			Insn::Return(ReturnInsn::new(ReturnType::Void))
+		];
+		let mut greet = Method::new(MethodAccessFlags::PUBLIC | MethodAccessFlags::STATIC, String::from("greet"), String::from("()V"));
+		greet.attributes.push(Attribute::Code(code));
+		
+		ClassFile {
+			magic: 0xCAFEBABE,
+			version: ClassVersion { major: MajorVersion::JAVA_8, minor: 0 },
+			access_flags: ClassAccessFlags::PUBLIC,
+			this_class: ClassName::from("Greeter"),
+			super_class: Some(ClassName::from("java/lang/Object")),
+			interfaces: Vec::new(),
+			fields: vec![greeting],
+			methods: vec![greet],
+			attributes: Vec::new(),
+			original_indices: None,
+			partial: None,
+			modified: Default::default()
+		}
+	}
+	
+	fn paths(matches: &[Match]) -> Vec<String> {
+		matches.iter().map(|x| x.path.to_string()).collect()
+	}
+	
+	#[test]
+	fn finds_string_constants() {
+		let class = greeter();
+		let field = MemberPath::field(&class.fields[0]);
+		let method = MemberPath::method(&class.methods[0]);
+		let matches = class.search(&SearchQuery::exact(GREETING));
+		assert_eq!(matches, vec![
+			Match { path: Path::Attribute(field.attribute(0)), target: SearchTarget::Strings, text: String::from(GREETING) },
+			Match { path: Path::Insn(method.insn(1)), target: SearchTarget::Strings, text: String::from(GREETING) }
+		]);
+		for x in matches.iter() {
+			assert!(class.resolve(&x.path).is_some());
+		}
+		
+		assert!(class.search(&SearchQuery::exact("Hello")).is_empty());
+		assert_eq!(class.search(&SearchQuery::substring("Hello")).len(), 2);
+	}
+	
+	#[test]
+	fn targets_filter_matches() {
+		let class = greeter();
+		let query = SearchQuery::substring("PrintStream");
+		assert_eq!(paths(&class.search(&query)), vec![
+			"method:greet()V/code/insn[0]",
+			"method:greet()V/code/insn[2]"
+		]);
+		let classes = class.search(&query.in_targets(&[SearchTarget::Classes]));
+		assert_eq!(classes.len(), 1);
+		assert_eq!(classes[0].text, "java/io/PrintStream");
+		
+		let names = class.search(&SearchQuery::substring("GREET").in_targets(&[SearchTarget::Names]));
+		assert_eq!(paths(&names), vec!["field:GREETING:Ljava/lang/String;"]);
+	}
+	
+	#[test]
+	fn array_classes_are_descriptors() {
+		let mut class = greeter();
+		let strings = || ClassRef::Array(Type::object("java/lang/String"));
+		if let Attribute::Code(code) = &mut class.methods[0].attributes[0] {
+			code.insns.insns.extend(vec![
+				Insn::CheckCast(CheckCastInsn::new(strings())),
+				Insn::InstanceOf(InstanceOfInsn::new(strings())),
+				Insn::NewArray(NewArrayInsn::new(Type::object("java/lang/String"))),
+				Insn::NewArray(NewArrayInsn::new(strings().as_type())),
+				Insn::MultiNewArray(MultiNewArrayInsn::new(ClassRef::Array(strings().as_type()), 2))
+			]);
+		}
+		let query = SearchQuery::exact("[Ljava/lang/String;").in_targets(&[SearchTarget::Classes]);
+		assert_eq!(paths(&class.search(&query)), vec![
+			"method:greet()V/code/insn[4]",
+			"method:greet()V/code/insn[5]",
+			"method:greet()V/code/insn[7]"
+		]);
+		assert_eq!(class.search(&SearchQuery::exact("[[Ljava/lang/String;")).len(), 1);
+	}
+	
+	#[cfg(feature = "regex")]
+	#[test]
+	fn regex_patterns() {
+		let class = greeter();
+		let matches = class.search(&SearchQuery::regex("^java/(lang|io)/").unwrap());
+		let texts: Vec<&str> = matches.iter().map(|x| x.text.as_str()).collect();
+		assert_eq!(texts, vec!["java/lang/Object", "java/lang/System", "java/io/PrintStream"]);
+		assert!(SearchQuery::regex("(").is_err());
+	}
+}
@@ -0,0 +1,26 @@
+//! Serializes floats by their bits, so that every NaN survives formats such as JSON that have no
+//! NaN
+
+pub(crate) mod float {
+	use serde::{Deserialize, Deserializer, Serializer};
+	
+	pub(crate) fn serialize<S: Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_u32(value.to_bits())
+	}
+	
+	pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+		u32::deserialize(deserializer).map(f32::from_bits)
+	}
+}
+
+pub(crate) mod double {
+	use serde::{Deserialize, Deserializer, Serializer};
+	
+	pub(crate) fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_u64(value.to_bits())
+	}
+	
+	pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+		u64::deserialize(deserializer).map(f64::from_bits)
+	}
+}
@@ -0,0 +1,656 @@
+//! The generic signatures stored in Signature attributes, see JVMS §4.7.9.1
+
+use crate::types::Type;
+use crate::error::{Result, ParserError};
+use std::fmt::{Display, Formatter, Write};
+
+/// The signature of a generic class: its type parameters and parameterised supertypes
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassSignature {
+	pub type_parameters: Vec<TypeParameter>,
+	pub super_class: ClassTypeSignature,
+	pub interfaces: Vec<ClassTypeSignature>
+}
+
+/// The signature of a generic method. A void method has a `Base(Type::Void)` result.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MethodSignature {
+	pub type_parameters: Vec<TypeParameter>,
+	pub parameters: Vec<TypeSignature>,
+	pub result: TypeSignature,
+	/// Class types or type variables
+	pub throws: Vec<TypeSignature>
+}
+
+/// The signature of a field, which is always a reference type
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldSignature {
+	pub field_type: TypeSignature
+}
+
+/// A formal type parameter such as `T extends Number & Comparable<T>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeParameter {
+	pub name: String,
+	/// None for an empty class bound, as javac writes when the first bound is an interface
+	pub class_bound: Option<TypeSignature>,
+	pub interface_bounds: Vec<TypeSignature>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeSignature {
+	/// A primitive type, or void as a method result
+	Base(Type),
+	Class(ClassTypeSignature),
+	TypeVariable(String),
+	/// An array of the given component type
+	Array(Box<TypeSignature>)
+}
+
+/// A possibly parameterised class type, e.g. `java/util/Map<TK;TV;>.Entry`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassTypeSignature {
+	/// The internal name of the outermost class
+	pub name: String,
+	pub type_arguments: Vec<TypeArgument>,
+	/// Inner classes, named relative to the class before them
+	pub inner: Vec<InnerClassSignature>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InnerClassSignature {
+	pub name: String,
+	pub type_arguments: Vec<TypeArgument>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeArgument {
+	/// `*`
+	Any,
+	Exact(TypeSignature),
+	/// `+`, `? extends`
+	Extends(TypeSignature),
+	/// `-`, `? super`
+	Super(TypeSignature)
+}
+
+impl ClassSignature {
+	pub fn parse(signature: &str) -> Result<Self> {
+		SignatureParser::parse(signature, |parser| {
+			let type_parameters = parser.type_parameters()?;
+			let super_class = parser.class_type()?;
+			let mut interfaces = Vec::new();
+			while parser.peek().is_some() {
+				interfaces.push(parser.class_type()?);
+			}
+			Ok(ClassSignature { type_parameters, super_class, interfaces })
+		})
+	}
+	
+	/// Renames every class in this signature, see [`ClassTypeSignature::map_class_names`]
+	pub fn map_class_names(&mut self, mapper: &dyn Fn(&str) -> Option<String>) {
+		for parameter in self.type_parameters.iter_mut() {
+			parameter.map_class_names(mapper);
+		}
+		self.super_class.map_class_names(mapper);
+		for interface in self.interfaces.iter_mut() {
+			interface.map_class_names(mapper);
+		}
+	}
+}
+
+impl MethodSignature {
+	pub fn parse(signature: &str) -> Result<Self> {
+		SignatureParser::parse(signature, |parser| {
+			let type_parameters = parser.type_parameters()?;
+			parser.expect(b'(')?;
+			let mut parameters = Vec::new();
+			while parser.peek() != Some(b')') {
+				parameters.push(parser.java_type()?);
+			}
+			parser.expect(b')')?;
+			let result = if parser.peek() == Some(b'V') {
+				parser.pos += 1;
+				TypeSignature::Base(Type::Void)
+			} else {
+				parser.java_type()?
+			};
+			let mut throws = Vec::new();
+			while parser.peek() == Some(b'^') {
+				parser.pos += 1;
+				let thrown = parser.reference_type()?;
+				if let TypeSignature::Array(_) = thrown {
+					return Err(ParserError::invalid_descriptor("Cannot throw an array"));
+				}
+				throws.push(thrown);
+			}
+			Ok(MethodSignature { type_parameters, parameters, result, throws })
+		})
+	}
+	
+	/// Renames every class in this signature, see [`ClassTypeSignature::map_class_names`]
+	pub fn map_class_names(&mut self, mapper: &dyn Fn(&str) -> Option<String>) {
+		for parameter in self.type_parameters.iter_mut() {
+			parameter.map_class_names(mapper);
+		}
+		for parameter in self.parameters.iter_mut() {
+			parameter.map_class_names(mapper);
+		}
+		self.result.map_class_names(mapper);
+		for thrown in self.throws.iter_mut() {
+			thrown.map_class_names(mapper);
+		}
+	}
+}
+
+impl FieldSignature {
+	pub fn parse(signature: &str) -> Result<Self> {
+		SignatureParser::parse(signature, |parser| {
+			Ok(FieldSignature { field_type: parser.reference_type()? })
+		})
+	}
+	
+	/// Renames every class in this signature, see [`ClassTypeSignature::map_class_names`]
+	pub fn map_class_names(&mut self, mapper: &dyn Fn(&str) -> Option<String>) {
+		self.field_type.map_class_names(mapper);
+	}
+}
+
+impl TypeParameter {
+	fn map_class_names(&mut self, mapper: &dyn Fn(&str) -> Option<String>) {
+		if let Some(bound) = &mut self.class_bound {
+			bound.map_class_names(mapper);
+		}
+		for bound in self.interface_bounds.iter_mut() {
+			bound.map_class_names(mapper);
+		}
+	}
+}
+
+impl TypeSignature {
+	/// Renames every class in this type, see [`ClassTypeSignature::map_class_names`]
+	pub fn map_class_names(&mut self, mapper: &dyn Fn(&str) -> Option<String>) {
+		match self {
+			TypeSignature::Class(class) => class.map_class_names(mapper),
+			TypeSignature::Array(component) => component.map_class_names(mapper),
+			TypeSignature::Base(_) | TypeSignature::TypeVariable(_) => {}
+		}
+	}
+}
+
+impl ClassTypeSignature {
+	/// Renames the classes in this type and its type arguments. `mapper` returns the new internal
+	/// name of a class, or None to keep it.
+	///
+	/// Inner classes are mapped by their binary name, e.g. `java/util/Map$Entry`, and renamed to
+	/// the part of the result after their mapped outer class, or after its last `$` otherwise.
+	pub fn map_class_names(&mut self, mapper: &dyn Fn(&str) -> Option<String>) {
+		let mut binary_name = self.name.clone();
+		self.name = mapper(&self.name).unwrap_or_else(|| self.name.clone());
+		let mut mapped_name = self.name.clone();
+		for argument in self.type_arguments.iter_mut() {
+			argument.map_class_names(mapper);
+		}
+		for inner in self.inner.iter_mut() {
+			binary_name = format!("{}${}", binary_name, inner.name);
+			let outer = format!("{}$", mapped_name);
+			mapped_name = match mapper(&binary_name) {
+				Some(mapped) => {
+					inner.name = match mapped.strip_prefix(&outer) {
+						Some(name) => name.to_string(),
+						None => mapped[mapped.rfind('$').map_or(0, |i| i + 1)..].to_string()
+					};
+					mapped
+				},
+				None => format!("{}{}", outer, inner.name)
+			};
+			for argument in inner.type_arguments.iter_mut() {
+				argument.map_class_names(mapper);
+			}
+		}
+	}
+}
+
+impl TypeArgument {
+	fn map_class_names(&mut self, mapper: &dyn Fn(&str) -> Option<String>) {
+		match self {
+			TypeArgument::Any => {},
+			TypeArgument::Exact(x) | TypeArgument::Extends(x) | TypeArgument::Super(x) => x.map_class_names(mapper)
+		}
+	}
+}
+
+impl Display for ClassSignature {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write_type_parameters(f, &self.type_parameters)?;
+		write!(f, "L{};", self.super_class)?;
+		for interface in self.interfaces.iter() {
+			write!(f, "L{};", interface)?;
+		}
+		Ok(())
+	}
+}
+
+impl Display for MethodSignature {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write_type_parameters(f, &self.type_parameters)?;
+		f.write_char('(')?;
+		for parameter in self.parameters.iter() {
+			write!(f, "{}", parameter)?;
+		}
+		write!(f, "){}", self.result)?;
+		for thrown in self.throws.iter() {
+			write!(f, "^{}", thrown)?;
+		}
+		Ok(())
+	}
+}
+
+impl Display for FieldSignature {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.field_type)
+	}
+}
+
+impl Display for TypeSignature {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TypeSignature::Base(typ) => f.write_str(&typ.descriptor()),
+			TypeSignature::Class(class) => write!(f, "L{};", class),
+			TypeSignature::TypeVariable(name) => write!(f, "T{};", name),
+			TypeSignature::Array(component) => write!(f, "[{}", component)
+		}
+	}
+}
+
+/// Writes the class type without its surrounding `L` and `;`
+impl Display for ClassTypeSignature {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.name)?;
+		write_type_arguments(f, &self.type_arguments)?;
+		for inner in self.inner.iter() {
+			write!(f, ".{}", inner.name)?;
+			write_type_arguments(f, &inner.type_arguments)?;
+		}
+		Ok(())
+	}
+}
+
+impl Display for TypeArgument {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TypeArgument::Any => f.write_char('*'),
+			TypeArgument::Exact(x) => write!(f, "{}", x),
+			TypeArgument::Extends(x) => write!(f, "+{}", x),
+			TypeArgument::Super(x) => write!(f, "-{}", x)
+		}
+	}
+}
+
+fn write_type_parameters(f: &mut Formatter<'_>, parameters: &[TypeParameter]) -> std::fmt::Result {
+	if parameters.is_empty() {
+		return Ok(());
+	}
+	f.write_char('<')?;
+	for parameter in parameters.iter() {
+		write!(f, "{}:", parameter.name)?;
+		if let Some(bound) = &parameter.class_bound {
+			write!(f, "{}", bound)?;
+		}
+		for bound in parameter.interface_bounds.iter() {
+			write!(f, ":{}", bound)?;
+		}
+	}
+	f.write_char('>')
+}
+
+fn write_type_arguments(f: &mut Formatter<'_>, arguments: &[TypeArgument]) -> std::fmt::Result {
+	if arguments.is_empty() {
+		return Ok(());
+	}
+	f.write_char('<')?;
+	for argument in arguments.iter() {
+		write!(f, "{}", argument)?;
+	}
+	f.write_char('>')
+}
+
+/// The most dimensions an array type may have, see JVMS §4.3.2
+const MAX_ARRAY_DIMENSIONS: usize = 255;
+/// How deep array components and type arguments may nest together, so that parsing, printing or
+/// dropping a hostile signature cannot overflow the stack
+const MAX_NESTING: usize = 255;
+/// How deep type arguments may nest, which takes more stack to parse than an array dimension
+pub(crate) const MAX_TYPE_ARGUMENT_DEPTH: usize = 32;
+
+struct SignatureParser<'a> {
+	src: &'a str,
+	pos: usize,
+	/// The array dimensions and type arguments the parser is inside
+	depth: usize,
+	/// The type arguments the parser is inside
+	type_argument_depth: usize
+}
+
+impl <'a> SignatureParser<'a> {
+	/// Runs `f` over the whole of `signature`, failing if anything is left over
+	fn parse<T, F>(signature: &'a str, f: F) -> Result<T>
+		where F: FnOnce(&mut SignatureParser<'a>) -> Result<T> {
+		let mut parser = SignatureParser { src: signature, pos: 0, depth: 0, type_argument_depth: 0 };
+		let parsed = f(&mut parser)?;
+		match parser.peek() {
+			None => Ok(parsed),
+			Some(x) => Err(ParserError::invalid_descriptor(format!("Unexpected '{}' after the end of the signature", x as char)))
+		}
+	}
+	
+	fn peek(&self) -> Option<u8> {
+		self.src.as_bytes().get(self.pos).copied()
+	}
+	
+	fn expect(&mut self, c: u8) -> Result<()> {
+		match self.peek() {
+			Some(x) if x == c => {
+				self.pos += 1;
+				Ok(())
+			},
+			Some(x) => Err(ParserError::invalid_descriptor(format!("Expected '{}' but found '{}'", c as char, x as char))),
+			None => Err(ParserError::invalid_descriptor(format!("Expected '{}' but found the end", c as char)))
+		}
+	}
+	
+	/// An unqualified name, which may not contain any of `.;[/<>:`
+	fn identifier(&mut self) -> Result<String> {
+		let rest = &self.src.as_bytes()[self.pos..];
+		let len = rest.iter().position(|c| b".;[/<>:".contains(c)).unwrap_or(rest.len());
+		if len == 0 {
+			return Err(ParserError::invalid_descriptor("Empty name"));
+		}
+		let identifier = self.src[self.pos..self.pos + len].to_string();
+		self.pos += len;
+		Ok(identifier)
+	}
+	
+	fn type_parameters(&mut self) -> Result<Vec<TypeParameter>> {
+		let mut parameters = Vec::new();
+		if self.peek() != Some(b'<') {
+			return Ok(parameters);
+		}
+		self.expect(b'<')?;
+		loop {
+			let name = self.identifier()?;
+			self.expect(b':')?;
+			let class_bound = match self.peek() {
+				Some(b':') | Some(b'>') => None,
+				_ => Some(self.reference_type()?)
+			};
+			let mut interface_bounds = Vec::new();
+			while self.peek() == Some(b':') {
+				self.pos += 1;
+				interface_bounds.push(self.reference_type()?);
+			}
+			parameters.push(TypeParameter { name, class_bound, interface_bounds });
+			if self.peek() == Some(b'>') {
+				self.pos += 1;
+				return Ok(parameters);
+			}
+		}
+	}
+	
+	fn java_type(&mut self) -> Result<TypeSignature> {
+		let base = match self.peek() {
+			Some(b'B') => Type::Byte,
+			Some(b'C') => Type::Char,
+			Some(b'D') => Type::Double,
+			Some(b'F') => Type::Float,
+			Some(b'I') => Type::Int,
+			Some(b'J') => Type::Long,
+			Some(b'S') => Type::Short,
+			Some(b'Z') => Type::Boolean,
+			_ => return self.reference_type()
+		};
+		self.pos += 1;
+		Ok(TypeSignature::Base(base))
+	}
+	
+	fn reference_type(&mut self) -> Result<TypeSignature> {
+		match self.peek() {
+			Some(b'L') => Ok(TypeSignature::Class(self.class_type()?)),
+			Some(b'T') => {
+				self.pos += 1;
+				let name = self.identifier()?;
+				self.expect(b';')?;
+				Ok(TypeSignature::TypeVariable(name))
+			},
+			Some(b'[') => {
+				let start = self.pos;
+				while self.peek() == Some(b'[') {
+					self.pos += 1;
+				}
+				let dimensions = self.pos - start;
+				if dimensions > MAX_ARRAY_DIMENSIONS {
+					return Err(ParserError::invalid_descriptor(format!("Array has {} dimensions, more than {}", dimensions, MAX_ARRAY_DIMENSIONS)));
+				}
+				if self.depth + dimensions > MAX_NESTING {
+					return Err(ParserError::invalid_descriptor(format!("Signature nests deeper than {}", MAX_NESTING)));
+				}
+				self.depth += dimensions;
+				let component = self.java_type();
+				self.depth -= dimensions;
+				let mut typ = component?;
+				for _ in 0..dimensions {
+					typ = TypeSignature::Array(Box::new(typ));
+				}
+				Ok(typ)
+			},
+			Some(x) => Err(ParserError::invalid_descriptor(format!("Unknown type '{}'", x as char))),
+			None => Err(ParserError::invalid_descriptor("Empty type string"))
+		}
+	}
+	
+	fn class_type(&mut self) -> Result<ClassTypeSignature> {
+		self.expect(b'L')?;
+		let mut name = self.identifier()?;
+		while self.peek() == Some(b'/') {
+			self.pos += 1;
+			name.push('/');
+			name.push_str(&self.identifier()?);
+		}
+		let type_arguments = self.type_arguments()?;
+		let mut inner = Vec::new();
+		while self.peek() == Some(b'.') {
+			self.pos += 1;
+			let name = self.identifier()?;
+			let type_arguments = self.type_arguments()?;
+			inner.push(InnerClassSignature { name, type_arguments });
+		}
+		self.expect(b';')?;
+		Ok(ClassTypeSignature { name, type_arguments, inner })
+	}
+	
+	fn type_arguments(&mut self) -> Result<Vec<TypeArgument>> {
+		if self.peek() != Some(b'<') {
+			return Ok(Vec::new());
+		}
+		self.expect(b'<')?;
+		if self.type_argument_depth == MAX_TYPE_ARGUMENT_DEPTH {
+			return Err(ParserError::invalid_descriptor(format!("Type arguments nest deeper than {}", MAX_TYPE_ARGUMENT_DEPTH)));
+		}
+		if self.depth == MAX_NESTING {
+			return Err(ParserError::invalid_descriptor(format!("Signature nests deeper than {}", MAX_NESTING)));
+		}
+		self.depth += 1;
+		self.type_argument_depth += 1;
+		let arguments = self.type_argument_list();
+		self.depth -= 1;
+		self.type_argument_depth -= 1;
+		arguments
+	}
+	
+	fn type_argument_list(&mut self) -> Result<Vec<TypeArgument>> {
+		let mut arguments = Vec::new();
+		loop {
+			arguments.push(match self.peek() {
+				Some(b'*') => {
+					self.pos += 1;
+					TypeArgument::Any
+				},
+				Some(b'+') => {
+					self.pos += 1;
+					TypeArgument::Extends(self.reference_type()?)
+				},
+				Some(b'-') => {
+					self.pos += 1;
+					TypeArgument::Super(self.reference_type()?)
+				},
+				_ => TypeArgument::Exact(self.reference_type()?)
+			});
+			if self.peek() == Some(b'>') {
+				self.pos += 1;
+				return Ok(arguments);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	/// Signatures harvested from the JDK's generic classes
+	const CLASSES: [&str; 6] = [
+		"<T:Ljava/lang/Object;>Ljava/lang/Object;Ljava/io/Serializable;Ljava/lang/reflect/GenericDeclaration;Ljava/lang/reflect/Type;Ljava/lang/reflect/AnnotatedElement;Ljava/lang/invoke/TypeDescriptor$OfField<Ljava/lang/Class<*>;>;Ljava/lang/constant/Constable;",
+		"<E:Ljava/lang/Object;>Ljava/util/AbstractSet<TE;>;Ljava/util/NavigableSet<TE;>;",
+		"<K:Ljava/lang/Object;V:Ljava/lang/Object;>Ljava/util/AbstractMap<TK;TV;>;Ljava/util/concurrent/ConcurrentMap<TK;TV;>;Ljava/io/Serializable;",
+		"<E:Ljava/lang/Enum<TE;>;>Ljava/lang/Object;Ljava/lang/Comparable<TE;>;Ljava/io/Serializable;",
+		"<T::Ljava/lang/Comparable<-TT;>;>Ljava/lang/Object;",
+		"<V:Ljava/lang/Object;>Ljava/lang/Object;"
+	];
+	
+	const METHODS: [&str; 14] = [
+		"(TT;)TT;",
+		"(Ljava/lang/Class<*>;)Z",
+		"(Ljava/util/Map<+TK;+TV;>;)V",
+		"(JLjava/util/concurrent/TimeUnit;)TT;",
+		"<A::Ljava/lang/annotation/Annotation;>(Ljava/lang/Class<TA;>;)[TA;",
+		"([Ljava/lang/Class<*>;)Ljava/lang/reflect/Constructor<TT;>;",
+		"<T:Ljava/lang/Enum<TT;>;>(Ljava/lang/Class<TT;>;Ljava/lang/String;)TT;",
+		"(Ljava/lang/CharSequence;)Ljava/util/stream/Collector<Ljava/lang/CharSequence;*Ljava/lang/String;>;",
+		"(Ljava/util/concurrent/CompletableFuture<*>;Ljava/util/concurrent/CompletableFuture$BiCompletion<***>;)V",
+		"<K::Ljava/lang/Comparable<-TK;>;V:Ljava/lang/Object;>()Ljava/util/Comparator<Ljava/util/Map$Entry<TK;TV;>;>;",
+		"<T:Ljava/lang/Object;>(Ljava/util/function/BinaryOperator<TT;>;)Ljava/util/stream/Collector<TT;*Ljava/util/Optional<TT;>;>;",
+		"(Ljava/lang/Object;Ljava/util/function/Function<-Ljava/lang/Throwable;+TT;>;Ljava/util/concurrent/CompletableFuture$UniExceptionally<TT;>;)Z",
+		"<X:Ljava/lang/Throwable;>(Ljava/util/function/Supplier<+TX;>;)TT;^TX;",
+		"()Ljava/util/TreeMap<TK;TV;>.KeySet;^Ljava/io/IOException;^TE;"
+	];
+	
+	const FIELDS: [&str; 5] = [
+		"TT;",
+		"[TE;",
+		"[[Ljava/util/List<[I>;",
+		"Ljava/util/TreeMap<TK;TV;>.EntrySet;",
+		"Ljava/util/HashMap<TK;TV;>.Node<TK;TV;>.Inner;"
+	];
+	
+	#[test]
+	fn corpus_round_trips() {
+		for signature in CLASSES.iter() {
+			assert_eq!(ClassSignature::parse(signature).unwrap().to_string(), *signature);
+		}
+		for signature in METHODS.iter() {
+			assert_eq!(MethodSignature::parse(signature).unwrap().to_string(), *signature);
+		}
+		for signature in FIELDS.iter() {
+			assert_eq!(FieldSignature::parse(signature).unwrap().to_string(), *signature);
+		}
+	}
+	
+	#[test]
+	fn parses_structure() {
+		let signature = ClassSignature::parse("<T::Ljava/lang/Comparable<-TT;>;>Ljava/lang/Object;Ljava/util/Map<*+[I>.Entry<TT;>;").unwrap();
+		let comparable = ClassTypeSignature {
+			name: String::from("java/lang/Comparable"),
+			type_arguments: vec![TypeArgument::Super(TypeSignature::TypeVariable(String::from("T")))],
+			inner: Vec::new()
+		};
+		assert_eq!(signature.type_parameters, vec![TypeParameter {
+			name: String::from("T"),
+			class_bound: None,
+			interface_bounds: vec![TypeSignature::Class(comparable)]
+		}]);
+		assert_eq!(signature.super_class.name, "java/lang/Object");
+		assert_eq!(signature.interfaces, vec![ClassTypeSignature {
+			name: String::from("java/util/Map"),
+			type_arguments: vec![TypeArgument::Any, TypeArgument::Extends(TypeSignature::Array(Box::new(TypeSignature::Base(Type::Int))))],
+			inner: vec![InnerClassSignature {
+				name: String::from("Entry"),
+				type_arguments: vec![TypeArgument::Exact(TypeSignature::TypeVariable(String::from("T")))]
+			}]
+		}]);
+		
+		let signature = MethodSignature::parse("(IZ)V").unwrap();
+		assert_eq!(signature.parameters, vec![TypeSignature::Base(Type::Int), TypeSignature::Base(Type::Boolean)]);
+		assert_eq!(signature.result, TypeSignature::Base(Type::Void));
+	}
+	
+	#[test]
+	fn invalid_signatures_are_rejected() {
+		let classes = ["", "Ljava/lang/Object", "<>Ljava/lang/Object;", "<T>Ljava/lang/Object;", "Ljava/util/List<>;", "Ljava//List;", "TT;", "Ljava/lang/Object;I"];
+		for signature in classes.iter() {
+			assert!(ClassSignature::parse(signature).is_err(), "{}", signature);
+		}
+		let methods = ["()", "(V)V", "()VV", "()V^[Ljava/lang/Exception;", "()V^I", "(TT)V"];
+		for signature in methods.iter() {
+			assert!(MethodSignature::parse(signature).is_err(), "{}", signature);
+		}
+		let fields = ["I", "V", "[V", "Ljava/util/List<I>;", "Ljava/util/Map<TK;TV;>.;", "Ljava/lang/Object;;"];
+		for signature in fields.iter() {
+			assert!(FieldSignature::parse(signature).is_err(), "{}", signature);
+		}
+	}
+	
+	#[test]
+	fn deep_signatures_are_rejected() {
+		let widest = format!("{}I", "[".repeat(255));
+		assert_eq!(FieldSignature::parse(&widest).unwrap().to_string(), widest);
+		let err = FieldSignature::parse(&format!("{}I", "[".repeat(256))).unwrap_err();
+		assert_eq!(err.to_string(), "Invalid Descriptor: Array has 256 dimensions, more than 255");
+		assert!(FieldSignature::parse(&format!("{}I", "[".repeat(65000))).is_err());
+		
+		let nested = |depth: usize| format!("{}Ljava/lang/Object;{}", "Ljava/util/List<".repeat(depth), ">;".repeat(depth));
+		assert!(FieldSignature::parse(&nested(32)).is_ok());
+		let err = FieldSignature::parse(&nested(20000)).unwrap_err();
+		assert_eq!(err.to_string(), "Invalid Descriptor: Type arguments nest deeper than 32");
+		
+		// arrays count towards the nesting too
+		let deepest = format!("{}{}I{}", "Ljava/util/List<".repeat(32), "[".repeat(255 - 32), ">;".repeat(32));
+		let parsed = FieldSignature::parse(&deepest).unwrap();
+		assert_eq!(parsed.to_string(), deepest);
+		assert_eq!(parsed.clone(), parsed);
+		let err = FieldSignature::parse(&deepest.replacen('[', "[[", 1)).unwrap_err();
+		assert_eq!(err.to_string(), "Invalid Descriptor: Signature nests deeper than 255");
+	}
+	
+	#[test]
+	fn map_class_names() {
+		let mapper = |name: &str| match name {
+			"com/example/Foo" => Some(String::from("com/example/Bar")),
+			"com/example/Foo$Inner" => Some(String::from("com/example/Bar$Renamed")),
+			"com/example/Foo$Inner$Deep" => Some(String::from("a/b$c$d")),
+			"T" => Some(String::from("Wrong")),
+			_ => None
+		};
+		
+		let mut signature = FieldSignature::parse("Lcom/example/Foo<TT;>.Inner<Lcom/example/Foo;>.Deep.Kept;").unwrap();
+		signature.map_class_names(&mapper);
+		assert_eq!(signature.to_string(), "Lcom/example/Bar<TT;>.Renamed<Lcom/example/Bar;>.d.Kept;");
+		
+		let mut signature = MethodSignature::parse("<T:Lcom/example/Foo;>(Ljava/util/List<+Lcom/example/Foo;>;)[Lcom/example/Foo;^Lcom/example/Foo;^TT;").unwrap();
+		signature.map_class_names(&mapper);
+		assert_eq!(signature.to_string(), "<T:Lcom/example/Bar;>(Ljava/util/List<+Lcom/example/Bar;>;)[Lcom/example/Bar;^Lcom/example/Bar;^TT;");
+		
+		let mut signature = ClassSignature::parse("<T::Ljava/lang/Comparable<TT;>;>Lcom/example/Foo<TT;>;Ljava/util/List<Lcom/example/Foo$Inner;>;").unwrap();
+		signature.map_class_names(&mapper);
+		assert_eq!(signature.to_string(), "<T::Ljava/lang/Comparable<TT;>;>Lcom/example/Bar<TT;>;Ljava/util/List<Lcom/example/Bar$Renamed;>;");
+	}
+}
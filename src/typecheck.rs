@@ -0,0 +1,553 @@
+//! A cheap approximation of the JVM verifier, for catching broken transforms before writing a class
+
+use crate::ast::*;
+use crate::code::CodeAttribute;
+use crate::error::{Result, ParserError};
+use crate::types::{Type, parse_field_desc, parse_method_desc};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+/// A stack value or local variable, only as precise as the checks need
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Value {
+	/// Also booleans, bytes, chars and shorts
+	Int,
+	Float,
+	Long,
+	Double,
+	Reference,
+	/// Created by the `new` at this instruction index and not yet passed to a constructor
+	Uninitialized(usize),
+	/// An unset local, the second slot of a long or double, or types that differ between paths
+	Top
+}
+
+impl Value {
+	fn size(self) -> u16 {
+		match self {
+			Value::Long | Value::Double => 2,
+			_ => 1
+		}
+	}
+	
+	fn of_type(kind: &Type) -> std::result::Result<Self, String> {
+		Ok(match kind {
+			Type::Boolean | Type::Byte | Type::Char | Type::Short | Type::Int => Value::Int,
+			Type::Long => Value::Long,
+			Type::Float => Value::Float,
+			Type::Double => Value::Double,
+			Type::Reference(_) | Type::Array(_) => Value::Reference,
+			Type::Void => return Err(String::from("void is not a value type"))
+		})
+	}
+	
+	fn of_op(kind: OpType) -> Self {
+		match kind {
+			OpType::Reference => Value::Reference,
+			OpType::Long => Value::Long,
+			OpType::Float => Value::Float,
+			OpType::Double => Value::Double,
+			_ => Value::Int
+		}
+	}
+	
+	fn of_primitive(kind: PrimitiveType) -> Self {
+		match kind {
+			PrimitiveType::Long => Value::Long,
+			PrimitiveType::Float => Value::Float,
+			PrimitiveType::Double => Value::Double,
+			_ => Value::Int
+		}
+	}
+	
+	fn of_integer(kind: IntegerType) -> Self {
+		match kind {
+			IntegerType::Int => Value::Int,
+			IntegerType::Long => Value::Long
+		}
+	}
+	
+	/// The value both paths agree on
+	fn merge(self, other: Value) -> Value {
+		if self == other { self } else { Value::Top }
+	}
+}
+
+impl Display for Value {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Value::Int => "int",
+			Value::Float => "float",
+			Value::Long => "long",
+			Value::Double => "double",
+			Value::Reference => "reference",
+			Value::Uninitialized(_) => "uninitialized reference",
+			Value::Top => "unusable value"
+		})
+	}
+}
+
+/// The state before an instruction
+#[derive(Clone, Debug, PartialEq)]
+struct Frame {
+	stack: Vec<Value>,
+	/// One entry per slot, a long or double is followed by a Top
+	locals: Vec<Value>
+}
+
+type Check<T> = std::result::Result<T, String>;
+
+impl Frame {
+	fn height(&self) -> u16 {
+		self.stack.iter().map(|x| x.size()).sum()
+	}
+	
+	fn push(&mut self, value: Value, max_stack: u16) -> Check<()> {
+		self.stack.push(value);
+		if self.height() > max_stack {
+			return Err(format!("the stack grows past max_stack ({})", max_stack));
+		}
+		Ok(())
+	}
+	
+	fn pop_any(&mut self) -> Check<Value> {
+		self.stack.pop().ok_or_else(|| String::from("pops an empty stack"))
+	}
+	
+	fn pop(&mut self, expected: Value) -> Check<()> {
+		match self.pop_any()? {
+			found if found == expected => Ok(()),
+			found => Err(format!("expected {} on the stack but found {}", expected, found))
+		}
+	}
+	
+	/// Pops values taking up exactly `slots` slots, returned bottom first
+	fn pop_slots(&mut self, slots: u16) -> Check<Vec<Value>> {
+		let mut values = Vec::new();
+		let mut popped = 0;
+		while popped < slots {
+			let value = self.pop_any()?;
+			popped += value.size();
+			values.push(value);
+		}
+		if popped != slots {
+			return Err(String::from("splits a long or double on the stack"));
+		}
+		values.reverse();
+		Ok(values)
+	}
+	
+	fn load(&self, index: u16, expected: Value) -> Check<()> {
+		match self.locals.get(index as usize) {
+			None => Err(format!("local {} is outside max_locals ({})", index, self.locals.len())),
+			Some(found) if *found == expected => Ok(()),
+			Some(Value::Top) => Err(format!("local {} is unset or holds different types on different paths", index)),
+			Some(found) => Err(format!("expected {} in local {} but found {}", expected, index, found))
+		}
+	}
+	
+	fn store(&mut self, index: u16, value: Value) -> Check<()> {
+		let index = index as usize;
+		if index + value.size() as usize > self.locals.len() {
+			return Err(format!("local {} is outside max_locals ({})", index, self.locals.len()));
+		}
+		// overwriting the second slot of a long or double invalidates it
+		if index > 0 && self.locals[index - 1].size() == 2 {
+			self.locals[index - 1] = Value::Top;
+		}
+		self.locals[index] = value;
+		if value.size() == 2 {
+			self.locals[index + 1] = Value::Top;
+		}
+		Ok(())
+	}
+	
+	/// Merges the state of another path into this one, returning whether anything changed
+	fn merge(&mut self, other: &Frame) -> Check<bool> {
+		if self.height() != other.height() || self.stack.len() != other.stack.len() {
+			return Err(format!("paths arrive with stack heights {} and {}", self.height(), other.height()));
+		}
+		let before = self.clone();
+		for (value, other) in self.stack.iter_mut().zip(other.stack.iter())
+			.chain(self.locals.iter_mut().zip(other.locals.iter())) {
+			*value = value.merge(*other);
+		}
+		Ok(*self != before)
+	}
+}
+
+impl CodeAttribute {
+	/// Simulates the operand stack and locals with coarse types, like a much simpler JVM verifier.
+	/// Checks that instructions pop what they expect, loads and stores match the types of locals,
+	/// longs and doubles are not split by dups, pops and swaps, the stack stays within max_stack and
+	/// all paths into an instruction agree on the stack height. Fails with
+	/// [`ParserError::TypeCheck`] at the first problem found.
+	pub fn type_check(&self, method_desc: &str, is_static: bool) -> Result<()> {
+		let (args, ret) = parse_method_desc(method_desc)?;
+		let ret = match ret {
+			Type::Void => None,
+			ret => Some(Value::of_type(&ret).map_err(ParserError::invalid_descriptor)?)
+		};
+		
+		let mut labels = HashMap::new();
+		for (index, insn) in self.insns.iter().enumerate() {
+			if let Insn::Label(label) = insn {
+				labels.insert(*label, index);
+			}
+		}
+		let label_index = |label: LabelInsn| labels.get(&label).copied()
+			.ok_or_else(|| ParserError::unplaced_label(format!("L{}", label.id)));
+		let mut handlers = Vec::with_capacity(self.exceptions.len());
+		for handler in self.exceptions.iter() {
+			handlers.push((label_index(handler.start)?, label_index(handler.end)?, label_index(handler.handler)?));
+		}
+		
+		if self.insns.is_empty() {
+			return Err(ParserError::type_check(0, "there are no instructions"));
+		}
+		let mut entry = Frame {
+			stack: Vec::new(),
+			locals: vec![Value::Top; self.max_locals as usize]
+		};
+		let mut params = Vec::with_capacity(args.len() + 1);
+		if !is_static {
+			params.push(Value::Reference);
+		}
+		for arg in args.iter() {
+			params.push(Value::of_type(arg).map_err(ParserError::invalid_descriptor)?);
+		}
+		let mut slot = 0;
+		for param in params {
+			entry.store(slot, param).map_err(|_| ParserError::type_check(0, "the arguments do not fit in max_locals"))?;
+			slot += param.size();
+		}
+		
+		let mut frames: Vec<Option<Frame>> = vec![None; self.insns.len()];
+		frames[0] = Some(entry);
+		let mut pending = BTreeSet::new();
+		pending.insert(0);
+		while let Some(index) = pending.iter().next().copied() {
+			pending.remove(&index);
+			let mut frame = frames[index].clone().unwrap();
+			let insn = &self.insns.insns[index];
+			
+			let mut successors = Vec::new();
+			for (_, _, handler) in handlers.iter().filter(|(start, end, _)| *start <= index && index < *end) {
+				let caught = Frame {
+					stack: vec![Value::Reference],
+					locals: frame.locals.clone()
+				};
+				successors.push((*handler, caught));
+			}
+			self.step(insn, index, &mut frame, ret).map_err(|reason| ParserError::type_check(index, reason))?;
+			for target in insn.jump_targets() {
+				successors.push((label_index(target)?, frame.clone()));
+			}
+			if !insn.is_terminal() {
+				if index + 1 == self.insns.len() {
+					return Err(ParserError::type_check(index, "execution falls off the end of the code"));
+				}
+				successors.push((index + 1, frame));
+			}
+			
+			for (target, state) in successors {
+				let changed = match &mut frames[target] {
+					Some(existing) => existing.merge(&state).map_err(|reason| ParserError::type_check(target, reason))?,
+					slot => {
+						*slot = Some(state);
+						true
+					}
+				};
+				if changed {
+					pending.insert(target);
+				}
+			}
+		}
+		Ok(())
+	}
+	
+	/// Applies an instruction to the state before it
+	fn step(&self, insn: &Insn, index: usize, frame: &mut Frame, ret: Option<Value>) -> Check<()> {
+		let max_stack = self.max_stack;
+		match insn {
+			Insn::Label(_) | Insn::Jump(_) | Insn::Nop(_) | Insn::ImpDep1(_) | Insn::ImpDep2(_) | Insn::BreakPoint(_) => {},
+			Insn::ArrayLoad(x) => {
+				frame.pop(Value::Int)?;
+				frame.pop(Value::Reference)?;
+				frame.push(Value::of_type(&x.kind)?, max_stack)?;
+			},
+			Insn::ArrayStore(x) => {
+				frame.pop(Value::of_type(&x.kind)?)?;
+				frame.pop(Value::Int)?;
+				frame.pop(Value::Reference)?;
+			},
+			Insn::Ldc(x) => frame.push(match &x.constant {
+				LdcType::Int(_) => Value::Int,
+				LdcType::Float(_) => Value::Float,
+				LdcType::Long(_) => Value::Long,
+				LdcType::Double(_) => Value::Double,
+				LdcType::Dynamic(x) => Value::of_type(&parse_field_desc(&x.descriptor).map_err(|err| err.to_string())?)?,
+				_ => Value::Reference
+			}, max_stack)?,
+			Insn::LocalLoad(x) => {
+				frame.load(x.index, Value::of_op(x.kind))?;
+				frame.push(Value::of_op(x.kind), max_stack)?;
+			},
+			Insn::LocalStore(x) => {
+				let value = frame.pop_any()?;
+				let expected = Value::of_op(x.kind);
+				// uninitialized objects may be kept in locals
+				if value != expected && !(expected == Value::Reference && matches!(value, Value::Uninitialized(_))) {
+					return Err(format!("expected {} on the stack but found {}", expected, value));
+				}
+				frame.store(x.index, value)?;
+			},
+			Insn::NewArray(_) => {
+				frame.pop(Value::Int)?;
+				frame.push(Value::Reference, max_stack)?;
+			},
+			Insn::Return(x) => match (x.kind, ret) {
+				(ReturnType::Void, None) => {},
+				(ReturnType::Void, Some(ret)) => return Err(format!("returns nothing from a method returning {}", ret)),
+				(_, None) => return Err(String::from("returns a value from a void method")),
+				(kind, Some(ret)) => {
+					let value = match kind {
+						ReturnType::Reference => Value::Reference,
+						ReturnType::Long => Value::Long,
+						ReturnType::Float => Value::Float,
+						ReturnType::Double => Value::Double,
+						_ => Value::Int
+					};
+					if value != ret {
+						return Err(format!("returns {} from a method returning {}", value, ret));
+					}
+					frame.pop(value)?;
+				}
+			},
+			Insn::ArrayLength(_) | Insn::InstanceOf(_) => {
+				frame.pop(Value::Reference)?;
+				frame.push(Value::Int, max_stack)?;
+			},
+			Insn::CheckCast(_) => {
+				frame.pop(Value::Reference)?;
+				frame.push(Value::Reference, max_stack)?;
+			},
+			Insn::Throw(_) | Insn::MonitorEnter(_) | Insn::MonitorExit(_) => frame.pop(Value::Reference)?,
+			Insn::Convert(x) => {
+				frame.pop(Value::of_primitive(x.from))?;
+				frame.push(Value::of_primitive(x.to), max_stack)?;
+			},
+			Insn::Add(AddInsn { kind }) | Insn::Divide(DivideInsn { kind }) | Insn::Multiply(MultiplyInsn { kind })
+			| Insn::Remainder(RemainderInsn { kind }) | Insn::Subtract(SubtractInsn { kind }) => {
+				let value = Value::of_primitive(*kind);
+				frame.pop(value)?;
+				frame.pop(value)?;
+				frame.push(value, max_stack)?;
+			},
+			Insn::Compare(x) => {
+				let value = Value::of_primitive(x.kind);
+				frame.pop(value)?;
+				frame.pop(value)?;
+				frame.push(Value::Int, max_stack)?;
+			},
+			Insn::Negate(x) => {
+				frame.pop(Value::of_primitive(x.kind))?;
+				frame.push(Value::of_primitive(x.kind), max_stack)?;
+			},
+			Insn::And(AndInsn { kind }) | Insn::Or(OrInsn { kind }) | Insn::Xor(XorInsn { kind }) => {
+				let value = Value::of_integer(*kind);
+				frame.pop(value)?;
+				frame.pop(value)?;
+				frame.push(value, max_stack)?;
+			},
+			Insn::ShiftLeft(ShiftLeftInsn { kind }) | Insn::ShiftRight(ShiftRightInsn { kind })
+			| Insn::LogicalShiftRight(LogicalShiftRightInsn { kind }) => {
+				let value = Value::of_integer(*kind);
+				frame.pop(Value::Int)?;
+				frame.pop(value)?;
+				frame.push(value, max_stack)?;
+			},
+			Insn::Dup(x) => {
+				let top = frame.pop_slots(x.num as u16)?;
+				let below = frame.pop_slots(x.down as u16)?;
+				for value in top.iter().chain(below.iter()).chain(top.iter()) {
+					frame.push(*value, max_stack)?;
+				}
+			},
+			Insn::Pop(x) => {
+				frame.pop_slots(if x.pop_two { 2 } else { 1 })?;
+			},
+			Insn::Swap(_) => {
+				let top = frame.pop_slots(1)?;
+				let below = frame.pop_slots(1)?;
+				frame.push(top[0], max_stack)?;
+				frame.push(below[0], max_stack)?;
+			},
+			Insn::GetField(x) => {
+				let value = Value::of_type(&parse_field_desc(&x.descriptor).map_err(|err| err.to_string())?)?;
+				if x.instance {
+					frame.pop(Value::Reference)?;
+				}
+				frame.push(value, max_stack)?;
+			},
+			Insn::PutField(x) => {
+				frame.pop(Value::of_type(&parse_field_desc(&x.descriptor).map_err(|err| err.to_string())?)?)?;
+				if x.instance {
+					frame.pop(Value::Reference)?;
+				}
+			},
+			Insn::ConditionalJump(x) => match x.condition {
+				JumpCondition::IsNull | JumpCondition::NotNull => frame.pop(Value::Reference)?,
+				JumpCondition::ReferencesEqual | JumpCondition::ReferencesNotEqual => {
+					frame.pop(Value::Reference)?;
+					frame.pop(Value::Reference)?;
+				},
+				JumpCondition::IntsEq | JumpCondition::IntsNotEq | JumpCondition::IntsLessThan
+				| JumpCondition::IntsLessThanOrEq | JumpCondition::IntsGreaterThan | JumpCondition::IntsGreaterThanOrEq => {
+					frame.pop(Value::Int)?;
+					frame.pop(Value::Int)?;
+				},
+				_ => frame.pop(Value::Int)?
+			},
+			Insn::IncrementInt(x) => frame.load(x.index, Value::Int)?,
+			Insn::InvokeDynamic(x) => Self::invoke(frame, &x.descriptor, max_stack)?,
+			Insn::Invoke(x) => {
+				Self::invoke_arguments(frame, &x.descriptor)?;
+				if x.kind != InvokeType::Static {
+					match frame.pop_any()? {
+						Value::Uninitialized(created) if x.kind == InvokeType::Special && x.name == "<init>" => {
+							// every copy of the new object is now initialized
+							for value in frame.stack.iter_mut().chain(frame.locals.iter_mut()) {
+								if *value == Value::Uninitialized(created) {
+									*value = Value::Reference;
+								}
+							}
+						},
+						Value::Reference => {},
+						found => return Err(format!("expected the receiver on the stack but found {}", found))
+					}
+				}
+				Self::invoke_return(frame, &x.descriptor, max_stack)?;
+			},
+			Insn::LookupSwitch(_) | Insn::TableSwitch(_) => frame.pop(Value::Int)?,
+			Insn::MultiNewArray(x) => {
+				for _ in 0..x.dimensions {
+					frame.pop(Value::Int)?;
+				}
+				frame.push(Value::Reference, max_stack)?;
+			},
+			Insn::NewObject(_) => frame.push(Value::Uninitialized(index), max_stack)?
+		}
+		Ok(())
+	}
+	
+	fn invoke(frame: &mut Frame, descriptor: &str, max_stack: u16) -> Check<()> {
+		Self::invoke_arguments(frame, descriptor)?;
+		Self::invoke_return(frame, descriptor, max_stack)
+	}
+	
+	fn invoke_arguments(frame: &mut Frame, descriptor: &str) -> Check<()> {
+		let (args, _) = parse_method_desc(descriptor).map_err(|err| err.to_string())?;
+		for arg in args.iter().rev() {
+			frame.pop(Value::of_type(arg)?)?;
+		}
+		Ok(())
+	}
+	
+	fn invoke_return(frame: &mut Frame, descriptor: &str, max_stack: u16) -> Check<()> {
+		match parse_method_desc(descriptor).map_err(|err| err.to_string())?.1 {
+			Type::Void => Ok(()),
+			ret => frame.push(Value::of_type(&ret)?, max_stack)
+		}
+	}
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::attributes::Attribute;
+	use crate::classfile::ClassFile;
+	use std::io::Cursor;
+	
+	fn code(build: impl FnOnce(&mut CodeAttribute)) -> CodeAttribute {
+		let mut code = CodeAttribute { max_stack: 4, max_locals: 4, ..CodeAttribute::default() };
+		build(&mut code);
+		code
+	}
+	
+	/// The instruction index and reason of a failed check
+	fn failure(code: &CodeAttribute, descriptor: &str) -> (usize, String) {
+		match code.type_check(descriptor, true).unwrap_err() {
+			ParserError::TypeCheck { index, reason } => (index, reason),
+			err => panic!("{}", err)
+		}
+	}
+	
+	#[test]
+	fn compiled_code_type_checks() {
+		let class = ClassFile::parse(&mut Cursor::new(&include_bytes!("../classes/snapshot/Snapshot.class")[..])).unwrap();
+		for method in class.methods.iter() {
+			for attr in method.attributes.iter() {
+				if let Attribute::Code(code) = attr {
+					code.type_check(&method.descriptor, method.access_flags.is_static()).unwrap();
+				}
+			}
+		}
+	}
+	
+	#[test]
+	fn broken_code_is_rejected() {
+		let empty_pop = code(|code| { code.insns.pop().return_void(); });
+		assert_eq!(failure(&empty_pop, "()V"), (0, String::from("pops an empty stack")));
+		
+		// the jump arrives with an empty stack, falling through leaves an int
+		let jump = code(|code| {
+			let target = code.insns.new_label();
+			code.insns.load(OpType::Int, 0)
+				.push(Insn::ConditionalJump(ConditionalJumpInsn::new(JumpCondition::IntEqZero, target)))
+				.ldc_int(1)
+				.push(Insn::Label(target))
+				.return_void();
+		});
+		assert_eq!(failure(&jump, "(I)V"), (3, String::from("paths arrive with stack heights 0 and 1")));
+		
+		let split = code(|code| { code.insns.push(Insn::Ldc(LdcInsn::new(LdcType::Long(0)))).pop().return_void(); });
+		assert_eq!(failure(&split, "()V").0, 1);
+		let swap = code(|code| {
+			code.insns.ldc_int(0).push(Insn::Ldc(LdcInsn::new(LdcType::Double(0.0)))).push(Insn::Swap(SwapInsn::new())).return_void();
+		});
+		assert_eq!(failure(&swap, "()V"), (2, String::from("splits a long or double on the stack")));
+		
+		let local = code(|code| { code.insns.load(OpType::Reference, 0).return_value(ReturnType::Reference); });
+		assert_eq!(failure(&local, "(I)Ljava/lang/Object;"), (0, String::from("expected reference in local 0 but found int")));
+		assert_eq!(failure(&local, "()Ljava/lang/Object;").0, 0);
+		assert_eq!(failure(&local, "(Ljava/lang/Object;)I"), (1, String::from("returns reference from a method returning int")));
+		
+		let arguments = code(|code| { code.insns.ldc_int(1).invoke_static("Test", "add", "(II)I").pop().return_void(); });
+		assert_eq!(failure(&arguments, "()V"), (1, String::from("pops an empty stack")));
+		
+		let off_end = code(|code| { code.insns.ldc_int(1).pop(); });
+		assert_eq!(failure(&off_end, "()V"), (1, String::from("execution falls off the end of the code")));
+		
+		let too_deep = code(|code| { code.insns.ldc_int(1).dup().dup().dup().dup(); });
+		assert_eq!(failure(&too_deep, "()V"), (4, String::from("the stack grows past max_stack (4)")));
+	}
+	
+	#[test]
+	fn objects_are_initialized_by_constructors() {
+		let valid = code(|code| {
+			code.insns.new_object("Test")
+				.dup()
+				.invoke_special("Test", "<init>", "()V")
+				.return_value(ReturnType::Reference);
+		});
+		valid.type_check("()LTest;", true).unwrap();
+		
+		let uninitialized = code(|code| {
+			code.insns.new_object("Test")
+				.dup()
+				.invoke_virtual("Test", "run", "()V")
+				.return_value(ReturnType::Reference);
+		});
+		assert_eq!(failure(&uninitialized, "()LTest;"), (2, String::from("expected the receiver on the stack but found uninitialized reference")));
+	}
+}
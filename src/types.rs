@@ -1,4 +1,8 @@
 use crate::error::{Result, ParserError};
+use crate::signature::MAX_TYPE_ARGUMENT_DEPTH;
+use std::borrow::Borrow;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
 
 const VOID: char = 'V';
 const BYTE: char = 'B';
@@ -11,6 +15,7 @@ const SHORT: char = 'S';
 const BOOLEAN: char = 'Z';
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
 	Reference(Option<String>), // If None then the reference refers to no particular class
 	Boolean,
@@ -21,7 +26,9 @@ pub enum Type {
 	Long,
 	Float,
 	Double,
-	Void
+	Void,
+	/// An array of the given component type
+	Array(Box<Type>)
 }
 
 impl Type {
@@ -38,21 +45,205 @@ impl Type {
 			Type::Float => 1,
 			Type::Double => 2,
 			Type::Void => 0,
+			Type::Array(_) => 1
 		}
 	}
+	
+	/// A reference to the class with the given internal name
+	pub fn object(class: &str) -> Self {
+		Type::Reference(Some(class.to_string()))
+	}
+	
+	/// The field descriptor of this type, e.g. `[Ljava/lang/String;`
+	pub fn descriptor(&self) -> String {
+		match self {
+			Type::Reference(Some(class)) => format!("L{};", class),
+			Type::Reference(None) => String::from("Ljava/lang/Object;"),
+			Type::Boolean => BOOLEAN.to_string(),
+			Type::Byte => BYTE.to_string(),
+			Type::Char => CHAR.to_string(),
+			Type::Short => SHORT.to_string(),
+			Type::Int => INT.to_string(),
+			Type::Long => LONG.to_string(),
+			Type::Float => FLOAT.to_string(),
+			Type::Double => DOUBLE.to_string(),
+			Type::Void => VOID.to_string(),
+			Type::Array(component) => format!("[{}", component.descriptor())
+		}
+	}
+}
+
+/// A class name in internal form, such as `java/lang/String`. Array classes are named by their
+/// descriptor, as in the constant pool.
+///
+/// The `From` conversions take the name as it is, use [`from_internal`](Self::from_internal) to
+/// validate it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct ClassName(String);
+
+impl ClassName {
+	/// A name such as `java/lang/String` or `[I`
+	pub fn from_internal(name: &str) -> Result<Self> {
+		if name.starts_with('[') {
+			if let Type::Array(_) = parse_field_desc(name)? {
+				return Ok(ClassName(name.to_string()));
+			}
+		}
+		if name.split('/').any(|part| part.is_empty() || part.contains(&['.', ';', '['][..])) {
+			return Err(ParserError::invalid_descriptor(format!("Invalid internal class name {}", name)));
+		}
+		Ok(ClassName(name.to_string()))
+	}
+	
+	/// A name such as `java.lang.String`, or `[Ljava.lang.String;` as given by `Class.getName`
+	pub fn from_binary(name: &str) -> Result<Self> {
+		if name.contains('/') {
+			return Err(ParserError::invalid_descriptor(format!("Invalid binary class name {}", name)));
+		}
+		ClassName::from_internal(&name.replace('.', "/"))
+	}
+	
+	/// A descriptor such as `Ljava/lang/String;` or `[I`
+	pub fn from_descriptor(desc: &str) -> Result<Self> {
+		if desc.starts_with('[') {
+			return ClassName::from_internal(desc);
+		}
+		match desc.strip_prefix('L').and_then(|x| x.strip_suffix(';')) {
+			Some(name) => ClassName::from_internal(name),
+			None => Err(ParserError::invalid_descriptor(format!("Invalid class descriptor {}", desc)))
+		}
+	}
+	
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+	
+	pub fn into_string(self) -> String {
+		self.0
+	}
+	
+	pub fn is_array(&self) -> bool {
+		self.0.starts_with('[')
+	}
+	
+	/// The name as given by `Class.getName`, e.g. `java.lang.String`
+	pub fn to_binary(&self) -> String {
+		self.0.replace('/', ".")
+	}
+	
+	/// The field descriptor of a reference to this class, e.g. `Ljava/lang/String;`
+	pub fn to_descriptor(&self) -> String {
+		if self.is_array() {
+			self.0.clone()
+		} else {
+			format!("L{};", self.0)
+		}
+	}
+}
+
+impl Deref for ClassName {
+	type Target = str;
+	
+	fn deref(&self) -> &str {
+		&self.0
+	}
+}
+
+impl AsRef<str> for ClassName {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+impl Borrow<str> for ClassName {
+	fn borrow(&self) -> &str {
+		&self.0
+	}
+}
+
+impl Display for ClassName {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl From<String> for ClassName {
+	fn from(name: String) -> Self {
+		ClassName(name)
+	}
+}
+
+impl From<&str> for ClassName {
+	fn from(name: &str) -> Self {
+		ClassName(name.to_string())
+	}
+}
+
+impl From<ClassName> for String {
+	fn from(name: ClassName) -> Self {
+		name.0
+	}
+}
+
+impl PartialEq<str> for ClassName {
+	fn eq(&self, other: &str) -> bool {
+		self.0 == other
+	}
+}
+
+impl PartialEq<&str> for ClassName {
+	fn eq(&self, other: &&str) -> bool {
+		self.0 == *other
+	}
+}
+
+impl PartialEq<String> for ClassName {
+	fn eq(&self, other: &String) -> bool {
+		&self.0 == other
+	}
+}
+
+impl PartialEq<ClassName> for str {
+	fn eq(&self, other: &ClassName) -> bool {
+		self == other.0
+	}
+}
+
+impl PartialEq<ClassName> for &str {
+	fn eq(&self, other: &ClassName) -> bool {
+		*self == other.0
+	}
+}
+
+impl PartialEq<ClassName> for String {
+	fn eq(&self, other: &ClassName) -> bool {
+		*self == other.0
+	}
+}
+
+/// The method descriptor taking `args` and returning `ret`, e.g. `(I[J)V`
+pub fn method_desc(args: &[Type], ret: &Type) -> String {
+	let mut desc = String::from("(");
+	for arg in args.iter() {
+		desc.push_str(&arg.descriptor());
+	}
+	desc.push(')');
+	desc.push_str(&ret.descriptor());
+	desc
 }
 
 pub fn parse_method_desc(desc: &str) -> Result<(Vec<Type>, Type)> {
-	parse_method_desc_chars(&desc.as_bytes())
+	parse_method_desc_chars(desc.as_bytes())
 }
 
 fn parse_method_desc_chars(desc: &[u8]) -> Result<(Vec<Type>, Type)> {
-	if desc[0] != b'(' {
+	if desc.first() != Some(&b'(') {
 		return Err(ParserError::invalid_descriptor("Method desc must start with '('"));
 	}
 	let mut args: Vec<Type> = Vec::new();
 	let mut i = 1usize;
-	while desc[i] != b')' {
+	while desc.get(i) != Some(&b')') {
 		let (typ, i2) = parse_type_chars(desc, i)?;
 		args.push(typ);
 		i = i2;
@@ -61,15 +252,33 @@ fn parse_method_desc_chars(desc: &[u8]) -> Result<(Vec<Type>, Type)> {
 			return Err(ParserError::invalid_descriptor("Method desc must have ')'"));
 		}
 	}
-	let (ret, _) = parse_type_chars(desc, i + 1)?;
+	if args.contains(&Type::Void) {
+		return Err(ParserError::invalid_descriptor("Method desc has a void parameter"));
+	}
+	let (ret, end) = parse_type_chars(desc, i + 1)?;
+	if end != desc.len() {
+		return Err(ParserError::invalid_descriptor("Method desc continues after its return type"));
+	}
 	Ok((args, ret))
 }
 
 pub fn parse_type(desc: &str) -> Result<(Type, usize)> {
-	parse_type_chars(&desc.as_bytes(), 0)
+	parse_type_chars(desc.as_bytes(), 0)
+}
+
+/// Parses a whole field descriptor, which may not be void
+pub fn parse_field_desc(desc: &str) -> Result<Type> {
+	let (typ, end) = parse_type(desc)?;
+	if end != desc.len() {
+		return Err(ParserError::invalid_descriptor("Field desc continues after its type"));
+	}
+	if typ == Type::Void {
+		return Err(ParserError::invalid_descriptor("Field of type void"));
+	}
+	Ok(typ)
 }
 
-fn parse_type_chars(desc: &[u8], mut index: usize) -> Result<(Type, usize)> {
+fn parse_type_chars(desc: &[u8], index: usize) -> Result<(Type, usize)> {
 	if index == desc.len() {
 		return Err(ParserError::invalid_descriptor("Empty type string"));
 	}
@@ -84,16 +293,309 @@ fn parse_type_chars(desc: &[u8], mut index: usize) -> Result<(Type, usize)> {
 		SHORT => (Type::Short, index + 1),
 		BOOLEAN => (Type::Boolean, index + 1),
 		'L' => {
-			let mut buf = String::new();
-			while desc[index] != b';' {
-				index += 1;
-				if index >= desc.len() {
-					return Err(ParserError::invalid_descriptor("Type missing ';'"))
-				}
-				buf.push(desc[index] as char);
+			let start = index + 1;
+			let end = desc[start..].iter().position(|c| *c == b';')
+				.ok_or_else(|| ParserError::invalid_descriptor("Type missing ';'"))? + start;
+			let name = String::from_utf8_lossy(&desc[start..end]).into_owned();
+			(Type::Reference(Some(name)), end + 1)
+		}
+		'[' => {
+			let dimensions = desc[index..].iter().take_while(|c| **c == b'[').count();
+			if dimensions > 255 {
+				return Err(ParserError::invalid_descriptor("Array of more than 255 dimensions"));
 			}
-			(Type::Reference(Some(buf)), index + 1)
+			let (mut typ, index) = parse_type_chars(desc, index + dimensions)?;
+			if typ == Type::Void {
+				return Err(ParserError::invalid_descriptor("Array of void"));
+			}
+			for _ in 0..dimensions {
+				typ = Type::Array(Box::new(typ));
+			}
+			(typ, index)
 		}
 		x => return Err(ParserError::invalid_descriptor(format!("Unknown type '{}'", x)))
 	})
 }
+
+/// Rewrites every class name in a field descriptor, method descriptor or generic signature.
+/// `mapper` returns the new internal name of a class, or None to keep it.
+pub fn remap_types(signature: &str, mapper: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+	let mut remapper = Remapper {
+		src: signature,
+		pos: 0,
+		out: String::with_capacity(signature.len()),
+		mapper,
+		type_argument_depth: 0
+	};
+	remapper.signature()?;
+	Ok(remapper.out)
+}
+
+struct Remapper<'a> {
+	src: &'a str,
+	pos: usize,
+	out: String,
+	mapper: &'a dyn Fn(&str) -> Option<String>,
+	/// The type arguments the remapper is inside
+	type_argument_depth: usize
+}
+
+impl <'a> Remapper<'a> {
+	fn peek(&self) -> Option<u8> {
+		self.src.as_bytes().get(self.pos).copied()
+	}
+	
+	fn copy(&mut self, n: usize) {
+		self.out.push_str(&self.src[self.pos..self.pos + n]);
+		self.pos += n;
+	}
+	
+	fn expect(&mut self, c: u8) -> Result<()> {
+		match self.peek() {
+			Some(x) if x == c => {
+				self.copy(1);
+				Ok(())
+			},
+			Some(x) => Err(ParserError::invalid_descriptor(format!("Expected '{}' but found '{}'", c as char, x as char))),
+			None => Err(ParserError::invalid_descriptor(format!("Expected '{}' but found the end", c as char)))
+		}
+	}
+	
+	/// Reads up to, but not including, the first of `ends`
+	fn ident(&mut self, ends: &[u8]) -> Result<&'a str> {
+		let src: &'a str = self.src;
+		let len = src.as_bytes()[self.pos..].iter().position(|c| ends.contains(c))
+			.ok_or_else(|| ParserError::invalid_descriptor("Unterminated name"))?;
+		let ident = &src[self.pos..self.pos + len];
+		self.pos += len;
+		Ok(ident)
+	}
+	
+	fn signature(&mut self) -> Result<()> {
+		if self.peek() == Some(b'<') {
+			self.formal_parameters()?;
+		}
+		while let Some(c) = self.peek() {
+			match c {
+				// method parameters and thrown types
+				b'(' | b')' | b'^' => self.copy(1),
+				_ => self.typ()?
+			}
+		}
+		Ok(())
+	}
+	
+	fn formal_parameters(&mut self) -> Result<()> {
+		self.expect(b'<')?;
+		while self.peek() != Some(b'>') {
+			let name = self.ident(b":")?;
+			self.out.push_str(name);
+			while self.peek() == Some(b':') {
+				self.copy(1);
+				if matches!(self.peek(), Some(b'L') | Some(b'T') | Some(b'[')) {
+					self.typ()?;
+				}
+			}
+		}
+		self.expect(b'>')
+	}
+	
+	fn typ(&mut self) -> Result<()> {
+		match self.peek() {
+			Some(b'L') => self.class_type(),
+			Some(b'T') => {
+				self.copy(1);
+				let name = self.ident(b";")?;
+				self.out.push_str(name);
+				self.expect(b';')
+			},
+			Some(b'[') => {
+				let dimensions = self.src.as_bytes()[self.pos..].iter().take_while(|c| **c == b'[').count();
+				if dimensions > 255 {
+					return Err(ParserError::invalid_descriptor("Array of more than 255 dimensions"));
+				}
+				self.copy(dimensions);
+				self.typ()
+			},
+			Some(b'B') | Some(b'C') | Some(b'D') | Some(b'F') | Some(b'I') | Some(b'J') | Some(b'S') | Some(b'Z') | Some(b'V') => {
+				self.copy(1);
+				Ok(())
+			},
+			Some(x) => Err(ParserError::invalid_descriptor(format!("Unknown type '{}'", x as char))),
+			None => Err(ParserError::invalid_descriptor("Empty type string"))
+		}
+	}
+	
+	fn class_type(&mut self) -> Result<()> {
+		self.expect(b'L')?;
+		let name = self.ident(b"<.;")?;
+		match (self.mapper)(name) {
+			Some(mapped) => self.out.push_str(&mapped),
+			None => self.out.push_str(name)
+		}
+		loop {
+			match self.peek() {
+				Some(b'<') => self.type_arguments()?,
+				// an inner class of a parameterised class, named relative to it
+				Some(b'.') => {
+					self.copy(1);
+					let inner = self.ident(b"<.;")?;
+					self.out.push_str(inner);
+				},
+				_ => return self.expect(b';')
+			}
+		}
+	}
+	
+	fn type_arguments(&mut self) -> Result<()> {
+		self.expect(b'<')?;
+		if self.type_argument_depth == MAX_TYPE_ARGUMENT_DEPTH {
+			return Err(ParserError::invalid_descriptor(format!("Type arguments nest deeper than {}", MAX_TYPE_ARGUMENT_DEPTH)));
+		}
+		self.type_argument_depth += 1;
+		let remapped = self.type_argument_list();
+		self.type_argument_depth -= 1;
+		remapped
+	}
+	
+	fn type_argument_list(&mut self) -> Result<()> {
+		loop {
+			match self.peek() {
+				Some(b'>') => return self.expect(b'>'),
+				Some(b'*') => self.copy(1),
+				Some(b'+') | Some(b'-') => {
+					self.copy(1);
+					self.typ()?;
+				},
+				_ => self.typ()?
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn rename(name: &str) -> Option<String> {
+		match name {
+			"com/example/Foo" => Some(String::from("com/example/Bar")),
+			"T" | "Lazy" => Some(String::from("Wrong")),
+			_ => None
+		}
+	}
+	
+	#[test]
+	fn remap_descriptors() {
+		assert_eq!(remap_types("Lcom/example/Foo;", &rename).unwrap(), "Lcom/example/Bar;");
+		assert_eq!(remap_types("[[Lcom/example/Foo;", &rename).unwrap(), "[[Lcom/example/Bar;");
+		assert_eq!(remap_types("(ILcom/example/Foo;[J)Lcom/example/Foo;", &rename).unwrap(), "(ILcom/example/Bar;[J)Lcom/example/Bar;");
+		assert_eq!(remap_types("(Ljava/lang/String;)V", &rename).unwrap(), "(Ljava/lang/String;)V");
+	}
+	
+	#[test]
+	fn remap_signatures() {
+		// type variables and the names of formal parameters are not classes
+		assert_eq!(
+			remap_types("<T:Ljava/lang/Object;Lazy::Ljava/util/List<Lcom/example/Foo;>;>Lcom/example/Foo<TT;>;", &rename).unwrap(),
+			"<T:Ljava/lang/Object;Lazy::Ljava/util/List<Lcom/example/Bar;>;>Lcom/example/Bar<TT;>;"
+		);
+		assert_eq!(
+			remap_types("<T:Ljava/lang/Object;>(Ljava/util/Map<+Lcom/example/Foo;*>;TT;)Lcom/example/Foo<-TT;>.Lazy;^Lcom/example/Foo;^TT;", &rename).unwrap(),
+			"<T:Ljava/lang/Object;>(Ljava/util/Map<+Lcom/example/Bar;*>;TT;)Lcom/example/Bar<-TT;>.Lazy;^Lcom/example/Bar;^TT;"
+		);
+		assert!(remap_types("Lcom/example/Foo", &rename).is_err());
+		assert!(remap_types("Ljava/util/List<Lcom/example/Foo;", &rename).is_err());
+		assert!(remap_types("Q", &rename).is_err());
+		
+		// deep nesting fails rather than overflowing the stack
+		let widest = format!("{}Lcom/example/Foo;", "[".repeat(255));
+		assert_eq!(remap_types(&widest, &rename).unwrap(), widest.replace("Foo", "Bar"));
+		assert!(remap_types(&format!("{}I", "[".repeat(65000)), &rename).is_err());
+		let nested = format!("{}I{}", "Ljava/util/List<".repeat(20000), ">;".repeat(20000));
+		assert!(remap_types(&nested, &rename).is_err());
+	}
+	
+	#[test]
+	fn parse_array_types() {
+		assert_eq!(parse_type("[[I").unwrap(), (Type::Array(Box::new(Type::Array(Box::new(Type::Int)))), 3));
+		assert_eq!(parse_type("[Ljava/lang/String;").unwrap().0.descriptor(), "[Ljava/lang/String;");
+		assert!(parse_type("[V").is_err());
+	}
+	
+	#[test]
+	fn build_method_descriptors() {
+		let args = [Type::Int, Type::Array(Box::new(Type::Long)), Type::object("java/lang/String")];
+		let desc = method_desc(&args, &Type::Void);
+		assert_eq!(desc, "(I[JLjava/lang/String;)V");
+		assert_eq!(parse_method_desc(&desc).unwrap(), (args.to_vec(), Type::Void));
+		assert_eq!(method_desc(&[], &Type::object("java/lang/Object")), "()Ljava/lang/Object;");
+	}
+	
+	#[test]
+	fn malformed_descriptors_are_rejected() {
+		assert_eq!(parse_field_desc("[[Ljava/lang/String;").unwrap().descriptor(), "[[Ljava/lang/String;");
+		assert_eq!(parse_field_desc("J").unwrap(), Type::Long);
+		let dimensions = "[".repeat(255);
+		assert!(parse_field_desc(&format!("{}I", dimensions)).is_ok());
+		for desc in ["", "Q", "V", "II", "[V", "Ljava/lang/String", "Ljava/lang/String;I", "()V"].iter() {
+			assert!(parse_field_desc(desc).is_err(), "{}", desc);
+		}
+		assert!(parse_field_desc(&format!("[{}I", dimensions)).is_err());
+		
+		assert!(parse_method_desc("([Ljava/lang/String;)V").is_ok());
+		for desc in ["", "V", "(", "()", "(I", "(Q)V", "(V)V", "()VV", "()[V", "(I)I;"].iter() {
+			assert!(parse_method_desc(desc).is_err(), "{}", desc);
+		}
+	}
+	
+	#[test]
+	fn class_name_conversions() {
+		// internal, binary and descriptor forms of the same class
+		let forms = [
+			("java/lang/String", "java.lang.String", "Ljava/lang/String;"),
+			("Main", "Main", "LMain;"),
+			("a/b/Outer$Inner", "a.b.Outer$Inner", "La/b/Outer$Inner;"),
+			("[I", "[I", "[I"),
+			("[Ljava/lang/Object;", "[Ljava.lang.Object;", "[Ljava/lang/Object;")
+		];
+		for (internal, binary, descriptor) in forms.iter() {
+			let names = [
+				ClassName::from_internal(internal).unwrap(),
+				ClassName::from_binary(binary).unwrap(),
+				ClassName::from_descriptor(descriptor).unwrap()
+			];
+			for name in names.iter() {
+				assert_eq!(name, internal);
+				assert_eq!(name.to_binary(), *binary);
+				assert_eq!(name.to_descriptor(), *descriptor);
+			}
+		}
+		
+		let name = ClassName::from("java/util/List");
+		assert!(name.starts_with("java/"));
+		assert_eq!(String::from(name.clone()), "java/util/List");
+		assert_eq!(Type::object(&name).descriptor(), name.to_descriptor());
+	}
+	
+	#[test]
+	fn malformed_class_names_are_rejected() {
+		for name in ["", "java.lang.String", "Ljava/lang/String;", "java/lang/", "/Main", "java//Main", "[", "[V", "[Ljava/lang/String"].iter() {
+			assert!(ClassName::from_internal(name).is_err(), "{}", name);
+		}
+		for name in ["", "java/lang/String", "java..String", "java.lang.String;"].iter() {
+			assert!(ClassName::from_binary(name).is_err(), "{}", name);
+		}
+		for desc in ["", "I", "V", "java/lang/String", "Ljava/lang/String", "Ljava.lang.String;", "L;", "Ljava/lang/String;;", "[V"].iter() {
+			assert!(ClassName::from_descriptor(desc).is_err(), "{}", desc);
+		}
+	}
+	
+	#[test]
+	fn object_types_end_at_their_semicolon() {
+		let (typ, end) = parse_type("Ljava/lang/String;I").unwrap();
+		assert_eq!(typ, Type::Reference(Some(String::from("java/lang/String"))));
+		assert_eq!(end, 18);
+	}
+}
+
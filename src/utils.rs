@@ -1,6 +1,17 @@
-use std::io::Read;
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::io::{Cursor, Read, Write};
+use std::borrow::Cow;
+use crate::error::{Result, ParserError};
+
+/// Whether an index or unsigned operand fits the narrow form of an instruction, so that it needs
+/// no `wide` prefix or `_w` variant
+pub(crate) fn fits_u8(value: u16) -> bool {
+	value <= u8::MAX as u16
+}
+
+/// Whether a signed operand, such as the amount of an `iinc`, fits the narrow form
+pub(crate) fn fits_i8(value: i16) -> bool {
+	i8::MIN as i16 <= value && value <= i8::MAX as i16
+}
 
 pub trait VecUtils <T> {
 	/// Overwrites the given index with the given item and returns the previous item if successful
@@ -32,42 +43,124 @@ impl <T> VecUtils<T> for Vec<T> {
 	}
 }
 
+/// Keeps track of how many bytes have been read through it
+pub struct CountingReader<R> {
+	inner: R,
+	count: u64
+}
+
+impl <R: Read> CountingReader<R> {
+	pub fn new(inner: R) -> Self {
+		CountingReader {
+			inner,
+			count: 0
+		}
+	}
+	
+	pub fn position(&self) -> u64 {
+		self.count
+	}
+}
+
+impl <R: Read> Read for CountingReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let read = self.inner.read(buf)?;
+		self.count += read as u64;
+		Ok(read)
+	}
+}
+
+/// Keeps track of how many bytes have been written through it
+pub struct CountingWriter<W> {
+	inner: W,
+	count: u64
+}
+
+impl <W: Write> CountingWriter<W> {
+	pub fn new(inner: W) -> Self {
+		CountingWriter {
+			inner,
+			count: 0
+		}
+	}
+	
+	pub fn position(&self) -> u64 {
+		self.count
+	}
+}
+
+impl <W: Write> Write for CountingWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		let written = self.inner.write(buf)?;
+		self.count += written as u64;
+		Ok(written)
+	}
+	
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+/// The most a length-prefixed read allocates before any bytes arrive
+pub(crate) const READ_CHUNK: usize = 64 * 1024;
+
 pub trait ReadUtils: Read {
-	#[inline]
-	fn read_nbytes(&mut self, nbytes: usize) -> std::io::Result<Vec<u8>> {
-		let mut buf = vec![0u8; nbytes];
-		self.read_exact(&mut buf)?;
+	/// Reads exactly `nbytes` bytes. Memory grows with the bytes actually read rather than being
+	/// allocated up front, so a corrupt length cannot exhaust memory before the input runs out.
+	fn read_nbytes(&mut self, nbytes: usize, what: &'static str) -> Result<Vec<u8>> {
+		let mut buf = Vec::with_capacity(nbytes.min(READ_CHUNK));
+		Read::take(&mut *self, nbytes as u64).read_to_end(&mut buf)?;
+		if buf.len() < nbytes {
+			return Err(ParserError::truncated(what, nbytes));
+		}
 		Ok(buf)
 	}
 }
 impl<W: Read + ?Sized> ReadUtils for W {}
 
-pub trait MapUtils <K, V> {
-	/// returns true if inserted
-	fn insert_if_not_present(&mut self, key: K, value: V) -> bool;
-	
-	fn insert_if_not_present_lazy<F>(&mut self, key: K, value: F) -> bool
-		where F: FnOnce() -> V;
-}
-
-impl <K: Eq + Hash, V> MapUtils<K, V> for HashMap<K, V> {
-	#[inline]
-	fn insert_if_not_present(&mut self, key: K, value: V) -> bool {
-		if self.get(&key).is_none() {
-			self.insert(key, value);
-			true
-		} else {
-			false
+/// Borrows the next `nbytes` bytes of `rdr`. If fewer are left, consumes them and fails as
+/// [`ReadUtils::read_nbytes`] does.
+pub(crate) fn read_slice<'a>(rdr: &mut Cursor<&'a [u8]>, nbytes: usize, what: &'static str) -> Result<&'a [u8]> {
+	let bytes: &'a [u8] = rdr.get_ref();
+	let start = (rdr.position() as usize).min(bytes.len());
+	match bytes[start..].get(..nbytes) {
+		Some(slice) => {
+			rdr.set_position((start + nbytes) as u64);
+			Ok(slice)
+		},
+		None => {
+			rdr.set_position(bytes.len() as u64);
+			Err(ParserError::truncated(what, nbytes))
 		}
 	}
-	
-	#[inline]
-	fn insert_if_not_present_lazy<F>(&mut self, key: K, value: F) -> bool where F: FnOnce() -> V {
-		if self.get(&key).is_none() {
-			self.insert(key, value());
-			true
-		} else {
-			false
-		}
+}
+
+/// Runs `f` over the rest of `buf` as a slice, moving `buf` past what `f` read. Lets the public
+/// parse functions that take an owned buffer share the slice parsers.
+pub(crate) fn with_slice<T>(buf: &mut Cursor<Vec<u8>>, f: impl FnOnce(&mut Cursor<&[u8]>) -> Result<T>) -> Result<T> {
+	let start = (buf.position() as usize).min(buf.get_ref().len());
+	let mut slice = Cursor::new(&buf.get_ref()[start..]);
+	let parsed = f(&mut slice);
+	let read = slice.position();
+	buf.set_position(start as u64 + read);
+	parsed
+}
+
+/// Input of the class parser. Attribute contents are borrowed when the whole class is already in
+/// memory, and copied out of any other reader.
+pub(crate) trait ClassRead: Read {
+	/// Reads exactly `nbytes` bytes, as [`ReadUtils::read_nbytes`] does
+	fn read_contents(&mut self, nbytes: usize, what: &'static str) -> Result<Cow<'_, [u8]>>;
+}
+
+impl <R: Read> ClassRead for CountingReader<R> {
+	fn read_contents(&mut self, nbytes: usize, what: &'static str) -> Result<Cow<'_, [u8]>> {
+		Ok(Cow::Owned(self.read_nbytes(nbytes, what)?))
+	}
+}
+
+impl ClassRead for Cursor<&[u8]> {
+	fn read_contents(&mut self, nbytes: usize, what: &'static str) -> Result<Cow<'_, [u8]>> {
+		Ok(Cow::Borrowed(read_slice(self, nbytes, what)?))
 	}
 }
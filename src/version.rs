@@ -4,8 +4,10 @@ use std::cmp::{PartialOrd, Ordering};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
 use crate::error::{Result, ParserError};
 use std::convert::{TryFrom, TryInto};
+use std::fmt::{Display, Formatter};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassVersion {
 	pub major: MajorVersion,
 	pub minor: u16
@@ -42,21 +44,37 @@ impl Serializable for ClassVersion {
 	}
 }
 
-#[allow(dead_code)]
 impl ClassVersion {
-	fn new_major(major: MajorVersion) -> Self {
+	/// The version with the given major version and a minor version of 0
+	pub const fn new_major(major: MajorVersion) -> Self {
 		ClassVersion::new(major, 0)
 	}
-	fn new(major: MajorVersion, minor: u16) -> Self {
+	
+	pub const fn new(major: MajorVersion, minor: u16) -> Self {
 		ClassVersion {
 			major, minor
 		}
 	}
 }
 
+macro_rules! class_versions {
+	($($version:ident),*) => {
+		/// The version of each release, with a minor version of 0
+		impl ClassVersion {
+			$(pub const $version: ClassVersion = ClassVersion::new_major(MajorVersion::$version);)*
+		}
+	};
+}
+
+class_versions!(
+	JDK_1_1, JDK_1_2, JDK_1_3, JDK_1_4, JAVA_5, JAVA_6, JAVA_7, JAVA_8, JAVA_9, JAVA_10, JAVA_11,
+	JAVA_12, JAVA_13, JAVA_14, JAVA_15, JAVA_16, JAVA_17, JAVA_18, JAVA_19, JAVA_20, JAVA_21
+);
+
 #[allow(non_camel_case_types)]
 #[repr(u16)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MajorVersion {
 	JDK_1_1 = 45,
 	JDK_1_2 = 46,
@@ -72,7 +90,25 @@ pub enum MajorVersion {
 	JAVA_12 = 56,
 	JAVA_13 = 57,
 	JAVA_14 = 58,
-	JAVA_15 = 59
+	JAVA_15 = 59,
+	JAVA_16 = 60,
+	JAVA_17 = 61,
+	JAVA_18 = 62,
+	JAVA_19 = 63,
+	JAVA_20 = 64,
+	JAVA_21 = 65
+}
+
+/// The release name, e.g. `Java 17` or `JDK 1.4`
+impl Display for MajorVersion {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let release = u16::from(*self) - 44;
+		if *self >= MajorVersion::JAVA_5 {
+			write!(f, "Java {}", release)
+		} else {
+			write!(f, "JDK 1.{}", release)
+		}
+	}
 }
 
 impl From<MajorVersion> for u16 {
@@ -100,7 +136,26 @@ impl TryFrom<u16> for MajorVersion {
 			57 => MajorVersion::JAVA_13,
 			58 => MajorVersion::JAVA_14,
 			59 => MajorVersion::JAVA_15,
+			60 => MajorVersion::JAVA_16,
+			61 => MajorVersion::JAVA_17,
+			62 => MajorVersion::JAVA_18,
+			63 => MajorVersion::JAVA_19,
+			64 => MajorVersion::JAVA_20,
+			65 => MajorVersion::JAVA_21,
 			_ => return Err(ParserError::Unrecognized("major version", version.to_string()))
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn versions_up_to_java_21_are_recognised() {
+		assert_eq!(MajorVersion::try_from(60).unwrap(), MajorVersion::JAVA_16);
+		assert_eq!(MajorVersion::try_from(65).unwrap(), MajorVersion::JAVA_21);
+		assert_eq!(u16::from(MajorVersion::JAVA_21), 65);
+		assert!(MajorVersion::try_from(66).is_err());
+	}
+}
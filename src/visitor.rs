@@ -0,0 +1,244 @@
+use crate::ast::*;
+
+/// Visits the instructions of an [`InsnList`](crate::insnlist::InsnList) without having to match
+/// over every [`Insn`] variant.
+///
+/// Every method has a no-op default, so implementors only override the instruction kinds they are
+/// interested in. [`visit_insn`](InsnVisitor::visit_insn) performs the dispatch and can itself be
+/// overridden to observe every instruction before (or instead of) the specific callback.
+///
+/// ```
+/// use classfile::visitor::InsnVisitor;
+/// use classfile::ast::InvokeInsn;
+///
+/// #[derive(Default)]
+/// struct InvokeCounter {
+///     count: usize
+/// }
+///
+/// impl InsnVisitor for InvokeCounter {
+///     fn visit_invoke(&mut self, _insn: &InvokeInsn) {
+///         self.count += 1;
+///     }
+/// }
+/// ```
+#[allow(unused_variables)]
+pub trait InsnVisitor {
+	fn visit_insn(&mut self, insn: &Insn) {
+		match insn {
+			Insn::Label(x) => self.visit_label(x),
+			Insn::ArrayLoad(x) => self.visit_array_load(x),
+			Insn::ArrayStore(x) => self.visit_array_store(x),
+			Insn::Ldc(x) => self.visit_ldc(x),
+			Insn::LocalLoad(x) => self.visit_local_load(x),
+			Insn::LocalStore(x) => self.visit_local_store(x),
+			Insn::NewArray(x) => self.visit_new_array(x),
+			Insn::Return(x) => self.visit_return(x),
+			Insn::ArrayLength(x) => self.visit_array_length(x),
+			Insn::Throw(x) => self.visit_throw(x),
+			Insn::CheckCast(x) => self.visit_check_cast(x),
+			Insn::Convert(x) => self.visit_convert(x),
+			Insn::Add(x) => self.visit_add(x),
+			Insn::Compare(x) => self.visit_compare(x),
+			Insn::Divide(x) => self.visit_divide(x),
+			Insn::Multiply(x) => self.visit_multiply(x),
+			Insn::Negate(x) => self.visit_negate(x),
+			Insn::Remainder(x) => self.visit_remainder(x),
+			Insn::Subtract(x) => self.visit_subtract(x),
+			Insn::And(x) => self.visit_and(x),
+			Insn::Or(x) => self.visit_or(x),
+			Insn::Xor(x) => self.visit_xor(x),
+			Insn::ShiftLeft(x) => self.visit_shift_left(x),
+			Insn::ShiftRight(x) => self.visit_shift_right(x),
+			Insn::LogicalShiftRight(x) => self.visit_logical_shift_right(x),
+			Insn::Dup(x) => self.visit_dup(x),
+			Insn::Pop(x) => self.visit_pop(x),
+			Insn::GetField(x) => self.visit_get_field(x),
+			Insn::PutField(x) => self.visit_put_field(x),
+			Insn::Jump(x) => self.visit_jump(x),
+			Insn::ConditionalJump(x) => self.visit_conditional_jump(x),
+			Insn::IncrementInt(x) => self.visit_increment_int(x),
+			Insn::InstanceOf(x) => self.visit_instance_of(x),
+			Insn::InvokeDynamic(x) => self.visit_invoke_dynamic(x),
+			Insn::Invoke(x) => self.visit_invoke(x),
+			Insn::LookupSwitch(x) => self.visit_lookup_switch(x),
+			Insn::TableSwitch(x) => self.visit_table_switch(x),
+			Insn::MonitorEnter(x) => self.visit_monitor_enter(x),
+			Insn::MonitorExit(x) => self.visit_monitor_exit(x),
+			Insn::MultiNewArray(x) => self.visit_multi_new_array(x),
+			Insn::NewObject(x) => self.visit_new_object(x),
+			Insn::Nop(x) => self.visit_nop(x),
+			Insn::Swap(x) => self.visit_swap(x),
+			Insn::ImpDep1(x) => self.visit_imp_dep1(x),
+			Insn::ImpDep2(x) => self.visit_imp_dep2(x),
+			Insn::BreakPoint(x) => self.visit_break_point(x),
+		}
+	}
+	
+	fn visit_label(&mut self, insn: &LabelInsn) {}
+	fn visit_array_load(&mut self, insn: &ArrayLoadInsn) {}
+	fn visit_array_store(&mut self, insn: &ArrayStoreInsn) {}
+	fn visit_ldc(&mut self, insn: &LdcInsn) {}
+	fn visit_local_load(&mut self, insn: &LocalLoadInsn) {}
+	fn visit_local_store(&mut self, insn: &LocalStoreInsn) {}
+	fn visit_new_array(&mut self, insn: &NewArrayInsn) {}
+	fn visit_return(&mut self, insn: &ReturnInsn) {}
+	fn visit_array_length(&mut self, insn: &ArrayLengthInsn) {}
+	fn visit_throw(&mut self, insn: &ThrowInsn) {}
+	fn visit_check_cast(&mut self, insn: &CheckCastInsn) {}
+	fn visit_convert(&mut self, insn: &ConvertInsn) {}
+	fn visit_add(&mut self, insn: &AddInsn) {}
+	fn visit_compare(&mut self, insn: &CompareInsn) {}
+	fn visit_divide(&mut self, insn: &DivideInsn) {}
+	fn visit_multiply(&mut self, insn: &MultiplyInsn) {}
+	fn visit_negate(&mut self, insn: &NegateInsn) {}
+	fn visit_remainder(&mut self, insn: &RemainderInsn) {}
+	fn visit_subtract(&mut self, insn: &SubtractInsn) {}
+	fn visit_and(&mut self, insn: &AndInsn) {}
+	fn visit_or(&mut self, insn: &OrInsn) {}
+	fn visit_xor(&mut self, insn: &XorInsn) {}
+	fn visit_shift_left(&mut self, insn: &ShiftLeftInsn) {}
+	fn visit_shift_right(&mut self, insn: &ShiftRightInsn) {}
+	fn visit_logical_shift_right(&mut self, insn: &LogicalShiftRightInsn) {}
+	fn visit_dup(&mut self, insn: &DupInsn) {}
+	fn visit_pop(&mut self, insn: &PopInsn) {}
+	fn visit_get_field(&mut self, insn: &GetFieldInsn) {}
+	fn visit_put_field(&mut self, insn: &PutFieldInsn) {}
+	fn visit_jump(&mut self, insn: &JumpInsn) {}
+	fn visit_conditional_jump(&mut self, insn: &ConditionalJumpInsn) {}
+	fn visit_increment_int(&mut self, insn: &IncrementIntInsn) {}
+	fn visit_instance_of(&mut self, insn: &InstanceOfInsn) {}
+	fn visit_invoke_dynamic(&mut self, insn: &InvokeDynamicInsn) {}
+	fn visit_invoke(&mut self, insn: &InvokeInsn) {}
+	fn visit_lookup_switch(&mut self, insn: &LookupSwitchInsn) {}
+	fn visit_table_switch(&mut self, insn: &TableSwitchInsn) {}
+	fn visit_monitor_enter(&mut self, insn: &MonitorEnterInsn) {}
+	fn visit_monitor_exit(&mut self, insn: &MonitorExitInsn) {}
+	fn visit_multi_new_array(&mut self, insn: &MultiNewArrayInsn) {}
+	fn visit_new_object(&mut self, insn: &NewObjectInsn) {}
+	fn visit_nop(&mut self, insn: &NopInsn) {}
+	fn visit_swap(&mut self, insn: &SwapInsn) {}
+	fn visit_imp_dep1(&mut self, insn: &ImpDep1Insn) {}
+	fn visit_imp_dep2(&mut self, insn: &ImpDep2Insn) {}
+	fn visit_break_point(&mut self, insn: &BreakPointInsn) {}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::visitor::InsnVisitor;
+	use crate::ast::*;
+	use crate::code::CodeAttribute;
+	use crate::insnlist::InsnList;
+	use crate::types::ClassName;
+	use std::collections::HashSet;
+	
+	/// Counts every method invocation, split by invoke kind
+	#[derive(Default)]
+	struct InvokeCounter {
+		invokes: usize,
+		statics: usize,
+		dynamics: usize
+	}
+	
+	impl InsnVisitor for InvokeCounter {
+		fn visit_invoke(&mut self, insn: &InvokeInsn) {
+			self.invokes += 1;
+			if insn.kind == InvokeType::Static {
+				self.statics += 1;
+			}
+		}
+		
+		fn visit_invoke_dynamic(&mut self, _insn: &InvokeDynamicInsn) {
+			self.dynamics += 1;
+		}
+	}
+	
+	/// Replaces instance reads of `class.name` with a call to its getter
+	fn replace_with_getter(list: &mut InsnList, class: &str, name: &str, getter: &str) {
+		list.map_insns(|insn, _| {
+			match insn {
+				Insn::GetField(x) if x.instance && x.class == class && x.name == name => {
+					let descriptor = format!("(){}", x.descriptor);
+					vec![Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, x.class, getter.to_string(), descriptor, false)))]
+				}
+				insn => vec![insn]
+			}
+		});
+	}
+	
+	fn invoke(kind: InvokeType, name: &str) -> Insn {
+		Insn::Invoke(Box::new(InvokeInsn::new(kind, ClassName::from("Foo"), String::from(name), String::from("()V"), false)))
+	}
+	
+	fn get_field(instance: bool, name: &str) -> Insn {
+		Insn::GetField(Box::new(GetFieldInsn::new(instance, ClassName::from("Foo"), String::from(name), String::from("I"))))
+	}
+	
+	#[test]
+	fn count_invocations() {
+		let mut code = CodeAttribute::empty();
+		let label = code.insns.new_label();
+		code.insns.insns = vec![
+			invoke(InvokeType::Static, "a"),
+			Insn::Label(label),
+			invoke(InvokeType::Instance, "b"),
+			get_field(true, "value"),
+			invoke(InvokeType::Static, "c"),
+			Insn::Return(ReturnInsn::new(ReturnType::Void))
+		];
+		
+		let mut counter = InvokeCounter::default();
+		code.accept(&mut counter);
+		assert_eq!(counter.invokes, 3);
+		assert_eq!(counter.statics, 2);
+		assert_eq!(counter.dynamics, 0);
+	}
+	
+	#[test]
+	fn getfield_to_getter() {
+		let mut list = InsnList::new();
+		list.insns = vec![
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0)),
+			get_field(true, "value"),
+			Insn::LocalLoad(LocalLoadInsn::new(OpType::Reference, 0)),
+			get_field(true, "other"),
+			get_field(false, "value"),
+			Insn::Return(ReturnInsn::new(ReturnType::Void))
+		];
+		replace_with_getter(&mut list, "Foo", "value", "getValue");
+		
+		assert_eq!(list.len(), 6);
+		assert_eq!(list.insns[1], Insn::Invoke(Box::new(InvokeInsn::new(InvokeType::Instance, ClassName::from("Foo"), String::from("getValue"), String::from("()I"), false))));
+		// different field and static access must be left alone
+		assert_eq!(list.insns[3], get_field(true, "other"));
+		assert_eq!(list.insns[4], get_field(false, "value"));
+	}
+	
+	#[test]
+	fn map_allocates_fresh_labels() {
+		let mut list = InsnList::new();
+		let existing = list.new_label();
+		list.insns = vec![
+			Insn::Label(existing),
+			Insn::Nop(NopInsn::new()),
+			Insn::Nop(NopInsn::new())
+		];
+		
+		// expand every nop into a labelled nop
+		list.map_insns(|insn, labels| {
+			match insn {
+				Insn::Nop(_) => vec![Insn::Label(labels.new_label()), insn],
+				insn => vec![insn]
+			}
+		});
+		
+		assert_eq!(list.len(), 5);
+		let ids: HashSet<LabelInsn> = list.iter().filter_map(|insn| {
+			if let Insn::Label(x) = insn { Some(*x) } else { None }
+		}).collect();
+		assert_eq!(ids.len(), 3);
+		// labels created after the map must not collide with the ones it handed out
+		let next = list.new_label();
+		assert!(!ids.contains(&next));
+	}
+}